@@ -0,0 +1,117 @@
+//! Address families of the addresses lading binds and sends to.
+//!
+//! Targets may be reached over IPv4 or IPv6, or both. Generators label their
+//! metrics with the `address_family` of the address they send to, blackholes
+//! with that of the address each connection or packet came from, so that an
+//! experiment can tell the two apart.
+//!
+//! A blackhole bound to the IPv6 unspecified address, `[::]`, is dual-stack
+//! where the host allows it, accepting IPv4 as well as IPv6. IPv4 peers of a
+//! dual-stack socket appear as IPv4-mapped IPv6 addresses, `::ffff:a.b.c.d`;
+//! these are treated as the IPv4 addresses they are.
+
+use std::net::{IpAddr, SocketAddr};
+
+use hyper::Uri;
+
+/// The label name of address families.
+pub(crate) const LABEL: &str = "address_family";
+
+/// Return `addr`, or the IPv4 address it maps if it is an IPv4-mapped IPv6
+/// address.
+#[must_use]
+pub(crate) fn canonical(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V4(_) => addr,
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, _, _] => {
+                let octets = v6.octets();
+                IpAddr::from([octets[12], octets[13], octets[14], octets[15]])
+            }
+            _ => addr,
+        },
+    }
+}
+
+/// Return the name of the address family of `addr`, `ipv4` or `ipv6`.
+#[must_use]
+pub(crate) fn family(addr: IpAddr) -> &'static str {
+    match canonical(addr) {
+        IpAddr::V4(_) => "ipv4",
+        IpAddr::V6(_) => "ipv6",
+    }
+}
+
+/// Return the `address_family` label of `addr`.
+#[must_use]
+pub(crate) fn label(addr: IpAddr) -> (String, String) {
+    (LABEL.to_string(), family(addr).to_string())
+}
+
+/// Whether a socket bound to `addr` should accept IPv4 as well as IPv6, as a
+/// socket bound to the IPv6 unspecified address does.
+#[must_use]
+pub(crate) fn dual_stack(addr: SocketAddr) -> bool {
+    match addr {
+        SocketAddr::V4(_) => false,
+        SocketAddr::V6(v6) => v6.ip().is_unspecified(),
+    }
+}
+
+/// Return the labels of a generator sending to `uri`: the `address_family` of
+/// its host if that is an address literal, or none if it is a name. Names are
+/// not resolved here, as resolving blocks and a name may resolve to either
+/// family from one connection to the next.
+#[must_use]
+pub(crate) fn uri_labels(uri: &Uri) -> Vec<(String, String)> {
+    uri.host()
+        .map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+        .and_then(|host| host.parse::<IpAddr>().ok())
+        .map_or_else(Vec::new, |addr| vec![label(addr)])
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use proptest::prelude::*;
+
+    use super::{canonical, family, uri_labels, LABEL};
+
+    // An IPv4 address is of the IPv4 family whether or not it is mapped into
+    // IPv6, as a dual-stack socket reports it.
+    proptest! {
+        #[test]
+        fn mapped_ipv4_is_ipv4(addr: u32) {
+            let v4 = Ipv4Addr::from(addr);
+            let mapped = IpAddr::V6(v4.to_ipv6_mapped());
+            prop_assert_eq!(canonical(mapped), IpAddr::V4(v4));
+            prop_assert_eq!(family(mapped), "ipv4");
+        }
+
+        #[test]
+        fn unmapped_ipv6_is_ipv6(addr: u128) {
+            let v6 = Ipv6Addr::from(addr);
+            prop_assume!(v6.segments()[..6] != [0, 0, 0, 0, 0, 0xffff]);
+            prop_assert_eq!(canonical(IpAddr::V6(v6)), IpAddr::V6(v6));
+            prop_assert_eq!(family(IpAddr::V6(v6)), "ipv6");
+        }
+
+        // A URI host is labelled by its family when it is an address literal
+        // and not at all, without resolving it, when it is a name.
+        #[test]
+        fn uri_labelled_from_literal(v4: u32, v6: u128, port: u16) {
+            let v4 = Ipv4Addr::from(v4);
+            let uri = format!("http://{}:{}/", v4, port).parse().unwrap();
+            prop_assert_eq!(uri_labels(&uri), vec![(LABEL.to_string(), "ipv4".to_string())]);
+
+            let v6 = Ipv6Addr::from(v6);
+            prop_assume!(v6.segments()[..6] != [0, 0, 0, 0, 0, 0xffff]);
+            let uri = format!("http://[{}]:{}/", v6, port).parse().unwrap();
+            prop_assert_eq!(uri_labels(&uri), vec![(LABEL.to_string(), "ipv6".to_string())]);
+
+            let uri = format!("http://localhost:{}/", port).parse().unwrap();
+            prop_assert!(uri_labels(&uri).is_empty());
+        }
+    }
+}
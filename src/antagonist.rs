@@ -0,0 +1,313 @@
+//! Lading antagonists
+//!
+//! A target rarely has its host to itself. Antagonists are noisy neighbors that
+//! lading runs alongside the target, contending with it for the host's
+//! resources in a controlled way: burning CPU cores, dirtying memory bandwidth,
+//! writing to disk. Each antagonist is active only during the [`Stage`]s it is
+//! configured for, allowing a target's behavior under contention to be compared
+//! against its behavior without within a single run.
+//!
+//! Antagonists do their work on dedicated threads, outside of lading's async
+//! runtime, so as not to starve lading's own components. The threads are joined
+//! on shutdown, an antagonist's cleanup complete once it stops.
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use metrics::gauge;
+use serde::Deserialize;
+use tokio::{sync::watch, task};
+use tracing::info;
+
+use crate::signals::Shutdown;
+
+pub mod cpu;
+pub mod disk;
+pub mod memory;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// The stages of an experiment.
+pub enum Stage {
+    /// The target is running but samples are not yet collected.
+    Warmup,
+    /// Samples are collected.
+    Experiment,
+}
+
+fn default_stages() -> Vec<Stage> {
+    vec![Stage::Warmup, Stage::Experiment]
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+/// The kind of contention an antagonist creates.
+pub enum Kind {
+    /// See [`crate::antagonist::cpu::Config`] for details.
+    Cpu(cpu::Config),
+    /// See [`crate::antagonist::memory::Config`] for details.
+    MemoryBandwidth(memory::Config),
+    /// See [`crate::antagonist::disk::Config`] for details.
+    Disk(disk::Config),
+}
+
+impl Config {
+    /// The memory the antagonist allocates up front, its buffers, in bytes.
+    #[must_use]
+    pub fn planned_memory_bytes(&self) -> u64 {
+        let bytes = match &self.kind {
+            Kind::Cpu(_) => 0,
+            Kind::MemoryBandwidth(conf) => {
+                u128::from(conf.threads.get()) * conf.buffer_bytes.get_bytes()
+            }
+            Kind::Disk(conf) => conf.block_bytes.get_bytes(),
+        };
+        u64::try_from(bytes).unwrap_or(u64::MAX)
+    }
+}
+
+impl Kind {
+    fn name(&self) -> &'static str {
+        match self {
+            Kind::Cpu(_) => "cpu",
+            Kind::MemoryBandwidth(_) => "memory_bandwidth",
+            Kind::Disk(_) => "disk",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration for [`Server`]
+pub struct Config {
+    /// The stages during which the antagonist is active, by default all
+    #[serde(default = "default_stages")]
+    pub stages: Vec<Stage>,
+    /// The kind of contention to create
+    #[serde(flatten)]
+    pub kind: Kind,
+}
+
+#[derive(Debug)]
+/// Errors produced by [`Server`]
+pub enum Error {
+    /// Wrapper for [`std::io::Error`], a worker thread could not be spawned.
+    Io(io::Error),
+    /// A worker thread could not be joined, having panicked.
+    Join,
+}
+
+#[derive(Debug, Default)]
+/// Flags shared between a [`Server`] and its worker threads.
+pub(crate) struct Control {
+    active: AtomicBool,
+    stop: AtomicBool,
+}
+
+impl Control {
+    /// Whether workers should create contention now.
+    pub(crate) fn active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Whether workers should exit.
+    pub(crate) fn stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+}
+
+/// The body of a worker thread, run until [`Control::stopped`]. Metrics the
+/// worker emits are labeled with the labels it is passed.
+pub(crate) type Worker = Box<dyn FnOnce(&Control, &Vec<(String, String)>) + Send>;
+
+/// Spawn a thread for each of `workers`, named for the antagonist `name`.
+/// Should a thread fail to spawn those already spawned are stopped.
+#[allow(clippy::ptr_arg)]
+fn spawn(
+    name: &str,
+    workers: Vec<Worker>,
+    control: &Arc<Control>,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<thread::JoinHandle<()>>, Error> {
+    let mut handles = Vec::with_capacity(workers.len());
+    for (idx, work) in workers.into_iter().enumerate() {
+        let worker_control = Arc::clone(control);
+        let labels = labels.clone();
+        let handle = thread::Builder::new()
+            .name(format!("antagonist-{}-{}", name, idx))
+            .spawn(move || work(&worker_control, &labels));
+        match handle {
+            Ok(handle) => handles.push(handle),
+            Err(err) => {
+                control.stop.store(true, Ordering::Relaxed);
+                return Err(Error::Io(err));
+            }
+        }
+    }
+    Ok(handles)
+}
+
+/// Stop the workers sharing `control` and wait for their threads, `handles`,
+/// to exit. The threads are joined off the runtime, as each may take a slice
+/// of its work to notice.
+async fn stop(control: &Control, handles: Vec<thread::JoinHandle<()>>) -> Result<(), Error> {
+    control.stop.store(true, Ordering::Relaxed);
+    let joined = task::spawn_blocking(move || {
+        handles
+            .into_iter()
+            .map(thread::JoinHandle::join)
+            .fold(true, |joined, res| joined && res.is_ok())
+    })
+    .await;
+    match joined {
+        Ok(true) => Ok(()),
+        Ok(false) | Err(_) => Err(Error::Join),
+    }
+}
+
+#[derive(Debug)]
+/// The antagonist server.
+pub struct Server {
+    config: Config,
+    stage: watch::Receiver<Stage>,
+    shutdown: Shutdown,
+}
+
+impl Server {
+    /// Create a new [`Server`]
+    ///
+    /// The antagonist follows the experiment's progress through `stage`.
+    #[must_use]
+    pub fn new(config: Config, stage: watch::Receiver<Stage>, shutdown: Shutdown) -> Self {
+        Self {
+            config,
+            stage,
+            shutdown,
+        }
+    }
+
+    /// Runs this [`Server`] to completion
+    ///
+    /// Worker threads are started immediately and create contention whenever
+    /// the experiment is in one of the configured stages, until a shutdown
+    /// signal is received. The threads are then stopped and joined.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if a worker thread cannot be spawned, or
+    /// panics.
+    pub async fn run(mut self) -> Result<(), Error> {
+        let name = self.config.kind.name();
+        let labels = vec![("antagonist".to_string(), name.to_string())];
+        let control = Arc::new(Control::default());
+
+        let workers = match self.config.kind {
+            Kind::Cpu(ref conf) => conf.workers(),
+            Kind::MemoryBandwidth(ref conf) => conf.workers(),
+            Kind::Disk(ref conf) => conf.workers(),
+        };
+        let handles = spawn(name, workers, &control, &labels)?;
+
+        let mut stage_changes = true;
+        loop {
+            let active = self.config.stages.contains(&*self.stage.borrow());
+            if active != control.active() {
+                info!("antagonist {} active: {}", name, active);
+            }
+            control.active.store(active, Ordering::Relaxed);
+            gauge!("antagonist_active", if active { 1.0 } else { 0.0 }, &labels);
+
+            tokio::select! {
+                changed = self.stage.changed(), if stage_changes => {
+                    // The experiment has ended its stage updates, the last
+                    // stage holds until shutdown.
+                    stage_changes = changed.is_ok();
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    gauge!("antagonist_active", 0.0, &labels);
+                    return stop(&control, handles).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        num::NonZeroU16,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use byte_unit::Byte;
+    use proptest::prelude::*;
+
+    use super::{cpu, memory, spawn, stop, Control, Worker};
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    // Once stopped every worker has exited, active or not, its thread joined.
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(8))]
+        #[test]
+        fn workers_joined_on_stop(workers in 1..8_usize, active: bool) {
+            let exited = Arc::new(AtomicUsize::new(0));
+            let work: Vec<Worker> = (0..workers)
+                .map(|_| {
+                    let exited = Arc::clone(&exited);
+                    Box::new(move |control: &Control, _labels: &Vec<(String, String)>| {
+                        while !control.stopped() {
+                            std::thread::yield_now();
+                        }
+                        exited.fetch_add(1, Ordering::SeqCst);
+                    }) as Worker
+                })
+                .collect();
+            let control = Arc::new(Control::default());
+            control.active.store(active, Ordering::Relaxed);
+            let handles = spawn("test", work, &control, &vec![]).unwrap();
+            block_on(stop(&control, handles)).unwrap();
+            prop_assert_eq!(exited.load(Ordering::SeqCst), workers);
+            prop_assert_eq!(Arc::strong_count(&control), 1);
+        }
+    }
+
+    // The CPU and memory bandwidth workers exit when stopped, whether or not
+    // they were contending at the time.
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(8))]
+        #[test]
+        fn contention_stops(cores in 1..4_u16, threads in 1..4_u16, active: bool) {
+            let mut work = cpu::Config { cores: NonZeroU16::new(cores).unwrap() }.workers();
+            work.extend(
+                memory::Config {
+                    threads: NonZeroU16::new(threads).unwrap(),
+                    buffer_bytes: Byte::from_bytes(64 * 1024),
+                }
+                .workers(),
+            );
+            prop_assert_eq!(work.len(), usize::from(cores + threads));
+            let control = Arc::new(Control::default());
+            control.active.store(active, Ordering::Relaxed);
+            let handles = spawn("test", work, &control, &vec![]).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            block_on(stop(&control, handles)).unwrap();
+            prop_assert_eq!(Arc::strong_count(&control), 1);
+        }
+    }
+}
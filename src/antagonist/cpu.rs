@@ -0,0 +1,47 @@
+//! The CPU antagonist.
+//!
+//! Burns a configured number of cores by spinning, ceding them only while
+//! inactive.
+
+use std::{
+    num::NonZeroU16,
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use super::{Control, Worker};
+
+/// The time a worker spins, or sleeps, before checking its [`Control`].
+const SLICE: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+/// Configuration for the CPU antagonist
+pub struct Config {
+    /// The number of cores to burn
+    pub cores: NonZeroU16,
+}
+
+impl Config {
+    pub(crate) fn workers(&self) -> Vec<Worker> {
+        (0..self.cores.get())
+            .map(|_| Box::new(burn) as Worker)
+            .collect()
+    }
+}
+
+/// Spin while active, sleep while not.
+#[allow(clippy::ptr_arg)]
+fn burn(control: &Control, _labels: &Vec<(String, String)>) {
+    while !control.stopped() {
+        if control.active() {
+            let start = Instant::now();
+            while start.elapsed() < SLICE {
+                std::hint::spin_loop();
+            }
+        } else {
+            thread::sleep(SLICE);
+        }
+    }
+}
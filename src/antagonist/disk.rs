@@ -0,0 +1,209 @@
+//! The disk pressure antagonist.
+//!
+//! Writes, and by default fsyncs, to a file at a fixed rate, contending with
+//! the target for the filesystem. Point it at the filesystem the target spools
+//! to. The file is rewritten from its start once it reaches its maximum size
+//! and removed when the antagonist stops.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use byte_unit::{Byte, ByteUnit};
+use metrics::{counter, histogram};
+use serde::Deserialize;
+use tracing::error;
+use uuid::Uuid;
+
+use super::{Control, Worker};
+
+/// The time an inactive worker sleeps before checking its [`Control`].
+const IDLE: Duration = Duration::from_millis(10);
+
+fn default_block_bytes() -> Byte {
+    Byte::from_unit(64.0, ByteUnit::KiB).unwrap()
+}
+
+fn default_maximum_file_bytes() -> Byte {
+    Byte::from_unit(1.0, ByteUnit::GiB).unwrap()
+}
+
+fn default_fsync() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration for the disk pressure antagonist
+pub struct Config {
+    /// The directory to write in, on the filesystem to contend for
+    pub directory: PathBuf,
+    /// The rate at which to write
+    pub bytes_per_second: Byte,
+    /// The size of each write, at most `maximum_file_bytes`
+    #[serde(default = "default_block_bytes")]
+    pub block_bytes: Byte,
+    /// Whether to fsync after each write
+    #[serde(default = "default_fsync")]
+    pub fsync: bool,
+    /// The size the file is allowed to reach before it is rewritten from its
+    /// start
+    #[serde(default = "default_maximum_file_bytes")]
+    pub maximum_file_bytes: Byte,
+}
+
+impl Config {
+    pub(crate) fn workers(&self) -> Vec<Worker> {
+        let config = self.clone();
+        vec![Box::new(
+            move |control: &Control, labels: &Vec<(String, String)>| {
+                let path = config
+                    .directory
+                    .join(format!("lading-antagonist-{}", Uuid::new_v4()));
+                if let Err(err) = pressure(&config, &path, control, labels) {
+                    error!("disk antagonist failed: {}", err);
+                }
+                let _ = fs::remove_file(&path);
+            },
+        )]
+    }
+}
+
+/// Write to `path` at the configured rate while active, sleep while not.
+#[allow(clippy::ptr_arg)]
+fn pressure(
+    config: &Config,
+    path: &Path,
+    control: &Control,
+    labels: &Vec<(String, String)>,
+) -> Result<(), io::Error> {
+    let mut file: File = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)?;
+    let maximum_file_bytes = config.maximum_file_bytes.get_bytes() as u64;
+    // A block larger than the file may grow to is clamped, lest each write
+    // from the file's start take it past its maximum.
+    let block_bytes = config
+        .block_bytes
+        .get_bytes()
+        .min(u128::from(maximum_file_bytes));
+    let block = vec![0xA5; (block_bytes as usize).max(1)];
+    let bytes_per_second = (config.bytes_per_second.get_bytes() as f64).max(1.0);
+    let block_period = Duration::from_secs_f64(block.len() as f64 / bytes_per_second);
+
+    let mut written: u64 = 0;
+    let mut next = Instant::now();
+    while !control.stopped() {
+        if !control.active() {
+            thread::sleep(IDLE);
+            next = Instant::now();
+            continue;
+        }
+        // Sleep in short slices so that a stop or deactivation is not missed
+        // at low rates.
+        let now = Instant::now();
+        if now < next {
+            thread::sleep((next - now).min(IDLE));
+            continue;
+        }
+        next += block_period;
+
+        if written + block.len() as u64 > maximum_file_bytes {
+            file.seek(SeekFrom::Start(0))?;
+            written = 0;
+        }
+        file.write_all(&block)?;
+        written += block.len() as u64;
+        counter!("antagonist_disk_bytes_written", block.len() as u64, labels);
+        if config.fsync {
+            let start = Instant::now();
+            file.sync_data()?;
+            histogram!(
+                "antagonist_disk_fsync_seconds",
+                start.elapsed().as_secs_f64(),
+                labels
+            );
+            counter!("antagonist_disk_fsyncs", 1, labels);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        sync::{atomic::Ordering, Arc},
+        thread,
+        time::Duration,
+    };
+
+    use byte_unit::Byte;
+    use proptest::prelude::*;
+
+    use super::Config;
+    use crate::antagonist::{spawn, stop, Control};
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    // While active the file written reaches but never exceeds its maximum
+    // size, blocks larger than it included, and once the antagonist is stopped
+    // the file is gone.
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(4))]
+        #[test]
+        fn file_bounded_and_removed(fsync: bool, block_kib in 1..128_u64) {
+            let directory = std::env::temp_dir().join(format!(
+                "lading-antagonist-disk-{}-{}-{}",
+                std::process::id(),
+                fsync,
+                block_kib
+            ));
+            fs::create_dir_all(&directory).unwrap();
+            let maximum_file_bytes = 64 * 1024;
+            let config = Config {
+                directory: directory.clone(),
+                bytes_per_second: Byte::from_bytes(1024 * 1024),
+                block_bytes: Byte::from_bytes(u128::from(block_kib * 1024)),
+                fsync,
+                maximum_file_bytes: Byte::from_bytes(maximum_file_bytes),
+            };
+            let control = Arc::new(Control::default());
+            control.active.store(true, Ordering::Relaxed);
+            let handles = spawn("disk", config.workers(), &control, &vec![]).unwrap();
+
+            // The maximum is written in 64 milliseconds at this rate, sampling
+            // spans a second so that fsyncs may slow the writes.
+            let mut largest = 0;
+            for _ in 0..100 {
+                let len = fs::read_dir(&directory)
+                    .unwrap()
+                    .filter_map(|entry| entry.unwrap().metadata().ok())
+                    .map(|metadata| metadata.len())
+                    .max()
+                    .unwrap_or(0);
+                largest = largest.max(len);
+                thread::sleep(Duration::from_millis(10));
+            }
+            block_on(stop(&control, handles)).unwrap();
+            let remaining = fs::read_dir(&directory).unwrap().count();
+            fs::remove_dir_all(&directory).unwrap();
+
+            let block_bytes = u128::from(block_kib * 1024).min(maximum_file_bytes);
+            prop_assert!(u128::from(largest) > maximum_file_bytes - block_bytes);
+            prop_assert!(u128::from(largest) <= maximum_file_bytes);
+            prop_assert_eq!(remaining, 0);
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! The memory bandwidth antagonist.
+//!
+//! Each worker repeatedly writes over a buffer larger than the host's caches,
+//! contending with the target for memory bandwidth.
+
+use std::{num::NonZeroU16, thread, time::Duration};
+
+use byte_unit::{Byte, ByteUnit};
+use metrics::counter;
+use serde::Deserialize;
+
+use super::{Control, Worker};
+
+/// The time an inactive worker sleeps before checking its [`Control`].
+const IDLE: Duration = Duration::from_millis(10);
+/// The words written between checks of a worker's [`Control`].
+const STRIDE: usize = 128 * 1024;
+/// The bytes in a word of the buffer.
+const WORD_BYTES: usize = std::mem::size_of::<u64>();
+
+fn default_threads() -> NonZeroU16 {
+    NonZeroU16::new(1).unwrap()
+}
+
+fn default_buffer_bytes() -> Byte {
+    Byte::from_unit(256.0, ByteUnit::MiB).unwrap()
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+/// Configuration for the memory bandwidth antagonist
+pub struct Config {
+    /// The number of threads writing to memory, each with its own buffer
+    #[serde(default = "default_threads")]
+    pub threads: NonZeroU16,
+    /// The size of each thread's buffer, which should exceed the host's last
+    /// level cache
+    #[serde(default = "default_buffer_bytes")]
+    pub buffer_bytes: Byte,
+}
+
+impl Config {
+    pub(crate) fn workers(&self) -> Vec<Worker> {
+        let buffer_bytes = self.buffer_bytes.get_bytes() as usize;
+        (0..self.threads.get())
+            .map(|_| {
+                Box::new(move |control: &Control, labels: &Vec<(String, String)>| {
+                    dirty(buffer_bytes, control, labels);
+                }) as Worker
+            })
+            .collect()
+    }
+}
+
+/// Write over a buffer of `buffer_bytes` while active, sleep while not.
+#[allow(clippy::ptr_arg)]
+fn dirty(buffer_bytes: usize, control: &Control, labels: &Vec<(String, String)>) {
+    let mut buffer: Vec<u64> = vec![0; (buffer_bytes / WORD_BYTES).max(1)];
+    let mut offset = 0;
+    let mut pass: u64 = 0;
+    while !control.stopped() {
+        if !control.active() {
+            thread::sleep(IDLE);
+            continue;
+        }
+        let end = (offset + STRIDE).min(buffer.len());
+        // Volatile, else writes to a buffer that is never read may be elided.
+        for word in &mut buffer[offset..end] {
+            // Safety: `word` is a valid, aligned, exclusive reference.
+            unsafe { std::ptr::write_volatile(word, pass) };
+        }
+        counter!(
+            "antagonist_bytes_dirtied",
+            ((end - offset) * WORD_BYTES) as u64,
+            labels
+        );
+        offset = end;
+        if offset == buffer.len() {
+            offset = 0;
+            pass = pass.wrapping_add(1);
+        }
+    }
+}
@@ -1,26 +1,42 @@
 use std::{
     collections::HashMap,
+    ffi::OsStr,
     fmt::{self, Display},
-    io::Read,
-    path::PathBuf,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
     str::FromStr,
 };
 
-use clap::Parser;
+use byte_unit::Byte;
+use clap::{Args, Parser, Subcommand};
+use flate2::read::GzDecoder;
+use futures::future::{join_all, pending};
 use lading::{
-    blackhole,
-    captures::CaptureManager,
+    antagonist::{self, Stage},
+    blackhole, budget,
+    captures::{self, CaptureManager, Soak},
+    chaos, cleanup,
+    clock::{self, Clock},
     config::{self, Config, Telemetry},
-    generator, inspector, observer,
-    signals::Shutdown,
+    control, dashboard, determinism, diff, export, generator, inspector, numa, observer, pairs,
+    preflight, pushgateway, runtime_stats,
+    signals::{Phase, PhasedShutdown},
+    status, supervisor, sweep,
     target::{self, Behavior, Output},
+    telemetry::CardinalityLimit,
+    throttle, trace, watchdog,
 };
+use metrics::gauge;
 use metrics_exporter_prometheus::PrometheusBuilder;
+use metrics_util::MetricKindMask;
 use tokio::{
     runtime::Builder,
-    signal,
-    sync::broadcast,
-    time::{sleep, Duration},
+    signal::{self, unix},
+    sync::{broadcast, mpsc, watch},
+    time::Duration,
 };
 use tracing::{debug, error, info, warn};
 
@@ -63,20 +79,34 @@ impl FromStr for CliKeyValues {
 }
 
 #[derive(Parser)]
-#[clap(version, about, long_about = None)]
+#[clap(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Opts {
+    /// a tool to run in place of an experiment
+    #[clap(subcommand)]
+    tool: Option<Tool>,
     /// path on disk to the configuration file
     #[clap(long, default_value_t = default_config_path())]
     config_path: String,
     /// additional labels to apply to all captures, format KEY=VAL,KEY2=VAL
     #[clap(long)]
     global_labels: Option<CliKeyValues>,
+    /// an identifier for this experiment, attached to all captures, overrides
+    /// the configuration's experiment id
+    #[clap(long)]
+    experiment_id: Option<String>,
     /// additional environment variables to apply to the target, format
     /// KEY=VAL,KEY2=VAL
     #[clap(long)]
     target_environment_variables: Option<CliKeyValues>,
     /// the path of the target executable
-    target_path: PathBuf,
+    #[clap(required = true)]
+    target_path: Option<PathBuf>,
     /// arguments for the target executable
     target_arguments: Vec<String>,
     /// the path to write target's stdout
@@ -85,14 +115,30 @@ struct Opts {
     /// the path to write target's stderr
     #[clap(long, default_value_t = default_target_behavior())]
     target_stderr_path: Behavior,
+    /// a template of the target's configuration file, rendered to
+    /// target-config-output before the target is started, each ${NAME}
+    /// substituted by the target's environment variable NAME
+    #[clap(long, requires = "target_config_output")]
+    target_config_template: Option<PathBuf>,
+    /// the path to write the target's rendered configuration file
+    #[clap(long, requires = "target_config_template")]
+    target_config_output: Option<PathBuf>,
     /// path on disk to write captures, will override prometheus-addr if both
     /// are set
     #[clap(long)]
     capture_path: Option<String>,
+    /// the time, in milliseconds, between flushes of the capture file, no less
+    /// than 100
+    #[clap(long)]
+    capture_interval_milliseconds: Option<u64>,
     /// address to bind prometheus exporter to, will be overridden by
     /// capture-path if both are set
     #[clap(long)]
     prometheus_addr: Option<String>,
+    /// the time, in seconds, after which a metric not updated is dropped from
+    /// the prometheus exporter
+    #[clap(long)]
+    prometheus_idle_timeout_seconds: Option<u64>,
     /// the maximum time to wait, in seconds, for controlled shutdown
     #[clap(long, default_value_t = 30)]
     max_shutdown_delay: u16,
@@ -106,10 +152,217 @@ struct Opts {
     /// whether to ignore inspector configuration, if present, and not run the inspector
     #[clap(long)]
     disable_inspector: bool,
+    /// whether to skip the checks of the host made before the run starts
+    #[clap(long)]
+    disable_preflight: bool,
+    /// whether to refuse to start should the checks of the host made before
+    /// the run find problems, rather than warn of them
+    #[clap(long, conflicts_with = "disable_preflight")]
+    strict_preflight: bool,
+    /// the free disk the checks of the host require for the capture file and
+    /// unbounded samples
+    #[clap(long, default_value = "1 GiB")]
+    preflight_minimum_free_bytes: Byte,
+    /// remove what a previous, crashed run of this configuration left behind
+    /// -- partial captures, temporary files, orphaned target processes -- and
+    /// exit without running
+    #[clap(long)]
+    cleanup_only: bool,
+    /// before the run, measure how accurately the configured throttles hold
+    /// their rate on this host, recording the result in the capture header
+    #[clap(long)]
+    calibrate_throttle: bool,
+    /// path on disk to periodically write the run's status to, as JSON
+    #[clap(long)]
+    status_file: Option<PathBuf>,
+    /// the time, in seconds, between writes of the status file
+    #[clap(long, default_value_t = 5)]
+    status_interval_seconds: u64,
+    /// address to serve the control API on, through which generators may be
+    /// paused and resumed mid-experiment and the run's phases advanced
+    #[clap(long)]
+    control_addr: Option<SocketAddr>,
+    /// hold warmup and the experiment until advanced, by SIGUSR1 or the
+    /// control API, rather than for their durations
+    #[clap(long)]
+    hold_phases: bool,
+    /// OTLP gRPC endpoint to export traces of lading's run to, for instance
+    /// http://localhost:4317
+    #[clap(long)]
+    otlp_endpoint: Option<String>,
+    /// end the experiment once all generators have finished and no blackhole
+    /// has received bytes for this many seconds, experiment duration remains
+    /// an upper bound
+    #[clap(long)]
+    drain_quiescence_seconds: Option<u32>,
+    /// the time, in seconds, blackholes must receive no bytes during shutdown
+    /// before the target is signaled
+    #[clap(long, default_value_t = 5)]
+    shutdown_quiescence_seconds: u32,
+    /// the time, in seconds, to keep observing the target and running
+    /// blackholes once generators have stopped
+    #[clap(long, default_value_t = 0)]
+    cooldown_seconds: u32,
+    /// validate configuration and simulate the experiment schedule without
+    /// running the target or sending any traffic
+    #[clap(long)]
+    dry_run: bool,
+    /// build each generator's block cache twice, checking the builds are
+    /// byte-identical, and report every source of entropy generators draw on,
+    /// without running the target
+    #[clap(long)]
+    verify_determinism: bool,
+    /// directory to hand generators' block caches off between runs of one
+    /// configuration through, each cache loaded from it if present and
+    /// otherwise built and written to it
+    #[clap(long)]
+    block_cache_dir: Option<PathBuf>,
+    /// soak mode for multi-day runs, segmenting the capture file, requires
+    /// captures be written to disk
+    #[clap(long)]
+    soak: bool,
+    /// in soak mode, the time in seconds each capture segment spans
+    #[clap(long, default_value_t = 3600)]
+    soak_segment_seconds: u64,
+    /// in soak mode, gzip compress finished capture segments
+    #[clap(long)]
+    soak_compress_segments: bool,
+    /// in soak mode, the maximum number of finished capture segments to keep,
+    /// oldest pruned first
+    #[clap(long)]
+    soak_maximum_segments: Option<usize>,
+}
+
+#[derive(Subcommand)]
+/// The tools run in place of an experiment, see [`Opts::tool`].
+enum Tool {
+    Diff(DiffOpts),
+    Export(ExportOpts),
+    Dashboard(DashboardOpts),
+    Sweep(SweepOpts),
+    Ab(AbOpts),
 }
 
-fn get_config() -> (Opts, Config) {
-    let ops: Opts = Opts::parse();
+#[derive(Args)]
+/// Compare the records sent to the target against those a blackhole received,
+/// reporting loss, duplication, reordering and mutation
+struct DiffOpts {
+    /// path on disk to the newline delimited JSON records sent to the target
+    #[clap(long)]
+    sent_path: PathBuf,
+    /// path on disk to the newline delimited JSON records received from the
+    /// target, for instance a blackhole sample
+    #[clap(long)]
+    received_path: PathBuf,
+    /// the field identifying each record
+    #[clap(long, default_value = "id")]
+    id_field: String,
+}
+
+/// Run `lading diff`, returning whether the sent and received records agree.
+fn run_diff(opts: &DiffOpts) -> bool {
+    let open = |path: &PathBuf| {
+        File::open(path)
+            .map(BufReader::new)
+            .unwrap_or_else(|err| panic!("Could not open {}: {}", path.display(), err))
+    };
+    match diff::diff(
+        open(&opts.sent_path),
+        open(&opts.received_path),
+        &opts.id_field,
+    ) {
+        Ok(report) => {
+            println!("{}", report);
+            report.is_clean()
+        }
+        Err(err) => {
+            error!("diff failed: {:?}", err);
+            false
+        }
+    }
+}
+
+#[derive(Clone, Copy, clap::ArgEnum)]
+enum ExportFormat {
+    Openmetrics,
+}
+
+#[derive(Args)]
+/// Convert a capture file to another format
+struct ExportOpts {
+    /// path on disk to the capture file, gzip compressed if it ends in `.gz`
+    #[clap(long)]
+    capture_path: PathBuf,
+    /// the format to convert to
+    #[clap(long, arg_enum)]
+    format: ExportFormat,
+    /// path on disk to write the conversion to, stdout if unset
+    #[clap(long)]
+    output_path: Option<PathBuf>,
+}
+
+/// Run `lading export`, returning whether the conversion succeeded.
+fn run_export(opts: &ExportOpts) -> bool {
+    let capture = File::open(&opts.capture_path)
+        .unwrap_or_else(|err| panic!("Could not open {}: {}", opts.capture_path.display(), err));
+    let reader: Box<dyn BufRead> = if opts.capture_path.extension() == Some(OsStr::new("gz")) {
+        Box::new(BufReader::new(GzDecoder::new(capture)))
+    } else {
+        Box::new(BufReader::new(capture))
+    };
+    let writer: Box<dyn Write> = match opts.output_path {
+        Some(ref path) => {
+            Box::new(BufWriter::new(File::create(path).unwrap_or_else(|err| {
+                panic!("Could not create {}: {}", path.display(), err)
+            })))
+        }
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+    let result = match opts.format {
+        ExportFormat::Openmetrics => export::openmetrics(reader, writer),
+    };
+    match result {
+        Ok(summary) => {
+            // Not logged, logs share stdout with the conversion.
+            eprintln!(
+                "exported {} samples, skipped {} lines",
+                summary.samples, summary.skipped
+            );
+            true
+        }
+        Err(err) => {
+            error!("export failed: {:?}", err);
+            false
+        }
+    }
+}
+
+#[derive(Args)]
+/// Emit a Grafana dashboard for the components of a configuration
+struct DashboardOpts {
+    /// path on disk to the configuration file
+    #[clap(long, default_value_t = default_config_path())]
+    config_path: String,
+    /// the title of the dashboard
+    #[clap(long, default_value = "lading")]
+    title: String,
+}
+
+/// Run `lading dashboard`, writing the dashboard to stdout.
+fn run_dashboard(opts: &DashboardOpts) -> bool {
+    let contents = std::fs::read_to_string(&opts.config_path).unwrap_or_else(|_| {
+        panic!(
+            "Could not open configuration file at: {}",
+            &opts.config_path
+        )
+    });
+    let config: Config = serde_yaml::from_str(&contents).unwrap();
+    let dashboard = dashboard::grafana(&config, &opts.title);
+    println!("{}", serde_json::to_string_pretty(&dashboard).unwrap());
+    true
+}
+
+fn get_config(ops: Opts) -> (Opts, Config) {
     debug!(
         "Attempting to open configuration file at: {}",
         ops.config_path
@@ -121,8 +374,17 @@ fn get_config() -> (Opts, Config) {
     let mut contents = String::new();
     file.read_to_string(&mut contents).unwrap();
     let mut config: Config = serde_yaml::from_str(&contents).unwrap();
+    let stdin = match config.generator {
+        config::Generator::One(ref cfg) => matches!(**cfg, generator::Config::Stdin(_)),
+        config::Generator::Many(ref cfgs) => cfgs
+            .iter()
+            .any(|cfg| matches!(cfg, generator::Config::Stdin(_))),
+    };
     let target_config = target::Config {
-        command: ops.target_path.clone(),
+        command: ops
+            .target_path
+            .clone()
+            .expect("a target is required to run an experiment"),
         arguments: ops.target_arguments.clone(),
         environment_variables: ops
             .target_environment_variables
@@ -133,50 +395,145 @@ fn get_config() -> (Opts, Config) {
             stderr: ops.target_stderr_path.clone(),
             stdout: ops.target_stdout_path.clone(),
         },
+        config_file: ops
+            .target_config_template
+            .clone()
+            .zip(ops.target_config_output.clone())
+            .map(|(template, output)| target::ConfigFile { template, output }),
+        stdin,
     };
     config.target = Some(target_config);
+    if let Some(ref experiment_id) = ops.experiment_id {
+        config.experiment.id = Some(experiment_id.clone());
+    }
     let options_global_labels = ops.global_labels.clone().unwrap_or_default();
+    let options_soak = ops.soak.then(|| Soak {
+        segment_seconds: ops.soak_segment_seconds,
+        compress: ops.soak_compress_segments,
+        maximum_segments: ops.soak_maximum_segments,
+    });
     if let Some(ref prom_addr) = ops.prometheus_addr {
         config.telemetry = Telemetry::Prometheus {
             prometheus_addr: prom_addr.parse().unwrap(),
             global_labels: options_global_labels.inner,
+            idle_timeout_seconds: ops.prometheus_idle_timeout_seconds,
+            push_gateway: None,
         };
     } else if let Some(ref capture_path) = ops.capture_path {
         config.telemetry = Telemetry::Log {
             path: capture_path.parse().unwrap(),
             global_labels: options_global_labels.inner,
+            soak: options_soak,
+            interval_milliseconds: ops
+                .capture_interval_milliseconds
+                .unwrap_or_else(captures::default_interval_milliseconds),
         };
     } else {
         match config.telemetry {
             Telemetry::Prometheus {
                 ref mut global_labels,
+                ref mut idle_timeout_seconds,
                 ..
             } => {
                 for (k, v) in options_global_labels.inner {
                     global_labels.insert(k, v);
                 }
+                if ops.prometheus_idle_timeout_seconds.is_some() {
+                    *idle_timeout_seconds = ops.prometheus_idle_timeout_seconds;
+                }
             }
             Telemetry::Log {
                 ref mut global_labels,
+                ref mut soak,
+                ref mut interval_milliseconds,
                 ..
             } => {
                 for (k, v) in options_global_labels.inner {
                     global_labels.insert(k, v);
                 }
+                if options_soak.is_some() {
+                    *soak = options_soak;
+                }
+                if let Some(capture_interval) = ops.capture_interval_milliseconds {
+                    *interval_milliseconds = capture_interval;
+                }
             }
         }
     }
+    if ops.soak && matches!(config.telemetry, Telemetry::Prometheus { .. }) {
+        warn!("soak mode has no effect unless captures are written to disk");
+    }
     (ops, config)
 }
 
-async fn inner_main(
+#[derive(Clone, Copy)]
+/// The timing of an experiment, see [`Opts`].
+struct Schedule {
     experiment_duration: Duration,
     warmup_duration: Duration,
     max_shutdown_delay: Duration,
+    drain_quiescence: Option<Duration>,
+    shutdown_quiescence: Duration,
+    cooldown: Duration,
+    hold_phases: bool,
+}
+
+/// The time [`throttle::calibrate`] runs each throttle algorithm for.
+const THROTTLE_CALIBRATION_DURATION: Duration = Duration::from_secs(2);
+
+/// Run the experiment, returning why it ended and whether the target kept
+/// within its budget, if any.
+#[allow(clippy::too_many_arguments)]
+async fn inner_main(
+    schedule: Schedule,
     disable_inspector: bool,
+    throttle_calibration: Option<throttle::Calibration>,
+    status_file: Option<PathBuf>,
+    status_interval: Duration,
+    control_addr: Option<SocketAddr>,
+    lifecycle: &mut trace::Lifecycle,
     config: Config,
-) {
-    let shutdown = Shutdown::new();
+) -> (status::Ending, bool) {
+    let Schedule {
+        experiment_duration,
+        warmup_duration,
+        max_shutdown_delay,
+        drain_quiescence,
+        shutdown_quiescence,
+        cooldown,
+        hold_phases,
+    } = schedule;
+    let shutdown = PhasedShutdown::new();
+    let clock = Clock::real();
+    if let Some(ref id) = config.experiment.id {
+        info!("starting experiment {}", id);
+    }
+    lifecycle.stage("setup");
+    // Telemetry consumes the configuration, so the context the target is given
+    // is taken before.
+    let target_context = config.target_context();
+
+    // The run's state is reported to the status file, if there is one, as it
+    // changes.
+    let (status_snd, status_rcv) = watch::channel(status::State::default());
+    let status_handle = status_file.map(|path| {
+        let status_server = status::Server::new(
+            status::Config {
+                path,
+                interval: status_interval,
+                experiment_id: config.experiment.id.clone(),
+                warmup: warmup_duration,
+                experiment: experiment_duration,
+                blackholes: config.blackhole.is_some(),
+            },
+            status_rcv,
+        );
+        tokio::spawn(async move {
+            if let Err(err) = status_server.run().await {
+                error!("status file could not be written: {:?}", err);
+            }
+        })
+    });
 
     // Set up the telemetry sub-system.
     //
@@ -187,26 +544,95 @@ async fn inner_main(
         Telemetry::Prometheus {
             prometheus_addr,
             global_labels,
+            idle_timeout_seconds,
+            push_gateway,
         } => {
-            let mut builder = PrometheusBuilder::new().with_http_listener(prometheus_addr);
-            for (k, v) in global_labels {
+            let mut builder = PrometheusBuilder::new()
+                .with_http_listener(prometheus_addr)
+                .idle_timeout(
+                    MetricKindMask::ALL,
+                    idle_timeout_seconds.map(Duration::from_secs),
+                );
+            for (k, v) in global_labels
+                .into_iter()
+                .chain(config.experiment.metric_labels())
+            {
                 builder = builder.add_global_label(k, v);
             }
-            let _: () = builder.install().unwrap();
+            let (recorder, exporter) = builder.build().unwrap();
+            let handle = recorder.handle();
+            metrics::set_boxed_recorder(Box::new(CardinalityLimit::new(
+                recorder,
+                config.maximum_label_values,
+            )))
+            .unwrap();
+            let _exporter = tokio::spawn(exporter);
+            if let Some(push_gateway) = push_gateway {
+                let pusher = pushgateway::Pusher::new(
+                    &push_gateway,
+                    config.experiment.id.as_deref(),
+                    handle,
+                    shutdown.get(Phase::Telemetry),
+                )
+                .unwrap();
+                let _pusher = tokio::spawn(async move {
+                    if let Err(err) = pusher.run().await {
+                        error!("final push to the Pushgateway failed: {:?}", err);
+                    }
+                });
+            }
         }
         Telemetry::Log {
             path,
             global_labels,
+            soak,
+            interval_milliseconds,
         } => {
-            let mut capture_manager = CaptureManager::new(path, shutdown.clone()).await;
-            capture_manager.install();
-            for (k, v) in global_labels {
+            let mut capture_manager = CaptureManager::new(
+                path,
+                Duration::from_millis(interval_milliseconds),
+                soak,
+                shutdown.get(Phase::Telemetry),
+            )
+            .await;
+            capture_manager.install(config.maximum_label_values);
+            for (k, v) in global_labels
+                .into_iter()
+                .chain(config.experiment.metric_labels())
+            {
                 capture_manager.add_global_label(k, v);
             }
+            capture_manager.set_experiment(config.experiment.clone());
+            if let Some(ref calibration) = throttle_calibration {
+                capture_manager.set_throttle_calibration(calibration.clone());
+            }
             let _capmgr = tokio::spawn(capture_manager.run());
         }
     }
 
+    // Record the resolution of the platform's timer. Throttling, and so the
+    // shape of the load generated, is only as precise as this.
+    let timer_resolution = clock::resolution();
+    info!(
+        "timer resolution on {} is {:?}",
+        std::env::consts::ARCH,
+        timer_resolution
+    );
+    gauge!("timer_resolution_seconds", timer_resolution.as_secs_f64());
+    if let Some(calibration) = throttle_calibration {
+        for throttle in calibration.throttles {
+            gauge!(
+                "throttle_calibration_error_percent",
+                throttle.error_percent,
+                "throttle" => throttle.throttle
+            );
+        }
+        gauge!(
+            "throttle_calibration_sleep_overshoot_seconds",
+            calibration.sleep_overshoot_seconds
+        );
+    }
+
     // Set up the application servers. These are, depending on configuration:
     //
     // * the "generator" which pushes load into
@@ -225,19 +651,142 @@ async fn inner_main(
     //
     // GENERATOR
     //
-    match config.generator {
-        config::Generator::One(cfg) => {
-            let tgt_rcv = tgt_snd.subscribe();
-            let generator_server = generator::Server::new(*cfg, shutdown.clone()).unwrap();
-            let _gsrv = tokio::spawn(generator_server.run(tgt_rcv));
-        }
-        config::Generator::Many(cfgs) => {
-            for cfg in cfgs {
-                let tgt_rcv = tgt_snd.subscribe();
-                let generator_server = generator::Server::new(cfg, shutdown.clone()).unwrap();
-                let _gsrv = tokio::spawn(generator_server.run(tgt_rcv));
-            }
+    // Generators and blackholes are supervised, see `supervisor::supervise`. A
+    // component failure that cannot be recovered from is transmitted through
+    // `failure_snd` and aborts the experiment.
+    let (failure_snd, mut failure_rcv) = mpsc::unbounded_channel::<supervisor::Error>();
+    let component_failure = config.component_failure;
+    let generator_cfgs = match config.generator {
+        config::Generator::One(cfg) => vec![*cfg],
+        config::Generator::Many(cfgs) => cfgs,
+    };
+    let mut gsrv_handles = Vec::new();
+    let mut switches = Vec::new();
+    let mut generator_meters = Vec::new();
+    let jitters = config
+        .generator_jitter
+        .map(|jitter| jitter.draw(generator_cfgs.len()));
+    for (idx, cfg) in generator_cfgs.into_iter().enumerate() {
+        let mut tgt_rcv = tgt_snd.subscribe();
+        let gen_shutdown = shutdown.get(Phase::Generator);
+        let component = format!("generator_{}", idx);
+        // Restarts share the pause and meter of the first instance, and so
+        // their state.
+        let (switch, mut pause) = control::Switch::new(component.clone());
+        switches.push(switch);
+        let jitter = jitters.as_ref().map(|jitters| jitters[idx]);
+        if let Some(jitter) = jitter {
+            pause = pause.with_phase_jitter(jitter.maximum_phase_jitter, jitter.phase_seed);
         }
+        let start_offset = jitter.map_or(Duration::ZERO, |jitter| jitter.start_offset);
+        let mut offset_shutdown = shutdown.get(Phase::Generator);
+        let signaled = offset_shutdown.signaled();
+        let meter = generator::Meter::default();
+        generator_meters.push(meter.clone());
+        // The first instance is built eagerly so that its block cache is
+        // constructed before the target is started. Restarts build anew. A
+        // generator placed on a NUMA node builds its block cache from the
+        // node's memory and runs on the node's CPUs.
+        let placement = cfg.numa();
+        let mut initial = Some(lifecycle.in_span("block_build", &component, || {
+            generator::as_component(&component, || match placement {
+                Some(placement) => numa::with_memory(placement.node, || {
+                    generator::Server::new(
+                        cfg.clone(),
+                        gen_shutdown.clone(),
+                        pause.clone(),
+                        meter.clone(),
+                    )
+                })
+                .unwrap()
+                .unwrap(),
+                None => generator::Server::new(
+                    cfg.clone(),
+                    gen_shutdown.clone(),
+                    pause.clone(),
+                    meter.clone(),
+                )
+                .unwrap(),
+            })
+        }));
+        let name = component.clone();
+        let make = move || {
+            let initial = initial.take();
+            let cfg = cfg.clone();
+            let gen_shutdown = gen_shutdown.clone();
+            let pause = pause.clone();
+            let meter = meter.clone();
+            let name = name.clone();
+            let component = name.clone();
+            let spin = move || async move {
+                let server = match initial {
+                    Some(server) => server,
+                    None => generator::as_component(&component, || {
+                        generator::Server::new(cfg, gen_shutdown, pause, meter)
+                    })?,
+                };
+                server.spin().await
+            };
+            async move {
+                match placement {
+                    Some(placement) => numa::run(placement, &name, spin)
+                        .await
+                        .map_err(generator::Error::Numa)?,
+                    None => spin().await,
+                }
+            }
+        };
+        let failure_snd = failure_snd.clone();
+        gsrv_handles.push(tokio::spawn(async move {
+            let _ = tgt_rcv
+                .recv()
+                .await
+                .expect("target failed to transmit PID, catastrophic failure");
+            drop(tgt_rcv);
+            if !start_offset.is_zero() {
+                info!("{} starts {:?} after the target", component, start_offset);
+                tokio::select! {
+                    _ = tokio::time::sleep(start_offset) => {}
+                    _ = offset_shutdown.recv() => return,
+                }
+            }
+            drop(offset_shutdown);
+            if let Err(err) =
+                supervisor::supervise(component, component_failure, signaled, make).await
+            {
+                let _ = failure_snd.send(err);
+            }
+        }));
+    }
+
+    //
+    // CONTROL
+    //
+    // The run's phases are advanced by the control API, if there is one, and
+    // when held by SIGUSR1. Otherwise SIGUSR1 keeps its default disposition.
+    let advance = control::Advance::default();
+    if hold_phases {
+        let mut usr1 = unix::signal(unix::SignalKind::user_defined1()).unwrap();
+        let usr1_advance = advance.clone();
+        let _usr1 = tokio::spawn(async move {
+            while usr1.recv().await.is_some() {
+                info!("received SIGUSR1");
+                usr1_advance.advance();
+            }
+        });
+    }
+    if let Some(addr) = control_addr {
+        let control_server = control::Server::new(
+            addr,
+            switches,
+            advance.clone(),
+            shutdown.get(Phase::Generator),
+        );
+        let _csrv = tokio::spawn(async move {
+            if let Err(err) = control_server.run().await {
+                error!("control API failed: {:?}", err);
+            }
+        });
     }
 
     //
@@ -247,98 +796,987 @@ async fn inner_main(
         if !disable_inspector {
             let tgt_rcv = tgt_snd.subscribe();
             let inspector_server =
-                inspector::Server::new(inspector_conf, shutdown.clone()).unwrap();
+                inspector::Server::new(inspector_conf, shutdown.get(Phase::Target)).unwrap();
             let _isrv = tokio::spawn(inspector_server.run(tgt_rcv));
         }
     }
 
+    // The experiment's stage, followed by antagonists and blackhole rate
+    // alarms.
+    let (stage_snd, stage_rcv) = watch::channel(Stage::Warmup);
+
     //
     // BLACKHOLE
     //
-    match config.blackhole {
-        Some(config::Blackhole::One(cfg)) => {
-            let blackhole_server = blackhole::Server::new(*cfg, shutdown.clone());
-            let _bsrv = tokio::spawn(async {
-                match blackhole_server.run().await {
-                    Ok(()) => debug!("blackhole shut down successfully"),
-                    Err(err) => warn!("blackhole failed with {:?}", err),
+    let blackhole_cfgs = match config.blackhole {
+        Some(config::Blackhole::One(cfg)) => vec![*cfg],
+        Some(config::Blackhole::Many(cfgs)) => cfgs,
+        None => vec![],
+    };
+    let blackhole_present = !blackhole_cfgs.is_empty();
+    let mut target_config = config.target.unwrap();
+    for (name, value) in target_context {
+        target_config.provide(&name, &value);
+    }
+    let mut blackhole_meters = Vec::new();
+    for (idx, mut cfg) in blackhole_cfgs.into_iter().enumerate() {
+        let component = format!("blackhole_{}", idx);
+        // A blackhole bound to port 0 is given its port now, so that the
+        // target can be told of it before it is started. A vsock blackhole
+        // binds the port it is configured with.
+        if let Some(addr) = cfg
+            .resolve_binding_addr()
+            .expect("could not resolve blackhole binding address")
+        {
+            info!("{} binds {}", component, addr);
+            target_config.provide(&format!("LADING_BLACKHOLE_{}_ADDR", idx), &addr.to_string());
+            target_config.provide(
+                &format!("LADING_BLACKHOLE_{}_PORT", idx),
+                &addr.port().to_string(),
+            );
+        }
+        // The meter is shared by restarts of the blackhole, so its rate alarm
+        // judges the blackhole across them. Alarms judge only while load is
+        // applied, hence are shut down alongside the generators.
+        let meter = blackhole::Meter::default();
+        blackhole_meters.push(meter.clone());
+        if let Some(rate_conf) = cfg.expected_rate() {
+            let alarm = blackhole::rate::Alarm::new(
+                *rate_conf,
+                meter.clone(),
+                component.clone(),
+                stage_rcv.clone(),
+                shutdown.get(Phase::Generator),
+            );
+            let failure_snd = failure_snd.clone();
+            let component = component.clone();
+            let _asrv = tokio::spawn(async move {
+                if let Err(err) = alarm.run().await {
+                    let _ = failure_snd.send(supervisor::Error::Failed {
+                        component,
+                        reason: format!("{:?}", err),
+                    });
                 }
             });
         }
-        Some(config::Blackhole::Many(cfgs)) => {
-            for cfg in cfgs {
-                let blackhole_server = blackhole::Server::new(cfg, shutdown.clone());
-                let _bsrv = tokio::spawn(async {
-                    match blackhole_server.run().await {
-                        Ok(()) => debug!("blackhole shut down successfully"),
-                        Err(err) => warn!("blackhole failed with {:?}", err),
-                    }
+        let bh_shutdown = shutdown.get(Phase::Blackhole);
+        let signaled = bh_shutdown.signaled();
+        let placement = cfg.numa();
+        let name = component.clone();
+        let make = move || {
+            let server =
+                blackhole::Server::new(cfg.clone(), meter.clone(), &name, bh_shutdown.clone());
+            let name = name.clone();
+            async move {
+                match placement {
+                    Some(placement) => numa::run(placement, &name, move || server.run())
+                        .await
+                        .map_err(blackhole::Error::Numa)?,
+                    None => server.run().await,
+                }
+            }
+        };
+        let failure_snd = failure_snd.clone();
+        let _bsrv = tokio::spawn(async move {
+            match supervisor::supervise(component, component_failure, signaled, make).await {
+                Ok(()) => debug!("blackhole shut down successfully"),
+                Err(err) => {
+                    warn!("blackhole failed with {:?}", err);
+                    let _ = failure_snd.send(err);
+                }
+            }
+        });
+    }
+
+    //
+    // PAIRS
+    //
+    // Pairs are reported until the blackholes stop, so that the load in flight
+    // at shutdown is accounted for. Pairs naming unconfigured components fail
+    // the experiment as a failed component would.
+    if !config.pairs.is_empty() {
+        match pairs::Server::new(
+            &config.pairs,
+            &generator_meters,
+            &blackhole_meters,
+            shutdown.get(Phase::Blackhole),
+        ) {
+            Ok(pairs_server) => {
+                let _psrv = tokio::spawn(pairs_server.run());
+            }
+            Err(err) => {
+                let _ = failure_snd.send(supervisor::Error::Failed {
+                    component: "pairs".to_string(),
+                    reason: format!("{:?}", err),
                 });
             }
         }
-        None => {}
     }
 
+    //
+    // WATCHDOG
+    //
+    // A stalled target is reported as a failure, aborting the experiment, only
+    // if the watchdog is configured to abort.
+    if let Some(watchdog_conf) = config.watchdog {
+        let watchdog_server = watchdog::Server::new(watchdog_conf, shutdown.get(Phase::Target));
+        let failure_snd = failure_snd.clone();
+        let _wsrv = tokio::spawn(async move {
+            if let Err(err) = watchdog_server.run().await {
+                let _ = failure_snd.send(supervisor::Error::Failed {
+                    component: "watchdog".to_string(),
+                    reason: format!("{:?}", err),
+                });
+            }
+        });
+    }
+    // Only supervised components, rate alarms and the watchdog hold a sender,
+    // allowing the channel to close once they have all completed.
+    drop(failure_snd);
+
     //
     // OBSERVER
     //
     let obs_rcv = tgt_snd.subscribe();
-    let observer_server = observer::Server::new(config.observer, shutdown.clone()).unwrap();
+    let observer_server =
+        observer::Server::new(config.observer, shutdown.get(Phase::Target)).unwrap();
     let _osrv = tokio::spawn(observer_server.run(obs_rcv));
 
-    let target_server = target::Server::new(config.target.unwrap(), shutdown.clone()).unwrap();
-    let tsrv = tokio::spawn(target_server.run(tgt_snd));
+    //
+    // RUNTIME STATS
+    //
+    if !config.runtime_stats.is_empty() {
+        let runtime_stats_server =
+            runtime_stats::Server::new(config.runtime_stats, shutdown.get(Phase::Target));
+        let _rsrv = tokio::spawn(runtime_stats_server.run());
+    }
+
+    //
+    // ANTAGONIST
+    //
+    // Antagonists follow the experiment's stage, active only in those they
+    // are configured for. They are shut down alongside the generators.
+    for cfg in config.antagonist {
+        let antagonist_server =
+            antagonist::Server::new(cfg, stage_rcv.clone(), shutdown.get(Phase::Generator));
+        let _asrv = tokio::spawn(async move {
+            if let Err(err) = antagonist_server.run().await {
+                error!("antagonist failed with {:?}", err);
+            }
+        });
+    }
+
+    //
+    // BUDGET
+    //
+    // The target is judged against its budget in the steady state, while load
+    // is applied.
+    let budget_handle = config.budget.map(|budget_conf| {
+        let budget_server = budget::Server::new(
+            budget_conf,
+            stage_rcv.clone(),
+            shutdown.get(Phase::Generator),
+        );
+        tokio::spawn(budget_server.run())
+    });
 
-    info!("target is running, now sleeping for warmup");
-    sleep(warmup_duration).await;
-    info!("warmup completed, collecting samples");
+    //
+    // CHAOS
+    //
+    // Actions against the target begin with the experiment stage. They stop
+    // alongside the generators, a stopped target being continued so that it
+    // may be shut down.
+    if !config.chaos.is_empty() {
+        let chaos_server = chaos::Server::new(
+            config.chaos,
+            stage_rcv.clone(),
+            shutdown.get(Phase::Generator),
+        );
+        let _csrv = tokio::spawn(async move {
+            if let Err(err) = chaos_server.run().await {
+                error!("chaos schedule failed with {:?}", err);
+            }
+        });
+    }
+    drop(stage_rcv);
 
-    let experiment_duration = sleep(experiment_duration);
+    let target_server = target::Server::new(target_config, shutdown.get(Phase::Target)).unwrap();
+    let tsrv = tokio::spawn(target_server.run(tgt_snd));
+
+    let report = |phase, ending| {
+        let _ = status_snd.send(status::State { phase, ending });
+    };
+    report(status::Phase::Warmup, None);
+    lifecycle.stage("warmup");
+    captures::annotate("phase", "warmup");
+    if hold_phases {
+        info!("target is running, warmup held until advanced");
+    } else {
+        info!("target is running, now sleeping for warmup");
+    }
+    let advanced = advance.count();
     tokio::select! {
+        _ = clock.sleep(warmup_duration), if !hold_phases => {
+            info!("warmup completed, collecting samples");
+        }
+        _ = advance.wait(advanced) => {
+            info!("warmup advanced, collecting samples");
+        }
+    }
+    let _ = stage_snd.send(Stage::Experiment);
+    report(status::Phase::Experiment, None);
+    lifecycle.stage("experiment");
+    captures::annotate("phase", "experiment");
+
+    let experiment_duration = clock.sleep(experiment_duration);
+    let advanced = advance.count();
+    // The pipeline is drained once every generator has finished -- see
+    // `maximum_bytes` et al -- and the target has stopped pushing bytes into
+    // the blackholes. If the user has not asked for drain detection this
+    // future never completes.
+    let drained = async move {
+        match drain_quiescence {
+            Some(quiet_period) => {
+                join_all(gsrv_handles).await;
+                info!("all generators finished, waiting for blackhole quiescence");
+                blackhole::wait_for_quiescence(quiet_period).await;
+            }
+            None => pending().await,
+        }
+    };
+    let ending = tokio::select! {
         _ = signal::ctrl_c() => {
             info!("received ctrl-c");
-            shutdown.signal().unwrap();
+            status::Ending::Interrupted
         },
-        _ = experiment_duration => {
+        _ = experiment_duration, if !hold_phases => {
             info!("experiment duration exceeded");
-            shutdown.signal().unwrap();
+            status::Ending::DurationElapsed
+        }
+        _ = advance.wait(advanced) => {
+            info!("experiment advanced");
+            status::Ending::Advanced
+        }
+        _ = drained => {
+            info!("target drained");
+            status::Ending::Drained
+        }
+        Some(failure) = failure_rcv.recv() => {
+            error!("aborting experiment, component failed: {:?}", failure);
+            status::Ending::ComponentFailed
         }
         tgt = tsrv => {
             error!("target shut down unexpectedly with {:?}", tgt);
-            shutdown.signal().unwrap();
+            status::Ending::TargetExited
         }
-    }
+    };
+    report(status::Phase::ShuttingDown, Some(ending));
+    lifecycle.stage("shutdown");
+    captures::annotate("phase", "shutdown");
     info!(
         "Waiting for {} seconds for tasks to shutdown.",
         max_shutdown_delay.as_secs(),
     );
-    shutdown.wait(max_shutdown_delay).await;
+    // Generators are stopped first. Any data in-flight in the target is given
+    // the chance to arrive in the blackholes, if there are any, before the
+    // target is stopped.
+    let quiescence = async move {
+        if blackhole_present {
+            blackhole::wait_for_quiescence(shutdown_quiescence).await;
+        }
+    };
+    shutdown
+        .shutdown(max_shutdown_delay, cooldown, quiescence)
+        .await;
+    report(status::Phase::Finished, Some(ending));
+    if let Some(handle) = status_handle {
+        let _ = handle.await;
+    }
+    let bytes_written = generator::total_bytes_written();
+    let bytes_received = blackhole::total_bytes_received();
+    match status::efficiency(bytes_written, bytes_received) {
+        Some(efficiency) if blackhole_present => info!(
+            "generators wrote {} bytes, blackholes received {} bytes, efficiency {:.4}",
+            bytes_written, bytes_received, efficiency
+        ),
+        _ => info!("generators wrote {} bytes", bytes_written),
+    }
+    // A budget check that did not complete cannot vouch for the target, and so
+    // fails the run as a violation would.
+    let within_budget = match budget_handle {
+        Some(handle) => match handle.await {
+            Ok(violations) => {
+                if !violations.is_empty() {
+                    error!("target exceeded {} budget(s):", violations.len());
+                    for violation in &violations {
+                        error!("  {}", violation);
+                    }
+                }
+                violations.is_empty()
+            }
+            Err(err) => {
+                error!("budget check did not complete: {}", err);
+                false
+            }
+        },
+        None => true,
+    };
+    if let Some(ref id) = config.experiment.id {
+        info!("experiment {} finished", id);
+    }
+    (ending, within_budget)
+}
+
+#[derive(Args)]
+/// Run an experiment once for each point of a matrix of configuration
+/// overrides
+struct SweepOpts {
+    /// path on disk to the base configuration file
+    #[clap(long, default_value_t = default_config_path())]
+    config_path: String,
+    /// path on disk to the sweep file, holding the matrix of overrides
+    #[clap(long)]
+    sweep_path: PathBuf,
+    /// directory to write each run's configuration, capture and log to, and
+    /// the combined summary
+    #[clap(long)]
+    output_dir: PathBuf,
+    /// the time in seconds to wait between runs, letting the host settle
+    #[clap(long, default_value_t = 60)]
+    cooldown_seconds: u64,
+    /// an identifier shared by every run of the sweep
+    #[clap(long)]
+    experiment_id: Option<String>,
+    /// arguments passed to each run, for instance the target and experiment
+    /// duration
+    #[clap(last = true)]
+    run_arguments: Vec<String>,
+}
+
+/// Run lading to completion in `run_dir` with `config` and `run_arguments`,
+/// returning whether it exited successfully.
+fn run_in_dir(run_arguments: &[String], config: &serde_yaml::Value, run_dir: &Path) -> bool {
+    std::fs::create_dir_all(run_dir)
+        .unwrap_or_else(|err| panic!("Could not create {}: {}", run_dir.display(), err));
+    let config_path = run_dir.join("lading.yaml");
+    std::fs::write(&config_path, serde_yaml::to_string(config).unwrap())
+        .unwrap_or_else(|err| panic!("Could not write {}: {}", config_path.display(), err));
+    let log = File::create(run_dir.join("lading.log")).unwrap();
+    let status = Command::new(std::env::current_exe().unwrap())
+        .arg("--config-path")
+        .arg(&config_path)
+        .arg("--capture-path")
+        .arg(run_dir.join("capture.log"))
+        .args(run_arguments)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone().unwrap())
+        .stderr(log)
+        .status();
+    match status {
+        Ok(status) => status.success(),
+        Err(err) => {
+            error!("could not start run in {}: {}", run_dir.display(), err);
+            false
+        }
+    }
+}
+
+/// Report the run `name` complete in `run_dir`, summarizing its capture.
+fn finish_run(name: String, point: sweep::Point, succeeded: bool, run_dir: &Path) -> sweep::Run {
+    if !succeeded {
+        warn!(
+            "{} failed, see {}",
+            name,
+            run_dir.join("lading.log").display()
+        );
+    }
+    let summary = File::open(run_dir.join("capture.log"))
+        .and_then(|capture| sweep::summarize(BufReader::new(capture)))
+        .unwrap_or_else(|err| {
+            warn!("{}: could not summarize capture: {}", name, err);
+            Default::default()
+        });
+    sweep::Run {
+        name,
+        point,
+        succeeded,
+        summary,
+    }
+}
+
+/// Run `lading sweep`, returning whether every run succeeded.
+fn run_sweep(opts: &SweepOpts) -> bool {
+    let contents = std::fs::read_to_string(&opts.config_path).unwrap_or_else(|_| {
+        panic!(
+            "Could not open configuration file at: {}",
+            &opts.config_path
+        )
+    });
+    let base: serde_yaml::Value = serde_yaml::from_str(&contents).unwrap();
+    let contents = std::fs::read_to_string(&opts.sweep_path).unwrap_or_else(|_| {
+        panic!(
+            "Could not open sweep file at: {}",
+            opts.sweep_path.display()
+        )
+    });
+    let sweep_config: sweep::Config = serde_yaml::from_str(&contents).unwrap();
+
+    // Every point is checked before any is run, a malformed point should not
+    // be discovered hours into a sweep.
+    let mut configs = Vec::new();
+    for (idx, point) in sweep::points(&sweep_config.matrix).into_iter().enumerate() {
+        let name = format!("run-{:03}", idx);
+        let mut config = base.clone();
+        if let Err(err) = sweep::apply(&mut config, &point, opts.experiment_id.as_deref(), &name) {
+            error!("{}: could not apply overrides: {:?}", name, err);
+            return false;
+        }
+        if let Err(err) = serde_yaml::from_value::<Config>(config.clone()) {
+            error!("{}: configuration is not well-formed: {}", name, err);
+            return false;
+        }
+        configs.push((name, point, config));
+    }
+
+    let total = configs.len();
+    let mut runs = Vec::with_capacity(total);
+    for (idx, (name, point, config)) in configs.into_iter().enumerate() {
+        if idx > 0 {
+            info!("cooling down for {} seconds", opts.cooldown_seconds);
+            std::thread::sleep(Duration::from_secs(opts.cooldown_seconds));
+        }
+        info!("starting {} ({} of {}): {:?}", name, idx + 1, total, point);
+        let run_dir = opts.output_dir.join(&name);
+        let succeeded = run_in_dir(&opts.run_arguments, &config, &run_dir);
+        runs.push(finish_run(name, point, succeeded, &run_dir));
+    }
+
+    let summary_path = opts.output_dir.join("summary.csv");
+    let summary = File::create(&summary_path)
+        .unwrap_or_else(|err| panic!("Could not create {}: {}", summary_path.display(), err));
+    if let Err(err) = sweep::write_summary(&runs, BufWriter::new(summary)) {
+        error!("could not write summary: {}", err);
+        return false;
+    }
+    info!(
+        "sweep complete, summary written to {}",
+        summary_path.display()
+    );
+    runs.iter().all(|run| run.succeeded)
+}
+
+#[derive(Args)]
+/// Run an experiment against two candidate targets, one after the other, and
+/// compare them
+struct AbOpts {
+    /// path on disk to the configuration file
+    #[clap(long, default_value_t = default_config_path())]
+    config_path: String,
+    /// directory to write each candidate's configuration, capture and log to,
+    /// and the comparison
+    #[clap(long)]
+    output_dir: PathBuf,
+    /// the time in seconds to wait between candidates, letting the host
+    /// settle
+    #[clap(long, default_value_t = 60)]
+    cooldown_seconds: u64,
+    /// an identifier shared by both candidates
+    #[clap(long)]
+    experiment_id: Option<String>,
+    /// the target command of candidate a, the path of the target executable
+    /// followed by its arguments, one per occurrence of this option so that
+    /// none need be split or quoted
+    #[clap(
+        long,
+        required = true,
+        multiple_occurrences = true,
+        allow_hyphen_values = true
+    )]
+    a_target: Vec<String>,
+    /// the target command of candidate b, given as that of candidate a is. By
+    /// default that of candidate a
+    #[clap(long, multiple_occurrences = true, allow_hyphen_values = true)]
+    b_target: Vec<String>,
+    /// additional environment variables to apply to the target of candidate
+    /// a, format KEY=VAL,KEY2=VAL
+    #[clap(long)]
+    a_target_environment_variables: Option<CliKeyValues>,
+    /// additional environment variables to apply to the target of candidate
+    /// b, format KEY=VAL,KEY2=VAL
+    #[clap(long)]
+    b_target_environment_variables: Option<CliKeyValues>,
+    /// arguments passed to both candidates' runs, for instance the experiment
+    /// duration
+    #[clap(last = true)]
+    run_arguments: Vec<String>,
+}
+
+/// The arguments of a candidate's run: `run_arguments`, the directory block
+/// caches are handed off through, then the candidate's target environment, if
+/// any, and its target command.
+fn candidate_arguments(
+    run_arguments: &[String],
+    block_cache_dir: &Path,
+    target: &[String],
+    environment: Option<&CliKeyValues>,
+) -> Vec<String> {
+    let mut arguments = run_arguments.to_vec();
+    arguments.push("--block-cache-dir".to_string());
+    arguments.push(block_cache_dir.display().to_string());
+    if let Some(environment) = environment {
+        let mut pairs: Vec<String> = environment
+            .inner
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        pairs.sort_unstable();
+        arguments.push("--target-environment-variables".to_string());
+        arguments.push(pairs.join(","));
+    }
+    // The target's arguments may look like lading's own options.
+    arguments.push("--".to_string());
+    arguments.extend_from_slice(target);
+    arguments
+}
+
+/// Run `lading ab`, returning whether both candidates succeeded.
+fn run_ab(opts: &AbOpts) -> bool {
+    let contents = std::fs::read_to_string(&opts.config_path).unwrap_or_else(|_| {
+        panic!(
+            "Could not open configuration file at: {}",
+            &opts.config_path
+        )
+    });
+    let base: serde_yaml::Value = serde_yaml::from_str(&contents).unwrap();
+    if let Err(err) = serde_yaml::from_value::<Config>(base.clone()) {
+        error!("configuration is not well-formed: {}", err);
+        return false;
+    }
+    if opts.a_target[0].is_empty() {
+        error!("the target command of candidate a is empty");
+        return false;
+    }
+    let b_target = if opts.b_target.is_empty() {
+        &opts.a_target
+    } else {
+        &opts.b_target
+    };
+    if opts.b_target.is_empty()
+        && opts.a_target_environment_variables.is_none()
+        && opts.b_target_environment_variables.is_none()
+    {
+        warn!("candidates a and b are identical");
+    }
+    // Candidate a builds the block caches, candidate b loads them, so that
+    // both are offered byte-identical load. Caches left by an earlier
+    // comparison may be of another configuration.
+    let block_cache_dir = opts.output_dir.join("block_cache");
+    if block_cache_dir.exists() {
+        if let Err(err) = std::fs::remove_dir_all(&block_cache_dir) {
+            error!(
+                "could not clear block caches in {}: {}",
+                block_cache_dir.display(),
+                err
+            );
+            return false;
+        }
+    }
+    let candidates = [
+        (
+            "a",
+            candidate_arguments(
+                &opts.run_arguments,
+                &block_cache_dir,
+                &opts.a_target,
+                opts.a_target_environment_variables.as_ref(),
+            ),
+        ),
+        (
+            "b",
+            candidate_arguments(
+                &opts.run_arguments,
+                &block_cache_dir,
+                b_target,
+                opts.b_target_environment_variables.as_ref(),
+            ),
+        ),
+    ];
+
+    let mut runs = Vec::with_capacity(candidates.len());
+    for (idx, (name, arguments)) in candidates.iter().enumerate() {
+        if idx > 0 {
+            info!("cooling down for {} seconds", opts.cooldown_seconds);
+            std::thread::sleep(Duration::from_secs(opts.cooldown_seconds));
+        }
+        let mut config = base.clone();
+        sweep::apply(&mut config, &[], opts.experiment_id.as_deref(), name)
+            .expect("overrides without paths always apply");
+        info!("starting candidate {}", name);
+        let run_dir = opts.output_dir.join(name);
+        let succeeded = run_in_dir(arguments, &config, &run_dir);
+        runs.push(finish_run(
+            (*name).to_string(),
+            Vec::new(),
+            succeeded,
+            &run_dir,
+        ));
+    }
+
+    let comparison_path = opts.output_dir.join("comparison.csv");
+    let comparison = File::create(&comparison_path)
+        .unwrap_or_else(|err| panic!("Could not create {}: {}", comparison_path.display(), err));
+    if let Err(err) = sweep::write_comparison(&runs[0], &runs[1], BufWriter::new(comparison)) {
+        error!("could not write comparison: {}", err);
+        return false;
+    }
+    for (metric, a) in &runs[0].summary {
+        if let Some(b) = runs[1].summary.get(metric) {
+            match sweep::change(*a, *b) {
+                Some(change) => info!("{}: a {} b {} ({:+.2}%)", metric, a, b, change * 100.0),
+                None => info!("{}: a {} b {}", metric, a, b),
+            }
+        }
+    }
+    info!(
+        "comparison complete, written to {}",
+        comparison_path.display()
+    );
+    runs.iter().all(|run| run.succeeded)
+}
+
+/// Check the memory lading plans to allocate up front against the configured
+/// budget, if any. Returns false, having reported the plan, if the budget is
+/// exceeded.
+fn check_memory_budget(config: &Config) -> bool {
+    let budget = match config.memory_budget {
+        Some(budget) => budget,
+        None => return true,
+    };
+    let plan = config.memory_plan();
+    let planned: u64 = plan.iter().map(|(_, bytes)| bytes).sum();
+    let planned = Byte::from_bytes(u128::from(planned));
+    if planned <= budget {
+        info!(
+            "planned memory of {} is within the budget of {}",
+            planned.get_appropriate_unit(true),
+            budget.get_appropriate_unit(true)
+        );
+        return true;
+    }
+    error!(
+        "planned memory of {} exceeds the budget of {}, refusing to start",
+        planned.get_appropriate_unit(true),
+        budget.get_appropriate_unit(true)
+    );
+    for (component, bytes) in plan.into_iter().filter(|(_, bytes)| *bytes > 0) {
+        error!(
+            "  {}: {}",
+            component,
+            Byte::from_bytes(u128::from(bytes)).get_appropriate_unit(true)
+        );
+    }
+    false
+}
+
+/// Build each generator's block caches twice, reporting whether the builds
+/// were identical and every source of entropy generators draw on. Returns
+/// whether all builds were identical.
+fn verify_determinism(config: &Config) -> bool {
+    for entropy in determinism::entropy(config) {
+        info!("entropy source of {}", entropy);
+    }
+    let verifications = match determinism::verify(config) {
+        Ok(verifications) => verifications,
+        Err(err) => {
+            error!("could not build block cache: {:?}", err);
+            return false;
+        }
+    };
+    let mut identical = true;
+    for (component, verification) in verifications {
+        if verification.identical() {
+            info!(
+                "{} built identical block caches, digest {:016x}",
+                component, verification.first
+            );
+        } else {
+            error!(
+                "{} built differing block caches, digests {:016x} and {:016x}",
+                component, verification.first, verification.second
+            );
+            identical = false;
+        }
+    }
+    identical
+}
+
+/// Validate `config` and walk the experiment schedule on a simulated clock.
+///
+/// Generators are built -- including their block caches -- and immediately
+/// dropped. No target is started, no telemetry is installed and no traffic is
+/// sent. Returns false if the configuration is not well-formed.
+async fn dry_run(schedule: Schedule, config: Config) -> bool {
+    let Schedule {
+        experiment_duration,
+        warmup_duration,
+        max_shutdown_delay,
+        drain_quiescence,
+        shutdown_quiescence,
+        cooldown,
+        hold_phases,
+    } = schedule;
+    let shutdown = PhasedShutdown::new();
+    let clock = Clock::simulated();
+    if hold_phases {
+        info!(
+            "dry run: phases are held until advanced, each assumed advanced once its duration elapses"
+        );
+    }
+
+    let generator_cfgs = match config.generator {
+        config::Generator::One(cfg) => vec![*cfg],
+        config::Generator::Many(cfgs) => cfgs,
+    };
+    let generator_meters = vec![generator::Meter::default(); generator_cfgs.len()];
+    for (idx, cfg) in generator_cfgs.into_iter().enumerate() {
+        match generator::Server::new(
+            cfg,
+            shutdown.get(Phase::Generator),
+            control::Pause::default(),
+            generator::Meter::default(),
+        ) {
+            Ok(_) => info!("dry run: generator_{} is well-formed", idx),
+            Err(err) => {
+                error!("dry run: generator_{} is not well-formed: {:?}", idx, err);
+                return false;
+            }
+        }
+    }
+    let blackhole_present = config.blackhole.is_some();
+    let blackhole_meters = match config.blackhole {
+        Some(config::Blackhole::One(_)) => vec![blackhole::Meter::default()],
+        Some(config::Blackhole::Many(ref cfgs)) => vec![blackhole::Meter::default(); cfgs.len()],
+        None => vec![],
+    };
+    if let Err(err) = pairs::Server::new(
+        &config.pairs,
+        &generator_meters,
+        &blackhole_meters,
+        shutdown.get(Phase::Blackhole),
+    ) {
+        error!("dry run: pairs are not well-formed: {:?}", err);
+        return false;
+    }
+
+    info!(
+        "dry run: t+{:?} target started, warmup begins",
+        clock.elapsed()
+    );
+    clock.sleep(warmup_duration).await;
+    info!(
+        "dry run: t+{:?} warmup completed, collecting samples",
+        clock.elapsed()
+    );
+    if let Some(quiet_period) = drain_quiescence {
+        info!(
+            "dry run: experiment ends early once all generators finish and blackholes are quiet for {:?}",
+            quiet_period
+        );
+    }
+    clock.sleep(experiment_duration).await;
+    info!(
+        "dry run: t+{:?} experiment duration exceeded, shutdown begins",
+        clock.elapsed()
+    );
+    if !cooldown.is_zero() {
+        clock.sleep(cooldown).await;
+        info!(
+            "dry run: t+{:?} cooldown completed, target still observed until now",
+            clock.elapsed()
+        );
+    }
+    if blackhole_present {
+        info!(
+            "dry run: target signaled once blackholes are quiet for {:?}",
+            shutdown_quiescence
+        );
+    }
+    // Shutdown is bounded twice by `max_shutdown_delay`, once for controlled
+    // shutdown and once for the runtime, see `main`.
+    clock.sleep(max_shutdown_delay * 2).await;
+    info!(
+        "dry run: t+{:?} shutdown complete at the latest",
+        clock.elapsed()
+    );
+    true
 }
 
 fn main() {
     tracing_subscriber::fmt::init();
 
+    let opts = Opts::parse();
+    let succeeded = match opts.tool {
+        Some(Tool::Diff(ref diff)) => Some(run_diff(diff)),
+        Some(Tool::Dashboard(ref dashboard)) => Some(run_dashboard(dashboard)),
+        Some(Tool::Export(ref export)) => Some(run_export(export)),
+        Some(Tool::Sweep(ref sweep)) => Some(run_sweep(sweep)),
+        Some(Tool::Ab(ref ab)) => Some(run_ab(ab)),
+        None => None,
+    };
+    if let Some(succeeded) = succeeded {
+        if !succeeded {
+            std::process::exit(1);
+        }
+        return;
+    }
+
     info!("Starting lading run.");
-    let (opts, config): (Opts, Config) = get_config();
-    let experiment_duration = Duration::from_secs(opts.experiment_duration_seconds.into());
-    let warmup_duration = Duration::from_secs(opts.warmup_duration_seconds.into());
+    let (opts, config): (Opts, Config) = get_config(opts);
     // The maximum shutdown delay is shared between `inner_main` and this
     // function, hence the divide by two.
     let max_shutdown_delay = Duration::from_secs(opts.max_shutdown_delay.into()) / 2;
+    let schedule = Schedule {
+        experiment_duration: Duration::from_secs(opts.experiment_duration_seconds.into()),
+        warmup_duration: Duration::from_secs(opts.warmup_duration_seconds.into()),
+        max_shutdown_delay,
+        drain_quiescence: opts
+            .drain_quiescence_seconds
+            .map(|secs| Duration::from_secs(secs.into())),
+        shutdown_quiescence: Duration::from_secs(opts.shutdown_quiescence_seconds.into()),
+        cooldown: Duration::from_secs(opts.cooldown_seconds.into()),
+        hold_phases: opts.hold_phases,
+    };
     let disable_inspector = opts.disable_inspector;
+    if opts.cleanup_only {
+        let summary = cleanup::clean(&config, opts.status_file.as_deref());
+        info!(
+            "cleanup removed {} file(s) and killed {} process(es), {} could not be cleaned up",
+            summary.removed, summary.killed, summary.failed
+        );
+        if summary.failed > 0 {
+            std::process::exit(1);
+        }
+        return;
+    }
+    if !check_memory_budget(&config) {
+        std::process::exit(1);
+    }
+    if opts.verify_determinism {
+        if !verify_determinism(&config) {
+            std::process::exit(1);
+        }
+        return;
+    }
+    // Whatever a previous run left behind is cleaned up first, so that back to
+    // back runs on one host start from the same state.
+    if !opts.dry_run {
+        let summary = cleanup::clean(&config, opts.status_file.as_deref());
+        if summary.removed + summary.killed + summary.failed > 0 {
+            info!(
+                "cleaned up after a previous run: removed {} file(s), killed {} process(es), {} could not be cleaned up",
+                summary.removed, summary.killed, summary.failed
+            );
+        }
+        cleanup::claim(&config, opts.status_file.as_deref());
+    }
+    if !opts.disable_preflight {
+        let options = preflight::Options {
+            minimum_free_bytes: u64::try_from(opts.preflight_minimum_free_bytes.get_bytes())
+                .unwrap_or(u64::MAX),
+        };
+        let problems = preflight::check(&config, options);
+        if !problems.is_empty() {
+            if opts.strict_preflight {
+                error!(
+                    "preflight found {} problem(s), refusing to start:",
+                    problems.len()
+                );
+                for problem in problems {
+                    error!("  {}", problem);
+                }
+                std::process::exit(1);
+            }
+            warn!("preflight found {} problem(s):", problems.len());
+            for problem in problems {
+                warn!("  {}", problem);
+            }
+        }
+    }
 
     let runtime = Builder::new_multi_thread()
         .enable_io()
         .enable_time()
         .build()
         .unwrap();
-    runtime.block_on(inner_main(
-        experiment_duration,
-        warmup_duration,
-        max_shutdown_delay,
+    if opts.dry_run {
+        let well_formed = runtime.block_on(dry_run(schedule, config));
+        if !well_formed {
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(ref dir) = opts.block_cache_dir {
+        if let Err(err) = generator::hand_off_block_caches(dir.clone()) {
+            error!(
+                "could not hand off block caches in {}: {}",
+                dir.display(),
+                err
+            );
+            std::process::exit(1);
+        }
+    }
+    let mut lifecycle = match opts.otlp_endpoint {
+        Some(ref endpoint) => {
+            // Spans are exported by a task on the runtime.
+            let _guard = runtime.enter();
+            trace::Lifecycle::new(trace::Config {
+                endpoint: endpoint.clone(),
+                experiment_id: config.experiment.id.clone(),
+            })
+            .unwrap_or_else(|err| {
+                error!("could not export traces to {}: {:?}", endpoint, err);
+                std::process::exit(1);
+            })
+        }
+        None => trace::Lifecycle::default(),
+    };
+    // Calibration runs before telemetry is installed so that its throttles
+    // leave no trace in the captures. The throttles calibrated are those the
+    // generators are configured with.
+    let throttle_calibration = opts.calibrate_throttle.then(|| {
+        let throttles: Vec<_> = match config.generator {
+            config::Generator::One(ref cfg) => vec![cfg.throttle()],
+            config::Generator::Many(ref cfgs) => {
+                cfgs.iter().map(generator::Config::throttle).collect()
+            }
+        };
+        let calibration = runtime.block_on(throttle::calibrate(
+            &throttles,
+            THROTTLE_CALIBRATION_DURATION,
+        ));
+        for throttle in &calibration.throttles {
+            info!(
+                "throttle calibration of {} at {} units per second: error {:.3}%",
+                throttle.throttle, calibration.rate_per_second, throttle.error_percent
+            );
+        }
+        info!(
+            "throttle calibration sleep overshoot {:?}",
+            Duration::from_secs_f64(calibration.sleep_overshoot_seconds)
+        );
+        calibration
+    });
+    let (ending, within_budget) = runtime.block_on(inner_main(
+        schedule,
         disable_inspector,
+        throttle_calibration,
+        opts.status_file.clone(),
+        Duration::from_secs(opts.status_interval_seconds.max(1)),
+        opts.control_addr,
+        &mut lifecycle,
         config,
     ));
+    lifecycle.finish();
     // The splunk_hec generator spawns long running tasks that are not plugged
     // into the shutdown mechanism we have here. This is a bug and needs to be
     // addressed. However as a workaround we explicitly shutdown the
@@ -349,5 +1787,14 @@ fn main() {
         max_shutdown_delay.as_secs(),
     );
     runtime.shutdown_timeout(max_shutdown_delay);
+    // The target and inspector, if still running, were killed as the runtime
+    // shut down.
+    cleanup::release();
+    if ending.aborted() {
+        error!("experiment aborted: {:?}", ending);
+    }
     info!("Bye. :)");
+    if ending.aborted() || !within_budget {
+        std::process::exit(1);
+    }
 }
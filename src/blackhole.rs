@@ -5,15 +5,154 @@
 //! as little as possible with them and respond as minimally as possible in
 //! order to avoid overhead.
 
+use std::{
+    io,
+    net::{SocketAddr, TcpListener, UdpSocket},
+    os::unix::io::{AsRawFd, FromRawFd},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use hyper::server::conn::AddrIncoming;
+use metrics::counter;
+use nix::{
+    errno::Errno,
+    sys::socket::{self, sockopt, AddressFamily, SockFlag, SockType, SockaddrIn, SockaddrIn6},
+};
 use serde::Deserialize;
+use tokio::time::{interval, Duration, Instant};
+use tracing::warn;
 
-use crate::signals::Shutdown;
+use crate::{address, numa, signals::Shutdown, uring};
 
 pub mod http;
+pub mod rate;
+pub mod request_log;
+pub mod sample;
+pub mod source;
 pub mod splunk_hec;
 pub mod sqs;
 pub mod tcp;
 pub mod udp;
+#[cfg(target_os = "linux")]
+pub mod vsock;
+
+/// The backlog of listening sockets, see listen(2).
+const LISTEN_BACKLOG: usize = 1024;
+
+/// Total bytes received by all blackholes in this process. Used to detect
+/// when the target has stopped pushing load into lading.
+static BYTES_RECEIVED: AtomicU64 = AtomicU64::new(0);
+
+/// Return the total bytes received by all blackholes in this process.
+#[must_use]
+pub fn total_bytes_received() -> u64 {
+    BYTES_RECEIVED.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Default)]
+/// Counts the bytes a single blackhole receives from the target. Clones share
+/// their count, so a meter outlives restarts of its blackhole. See
+/// [`crate::blackhole::rate`].
+pub struct Meter {
+    received: Arc<AtomicU64>,
+}
+
+impl Meter {
+    /// Record that the blackhole has received `bytes` from the target.
+    pub(crate) fn record(&self, bytes: u64) {
+        self.received.fetch_add(bytes, Ordering::Relaxed);
+        BYTES_RECEIVED.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Return the total bytes received by the blackhole.
+    #[must_use]
+    pub fn total(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+}
+
+/// Bind a socket of type `ty` to `addr`, with `SO_REUSEPORT` if `reuse_port`
+/// is set so that other sockets may bind to the same address and the kernel
+/// balance between them. A socket bound to `[::]` is made dual-stack where the
+/// host allows, see [`crate::address`]. The socket is left blocking.
+pub(crate) fn bind<S>(addr: SocketAddr, ty: SockType, reuse_port: bool) -> Result<S, Errno>
+where
+    S: FromRawFd,
+{
+    let family = match addr {
+        SocketAddr::V4(_) => AddressFamily::Inet,
+        SocketAddr::V6(_) => AddressFamily::Inet6,
+    };
+    let fd = socket::socket(family, ty, SockFlag::empty(), None)?;
+    // SAFETY: `fd` was just created and is owned by nothing else. Ownership
+    // passes to `owned`, closing `fd` should any of what follows fail.
+    let owned = unsafe { S::from_raw_fd(fd) };
+    if ty == SockType::Stream {
+        socket::setsockopt(fd, sockopt::ReuseAddr, &true)?;
+    }
+    if reuse_port {
+        socket::setsockopt(fd, sockopt::ReusePort, &true)?;
+    }
+    if address::dual_stack(addr) {
+        // The host's default, `net.ipv6.bindv6only`, is not relied on.
+        if let Err(err) = socket::setsockopt(fd, sockopt::Ipv6V6Only, &false) {
+            warn!(
+                "{} cannot be dual-stack, accepting IPv6 only: {}",
+                addr, err
+            );
+        }
+    }
+    match addr {
+        SocketAddr::V4(addr) => socket::bind(fd, &SockaddrIn::from(addr))?,
+        SocketAddr::V6(addr) => socket::bind(fd, &SockaddrIn6::from(addr))?,
+    }
+    Ok(owned)
+}
+
+/// Bind a listening TCP socket to `addr`, as [`bind`] does.
+pub(crate) fn listen(addr: SocketAddr, reuse_port: bool) -> Result<TcpListener, Errno> {
+    let listener: TcpListener = bind(addr, SockType::Stream, reuse_port)?;
+    socket::listen(listener.as_raw_fd(), LISTEN_BACKLOG)?;
+    Ok(listener)
+}
+
+/// Bind an HTTP server's listening socket to `addr`, as [`bind`] does.
+pub(crate) fn incoming(addr: SocketAddr) -> Result<AddrIncoming, io::Error> {
+    let listener = listen(addr, false)?;
+    listener.set_nonblocking(true)?;
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    AddrIncoming::from_listener(listener).map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+}
+
+/// Count a connection accepted from `addr`, labelled by its address family.
+pub(crate) fn record_connection(addr: SocketAddr) {
+    let labels = vec![address::label(addr.ip())];
+    counter!("connection_accepted", 1, &labels);
+}
+
+/// Wait until no blackhole has received bytes for `quiet_period`.
+///
+/// The received byte total is checked once a second, so quiescence is
+/// detected with second granularity.
+pub async fn wait_for_quiescence(quiet_period: Duration) {
+    let mut check_pulse = interval(Duration::from_secs(1));
+    let mut last_total = total_bytes_received();
+    let mut quiet_since = Instant::now();
+
+    loop {
+        check_pulse.tick().await;
+        let total = total_bytes_received();
+        if total != last_total {
+            last_total = total;
+            quiet_since = Instant::now();
+        } else if quiet_since.elapsed() >= quiet_period {
+            return;
+        }
+    }
+}
 
 #[derive(Debug)]
 /// Errors produced by [`Server`].
@@ -28,9 +167,14 @@ pub enum Error {
     Udp(udp::Error),
     /// See [`crate::blackhole::sqs::Error`] for details.
     Sqs(sqs::Error),
+    /// See [`crate::blackhole::vsock::Error`] for details.
+    #[cfg(target_os = "linux")]
+    Vsock(vsock::Error),
+    /// See [`crate::numa::Error`] for details.
+    Numa(numa::Error),
 }
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+#[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 /// Configuration for [`Server`]
 pub enum Config {
@@ -44,6 +188,164 @@ pub enum Config {
     Udp(udp::Config),
     /// See [`crate::blackhole::sqs::Config`] for details.
     Sqs(sqs::Config),
+    /// See [`crate::blackhole::vsock::Config`] for details.
+    #[cfg(target_os = "linux")]
+    Vsock(vsock::Config),
+}
+
+impl Config {
+    /// The range the blackhole's received byte rate is expected to stay in, if
+    /// configured.
+    #[must_use]
+    pub fn expected_rate(&self) -> Option<&rate::Config> {
+        match self {
+            Config::Tcp(conf) => conf.expected_rate.as_ref(),
+            Config::Http(conf) => conf.expected_rate.as_ref(),
+            Config::SplunkHec(conf) => conf.expected_rate.as_ref(),
+            Config::Udp(conf) => conf.expected_rate.as_ref(),
+            Config::Sqs(conf) => conf.expected_rate.as_ref(),
+            #[cfg(target_os = "linux")]
+            Config::Vsock(conf) => conf.expected_rate.as_ref(),
+        }
+    }
+
+    /// The memory the blackhole allocates up front, in bytes. Buffers allocated
+    /// per connection are not counted.
+    #[must_use]
+    pub fn planned_memory_bytes(&self) -> u64 {
+        match self {
+            Config::Udp(conf) => (conf.sockets.get() * udp::RECEIVE_BUFFER_BYTES) as u64,
+            Config::Tcp(_) | Config::Http(_) | Config::SplunkHec(_) | Config::Sqs(_) => 0,
+            #[cfg(target_os = "linux")]
+            Config::Vsock(_) => 0,
+        }
+    }
+
+    /// The address the blackhole binds to, if it binds an IP address. The
+    /// vsock blackhole binds a vsock port instead.
+    #[must_use]
+    pub fn binding_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Config::Tcp(conf) => Some(conf.binding_addr),
+            Config::Http(conf) => Some(conf.binding_addr),
+            Config::SplunkHec(conf) => Some(conf.binding_addr),
+            Config::Udp(conf) => Some(conf.binding_addr),
+            Config::Sqs(conf) => Some(conf.binding_addr),
+            #[cfg(target_os = "linux")]
+            Config::Vsock(_) => None,
+        }
+    }
+
+    fn binding_addr_mut(&mut self) -> Option<&mut SocketAddr> {
+        match self {
+            Config::Tcp(conf) => Some(&mut conf.binding_addr),
+            Config::Http(conf) => Some(&mut conf.binding_addr),
+            Config::SplunkHec(conf) => Some(&mut conf.binding_addr),
+            Config::Udp(conf) => Some(&mut conf.binding_addr),
+            Config::Sqs(conf) => Some(&mut conf.binding_addr),
+            #[cfg(target_os = "linux")]
+            Config::Vsock(_) => None,
+        }
+    }
+
+    /// Resolve a binding address with port 0 to a port the kernel finds free,
+    /// returning the address the blackhole will bind to, if it binds an IP
+    /// address.
+    ///
+    /// The port is resolved once, before the blackhole is started, so that a
+    /// restarted blackhole binds the same port and the target can be told of
+    /// it. The port is free when resolved; in the moment before the blackhole
+    /// binds it another process could take it, in which case the blackhole
+    /// fails.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if no port can be bound.
+    pub fn resolve_binding_addr(&mut self) -> Result<Option<SocketAddr>, io::Error> {
+        let addr = match self.binding_addr() {
+            Some(addr) if addr.port() == 0 => addr,
+            bound => return Ok(bound),
+        };
+        let resolved = match self {
+            Config::Udp(_) => bind::<UdpSocket>(addr, SockType::Datagram, false)?.local_addr()?,
+            Config::Tcp(_) | Config::Http(_) | Config::SplunkHec(_) | Config::Sqs(_) => {
+                listen(addr, false)?.local_addr()?
+            }
+            #[cfg(target_os = "linux")]
+            Config::Vsock(_) => return Ok(None),
+        };
+        if let Some(binding_addr) = self.binding_addr_mut() {
+            *binding_addr = resolved;
+        }
+        Ok(Some(resolved))
+    }
+
+    /// The sockets the blackhole binds, not counting connections accepted on
+    /// them.
+    #[must_use]
+    pub fn planned_sockets(&self) -> u64 {
+        match self {
+            Config::Tcp(conf) => conf.acceptors.get() as u64,
+            Config::Udp(conf) => conf.sockets.get() as u64,
+            Config::Http(_) | Config::SplunkHec(_) | Config::Sqs(_) => 1,
+            #[cfg(target_os = "linux")]
+            Config::Vsock(_) => 1,
+        }
+    }
+
+    /// The sample configuration of the blackhole, if configured.
+    #[must_use]
+    pub fn sample(&self) -> Option<&sample::Config> {
+        match self {
+            Config::Tcp(conf) => conf.sample.as_ref(),
+            Config::Http(conf) => conf.sample.as_ref(),
+            Config::SplunkHec(conf) => conf.sample.as_ref(),
+            Config::Udp(conf) => conf.sample.as_ref(),
+            Config::Sqs(conf) => conf.sample.as_ref(),
+            #[cfg(target_os = "linux")]
+            Config::Vsock(_) => None,
+        }
+    }
+
+    /// The request log configuration of the blackhole, if configured. Only
+    /// blackholes speaking HTTP log requests.
+    #[must_use]
+    pub fn request_log(&self) -> Option<&sample::Config> {
+        match self {
+            Config::Http(conf) => conf.request_log.as_ref(),
+            Config::SplunkHec(conf) => conf.request_log.as_ref(),
+            Config::Sqs(conf) => conf.request_log.as_ref(),
+            Config::Tcp(_) | Config::Udp(_) => None,
+            #[cfg(target_os = "linux")]
+            Config::Vsock(_) => None,
+        }
+    }
+
+    /// The I/O backend of the blackhole.
+    #[must_use]
+    pub fn backend(&self) -> uring::Backend {
+        match self {
+            Config::Tcp(conf) => conf.backend,
+            Config::Udp(conf) => conf.backend,
+            Config::Http(_) | Config::SplunkHec(_) | Config::Sqs(_) => uring::Backend::Epoll,
+            #[cfg(target_os = "linux")]
+            Config::Vsock(_) => uring::Backend::Epoll,
+        }
+    }
+
+    /// The NUMA placement of the blackhole, if configured.
+    #[must_use]
+    pub fn numa(&self) -> Option<numa::Placement> {
+        match self {
+            Config::Tcp(conf) => conf.numa,
+            Config::Http(conf) => conf.numa,
+            Config::SplunkHec(conf) => conf.numa,
+            Config::Udp(conf) => conf.numa,
+            Config::Sqs(conf) => conf.numa,
+            #[cfg(target_os = "linux")]
+            Config::Vsock(conf) => conf.numa,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -62,26 +364,34 @@ pub enum Server {
     Udp(udp::Udp),
     /// See [`crate::blackhole::sqs::Sqs`] for details.
     Sqs(sqs::Sqs),
+    /// See [`crate::blackhole::vsock::Vsock`] for details.
+    #[cfg(target_os = "linux")]
+    Vsock(vsock::Vsock),
 }
 
 impl Server {
     /// Create a new [`Server`]
     ///
     /// This function creates a new [`Server`] instance, deferring to the
-    /// underlying sub-server.
+    /// underlying sub-server. Received bytes are counted by `meter`, and
+    /// attributed to their sources as the blackhole `name`.
     ///
     /// # Errors
     ///
     /// Function will return an error if the underlying sub-server creation
     /// signals error.
     #[must_use]
-    pub fn new(config: Config, shutdown: Shutdown) -> Self {
+    pub fn new(config: Config, meter: Meter, name: &str, shutdown: Shutdown) -> Self {
         match config {
-            Config::Tcp(conf) => Self::Tcp(tcp::Tcp::new(&conf, shutdown)),
-            Config::Http(conf) => Self::Http(http::Http::new(&conf, shutdown)),
-            Config::Udp(conf) => Self::Udp(udp::Udp::new(&conf, shutdown)),
-            Config::Sqs(conf) => Self::Sqs(sqs::Sqs::new(&conf, shutdown)),
-            Config::SplunkHec(conf) => Self::SplunkHec(splunk_hec::SplunkHec::new(&conf, shutdown)),
+            Config::Tcp(conf) => Self::Tcp(tcp::Tcp::new(&conf, meter, name, shutdown)),
+            Config::Http(conf) => Self::Http(http::Http::new(&conf, meter, name, shutdown)),
+            Config::Udp(conf) => Self::Udp(udp::Udp::new(&conf, meter, name, shutdown)),
+            Config::Sqs(conf) => Self::Sqs(sqs::Sqs::new(&conf, meter, name, shutdown)),
+            #[cfg(target_os = "linux")]
+            Config::Vsock(conf) => Self::Vsock(vsock::Vsock::new(&conf, meter, shutdown)),
+            Config::SplunkHec(conf) => {
+                Self::SplunkHec(splunk_hec::SplunkHec::new(&conf, meter, name, shutdown))
+            }
         }
     }
 
@@ -100,6 +410,8 @@ impl Server {
             Server::Http(inner) => inner.run().await.map_err(Error::Http),
             Server::Udp(inner) => inner.run().await.map_err(Error::Udp),
             Server::Sqs(inner) => inner.run().await.map_err(Error::Sqs),
+            #[cfg(target_os = "linux")]
+            Server::Vsock(inner) => inner.run().await.map_err(Error::Vsock),
             Server::SplunkHec(inner) => inner.run().await.map_err(Error::SplunkHec),
         }
     }
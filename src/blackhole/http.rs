@@ -1,19 +1,45 @@
 //! The HTTP protocol speaking blackhole.
 
-use std::{net::SocketAddr, str::FromStr, time::Duration};
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    num::NonZeroU16,
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
 
+use futures::{future, stream};
 use hyper::{
     body, header,
-    server::conn::{AddrIncoming, AddrStream},
+    server::{
+        accept::{self, Accept},
+        conn::AddrStream,
+    },
     service::{make_service_fn, service_fn},
     Body, Request, Response, Server, StatusCode,
 };
 use once_cell::unsync::OnceCell;
 use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::{sleep, Instant, Sleep},
+};
 use tower::ServiceBuilder;
 use tracing::{debug, error, info};
 
-use crate::signals::Shutdown;
+use super::{
+    rate,
+    request_log::{self, RequestLog},
+    sample::{self, Sampler},
+    source::{self, Sources},
+    Meter,
+};
+use crate::{numa, signals::Shutdown};
 
 #[allow(clippy::declare_interior_mutable_const)]
 const RESPONSE: OnceCell<Vec<u8>> = OnceCell::new();
@@ -27,6 +53,8 @@ fn default_concurrent_requests_max() -> usize {
 pub enum Error {
     /// Wrapper for [`hyper::Error`].
     Hyper(hyper::Error),
+    /// Wrapper for [`std::io::Error`].
+    Io(std::io::Error),
 }
 
 #[derive(Debug, Copy, Clone, Deserialize)]
@@ -53,7 +81,79 @@ fn default_body_variant() -> BodyVariant {
     BodyVariant::AwsKinesis
 }
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+fn default_status() -> StatusCode {
+    StatusCode::OK
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// How the blackhole responds to requests whose path begins with `path`, see
+/// [`Config::routes`]
+pub struct Route {
+    /// the path prefix requests are matched by
+    pub path: String,
+    /// the status to respond to valid requests with, default 200
+    #[serde(default = "default_status", with = "http_serde::status_code")]
+    pub status: StatusCode,
+    /// milliseconds to wait before responding, default none
+    #[serde(default)]
+    pub latency_milliseconds: u64,
+    /// the body variant to respond with, default the blackhole's
+    pub body_variant: Option<BodyVariant>,
+    /// respond 415 to requests without this content type
+    pub content_type: Option<String>,
+    /// respond 413 to requests whose decoded body is larger than this
+    pub maximum_body_bytes: Option<byte_unit::Byte>,
+    /// respond 400 to requests whose decoded body is not JSON
+    #[serde(default)]
+    pub json: bool,
+}
+
+impl Route {
+    /// The route taken by requests no configured route matches.
+    fn fallback() -> Self {
+        Self {
+            path: String::new(),
+            status: default_status(),
+            latency_milliseconds: 0,
+            body_variant: None,
+            content_type: None,
+            maximum_body_bytes: None,
+            json: false,
+        }
+    }
+
+    /// Check the request of `headers` and decoded `body` against this route's
+    /// validation settings, returning the status to reject it with if invalid.
+    fn validate(&self, headers: &header::HeaderMap, body: &[u8]) -> Result<(), StatusCode> {
+        if let Some(ref content_type) = self.content_type {
+            let matches = headers
+                .get(header::CONTENT_TYPE)
+                .map_or(false, |value| value.as_bytes() == content_type.as_bytes());
+            if !matches {
+                return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            }
+        }
+        if let Some(maximum) = self.maximum_body_bytes {
+            if body.len() as u128 > maximum.get_bytes() {
+                return Err(StatusCode::PAYLOAD_TOO_LARGE);
+            }
+        }
+        if self.json && serde_json::from_slice::<serde::de::IgnoredAny>(body).is_err() {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        Ok(())
+    }
+}
+
+/// The first of `routes` matching `path`. The last route must be the fallback.
+fn route<'a>(routes: &'a [Route], path: &str) -> &'a Route {
+    routes
+        .iter()
+        .find(|route| path.starts_with(&route.path))
+        .expect("the fallback route matches every path")
+}
+
+#[derive(Debug, Deserialize, Clone)]
 /// Configuration for [`Http`]
 pub struct Config {
     /// number of concurrent HTTP connections to allow
@@ -61,9 +161,37 @@ pub struct Config {
     pub concurrent_requests_max: usize,
     /// address -- IP plus port -- to bind to
     pub binding_addr: SocketAddr,
+    /// persist a sample of received request bodies to disk, see
+    /// [`crate::blackhole::sample`]
+    pub sample: Option<sample::Config>,
+    /// log the method, path, headers and body length of a sample of received
+    /// requests to disk, see [`crate::blackhole::request_log`]
+    pub request_log: Option<sample::Config>,
+    /// alarm when the rate of request body bytes received, after any
+    /// `content-encoding` is decoded and whichever route answers, leaves an
+    /// expected range, see [`crate::blackhole::rate`]
+    pub expected_rate: Option<rate::Config>,
+    /// count received bytes by the address they came from, see
+    /// [`crate::blackhole::source`]
+    pub sources: Option<source::Config>,
     /// the body variant to respond with, default nothing
     #[serde(default = "default_body_variant")]
     pub body_variant: BodyVariant,
+    /// rules for requests by path, the first whose path prefixes the request's
+    /// applying. Requests no rule matches are answered 200 with `body_variant`.
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    /// maximum number of connections open at once, further connections are
+    /// not accepted until one closes. Unlimited if unset, may not be zero.
+    pub maximum_connections: Option<NonZeroU16>,
+    /// seconds a connection may sit idle between requests before it is
+    /// closed. If unset idle connections are kept open.
+    pub keep_alive_timeout_seconds: Option<u64>,
+    /// close each connection once it has served this many requests. Unlimited
+    /// if unset, 1 disables keep-alive.
+    pub maximum_requests_per_connection: Option<u64>,
+    /// place this blackhole on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
 }
 
 #[derive(Serialize)]
@@ -83,30 +211,62 @@ struct KinesisPutRecordBatchResponse {
 }
 
 #[allow(clippy::borrow_interior_mutable_const)]
+#[allow(clippy::too_many_arguments)]
 async fn srv(
     body_variant: BodyVariant,
+    routes: Arc<Vec<Route>>,
+    sampler: Option<Arc<Sampler>>,
+    request_log: Option<Arc<RequestLog>>,
+    source: Option<Vec<(String, String)>>,
+    meter: Meter,
+    close: bool,
     req: Request<Body>,
 ) -> Result<Response<Body>, hyper::Error> {
     metrics::counter!("requests_received", 1);
 
     let (parts, body) = req.into_parts();
+    let route = route(&routes, parts.uri.path());
 
     let bytes = body::to_bytes(body).await?;
+    if let Some(request_log) = &request_log {
+        request_log.log(&parts, bytes.len());
+    }
 
     match crate::codec::decode(parts.headers.get(hyper::header::CONTENT_ENCODING), bytes) {
         Err(response) => Ok(response),
         Ok(body) => {
             metrics::counter!("bytes_received", body.len() as u64);
+            meter.record(body.len() as u64);
+            if let Some(sampler) = &sampler {
+                sampler.sample(&body);
+            }
+            if let Some(ref source) = source {
+                source::record(source, body.len() as u64);
+            }
 
             let mut okay = Response::default();
-            *okay.status_mut() = StatusCode::OK;
+            if close {
+                okay.headers_mut().insert(
+                    header::CONNECTION,
+                    header::HeaderValue::from_static("close"),
+                );
+            }
+            if route.latency_milliseconds > 0 {
+                sleep(Duration::from_millis(route.latency_milliseconds)).await;
+            }
+            if let Err(status) = route.validate(&parts.headers, &body) {
+                metrics::counter!("requests_rejected", 1, "route" => route.path.clone());
+                *okay.status_mut() = status;
+                return Ok(okay);
+            }
+
+            *okay.status_mut() = route.status;
             okay.headers_mut().insert(
                 header::CONTENT_TYPE,
                 header::HeaderValue::from_static("application/json"),
             );
-
             let body_bytes = RESPONSE
-                .get_or_init(|| match body_variant {
+                .get_or_init(|| match route.body_variant.unwrap_or(body_variant) {
                     BodyVariant::AwsKinesis => {
                         let response = KinesisPutRecordBatchResponse {
                             encrypted: None,
@@ -128,23 +288,122 @@ async fn srv(
     }
 }
 
+/// A connection accepted by the blackhole. Reads end, closing the connection,
+/// once it has been idle for its keep-alive timeout. Holds its permit of the
+/// blackhole's connection limit, if any, until closed.
+struct Connection {
+    stream: AddrStream,
+    keep_alive: Option<(Duration, Pin<Box<Sleep>>)>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Connection {
+    fn new(
+        stream: AddrStream,
+        keep_alive_timeout: Option<Duration>,
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> Self {
+        Self {
+            stream,
+            keep_alive: keep_alive_timeout.map(|timeout| (timeout, Box::pin(sleep(timeout)))),
+            _permit: permit,
+        }
+    }
+
+    /// Restart the connection's keep-alive timeout.
+    fn touch(&mut self) {
+        if let Some((timeout, ref mut idle)) = self.keep_alive {
+            idle.as_mut().reset(Instant::now() + timeout);
+        }
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        match Pin::new(&mut this.stream).poll_read(cx, buf) {
+            Poll::Ready(res) => {
+                this.touch();
+                Poll::Ready(res)
+            }
+            Poll::Pending => match this.keep_alive {
+                // Reading nothing signals the end of the stream.
+                Some((_, ref mut idle)) if idle.as_mut().poll(cx).is_ready() => Poll::Ready(Ok(())),
+                _ => Poll::Pending,
+            },
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let res = Pin::new(&mut this.stream).poll_write(cx, buf);
+        if res.is_ready() {
+            this.touch();
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
 #[derive(Debug)]
 /// The HTTP blackhole.
 pub struct Http {
     httpd_addr: SocketAddr,
     body_variant: BodyVariant,
+    routes: Arc<Vec<Route>>,
     concurrency_limit: usize,
+    maximum_connections: Option<NonZeroU16>,
+    keep_alive_timeout: Option<Duration>,
+    maximum_requests_per_connection: Option<u64>,
+    name: String,
+    sample: Option<sample::Config>,
+    request_log: Option<sample::Config>,
+    sources: Option<Arc<Sources>>,
+    meter: Meter,
     shutdown: Shutdown,
 }
 
 impl Http {
-    /// Create a new [`Http`] server instance
+    /// Create a new [`Http`] server instance, the blackhole `name`
     #[must_use]
-    pub fn new(config: &Config, shutdown: Shutdown) -> Self {
+    pub fn new(config: &Config, meter: Meter, name: &str, shutdown: Shutdown) -> Self {
         Self {
             httpd_addr: config.binding_addr,
             body_variant: config.body_variant,
+            routes: Arc::new(
+                config
+                    .routes
+                    .iter()
+                    .cloned()
+                    .chain(std::iter::once(Route::fallback()))
+                    .collect(),
+            ),
             concurrency_limit: config.concurrent_requests_max,
+            maximum_connections: config.maximum_connections,
+            keep_alive_timeout: config.keep_alive_timeout_seconds.map(Duration::from_secs),
+            maximum_requests_per_connection: config.maximum_requests_per_connection,
+            name: name.to_string(),
+            sample: config.sample.clone(),
+            request_log: config.request_log.clone(),
+            sources: source::open(config.sources.as_ref(), name),
+            meter,
             shutdown,
         }
     }
@@ -156,17 +415,56 @@ impl Http {
     ///
     /// # Errors
     ///
-    /// Function will return an error if receiving a packet fails.
+    /// Function will return an error if receiving a packet fails or the sample
+    /// or request log file cannot be opened.
     ///
     /// # Panics
     ///
     /// None known.
     pub async fn run(mut self) -> Result<(), Error> {
-        let service = make_service_fn(|_: &AddrStream| async move {
-            Ok::<_, hyper::Error>(service_fn(move |request| {
-                debug!("REQUEST: {:?}", request);
-                srv(self.body_variant, request)
-            }))
+        let sampler = sample::open(self.sample.as_ref(), &self.name, "http", self.httpd_addr)
+            .await
+            .map_err(Error::Io)?;
+        let request_log = request_log::open(
+            self.request_log.as_ref(),
+            &self.name,
+            "http",
+            self.httpd_addr,
+        )
+        .await
+        .map_err(Error::Io)?;
+        let body_variant = self.body_variant;
+        let routes = Arc::clone(&self.routes);
+        let sources = self.sources.clone();
+        let meter = self.meter.clone();
+        let maximum_requests = self.maximum_requests_per_connection;
+        let service = make_service_fn(move |conn: &Connection| {
+            super::record_connection(conn.stream.remote_addr());
+            let sampler = sampler.clone();
+            let request_log = request_log.clone();
+            let source = sources
+                .as_ref()
+                .map(|sources| sources.labels(conn.stream.remote_addr().ip()));
+            let routes = Arc::clone(&routes);
+            let meter = meter.clone();
+            let mut served = 0;
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |request| {
+                    debug!("REQUEST: {:?}", request);
+                    served += 1;
+                    let close = maximum_requests.map_or(false, |maximum| served >= maximum);
+                    srv(
+                        body_variant,
+                        Arc::clone(&routes),
+                        sampler.clone(),
+                        request_log.clone(),
+                        source.clone(),
+                        meter.clone(),
+                        close,
+                        request,
+                    )
+                }))
+            }
         });
         let svc = ServiceBuilder::new()
             .load_shed()
@@ -174,14 +472,35 @@ impl Http {
             .timeout(Duration::from_secs(1))
             .service(service);
 
-        let addr = AddrIncoming::bind(&self.httpd_addr)
+        let addr = super::incoming(self.httpd_addr)
             .map(|mut addr| {
                 addr.set_keepalive(Some(Duration::from_secs(60)));
                 addr
             })
-            .map_err(Error::Hyper)?;
+            .map_err(Error::Io)?;
+        // Connections beyond the limit wait to be accepted until a permit is
+        // released, by a connection closing.
+        let permits = self
+            .maximum_connections
+            .map(|maximum| Arc::new(Semaphore::new(usize::from(maximum.get()))));
+        let keep_alive_timeout = self.keep_alive_timeout;
+        let incoming = stream::unfold((addr, permits), move |(mut addr, permits)| async move {
+            let permit = match permits {
+                Some(ref permits) => Some(
+                    Arc::clone(permits)
+                        .acquire_owned()
+                        .await
+                        .expect("semaphore is never closed"),
+                ),
+                None => None,
+            };
+            let stream = future::poll_fn(|cx| Pin::new(&mut addr).poll_accept(cx)).await?;
+            let connection =
+                stream.map(|stream| Connection::new(stream, keep_alive_timeout, permit));
+            Some((connection, (addr, permits)))
+        });
 
-        let server = Server::builder(addr).serve(svc);
+        let server = Server::builder(accept::from_stream(incoming)).serve(svc);
         loop {
             tokio::select! {
                 res = server => {
@@ -196,3 +515,45 @@ impl Http {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::{route, Config, Route};
+
+    // A request takes the first route whose path prefixes its own, the
+    // fallback if there is none.
+    proptest! {
+        #[test]
+        fn first_matching_route(paths in proptest::collection::vec("(/[ab]{0,2}){0,3}", 0..8), path in "(/[ab]{0,2}){0,4}") {
+            let routes: Vec<Route> = paths
+                .iter()
+                .map(|prefix| Route { path: prefix.clone(), ..Route::fallback() })
+                .chain(std::iter::once(Route::fallback()))
+                .collect();
+            let taken = route(&routes, &path);
+            prop_assert!(path.starts_with(&taken.path));
+            let first = paths.iter().position(|prefix| path.starts_with(prefix.as_str()));
+            match first {
+                Some(idx) => prop_assert_eq!(&taken.path, &paths[idx]),
+                None => prop_assert_eq!(&taken.path, ""),
+            }
+        }
+    }
+    // A connection limit is taken as configured, save zero, which would
+    // accept no connections at all.
+    proptest! {
+        #[test]
+        fn maximum_connections_non_zero(maximum in 0..=u16::MAX) {
+            let config = serde_yaml::from_str::<Config>(&format!(
+                "binding_addr: 127.0.0.1:8080\nmaximum_connections: {}\n",
+                maximum
+            ));
+            match config {
+                Ok(config) => prop_assert_eq!(config.maximum_connections.map(|max| max.get()), Some(maximum)),
+                Err(_) => prop_assert_eq!(maximum, 0),
+            }
+        }
+    }
+}
@@ -0,0 +1,230 @@
+//! Alarm on a blackhole's received byte rate
+//!
+//! Often the question an experiment answers is simple: did the target keep up?
+//! A blackhole may be configured with the range of byte rates it expects to
+//! receive at. Once the experiment is past warmup, [`Alarm`] measures the rate
+//! the blackhole receives at once a second. Should the rate leave the range
+//! for long enough an `slo_violation` is counted, labeled by blackhole and the
+//! bound violated, and a warning logged. If so configured the experiment is
+//! failed.
+
+use std::num::NonZeroU32;
+
+use byte_unit::Byte;
+use metrics::{counter, gauge};
+use serde::Deserialize;
+use tokio::{
+    sync::watch,
+    time::{self, Duration, Instant},
+};
+use tracing::{info, warn};
+
+use super::Meter;
+use crate::{antagonist::Stage, signals::Shutdown};
+
+fn default_sustained_seconds() -> NonZeroU32 {
+    NonZeroU32::new(10).unwrap()
+}
+
+#[derive(Debug)]
+/// Errors produced by [`Alarm`]
+pub enum Error {
+    /// The received byte rate left the expected range and the alarm is
+    /// configured to fail the experiment.
+    Violation {
+        /// The bound violated, `minimum` or `maximum`.
+        bound: &'static str,
+        /// The received byte rate at the time of violation.
+        bytes_per_second: f64,
+    },
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+/// Configuration for [`Alarm`]
+pub struct Config {
+    /// The rate below which the blackhole should not receive, if any
+    pub minimum_bytes_per_second: Option<Byte>,
+    /// The rate above which the blackhole should not receive, if any
+    pub maximum_bytes_per_second: Option<Byte>,
+    /// The time in seconds the rate must be out of range before the range is
+    /// considered violated
+    #[serde(default = "default_sustained_seconds")]
+    pub sustained_seconds: NonZeroU32,
+    /// Whether to fail the experiment when the range is violated
+    #[serde(default)]
+    pub fail: bool,
+}
+
+impl Config {
+    /// The bound `bytes_per_second` violates, if any.
+    fn violated(&self, bytes_per_second: f64) -> Option<&'static str> {
+        if let Some(minimum) = self.minimum_bytes_per_second {
+            if bytes_per_second < minimum.get_bytes() as f64 {
+                return Some("minimum");
+            }
+        }
+        if let Some(maximum) = self.maximum_bytes_per_second {
+            if bytes_per_second > maximum.get_bytes() as f64 {
+                return Some("maximum");
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug)]
+/// Tracks how long a blackhole's rate has been out of range.
+struct Judge {
+    config: Config,
+    out_of_range_for: Duration,
+    violated: bool,
+}
+
+impl Judge {
+    fn new(config: Config) -> Self {
+        Self {
+            config,
+            out_of_range_for: Duration::ZERO,
+            violated: false,
+        }
+    }
+
+    /// Judge `bytes_per_second`, measured over `elapsed`. Returns the bound
+    /// violated, once per sustained excursion out of range.
+    fn observe(&mut self, bytes_per_second: f64, elapsed: Duration) -> Option<&'static str> {
+        let bound = match self.config.violated(bytes_per_second) {
+            Some(bound) => bound,
+            None => {
+                self.out_of_range_for = Duration::ZERO;
+                self.violated = false;
+                return None;
+            }
+        };
+        self.out_of_range_for += elapsed;
+        let sustained = Duration::from_secs(self.config.sustained_seconds.get().into());
+        if !self.violated && self.out_of_range_for >= sustained {
+            self.violated = true;
+            return Some(bound);
+        }
+        None
+    }
+}
+
+#[derive(Debug)]
+/// The received byte rate alarm of a single blackhole.
+pub struct Alarm {
+    config: Config,
+    meter: Meter,
+    name: String,
+    stage: watch::Receiver<Stage>,
+    shutdown: Shutdown,
+}
+
+impl Alarm {
+    /// Create a new [`Alarm`] instance, judging the rate `meter` counts for
+    /// the blackhole `name`.
+    #[must_use]
+    pub fn new(
+        config: Config,
+        meter: Meter,
+        name: String,
+        stage: watch::Receiver<Stage>,
+        shutdown: Shutdown,
+    ) -> Self {
+        Self {
+            config,
+            meter,
+            name,
+            stage,
+            shutdown,
+        }
+    }
+
+    /// Run this [`Alarm`] to completion
+    ///
+    /// This function judges the blackhole's received byte rate during the
+    /// experiment stage until a shutdown signal is received.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the rate leaves the expected range and
+    /// the alarm is configured to fail the experiment.
+    pub async fn run(mut self) -> Result<(), Error> {
+        let labels = vec![("blackhole".to_string(), self.name.clone())];
+        let mut judge = Judge::new(self.config);
+        let mut check_delay = time::interval(Duration::from_secs(1));
+        let mut last_check = Instant::now();
+        let mut last_total = self.meter.total();
+
+        loop {
+            tokio::select! {
+                _ = check_delay.tick() => {
+                    let now = Instant::now();
+                    let elapsed = now.duration_since(last_check);
+                    let total = self.meter.total();
+                    last_check = now;
+                    let received = total - last_total;
+                    last_total = total;
+                    if elapsed.is_zero() || *self.stage.borrow() != Stage::Experiment {
+                        continue;
+                    }
+                    let bytes_per_second = received as f64 / elapsed.as_secs_f64();
+                    gauge!("received_bytes_per_second", bytes_per_second, &labels);
+                    if let Some(bound) = judge.observe(bytes_per_second, elapsed) {
+                        warn!(
+                            "blackhole {} received {:.0} bytes per second, out of its {} bound for {:?}",
+                            self.name, bytes_per_second, bound, judge.out_of_range_for
+                        );
+                        let mut violation_labels = labels.clone();
+                        violation_labels.push(("bound".to_string(), bound.to_string()));
+                        counter!("slo_violation", 1, &violation_labels);
+                        if self.config.fail {
+                            return Err(Error::Violation { bound, bytes_per_second });
+                        }
+                    }
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroU32;
+
+    use byte_unit::Byte;
+    use proptest::prelude::*;
+    use tokio::time::Duration;
+
+    use super::{Config, Judge};
+
+    // A rate below the minimum is judged a violation after exactly
+    // `sustained_seconds`, once, and the judgment resets once back in range.
+    proptest! {
+        #[test]
+        fn sustained_violation_once(sustained_seconds in 1..64_u32, extra in 0..64_u32, minimum in 1..u32::MAX) {
+            let mut judge = Judge::new(Config {
+                minimum_bytes_per_second: Some(Byte::from_bytes(u128::from(minimum))),
+                maximum_bytes_per_second: None,
+                sustained_seconds: NonZeroU32::new(sustained_seconds).unwrap(),
+                fail: false,
+            });
+            let second = Duration::from_secs(1);
+            let below = f64::from(minimum - 1);
+            for tick in 1..=(sustained_seconds + extra) {
+                let bound = judge.observe(below, second);
+                if tick == sustained_seconds {
+                    prop_assert_eq!(bound, Some("minimum"));
+                } else {
+                    prop_assert_eq!(bound, None);
+                }
+            }
+            prop_assert_eq!(judge.observe(f64::from(minimum), second), None);
+            prop_assert_eq!(judge.out_of_range_for, Duration::ZERO);
+        }
+    }
+}
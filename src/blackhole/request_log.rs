@@ -0,0 +1,161 @@
+//! Log the metadata of a sample of received requests to disk.
+//!
+//! When a target switches endpoints or stops compressing under load the
+//! payloads a blackhole receives say little of it, the requests carrying them
+//! do. A [`RequestLog`] appends the method, path, headers and body length of a
+//! deterministic fraction of received requests, up to an optional byte budget,
+//! to `<directory>/<name>-<kind>-<port>.requests`, `name` the blackhole's, as
+//! newline delimited JSON. Bodies are not logged, see
+//! [`crate::blackhole::sample`] for those. The last line may be cut short by
+//! the byte budget.
+//!
+//! Headers bearing credentials -- `Authorization`, which carries Splunk HEC
+//! tokens, cookies, API keys and AWS signatures among them -- are logged with
+//! their values redacted.
+
+use std::{io, net::SocketAddr, sync::Arc};
+
+use hyper::http::request::Parts;
+use serde::Serialize;
+
+use super::sample::{Config, Sampler};
+
+/// Create a [`RequestLog`] for the blackhole `name` of kind `kind` bound to
+/// `binding_addr`, if request logging is configured.
+///
+/// # Errors
+///
+/// Function will return an error if [`Sampler::new`] does.
+pub(crate) async fn open(
+    config: Option<&Config>,
+    name: &str,
+    kind: &str,
+    binding_addr: SocketAddr,
+) -> Result<Option<Arc<RequestLog>>, io::Error> {
+    match config {
+        Some(config) => {
+            let file_name = format!("{}-{}-{}.requests", name, kind, binding_addr.port());
+            let sampler = Sampler::new(config, &file_name).await?;
+            Ok(Some(Arc::new(RequestLog { sampler })))
+        }
+        None => Ok(None),
+    }
+}
+
+/// The headers, compared case-insensitively, whose values are redacted.
+const REDACTED_HEADERS: [&str; 8] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+    "dd-api-key",
+    "x-splunk-request-channel",
+    "x-goog-api-key",
+];
+
+/// The prefix, compared case-insensitively, of headers whose values are
+/// redacted: AWS request signatures and session tokens.
+const REDACTED_PREFIX: &str = "x-amz-";
+
+/// The value logged in place of a redacted header's.
+const REDACTED: &str = "[redacted]";
+
+/// Whether the value of the header `name` is redacted.
+fn redacted(name: &str) -> bool {
+    REDACTED_HEADERS
+        .iter()
+        .any(|redacted| name.eq_ignore_ascii_case(redacted))
+        || name
+            .get(..REDACTED_PREFIX.len())
+            .map_or(false, |prefix| prefix.eq_ignore_ascii_case(REDACTED_PREFIX))
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    method: &'a str,
+    path: &'a str,
+    headers: Vec<(&'a str, String)>,
+    content_length: usize,
+}
+
+#[derive(Debug)]
+/// Appends the metadata of a sample of received requests to a file.
+pub(crate) struct RequestLog {
+    sampler: Sampler,
+}
+
+/// Return the log line of the request of `parts`, its body `content_length`
+/// bytes, credentials redacted.
+fn line(parts: &Parts, content_length: usize) -> Vec<u8> {
+    let entry = Entry {
+        method: parts.method.as_str(),
+        path: parts.uri.path(),
+        headers: parts
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                let value = if redacted(name.as_str()) {
+                    REDACTED.to_string()
+                } else {
+                    String::from_utf8_lossy(value.as_bytes()).into_owned()
+                };
+                (name.as_str(), value)
+            })
+            .collect(),
+        content_length,
+    };
+    let mut line = serde_json::to_vec(&entry).expect("entries always serialize");
+    line.push(b'\n');
+    line
+}
+
+impl RequestLog {
+    /// Offer the request of `parts`, its body `content_length` bytes as
+    /// received, to the log.
+    pub(crate) fn log(&self, parts: &Parts, content_length: usize) {
+        if !self.sampler.admit() {
+            return;
+        }
+        self.sampler.write(&line(parts, content_length));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::Request;
+    use proptest::prelude::*;
+
+    use super::{line, REDACTED, REDACTED_HEADERS};
+
+    // The value of a credential-bearing header never reaches the log, however
+    // its name is cased, while other headers are logged verbatim.
+    proptest! {
+        #[test]
+        fn credentials_redacted(
+            header in prop::sample::select(
+                REDACTED_HEADERS
+                    .iter()
+                    .map(|name| (*name).to_string())
+                    .chain(["x-amz-security-token".to_string(), "X-Amz-Date".to_string()])
+                    .collect::<Vec<_>>()
+            ),
+            uppercase: bool,
+            secret in "[A-Za-z0-9]{16,32}",
+            content_type in "[a-z]{1,16}",
+        ) {
+            let header = if uppercase { header.to_ascii_uppercase() } else { header };
+            let (parts, ()) = Request::builder()
+                .uri("/services/collector")
+                .header(header.as_str(), secret.as_str())
+                .header("content-type", content_type.as_str())
+                .body(())
+                .unwrap()
+                .into_parts();
+            let logged = String::from_utf8(line(&parts, 0)).unwrap();
+            prop_assert!(!logged.contains(&secret));
+            prop_assert!(logged.contains(REDACTED));
+            prop_assert!(logged.contains(&content_type));
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! Persist a sample of received payloads to disk.
+//!
+//! When the counts a blackhole reports look wrong it is useful to inspect
+//! exactly what the target emitted. A [`Sampler`] appends a deterministic
+//! fraction of received payloads, up to an optional byte budget, to a file in
+//! a configured directory. Payloads are written as received, after any content
+//! decoding, without separators.
+
+use std::{
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use byte_unit::Byte;
+use metrics::counter;
+use serde::Deserialize;
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::mpsc,
+};
+use tracing::{error, info};
+
+/// The number of payloads that may wait to be written before further payloads
+/// are dropped from the sample.
+const PENDING_SAMPLES: usize = 1024;
+
+fn default_fraction() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration for [`Sampler`]
+pub struct Config {
+    /// directory sampled payloads are written to, created if absent
+    pub directory: PathBuf,
+    /// fraction of received payloads to persist, between 0.0 and 1.0
+    #[serde(default = "default_fraction")]
+    pub fraction: f64,
+    /// stop persisting once this many bytes have been written. If absent the
+    /// sample grows without bound.
+    pub maximum_bytes: Option<Byte>,
+}
+
+/// Whether the payload at `index` is part of a sample of `fraction` of all
+/// payloads.
+///
+/// Exactly `floor(n * fraction)` of the first `n` payloads are admitted, spread
+/// evenly.
+fn admitted(index: u64, fraction: f64) -> bool {
+    ((index + 1) as f64 * fraction).floor() > (index as f64 * fraction).floor()
+}
+
+/// Create a [`Sampler`] for the blackhole `name` of kind `kind` bound to
+/// `binding_addr`, if sampling is configured. The sample is written to
+/// `<name>-<kind>-<port>.sample`, the blackhole's name keeping it apart from
+/// those of other blackholes of its kind on the same port.
+///
+/// # Errors
+///
+/// Function will return an error if [`Sampler::new`] does.
+pub(crate) async fn open(
+    config: Option<&Config>,
+    name: &str,
+    kind: &str,
+    binding_addr: SocketAddr,
+) -> Result<Option<Arc<Sampler>>, io::Error> {
+    match config {
+        Some(config) => {
+            let file_name = format!("{}-{}-{}.sample", name, kind, binding_addr.port());
+            Ok(Some(Arc::new(Sampler::new(config, &file_name).await?)))
+        }
+        None => Ok(None),
+    }
+}
+
+#[derive(Debug)]
+/// Appends a sample of received payloads to a file.
+///
+/// Payloads are handed to a task writing them, so that sampling never blocks
+/// the blackhole. Should the task fall [`PENDING_SAMPLES`] behind, payloads
+/// are dropped from the sample and counted as `samples_dropped`.
+pub(crate) struct Sampler {
+    samples: mpsc::Sender<Vec<u8>>,
+    fraction: f64,
+    maximum_bytes: u64,
+    offered: AtomicU64,
+    /// Bytes written or waiting to be, counted against `maximum_bytes`.
+    reserved: AtomicU64,
+}
+
+impl Sampler {
+    /// Create a new [`Sampler`] appending to `<directory>/<file_name>`.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the directory cannot be created or the
+    /// sample file cannot be opened.
+    pub(crate) async fn new(config: &Config, file_name: &str) -> Result<Self, io::Error> {
+        fs::create_dir_all(&config.directory).await?;
+        let path = config.directory.join(file_name);
+        // Append, not truncate: a restarted blackhole must not discard the
+        // sample its previous incarnation wrote.
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        info!("sampling to {}", path.display());
+        let (samples, pending) = mpsc::channel(PENDING_SAMPLES);
+        tokio::spawn(persist(file, path, pending));
+        Ok(Self {
+            samples,
+            fraction: config.fraction.clamp(0.0, 1.0),
+            maximum_bytes: config
+                .maximum_bytes
+                .map_or(u64::MAX, |b| b.get_bytes() as u64),
+            offered: AtomicU64::new(0),
+            reserved: AtomicU64::new(0),
+        })
+    }
+
+    /// Offer a payload, or a connection's worth of payloads, to the sample.
+    /// Returns true if it is admitted and should be passed to
+    /// [`Sampler::write`].
+    pub(crate) fn admit(&self) -> bool {
+        admitted(self.offered.fetch_add(1, Ordering::Relaxed), self.fraction)
+    }
+
+    /// Write `bytes` to the sample, truncated to the remaining byte budget.
+    pub(crate) fn write(&self, bytes: &[u8]) {
+        let maximum_bytes = self.maximum_bytes;
+        let mut len = 0;
+        let reserved =
+            self.reserved
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |reserved| {
+                    let remaining = maximum_bytes.saturating_sub(reserved);
+                    len = bytes
+                        .len()
+                        .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+                    (len > 0).then(|| reserved + len as u64)
+                });
+        if reserved.is_err() {
+            return;
+        }
+        if self.samples.try_send(bytes[..len].to_vec()).is_err() {
+            // The budget reserved is returned, the payload never written.
+            self.reserved.fetch_sub(len as u64, Ordering::Relaxed);
+            counter!("samples_dropped", 1);
+        }
+    }
+
+    /// Offer a single payload to the sample, writing it if admitted.
+    pub(crate) fn sample(&self, bytes: &[u8]) {
+        if self.admit() {
+            self.write(bytes);
+        }
+    }
+}
+
+/// Append each of `samples` to `file`, at `path`, until every [`Sampler`]
+/// sending them is dropped.
+async fn persist(mut file: File, path: PathBuf, mut samples: mpsc::Receiver<Vec<u8>>) {
+    while let Some(bytes) = samples.recv().await {
+        match file.write_all(&bytes).await {
+            Ok(()) => counter!("bytes_sampled", bytes.len() as u64),
+            Err(err) => error!("failed to write sample to {}: {}", path.display(), err),
+        }
+    }
+    if let Err(err) = file.flush().await {
+        error!("failed to write sample to {}: {}", path.display(), err);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::Ordering;
+
+    use byte_unit::Byte;
+    use proptest::{collection, prelude::*};
+
+    use super::{admitted, Config, Sampler};
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    // Of the first `n` payloads exactly `floor(n * fraction)` are admitted.
+    proptest! {
+        #[test]
+        fn admits_fraction(n in 0..10_000_u64, fraction in 0.0..=1.0_f64) {
+            let count = (0..n).filter(|i| admitted(*i, fraction)).count() as u64;
+            prop_assert_eq!(count, (n as f64 * fraction).floor() as u64);
+        }
+    }
+
+    // However many payloads are written, the bytes reserved for the sample
+    // never exceed its budget, and all of it is used if enough is offered.
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(8))]
+        #[test]
+        fn budget_never_exceeded(maximum_bytes in 0..4096_u64, lens in collection::vec(0..512_usize, 0..64)) {
+            let directory = std::env::temp_dir().join(format!(
+                "lading-sample-{}-{}",
+                std::process::id(),
+                maximum_bytes
+            ));
+            let config = Config {
+                directory: directory.clone(),
+                fraction: 1.0,
+                maximum_bytes: Some(Byte::from_bytes(u128::from(maximum_bytes))),
+            };
+            block_on(async move {
+                let sampler = Sampler::new(&config, "budget.sample").await.unwrap();
+                let offered: usize = lens.iter().sum();
+                for len in lens {
+                    sampler.sample(&vec![b'x'; len]);
+                }
+                let reserved = sampler.reserved.load(Ordering::Relaxed);
+                prop_assert_eq!(reserved, maximum_bytes.min(offered as u64));
+                Ok(())
+            })?;
+            std::fs::remove_dir_all(directory).unwrap();
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Attribute received bytes to the address they came from.
+//!
+//! When several generators drive several targets into one blackhole its
+//! `bytes_received` cannot tell which target sent what. A [`Sources`] counts
+//! received bytes again as `source_bytes_received`, labelled by the blackhole
+//! and the IP address they came from. Only the first `maximum_sources`
+//! addresses seen are labelled as themselves, later addresses as
+//! [`OTHER_SOURCE`], so that a target sending from many addresses does not
+//! swamp lading's telemetry.
+
+use std::{net::IpAddr, num::NonZeroUsize, sync::Arc};
+
+use metrics::counter;
+use serde::Deserialize;
+
+use crate::{address, telemetry::LabelValues};
+
+/// The label of sources beyond `maximum_sources`.
+pub(crate) const OTHER_SOURCE: &str = "other";
+
+fn default_maximum_sources() -> NonZeroUsize {
+    NonZeroUsize::new(16).unwrap()
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+/// Configuration for [`Sources`]
+pub struct Config {
+    /// the number of source addresses labelled as themselves, those seen
+    /// after labelled `other`
+    #[serde(default = "default_maximum_sources")]
+    pub maximum_sources: NonZeroUsize,
+}
+
+/// Create a [`Sources`] for the blackhole `blackhole`, if attribution is
+/// configured.
+pub(crate) fn open(config: Option<&Config>, blackhole: &str) -> Option<Arc<Sources>> {
+    config.map(|config| Arc::new(Sources::new(config, blackhole)))
+}
+
+/// Record that `bytes` were received from the source labelled `labels`, see
+/// [`Sources::labels`].
+#[allow(clippy::ptr_arg)]
+pub(crate) fn record(labels: &Vec<(String, String)>, bytes: u64) {
+    counter!("source_bytes_received", bytes, labels);
+}
+
+#[derive(Debug)]
+/// Labels the sources of received bytes, up to a maximum.
+pub(crate) struct Sources {
+    blackhole: String,
+    seen: LabelValues,
+}
+
+impl Sources {
+    fn new(config: &Config, blackhole: &str) -> Self {
+        Self {
+            blackhole: blackhole.to_string(),
+            seen: LabelValues::new(config.maximum_sources.get()),
+        }
+    }
+
+    /// Return the label of source `addr`. IPv4 sources of a dual-stack
+    /// blackhole are labelled by their IPv4 address.
+    fn label(&self, addr: IpAddr) -> String {
+        let addr = address::canonical(addr).to_string();
+        if self.seen.admit(&addr) {
+            addr
+        } else {
+            OTHER_SOURCE.to_string()
+        }
+    }
+
+    /// Return the labels of bytes received from source `addr`.
+    pub(crate) fn labels(&self, addr: IpAddr) -> Vec<(String, String)> {
+        vec![
+            ("blackhole".to_string(), self.blackhole.clone()),
+            ("source".to_string(), self.label(addr)),
+        ]
+    }
+
+    /// Record that `bytes` were received from `addr`.
+    pub(crate) fn record(&self, addr: IpAddr, bytes: u64) {
+        record(&self.labels(addr), bytes);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        num::NonZeroUsize,
+    };
+
+    use proptest::prelude::*;
+
+    use super::{Config, Sources, OTHER_SOURCE};
+
+    // The first `maximum_sources` addresses are always labelled as themselves
+    // and no more than that many labels other than `other` are ever given.
+    proptest! {
+        #[test]
+        fn labels_capped(maximum_sources in 1..32_usize, addrs: Vec<u32>) {
+            let sources = Sources::new(&Config {
+                maximum_sources: NonZeroUsize::new(maximum_sources).unwrap(),
+            }, "blackhole_0");
+            let mut labelled = Vec::new();
+            for addr in addrs {
+                let addr = IpAddr::V4(Ipv4Addr::from(addr));
+                let label = sources.label(addr);
+                if label == OTHER_SOURCE {
+                    prop_assert_eq!(labelled.len(), maximum_sources);
+                    prop_assert!(!labelled.contains(&addr));
+                } else {
+                    prop_assert_eq!(label, addr.to_string());
+                    if !labelled.contains(&addr) {
+                        labelled.push(addr);
+                    }
+                }
+                prop_assert_eq!(sources.label(addr) == OTHER_SOURCE, !labelled.contains(&addr));
+            }
+            prop_assert!(labelled.len() <= maximum_sources);
+        }
+    }
+}
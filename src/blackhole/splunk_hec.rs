@@ -3,21 +3,32 @@
 use std::{
     collections::HashMap,
     net::SocketAddr,
-    sync::atomic::{AtomicU64, Ordering},
-    time::Duration,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use hyper::{
     body, header,
-    server::conn::{AddrIncoming, AddrStream},
+    server::conn::AddrStream,
     service::{make_service_fn, service_fn},
     Body, Method, Request, Response, Server, StatusCode,
 };
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 use tower::ServiceBuilder;
 use tracing::{error, info};
 
-use crate::signals::Shutdown;
+use super::{
+    rate,
+    request_log::{self, RequestLog},
+    sample::{self, Sampler},
+    source::{self, Sources},
+    Meter,
+};
+use crate::{numa, signals::Shutdown};
 
 static ACK_ID: AtomicU64 = AtomicU64::new(0);
 
@@ -25,14 +36,23 @@ fn default_concurrent_requests_max() -> usize {
     100
 }
 
+fn default_expire_seconds() -> u64 {
+    300
+}
+
+/// How often pending acks are checked for expiry.
+const ACK_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Debug)]
 /// Errors produced by [`SplunkHec`].
 pub enum Error {
     /// Wrapper for [`hyper::Error`].
     Hyper(hyper::Error),
+    /// Wrapper for [`std::io::Error`].
+    Io(std::io::Error),
 }
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+#[derive(Debug, Deserialize, Clone)]
 /// Configuration for [`SplunkHec`].
 pub struct Config {
     /// number of concurrent HTTP connections to allow
@@ -40,6 +60,135 @@ pub struct Config {
     pub concurrent_requests_max: usize,
     /// address -- IP plus port -- to bind to
     pub binding_addr: SocketAddr,
+    /// persist a sample of received request bodies to disk, see
+    /// [`crate::blackhole::sample`]
+    pub sample: Option<sample::Config>,
+    /// log the method, path, headers and body length of a sample of received
+    /// requests to disk, see [`crate::blackhole::request_log`]
+    pub request_log: Option<sample::Config>,
+    /// alarm when the rate of request body bytes received, after any
+    /// `content-encoding` is decoded, leaves an expected range, see
+    /// [`crate::blackhole::rate`]
+    pub expected_rate: Option<rate::Config>,
+    /// count received bytes by the address they came from, see
+    /// [`crate::blackhole::source`]
+    pub sources: Option<source::Config>,
+    /// place this blackhole on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+    /// simulate a backlog of indexer acknowledgements, see [`AckConfig`]. If
+    /// unset every ack is available as soon as it is queried.
+    pub acks: Option<AckConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+/// Configuration of the indexer acknowledgements [`SplunkHec`] issues
+pub struct AckConfig {
+    /// milliseconds after an event request is received before its ack is
+    /// available
+    #[serde(default)]
+    pub delay_milliseconds: u64,
+    /// fraction of acks, from 0.0 to 1.0, that never become available
+    #[serde(default)]
+    pub loss_ratio: f64,
+    /// the seed for drawing which acks are lost, by default all zeros
+    #[serde(default)]
+    pub seed: [u8; 32],
+    /// the number of acks pending at once beyond which event requests are
+    /// refused with 503 server busy. If unset event requests are never refused.
+    pub maximum_pending: Option<usize>,
+    /// seconds after an ack becomes available that it is dropped if still not
+    /// queried, counted as `acks_expired`
+    #[serde(default = "default_expire_seconds")]
+    pub expire_seconds: u64,
+}
+
+/// The indexer acknowledgements issued but not yet queried as available.
+///
+/// A target that gives up on an ack never queries it, so acks left unqueried
+/// past [`AckConfig::expire_seconds`] are dropped rather than held forever.
+#[derive(Debug)]
+struct Acks {
+    config: AckConfig,
+    pending: Mutex<Pending>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    /// When each pending ack becomes available, by ack id. Lost acks are never
+    /// pending.
+    acks: HashMap<u64, Instant>,
+    /// When pending acks are next checked for expiry.
+    next_sweep: Instant,
+    /// Draws which acks are lost.
+    rng: StdRng,
+}
+
+impl Acks {
+    fn new(config: AckConfig) -> Self {
+        Self {
+            config,
+            pending: Mutex::new(Pending {
+                acks: HashMap::new(),
+                next_sweep: Instant::now(),
+                rng: StdRng::from_seed(config.seed),
+            }),
+        }
+    }
+
+    /// Drop the acks available for longer than the expiry at `now`, checking
+    /// no more often than [`ACK_SWEEP_INTERVAL`].
+    fn expire(&self, pending: &mut Pending, now: Instant) {
+        if now < pending.next_sweep {
+            return;
+        }
+        pending.next_sweep = now + ACK_SWEEP_INTERVAL;
+        let expiry = Duration::from_secs(self.config.expire_seconds);
+        let before = pending.acks.len();
+        pending
+            .acks
+            .retain(|_, available| now.saturating_duration_since(*available) < expiry);
+        let expired = before - pending.acks.len();
+        if expired > 0 {
+            metrics::counter!("acks_expired", expired as u64);
+        }
+    }
+
+    /// Issue the ack of an event request received at `now`, or `None` if the
+    /// server is busy.
+    #[allow(clippy::cast_precision_loss)]
+    fn issue(&self, now: Instant) -> Option<u64> {
+        let mut pending = self.pending.lock().unwrap();
+        self.expire(&mut pending, now);
+        if let Some(maximum_pending) = self.config.maximum_pending {
+            if pending.acks.len() >= maximum_pending {
+                return None;
+            }
+        }
+        let ack_id = ACK_ID.fetch_add(1, Ordering::Relaxed);
+        if pending.rng.gen::<f64>() < self.config.loss_ratio {
+            metrics::counter!("acks_lost", 1);
+        } else {
+            let available = now + Duration::from_millis(self.config.delay_milliseconds);
+            pending.acks.insert(ack_id, available);
+        }
+        metrics::gauge!("acks_pending", pending.acks.len() as f64);
+        Some(ack_id)
+    }
+
+    /// Whether ack `ack_id` is available at `now`. An available ack is no
+    /// longer pending, as Splunk reports each ack available only once.
+    fn query(&self, ack_id: u64, now: Instant) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        self.expire(&mut pending, now);
+        let pending = &mut pending.acks;
+        match pending.get(&ack_id) {
+            Some(available) if *available <= now => {
+                pending.remove(&ack_id);
+                true
+            }
+            _ => false,
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -52,12 +201,13 @@ struct HecAckResponse {
     acks: HashMap<u64, bool>,
 }
 
-impl From<HecAckRequest> for HecAckResponse {
-    fn from(ack_request: HecAckRequest) -> Self {
+impl HecAckResponse {
+    fn new(ack_request: HecAckRequest, acks: Option<&Acks>) -> Self {
+        let now = Instant::now();
         let acks = ack_request
             .acks
             .into_iter()
-            .map(|ack_id| (ack_id, true))
+            .map(|ack_id| (ack_id, acks.map_or(true, |acks| acks.query(ack_id, now))))
             .collect();
         HecAckResponse { acks }
     }
@@ -68,20 +218,37 @@ struct HecResponse {
     text: &'static str,
     // https://docs.splunk.com/Documentation/Splunk/8.2.3/Data/TroubleshootHTTPEventCollector#Possible_error_codes
     code: u8,
-    #[serde(rename = "ackId")]
-    ack_id: u64,
+    #[serde(rename = "ackId", skip_serializing_if = "Option::is_none")]
+    ack_id: Option<u64>,
 }
 
-async fn srv(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+async fn srv(
+    sampler: Option<Arc<Sampler>>,
+    request_log: Option<Arc<RequestLog>>,
+    acks: Option<Arc<Acks>>,
+    source: Option<Vec<(String, String)>>,
+    meter: Meter,
+    req: Request<Body>,
+) -> Result<Response<Body>, hyper::Error> {
     metrics::counter!("requests_received", 1);
 
     let (parts, body) = req.into_parts();
     let bytes = body::to_bytes(body).await?;
+    if let Some(request_log) = &request_log {
+        request_log.log(&parts, bytes.len());
+    }
 
     match crate::codec::decode(parts.headers.get(hyper::header::CONTENT_ENCODING), bytes) {
         Err(response) => Ok(response),
         Ok(body) => {
             metrics::counter!("bytes_received", body.len() as u64);
+            meter.record(body.len() as u64);
+            if let Some(sampler) = &sampler {
+                sampler.sample(&body);
+            }
+            if let Some(ref source) = source {
+                source::record(source, body.len() as u64);
+            }
 
             let mut okay = Response::default();
             *okay.status_mut() = StatusCode::OK;
@@ -99,21 +266,41 @@ async fn srv(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
                     | "/services/collector/raw"
                     | "/services/collector/raw/1.0",
                 ) => {
-                    let ack_id = ACK_ID.fetch_add(1, Ordering::Relaxed);
-                    let body_bytes = serde_json::to_vec(&HecResponse {
-                        text: "Success",
-                        code: 0,
-                        ack_id,
-                    })
-                    .unwrap();
+                    let response = match acks {
+                        None => HecResponse {
+                            text: "Success",
+                            code: 0,
+                            ack_id: Some(ACK_ID.fetch_add(1, Ordering::Relaxed)),
+                        },
+                        Some(acks) => match acks.issue(Instant::now()) {
+                            Some(ack_id) => HecResponse {
+                                text: "Success",
+                                code: 0,
+                                ack_id: Some(ack_id),
+                            },
+                            None => {
+                                metrics::counter!("requests_busy", 1);
+                                *okay.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                                HecResponse {
+                                    text: "Server is busy",
+                                    code: 9,
+                                    ack_id: None,
+                                }
+                            }
+                        },
+                    };
+                    let body_bytes = serde_json::to_vec(&response).unwrap();
                     *okay.body_mut() = Body::from(body_bytes);
                 }
                 // Path for querying indexer acknowledgements
                 (Method::POST, "/services/collector/ack") => {
                     match serde_json::from_slice::<HecAckRequest>(&body) {
                         Ok(ack_request) => {
-                            let body_bytes =
-                                serde_json::to_vec(&HecAckResponse::from(ack_request)).unwrap();
+                            let body_bytes = serde_json::to_vec(&HecAckResponse::new(
+                                ack_request,
+                                acks.as_deref(),
+                            ))
+                            .unwrap();
                             *okay.body_mut() = Body::from(body_bytes);
                         }
                         Err(_) => {
@@ -134,16 +321,28 @@ async fn srv(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
 pub struct SplunkHec {
     concurrency_limit: usize,
     httpd_addr: SocketAddr,
+    name: String,
+    sample: Option<sample::Config>,
+    request_log: Option<sample::Config>,
+    acks: Option<Arc<Acks>>,
+    sources: Option<Arc<Sources>>,
+    meter: Meter,
     shutdown: Shutdown,
 }
 
 impl SplunkHec {
-    /// Create a new [`SplunkHec`] server instance
+    /// Create a new [`SplunkHec`] server instance, the blackhole `name`
     #[must_use]
-    pub fn new(config: &Config, shutdown: Shutdown) -> Self {
+    pub fn new(config: &Config, meter: Meter, name: &str, shutdown: Shutdown) -> Self {
         Self {
             httpd_addr: config.binding_addr,
             concurrency_limit: config.concurrent_requests_max,
+            name: name.to_string(),
+            sample: config.sample.clone(),
+            request_log: config.request_log.clone(),
+            acks: config.acks.map(|acks| Arc::new(Acks::new(acks))),
+            sources: source::open(config.sources.as_ref(), name),
+            meter,
             shutdown,
         }
     }
@@ -155,26 +354,66 @@ impl SplunkHec {
     ///
     /// # Errors
     ///
-    /// Function will return an error if receiving a packet fails.
+    /// Function will return an error if receiving a packet fails or the sample
+    /// or request log file cannot be opened.
     ///
     /// # Panics
     ///
     /// None known.
     pub async fn run(mut self) -> Result<(), Error> {
-        let service =
-            make_service_fn(|_: &AddrStream| async move { Ok::<_, hyper::Error>(service_fn(srv)) });
+        let sampler = sample::open(
+            self.sample.as_ref(),
+            &self.name,
+            "splunk_hec",
+            self.httpd_addr,
+        )
+        .await
+        .map_err(Error::Io)?;
+        let request_log = request_log::open(
+            self.request_log.as_ref(),
+            &self.name,
+            "splunk_hec",
+            self.httpd_addr,
+        )
+        .await
+        .map_err(Error::Io)?;
+        let acks = self.acks.clone();
+        let sources = self.sources.clone();
+        let meter = self.meter.clone();
+        let service = make_service_fn(move |conn: &AddrStream| {
+            super::record_connection(conn.remote_addr());
+            let sampler = sampler.clone();
+            let request_log = request_log.clone();
+            let acks = acks.clone();
+            let source = sources
+                .as_ref()
+                .map(|sources| sources.labels(conn.remote_addr().ip()));
+            let meter = meter.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |request| {
+                    srv(
+                        sampler.clone(),
+                        request_log.clone(),
+                        acks.clone(),
+                        source.clone(),
+                        meter.clone(),
+                        request,
+                    )
+                }))
+            }
+        });
         let svc = ServiceBuilder::new()
             .load_shed()
             .concurrency_limit(self.concurrency_limit)
             .timeout(Duration::from_secs(1))
             .service(service);
 
-        let addr = AddrIncoming::bind(&self.httpd_addr)
+        let addr = super::incoming(self.httpd_addr)
             .map(|mut addr| {
                 addr.set_keepalive(Some(Duration::from_secs(60)));
                 addr
             })
-            .map_err(Error::Hyper)?;
+            .map_err(Error::Io)?;
         let server = Server::builder(addr).serve(svc);
         loop {
             tokio::select! {
@@ -190,3 +429,84 @@ impl SplunkHec {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use proptest::prelude::*;
+
+    use super::{AckConfig, Acks};
+
+    // An ack is unavailable until its delay has passed, is then available once
+    // only, and no more than `maximum_pending` acks are issued unqueried.
+    proptest! {
+        #[test]
+        fn acks_delayed_and_bounded(delay_milliseconds in 1..10_000_u64, maximum_pending in 1..64_usize, requests in 1..128_usize) {
+            let acks = Acks::new(AckConfig {
+                delay_milliseconds,
+                loss_ratio: 0.0,
+                seed: [0; 32],
+                maximum_pending: Some(maximum_pending),
+                expire_seconds: 3_600,
+            });
+            let now = Instant::now();
+            let issued: Vec<u64> = (0..requests).filter_map(|_| acks.issue(now)).collect();
+            prop_assert_eq!(issued.len(), requests.min(maximum_pending));
+
+            let delay = Duration::from_millis(delay_milliseconds);
+            for ack_id in &issued {
+                prop_assert!(!acks.query(*ack_id, now + delay - Duration::from_millis(1)));
+                prop_assert!(acks.query(*ack_id, now + delay));
+                prop_assert!(!acks.query(*ack_id, now + delay));
+            }
+            prop_assert!(acks.issue(now).is_some());
+        }
+    }
+
+    // An ack left unqueried past its expiry is dropped, freeing its place
+    // among the pending.
+    proptest! {
+        #[test]
+        fn unqueried_acks_expire(delay_milliseconds in 0..10_000_u64, expire_seconds in 1..600_u64, maximum_pending in 1..64_usize) {
+            let acks = Acks::new(AckConfig {
+                delay_milliseconds,
+                loss_ratio: 0.0,
+                seed: [0; 32],
+                maximum_pending: Some(maximum_pending),
+                expire_seconds,
+            });
+            let now = Instant::now();
+            let issued: Vec<u64> = (0..maximum_pending).filter_map(|_| acks.issue(now)).collect();
+            prop_assert_eq!(issued.len(), maximum_pending);
+            prop_assert!(acks.issue(now).is_none());
+
+            let expired = now + Duration::from_millis(delay_milliseconds) + Duration::from_secs(expire_seconds);
+            prop_assert!(acks.issue(expired).is_some());
+            for ack_id in &issued {
+                prop_assert!(!acks.query(*ack_id, expired));
+            }
+        }
+    }
+    // Which acks are lost is drawn from the seed: acks issued alike from the
+    // same seed are lost alike.
+    proptest! {
+        #[test]
+        fn ack_loss_seeded(seed: [u8; 32], loss_ratio in 0.0..=1.0_f64, requests in 1..128_usize) {
+            let lost = || {
+                let acks = Acks::new(AckConfig {
+                    delay_milliseconds: 0,
+                    loss_ratio,
+                    seed,
+                    maximum_pending: None,
+                    expire_seconds: 3_600,
+                });
+                let now = Instant::now();
+                (0..requests)
+                    .map(|_| !acks.query(acks.issue(now).unwrap(), now))
+                    .collect::<Vec<bool>>()
+            };
+            prop_assert_eq!(lost(), lost());
+        }
+    }
+}
@@ -1,10 +1,14 @@
 //! The [SQS](https://aws.amazon.com/sqs/) protocol speaking blackhole.
+//!
+//! Messages sent with `SendMessage` or `SendMessageBatch` are acknowledged and
+//! counted, their bodies by decoded length. Receives are answered with random
+//! messages.
 
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc};
 
 use hyper::{
     body,
-    server::conn::{AddrIncoming, AddrStream},
+    server::conn::AddrStream,
     service::{make_service_fn, service_fn},
     Body, Request, Response, Server, StatusCode,
 };
@@ -14,20 +18,29 @@ use tokio::time::Duration;
 use tower::ServiceBuilder;
 use tracing::{error, info};
 
-use crate::signals::Shutdown;
+use super::{
+    rate,
+    request_log::{self, RequestLog},
+    sample::{self, Sampler},
+    source::{self, Sources},
+    Meter,
+};
+use crate::{numa, signals::Shutdown};
 
 #[derive(Debug)]
 /// Errors produced by [`Sqs`]
 pub enum Error {
     /// Wrapper for [`hyper::Error`].
     Hyper(hyper::Error),
+    /// Wrapper for [`std::io::Error`].
+    Io(std::io::Error),
 }
 
 fn default_concurrent_requests_max() -> usize {
     100
 }
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+#[derive(Debug, Deserialize, Clone)]
 /// Configuration for [`Sqs`]
 pub struct Config {
     /// number of concurrent HTTP connections to allow
@@ -35,6 +48,21 @@ pub struct Config {
     pub concurrent_requests_max: usize,
     /// address -- IP plus port -- to bind to
     pub binding_addr: SocketAddr,
+    /// persist a sample of received request bodies to disk, see
+    /// [`crate::blackhole::sample`]
+    pub sample: Option<sample::Config>,
+    /// log the method, path, headers and body length of a sample of received
+    /// requests to disk, see [`crate::blackhole::request_log`]
+    pub request_log: Option<sample::Config>,
+    /// alarm when the rate of request bytes received, form encoded as sent
+    /// rather than the decoded message bodies, leaves an expected range, see
+    /// [`crate::blackhole::rate`]
+    pub expected_rate: Option<rate::Config>,
+    /// count received bytes by the address they came from, see
+    /// [`crate::blackhole::source`]
+    pub sources: Option<source::Config>,
+    /// place this blackhole on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
 }
 
 #[derive(Debug)]
@@ -42,16 +70,26 @@ pub struct Config {
 pub struct Sqs {
     httpd_addr: SocketAddr,
     concurrency_limit: usize,
+    name: String,
+    sample: Option<sample::Config>,
+    request_log: Option<sample::Config>,
+    sources: Option<Arc<Sources>>,
+    meter: Meter,
     shutdown: Shutdown,
 }
 
 impl Sqs {
-    /// Create a new [`Sqs`] server instance
+    /// Create a new [`Sqs`] server instance, the blackhole `name`
     #[must_use]
-    pub fn new(config: &Config, shutdown: Shutdown) -> Self {
+    pub fn new(config: &Config, meter: Meter, name: &str, shutdown: Shutdown) -> Self {
         Self {
             httpd_addr: config.binding_addr,
             concurrency_limit: config.concurrent_requests_max,
+            name: name.to_string(),
+            sample: config.sample.clone(),
+            request_log: config.request_log.clone(),
+            sources: source::open(config.sources.as_ref(), name),
+            meter,
             shutdown,
         }
     }
@@ -63,26 +101,58 @@ impl Sqs {
     ///
     /// # Errors
     ///
-    /// Function will return an if an http server error ocurrs.
+    /// Function will return an if an http server error ocurrs or the sample
+    /// or request log file cannot be opened.
     ///
     /// # Panics
     ///
     /// None known.
     pub async fn run(mut self) -> Result<(), Error> {
-        let service =
-            make_service_fn(|_: &AddrStream| async move { Ok::<_, hyper::Error>(service_fn(srv)) });
+        let sampler = sample::open(self.sample.as_ref(), &self.name, "sqs", self.httpd_addr)
+            .await
+            .map_err(Error::Io)?;
+        let request_log = request_log::open(
+            self.request_log.as_ref(),
+            &self.name,
+            "sqs",
+            self.httpd_addr,
+        )
+        .await
+        .map_err(Error::Io)?;
+        let sources = self.sources.clone();
+        let meter = self.meter.clone();
+        let service = make_service_fn(move |conn: &AddrStream| {
+            super::record_connection(conn.remote_addr());
+            let sampler = sampler.clone();
+            let request_log = request_log.clone();
+            let source = sources
+                .as_ref()
+                .map(|sources| sources.labels(conn.remote_addr().ip()));
+            let meter = meter.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |request| {
+                    srv(
+                        sampler.clone(),
+                        request_log.clone(),
+                        source.clone(),
+                        meter.clone(),
+                        request,
+                    )
+                }))
+            }
+        });
         let svc = ServiceBuilder::new()
             .load_shed()
             .concurrency_limit(self.concurrency_limit)
             .timeout(Duration::from_secs(1))
             .service(service);
 
-        let addr = AddrIncoming::bind(&self.httpd_addr)
+        let addr = super::incoming(self.httpd_addr)
             .map(|mut addr| {
                 addr.set_keepalive(Some(Duration::from_secs(60)));
                 addr
             })
-            .unwrap();
+            .map_err(Error::Io)?;
         let server = Server::builder(addr).serve(svc);
         loop {
             tokio::select! {
@@ -198,11 +268,66 @@ impl DeleteMessageBatch {
     }
 }
 
-async fn srv(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+/// The messages of a `SendMessage` or `SendMessageBatch` request.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct SentMessages {
+    /// The ids of the entries of a batch, in request order.
+    ids: Vec<String>,
+    /// The number of message bodies.
+    messages: u64,
+    /// The bytes of the message bodies, decoded.
+    body_bytes: u64,
+}
+
+/// The length of the `application/x-www-form-urlencoded` `value` once
+/// decoded, each percent escape standing for one byte.
+fn decoded_len(value: &[u8]) -> u64 {
+    let escapes = value.iter().filter(|byte| **byte == b'%').count();
+    value.len().saturating_sub(2 * escapes) as u64
+}
+
+/// Parse the form encoded request `form` for the messages it sends. Bodies are
+/// counted, not decoded, as the target need not send text.
+fn sent_messages(form: &[u8]) -> SentMessages {
+    let mut sent = SentMessages::default();
+    for pair in form.split(|byte| *byte == b'&') {
+        let mut parts = pair.splitn(2, |byte| *byte == b'=');
+        let (key, value) = match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => (key, value),
+            _ => continue,
+        };
+        if key == b"MessageBody" || key.ends_with(b".MessageBody") {
+            sent.messages += 1;
+            sent.body_bytes += decoded_len(value);
+        } else if key.starts_with(b"SendMessageBatchRequestEntry.") && key.ends_with(b".Id") {
+            sent.ids.push(String::from_utf8_lossy(value).into_owned());
+        }
+    }
+    sent
+}
+
+async fn srv(
+    sampler: Option<Arc<Sampler>>,
+    request_log: Option<Arc<RequestLog>>,
+    source: Option<Vec<(String, String)>>,
+    meter: Meter,
+    req: Request<Body>,
+) -> Result<Response<Body>, hyper::Error> {
     metrics::counter!("requests_received", 1);
 
-    let bytes = body::to_bytes(req).await?;
+    let (parts, body) = req.into_parts();
+    let bytes = body::to_bytes(body).await?;
+    if let Some(request_log) = &request_log {
+        request_log.log(&parts, bytes.len());
+    }
     metrics::counter!("bytes_received", bytes.len() as u64);
+    meter.record(bytes.len() as u64);
+    if let Some(sampler) = &sampler {
+        sampler.sample(&bytes);
+    }
+    if let Some(ref source) = source {
+        source::record(source, bytes.len() as u64);
+    }
 
     let action: Action = serde_qs::from_bytes(&bytes).unwrap();
 
@@ -216,6 +341,21 @@ async fn srv(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
                 .body(Body::from(generate_receive_message_response(num_messages)))
                 .unwrap())
         }
+        "SendMessage" | "SendMessageBatch" => {
+            let sent = sent_messages(&bytes);
+            metrics::counter!("messages_received", sent.messages);
+            metrics::counter!("message_bytes_received", sent.body_bytes);
+            let body = if action.action == "SendMessage" {
+                generate_send_message_response()
+            } else {
+                generate_send_message_batch_response(&sent.ids)
+            };
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("content-type", "text/html")
+                .body(Body::from(body))
+                .unwrap())
+        }
         "DeleteMessage" => Ok(Response::builder()
             .status(StatusCode::OK)
             .header("content-type", "text/html")
@@ -254,6 +394,24 @@ fn generate_delete_message_response() -> String {
     request_id)
 }
 
+fn generate_send_message_response() -> String {
+    format!("<SendMessageResponse><SendMessageResult><MessageId>{}</MessageId></SendMessageResult><ResponseMetadata><RequestId>{}</RequestId></ResponseMetadata></SendMessageResponse>",
+    random_string(36), random_string(52))
+}
+
+fn generate_send_message_batch_response(ids: &[String]) -> String {
+    let mut entries = String::new();
+    for id in ids {
+        entries += &format!(
+            "<SendMessageBatchResultEntry><Id>{}</Id><MessageId>{}</MessageId></SendMessageBatchResultEntry>",
+            id,
+            random_string(36)
+        );
+    }
+    format!("<SendMessageBatchResponse><SendMessageBatchResult>{}</SendMessageBatchResult><ResponseMetadata><RequestId>{}</RequestId></ResponseMetadata></SendMessageBatchResponse>",
+    entries, random_string(52))
+}
+
 fn generate_receive_message_response(num_messages: u32) -> String {
     let mut messages = String::new();
     for _ in 0..num_messages {
@@ -275,3 +433,29 @@ fn generate_message() -> String {
         message_id, receipt_handle, md5, body
     )
 }
+
+#[cfg(test)]
+mod test {
+    use proptest::{collection, prelude::*};
+
+    use super::sent_messages;
+    use crate::generator::sqs::encode_request;
+
+    // The messages of a request the sqs generator sends are counted, their
+    // bodies in bytes as sent rather than as encoded, and a batch's entry ids
+    // are found in order.
+    proptest! {
+        #[test]
+        fn sent_messages_decoded(messages in collection::vec(collection::vec(any::<u8>(), 0..256), 1..10)) {
+            let bodies: Vec<&[u8]> = messages.iter().map(Vec::as_slice).collect();
+            let form = encode_request("http://localhost:9324/queue/lading", &bodies);
+            let sent = sent_messages(&form);
+            prop_assert_eq!(sent.messages, messages.len() as u64);
+            prop_assert_eq!(sent.body_bytes, messages.iter().map(|m| m.len() as u64).sum::<u64>());
+            if messages.len() > 1 {
+                let ids: Vec<String> = (1..=messages.len()).map(|idx| format!("message_{}", idx)).collect();
+                prop_assert_eq!(sent.ids, ids);
+            }
+        }
+    }
+}
@@ -1,52 +1,418 @@
 //! The TCP protocol speaking blackhole.
+//!
+//! When configured with [`ProtocolMatcher`] instances this blackhole will sniff
+//! the leading bytes of each connection and classify the stream, labeling its
+//! received byte counts by the detected protocol. This is useful for targets
+//! that multiplex several protocols over a single port.
+//!
+//! At high rates the blackhole must not become the bottleneck. Each connection
+//! reads into a large buffer it reuses for its lifetime and may drain several
+//! reads each time the socket becomes readable, rather than returning to the
+//! runtime after every read. Connections may be accepted by several tasks, each
+//! with a listener bound to the same address with `SO_REUSEPORT`.
+//!
+//! Connections may be read with the io_uring backend, see [`crate::uring`],
+//! each acceptor and the connections it accepts then run on their own thread.
+//! Reads complete with whatever the kernel has buffered, so `burst_reads` does
+//! not apply.
 
-use std::{io, net::SocketAddr};
+use std::{
+    io,
+    net::SocketAddr,
+    num::{NonZeroU32, NonZeroUsize},
+    sync::Arc,
+};
 
-use futures::stream::StreamExt;
+use byte_unit::{Byte, ByteUnit};
+use futures::{
+    future::{BoxFuture, FutureExt},
+    stream::{FuturesUnordered, StreamExt},
+};
 use metrics::counter;
 use serde::Deserialize;
-use tokio::net::{TcpListener, TcpStream};
-use tokio_util::io::ReaderStream;
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpListener, TcpStream},
+};
 use tracing::info;
 
-use crate::signals::Shutdown;
+use super::{
+    rate,
+    sample::{self, Sampler},
+    source::{self, Sources},
+    Meter,
+};
+use crate::{
+    address, numa,
+    signals::Shutdown,
+    uring::{self, Backend},
+};
+
+const UNKNOWN_PROTOCOL: &str = "unknown";
+
+fn default_read_buffer_bytes() -> Byte {
+    Byte::from_unit(32.0, ByteUnit::KiB).unwrap()
+}
+
+fn default_burst_reads() -> NonZeroU32 {
+    NonZeroU32::new(1).unwrap()
+}
+
+fn default_acceptors() -> NonZeroUsize {
+    NonZeroUsize::new(1).unwrap()
+}
 
 #[derive(Debug)]
 /// Errors emitted by [`Tcp`]
 pub enum Error {
     /// Wrapper for [`std::io::Error`].
     Io(io::Error),
+    /// Wrapper for [`nix::errno::Errno`].
+    Errno(nix::errno::Errno),
+    /// Wrapper for [`crate::uring::Error`].
+    Uring(uring::Error),
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Classifies a TCP stream by the bytes it begins with.
+pub struct ProtocolMatcher {
+    /// The name of the protocol, used as the `protocol` label value.
+    pub name: String,
+    /// The bytes a stream must begin with to be classified as this protocol.
+    pub prefix: String,
 }
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+#[derive(Debug, Deserialize, Clone)]
 /// Configuration for [`Tcp`]
 pub struct Config {
     /// address -- IP plus port -- to bind to
-    binding_addr: SocketAddr,
+    pub binding_addr: SocketAddr,
+    /// matchers used to classify each connection by its leading bytes, checked
+    /// in order. If empty no classification is done.
+    #[serde(default)]
+    protocol_matchers: Vec<ProtocolMatcher>,
+    /// the size of the buffer each connection reads into, by default 32 KiB.
+    /// Every connection holds one, so raise it only for targets sending at
+    /// line rate over few connections.
+    #[serde(default = "default_read_buffer_bytes")]
+    read_buffer_bytes: Byte,
+    /// the maximum number of reads drained from a connection each time it
+    /// becomes readable
+    #[serde(default = "default_burst_reads")]
+    burst_reads: NonZeroU32,
+    /// number of tasks accepting connections. More than one binds a listener
+    /// per task with `SO_REUSEPORT`.
+    #[serde(default = "default_acceptors")]
+    pub acceptors: NonZeroUsize,
+    /// the I/O backend connections are read with
+    #[serde(default)]
+    pub backend: Backend,
+    /// persist a sample of received connections to disk, see
+    /// [`crate::blackhole::sample`]. The sampled fraction applies to
+    /// connections, not reads.
+    pub sample: Option<sample::Config>,
+    /// alarm when the rate of bytes read from the stream, summed over every
+    /// connection, leaves an expected range, see [`crate::blackhole::rate`]
+    pub expected_rate: Option<rate::Config>,
+    /// count received bytes by the address they came from, see
+    /// [`crate::blackhole::source`]
+    pub sources: Option<source::Config>,
+    /// place this blackhole on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
 }
 
 #[derive(Debug)]
 /// The TCP blackhole.
 pub struct Tcp {
     binding_addr: SocketAddr,
+    acceptors: NonZeroUsize,
+    backend: Backend,
+    reader: Reader,
+    name: String,
+    sample: Option<sample::Config>,
     shutdown: Shutdown,
 }
 
+/// Return the name of the first matcher whose prefix begins `sniffed`, else
+/// [`UNKNOWN_PROTOCOL`].
+fn classify<'a>(matchers: &'a [ProtocolMatcher], sniffed: &[u8]) -> &'a str {
+    matchers
+        .iter()
+        .find(|m| sniffed.starts_with(m.prefix.as_bytes()))
+        .map_or(UNKNOWN_PROTOCOL, |m| m.name.as_str())
+}
+
+#[derive(Debug, Clone)]
+/// Reads connections to completion, shared by every acceptor.
+struct Reader {
+    matchers: Arc<Vec<ProtocolMatcher>>,
+    buffer_bytes: usize,
+    burst_reads: NonZeroU32,
+    sources: Option<Arc<Sources>>,
+    meter: Meter,
+}
+
+/// The received bytes of a single connection, classified by protocol.
+struct Connection<'a> {
+    matchers: &'a [ProtocolMatcher],
+    sampler: Option<Arc<Sampler>>,
+    meter: &'a Meter,
+    source: Option<Vec<(String, String)>>,
+    family: (String, String),
+    // Bytes are held back from `bytes_received` until we have seen enough of
+    // the stream to classify it, or the stream has ended.
+    sniff_len: usize,
+    sniffed: Vec<u8>,
+    pending_bytes: u64,
+    labels: Option<Vec<(String, String)>>,
+}
+
+impl<'a> Connection<'a> {
+    fn new(
+        matchers: &'a [ProtocolMatcher],
+        sampler: Option<Arc<Sampler>>,
+        meter: &'a Meter,
+        source: Option<Vec<(String, String)>>,
+        addr: SocketAddr,
+    ) -> Self {
+        let sniff_len = matchers.iter().map(|m| m.prefix.len()).max().unwrap_or(0);
+        let family = address::label(addr.ip());
+        Self {
+            matchers,
+            sampler,
+            meter,
+            source,
+            sniff_len,
+            sniffed: Vec::with_capacity(sniff_len),
+            pending_bytes: 0,
+            labels: if sniff_len == 0 {
+                Some(vec![family.clone()])
+            } else {
+                None
+            },
+            family,
+        }
+    }
+
+    /// Record a read of `bytes` from the connection.
+    fn record(&mut self, bytes: &[u8]) {
+        counter!("message_received", 1);
+        self.meter.record(bytes.len() as u64);
+        if let Some(sampler) = &self.sampler {
+            sampler.write(bytes);
+        }
+        if let Some(ref source) = self.source {
+            source::record(source, bytes.len() as u64);
+        }
+        if let Some(ref labels) = self.labels {
+            counter!("bytes_received", bytes.len() as u64, labels);
+            return;
+        }
+        let needed = self.sniff_len - self.sniffed.len();
+        self.sniffed
+            .extend_from_slice(&bytes[..needed.min(bytes.len())]);
+        self.pending_bytes += bytes.len() as u64;
+        if self.sniffed.len() >= self.sniff_len {
+            self.classify();
+        }
+    }
+
+    fn classify(&mut self) {
+        let protocol = classify(self.matchers, &self.sniffed);
+        let lbls = vec![
+            ("protocol".to_string(), protocol.to_string()),
+            self.family.clone(),
+        ];
+        counter!("connection_classified", 1, &lbls);
+        counter!("bytes_received", self.pending_bytes, &lbls);
+        self.labels = Some(lbls);
+    }
+
+    /// Record the end of the connection.
+    fn finish(mut self) {
+        // The stream ended before we saw enough bytes to fill our sniff
+        // buffer. Classify with what we have.
+        if self.labels.is_none() && self.pending_bytes > 0 {
+            self.classify();
+        }
+    }
+}
+
+impl Reader {
+    async fn handle_connection(
+        self,
+        mut socket: TcpStream,
+        addr: SocketAddr,
+        sampler: Option<Arc<Sampler>>,
+    ) {
+        let sampler = sampler.filter(|s| s.admit());
+        let source = self
+            .sources
+            .as_ref()
+            .map(|sources| sources.labels(addr.ip()));
+        let mut connection = Connection::new(&self.matchers, sampler, &self.meter, source, addr);
+        let mut buf: Vec<u8> = vec![0; self.buffer_bytes.max(1)];
+
+        'connection: loop {
+            match socket.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(bytes) => connection.record(&buf[..bytes]),
+            }
+            // Drain what else is already buffered in the kernel without
+            // returning to the runtime, up to the burst limit.
+            for _ in 1..self.burst_reads.get() {
+                match socket.try_read(&mut buf) {
+                    Ok(0) => break 'connection,
+                    Ok(bytes) => connection.record(&buf[..bytes]),
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(_) => break 'connection,
+                }
+            }
+        }
+        connection.finish();
+    }
+
+    /// Accept connections on `listener` until a shutdown signal is received.
+    async fn accept(
+        self,
+        listener: TcpListener,
+        sampler: Option<Arc<Sampler>>,
+        mut shutdown: Shutdown,
+    ) -> Result<(), io::Error> {
+        loop {
+            tokio::select! {
+                conn = listener.accept() => {
+                    let (socket, addr) = conn?;
+                    let labels = vec![address::label(addr.ip())];
+                    counter!("connection_accepted", 1, &labels);
+                    tokio::spawn(self.clone().handle_connection(socket, addr, sampler.clone()));
+                }
+                _ = shutdown.recv() => {
+                    return Ok(())
+                }
+            }
+        }
+    }
+
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    async fn handle_uring_connection(
+        self,
+        socket: tokio_uring::net::TcpStream,
+        addr: SocketAddr,
+        sampler: Option<Arc<Sampler>>,
+    ) {
+        let sampler = sampler.filter(|s| s.admit());
+        let source = self
+            .sources
+            .as_ref()
+            .map(|sources| sources.labels(addr.ip()));
+        let mut connection = Connection::new(&self.matchers, sampler, &self.meter, source, addr);
+        // The buffer is owned by the ring while a read is in flight. Its
+        // length is set to the bytes read.
+        let mut buf: Vec<u8> = Vec::with_capacity(self.buffer_bytes.max(1));
+
+        loop {
+            buf.clear();
+            let (read, returned) = socket.read(buf).await;
+            buf = returned;
+            match read {
+                Ok(0) | Err(_) => break,
+                Ok(bytes) => connection.record(&buf[..bytes]),
+            }
+        }
+        connection.finish();
+    }
+
+    /// Accept connections on `listener` until a shutdown signal is received,
+    /// with the io_uring backend.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    async fn accept_uring(
+        self,
+        listener: tokio_uring::net::TcpListener,
+        sampler: Option<Arc<Sampler>>,
+        mut shutdown: Shutdown,
+    ) -> Result<(), io::Error> {
+        loop {
+            tokio::select! {
+                conn = listener.accept() => {
+                    let (socket, addr) = conn?;
+                    let labels = vec![address::label(addr.ip())];
+                    counter!("connection_accepted", 1, &labels);
+                    tokio_uring::spawn(self.clone().handle_uring_connection(socket, addr, sampler.clone()));
+                }
+                _ = shutdown.recv() => {
+                    return Ok(())
+                }
+            }
+        }
+    }
+}
+
 impl Tcp {
-    /// Create a new [`Tcp`] server instance
+    /// Create a new [`Tcp`] server instance, the blackhole `name`
     #[must_use]
-    pub fn new(config: &Config, shutdown: Shutdown) -> Self {
+    pub fn new(config: &Config, meter: Meter, name: &str, shutdown: Shutdown) -> Self {
         Self {
             binding_addr: config.binding_addr,
+            acceptors: config.acceptors,
+            backend: config.backend,
+            reader: Reader {
+                matchers: Arc::new(config.protocol_matchers.clone()),
+                buffer_bytes: config.read_buffer_bytes.get_bytes() as usize,
+                burst_reads: config.burst_reads,
+                sources: source::open(config.sources.as_ref(), name),
+                meter,
+            },
+            name: name.to_string(),
+            sample: config.sample.clone(),
             shutdown,
         }
     }
 
-    async fn handle_connection(socket: TcpStream) {
-        let mut stream = ReaderStream::new(socket);
+    /// Bind the blackhole's listeners, with `SO_REUSEPORT` if there are
+    /// several.
+    fn bind(&self) -> Result<Vec<std::net::TcpListener>, Error> {
+        let reuse_port = self.acceptors.get() > 1;
+        (0..self.acceptors.get())
+            .map(|_| super::listen(self.binding_addr, reuse_port).map_err(Error::Errno))
+            .collect()
+    }
 
-        while stream.next().await.is_some() {
-            counter!("message_received", 1);
+    /// Start accepting on `listener` with the configured backend, returning
+    /// the acceptor's outcome.
+    fn acceptor(
+        &self,
+        listener: std::net::TcpListener,
+        sampler: Option<Arc<Sampler>>,
+    ) -> Result<BoxFuture<'static, Result<(), Error>>, Error> {
+        let reader = self.reader.clone();
+        let shutdown = self.shutdown.clone();
+        match self.backend {
+            Backend::Epoll => {
+                listener.set_nonblocking(true).map_err(Error::Io)?;
+                let listener = TcpListener::from_std(listener).map_err(Error::Io)?;
+                let handle = tokio::spawn(reader.accept(listener, sampler, shutdown));
+                Ok(async move {
+                    handle
+                        .await
+                        .map_err(|err| Error::Io(io::Error::new(io::ErrorKind::Other, err)))?
+                        .map_err(Error::Io)
+                }
+                .boxed())
+            }
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            Backend::IoUring => Ok(async move {
+                uring::run("tcp-blackhole", move || {
+                    let listener = tokio_uring::net::TcpListener::from_std(listener);
+                    reader.accept_uring(listener, sampler, shutdown)
+                })
+                .await
+                .map_err(Error::Uring)?
+                .map_err(Error::Io)
+            }
+            .boxed()),
+            #[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+            Backend::IoUring => Err(Error::Uring(uring::Error::Unsupported)),
         }
     }
 
@@ -57,24 +423,28 @@ impl Tcp {
     ///
     /// # Errors
     ///
-    /// Function will return an error if binding to the assigned address fails.
+    /// Function will return an error if binding to the assigned address fails,
+    /// the sample file cannot be opened or the configured backend is not
+    /// available.
     ///
     /// # Panics
     ///
     /// None known.
     pub async fn run(mut self) -> Result<(), Error> {
-        let listener = TcpListener::bind(self.binding_addr)
+        let listeners = self.bind()?;
+        let sampler = sample::open(self.sample.as_ref(), &self.name, "tcp", self.binding_addr)
             .await
             .map_err(Error::Io)?;
 
+        let mut acceptors: FuturesUnordered<_> = listeners
+            .into_iter()
+            .map(|listener| self.acceptor(listener, sampler.clone()))
+            .collect::<Result<_, _>>()?;
+
         loop {
             tokio::select! {
-                conn = listener.accept() => {
-                    let (socket, _) = conn.map_err(Error::Io)?;
-                    counter!("connection_accepted", 1);
-                    tokio::spawn(async move {
-                        Self::handle_connection(socket).await;
-                    });
+                Some(acceptor) = acceptors.next() => {
+                    acceptor?;
                 }
                 _ = self.shutdown.recv() => {
                     info!("shutdown signal received");
@@ -84,3 +454,45 @@ impl Tcp {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::{classify, ProtocolMatcher, UNKNOWN_PROTOCOL};
+
+    fn matchers() -> Vec<ProtocolMatcher> {
+        vec![
+            ProtocolMatcher {
+                name: "syslog".to_string(),
+                prefix: "<".to_string(),
+            },
+            ProtocolMatcher {
+                name: "json".to_string(),
+                prefix: "{".to_string(),
+            },
+        ]
+    }
+
+    // A stream that begins with a matcher's prefix is always classified as
+    // that matcher's protocol.
+    proptest! {
+        #[test]
+        fn prefix_always_classifies(rest: Vec<u8>) {
+            let matchers = matchers();
+            for m in &matchers {
+                let mut sniffed = m.prefix.as_bytes().to_vec();
+                sniffed.extend_from_slice(&rest);
+                prop_assert_eq!(m.name.as_str(), classify(&matchers, &sniffed));
+            }
+        }
+    }
+
+    // A stream that matches no prefix is unknown.
+    proptest! {
+        #[test]
+        fn no_prefix_is_unknown(sniffed in "[a-z]*") {
+            prop_assert_eq!(UNKNOWN_PROTOCOL, classify(&matchers(), sniffed.as_bytes()));
+        }
+    }
+}
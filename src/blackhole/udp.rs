@@ -1,45 +1,282 @@
 //! The UDP protocol speaking blackhole.
+//!
+//! A single socket cannot drain high packet rates, and the kernel drops what
+//! does not fit in the socket's receive buffer silently. This blackhole may
+//! bind several sockets to the same address with `SO_REUSEPORT`, each read by
+//! its own task, the kernel balancing packets between them. On Linux the
+//! kernel's drop counters for the blackhole's sockets are reported as
+//! `kernel_packets_dropped`, labeled by blackhole, so that silent drops are
+//! not silent.
+//!
+//! Sockets may be read with the io_uring backend, see [`crate::uring`], each
+//! socket then read on its own thread.
 
-use std::{io, net::SocketAddr};
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    num::NonZeroUsize,
+    sync::Arc,
+    time::Duration,
+};
 
+use futures::{
+    future::{BoxFuture, FutureExt},
+    stream::{FuturesUnordered, StreamExt},
+};
 use metrics::counter;
+use nix::sys::socket::SockType;
 use serde::Deserialize;
-use tokio::net::UdpSocket;
+use tokio::{net::UdpSocket, time};
 use tracing::info;
 
-use crate::signals::Shutdown;
+use super::{
+    rate,
+    sample::{self, Sampler},
+    source::{self, Sources},
+    Meter,
+};
+use crate::{
+    address, numa,
+    signals::Shutdown,
+    uring::{self, Backend},
+};
+
+/// The size of each socket's receive buffer, enough for any UDP packet.
+pub(crate) const RECEIVE_BUFFER_BYTES: usize = 65536;
+
+fn default_sockets() -> NonZeroUsize {
+    NonZeroUsize::new(1).unwrap()
+}
 
 #[derive(Debug)]
 /// Errors produced by [`Udp`].
 pub enum Error {
     /// Wrapper for [`std::io::Error`].
     Io(io::Error),
+    /// Wrapper for [`nix::errno::Errno`].
+    Errno(nix::errno::Errno),
+    /// Wrapper for [`crate::uring::Error`].
+    Uring(uring::Error),
 }
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+#[derive(Debug, Deserialize, Clone)]
 /// Configuration for [`Udp`].
 pub struct Config {
     /// address -- IP plus port -- to bind to
     pub binding_addr: SocketAddr,
+    /// number of sockets to bind, each read by its own task. More than one
+    /// socket binds with `SO_REUSEPORT`.
+    #[serde(default = "default_sockets")]
+    pub sockets: NonZeroUsize,
+    /// the I/O backend sockets are read with
+    #[serde(default)]
+    pub backend: Backend,
+    /// persist a sample of received packets to disk, see
+    /// [`crate::blackhole::sample`]
+    pub sample: Option<sample::Config>,
+    /// alarm when the rate of datagram payload bytes received, summed over
+    /// both address families, leaves an expected range, see
+    /// [`crate::blackhole::rate`]
+    pub expected_rate: Option<rate::Config>,
+    /// count received bytes by the address they came from, see
+    /// [`crate::blackhole::source`]
+    pub sources: Option<source::Config>,
+    /// place this blackhole on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
 }
 
 #[derive(Debug)]
 /// The UDP blackhole.
 pub struct Udp {
     binding_addr: SocketAddr,
+    sockets: NonZeroUsize,
+    backend: Backend,
+    name: String,
+    sample: Option<sample::Config>,
+    sources: Option<Arc<Sources>>,
+    meter: Meter,
     shutdown: Shutdown,
 }
 
+/// Sum the `drops` column of the lines of a `/proc/net/udp` or
+/// `/proc/net/udp6` file whose socket inode is one of `inodes`. Lines that are
+/// not socket lines are skipped.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_drops(contents: &str, inodes: &[u64]) -> u64 {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let inode: u64 = fields.get(9)?.parse().ok()?;
+            let drops: u64 = fields.get(12)?.parse().ok()?;
+            inodes.contains(&inode).then(|| drops)
+        })
+        .sum()
+}
+
+/// The packets the kernel has dropped for the sockets with `inodes`, for want
+/// of receive buffer. A host without IPv6 has no `/proc/net/udp6`, its drops
+/// counted as none.
+#[cfg(target_os = "linux")]
+fn kernel_drops(inodes: &[u64]) -> Result<u64, io::Error> {
+    let mut drops = 0;
+    for path in ["/proc/net/udp", "/proc/net/udp6"] {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => drops += parse_drops(&contents, inodes),
+            Err(err) if err.kind() == io::ErrorKind::NotFound && path.ends_with('6') => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(drops)
+}
+
 impl Udp {
-    /// Create a new [`Udp`] server instance
+    /// Create a new [`Udp`] server instance, the blackhole `name`
     #[must_use]
-    pub fn new(config: &Config, shutdown: Shutdown) -> Self {
+    pub fn new(config: &Config, meter: Meter, name: &str, shutdown: Shutdown) -> Self {
         Self {
             binding_addr: config.binding_addr,
+            sockets: config.sockets,
+            backend: config.backend,
+            name: name.to_string(),
+            sample: config.sample.clone(),
+            sources: source::open(config.sources.as_ref(), name),
+            meter,
             shutdown,
         }
     }
 
+    /// Receive packets on `socket` until a shutdown signal is received.
+    async fn receive(
+        socket: UdpSocket,
+        sampler: Option<Arc<Sampler>>,
+        sources: Option<Arc<Sources>>,
+        meter: Meter,
+        mut shutdown: Shutdown,
+    ) -> Result<(), io::Error> {
+        let mut buf: Vec<u8> = vec![0; RECEIVE_BUFFER_BYTES];
+        let ipv4_labels = vec![address::label(Ipv4Addr::UNSPECIFIED.into())];
+        let ipv6_labels = vec![address::label(Ipv6Addr::UNSPECIFIED.into())];
+
+        loop {
+            tokio::select! {
+                packet = socket.recv_from(&mut buf) => {
+                    let (bytes, addr) = packet?;
+                    let labels = if address::family(addr.ip()) == "ipv4" {
+                        &ipv4_labels
+                    } else {
+                        &ipv6_labels
+                    };
+                    counter!("packet_received", 1, labels);
+                    counter!("bytes_received", bytes as u64, labels);
+                    meter.record(bytes as u64);
+                    if let Some(sampler) = &sampler {
+                        sampler.sample(&buf[..bytes]);
+                    }
+                    if let Some(sources) = &sources {
+                        sources.record(addr.ip(), bytes as u64);
+                    }
+                }
+                _ = shutdown.recv() => {
+                    return Ok(())
+                }
+            }
+        }
+    }
+
+    /// Receive packets on `socket` until a shutdown signal is received, with
+    /// the io_uring backend.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    async fn receive_uring(
+        socket: tokio_uring::net::UdpSocket,
+        sampler: Option<Arc<Sampler>>,
+        sources: Option<Arc<Sources>>,
+        meter: Meter,
+        mut shutdown: Shutdown,
+    ) -> Result<(), io::Error> {
+        // The buffer is owned by the ring while a receive is in flight. Its
+        // length is set to the bytes received.
+        let mut buf: Vec<u8> = Vec::with_capacity(RECEIVE_BUFFER_BYTES);
+        let ipv4_labels = vec![address::label(Ipv4Addr::UNSPECIFIED.into())];
+        let ipv6_labels = vec![address::label(Ipv6Addr::UNSPECIFIED.into())];
+
+        loop {
+            buf.clear();
+            tokio::select! {
+                (packet, returned) = socket.recv_from(buf) => {
+                    buf = returned;
+                    let (bytes, addr) = packet?;
+                    let labels = if address::family(addr.ip()) == "ipv4" {
+                        &ipv4_labels
+                    } else {
+                        &ipv6_labels
+                    };
+                    counter!("packet_received", 1, labels);
+                    counter!("bytes_received", bytes as u64, labels);
+                    meter.record(bytes as u64);
+                    if let Some(sampler) = &sampler {
+                        sampler.sample(&buf[..bytes]);
+                    }
+                    if let Some(sources) = &sources {
+                        sources.record(addr.ip(), bytes as u64);
+                    }
+                }
+                _ = shutdown.recv() => {
+                    return Ok(())
+                }
+            }
+        }
+    }
+
+    /// Bind the blackhole's sockets, with `SO_REUSEPORT` if there are several.
+    fn bind(&self) -> Result<Vec<std::net::UdpSocket>, Error> {
+        let reuse_port = self.sockets.get() > 1;
+        (0..self.sockets.get())
+            .map(|_| {
+                super::bind(self.binding_addr, SockType::Datagram, reuse_port).map_err(Error::Errno)
+            })
+            .collect()
+    }
+
+    /// Start receiving on `socket` with the configured backend, returning the
+    /// worker's outcome.
+    fn worker(
+        &self,
+        socket: std::net::UdpSocket,
+        sampler: Option<Arc<Sampler>>,
+    ) -> Result<BoxFuture<'static, Result<(), Error>>, Error> {
+        let sources = self.sources.clone();
+        let meter = self.meter.clone();
+        let shutdown = self.shutdown.clone();
+        match self.backend {
+            Backend::Epoll => {
+                socket.set_nonblocking(true).map_err(Error::Io)?;
+                let socket = UdpSocket::from_std(socket).map_err(Error::Io)?;
+                let handle = tokio::spawn(Self::receive(socket, sampler, sources, meter, shutdown));
+                Ok(async move {
+                    handle
+                        .await
+                        .map_err(|err| Error::Io(io::Error::new(io::ErrorKind::Other, err)))?
+                        .map_err(Error::Io)
+                }
+                .boxed())
+            }
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            Backend::IoUring => Ok(async move {
+                uring::run("udp-blackhole", move || {
+                    let socket = tokio_uring::net::UdpSocket::from_std(socket);
+                    Self::receive_uring(socket, sampler, sources, meter, shutdown)
+                })
+                .await
+                .map_err(Error::Uring)?
+                .map_err(Error::Io)
+            }
+            .boxed()),
+            #[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+            Backend::IoUring => Err(Error::Uring(uring::Error::Unsupported)),
+        }
+    }
+
     /// Run [`Udp`] to completion
     ///
     /// This function runs the UDP server forever, unless a shutdown signal is
@@ -47,22 +284,49 @@ impl Udp {
     ///
     /// # Errors
     ///
-    /// Function will return an error if receiving a packet fails.
+    /// Function will return an error if binding a socket or receiving a packet
+    /// fails, the sample file cannot be opened or the configured backend is not
+    /// available.
     ///
     /// # Panics
     ///
     /// None known.
     pub async fn run(mut self) -> Result<(), Error> {
-        let socket = UdpSocket::bind(&self.binding_addr)
+        let sockets = self.bind()?;
+        #[cfg(target_os = "linux")]
+        let inodes: Vec<u64> = {
+            use std::os::unix::io::AsRawFd;
+
+            sockets
+                .iter()
+                .map(|socket| nix::sys::stat::fstat(socket.as_raw_fd()).map(|stat| stat.st_ino))
+                .collect::<Result<_, _>>()
+                .map_err(Error::Errno)?
+        };
+        let sampler = sample::open(self.sample.as_ref(), &self.name, "udp", self.binding_addr)
             .await
             .map_err(Error::Io)?;
-        let mut buf: Vec<u8> = vec![0; 65536];
+
+        let mut workers: FuturesUnordered<_> = sockets
+            .into_iter()
+            .map(|socket| self.worker(socket, sampler.clone()))
+            .collect::<Result<_, _>>()?;
+        let mut drops_delay = time::interval(Duration::from_secs(1));
 
         loop {
             tokio::select! {
-                packet = socket.recv_from(&mut buf) => {
-                    counter!("packet_received", 1);
-                    packet.map_err(Error::Io)?;
+                _ = drops_delay.tick() => {
+                    #[cfg(target_os = "linux")]
+                    if let Ok(drops) = kernel_drops(&inodes) {
+                        metrics::gauge!(
+                            "kernel_packets_dropped",
+                            drops as f64,
+                            "blackhole" => self.name.clone()
+                        );
+                    }
+                }
+                Some(worker) = workers.next() => {
+                    worker?;
                 }
                 _ = self.shutdown.recv() => {
                     info!("shutdown signal received");
@@ -72,3 +336,37 @@ impl Udp {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use proptest::{collection, prelude::*};
+
+    use super::parse_drops;
+
+    const HEADER: &str = "   sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops\n";
+
+    // Drops are summed over exactly the sockets asked for.
+    proptest! {
+        #[test]
+        fn drops_of_inodes(sockets in collection::vec((any::<u32>(), any::<u32>(), any::<bool>()), 0..32)) {
+            let mut contents = HEADER.to_string();
+            for (idx, (inode, drops, _)) in sockets.iter().enumerate() {
+                contents.push_str(&format!(
+                    "{:>5}: 00000000:1F90 00000000:0000 07 00000000:00000000 00:00000000 00000000     0        0 {} 2 0000000000000000 {}\n",
+                    idx, inode, drops
+                ));
+            }
+            let inodes: Vec<u64> = sockets
+                .iter()
+                .filter(|(_, _, chosen)| *chosen)
+                .map(|(inode, _, _)| u64::from(*inode))
+                .collect();
+            let expected: u64 = sockets
+                .iter()
+                .filter(|(inode, _, _)| inodes.contains(&u64::from(*inode)))
+                .map(|(_, drops, _)| u64::from(*drops))
+                .sum();
+            prop_assert_eq!(parse_drops(&contents, &inodes), expected);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! The vsock speaking blackhole.
+//!
+//! Targets run inside a virtual machine may reach the host only over vsock,
+//! see [`crate::vsock`]. This blackhole listens on a vsock port and reads each
+//! connection to completion, discarding what it reads, as the TCP blackhole
+//! does over the network. Received bytes are labelled by the CID of the
+//! machine they came from.
+
+use std::io;
+
+use byte_unit::{Byte, ByteUnit};
+use metrics::counter;
+use serde::Deserialize;
+use tokio::io::unix::AsyncFd;
+use tracing::info;
+
+use super::{rate, Meter};
+use crate::{
+    numa,
+    signals::Shutdown,
+    vsock::{self, Socket},
+};
+
+fn default_cid() -> u32 {
+    vsock::CID_ANY
+}
+
+fn default_read_buffer_bytes() -> Byte {
+    Byte::from_unit(32.0, ByteUnit::KiB).unwrap()
+}
+
+#[derive(Debug)]
+/// Errors emitted by [`Vsock`]
+pub enum Error {
+    /// Wrapper for [`std::io::Error`].
+    Io(io::Error),
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration for [`Vsock`]
+pub struct Config {
+    /// the context identifier to bind to. Defaults to any, accepting
+    /// connections from every machine.
+    #[serde(default = "default_cid")]
+    pub cid: u32,
+    /// the vsock port to bind to
+    pub port: u32,
+    /// the size of the buffer each connection reads into, by default 32 KiB.
+    /// Every connection holds one, so raise it only for targets sending at
+    /// line rate over few connections.
+    #[serde(default = "default_read_buffer_bytes")]
+    read_buffer_bytes: Byte,
+    /// alarm when the rate of bytes read from vsock connections, summed over
+    /// every connection, leaves an expected range, see
+    /// [`crate::blackhole::rate`]
+    pub expected_rate: Option<rate::Config>,
+    /// place this blackhole on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+}
+
+#[derive(Debug)]
+/// The vsock blackhole.
+pub struct Vsock {
+    cid: u32,
+    port: u32,
+    buffer_bytes: usize,
+    meter: Meter,
+    shutdown: Shutdown,
+}
+
+/// Read `socket`, accepted from the machine `peer_cid`, to completion.
+async fn handle_connection(
+    socket: AsyncFd<Socket>,
+    peer_cid: u32,
+    buffer_bytes: usize,
+    meter: Meter,
+) {
+    let labels = vec![vsock::label(), ("cid".to_string(), peer_cid.to_string())];
+    let mut buf: Vec<u8> = vec![0; buffer_bytes.max(1)];
+    loop {
+        match vsock::read(&socket, &mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(bytes) => {
+                counter!("message_received", 1);
+                counter!("bytes_received", bytes as u64, &labels);
+                meter.record(bytes as u64);
+            }
+        }
+    }
+}
+
+impl Vsock {
+    /// Create a new [`Vsock`] server instance
+    #[must_use]
+    pub fn new(config: &Config, meter: Meter, shutdown: Shutdown) -> Self {
+        Self {
+            cid: config.cid,
+            port: config.port,
+            buffer_bytes: config.read_buffer_bytes.get_bytes() as usize,
+            meter,
+            shutdown,
+        }
+    }
+
+    /// Run [`Vsock`] to completion
+    ///
+    /// This function runs the vsock server forever, unless a shutdown signal
+    /// is received or an unrecoverable error is encountered.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the host does not support vsock or
+    /// binding to the assigned port fails.
+    ///
+    /// # Panics
+    ///
+    /// None known.
+    pub async fn run(mut self) -> Result<(), Error> {
+        let listener = vsock::listen(self.cid, self.port).map_err(Error::Io)?;
+        let labels = vec![vsock::label()];
+
+        loop {
+            tokio::select! {
+                conn = vsock::accept(&listener) => {
+                    let (socket, peer_cid) = conn.map_err(Error::Io)?;
+                    counter!("connection_accepted", 1, &labels);
+                    tokio::spawn(handle_connection(socket, peer_cid, self.buffer_bytes, self.meter.clone()));
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(())
+                }
+            }
+        }
+    }
+}
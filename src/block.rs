@@ -1,16 +1,30 @@
 use std::{
+    cell::RefCell,
+    collections::{hash_map::DefaultHasher, HashMap},
     convert::TryInto,
+    fs::{self, File},
+    hash::{Hash, Hasher},
+    io::{self, BufReader, BufWriter, Read, Write},
     num::{NonZeroU32, NonZeroUsize},
+    path::{Path, PathBuf},
+    sync::Mutex,
 };
 
-use metrics::gauge;
+use metrics::{counter, gauge};
+use nix::{
+    sys::mman,
+    unistd::{sysconf, SysconfVar},
+};
+use once_cell::sync::OnceCell;
 use rand::{prelude::SliceRandom, Rng};
+use tracing::{info, warn};
 
-use crate::payload::Serialize;
+use crate::payload::{EventLimit, Oversize, Serialize};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Error {
     Chunk(ChunkError),
+    Lock(nix::errno::Errno),
 }
 
 impl From<ChunkError> for Error {
@@ -24,6 +38,99 @@ pub(crate) struct Block {
     pub(crate) total_bytes: NonZeroU32,
     pub(crate) lines: u64,
     pub(crate) bytes: Vec<u8>,
+    /// Whether `bytes` is locked into RAM, see [`lock`].
+    locked: bool,
+}
+
+impl Block {
+    pub(crate) fn new(total_bytes: NonZeroU32, lines: u64, bytes: Vec<u8>) -> Self {
+        Self {
+            total_bytes,
+            lines,
+            bytes,
+            locked: false,
+        }
+    }
+}
+
+impl Drop for Block {
+    fn drop(&mut self) {
+        if self.locked {
+            // SAFETY: the range unlocked is exactly that locked by [`lock`].
+            // Should unlocking fail the pages stay locked until the process
+            // exits, there being nothing better to do.
+            let _res = unsafe { mman::munlock(self.bytes.as_ptr().cast(), self.bytes.len()) };
+        }
+    }
+}
+
+/// Summary statistics of a block cache, see [`Summary::emit`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Summary {
+    /// The total bytes of all blocks in the cache.
+    pub(crate) total_bytes: u64,
+    /// The number of blocks in the cache.
+    pub(crate) blocks: u64,
+    /// The total events -- newline delimited lines -- of all blocks in the
+    /// cache.
+    pub(crate) events: u64,
+}
+
+impl Summary {
+    pub(crate) fn new(block_cache: &[Block]) -> Self {
+        Self {
+            total_bytes: block_cache
+                .iter()
+                .map(|blk| u64::from(blk.total_bytes.get()))
+                .sum(),
+            blocks: block_cache.len() as u64,
+            events: block_cache.iter().map(|blk| blk.lines).sum(),
+        }
+    }
+
+    /// Mean events per block, zero if the cache is empty.
+    pub(crate) fn events_per_block(&self) -> f64 {
+        if self.blocks == 0 {
+            return 0.0;
+        }
+        self.events as f64 / self.blocks as f64
+    }
+
+    /// Mean bytes per event. `None` if the cache holds no newline delimited
+    /// events, as is the case for binary payloads.
+    pub(crate) fn bytes_per_event(&self) -> Option<f64> {
+        if self.events == 0 {
+            return None;
+        }
+        Some(self.total_bytes as f64 / self.events as f64)
+    }
+
+    /// Log this summary and emit it as gauges, allowing the user to check
+    /// their payload configuration produces the events they intended. The
+    /// gauges are labeled by the component the cache is built for, see
+    /// [`as_component`].
+    #[allow(clippy::ptr_arg)]
+    pub(crate) fn emit(&self, labels: &Vec<(String, String)>) {
+        let labels = component_labels(labels);
+        gauge!("block_cache_bytes", self.total_bytes as f64, &labels);
+        gauge!("block_cache_blocks", self.blocks as f64, &labels);
+        gauge!(
+            "block_cache_events_per_block",
+            self.events_per_block(),
+            &labels
+        );
+        if let Some(bytes_per_event) = self.bytes_per_event() {
+            gauge!("block_cache_bytes_per_event", bytes_per_event, &labels);
+        }
+        info!(
+            "block cache constructed: {} bytes in {} blocks, {:.2} events per block, {} bytes per event",
+            self.total_bytes,
+            self.blocks,
+            self.events_per_block(),
+            self.bytes_per_event()
+                .map_or_else(|| "unknown".to_string(), |bpe| format!("{:.2}", bpe)),
+        );
+    }
 }
 
 #[inline]
@@ -95,8 +202,198 @@ where
     Ok(chunks)
 }
 
+/// Identifies a block cache handoff file, see [`Handoff`].
+const HANDOFF_MAGIC: &[u8; 8] = b"LADINGBC";
+
+/// Block caches handed off between runs, see [`hand_off`].
+static HANDOFF: OnceCell<Handoff> = OnceCell::new();
+
+thread_local! {
+    /// The component building block caches on this thread, see
+    /// [`as_component`].
+    static COMPONENT: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Run `build`, the block caches it builds on this thread handed off as those
+/// of `component`, see [`hand_off`]. Components are thereby kept from loading
+/// one another's caches should their labels and block sizes match.
+pub(crate) fn as_component<F, T>(component: &str, build: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let previous = COMPONENT.with(|current| current.replace(Some(component.to_string())));
+    let output = build();
+    COMPONENT.with(|current| *current.borrow_mut() = previous);
+    output
+}
+
+/// `labels` with the component building block caches on this thread, if any,
+/// see [`as_component`].
 #[allow(clippy::ptr_arg)]
-#[allow(clippy::cast_precision_loss)]
+fn component_labels(labels: &Vec<(String, String)>) -> Vec<(String, String)> {
+    let mut labels = labels.clone();
+    if let Some(component) = COMPONENT.with(|current| current.borrow().clone()) {
+        labels.push(("component".to_string(), component));
+    }
+    labels
+}
+
+/// Hands block caches off between runs of one configuration.
+///
+/// Each cache a component builds is written to the handoff directory, named
+/// by the component, see [`as_component`], its labels and the order it was
+/// built in. A later run of the same configuration builds its caches in the
+/// same order and loads them rather than build them again. A file records the
+/// block sizes its cache was built for and is ignored, the cache built afresh,
+/// if they differ from those requested.
+#[derive(Debug)]
+struct Handoff {
+    dir: PathBuf,
+    /// The number of caches each component has built so far, by component
+    /// and labels.
+    built: Mutex<HashMap<String, usize>>,
+}
+
+impl Handoff {
+    /// The path of the next cache of the component labeled `labels`.
+    fn next_path(&self, labels: &[(String, String)]) -> PathBuf {
+        let mut component: Vec<String> = COMPONENT
+            .with(|current| current.borrow().clone())
+            .into_iter()
+            .collect();
+        component.extend(labels.iter().map(|(k, v)| format!("{}={}", k, v)));
+        let component = component.join(",");
+        let mut built = self.built.lock().unwrap();
+        let idx = built.entry(component.clone()).or_insert(0);
+        let mut hasher = DefaultHasher::new();
+        component.hash(&mut hasher);
+        idx.hash(&mut hasher);
+        *idx += 1;
+        self.dir.join(format!("{:016x}.blocks", hasher.finish()))
+    }
+}
+
+/// Hand block caches off between runs through `dir`: load each cache from
+/// there rather than build it, and write each cache built there. Only the
+/// first call has effect.
+///
+/// # Errors
+///
+/// Function will return an error if `dir` cannot be created.
+pub(crate) fn hand_off(dir: PathBuf) -> Result<(), io::Error> {
+    fs::create_dir_all(&dir)?;
+    let _ = HANDOFF.set(Handoff {
+        dir,
+        built: Mutex::new(HashMap::new()),
+    });
+    Ok(())
+}
+
+/// Write `block_cache`, built for `block_chunks`, to `writer`.
+fn write_handoff<W>(
+    block_chunks: &[usize],
+    block_cache: &[Block],
+    mut writer: W,
+) -> Result<(), io::Error>
+where
+    W: Write,
+{
+    writer.write_all(HANDOFF_MAGIC)?;
+    writer.write_all(&(block_chunks.len() as u64).to_le_bytes())?;
+    for chunk in block_chunks {
+        writer.write_all(&(*chunk as u64).to_le_bytes())?;
+    }
+    writer.write_all(&(block_cache.len() as u64).to_le_bytes())?;
+    for block in block_cache {
+        writer.write_all(&block.total_bytes.get().to_le_bytes())?;
+        writer.write_all(&block.lines.to_le_bytes())?;
+        writer.write_all(&block.bytes)?;
+    }
+    writer.flush()
+}
+
+fn read_u64<R>(reader: &mut R) -> Result<u64, io::Error>
+where
+    R: Read,
+{
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Read a block cache from `reader`, if it was built for `block_chunks`.
+fn read_handoff<R>(block_chunks: &[usize], mut reader: R) -> Result<Option<Vec<Block>>, io::Error>
+where
+    R: Read,
+{
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed block cache");
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != HANDOFF_MAGIC {
+        return Err(invalid());
+    }
+    let chunks = read_u64(&mut reader)?;
+    if chunks != block_chunks.len() as u64 {
+        return Ok(None);
+    }
+    for chunk in block_chunks {
+        if read_u64(&mut reader)? != *chunk as u64 {
+            return Ok(None);
+        }
+    }
+    // Blocks are built in the order of their chunks, chunks that serialize to
+    // nothing skipped, and none is larger than its chunk. Lengths are checked
+    // against the chunks before anything is allocated for them.
+    let blocks = read_u64(&mut reader)?;
+    if blocks > block_chunks.len() as u64 {
+        return Err(invalid());
+    }
+    let mut chunks = block_chunks.iter();
+    let mut block_cache = Vec::with_capacity(block_chunks.len());
+    for _ in 0..blocks {
+        let mut total_bytes = [0; 4];
+        reader.read_exact(&mut total_bytes)?;
+        let total_bytes = NonZeroU32::new(u32::from_le_bytes(total_bytes)).ok_or_else(invalid)?;
+        if !chunks.any(|chunk| total_bytes.get() as usize <= *chunk) {
+            return Err(invalid());
+        }
+        let lines = read_u64(&mut reader)?;
+        let mut bytes = vec![0; total_bytes.get() as usize];
+        reader.read_exact(&mut bytes)?;
+        block_cache.push(Block::new(total_bytes, lines, bytes));
+    }
+    if block_cache.is_empty() {
+        return Err(invalid());
+    }
+    Ok(Some(block_cache))
+}
+
+/// Load the block cache at `path` built for `block_chunks`, if any.
+fn load_handoff(path: &Path, block_chunks: &[usize]) -> Option<Vec<Block>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return None,
+        Err(err) => {
+            warn!("could not open block cache {}: {}", path.display(), err);
+            return None;
+        }
+    };
+    match read_handoff(block_chunks, BufReader::new(file)) {
+        Ok(Some(block_cache)) => Some(block_cache),
+        Ok(None) => {
+            info!(
+                "block cache {} was built for other block sizes, rebuilding",
+                path.display()
+            );
+            None
+        }
+        Err(err) => {
+            warn!("could not read block cache {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
 /// Construct a new block cache of form defined by `serializer`.
 ///
 /// A "block cache" is a pre-made vec of serialized arbitrary instances of the
@@ -105,26 +402,51 @@ where
 /// of time. We vary the size of blocks -- via `block_chunks` -- to allow the
 /// user to express a range of block sizes they wish to see.
 ///
+/// If `event_limit` is set each block's events are held to it, counting
+/// oversize events in `events_truncated` or `events_split`.
+///
+/// If block caches are handed off between runs, see [`hand_off`], the cache is
+/// loaded rather than built if an earlier run built it.
+///
 /// # Panics
 ///
 /// Function will panic if the `serializer` signals an error. In the futures we
 /// would like to propagate this error to the caller.
+#[allow(clippy::ptr_arg)]
+#[allow(clippy::cast_precision_loss)]
 pub(crate) fn construct_block_cache<R, S>(
     mut rng: R,
     serializer: &S,
     block_chunks: &[usize],
+    event_limit: Option<EventLimit>,
     labels: &Vec<(String, String)>,
 ) -> Vec<Block>
 where
     S: Serialize,
     R: Rng,
 {
+    let handoff_path = HANDOFF.get().map(|handoff| handoff.next_path(labels));
+    if let Some(ref path) = handoff_path {
+        if let Some(block_cache) = load_handoff(path, block_chunks) {
+            info!("block cache loaded from {}", path.display());
+            Summary::new(&block_cache).emit(labels);
+            gauge!("block_construction_complete", 1.0, labels);
+            return block_cache;
+        }
+    }
+
     let mut block_cache: Vec<Block> = Vec::with_capacity(block_chunks.len());
+    let mut oversize_events = 0;
     for block_size in block_chunks {
         let mut block: Vec<u8> = Vec::with_capacity(*block_size);
         serializer
             .to_bytes(&mut rng, *block_size, &mut block)
             .unwrap();
+        if let Some(limit) = event_limit {
+            let mut limited: Vec<u8> = Vec::with_capacity(block.len());
+            oversize_events += limit.apply(&block, *block_size, &mut limited);
+            block = limited;
+        }
         block.shrink_to_fit();
         if block.is_empty() {
             // Blocks may be empty, especially when the amount of bytes
@@ -138,25 +460,78 @@ where
         }
         let total_bytes = NonZeroU32::new(block.len().try_into().unwrap()).unwrap();
         let newlines = total_newlines(&block);
-        block_cache.push(Block {
-            total_bytes,
-            lines: newlines,
-            bytes: block,
-        });
+        block_cache.push(Block::new(total_bytes, newlines, block));
     }
     assert!(!block_cache.is_empty());
+    if let Some(limit) = event_limit {
+        match limit.oversize {
+            Oversize::Truncate => counter!("events_truncated", oversize_events, labels),
+            Oversize::Split => counter!("events_split", oversize_events, labels),
+        }
+    }
+    if let Some(path) = handoff_path {
+        let written = File::create(&path)
+            .and_then(|file| write_handoff(block_chunks, &block_cache, BufWriter::new(file)));
+        if let Err(err) = written {
+            warn!("could not write block cache {}: {}", path.display(), err);
+        }
+    }
+    Summary::new(&block_cache).emit(labels);
     gauge!("block_construction_complete", 1.0, labels);
     block_cache
 }
 
+/// Lock the memory of `block_cache` into RAM and touch each of its pages, so
+/// that neither page faults nor swap add jitter once the run starts.
+///
+/// Each block is unlocked as it is dropped, and so must not have its bytes
+/// replaced once locked. The memory a process may lock is limited by
+/// `RLIMIT_MEMLOCK`, see mlock(2). The bytes locked are recorded as
+/// `block_cache_locked_bytes`, labeled by the component the cache is built
+/// for, see [`as_component`].
+///
+/// # Errors
+///
+/// Function will return an error if the cache cannot be locked. Blocks locked
+/// before the failure stay locked until dropped.
+#[allow(clippy::ptr_arg)]
+pub(crate) fn lock(block_cache: &mut [Block], labels: &Vec<(String, String)>) -> Result<(), Error> {
+    let page_size = sysconf(SysconfVar::PAGE_SIZE)
+        .ok()
+        .flatten()
+        .and_then(|size| usize::try_from(size).ok())
+        .unwrap_or(4096);
+    let mut locked_bytes: u64 = 0;
+    for block in block_cache {
+        let bytes = &block.bytes;
+        // SAFETY: the range locked is exactly that of `bytes`.
+        unsafe { mman::mlock(bytes.as_ptr().cast(), bytes.len()) }.map_err(Error::Lock)?;
+        block.locked = true;
+        for offset in (0..bytes.len()).step_by(page_size) {
+            // SAFETY: `offset` is in bounds of `bytes`. The read is volatile
+            // so that it is not elided.
+            unsafe { std::ptr::read_volatile(bytes.as_ptr().add(offset)) };
+        }
+        locked_bytes += bytes.len() as u64;
+    }
+    gauge!(
+        "block_cache_locked_bytes",
+        locked_bytes as f64,
+        &component_labels(labels)
+    );
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
-    use std::num::NonZeroUsize;
+    use std::num::{NonZeroU32, NonZeroUsize};
 
     use proptest::{collection, prelude::*};
     use rand::{rngs::SmallRng, SeedableRng};
 
-    use crate::block::{chunk_bytes, ChunkError, Error};
+    use crate::block::{
+        chunk_bytes, read_handoff, write_handoff, Block, ChunkError, Error, Summary,
+    };
 
     /// Construct our block_bytes_sizes vector and the total_bytes value. We are
     /// careful to never generate an empty vector nor a total_bytes that is less
@@ -204,4 +579,95 @@ mod test {
             prop_assert_eq!(Err(Error::Chunk(ChunkError::EmptyBlockBytes)), chunk_bytes(&mut rng, total_bytes, &[]));
         }
     }
+
+    // A summary's bytes per event, multiplied back out, recovers the cache's
+    // total bytes.
+    proptest! {
+        #[test]
+        fn summary_bytes_per_event_recovers_total(sizes in collection::vec((1..u32::MAX, 1..1_000_u64), 1..100)) {
+            let block_cache: Vec<Block> = sizes
+                .into_iter()
+                .map(|(total_bytes, lines)| Block::new(
+                    NonZeroU32::new(total_bytes).unwrap(),
+                    lines,
+                    vec![],
+                ))
+                .collect();
+            let summary = Summary::new(&block_cache);
+            prop_assert_eq!(summary.blocks, block_cache.len() as u64);
+            let bytes_per_event = summary.bytes_per_event().unwrap();
+            let recovered = bytes_per_event * summary.events as f64;
+            prop_assert!((recovered - summary.total_bytes as f64).abs() <= summary.total_bytes as f64 * 1e-9);
+        }
+    }
+
+    // A handed off block cache is read back as written, and not at all for
+    // other block sizes.
+    proptest! {
+        #[test]
+        fn handoff_round_trip(
+            blocks in collection::vec((collection::vec(any::<u8>(), 1..256), any::<u64>()), 1..16),
+            other_chunk in 1..1_000_usize,
+        ) {
+            let block_cache: Vec<Block> = blocks
+                .into_iter()
+                .map(|(bytes, lines)| Block::new(
+                    NonZeroU32::new(bytes.len() as u32).unwrap(),
+                    lines,
+                    bytes,
+                ))
+                .collect();
+            let block_chunks: Vec<usize> = block_cache.iter().map(|blk| blk.bytes.len()).collect();
+            let mut buffer = Vec::new();
+            write_handoff(&block_chunks, &block_cache, &mut buffer).unwrap();
+
+            let read = read_handoff(&block_chunks, buffer.as_slice()).unwrap().unwrap();
+            prop_assert_eq!(read.len(), block_cache.len());
+            for (read, written) in read.iter().zip(block_cache.iter()) {
+                prop_assert_eq!(read.total_bytes, written.total_bytes);
+                prop_assert_eq!(read.lines, written.lines);
+                prop_assert_eq!(&read.bytes, &written.bytes);
+            }
+
+            let mut other_chunks = block_chunks.clone();
+            other_chunks.push(other_chunk);
+            prop_assert!(read_handoff(&other_chunks, buffer.as_slice()).unwrap().is_none());
+        }
+    }
+
+    // A handed off block cache claiming more blocks than it has chunks, or a
+    // block larger than its chunk, is malformed.
+    proptest! {
+        #[test]
+        fn handoff_bounded_by_chunks(
+            chunks in collection::vec(1..256_usize, 1..16),
+            extra_blocks in 1..u64::from(u32::MAX),
+            oversize in 1..u32::MAX,
+        ) {
+            let block_cache: Vec<Block> = chunks
+                .iter()
+                .map(|chunk| Block::new(NonZeroU32::new(*chunk as u32).unwrap(), 0, vec![0; *chunk]))
+                .collect();
+            let mut buffer = Vec::new();
+            write_handoff(&chunks, &block_cache, &mut buffer).unwrap();
+            let blocks_at = 8 + 8 * (chunks.len() + 1);
+
+            let mut too_many = buffer.clone();
+            too_many[blocks_at..blocks_at + 8]
+                .copy_from_slice(&(chunks.len() as u64 + extra_blocks).to_le_bytes());
+            prop_assert_eq!(
+                read_handoff(&chunks, too_many.as_slice()).unwrap_err().kind(),
+                std::io::ErrorKind::InvalidData
+            );
+
+            let largest = *chunks.iter().max().unwrap() as u32;
+            let mut too_large = buffer;
+            too_large[blocks_at + 8..blocks_at + 12]
+                .copy_from_slice(&largest.saturating_add(oversize).to_le_bytes());
+            prop_assert_eq!(
+                read_handoff(&chunks, too_large.as_slice()).unwrap_err().kind(),
+                std::io::ErrorKind::InvalidData
+            );
+        }
+    }
 }
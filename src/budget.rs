@@ -0,0 +1,298 @@
+//! Hold the target to a resource budget
+//!
+//! A resource regression in the target -- it needs more memory, or burns more
+//! CPU, for the same load -- should fail a run as surely as a crash does. The
+//! budget samples the target's resident memory and CPU time, see
+//! [`crate::observer`], once a second during the experiment stage, the steady
+//! state after warmup. Once load stops the peak resident memory and the mean
+//! CPU utilization are judged against the configured budget and every
+//! violation returned, for lading to report and exit non-zero on.
+
+use std::fmt;
+
+use serde::Deserialize;
+use tokio::{
+    sync::watch,
+    time::{self, Duration, Instant},
+};
+use tracing::{info, warn};
+
+use crate::{antagonist::Stage, observer, signals::Shutdown};
+
+/// Bytes in a mebibyte.
+const MIB: f64 = 1_048_576.0;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+/// Configuration for [`Server`]
+pub struct Config {
+    /// The resident memory, in MiB, the target must stay at or under
+    pub rss_mib: Option<u64>,
+    /// The CPU utilization, in percent of one core, the target's mean must
+    /// stay at or under
+    pub cpu_percent: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A budget the target exceeded in the steady state.
+pub enum Violation {
+    /// The target's resident memory exceeded its budget.
+    Rss {
+        /// The peak resident memory of the target in MiB
+        peak_mib: f64,
+        /// The budget in MiB
+        budget_mib: u64,
+    },
+    /// The target's mean CPU utilization exceeded its budget.
+    Cpu {
+        /// The mean CPU utilization of the target in percent of one core
+        mean_percent: f64,
+        /// The budget in percent of one core
+        budget_percent: f64,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Violation::Rss {
+                peak_mib,
+                budget_mib,
+            } => write!(
+                f,
+                "peak RSS of {:.1} MiB exceeds the budget of {} MiB",
+                peak_mib, budget_mib
+            ),
+            Violation::Cpu {
+                mean_percent,
+                budget_percent,
+            } => write!(
+                f,
+                "mean CPU of {:.1}% exceeds the budget of {:.1}%",
+                mean_percent, budget_percent
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// The CPU seconds one target process had consumed when first and latest
+/// sampled.
+struct ProcessCpu {
+    pid: u32,
+    first_cpu_seconds: f64,
+    last_cpu_seconds: f64,
+}
+
+#[derive(Debug, Default)]
+/// The target's resource usage over the steady state.
+struct Usage {
+    peak_rss_bytes: u64,
+    /// The CPU seconds of each process the target ran as, restarted by
+    /// [`crate::chaos`], in order.
+    processes: Vec<ProcessCpu>,
+    /// The time of the first and latest samples.
+    first_at: Option<Duration>,
+    last_at: Option<Duration>,
+}
+
+impl Usage {
+    /// Observe the total CPU seconds and resident memory of the target, `pid`,
+    /// `at` some time since an arbitrary, fixed start.
+    fn observe(&mut self, pid: u32, cpu_seconds: f64, rss_bytes: u64, at: Duration) {
+        self.peak_rss_bytes = self.peak_rss_bytes.max(rss_bytes);
+        match self.processes.last_mut() {
+            Some(process) if process.pid == pid => process.last_cpu_seconds = cpu_seconds,
+            latest => {
+                // A restarted target started in the steady state, all the CPU
+                // time it has consumed was consumed in it.
+                let first_cpu_seconds = if latest.is_some() { 0.0 } else { cpu_seconds };
+                self.processes.push(ProcessCpu {
+                    pid,
+                    first_cpu_seconds,
+                    last_cpu_seconds: cpu_seconds,
+                });
+            }
+        }
+        self.first_at.get_or_insert(at);
+        self.last_at = Some(at);
+    }
+
+    /// The mean CPU utilization between the first and latest samples, in
+    /// percent of one core, if they are apart. The CPU time of each process
+    /// the target ran as is summed.
+    fn mean_cpu_percent(&self) -> Option<f64> {
+        let elapsed = self.last_at?.checked_sub(self.first_at?)?;
+        if elapsed.is_zero() {
+            return None;
+        }
+        let cpu_seconds: f64 = self
+            .processes
+            .iter()
+            .map(|process| process.last_cpu_seconds - process.first_cpu_seconds)
+            .sum();
+        Some(cpu_seconds / elapsed.as_secs_f64() * 100.0)
+    }
+
+    /// Judge the usage against `config`, returning every budget exceeded.
+    fn violations(&self, config: &Config) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        if let Some(budget_mib) = config.rss_mib {
+            let peak_mib = self.peak_rss_bytes as f64 / MIB;
+            if peak_mib > budget_mib as f64 {
+                violations.push(Violation::Rss {
+                    peak_mib,
+                    budget_mib,
+                });
+            }
+        }
+        if let (Some(budget_percent), Some(mean_percent)) =
+            (config.cpu_percent, self.mean_cpu_percent())
+        {
+            if mean_percent > budget_percent {
+                violations.push(Violation::Cpu {
+                    mean_percent,
+                    budget_percent,
+                });
+            }
+        }
+        violations
+    }
+}
+
+#[derive(Debug)]
+/// The target resource budget.
+///
+/// Samples the target once a second while the experiment is in its steady
+/// state, judging the samples once shut down.
+pub struct Server {
+    config: Config,
+    stage: watch::Receiver<Stage>,
+    shutdown: Shutdown,
+}
+
+impl Server {
+    /// Create a new [`Server`] instance
+    #[must_use]
+    pub fn new(config: Config, stage: watch::Receiver<Stage>, shutdown: Shutdown) -> Self {
+        Self {
+            config,
+            stage,
+            shutdown,
+        }
+    }
+
+    /// Run this [`Server`] to completion
+    ///
+    /// This function samples the target until a shutdown signal is received,
+    /// returning the budgets the target exceeded. A target never observed in
+    /// the steady state is not judged.
+    pub async fn run(mut self) -> Vec<Violation> {
+        let mut usage = Usage::default();
+        let mut sample_delay = time::interval(Duration::from_secs(1));
+        let start = Instant::now();
+
+        loop {
+            tokio::select! {
+                _ = sample_delay.tick() => {
+                    if *self.stage.borrow() != Stage::Experiment {
+                        continue;
+                    }
+                    if let (Some((pid, cpu_seconds)), Some(rss_bytes)) =
+                        (observer::target_cpu(), observer::target_rss_bytes())
+                    {
+                        usage.observe(pid, cpu_seconds, rss_bytes, start.elapsed());
+                    }
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    break;
+                }
+            }
+        }
+        if usage.last_at.is_none() {
+            warn!("target was not observed in the steady state, budget not judged");
+            return Vec::new();
+        }
+        usage.violations(&self.config)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use tokio::time::Duration;
+
+    use super::{Config, Usage, Violation};
+
+    // A target consuming CPU at a constant rate, and whose memory peaks once,
+    // violates each budget exactly when its usage exceeds it.
+    proptest! {
+        #[test]
+        fn constant_usage_judged(
+            cpu_percent in 0_u32..800,
+            budget_percent in 1_u32..800,
+            peak_mib in 1_u64..4096,
+            budget_mib in 1_u64..4096,
+            samples in 2_u64..120,
+        ) {
+            // Equal usage and budget are not exact in floating point.
+            prop_assume!(cpu_percent != budget_percent);
+            let mut usage = Usage::default();
+            for sample in 0..samples {
+                let rss_bytes = if sample == samples / 2 { peak_mib << 20 } else { 0 };
+                let cpu_seconds = f64::from(cpu_percent) / 100.0 * sample as f64;
+                usage.observe(1, cpu_seconds, rss_bytes, Duration::from_secs(sample));
+            }
+            let violations = usage.violations(&Config {
+                rss_mib: Some(budget_mib),
+                cpu_percent: Some(f64::from(budget_percent)),
+            });
+            prop_assert_eq!(
+                violations.iter().any(|v| matches!(v, Violation::Rss { .. })),
+                peak_mib > budget_mib
+            );
+            prop_assert_eq!(
+                violations.iter().any(|v| matches!(v, Violation::Cpu { .. })),
+                cpu_percent > budget_percent
+            );
+        }
+    }
+
+    // A target consuming CPU at a constant rate is judged the same however
+    // often it is restarted, each restarted process's CPU time starting from
+    // zero. The target is restarted just after it is sampled, so that none of
+    // its time goes unobserved.
+    proptest! {
+        #[test]
+        fn restarted_usage_judged(
+            cpu_percent in 0_u32..800,
+            budget_percent in 1_u32..800,
+            samples in 2_u64..120,
+            restart_every in 1_u64..30,
+            initial_cpu_seconds in 0_u32..10_000,
+        ) {
+            prop_assume!(cpu_percent != budget_percent);
+            let rate = f64::from(cpu_percent) / 100.0;
+            let mut usage = Usage::default();
+            let mut pid = 1;
+            let mut started = 0;
+            let mut initial = f64::from(initial_cpu_seconds);
+            for sample in 0..samples {
+                if sample > 0 && sample % restart_every == 0 {
+                    pid += 1;
+                    started = sample - 1;
+                    initial = 0.0;
+                }
+                let cpu_seconds = initial + rate * (sample - started) as f64;
+                usage.observe(pid, cpu_seconds, 0, Duration::from_secs(sample));
+            }
+            let mean_percent = usage.mean_cpu_percent().unwrap();
+            prop_assert!((mean_percent - f64::from(cpu_percent)).abs() < 1e-6);
+            let violations = usage.violations(&Config {
+                rss_mib: None,
+                cpu_percent: Some(f64::from(budget_percent)),
+            });
+            prop_assert_eq!(!violations.is_empty(), cpu_percent > budget_percent);
+        }
+    }
+}
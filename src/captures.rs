@@ -6,28 +6,70 @@
 //! that the generator, blackhole etc code are unaware of anything other than
 //! their [`metrics`] integration while [`CaptureManager`] need only hook into
 //! that same crate.
+//!
+//! Metrics are flushed once every capture interval, by default a second and
+//! no less than [`MINIMUM_INTERVAL`]. Each counter line carries the counter's
+//! cumulative value and its `delta` since the previous flush, so that rates
+//! are unambiguous downstream however often lines are written. Counter and
+//! gauge lines also carry the time their metric was `updated`: the time of
+//! the flush closing the capture interval it was last updated in, so that a
+//! metric gone quiet is told apart from one still moving. Updates only note
+//! the interval underway, they never read the clock.
+//!
+//! Counters are 64 bit and wrap to zero on overflow, their `delta` correct
+//! across a wrap. A counter set to less than its value -- a source that counts
+//! for itself has restarted and counts again from zero -- is reset: its line
+//! carries `reset` and its `delta` is its new value, so that analysis does not
+//! mistake the fall for negative traffic. Counters are held here, not by the
+//! components incrementing them, so a component restarted by
+//! [`crate::supervisor`] continues its counters where the failed instance left
+//! off, the restart recorded as `component_restart`.
+//!
+//! Discrete events -- the run entering a phase, a chaos action, an operator's
+//! note -- are recorded with [`annotate`] and written as [`Annotation`] lines
+//! alongside the metrics, so that plots may mark what happened when.
+//!
+//! For multi-day runs the capture file may be segmented, see [`Soak`].
+//!
+//! Capture files are written to survive lading being killed hard. Each line
+//! carries a `crc32` checksum of itself and is written to a `.partial` file,
+//! renamed into place only once complete. A torn write is then limited to the
+//! final line of a `.partial` file and is detected, and skipped, by
+//! [`recover`].
 
 use std::{
     borrow::Cow,
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     ffi::OsStr,
-    io,
-    path::PathBuf,
-    sync::{atomic::Ordering, Arc},
+    io::{self, BufRead},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     time::{SystemTime, UNIX_EPOCH},
 };
 
-use metrics_util::registry::{AtomicStorage, Registry};
-use serde::Serialize;
+use flate2::{write::GzEncoder, Compression, Crc};
+use metrics::{CounterFn, GaugeFn};
+use metrics_util::{
+    registry::{Registry, Storage},
+    AtomicBucket,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 use tokio::{
-    fs::File,
+    fs::{self, File},
     io::{AsyncWriteExt, BufWriter},
-    time::{self, Duration},
+    task,
+    time::{self, Duration, Instant},
 };
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 
-use crate::signals::Shutdown;
+use crate::{
+    config::Experiment, signals::Shutdown, telemetry::CardinalityLimit, throttle::Calibration,
+};
 
 #[derive(Debug, Serialize, Clone, Copy)]
 #[serde(rename_all = "snake_case")]
@@ -37,6 +79,9 @@ pub enum MetricKind {
     Counter,
     /// A point-at-time value.
     Gauge,
+    /// A quantile of the values observed since the last flush. The quantile is
+    /// recorded in the `quantile` label.
+    Histogram,
 }
 
 #[derive(Debug, Serialize, Clone, Copy)]
@@ -49,6 +94,11 @@ pub enum LineValue {
     Float(f64),
 }
 
+/// The fields of a capture [`Line`] that change from one sample of a series
+/// to the next. The remaining fields, the run, metric and labels, identify the
+/// series.
+pub const SAMPLE_FIELDS: [&str; 6] = ["time", "updated", "fetch_index", "value", "delta", "reset"];
+
 #[derive(Debug, Serialize)]
 /// The structure of a capture file line.
 pub struct Line<'a> {
@@ -58,6 +108,11 @@ pub struct Line<'a> {
     pub run_id: Cow<'a, Uuid>,
     /// The time in milliseconds that this line was written.
     pub time: u128,
+    /// The time in milliseconds of the flush closing the capture interval the
+    /// metric was last updated in, at or before `time`. Absent for histograms
+    /// and for metrics never updated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated: Option<u128>,
     /// The "fetch index". Previous versions of lading scraped prometheus
     /// metrics from their targets and kept an increment index of polls. Now
     /// this records the number of times the internal metrics cache has been
@@ -69,13 +124,303 @@ pub struct Line<'a> {
     pub metric_kind: MetricKind,
     /// The value of the metric on this line.
     pub value: LineValue,
+    /// For counters, the increase of the value since the previous flush. The
+    /// first flush of a counter records its value entire.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta: Option<u64>,
+    /// For counters, whether the counter was reset since the previous flush.
+    /// The `delta` of a reset counter is its value since the reset.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub reset: bool,
     #[serde(flatten)]
     /// The labels associated with this metric.
     pub labels: HashMap<String, String>,
 }
 
+#[derive(Debug, Serialize)]
+/// The structure of a capture file line recording an event, see [`annotate`].
+/// Distinguished from a [`Line`] by its `annotation` field.
+pub struct Annotation<'a> {
+    #[serde(borrow)]
+    /// An id that is mostly unique to this run, see [`Line`].
+    pub run_id: Cow<'a, Uuid>,
+    /// The time in milliseconds that the event happened.
+    pub time: u128,
+    /// The fetch index of the flush that wrote this line, see [`Line`].
+    pub fetch_index: u64,
+    /// The kind of event, `phase` or `chaos` say.
+    pub annotation: String,
+    /// What happened.
+    pub message: String,
+}
+
+/// Events recorded by [`annotate`] since the previous flush, each its time in
+/// milliseconds, kind and message.
+static PENDING_ANNOTATIONS: Lazy<Mutex<Vec<(u64, String, String)>>> =
+    Lazy::new(|| Mutex::new(Vec::new()));
+
+/// The most events held for one flush, see [`annotate`].
+const MAXIMUM_PENDING_ANNOTATIONS: usize = 1_024;
+
+/// Whether a [`CaptureManager`] is installed to flush annotations.
+static ANNOTATING: AtomicBool = AtomicBool::new(false);
+
+/// Record an event of kind `annotation` in the capture file, as an
+/// [`Annotation`] line written with the next flush.
+///
+/// Without an installed [`CaptureManager`] nothing would ever flush the
+/// event, and it is dropped. No more than [`MAXIMUM_PENDING_ANNOTATIONS`]
+/// events are held between flushes, those beyond counted as
+/// `annotations_dropped`.
+pub fn annotate<K, M>(annotation: K, message: M)
+where
+    K: Into<String>,
+    M: Into<String>,
+{
+    if !ANNOTATING.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut pending = PENDING_ANNOTATIONS.lock().unwrap();
+    if pending.len() >= MAXIMUM_PENDING_ANNOTATIONS {
+        drop(pending);
+        metrics::counter!("annotations_dropped", 1);
+        return;
+    }
+    pending.push((now_millis(), annotation.into(), message.into()));
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+/// Configuration for soak mode, intended for unattended multi-day runs.
+///
+/// In soak mode the capture file is split into segments, each suffixed with
+/// its index. Finished segments may be compressed and the oldest pruned so
+/// that a long run does not exhaust disk. On every new segment a snapshot of
+/// the run's progress is written alongside the capture file.
+pub struct Soak {
+    /// The duration of each capture segment, in seconds.
+    pub segment_seconds: u64,
+    /// Whether to gzip compress finished segments.
+    #[serde(default)]
+    pub compress: bool,
+    /// The maximum number of finished segments to keep, the oldest pruned
+    /// first. If unset no segment is pruned.
+    #[serde(default)]
+    pub maximum_segments: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+/// Identifies the run and experiment a capture file belongs to. Written beside
+/// the capture file as it is started.
+struct Header<'a> {
+    run_id: &'a Uuid,
+    time: u128,
+    experiment_id: Option<&'a str>,
+    experiment_labels: &'a HashMap<String, String>,
+    throttle_calibration: Option<&'a Calibration>,
+}
+
+#[derive(Debug, Serialize)]
+/// A summary of a soak run's progress, see [`Soak`].
+struct Snapshot<'a> {
+    run_id: &'a Uuid,
+    experiment_id: Option<&'a str>,
+    experiment_labels: &'a HashMap<String, String>,
+    time: u128,
+    fetch_index: u64,
+    lines_written: u64,
+    segments: &'a VecDeque<PathBuf>,
+    current_segment: &'a Path,
+}
+
+/// The shortest capture interval, see [`CaptureManager::new`].
+pub const MINIMUM_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The default capture interval, in milliseconds.
+#[must_use]
+pub fn default_interval_milliseconds() -> u64 {
+    1000
+}
+
+/// The quantiles recorded for each histogram on every flush.
+const HISTOGRAM_QUANTILES: [f64; 5] = [0.0, 0.5, 0.9, 0.99, 1.0];
+
+/// Return the `quantile` of `sorted`, which must be sorted and non-empty.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+fn quantile_of(sorted: &[f64], quantile: f64) -> f64 {
+    let idx = ((sorted.len() - 1) as f64 * quantile).round() as usize;
+    sorted[idx]
+}
+
+/// Return the time in milliseconds since the epoch, 0 if the clock is set
+/// before it.
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| {
+            u64::try_from(since.as_millis()).unwrap_or(u64::MAX)
+        })
+}
+
+/// The capture interval underway, counted from one so that zero marks a
+/// metric never updated. Advanced by each flush.
+static INTERVAL: AtomicU64 = AtomicU64::new(1);
+
+/// Return the capture interval underway.
+fn interval() -> u64 {
+    INTERVAL.load(Ordering::Relaxed)
+}
+
+/// Return `updated`, the time the metric `key` was last updated, as recorded
+/// in [`Line`], given the interval it was last `updated` in. A metric updated
+/// in the interval `closed` at `now_ms`, or after, is stamped now, others keep
+/// their stamp in `stamps`. A metric never updated has no such time.
+fn updated_at(
+    stamps: &mut HashMap<metrics::Key, u128>,
+    key: &metrics::Key,
+    updated: &AtomicU64,
+    closed: u64,
+    now_ms: u128,
+) -> Option<u128> {
+    match updated.load(Ordering::Relaxed) {
+        0 => None,
+        interval if interval >= closed => {
+            match stamps.get_mut(key) {
+                Some(stamp) => *stamp = now_ms,
+                None => {
+                    stamps.insert(key.clone(), now_ms);
+                }
+            }
+            Some(now_ms)
+        }
+        _ => stamps.get(key).copied(),
+    }
+}
+
+/// Return the increase of a counter from `previous` to `current`, each its
+/// value and the number of times it had been reset, and whether it was reset
+/// in between. A counter that wrapped increased by the distance wrapped.
+fn counter_delta(previous: (u64, u64), current: (u64, u64)) -> (u64, bool) {
+    let (previous_value, previous_resets) = previous;
+    let (value, resets) = current;
+    if resets == previous_resets {
+        (value.wrapping_sub(previous_value), false)
+    } else {
+        (value, true)
+    }
+}
+
+#[derive(Debug, Default)]
+/// A counter, the number of times it has been reset and the capture interval
+/// it was last updated in.
+struct TimedCounter {
+    value: AtomicU64,
+    /// Twice the number of times the counter has been reset, odd while a
+    /// reset is underway, so that a flush never observes a reset value
+    /// without its reset. See [`TimedCounter::load`].
+    resets: AtomicU64,
+    updated: AtomicU64,
+}
+
+impl TimedCounter {
+    /// Return the value of the counter and the number of times it has been
+    /// reset, consistent with one another.
+    fn load(&self) -> (u64, u64) {
+        loop {
+            let before = self.resets.load(Ordering::Acquire);
+            if before % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            let value = self.value.load(Ordering::Acquire);
+            if self.resets.load(Ordering::Acquire) == before {
+                return (value, before / 2);
+            }
+        }
+    }
+}
+
+impl CounterFn for TimedCounter {
+    fn increment(&self, value: u64) {
+        self.value.fetch_add(value, Ordering::Relaxed);
+        self.updated.store(interval(), Ordering::Relaxed);
+    }
+
+    fn absolute(&self, value: u64) {
+        // A counter is set absolutely by the one source that counts for
+        // itself, never concurrently.
+        if value < self.value.load(Ordering::Relaxed) {
+            self.resets.fetch_add(1, Ordering::AcqRel);
+            self.value.store(value, Ordering::Release);
+            self.resets.fetch_add(1, Ordering::Release);
+        } else {
+            self.value.store(value, Ordering::Release);
+        }
+        self.updated.store(interval(), Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Default)]
+/// A gauge, its value stored as `f64` bits, and the capture interval it was
+/// last updated in.
+struct TimedGauge {
+    bits: AtomicU64,
+    updated: AtomicU64,
+}
+
+impl TimedGauge {
+    fn update<F>(&self, f: F)
+    where
+        F: Fn(f64) -> f64,
+    {
+        // The closure always returns `Some`, the update never fails.
+        let _ = self
+            .bits
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some(f(f64::from_bits(bits)).to_bits())
+            });
+        self.updated.store(interval(), Ordering::Relaxed);
+    }
+}
+
+impl GaugeFn for TimedGauge {
+    fn increment(&self, value: f64) {
+        self.update(|current| current + value);
+    }
+
+    fn decrement(&self, value: f64) {
+        self.update(|current| current - value);
+    }
+
+    fn set(&self, value: f64) {
+        self.update(|_| value);
+    }
+}
+
+/// Storage of timed counters and gauges for [`Registry`]. Histograms are
+/// flushed entire every interval and not timed.
+struct TimedStorage;
+
+impl Storage<metrics::Key> for TimedStorage {
+    type Counter = Arc<TimedCounter>;
+    type Gauge = Arc<TimedGauge>;
+    type Histogram = Arc<AtomicBucket<f64>>;
+
+    fn counter(&self, _: &metrics::Key) -> Self::Counter {
+        Arc::new(TimedCounter::default())
+    }
+
+    fn gauge(&self, _: &metrics::Key) -> Self::Gauge {
+        Arc::new(TimedGauge::default())
+    }
+
+    fn histogram(&self, _: &metrics::Key) -> Self::Histogram {
+        Arc::new(AtomicBucket::new())
+    }
+}
+
 struct Inner {
-    registry: Registry<metrics::Key, AtomicStorage>,
+    registry: Registry<metrics::Key, TimedStorage>,
 }
 
 #[allow(missing_debug_implementations)]
@@ -86,44 +431,215 @@ struct Inner {
 pub struct CaptureManager {
     fetch_index: u64,
     run_id: Uuid,
+    interval: Duration,
+    /// The value, and resets, of each counter as of the previous flush.
+    previous: HashMap<metrics::Key, (u64, u64)>,
+    /// The time in milliseconds each counter and gauge was last stamped
+    /// updated, see [`updated_at`].
+    stamps: HashMap<metrics::Key, u128>,
     capture_fp: BufWriter<File>,
     capture_path: PathBuf,
     shutdown: Shutdown,
     inner: Arc<Inner>,
     global_labels: HashMap<String, String>,
+    experiment: Experiment,
+    throttle_calibration: Option<Calibration>,
+    lines_written: u64,
+    soak: Option<SoakState>,
+}
+
+/// The segment bookkeeping of a [`CaptureManager`] in soak mode.
+struct SoakState {
+    config: Soak,
+    segment_index: u32,
+    segment_path: PathBuf,
+    segment_started: Instant,
+    /// Finished segments, oldest first.
+    finished: VecDeque<PathBuf>,
+}
+
+/// The field, appended to each line, holding the line's checksum.
+const CHECKSUM_FIELD: &str = ",\"crc32\":\"";
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(bytes);
+    crc.sum()
+}
+
+/// Append a checksum of `json`, a serialized JSON object, to `json` as a
+/// field of the same object.
+fn seal(json: &str) -> String {
+    debug_assert!(json.ends_with('}'));
+    format!(
+        "{}{}{:08x}\"}}",
+        &json[..json.len() - 1],
+        CHECKSUM_FIELD,
+        crc32(json.as_bytes())
+    )
+}
+
+/// Reverse [`seal`], returning `None` if `line` is not sealed or its checksum
+/// does not match.
+fn unseal(line: &str) -> Option<String> {
+    let idx = line.rfind(CHECKSUM_FIELD)?;
+    let checksum = line[idx + CHECKSUM_FIELD.len()..].strip_suffix("\"}")?;
+    let checksum = u32::from_str_radix(checksum, 16).ok()?;
+    let json = format!("{}}}", &line[..idx]);
+    (crc32(json.as_bytes()) == checksum).then(|| json)
+}
+
+#[derive(Debug, Default)]
+/// The records of a capture file read by [`recover`].
+pub struct Recovered {
+    /// Lines whose checksum matched, without their checksum.
+    pub records: Vec<String>,
+    /// The number of lines that were torn or otherwise corrupt.
+    pub corrupt: u64,
+}
+
+/// Read the capture lines of `reader`, skipping any line that is torn or
+/// otherwise corrupt.
+///
+/// Lines written before captures carried checksums are accepted so long as
+/// they are valid JSON.
+///
+/// # Errors
+///
+/// Function will return an error if reading from `reader` fails.
+pub fn recover<R>(reader: R) -> Result<Recovered, io::Error>
+where
+    R: BufRead,
+{
+    let mut recovered = Recovered::default();
+    for line in reader.split(b'\n') {
+        let line = line?;
+        let record = std::str::from_utf8(&line).ok().and_then(|line| {
+            if line.contains(CHECKSUM_FIELD) {
+                unseal(line)
+            } else {
+                serde_json::from_str::<serde_json::Value>(line)
+                    .ok()
+                    .map(|_| line.to_string())
+            }
+        });
+        match record {
+            Some(record) => recovered.records.push(record),
+            None => recovered.corrupt += 1,
+        }
+    }
+    Ok(recovered)
+}
+
+/// Return `path` with `suffix` appended.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.to_path_buf().into_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Write `value` as JSON to `path`, by way of a temporary file renamed into
+/// place so that a reader never observes a partial write.
+pub(crate) async fn write_json<T>(path: &Path, value: &T) -> Result<(), io::Error>
+where
+    T: Serialize,
+{
+    let tmp = with_suffix(path, ".tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(value)?).await?;
+    fs::rename(&tmp, path).await
+}
+
+/// Return the path a capture file is written to until it is complete.
+fn partial_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".partial")
+}
+
+/// Return the path of segment `index` of `capture_path`.
+fn segment_path(capture_path: &Path, index: u32) -> PathBuf {
+    with_suffix(capture_path, &format!(".{:05}", index))
+}
+
+/// Gzip compress `path` into `path.gz`, removing `path`. Returns the path of
+/// the compressed file.
+fn compress(path: PathBuf) -> Result<PathBuf, io::Error> {
+    let compressed = with_suffix(&path, ".gz");
+    let mut input = std::fs::File::open(&path)?;
+    let mut encoder = GzEncoder::new(std::fs::File::create(&compressed)?, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+    std::fs::remove_file(&path)?;
+    Ok(compressed)
 }
 
 impl CaptureManager {
     /// Create a new [`CaptureManager`]
     ///
+    /// Metrics are flushed every `interval`, raised to [`MINIMUM_INTERVAL`] if
+    /// shorter. If `soak` is set captures are written to segments of
+    /// `capture_path`, see [`Soak`].
+    ///
     /// # Panics
     ///
     /// Function will panic if the underlying capture file cannot be opened.
-    pub async fn new(capture_path: PathBuf, shutdown: Shutdown) -> Self {
-        let fp = File::create(&capture_path).await.unwrap();
+    pub async fn new(
+        capture_path: PathBuf,
+        interval: Duration,
+        soak: Option<Soak>,
+        shutdown: Shutdown,
+    ) -> Self {
+        if interval < MINIMUM_INTERVAL {
+            warn!(
+                "capture interval of {:?} is below the minimum, using {:?}",
+                interval, MINIMUM_INTERVAL
+            );
+        }
+        let soak = soak.map(|config| SoakState {
+            config,
+            segment_index: 0,
+            segment_path: segment_path(&capture_path, 0),
+            segment_started: Instant::now(),
+            finished: VecDeque::new(),
+        });
+        let path = soak
+            .as_ref()
+            .map_or(capture_path.as_path(), |soak| soak.segment_path.as_path());
+        let fp = File::create(partial_path(path)).await.unwrap();
         Self {
             run_id: Uuid::new_v4(),
             fetch_index: 0,
+            interval: interval.max(MINIMUM_INTERVAL),
+            previous: HashMap::new(),
+            stamps: HashMap::new(),
             capture_fp: BufWriter::new(fp),
             capture_path,
             shutdown,
             inner: Arc::new(Inner {
-                registry: Registry::atomic(),
+                registry: Registry::new(TimedStorage),
             }),
             global_labels: HashMap::new(),
+            experiment: Experiment::default(),
+            throttle_calibration: None,
+            lines_written: 0,
+            soak,
         }
     }
 
-    /// Install the [`CaptureManager`] as global [`metrics::Recorder`]
+    /// Install the [`CaptureManager`] as global [`metrics::Recorder`], each
+    /// label of each metric limited to `maximum_label_values` unique values.
     ///
     /// # Panics
     ///
     /// Function will panic if there is already a global recorder set.
-    pub fn install(&self) {
+    pub fn install(&self, maximum_label_values: usize) {
         let recorder = CaptureRecorder {
             inner: Arc::clone(&self.inner),
         };
-        metrics::set_boxed_recorder(Box::new(recorder)).unwrap();
+        metrics::set_boxed_recorder(Box::new(CardinalityLimit::new(
+            recorder,
+            maximum_label_values,
+        )))
+        .unwrap();
+        ANNOTATING.store(true, Ordering::Relaxed);
     }
 
     /// Add a global label to all metrics managed by [`CaptureManager`].
@@ -135,11 +651,38 @@ impl CaptureManager {
         self.global_labels.insert(key.into(), value.into());
     }
 
+    /// Identify the experiment the captures belong to, recorded in the
+    /// capture header and soak snapshots. Its labels are not added to
+    /// metrics, see [`CaptureManager::add_global_label`].
+    pub fn set_experiment(&mut self, experiment: Experiment) {
+        self.experiment = experiment;
+    }
+
+    /// Record the accuracy of throttles on this host in the capture header,
+    /// see [`crate::throttle::calibrate`].
+    pub fn set_throttle_calibration(&mut self, calibration: Calibration) {
+        self.throttle_calibration = Some(calibration);
+    }
+
     async fn record_captures(&mut self) {
         let now_ms: u128 = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_millis();
+        // Updates from here on fall in the next interval. Those racing this
+        // flush are stamped now and again by the next.
+        let closed = INTERVAL.fetch_add(1, Ordering::Relaxed);
+        let pending = std::mem::take(&mut *PENDING_ANNOTATIONS.lock().unwrap());
+        let annotations: Vec<Annotation> = pending
+            .into_iter()
+            .map(|(time, annotation, message)| Annotation {
+                run_id: Cow::Borrowed(&self.run_id),
+                time: u128::from(time),
+                fetch_index: self.fetch_index,
+                annotation,
+                message,
+            })
+            .collect();
         let mut lines = Vec::new();
         self.inner
             .registry
@@ -149,13 +692,22 @@ impl CaptureManager {
                     // TODO we're allocating the same small strings over and over most likely
                     labels.insert(lbl.key().into(), lbl.value().into());
                 }
+                let (value, resets) = counter.load();
+                let previous = self
+                    .previous
+                    .insert(key.clone(), (value, resets))
+                    .unwrap_or((0, 0));
+                let (delta, reset) = counter_delta(previous, (value, resets));
                 let line = Line {
                     run_id: Cow::Borrowed(&self.run_id),
                     time: now_ms,
+                    updated: updated_at(&mut self.stamps, key, &counter.updated, closed, now_ms),
                     fetch_index: self.fetch_index,
                     metric_name: key.name().into(),
                     metric_kind: MetricKind::Counter,
-                    value: LineValue::Int(counter.load(Ordering::Relaxed)),
+                    value: LineValue::Int(value),
+                    delta: Some(delta),
+                    reset,
                     labels,
                 };
                 lines.push(line);
@@ -168,18 +720,51 @@ impl CaptureManager {
                     // TODO we're allocating the same small strings over and over most likely
                     labels.insert(lbl.key().into(), lbl.value().into());
                 }
-                let value: f64 = f64::from_bits(gauge.load(Ordering::Relaxed));
+                let value: f64 = f64::from_bits(gauge.bits.load(Ordering::Relaxed));
                 let line = Line {
                     run_id: Cow::Borrowed(&self.run_id),
                     time: now_ms,
+                    updated: updated_at(&mut self.stamps, key, &gauge.updated, closed, now_ms),
                     fetch_index: self.fetch_index,
                     metric_name: key.name().into(),
                     metric_kind: MetricKind::Gauge,
                     value: LineValue::Float(value),
+                    delta: None,
+                    reset: false,
                     labels,
                 };
                 lines.push(line);
             });
+        self.inner
+            .registry
+            .visit_histograms(|key: &metrics::Key, histogram| {
+                let mut values: Vec<f64> = Vec::new();
+                histogram.clear_with(|vs| values.extend_from_slice(vs));
+                if values.is_empty() {
+                    return;
+                }
+                values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+                for quantile in HISTOGRAM_QUANTILES {
+                    let mut labels = self.global_labels.clone();
+                    for lbl in key.labels() {
+                        labels.insert(lbl.key().into(), lbl.value().into());
+                    }
+                    labels.insert("quantile".into(), quantile.to_string());
+                    let line = Line {
+                        run_id: Cow::Borrowed(&self.run_id),
+                        time: now_ms,
+                        updated: None,
+                        fetch_index: self.fetch_index,
+                        metric_name: key.name().into(),
+                        metric_kind: MetricKind::Histogram,
+                        value: LineValue::Float(quantile_of(&values, quantile)),
+                        delta: None,
+                        reset: false,
+                        labels,
+                    };
+                    lines.push(line);
+                }
+            });
         debug!(
             "Recording {} captures to {}",
             lines.len(),
@@ -188,18 +773,105 @@ impl CaptureManager {
                 .and_then(OsStr::to_str)
                 .unwrap()
         );
+        self.lines_written += (annotations.len() + lines.len()) as u64;
+        for annotation in annotations {
+            let pyld = seal(&serde_json::to_string(&annotation).unwrap());
+            self.capture_fp.write_all(pyld.as_bytes()).await.unwrap();
+            self.capture_fp.write_all(b"\n").await.unwrap();
+        }
         for line in lines.drain(..) {
-            let pyld = serde_json::to_string(&line).unwrap();
+            let pyld = seal(&serde_json::to_string(&line).unwrap());
             self.capture_fp.write_all(pyld.as_bytes()).await.unwrap();
             self.capture_fp.write_all(b"\n").await.unwrap();
         }
+        // Flushing every interval keeps partial data on disk should lading
+        // crash, which matters most on long runs.
+        self.capture_fp.flush().await.unwrap();
+    }
+
+    /// The path of the capture file currently being written, once complete.
+    fn active_path(&self) -> &Path {
+        self.soak
+            .as_ref()
+            .map_or(self.capture_path.as_path(), |soak| {
+                soak.segment_path.as_path()
+            })
+    }
+
+    /// Flush and sync the capture file currently being written, then move it
+    /// into place.
+    async fn finalize(&mut self) -> Result<(), io::Error> {
+        self.capture_fp.flush().await?;
+        self.capture_fp.get_ref().sync_all().await?;
+        let path = self.active_path();
+        fs::rename(partial_path(path), path).await
+    }
+
+    /// Start a new capture segment if the current one has run its course.
+    /// Does nothing if not in soak mode.
+    async fn maybe_rotate(&mut self) -> Result<(), io::Error> {
+        match self.soak {
+            Some(ref soak)
+                if soak.segment_started.elapsed()
+                    >= Duration::from_secs(soak.config.segment_seconds) => {}
+            _ => return Ok(()),
+        }
+
+        self.finalize().await?;
+        let soak = match self.soak {
+            Some(ref mut soak) => soak,
+            None => return Ok(()),
+        };
+        soak.segment_index += 1;
+        let finished = std::mem::replace(
+            &mut soak.segment_path,
+            segment_path(&self.capture_path, soak.segment_index),
+        );
+        self.capture_fp = BufWriter::new(File::create(partial_path(&soak.segment_path)).await?);
+        soak.segment_started = Instant::now();
+        info!("capture segment {} started", soak.segment_path.display());
+
+        let finished = if soak.config.compress {
+            task::spawn_blocking(move || compress(finished))
+                .await
+                .expect("compression task panicked")?
+        } else {
+            finished
+        };
+        soak.finished.push_back(finished);
+        if let Some(maximum_segments) = soak.config.maximum_segments {
+            while soak.finished.len() > maximum_segments {
+                let oldest = soak.finished.pop_front().unwrap();
+                info!("pruning capture segment {}", oldest.display());
+                fs::remove_file(&oldest).await?;
+            }
+        }
+
+        let snapshot = Snapshot {
+            run_id: &self.run_id,
+            experiment_id: self.experiment.id.as_deref(),
+            experiment_labels: &self.experiment.labels,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis(),
+            fetch_index: self.fetch_index,
+            lines_written: self.lines_written,
+            segments: &soak.finished,
+            current_segment: &soak.segment_path,
+        };
+        write_json(
+            &with_suffix(&self.capture_path, ".snapshot.json"),
+            &snapshot,
+        )
+        .await
     }
 
     /// Run [`CaptureManager`] to completion
     ///
-    /// Once a second any metrics produced by this program are flushed to disk
-    /// and this process only stops once an error occurs or a shutdown signal is
-    /// received.
+    /// Once every capture interval any metrics produced by this program are
+    /// flushed to disk and this process only stops once an error occurs or a
+    /// shutdown signal is received.
     ///
     /// # Errors
     ///
@@ -209,16 +881,30 @@ impl CaptureManager {
     ///
     /// None known.
     pub async fn run(mut self) -> Result<(), io::Error> {
-        let mut write_delay = time::interval(Duration::from_secs(1));
+        let header = Header {
+            run_id: &self.run_id,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis(),
+            experiment_id: self.experiment.id.as_deref(),
+            experiment_labels: &self.experiment.labels,
+            throttle_calibration: self.throttle_calibration.as_ref(),
+        };
+        write_json(&with_suffix(&self.capture_path, ".header.json"), &header).await?;
+
+        let mut write_delay = time::interval(self.interval);
 
         loop {
             tokio::select! {
                 _ = write_delay.tick() => {
                     self.record_captures().await;
                     self.fetch_index += 1;
+                    self.maybe_rotate().await?;
                 }
                 _ = self.shutdown.recv() => {
                     self.record_captures().await;
+                    self.finalize().await?;
                     info!("shutdown signal received");
                     return Ok(())
                 }
@@ -271,8 +957,108 @@ impl metrics::Recorder for CaptureRecorder {
             .get_or_create_gauge(key, |c| c.clone().into())
     }
 
-    fn register_histogram(&self, _key: &metrics::Key) -> metrics::Histogram {
-        // nothing, intentionally
-        unimplemented!()
+    fn register_histogram(&self, key: &metrics::Key) -> metrics::Histogram {
+        self.inner
+            .registry
+            .get_or_create_histogram(key, |h| h.clone().into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{borrow::Cow, collections::HashMap};
+
+    use metrics::CounterFn;
+    use proptest::prelude::*;
+    use uuid::Uuid;
+
+    use super::{
+        counter_delta, recover, seal, unseal, Line, LineValue, MetricKind, TimedCounter,
+        SAMPLE_FIELDS,
+    };
+
+    // Every sample field is a field of a capture line, so that readers
+    // separating samples from series miss none of them.
+    proptest! {
+        #[test]
+        fn sample_fields_are_line_fields(time: u64, fetch_index: u64, value: u64, delta: u64) {
+            let run_id = Uuid::new_v4();
+            let line = Line {
+                run_id: Cow::Borrowed(&run_id),
+                time: u128::from(time),
+                updated: Some(u128::from(time)),
+                fetch_index,
+                metric_name: "metric".to_string(),
+                metric_kind: MetricKind::Counter,
+                value: LineValue::Int(value),
+                delta: Some(delta),
+                reset: true,
+                labels: HashMap::new(),
+            };
+            let fields = serde_json::to_value(&line).unwrap();
+            for field in SAMPLE_FIELDS {
+                prop_assert!(fields.get(field).is_some(), "{} is not a line field", field);
+            }
+        }
+    }
+
+    // A counter increased without reset, even past wrapping, has a delta of
+    // its increase. A reset counter has a delta of its value since the reset.
+    proptest! {
+        #[test]
+        fn counter_delta_wraps_and_resets(previous: u64, increase: u64, resets: u64, reset: bool) {
+            let value = previous.wrapping_add(increase);
+            if reset {
+                let current = (value, resets.wrapping_add(1));
+                prop_assert_eq!(counter_delta((previous, resets), current), (value, true));
+            } else {
+                let current = (value, resets);
+                prop_assert_eq!(counter_delta((previous, resets), current), (increase, false));
+            }
+        }
+    }
+
+    // A counter set absolutely below its value is reset once, and counts on
+    // from its new value.
+    proptest! {
+        #[test]
+        fn counter_resets_counted(updates in prop::collection::vec((any::<bool>(), 0_u64..1_000), 0..64)) {
+            let counter = TimedCounter::default();
+            let (mut value, mut resets) = (0_u64, 0_u64);
+            for (absolute, update) in updates {
+                if absolute {
+                    if update < value {
+                        resets += 1;
+                    }
+                    value = update;
+                    counter.absolute(update);
+                } else {
+                    value += update;
+                    counter.increment(update);
+                }
+                prop_assert_eq!(counter.load(), (value, resets));
+            }
+        }
+    }
+
+    // A sealed line always unseals to the line it was made from.
+    proptest! {
+        #[test]
+        fn seal_unseal_identity(key in "[a-z_]{1,16}", value in "[ -~]*") {
+            let json = serde_json::json!({ key: value }).to_string();
+            prop_assert_eq!(Some(json.clone()), unseal(&seal(&json)));
+        }
+    }
+
+    // A sealed line torn anywhere short of its end is never recovered.
+    proptest! {
+        #[test]
+        fn torn_line_never_recovered(key in "[a-z_]{1,16}", value in "[ -~]*", tear: prop::sample::Index) {
+            let json = serde_json::json!({ key: value }).to_string();
+            let sealed = seal(&json);
+            let torn = &sealed.as_bytes()[..tear.index(sealed.len())];
+            let recovered = recover(torn).unwrap();
+            prop_assert!(recovered.records.is_empty());
+        }
     }
 }
@@ -0,0 +1,318 @@
+//! Act against the target on a schedule
+//!
+//! How a target recovers -- from being frozen, from being killed outright --
+//! is as much a part of its behavior as its steady state throughput. The chaos
+//! [`Server`] runs a schedule of timed [`Action`]s against the target once the
+//! experiment stage begins:
+//!
+//! * `stop` freezes the target with SIGSTOP for `duration_seconds`, thawing it
+//!   with SIGCONT. Overlapping stops hold the target stopped until the last of
+//!   them is up.
+//! * `kill` kills the target with SIGKILL, the target being restarted, see
+//!   [`crate::target::expect_restart`].
+//!
+//! Each action is recorded in the capture as the counter `chaos_action`,
+//! labeled with the signal sent, and as a `chaos` annotation, see
+//! [`crate::captures::annotate`], and a stopped target as the gauge
+//! `chaos_stopped`, so that recovery may be measured against the moment of the
+//! action.
+
+use std::{cmp::Ordering, num::NonZeroU32};
+
+use metrics::{counter, gauge};
+use nix::{
+    errno::Errno,
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+use serde::Deserialize;
+use tokio::{
+    sync::watch,
+    time::{self, Duration, Instant},
+};
+use tracing::{info, warn};
+
+use crate::{antagonist::Stage, captures, signals::Shutdown, target};
+
+#[derive(Debug)]
+/// Errors produced by [`Server`]
+pub enum Error {
+    /// Wrapper for [`nix::errno::Errno`]
+    Errno(Errno),
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// What is done to the target.
+pub enum Kind {
+    /// Stop the target with SIGSTOP, continuing it with SIGCONT after
+    /// `duration_seconds`.
+    Stop {
+        /// The seconds the target is held stopped
+        duration_seconds: NonZeroU32,
+    },
+    /// Kill the target with SIGKILL. The target is restarted.
+    Kill,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+/// An action taken against the target.
+pub struct Action {
+    /// The seconds after the experiment stage begins to act
+    pub at_seconds: u32,
+    /// What is done to the target
+    pub action: Kind,
+}
+
+/// One signal of the schedule, sent `at` after the experiment stage begins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Step {
+    at: Duration,
+    signal: Signal,
+}
+
+/// Expand `actions` into the signals they send, in the order they are sent.
+/// A stop is two steps, the target continued once its duration is up.
+/// Overlapping stops are merged into one, lest the first to end continue the
+/// target while another holds it stopped.
+fn schedule(actions: &[Action]) -> Vec<Step> {
+    let mut steps = Vec::with_capacity(actions.len() * 2);
+    let mut stops = Vec::new();
+    for action in actions {
+        let at = Duration::from_secs(u64::from(action.at_seconds));
+        match action.action {
+            Kind::Stop { duration_seconds } => {
+                stops.push((
+                    at,
+                    at + Duration::from_secs(u64::from(duration_seconds.get())),
+                ));
+            }
+            Kind::Kill => steps.push(Step {
+                at,
+                signal: Signal::SIGKILL,
+            }),
+        }
+    }
+    stops.sort_unstable();
+    let mut windows: Vec<(Duration, Duration)> = Vec::with_capacity(stops.len());
+    for (at, until) in stops {
+        match windows.last_mut() {
+            Some((_, last_until)) if at < *last_until => {
+                *last_until = (*last_until).max(until);
+            }
+            _ => windows.push((at, until)),
+        }
+    }
+    for (at, until) in windows {
+        steps.push(Step {
+            at,
+            signal: Signal::SIGSTOP,
+        });
+        steps.push(Step {
+            at: until,
+            signal: Signal::SIGCONT,
+        });
+    }
+    // Steps at the same moment continue the target before acting on it again.
+    steps.sort_by(|a, b| match a.at.cmp(&b.at) {
+        Ordering::Equal => (b.signal == Signal::SIGCONT).cmp(&(a.signal == Signal::SIGCONT)),
+        ordering => ordering,
+    });
+    steps
+}
+
+#[derive(Debug)]
+/// The chaos schedule.
+///
+/// Waits for the experiment stage, then sends each signal of the schedule to
+/// the target as it comes due.
+pub struct Server {
+    actions: Vec<Action>,
+    stage: watch::Receiver<Stage>,
+    shutdown: Shutdown,
+}
+
+impl Server {
+    /// Create a new [`Server`] instance
+    #[must_use]
+    pub fn new(actions: Vec<Action>, stage: watch::Receiver<Stage>, shutdown: Shutdown) -> Self {
+        Self {
+            actions,
+            stage,
+            shutdown,
+        }
+    }
+
+    /// Run this [`Server`] to completion
+    ///
+    /// This function runs the schedule until it is exhausted or a shutdown
+    /// signal is received. A target stopped at shutdown is continued, so that
+    /// it may be shut down in turn.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the target cannot be signaled.
+    pub async fn run(mut self) -> Result<(), Error> {
+        let steps = schedule(&self.actions);
+        gauge!("chaos_stopped", 0.0);
+
+        while *self.stage.borrow() != Stage::Experiment {
+            tokio::select! {
+                res = self.stage.changed() => {
+                    if res.is_err() {
+                        return Ok(());
+                    }
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(());
+                }
+            }
+        }
+        let start = Instant::now();
+
+        let mut stopped = false;
+        for step in steps {
+            tokio::select! {
+                _ = time::sleep_until(start + step.at) => {
+                    signal(step.signal)?;
+                    match step.signal {
+                        Signal::SIGSTOP => stopped = true,
+                        Signal::SIGCONT => stopped = false,
+                        _ => {}
+                    }
+                    gauge!("chaos_stopped", if stopped { 1.0 } else { 0.0 });
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    if stopped {
+                        signal(Signal::SIGCONT)?;
+                        gauge!("chaos_stopped", 0.0);
+                    }
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Send `signal` to the target, recording the action.
+fn signal(signal: Signal) -> Result<(), Error> {
+    let pid = match target::target_pid() {
+        Some(pid) => pid,
+        None => {
+            warn!("target not running, {} not sent", signal);
+            return Ok(());
+        }
+    };
+    // The restart is expected before the kill is sent, else the target may be
+    // reaped before it is, and withdrawn should the kill fail, else the
+    // target's next unexpected exit is taken for a restart.
+    let restart = signal == Signal::SIGKILL;
+    if restart {
+        target::expect_restart();
+    }
+    info!("chaos: sending {} to target {}", signal, pid);
+    if let Err(errno) = kill(
+        Pid::from_raw(pid.try_into().expect("PID coercion failed")),
+        signal,
+    ) {
+        if restart {
+            target::cancel_restart();
+        }
+        return Err(Error::Errno(errno));
+    }
+    counter!("chaos_action", 1, "signal" => signal.as_str());
+    captures::annotate("chaos", format!("sent {} to target {}", signal, pid));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroU32;
+
+    use nix::sys::signal::Signal;
+    use proptest::prelude::*;
+    use tokio::time::Duration;
+
+    use super::{schedule, Action, Kind, Step};
+
+    fn action() -> impl Strategy<Value = Action> {
+        (0..600_u32, prop::option::of(1..120_u32)).prop_map(|(at_seconds, duration)| Action {
+            at_seconds,
+            action: match duration {
+                Some(duration) => Kind::Stop {
+                    duration_seconds: NonZeroU32::new(duration).unwrap(),
+                },
+                None => Kind::Kill,
+            },
+        })
+    }
+
+    /// Whether the target is stopped once the steps of `steps` due by `at`
+    /// are sent.
+    fn stopped_at(steps: &[Step], at: Duration) -> bool {
+        steps
+            .iter()
+            .take_while(|step| step.at <= at)
+            .fold(false, |stopped, step| match step.signal {
+                Signal::SIGSTOP => true,
+                Signal::SIGCONT => false,
+                _ => stopped,
+            })
+    }
+
+    // The schedule is ordered by time, stops and continues alternate, and the
+    // target is stopped throughout every stop's duration, however stops
+    // overlap, and continued after the last.
+    proptest! {
+        #[test]
+        fn stops_are_continued(actions in prop::collection::vec(action(), 0..16)) {
+            let steps = schedule(&actions);
+            prop_assert!(steps.windows(2).all(|pair| pair[0].at <= pair[1].at));
+            let signals: Vec<Signal> = steps
+                .iter()
+                .map(|step| step.signal)
+                .filter(|signal| *signal != Signal::SIGKILL)
+                .collect();
+            for (idx, signal) in signals.iter().enumerate() {
+                let expected = if idx % 2 == 0 { Signal::SIGSTOP } else { Signal::SIGCONT };
+                prop_assert_eq!(*signal, expected);
+            }
+            prop_assert_eq!(signals.len() % 2, 0);
+            for action in &actions {
+                if let Kind::Stop { duration_seconds } = action.action {
+                    let at = Duration::from_secs(u64::from(action.at_seconds));
+                    let until = at + Duration::from_secs(u64::from(duration_seconds.get()));
+                    prop_assert!(stopped_at(&steps, at));
+                    for step in steps.iter().filter(|step| step.at >= at && step.at < until) {
+                        prop_assert!(stopped_at(&steps, step.at));
+                    }
+                }
+            }
+        }
+    }
+
+    // Of two overlapping stops the shorter, within the longer, does not
+    // continue the target: it is stopped once and continued once the longer
+    // is up.
+    proptest! {
+        #[test]
+        fn overlapping_stops_merged(at_seconds in 0..600_u32, offset in 0..60_u32, inner in 1..60_u32, outer_extra in 1..60_u32) {
+            let stop = |at_seconds, duration| Action {
+                at_seconds,
+                action: Kind::Stop { duration_seconds: NonZeroU32::new(duration).unwrap() },
+            };
+            let outer = offset + inner + outer_extra;
+            let steps = schedule(&[stop(at_seconds, outer), stop(at_seconds + offset, inner)]);
+            prop_assert_eq!(
+                steps,
+                vec![
+                    Step { at: Duration::from_secs(u64::from(at_seconds)), signal: Signal::SIGSTOP },
+                    Step { at: Duration::from_secs(u64::from(at_seconds + outer)), signal: Signal::SIGCONT },
+                ]
+            );
+        }
+    }
+}
@@ -0,0 +1,558 @@
+//! Clean up after a run
+//!
+//! A run leaves things on the host as it goes: temporary and partial files
+//! beside the capture and status files, the disk antagonist's files, the files
+//! of file generators, the FIFOs of fifo generators, the trees of file tree
+//! generators, the rendered target configuration and the target and inspector
+//! processes. A run that ends normally removes most of these, one that crashes
+//! does not, and the next run on the host trips over them -- a stale partial
+//! capture, a target from the crashed run still holding its ports. [`clean`]
+//! removes everything a run of a configuration may have left behind, whether or
+//! not that run is still around to do so itself.
+//!
+//! Processes are tracked in a file per lading process in the temporary
+//! directory, see [`track`]. Only processes whose lading has exited are
+//! killed, so that runs sharing a host do not clean up after one another. On
+//! Linux a process is only killed if its command is the one recorded, so that
+//! a reused PID is left alone. Elsewhere orphaned processes are reported but
+//! not killed. Likewise each run records the files it may leave behind beside
+//! its processes, see [`claim`], and files a running lading may yet write are
+//! left alone.
+//!
+//! lading creates no unix sockets, cgroups or containers, so there are none to
+//! clean up.
+
+use std::{
+    ffi::OsStr,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+};
+
+use nix::{
+    errno::Errno,
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    antagonist,
+    config::{self, Config, Telemetry},
+    generator,
+};
+
+/// The prefix of the files [`track`] records processes in.
+const PROCESS_FILE_PREFIX: &str = "lading-";
+/// The suffix of the files [`track`] records processes in.
+const PROCESS_FILE_SUFFIX: &str = ".pids";
+/// The suffix of the files [`claim`] records the files of runs in.
+const CLAIM_FILE_SUFFIX: &str = ".claims";
+/// The prefix of the disk antagonist's files.
+const ANTAGONIST_FILE_PREFIX: &str = "lading-antagonist-";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// What [`clean`] did.
+pub struct Summary {
+    /// Files removed
+    pub removed: usize,
+    /// Orphaned processes killed
+    pub killed: usize,
+    /// Files or processes that could not be cleaned up
+    pub failed: usize,
+}
+
+/// The file this lading process records what it leaves behind in, those of
+/// [`track`] for `suffix` [`PROCESS_FILE_SUFFIX`], those of [`claim`] for
+/// [`CLAIM_FILE_SUFFIX`].
+fn own_file(suffix: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "{}{}{}",
+        PROCESS_FILE_PREFIX,
+        std::process::id(),
+        suffix
+    ))
+}
+
+/// Record that this lading process spawned `pid`, running `command`, so that
+/// it may be killed by a later [`clean`] should this process crash.
+pub(crate) fn track(pid: u32, command: &Path) {
+    let res = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(own_file(PROCESS_FILE_SUFFIX))
+        .and_then(|mut file| writeln!(file, "{} {}", pid, command.display()));
+    if let Err(err) = res {
+        warn!("could not track process {}: {}", pid, err);
+    }
+}
+
+/// Forget the processes this lading process spawned and the files it claimed,
+/// once the run is over.
+pub fn release() {
+    for suffix in [PROCESS_FILE_SUFFIX, CLAIM_FILE_SUFFIX] {
+        match fs::remove_file(own_file(suffix)) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => warn!("could not remove {} file: {}", suffix, err),
+        }
+    }
+}
+
+/// Whether a process `pid` is running.
+fn alive(pid: u32) -> bool {
+    match i32::try_from(pid) {
+        Ok(pid) => kill(Pid::from_raw(pid), None) != Err(Errno::ESRCH),
+        Err(_) => false,
+    }
+}
+
+/// Whether process `pid` is running `command`.
+#[cfg(target_os = "linux")]
+fn runs(pid: u32, command: &str) -> bool {
+    fs::read(format!("/proc/{}/cmdline", pid)).map_or(false, |cmdline| {
+        cmdline.split(|byte| *byte == 0).next() == Some(command.as_bytes())
+    })
+}
+
+/// Whether process `pid` is running `command`. Without procfs this cannot be
+/// known.
+#[cfg(not(target_os = "linux"))]
+fn runs(_pid: u32, _command: &str) -> bool {
+    false
+}
+
+/// Return the PID of the lading process that wrote `name`, if `name` is a
+/// process or claim file ending `suffix`.
+fn file_owner(name: &str, suffix: &str) -> Option<u32> {
+    name.strip_prefix(PROCESS_FILE_PREFIX)?
+        .strip_suffix(suffix)?
+        .parse()
+        .ok()
+}
+
+/// Whether `name` is the name of a file generated from a file generator's
+/// `path_template` whose file name is `prefix`, four digits, `suffix`.
+fn matches_template(prefix: &str, suffix: &str, name: &str) -> bool {
+    name.strip_prefix(prefix)
+        .and_then(|rest| rest.strip_suffix(suffix))
+        .map_or(false, |index| {
+            index.len() == 4 && index.bytes().all(|byte| byte.is_ascii_digit())
+        })
+}
+
+/// The files in `directory` whose names satisfy `matches`. A directory that
+/// does not exist holds no files.
+fn matching<F>(directory: &Path, matches: F) -> Vec<PathBuf>
+where
+    F: Fn(&str) -> bool,
+{
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(err) => {
+            if err.kind() != io::ErrorKind::NotFound {
+                warn!("could not read {}: {}", directory.display(), err);
+            }
+            return Vec::new();
+        }
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_str().map_or(false, &matches))
+        .map(|entry| entry.path())
+        .collect()
+}
+
+/// The directory holding `path` and its file name.
+fn split(path: &Path) -> Option<(&Path, &str)> {
+    let name = path.file_name().and_then(OsStr::to_str)?;
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    Some((directory, name))
+}
+
+/// Which files in a directory a run may leave behind.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum Rule {
+    /// Files named the prefix and ending `.partial` or `.tmp`.
+    Partial(String),
+    /// The file of exactly this name.
+    Exact(String),
+    /// The FIFO of exactly this name, never a file the generator would have
+    /// refused to write into.
+    Fifo(String),
+    /// Files whose names start with the prefix.
+    Prefix(String),
+    /// Files generated from a file generator's `path_template`, see
+    /// [`matches_template`].
+    Template { prefix: String, suffix: String },
+    /// The entries of a file tree generator.
+    FileTree,
+}
+
+impl Rule {
+    /// Whether the file `name` is one of this rule's.
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            Rule::Partial(prefix) => {
+                name.starts_with(prefix.as_str())
+                    && (name.ends_with(".partial") || name.ends_with(".tmp"))
+            }
+            Rule::Exact(exact) | Rule::Fifo(exact) => name == exact.as_str(),
+            Rule::Prefix(prefix) => name.starts_with(prefix.as_str()),
+            Rule::Template { prefix, suffix } => matches_template(prefix, suffix, name),
+            Rule::FileTree => generator::file_tree::is_entry(name),
+        }
+    }
+}
+
+/// The files a run may leave behind in `directory`, those its `rule` matches.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Pattern {
+    directory: PathBuf,
+    rule: Rule,
+}
+
+impl Pattern {
+    fn new(directory: &Path, rule: Rule) -> Self {
+        Self {
+            directory: canonical(directory),
+            rule,
+        }
+    }
+
+    /// Whether `path` is one of this pattern's files.
+    fn covers(&self, path: &Path) -> bool {
+        split(path).map_or(false, |(directory, name)| {
+            canonical(directory) == self.directory && self.rule.matches(name)
+        })
+    }
+}
+
+/// `directory` made canonical where it exists, so that runs naming one
+/// directory differently agree on it.
+fn canonical(directory: &Path) -> PathBuf {
+    fs::canonicalize(directory).unwrap_or_else(|_| directory.to_path_buf())
+}
+
+/// The patterns of the files a run of `config`, writing its status to
+/// `status_file`, may leave behind.
+fn patterns(config: &Config, status_file: Option<&Path>) -> Vec<Pattern> {
+    let mut patterns = Vec::new();
+
+    // Partial captures and capture segments, and the temporary files headers
+    // and snapshots are written through. Finished captures are results and
+    // kept.
+    if let Telemetry::Log { ref path, .. } = config.telemetry {
+        if let Some((directory, name)) = split(path) {
+            patterns.push(Pattern::new(directory, Rule::Partial(name.to_string())));
+        }
+    }
+    if let Some((directory, name)) = status_file.and_then(split) {
+        patterns.push(Pattern::new(
+            directory,
+            Rule::Exact(format!("{}.tmp", name)),
+        ));
+    }
+
+    for antagonist in &config.antagonist {
+        if let antagonist::Kind::Disk(ref disk) = antagonist.kind {
+            patterns.push(Pattern::new(
+                &disk.directory,
+                Rule::Prefix(ANTAGONIST_FILE_PREFIX.to_string()),
+            ));
+        }
+    }
+
+    let generators: Vec<&generator::Config> = match config.generator {
+        config::Generator::One(ref cfg) => vec![cfg.as_ref()],
+        config::Generator::Many(ref cfgs) => cfgs.iter().collect(),
+    };
+    for cfg in generators {
+        match cfg {
+            generator::Config::FileGen(file_gen) => {
+                let template = Path::new(&file_gen.path_template);
+                if let Some((directory, name)) = split(template) {
+                    if let Some((prefix, suffix)) = name.split_once("%NNN%") {
+                        patterns.push(Pattern::new(
+                            directory,
+                            Rule::Template {
+                                prefix: prefix.to_string(),
+                                suffix: suffix.to_string(),
+                            },
+                        ));
+                    }
+                }
+            }
+            generator::Config::Fifo(fifo) => {
+                if let Some((directory, name)) = split(&fifo.path) {
+                    patterns.push(Pattern::new(directory, Rule::Fifo(name.to_string())));
+                }
+            }
+            generator::Config::FileTree(file_tree) => {
+                patterns.push(Pattern::new(&file_tree.root, Rule::FileTree));
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(config_file) = config.target.as_ref().and_then(|t| t.config_file.as_ref()) {
+        if let Some((directory, name)) = split(&config_file.output) {
+            patterns.push(Pattern::new(directory, Rule::Exact(name.to_string())));
+        }
+    }
+
+    patterns
+}
+
+/// The files of `patterns` that exist now.
+fn existing(patterns: &[Pattern]) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        let found = matching(&pattern.directory, |entry| pattern.rule.matches(entry));
+        if let Rule::Fifo(_) = pattern.rule {
+            paths.extend(found.into_iter().filter(|path| {
+                fs::symlink_metadata(path).map_or(false, |metadata| metadata.file_type().is_fifo())
+            }));
+        } else {
+            paths.extend(found);
+        }
+    }
+    paths
+}
+
+/// The files a run of `config`, writing its status to `status_file`, may have
+/// left behind that exist now.
+#[must_use]
+pub fn leftovers(config: &Config, status_file: Option<&Path>) -> Vec<PathBuf> {
+    existing(&patterns(config, status_file))
+}
+
+/// Record that this lading process, running `config` and writing its status
+/// to `status_file`, may leave files behind, so that a [`clean`] run alongside
+/// leaves them alone until this process exits.
+pub fn claim(config: &Config, status_file: Option<&Path>) {
+    let res = patterns(config, status_file)
+        .iter()
+        .try_fold(String::new(), |mut claims, pattern| {
+            claims.push_str(&serde_json::to_string(pattern)?);
+            claims.push('\n');
+            Ok::<_, serde_json::Error>(claims)
+        })
+        .map_err(io::Error::from)
+        .and_then(|claims| fs::write(own_file(CLAIM_FILE_SUFFIX), claims));
+    if let Err(err) = res {
+        warn!("could not claim the files of this run: {}", err);
+    }
+}
+
+/// The patterns claimed by lading processes other than this one that are
+/// still running, read from the claim files in `directory`. The claim files
+/// of lading processes that have exited are removed.
+fn live_claims(directory: &Path, summary: &mut Summary) -> Vec<Pattern> {
+    let claim_files = matching(directory, |entry| {
+        file_owner(entry, CLAIM_FILE_SUFFIX).map_or(false, |owner| owner != std::process::id())
+    });
+    let mut claims = Vec::new();
+    for claim_file in claim_files {
+        let owner = claim_file
+            .file_name()
+            .and_then(OsStr::to_str)
+            .and_then(|name| file_owner(name, CLAIM_FILE_SUFFIX));
+        if !owner.map_or(false, alive) {
+            remove(&claim_file, summary);
+            continue;
+        }
+        match fs::read_to_string(&claim_file) {
+            Ok(contents) => {
+                claims.extend(
+                    contents
+                        .lines()
+                        .filter_map(|line| serde_json::from_str::<Pattern>(line).ok()),
+                );
+            }
+            Err(err) => warn!("could not read {}: {}", claim_file.display(), err),
+        }
+    }
+    claims
+}
+
+/// Kill the processes spawned by lading processes that have since exited,
+/// forgetting them.
+fn clean_orphans(summary: &mut Summary) {
+    let process_files = matching(&std::env::temp_dir(), |entry| {
+        file_owner(entry, PROCESS_FILE_SUFFIX)
+            .map_or(false, |owner| owner != std::process::id() && !alive(owner))
+    });
+    for process_file in process_files {
+        let contents = match fs::read_to_string(&process_file) {
+            Ok(contents) => contents,
+            Err(err) => {
+                warn!("could not read {}: {}", process_file.display(), err);
+                summary.failed += 1;
+                continue;
+            }
+        };
+        let mut orphaned = false;
+        for line in contents.lines() {
+            let (pid, command) = match line.split_once(' ') {
+                Some((pid, command)) => match pid.parse::<u32>() {
+                    Ok(pid) => (pid, command),
+                    Err(_) => continue,
+                },
+                None => continue,
+            };
+            if !alive(pid) {
+                continue;
+            }
+            if !runs(pid, command) {
+                warn!(
+                    "process {} of a previous run may still be running {}, not killed",
+                    pid, command
+                );
+                orphaned = true;
+                summary.failed += 1;
+                continue;
+            }
+            info!("killing process {} of a previous run, {}", pid, command);
+            match kill(Pid::from_raw(pid.try_into().unwrap()), Signal::SIGKILL) {
+                Ok(()) => summary.killed += 1,
+                Err(err) => {
+                    warn!("could not kill process {}: {}", pid, err);
+                    orphaned = true;
+                    summary.failed += 1;
+                }
+            }
+        }
+        if !orphaned {
+            remove(&process_file, summary);
+        }
+    }
+}
+
+/// Remove the file or directory at `path`, recording the outcome in
+/// `summary`.
+fn remove(path: &Path, summary: &mut Summary) {
+    let res = if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    match res {
+        Ok(()) => summary.removed += 1,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => {
+            warn!("could not remove {}: {}", path.display(), err);
+            summary.failed += 1;
+        }
+    }
+}
+
+/// Remove each of `paths` but those `claims` covers.
+fn clean_files(paths: &[PathBuf], claims: &[Pattern], summary: &mut Summary) {
+    for path in paths {
+        if claims.iter().any(|claim| claim.covers(path)) {
+            info!("leaving {}, a running lading may write it", path.display());
+            continue;
+        }
+        info!("removing {}", path.display());
+        remove(path, summary);
+    }
+}
+
+/// Remove everything a previous run of `config`, writing its status to
+/// `status_file`, left behind: the files of [`leftovers`] and processes
+/// orphaned by a crashed lading. Files a running lading has claimed are left
+/// alone.
+///
+/// Cleaning up is best effort. Each file or process that cannot be cleaned up
+/// is logged and counted in the returned [`Summary`], the rest are cleaned up
+/// regardless.
+#[must_use]
+pub fn clean(config: &Config, status_file: Option<&Path>) -> Summary {
+    let mut summary = Summary::default();
+    clean_orphans(&mut summary);
+    let claims = live_claims(&std::env::temp_dir(), &mut summary);
+    clean_files(&leftovers(config, status_file), &claims, &mut summary);
+    summary
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use proptest::prelude::*;
+
+    use super::{
+        clean_files, existing, file_owner, live_claims, matches_template, Pattern, Rule, Summary,
+        CLAIM_FILE_SUFFIX, PROCESS_FILE_SUFFIX,
+    };
+
+    // Exactly the names a file generator produces from its template match it.
+    proptest! {
+        #[test]
+        fn template_matches_generated_names(
+            prefix in "[a-z_.]{0,8}",
+            suffix in "[a-z_.]{0,8}",
+            index in 0_u32..10_000,
+            other in "[a-z0-9]{0,6}",
+        ) {
+            let name = format!("{}{:04}{}", prefix, index, suffix);
+            prop_assert!(matches_template(&prefix, &suffix, &name));
+            let other_name = format!("{}{}{}", prefix, other, suffix);
+            let generated = other.len() == 4 && other.bytes().all(|b| b.is_ascii_digit());
+            prop_assert_eq!(matches_template(&prefix, &suffix, &other_name), generated);
+        }
+
+        #[test]
+        fn file_owner_round_trip(pid: u32) {
+            prop_assert_eq!(file_owner(&format!("lading-{}.pids", pid), PROCESS_FILE_SUFFIX), Some(pid));
+            prop_assert_eq!(file_owner(&format!("lading-{}.claims", pid), CLAIM_FILE_SUFFIX), Some(pid));
+            prop_assert_eq!(file_owner(&format!("lading-{}.pids", pid), CLAIM_FILE_SUFFIX), None);
+            prop_assert_eq!(file_owner(&format!("lading-antagonist-{}", pid), PROCESS_FILE_SUFFIX), None);
+        }
+    }
+
+    // The files of a running lading -- PID 1 is always running -- survive a
+    // clean, those no running lading claims do not.
+    proptest! {
+        #[test]
+        fn live_run_files_survive(
+            prefix in "[a-z]{1,8}",
+            indexes in prop::collection::btree_set(0_u32..10_000, 1..8),
+        ) {
+            let directory = std::env::temp_dir().join(format!(
+                "lading-cleanup-test-{}-{}",
+                std::process::id(),
+                prefix
+            ));
+            fs::create_dir_all(&directory).unwrap();
+            let pattern = Pattern::new(
+                &directory,
+                Rule::Template { prefix: prefix.clone(), suffix: ".log".to_string() },
+            );
+            for index in &indexes {
+                fs::write(directory.join(format!("{}{:04}.log", prefix, index)), b"").unwrap();
+            }
+            let claim_file = directory.join(format!("lading-1{}", CLAIM_FILE_SUFFIX));
+            fs::write(&claim_file, serde_json::to_string(&pattern).unwrap() + "\n").unwrap();
+
+            let paths = existing(&[pattern]);
+            prop_assert_eq!(paths.len(), indexes.len());
+            let mut summary = Summary::default();
+            let claims = live_claims(&directory, &mut summary);
+            clean_files(&paths, &claims, &mut summary);
+            prop_assert_eq!(summary.removed, 0);
+            prop_assert!(paths.iter().all(|path| path.exists()));
+
+            clean_files(&paths, &[], &mut summary);
+            prop_assert_eq!(summary.removed, indexes.len());
+            prop_assert!(paths.iter().all(|path| !path.exists()));
+            fs::remove_dir_all(&directory).unwrap();
+        }
+    }
+}
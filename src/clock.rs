@@ -0,0 +1,97 @@
+//! Module to abstract the passage of time in lading.
+//!
+//! The experiment schedule -- warmup, experiment duration, shutdown -- is
+//! driven by a [`Clock`]. Under normal operation this is wall-clock time. In a
+//! dry run the clock is simulated: sleeps complete immediately and advance the
+//! clock, allowing a long schedule to be checked in moments.
+//!
+//! Timer resolution differs between platforms, notably between `x86_64` and
+//! aarch64 rigs. [`resolution`] estimates that of the clock timing throttles so
+//! that it may be recorded at startup, keeping results across architectures
+//! comparable.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use governor::clock::{Clock as _, Reference};
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::throttle;
+
+/// The number of readings [`resolution`] takes the minimum of.
+const RESOLUTION_SAMPLES: usize = 100;
+
+/// Estimate the resolution of the clock timing throttles -- quanta on
+/// `x86_64`, the monotonic clock elsewhere -- the smallest non-zero difference
+/// observed between successive readings.
+#[must_use]
+pub fn resolution() -> Duration {
+    let clock = throttle::Clock::default();
+    let mut smallest = Duration::MAX;
+    for _ in 0..RESOLUTION_SAMPLES {
+        let start = clock.now();
+        let mut now = clock.now();
+        while now == start {
+            now = clock.now();
+        }
+        smallest = smallest.min(Duration::from(Reference::duration_since(&now, start)));
+    }
+    smallest
+}
+
+#[derive(Debug, Clone)]
+/// A source of time for the experiment schedule.
+pub enum Clock {
+    /// Wall-clock time. Sleeps take as long as requested.
+    Real {
+        /// The instant this clock was created.
+        start: Instant,
+    },
+    /// Simulated time. Sleeps complete immediately, advancing the clock.
+    Simulated {
+        /// Nanoseconds elapsed since this clock was created. Shared between
+        /// clones.
+        elapsed: Arc<AtomicU64>,
+    },
+}
+
+impl Clock {
+    /// Create a new wall-clock [`Clock`].
+    #[must_use]
+    pub fn real() -> Self {
+        Self::Real {
+            start: Instant::now(),
+        }
+    }
+
+    /// Create a new simulated [`Clock`].
+    #[must_use]
+    pub fn simulated() -> Self {
+        Self::Simulated {
+            elapsed: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Time elapsed since this clock was created.
+    #[must_use]
+    pub fn elapsed(&self) -> Duration {
+        match self {
+            Self::Real { start } => start.elapsed(),
+            Self::Simulated { elapsed } => Duration::from_nanos(elapsed.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Wait until `duration` has elapsed on this clock.
+    pub async fn sleep(&self, duration: Duration) {
+        match self {
+            Self::Real { .. } => sleep(duration).await,
+            Self::Simulated { elapsed } => {
+                let nanos = duration.as_nanos().try_into().unwrap_or(u64::MAX);
+                elapsed.fetch_add(nanos, Ordering::Relaxed);
+                tokio::task::yield_now().await;
+            }
+        }
+    }
+}
@@ -1,11 +1,19 @@
 //! This module controls configuration parsing from the end user, providing a
 //! convenience mechanism for the rest of the program. Crashes are most likely
 //! to originate from this code, intentionally.
-use std::{collections::HashMap, net::SocketAddr, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    path::PathBuf,
+};
 
+use byte_unit::Byte;
 use serde::Deserialize;
 
-use crate::{blackhole, generator, inspector, observer, target};
+use crate::{
+    antagonist, blackhole, budget, captures, chaos, generator, inspector, observer, pairs,
+    pushgateway, runtime_stats, supervisor, target, watchdog,
+};
 
 /// Generator configuration for this program.
 ///
@@ -14,8 +22,11 @@ use crate::{blackhole, generator, inspector, observer, target};
 /// and that they do not exist in an array. In order to avoid breaking those
 /// configs we support this goofy structure. A deprecation cycle here is in
 /// order someday.
+///
+/// An experiment has at most one stdin generator, the target having but one
+/// stdin; a configuration with more is rejected.
 #[derive(Debug, Deserialize)]
-#[serde(untagged)]
+#[serde(try_from = "Generators")]
 pub enum Generator {
     /// Load in only one generator
     One(Box<generator::Config>),
@@ -23,6 +34,37 @@ pub enum Generator {
     Many(Vec<generator::Config>),
 }
 
+/// The shape of [`Generator`] as written, before it is checked.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Generators {
+    One(Box<generator::Config>),
+    Many(Vec<generator::Config>),
+}
+
+impl TryFrom<Generators> for Generator {
+    type Error = String;
+
+    fn try_from(generators: Generators) -> Result<Self, Self::Error> {
+        match generators {
+            Generators::One(cfg) => Ok(Self::One(cfg)),
+            Generators::Many(cfgs) => {
+                let stdin = cfgs
+                    .iter()
+                    .filter(|cfg| matches!(cfg, generator::Config::Stdin(_)))
+                    .count();
+                if stdin > 1 {
+                    return Err(format!(
+                        "at most one stdin generator may be configured, found {}",
+                        stdin
+                    ));
+                }
+                Ok(Self::Many(cfgs))
+            }
+        }
+    }
+}
+
 /// Blackhole configuration for this program.
 ///
 /// We have many uses that exist prior to the introduction of multiple
@@ -39,9 +81,37 @@ pub enum Blackhole {
     Many(Vec<blackhole::Config>),
 }
 
+#[derive(Debug, Deserialize, Default, Clone, PartialEq, Eq)]
+/// Identifies an experiment, so that runs -- from a parameter sweep, say -- can
+/// be grouped downstream without relying on file naming conventions.
+pub struct Experiment {
+    /// An identifier for this experiment
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Labels describing this experiment
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+}
+
+impl Experiment {
+    /// The labels to attach to every metric: [`Experiment::labels`] plus, if
+    /// set, the id as `experiment_id`.
+    #[must_use]
+    pub fn metric_labels(&self) -> HashMap<String, String> {
+        let mut labels = self.labels.clone();
+        if let Some(ref id) = self.id {
+            labels.insert("experiment_id".to_string(), id.clone());
+        }
+        labels
+    }
+}
+
 /// Main configuration struct for this program
 #[derive(Debug, Deserialize)]
 pub struct Config {
+    /// Identifies this experiment in every output
+    #[serde(default)]
+    pub experiment: Experiment,
     /// The method by which to express telemetry
     #[serde(default)]
     pub telemetry: Telemetry,
@@ -57,6 +127,125 @@ pub struct Config {
     pub blackhole: Option<Blackhole>,
     /// The target inspector sub-program
     pub inspector: Option<inspector::Config>,
+    /// Noisy neighbors contending with the target for host resources
+    #[serde(default)]
+    pub antagonist: Vec<antagonist::Config>,
+    /// Collectors of runtime statistics the target exposes about itself
+    #[serde(default)]
+    pub runtime_stats: Vec<runtime_stats::Config>,
+    /// What to do when a generator or blackhole fails
+    #[serde(default)]
+    pub component_failure: supervisor::Policy,
+    /// Flags the target when it stalls under load
+    pub watchdog: Option<watchdog::Config>,
+    /// The resources the target must stay within in the steady state, failing
+    /// the run otherwise
+    pub budget: Option<budget::Config>,
+    /// Randomized start offsets and phase jitter decorrelating generators
+    pub generator_jitter: Option<generator::Jitter>,
+    /// Actions taken against the target on a schedule, see [`crate::chaos`]
+    #[serde(default)]
+    pub chaos: Vec<chaos::Action>,
+    /// Generators paired with the blackholes their load is expected back at,
+    /// see [`crate::pairs`]
+    #[serde(default)]
+    pub pairs: Vec<pairs::Config>,
+    /// The maximum number of unique values each label of lading's own metrics
+    /// may take before further values are folded together
+    #[serde(default = "default_maximum_label_values")]
+    pub maximum_label_values: usize,
+    /// The memory lading may plan to allocate up front, see
+    /// [`Config::memory_plan`]. If the plan exceeds the budget lading refuses
+    /// to start.
+    pub memory_budget: Option<Byte>,
+}
+
+impl Config {
+    /// The memory each component allocates up front -- block caches, buffers
+    /// and the like -- in bytes, by component name. Memory a component
+    /// allocates as the run goes, per connection say, cannot be estimated and
+    /// is not counted.
+    #[must_use]
+    pub fn memory_plan(&self) -> Vec<(String, u64)> {
+        let generators: Vec<&generator::Config> = match self.generator {
+            Generator::One(ref cfg) => vec![cfg.as_ref()],
+            Generator::Many(ref cfgs) => cfgs.iter().collect(),
+        };
+        let blackholes: Vec<&blackhole::Config> = match self.blackhole {
+            Some(Blackhole::One(ref cfg)) => vec![cfg.as_ref()],
+            Some(Blackhole::Many(ref cfgs)) => cfgs.iter().collect(),
+            None => vec![],
+        };
+        let generators = generators
+            .into_iter()
+            .enumerate()
+            .map(|(idx, cfg)| (format!("generator_{}", idx), cfg.planned_memory_bytes()));
+        let blackholes = blackholes
+            .into_iter()
+            .enumerate()
+            .map(|(idx, cfg)| (format!("blackhole_{}", idx), cfg.planned_memory_bytes()));
+        let antagonists = self
+            .antagonist
+            .iter()
+            .enumerate()
+            .map(|(idx, cfg)| (format!("antagonist_{}", idx), cfg.planned_memory_bytes()));
+        generators.chain(blackholes).chain(antagonists).collect()
+    }
+
+    /// The experiment's context as provided to the target, see
+    /// [`target::Config::provide`]: `LADING_EXPERIMENT_ID` if an id is set,
+    /// `LADING_LABELS`, every label attached to captures as comma separated
+    /// `KEY=VAL` pairs ordered by key, `LADING_PROMETHEUS_ADDR` if telemetry
+    /// is exported to prometheus and `LADING_GENERATOR_<N>_BYTES_PER_SECOND`
+    /// for each generator limited in bytes. Blackhole addresses are provided
+    /// once bound, not here.
+    #[must_use]
+    pub fn target_context(&self) -> Vec<(String, String)> {
+        let mut context = Vec::new();
+        if let Some(ref id) = self.experiment.id {
+            context.push(("LADING_EXPERIMENT_ID".to_string(), id.clone()));
+        }
+        let (global_labels, prometheus_addr) = match self.telemetry {
+            Telemetry::Prometheus {
+                prometheus_addr,
+                ref global_labels,
+                ..
+            } => (global_labels, Some(prometheus_addr)),
+            Telemetry::Log {
+                ref global_labels, ..
+            } => (global_labels, None),
+        };
+        let experiment_labels = self.experiment.metric_labels();
+        let labels: BTreeMap<&String, &String> = global_labels
+            .iter()
+            .chain(experiment_labels.iter())
+            .collect();
+        let labels: Vec<String> = labels
+            .into_iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        context.push(("LADING_LABELS".to_string(), labels.join(",")));
+        if let Some(addr) = prometheus_addr {
+            context.push(("LADING_PROMETHEUS_ADDR".to_string(), addr.to_string()));
+        }
+        let generators: Vec<&generator::Config> = match self.generator {
+            Generator::One(ref cfg) => vec![cfg.as_ref()],
+            Generator::Many(ref cfgs) => cfgs.iter().collect(),
+        };
+        for (idx, cfg) in generators.into_iter().enumerate() {
+            if let Some(rate) = cfg.bytes_per_second() {
+                context.push((
+                    format!("LADING_GENERATOR_{}_BYTES_PER_SECOND", idx),
+                    rate.get_bytes().to_string(),
+                ));
+            }
+        }
+        context
+    }
+}
+
+fn default_maximum_label_values() -> usize {
+    64
 }
 
 #[derive(Debug, Deserialize)]
@@ -71,6 +260,16 @@ pub enum Telemetry {
         prometheus_addr: SocketAddr,
         /// Additional labels to include in every metric
         global_labels: HashMap<String, String>,
+        /// Drop metrics not updated for this many seconds from the exporter,
+        /// so that short-lived label sets -- error kinds, connections -- do
+        /// not accumulate over a long run. If unset metrics are kept for the
+        /// life of the run.
+        #[serde(default)]
+        idle_timeout_seconds: Option<u64>,
+        /// Push metrics to a Pushgateway as lading shuts down, see
+        /// [`crate::pushgateway`]
+        #[serde(default)]
+        push_gateway: Option<pushgateway::Config>,
     },
     /// In log mode lading will emit its internal telemetry to a structured log
     /// file, the "capture" file.
@@ -79,6 +278,13 @@ pub enum Telemetry {
         path: PathBuf,
         /// Additional labels to include in every metric
         global_labels: HashMap<String, String>,
+        /// Segment the capture file for long runs, see [`captures::Soak`]
+        #[serde(default)]
+        soak: Option<captures::Soak>,
+        /// The time, in milliseconds, between flushes of the capture file, no
+        /// less than [`captures::MINIMUM_INTERVAL`]
+        #[serde(default = "captures::default_interval_milliseconds")]
+        interval_milliseconds: u64,
     },
 }
 
@@ -87,6 +293,8 @@ impl Default for Telemetry {
         Self::Prometheus {
             prometheus_addr: "0.0.0.0:9000".parse().unwrap(),
             global_labels: HashMap::default(),
+            idle_timeout_seconds: None,
+            push_gateway: None,
         }
     }
 }
@@ -0,0 +1,489 @@
+//! Control generators, and the run's phases, while a run is underway
+//!
+//! Investigating a target's behavior interactively often calls for a quiet
+//! period mid-experiment: stop the load, watch the target settle, start the
+//! load again. The control [`Server`] is a small HTTP API through which each
+//! generator may be paused and resumed by its index:
+//!
+//! * `POST /generators/<idx>/pause` pauses generator `<idx>`, keeping its
+//!   connections open.
+//! * `POST /generators/<idx>/pause?close_connections=true` pauses generator
+//!   `<idx>`, closing its connections.
+//! * `POST /generators/<idx>/resume` resumes generator `<idx>`.
+//! * `GET /generators` responds with the [`State`] of each generator as JSON.
+//! * `POST /phase/advance` ends the run's current phase, see [`Advance`].
+//! * `POST /annotations` records the request body, an operator's note of no
+//!   more than 64 KiB, in the capture file, see [`crate::captures::annotate`].
+//!
+//! A paused generator holds its throttle: no capacity is taken and nothing is
+//! sent until it is resumed, when it continues at its configured rate. Only
+//! generators that hold connections of their own, the tcp, unix_stream,
+//! websocket and redis generators, close them when asked to. The clients of
+//! other generators keep idle connections pooled.
+//!
+//! A generator's [`Pause`] is held by each of its throttles, and so also
+//! carries the generator's phase jitter, see [`Pause::with_phase_jitter`].
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use hyper::{
+    body::HttpBody,
+    header,
+    server::conn::AddrStream,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, StatusCode,
+};
+use metrics::gauge;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use serde::Serialize;
+use tokio::sync::{watch, Notify};
+use tracing::{error, info};
+
+use crate::{captures, signals::Shutdown};
+
+#[derive(Debug)]
+/// Errors produced by [`Server`]
+pub enum Error {
+    /// Wrapper for [`hyper::Error`]
+    Hyper(hyper::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "state")]
+/// The state of a generator
+pub enum State {
+    /// The generator runs at its configured rate.
+    Running,
+    /// The generator sends nothing.
+    Paused {
+        /// Whether the generator closes its connections while paused.
+        close_connections: bool,
+    },
+}
+
+#[derive(Debug)]
+/// Pauses and resumes one generator, through its [`Pause`]
+pub struct Switch {
+    component: String,
+    snd: watch::Sender<State>,
+}
+
+impl Switch {
+    /// Create a new [`Switch`] for `component`, and the [`Pause`] it controls.
+    #[must_use]
+    pub fn new(component: String) -> (Self, Pause) {
+        let (snd, rcv) = watch::channel(State::Running);
+        (Self { component, snd }, Pause { rcv, jitter: None })
+    }
+
+    fn set(&self, state: State) {
+        let paused = if matches!(state, State::Paused { .. }) {
+            1.0
+        } else {
+            0.0
+        };
+        gauge!("generator_paused", paused, "component" => self.component.clone());
+        info!("{} is now {:?}", self.component, state);
+        self.snd.send_replace(state);
+    }
+
+    fn state(&self) -> State {
+        *self.snd.borrow()
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A generator's view of its [`Switch`]
+///
+/// The default [`Pause`] is never paused, for generators run without a control
+/// API.
+pub struct Pause {
+    rcv: watch::Receiver<State>,
+    jitter: Option<Arc<PhaseJitter>>,
+}
+
+#[derive(Debug)]
+/// See [`Pause::with_phase_jitter`].
+struct PhaseJitter {
+    /// The longest delay drawn.
+    maximum: Duration,
+    /// Shared by the generator's throttles, so that its connections do not
+    /// draw the same delays.
+    rng: Mutex<SmallRng>,
+}
+
+impl Default for Pause {
+    fn default() -> Self {
+        let (_, rcv) = watch::channel(State::Running);
+        Self { rcv, jitter: None }
+    }
+}
+
+impl Pause {
+    /// Delay the first release of capacity by each of the generator's
+    /// throttles a random time up to `maximum`, drawn from `seed`. The delay
+    /// shifts the phase of the generator's sends, never its rate. No jitter is
+    /// applied if `maximum` is zero.
+    #[must_use]
+    pub fn with_phase_jitter(mut self, maximum: Duration, seed: u64) -> Self {
+        self.jitter = (!maximum.is_zero()).then(|| {
+            Arc::new(PhaseJitter {
+                maximum,
+                rng: Mutex::new(SmallRng::seed_from_u64(seed)),
+            })
+        });
+        self
+    }
+
+    /// Draw the delay of a throttle's first release of capacity, if the
+    /// generator has phase jitter.
+    pub(crate) fn phase_jitter(&self) -> Option<Duration> {
+        self.jitter.as_ref().map(|jitter| {
+            let mut rng = jitter.rng.lock().expect("phase jitter lock poisoned");
+            rng.gen_range(Duration::ZERO..=jitter.maximum)
+        })
+    }
+
+    /// Whether the generator is paused and should close its connections.
+    pub(crate) fn closes_connections(&self) -> bool {
+        matches!(
+            *self.rcv.borrow(),
+            State::Paused {
+                close_connections: true
+            }
+        )
+    }
+
+    /// Wait until the generator is not paused.
+    pub(crate) async fn until_running(&mut self) {
+        while *self.rcv.borrow() != State::Running {
+            if self.rcv.changed().await.is_err() {
+                // The switch is gone and the state can no longer change.
+                return;
+            }
+        }
+    }
+
+    /// Wait until the generator's state changes. Never completes once the
+    /// switch is gone.
+    pub(crate) async fn changed(&mut self) {
+        if self.rcv.changed().await.is_err() {
+            futures::future::pending::<()>().await;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Advances a run from its current phase -- warmup or the experiment -- to the
+/// next, for a human in the loop to hold a phase until they are ready to
+/// proceed
+///
+/// Advances are counted. A phase ends on the first advance made after it
+/// began, see [`Advance::count`], so that an advance made as a phase ends is
+/// never carried into the next.
+pub struct Advance {
+    count: Arc<AtomicU64>,
+    notify: Arc<Notify>,
+}
+
+impl Advance {
+    /// Advance the run from its current phase.
+    pub fn advance(&self) {
+        info!("phase advance requested");
+        self.count.fetch_add(1, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    /// Return the number of advances made so far, taken as a phase begins.
+    #[must_use]
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Wait until the run is advanced from the phase that began when `since`
+    /// advances had been made.
+    pub async fn wait(&self, since: u64) {
+        loop {
+            // Created before the count is read, so that an advance made in
+            // between still wakes it.
+            let notified = self.notify.notified();
+            if self.count() > since {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// The longest request body read, an operator's note the only body the API
+/// takes.
+const MAXIMUM_BODY_BYTES: usize = 64 * 1024;
+
+/// Read `body` whole, or `None` if it is longer than [`MAXIMUM_BODY_BYTES`].
+async fn read_body(mut body: Body) -> Result<Option<Vec<u8>>, hyper::Error> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > MAXIMUM_BODY_BYTES {
+            return Ok(None);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Some(bytes))
+}
+
+fn respond(status: StatusCode, body: Body) -> Response<Body> {
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    response
+}
+
+/// Serve the control request `method` `path`, `query` being its query string
+/// and `body` its body.
+fn route(
+    switches: &[Switch],
+    advance: &Advance,
+    method: &Method,
+    path: &str,
+    query: Option<&str>,
+    body: &[u8],
+) -> Response<Body> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        (&Method::GET, ["generators"]) => {
+            let states: Vec<State> = switches.iter().map(Switch::state).collect();
+            let mut response = respond(
+                StatusCode::OK,
+                Body::from(serde_json::to_vec(&states).expect("states always serialize")),
+            );
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                header::HeaderValue::from_static("application/json"),
+            );
+            response
+        }
+        (&Method::POST, ["generators", idx, action]) => {
+            let switch = match idx.parse::<usize>().ok().and_then(|idx| switches.get(idx)) {
+                Some(switch) => switch,
+                None => return respond(StatusCode::NOT_FOUND, Body::from("no such generator")),
+            };
+            let state = match *action {
+                "pause" => State::Paused {
+                    close_connections: query
+                        .unwrap_or_default()
+                        .split('&')
+                        .any(|pair| pair == "close_connections=true"),
+                },
+                "resume" => State::Running,
+                _ => return respond(StatusCode::NOT_FOUND, Body::from("no such action")),
+            };
+            switch.set(state);
+            respond(StatusCode::NO_CONTENT, Body::empty())
+        }
+        (&Method::POST, ["phase", "advance"]) => {
+            advance.advance();
+            respond(StatusCode::NO_CONTENT, Body::empty())
+        }
+        (&Method::POST, ["annotations"]) => match std::str::from_utf8(body) {
+            Ok(note) if !note.trim().is_empty() => {
+                info!("operator note: {}", note.trim());
+                captures::annotate("note", note.trim());
+                respond(StatusCode::NO_CONTENT, Body::empty())
+            }
+            _ => respond(
+                StatusCode::BAD_REQUEST,
+                Body::from("note must be non-empty UTF-8"),
+            ),
+        },
+        _ => respond(StatusCode::NOT_FOUND, Body::empty()),
+    }
+}
+
+#[derive(Debug)]
+/// The control API server.
+pub struct Server {
+    addr: SocketAddr,
+    switches: Arc<Vec<Switch>>,
+    advance: Advance,
+    shutdown: Shutdown,
+}
+
+impl Server {
+    /// Create a new [`Server`] instance listening on `addr`, controlling the
+    /// generators of `switches` by their index and the run's phases through
+    /// `advance`.
+    #[must_use]
+    pub fn new(
+        addr: SocketAddr,
+        switches: Vec<Switch>,
+        advance: Advance,
+        shutdown: Shutdown,
+    ) -> Self {
+        Self {
+            addr,
+            switches: Arc::new(switches),
+            advance,
+            shutdown,
+        }
+    }
+
+    /// Run [`Server`] to completion or until a shutdown signal is received.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the server cannot bind to its address
+    /// or fails.
+    pub async fn run(mut self) -> Result<(), Error> {
+        let switches = Arc::clone(&self.switches);
+        let advance = self.advance.clone();
+        let service = make_service_fn(move |_: &AddrStream| {
+            let switches = Arc::clone(&switches);
+            let advance = advance.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |request: Request<Body>| {
+                    let switches = Arc::clone(&switches);
+                    let advance = advance.clone();
+                    async move {
+                        let (parts, body) = request.into_parts();
+                        let response = match read_body(body).await? {
+                            Some(body) => route(
+                                &switches,
+                                &advance,
+                                &parts.method,
+                                parts.uri.path(),
+                                parts.uri.query(),
+                                &body,
+                            ),
+                            None => respond(
+                                StatusCode::PAYLOAD_TOO_LARGE,
+                                Body::from("request body too large"),
+                            ),
+                        };
+                        Ok::<_, hyper::Error>(response)
+                    }
+                }))
+            }
+        });
+
+        let server = hyper::Server::try_bind(&self.addr)
+            .map_err(Error::Hyper)?
+            .serve(service);
+        info!("control API listening on {}", self.addr);
+        tokio::select! {
+            res = server => {
+                error!("control API shutdown unexpectedly");
+                res.map_err(Error::Hyper)
+            }
+            _ = self.shutdown.recv() => {
+                info!("shutdown signal received");
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::{Body, Method, StatusCode};
+    use proptest::prelude::*;
+    use tokio::time::{self, Duration};
+
+    use super::{read_body, route, Advance, State, Switch, MAXIMUM_BODY_BYTES};
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    // Advances made before a phase began do not end it. The first made after
+    // ends it for every waiter.
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(16))]
+        #[test]
+        fn advance_ends_current_phase(earlier in 0_u64..4, waiters in 1_usize..4) {
+            block_on(async {
+                let advance = Advance::default();
+                for _ in 0..earlier {
+                    advance.advance();
+                }
+                let since = advance.count();
+                prop_assert_eq!(since, earlier);
+                let held = time::timeout(Duration::from_millis(5), advance.wait(since)).await;
+                prop_assert!(held.is_err());
+                let handles: Vec<_> = (0..waiters)
+                    .map(|_| {
+                        let advance = advance.clone();
+                        tokio::spawn(async move { advance.wait(since).await })
+                    })
+                    .collect();
+                tokio::task::yield_now().await;
+                advance.advance();
+                for handle in handles {
+                    let ended = time::timeout(Duration::from_secs(1), handle).await;
+                    prop_assert!(matches!(ended, Ok(Ok(()))));
+                }
+                Ok(())
+            })?;
+        }
+    }
+
+    // Pausing then resuming a generator leaves it running, other generators
+    // untouched, and requests for generators that do not exist are not found.
+    proptest! {
+        #[test]
+        fn pause_resume(total in 1..8_usize, idx in 0..16_usize, close_connections: bool) {
+            let (switches, pauses): (Vec<Switch>, Vec<_>) = (0..total)
+                .map(|i| Switch::new(format!("generator_{}", i)))
+                .unzip();
+            let path = format!("/generators/{}/pause", idx);
+            let query = close_connections.then(|| "close_connections=true");
+            let advance = Advance::default();
+            let response = route(&switches, &advance, &Method::POST, &path, query, b"");
+            if idx >= total {
+                prop_assert_eq!(response.status(), StatusCode::NOT_FOUND);
+                return Ok(());
+            }
+            prop_assert_eq!(response.status(), StatusCode::NO_CONTENT);
+            for (i, pause) in pauses.iter().enumerate() {
+                let expected = if i == idx {
+                    State::Paused { close_connections }
+                } else {
+                    State::Running
+                };
+                prop_assert_eq!(*pause.rcv.borrow(), expected);
+                prop_assert_eq!(pause.closes_connections(), i == idx && close_connections);
+            }
+            let path = format!("/generators/{}/resume", idx);
+            route(&switches, &advance, &Method::POST, &path, None, b"");
+            prop_assert!(pauses.iter().all(|pause| *pause.rcv.borrow() == State::Running));
+        }
+    }
+
+    // A body is read whole however it is chunked, unless it is longer than
+    // the maximum.
+    proptest! {
+        #[test]
+        fn body_read_up_to_maximum(length in 0_usize..2 * MAXIMUM_BODY_BYTES, chunk in 1_usize..16_384) {
+            let bytes = vec![b'n'; length];
+            let chunks: Vec<Result<Vec<u8>, std::io::Error>> =
+                bytes.chunks(chunk).map(|chunk| Ok(chunk.to_vec())).collect();
+            let body = Body::wrap_stream(futures::stream::iter(chunks));
+            let read = block_on(read_body(body)).unwrap();
+            if length <= MAXIMUM_BODY_BYTES {
+                prop_assert_eq!(read, Some(bytes));
+            } else {
+                prop_assert_eq!(read, None);
+            }
+        }
+    }
+}
@@ -0,0 +1,449 @@
+//! Generate a Grafana dashboard for an experiment
+//!
+//! Standing up visualization for each experiment by hand is tedious. Given a
+//! lading [`Config`], [`grafana`] produces a dashboard with a row of panels for
+//! each kind of generator, blackhole and runtime stat collector configured and
+//! one for the target, each panel querying a metric that component emits.
+//!
+//! Queries are written against a Prometheus datasource, chosen when the
+//! dashboard is imported. They match both lading's live prometheus exporter
+//! and captures backfilled with `lading export`, whose counters carry a
+//! `_total` suffix. Metrics are not labeled by component instance, so two
+//! generators of the same kind share panels.
+
+use serde_json::{json, Value};
+
+use crate::{
+    blackhole,
+    config::{Blackhole, Config, Generator},
+    generator, runtime_stats,
+};
+
+/// Panels per row of the dashboard grid.
+const PANELS_PER_ROW: u64 = 2;
+/// The height of each panel, in grid units.
+const PANEL_HEIGHT: u64 = 8;
+/// The width of the dashboard grid, in grid units.
+const GRID_WIDTH: u64 = 24;
+
+#[derive(Debug, Clone, Copy)]
+/// How a metric is queried.
+enum Kind {
+    /// Plotted as a per-second rate.
+    Counter,
+    /// Plotted as-is.
+    Gauge,
+    /// Plotted as a per-second rate, for gauges that only increase.
+    CumulativeGauge,
+    /// Plotted at its 0.5 and 0.99 quantiles.
+    Histogram,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// A metric lading emits and how to plot it.
+struct Metric {
+    name: &'static str,
+    kind: Kind,
+    unit: &'static str,
+}
+
+const fn metric(name: &'static str, kind: Kind, unit: &'static str) -> Metric {
+    Metric { name, kind, unit }
+}
+
+/// Metrics every generator emits.
+const GENERATOR: [Metric; 5] = [
+    metric("bytes_written", Kind::Counter, "Bps"),
+    metric("achieved_bytes_per_second", Kind::Gauge, "Bps"),
+    metric("throttle_wait_seconds", Kind::Histogram, "s"),
+    metric("block_cache_bytes", Kind::Gauge, "bytes"),
+    metric("generator_complete", Kind::Gauge, "short"),
+];
+
+/// Metrics generators making requests emit.
+const REQUESTS: [Metric; 3] = [
+    metric("requests_sent", Kind::Counter, "reqps"),
+    metric("request_ok", Kind::Counter, "reqps"),
+    metric("request_failure", Kind::Counter, "reqps"),
+];
+
+/// Metrics every blackhole emits.
+const BLACKHOLE: [Metric; 1] = [metric("bytes_received", Kind::Counter, "Bps")];
+
+/// Metrics the observer emits about the target.
+const TARGET: [Metric; 9] = [
+    metric("rss_bytes", Kind::Gauge, "bytes"),
+    metric("user_time_seconds", Kind::CumulativeGauge, "percentunit"),
+    metric("kernel_time_seconds", Kind::CumulativeGauge, "percentunit"),
+    metric("num_threads", Kind::Gauge, "short"),
+    metric("vsize_bytes", Kind::Gauge, "bytes"),
+    metric("disk_read_bytes", Kind::CumulativeGauge, "Bps"),
+    metric("disk_write_bytes", Kind::CumulativeGauge, "Bps"),
+    metric("network_receive_bytes", Kind::CumulativeGauge, "Bps"),
+    metric("network_transmit_bytes", Kind::CumulativeGauge, "Bps"),
+];
+
+/// Metrics the Jolokia runtime stat collector emits.
+const JOLOKIA: [Metric; 5] = [
+    metric("jvm_heap_used_bytes", Kind::Gauge, "bytes"),
+    metric("jvm_heap_committed_bytes", Kind::Gauge, "bytes"),
+    metric("jvm_heap_max_bytes", Kind::Gauge, "bytes"),
+    metric("jvm_gc_collection_count", Kind::CumulativeGauge, "short"),
+    metric(
+        "jvm_gc_collection_seconds",
+        Kind::CumulativeGauge,
+        "percentunit",
+    ),
+];
+
+/// Metrics the expvar runtime stat collector emits.
+const EXPVAR: [Metric; 6] = [
+    metric("go_heap_alloc_bytes", Kind::Gauge, "bytes"),
+    metric("go_heap_sys_bytes", Kind::Gauge, "bytes"),
+    metric("go_heap_objects", Kind::Gauge, "short"),
+    metric("go_gc_next_bytes", Kind::Gauge, "bytes"),
+    metric("go_gc_count", Kind::CumulativeGauge, "short"),
+    metric("go_gc_pause_seconds", Kind::CumulativeGauge, "percentunit"),
+];
+
+/// Metrics antagonists emit.
+const ANTAGONIST: [Metric; 5] = [
+    metric("antagonist_active", Kind::Gauge, "short"),
+    metric("antagonist_bytes_dirtied", Kind::Counter, "Bps"),
+    metric("antagonist_disk_bytes_written", Kind::Counter, "Bps"),
+    metric("antagonist_disk_fsyncs", Kind::Counter, "short"),
+    metric("antagonist_disk_fsync_seconds", Kind::Histogram, "s"),
+];
+
+/// Metrics the watchdog emits about the target.
+const WATCHDOG: [Metric; 2] = [
+    metric("target_stalled", Kind::Gauge, "short"),
+    metric("target_stall", Kind::Counter, "short"),
+];
+
+/// Metrics the supervisor emits about generators and blackholes.
+const SUPERVISOR: [Metric; 2] = [
+    metric("component_restart", Kind::Counter, "short"),
+    metric("component_failure", Kind::Counter, "short"),
+];
+
+/// The name and metrics of a generator's kind.
+fn generator_metrics(config: &generator::Config) -> (&'static str, Vec<Metric>) {
+    let mut metrics = GENERATOR.to_vec();
+    let name = match config {
+        generator::Config::Tcp(_) => {
+            metrics.push(metric("connection_failure", Kind::Counter, "short"));
+            metrics.push(metric("request_failure", Kind::Counter, "short"));
+            metrics.push(metric("connect_seconds", Kind::Histogram, "s"));
+            metrics.push(metric("first_write_seconds", Kind::Histogram, "s"));
+            metrics.push(metric("heartbeats_sent", Kind::Counter, "short"));
+            metrics.push(metric("connection_half_closed", Kind::Counter, "short"));
+            metrics.push(metric("bytes_read", Kind::Counter, "Bps"));
+            metrics.push(metric("messages_read", Kind::Counter, "short"));
+            metrics.push(metric("request_ok", Kind::Counter, "short"));
+            "tcp"
+        }
+        generator::Config::Http(_) => {
+            metrics.extend(REQUESTS);
+            "http"
+        }
+        generator::Config::SplunkHec(_) => {
+            metrics.extend(REQUESTS);
+            metrics.push(metric("request_timeout", Kind::Counter, "reqps"));
+            metrics.push(metric("ack_ids_acked", Kind::Counter, "short"));
+            metrics.push(metric("ack_ids_dropped", Kind::Counter, "short"));
+            "splunk_hec"
+        }
+        generator::Config::Kafka(_) => {
+            metrics.extend(REQUESTS);
+            "kafka"
+        }
+        generator::Config::FileGen(_) => {
+            metrics.push(metric("lines_written", Kind::Counter, "short"));
+            metrics.push(metric("file_rotated", Kind::Counter, "short"));
+            metrics.push(metric("current_target_size_bytes", Kind::Gauge, "bytes"));
+            "file_gen"
+        }
+        generator::Config::FileTree(_) => {
+            metrics.push(metric("operations", Kind::Counter, "ops"));
+            metrics.push(metric("operation_failure", Kind::Counter, "short"));
+            metrics.push(metric("operation_skipped", Kind::Counter, "short"));
+            metrics.push(metric("tree_entries", Kind::Gauge, "short"));
+            "file_tree"
+        }
+        generator::Config::ProcessChurn(_) => {
+            metrics.push(metric("processes_spawned", Kind::Counter, "short"));
+            metrics.push(metric("processes_exited", Kind::Counter, "short"));
+            metrics.push(metric("processes_killed", Kind::Counter, "short"));
+            metrics.push(metric("spawn_failure", Kind::Counter, "short"));
+            metrics.push(metric("spawn_skipped", Kind::Counter, "short"));
+            metrics.push(metric("processes_live", Kind::Gauge, "short"));
+            "process_churn"
+        }
+        generator::Config::Grpc(_) => {
+            metrics.extend(REQUESTS);
+            "grpc"
+        }
+        generator::Config::UnixStream(_) => {
+            metrics.push(metric("connection_failure", Kind::Counter, "short"));
+            metrics.push(metric("request_failure", Kind::Counter, "short"));
+            metrics.push(metric("bytes_read", Kind::Counter, "Bps"));
+            metrics.push(metric("messages_read", Kind::Counter, "short"));
+            "unix_stream"
+        }
+        #[cfg(target_os = "linux")]
+        generator::Config::Vsock(_) => {
+            metrics.push(metric("connection_failure", Kind::Counter, "short"));
+            metrics.push(metric("request_failure", Kind::Counter, "short"));
+            "vsock"
+        }
+        generator::Config::Fifo(_) => {
+            metrics.push(metric("request_failure", Kind::Counter, "short"));
+            metrics.push(metric("reader_disconnected", Kind::Counter, "short"));
+            "fifo"
+        }
+        generator::Config::Stdin(_) => {
+            metrics.push(metric("request_failure", Kind::Counter, "short"));
+            metrics.push(metric("stdin_closed", Kind::Counter, "short"));
+            "stdin"
+        }
+        generator::Config::Websocket(_) => {
+            metrics.push(metric("connection_failure", Kind::Counter, "short"));
+            metrics.push(metric("request_failure", Kind::Counter, "short"));
+            metrics.push(metric("heartbeats_sent", Kind::Counter, "short"));
+            "websocket"
+        }
+        generator::Config::Redis(_) => {
+            metrics.extend(REQUESTS);
+            metrics.push(metric("connection_failure", Kind::Counter, "short"));
+            "redis"
+        }
+        generator::Config::Statsd(_) => {
+            metrics.push(metric("packets_sent", Kind::Counter, "pps"));
+            metrics.push(metric("request_failure", Kind::Counter, "short"));
+            "statsd"
+        }
+        generator::Config::Sqs(_) => {
+            metrics.extend(REQUESTS);
+            metrics.push(metric("messages_sent", Kind::Counter, "short"));
+            "sqs"
+        }
+        generator::Config::Elasticsearch(_) => {
+            metrics.extend(REQUESTS);
+            metrics.push(metric("documents_indexed", Kind::Counter, "short"));
+            metrics.push(metric("documents_failed", Kind::Counter, "short"));
+            "elasticsearch"
+        }
+        generator::Config::Zipkin(_) => {
+            metrics.extend(REQUESTS);
+            metrics.push(metric("spans_sent", Kind::Counter, "short"));
+            "zipkin"
+        }
+        generator::Config::Jaeger(_) => {
+            metrics.push(metric("packets_sent", Kind::Counter, "pps"));
+            metrics.push(metric("spans_sent", Kind::Counter, "short"));
+            metrics.push(metric("request_failure", Kind::Counter, "short"));
+            "jaeger"
+        }
+        generator::Config::NetFlow(_) => {
+            metrics.push(metric("packets_sent", Kind::Counter, "pps"));
+            metrics.push(metric("flows_sent", Kind::Counter, "short"));
+            metrics.push(metric("request_failure", Kind::Counter, "short"));
+            "netflow"
+        }
+        generator::Config::PubSub(_) => {
+            metrics.extend(REQUESTS);
+            metrics.push(metric("messages_sent", Kind::Counter, "short"));
+            "pubsub"
+        }
+    };
+    (name, metrics)
+}
+
+/// The name and metrics of a blackhole's kind.
+fn blackhole_metrics(config: &blackhole::Config) -> (&'static str, Vec<Metric>) {
+    let mut metrics = BLACKHOLE.to_vec();
+    let name = match config {
+        blackhole::Config::Tcp(_) => {
+            metrics.push(metric("connection_accepted", Kind::Counter, "short"));
+            metrics.push(metric("message_received", Kind::Counter, "short"));
+            "tcp"
+        }
+        blackhole::Config::Http(_) => {
+            metrics.push(metric("requests_received", Kind::Counter, "reqps"));
+            "http"
+        }
+        blackhole::Config::SplunkHec(_) => {
+            metrics.push(metric("requests_received", Kind::Counter, "reqps"));
+            "splunk_hec"
+        }
+        blackhole::Config::Udp(_) => {
+            metrics.push(metric("packet_received", Kind::Counter, "short"));
+            metrics.push(metric(
+                "kernel_packets_dropped",
+                Kind::CumulativeGauge,
+                "short",
+            ));
+            "udp"
+        }
+        blackhole::Config::Sqs(_) => {
+            metrics.push(metric("requests_received", Kind::Counter, "reqps"));
+            "sqs"
+        }
+        #[cfg(target_os = "linux")]
+        blackhole::Config::Vsock(_) => {
+            metrics.push(metric("connection_accepted", Kind::Counter, "short"));
+            "vsock"
+        }
+    };
+    if config.expected_rate().is_some() {
+        metrics.push(metric("received_bytes_per_second", Kind::Gauge, "Bps"));
+        metrics.push(metric("slo_violation", Kind::Counter, "short"));
+    }
+    (name, metrics)
+}
+
+/// The PromQL queries plotting `metric`.
+fn queries(metric: &Metric) -> Vec<String> {
+    match metric.kind {
+        Kind::Counter => vec![format!(
+            "rate({{__name__=~\"{}(_total)?\"}}[1m])",
+            metric.name
+        )],
+        Kind::Gauge => vec![metric.name.to_string()],
+        Kind::CumulativeGauge => vec![format!("rate({}[1m])", metric.name)],
+        Kind::Histogram => ["0.5", "0.99"]
+            .iter()
+            .map(|quantile| format!("{}{{quantile=\"{}\"}}", metric.name, quantile))
+            .collect(),
+    }
+}
+
+#[derive(Debug, Default)]
+/// Lays panels out on the dashboard grid, assigning ids.
+struct Layout {
+    panels: Vec<Value>,
+    next_id: u64,
+    y: u64,
+    column: u64,
+}
+
+impl Layout {
+    fn id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn row(&mut self, title: &str, metrics: &[Metric]) {
+        if self.column != 0 {
+            self.y += PANEL_HEIGHT;
+            self.column = 0;
+        }
+        let id = self.id();
+        self.panels.push(json!({
+            "id": id,
+            "type": "row",
+            "title": title,
+            "collapsed": false,
+            "panels": [],
+            "gridPos": { "x": 0, "y": self.y, "w": GRID_WIDTH, "h": 1 },
+        }));
+        self.y += 1;
+
+        let width = GRID_WIDTH / PANELS_PER_ROW;
+        for metric in metrics {
+            let targets: Vec<Value> = queries(metric)
+                .into_iter()
+                .zip('A'..)
+                .map(|(expr, ref_id)| json!({ "expr": expr, "refId": ref_id.to_string() }))
+                .collect();
+            let id = self.id();
+            self.panels.push(json!({
+                "id": id,
+                "type": "timeseries",
+                "title": metric.name,
+                "datasource": { "type": "prometheus", "uid": "${datasource}" },
+                "targets": targets,
+                "fieldConfig": { "defaults": { "unit": metric.unit }, "overrides": [] },
+                "gridPos": { "x": self.column * width, "y": self.y, "w": width, "h": PANEL_HEIGHT },
+            }));
+            self.column += 1;
+            if self.column == PANELS_PER_ROW {
+                self.y += PANEL_HEIGHT;
+                self.column = 0;
+            }
+        }
+    }
+}
+
+/// Produce a Grafana dashboard, titled `title`, for the components of
+/// `config`.
+#[must_use]
+pub fn grafana(config: &Config, title: &str) -> Value {
+    let mut layout = Layout::default();
+
+    let generators: Vec<&generator::Config> = match config.generator {
+        Generator::One(ref cfg) => vec![&**cfg],
+        Generator::Many(ref cfgs) => cfgs.iter().collect(),
+    };
+    let mut seen = Vec::new();
+    for cfg in generators {
+        let (name, metrics) = generator_metrics(cfg);
+        if !seen.contains(&name) {
+            seen.push(name);
+            layout.row(&format!("generator: {}", name), &metrics);
+        }
+    }
+
+    let blackholes: Vec<&blackhole::Config> = match config.blackhole {
+        Some(Blackhole::One(ref cfg)) => vec![&**cfg],
+        Some(Blackhole::Many(ref cfgs)) => cfgs.iter().collect(),
+        None => vec![],
+    };
+    let mut seen = Vec::new();
+    for cfg in blackholes {
+        let (name, metrics) = blackhole_metrics(cfg);
+        if !seen.contains(&name) {
+            seen.push(name);
+            layout.row(&format!("blackhole: {}", name), &metrics);
+        }
+    }
+
+    layout.row("target", &TARGET);
+    if config.watchdog.is_some() {
+        layout.row("watchdog", &WATCHDOG);
+    }
+
+    let mut seen = Vec::new();
+    for cfg in &config.runtime_stats {
+        let (name, metrics): (_, &[Metric]) = match cfg {
+            runtime_stats::Config::Jolokia { .. } => ("jolokia", &JOLOKIA),
+            runtime_stats::Config::Expvar { .. } => ("expvar", &EXPVAR),
+        };
+        if !seen.contains(&name) {
+            seen.push(name);
+            layout.row(&format!("runtime: {}", name), metrics);
+        }
+    }
+    if !config.antagonist.is_empty() {
+        layout.row("antagonist", &ANTAGONIST);
+    }
+    layout.row("supervisor", &SUPERVISOR);
+
+    json!({
+        "title": title,
+        "uid": null,
+        "schemaVersion": 36,
+        "editable": true,
+        "time": { "from": "now-1h", "to": "now" },
+        "templating": {
+            "list": [{
+                "name": "datasource",
+                "label": "Datasource",
+                "type": "datasource",
+                "query": "prometheus",
+            }],
+        },
+        "panels": layout.panels,
+    })
+}
@@ -0,0 +1,245 @@
+//! Verify that a configuration's offered load is determined by its seeds
+//!
+//! Comparing two runs of the same configuration is only meaningful if both
+//! offered the target the same load. Each generator builds its block cache
+//! from its seed, but a payload may also draw on sources the seed does not
+//! determine -- the wall clock, a file on disk -- and the order blocks reach
+//! the target may depend on scheduling. [`verify`] builds each generator's
+//! block caches twice and compares them, and [`entropy`] reports every source
+//! of entropy each generator draws on, seeded or not.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt,
+    hash::{Hash, Hasher},
+    path::PathBuf,
+};
+
+use crate::{
+    block::Block,
+    config::{self, Config},
+    generator,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A source of entropy a generator draws on
+pub struct Entropy {
+    /// The generator, `generator_0` say
+    pub component: String,
+    /// The source
+    pub source: String,
+    /// Whether the source is determined by the generator's seed, or by its
+    /// configuration
+    pub determined: bool,
+}
+
+impl fmt::Display for Entropy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = if self.determined {
+            "determined"
+        } else {
+            "NOT determined"
+        };
+        write!(f, "{}: {} ({})", self.component, self.source, kind)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The result of building a generator's block caches twice, see [`verify`]
+pub struct Verification {
+    /// A digest of the first build's block caches
+    pub first: u64,
+    /// A digest of the second build's block caches
+    pub second: u64,
+}
+
+impl Verification {
+    /// Whether both builds were byte-identical.
+    #[must_use]
+    pub fn identical(&self) -> bool {
+        self.first == self.second
+    }
+}
+
+/// Digest the bytes of `block_caches`, in order.
+fn digest(block_caches: &[Vec<Block>]) -> u64 {
+    // `DefaultHasher::new` is keyed identically every time, so digests are
+    // comparable within, and across, processes built alike.
+    let mut hasher = DefaultHasher::new();
+    for block_cache in block_caches {
+        block_cache.len().hash(&mut hasher);
+        for block in block_cache {
+            block.bytes.hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+fn generators(config: &Config) -> Vec<&generator::Config> {
+    match config.generator {
+        config::Generator::One(ref cfg) => vec![cfg.as_ref()],
+        config::Generator::Many(ref cfgs) => cfgs.iter().collect(),
+    }
+}
+
+/// Build each generator's block caches twice, returning the digests of both
+/// builds by component name. Only one build is held in memory at a time.
+///
+/// # Errors
+///
+/// Function will return an error if a generator's block cache cannot be built.
+pub fn verify(config: &Config) -> Result<Vec<(String, Verification)>, generator::Error> {
+    generators(config)
+        .into_iter()
+        .enumerate()
+        .map(|(idx, cfg)| {
+            let first = digest(&cfg.block_caches()?);
+            let second = digest(&cfg.block_caches()?);
+            Ok((format!("generator_{}", idx), Verification { first, second }))
+        })
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Report the entropy of a stream generator's `variant` to `push`, returning
+/// its static path, if any, and its connections.
+fn stream_entropy<'a, F>(
+    variant: &'a generator::tcp::GeneratorVariant,
+    push: &mut F,
+) -> (Option<&'a PathBuf>, u16)
+where
+    F: FnMut(String, bool),
+{
+    match variant {
+        generator::tcp::GeneratorVariant::Syslog5424 => {
+            push("wall clock, syslog5424 timestamps".to_string(), false);
+            (None, 1)
+        }
+        generator::tcp::GeneratorVariant::Static { static_path } => (Some(static_path), 1),
+        _ => (None, 1),
+    }
+}
+
+/// Report every source of entropy each generator of `config` draws on.
+#[must_use]
+pub fn entropy(config: &Config) -> Vec<Entropy> {
+    let mut sources = Vec::new();
+    for (idx, cfg) in generators(config).into_iter().enumerate() {
+        let component = format!("generator_{}", idx);
+        let mut push = |source: String, determined: bool| {
+            sources.push(Entropy {
+                component: component.clone(),
+                source,
+                determined,
+            });
+        };
+        push(format!("seed {}", hex(&cfg.seed())), true);
+        if let Some(ref jitter) = config.generator_jitter {
+            push(
+                format!(
+                    "generator jitter seed {}, the timing of sends but not their contents",
+                    hex(&jitter.seed)
+                ),
+                true,
+            );
+        }
+        let (static_path, parallel_connections) = match cfg {
+            generator::Config::Tcp(generator::tcp::Config { variant, .. })
+            | generator::Config::UnixStream(generator::unix_stream::Config { variant, .. })
+            | generator::Config::Fifo(generator::fifo::Config { variant, .. })
+            | generator::Config::Stdin(generator::stdin::Config { variant, .. }) => {
+                stream_entropy(variant, &mut push)
+            }
+            #[cfg(target_os = "linux")]
+            generator::Config::Vsock(generator::vsock::Config { variant, .. }) => {
+                stream_entropy(variant, &mut push)
+            }
+            generator::Config::Http(conf) => match conf.method.variant() {
+                generator::http::Variant::Static { static_path } => {
+                    (Some(static_path), conf.parallel_connections)
+                }
+                _ => (None, conf.parallel_connections),
+            },
+            generator::Config::SplunkHec(conf) => (None, conf.parallel_connections),
+            generator::Config::Zipkin(conf) => (None, conf.parallel_connections),
+            generator::Config::Kafka(_)
+            | generator::Config::Statsd(_)
+            | generator::Config::Jaeger(_)
+            | generator::Config::NetFlow(_) => (None, 1),
+            generator::Config::FileTree(_) => {
+                push(
+                    "scheduling, creates, renames and deletes interleave by the clock".to_string(),
+                    false,
+                );
+                (None, 1)
+            }
+            generator::Config::ProcessChurn(_) => {
+                push(
+                    "scheduling, processes exit and are killed by the clock".to_string(),
+                    false,
+                );
+                (None, 1)
+            }
+            generator::Config::Grpc(conf) => match conf.variant {
+                generator::http::Variant::Static { ref static_path } => (Some(static_path), 1),
+                _ => (None, 1),
+            },
+            generator::Config::Websocket(conf) => match conf.variant {
+                generator::http::Variant::Static { ref static_path } => {
+                    (Some(static_path), conf.parallel_connections)
+                }
+                _ => (None, conf.parallel_connections),
+            },
+            generator::Config::Redis(conf) => match conf.variant {
+                generator::http::Variant::Static { ref static_path } => (Some(static_path), 1),
+                _ => (None, 1),
+            },
+            generator::Config::Sqs(generator::sqs::Config {
+                variant,
+                parallel_connections,
+                ..
+            })
+            | generator::Config::Elasticsearch(generator::elasticsearch::Config {
+                variant,
+                parallel_connections,
+                ..
+            })
+            | generator::Config::PubSub(generator::pubsub::Config {
+                variant,
+                parallel_connections,
+                ..
+            }) => match variant {
+                generator::http::Variant::Static { static_path } => {
+                    (Some(static_path), *parallel_connections)
+                }
+                _ => (None, *parallel_connections),
+            },
+            generator::Config::FileGen(conf) => match conf.variant {
+                generator::file_gen::Variant::Static { ref static_path } => (Some(static_path), 1),
+                _ => (None, 1),
+            },
+        };
+        if let Some(static_path) = static_path {
+            push(
+                format!(
+                    "contents of {}, determined while unchanged",
+                    static_path.display()
+                ),
+                true,
+            );
+        }
+        if parallel_connections > 1 {
+            push(
+                format!(
+                    "scheduling, blocks are sent over {} connections at once and interleave",
+                    parallel_connections
+                ),
+                false,
+            );
+        }
+    }
+    sources
+}
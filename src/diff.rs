@@ -0,0 +1,217 @@
+//! Compare the records lading sent against those a blackhole received.
+//!
+//! For payload formats where each record carries an identity -- the `json`
+//! payload's `id` field, for instance -- the records sent to the target and
+//! those the target emitted can be matched up. [`diff`] does this for newline
+//! delimited JSON, where a line may also hold an array of records, reporting
+//! loss, duplication, reordering and mutation. Records are compared by value,
+//! not by their encoding, so a target that reorders an object's keys has not
+//! mutated it.
+//!
+//! Received records are usually a sample, see [`crate::blackhole::sample`].
+//! When sampling a fraction of payloads loss is expected and should be read in
+//! proportion.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, BufRead},
+};
+
+use serde_json::Value;
+
+#[derive(Debug)]
+/// Errors produced by [`diff`]
+pub enum Error {
+    /// Wrapper for [`std::io::Error`].
+    Io(io::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// The result of comparing sent and received records, see [`diff`].
+pub struct Report {
+    /// Identified records sent.
+    pub sent: u64,
+    /// Identified records received.
+    pub received: u64,
+    /// Records sent but never received.
+    pub lost: u64,
+    /// Received records whose identity had already been received.
+    pub duplicated: u64,
+    /// Received records that arrived after a record sent later than them.
+    pub reordered: u64,
+    /// Received records whose value differs from the sent record of the same
+    /// identity.
+    pub mutated: u64,
+    /// Received records whose identity was never sent.
+    pub unexpected: u64,
+    /// Records, sent or received, that could not be identified: not JSON, or
+    /// lacking the identity field.
+    pub unidentified: u64,
+}
+
+impl Report {
+    /// Whether every sent record was received exactly once, in order and
+    /// unchanged, and nothing else was received.
+    #[must_use]
+    pub fn is_clean(&self) -> bool {
+        self.lost == 0
+            && self.duplicated == 0
+            && self.reordered == 0
+            && self.mutated == 0
+            && self.unexpected == 0
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "sent: {}", self.sent)?;
+        writeln!(f, "received: {}", self.received)?;
+        writeln!(f, "lost: {}", self.lost)?;
+        writeln!(f, "duplicated: {}", self.duplicated)?;
+        writeln!(f, "reordered: {}", self.reordered)?;
+        writeln!(f, "mutated: {}", self.mutated)?;
+        writeln!(f, "unexpected: {}", self.unexpected)?;
+        write!(f, "unidentified: {}", self.unidentified)
+    }
+}
+
+/// Call `f` with each record in `reader`, and its identity if it has one.
+fn for_each_record<R, F>(reader: R, id_field: &str, mut f: F) -> Result<(), Error>
+where
+    R: BufRead,
+    F: FnMut(Option<String>, Value),
+{
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let records = match serde_json::from_str::<Value>(&line) {
+            Ok(Value::Array(records)) => records,
+            Ok(record) => vec![record],
+            Err(_) => {
+                f(None, Value::Null);
+                continue;
+            }
+        };
+        for record in records {
+            let id = record.get(id_field).map(Value::to_string);
+            f(id, record);
+        }
+    }
+    Ok(())
+}
+
+/// Compare the records in `sent` against those in `received`, matching them by
+/// the value of `id_field`.
+///
+/// # Errors
+///
+/// Function will return an error if either reader cannot be read.
+pub fn diff<S, R>(sent: S, received: R, id_field: &str) -> Result<Report, Error>
+where
+    S: BufRead,
+    R: BufRead,
+{
+    let mut report = Report::default();
+
+    // Identity to the record's position in the sent order and its value. A
+    // duplicate identity in the sent records keeps its first occurrence.
+    let mut expected: HashMap<String, (u64, Value, bool)> = HashMap::new();
+    for_each_record(sent, id_field, |id, record| match id {
+        Some(id) => {
+            let position = report.sent;
+            report.sent += 1;
+            expected.entry(id).or_insert((position, record, false));
+        }
+        None => report.unidentified += 1,
+    })?;
+
+    let mut latest: Option<u64> = None;
+    for_each_record(received, id_field, |id, record| {
+        let id = match id {
+            Some(id) => id,
+            None => {
+                report.unidentified += 1;
+                return;
+            }
+        };
+        report.received += 1;
+        let (position, value, seen) = match expected.get_mut(&id) {
+            Some(entry) => entry,
+            None => {
+                report.unexpected += 1;
+                return;
+            }
+        };
+        if *seen {
+            report.duplicated += 1;
+            return;
+        }
+        *seen = true;
+        if *value != record {
+            report.mutated += 1;
+        }
+        match latest {
+            Some(latest) if *position < latest => report.reordered += 1,
+            _ => latest = Some(*position),
+        }
+    })?;
+
+    report.lost = expected.values().filter(|(_, _, seen)| !seen).count() as u64;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::{collection, prelude::*};
+
+    use super::diff;
+
+    fn records(ids: &[u16]) -> String {
+        ids.iter()
+            .map(|id| format!("{{\"id\":{},\"value\":\"{}\"}}\n", id, id))
+            .collect()
+    }
+
+    // A target that emits exactly what it was sent, in order, produces a
+    // clean report.
+    proptest! {
+        #[test]
+        fn identity_is_clean(ids in collection::hash_set(any::<u16>(), 0..256)) {
+            let ids: Vec<u16> = ids.into_iter().collect();
+            let sent = records(&ids);
+            let report = diff(sent.as_bytes(), sent.as_bytes(), "id").unwrap();
+            prop_assert!(report.is_clean());
+            prop_assert_eq!(report.sent, ids.len() as u64);
+            prop_assert_eq!(report.received, ids.len() as u64);
+        }
+    }
+
+    // Dropping a suffix of the sent records reports exactly that many lost,
+    // duplicating a prefix exactly that many duplicated.
+    proptest! {
+        #[test]
+        fn loss_and_duplication_counted(ids in collection::hash_set(any::<u16>(), 1..256), lost in 0..256_usize, duplicated in 0..256_usize) {
+            let ids: Vec<u16> = ids.into_iter().collect();
+            let lost = lost % ids.len();
+            let kept = &ids[..ids.len() - lost];
+            let duplicated = duplicated % (kept.len() + 1);
+            let mut received = records(kept);
+            received.push_str(&records(&kept[..duplicated]));
+
+            let report = diff(records(&ids).as_bytes(), received.as_bytes(), "id").unwrap();
+            prop_assert_eq!(report.lost, lost as u64);
+            prop_assert_eq!(report.duplicated, duplicated as u64);
+            prop_assert_eq!(report.reordered, 0);
+            prop_assert_eq!(report.mutated, 0);
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! Export capture files to other formats
+//!
+//! Capture files, see [`crate::captures`], are convenient for lading to write
+//! but not for browsing past runs. [`openmetrics`] converts a capture file into
+//! [OpenMetrics] text with timestamps, suitable for backfilling into
+//! Prometheus with `promtool tsdb create-blocks-from openmetrics` and viewing
+//! in Grafana.
+//!
+//! Counters keep their kind, their samples suffixed `_total`. Gauges are
+//! gauges. Histograms are captured as quantiles and are exported as gauges
+//! labeled by `quantile`. Every sample is labeled with the `run_id` of the run
+//! that captured it.
+//!
+//! [OpenMetrics]: https://github.com/OpenObservability/OpenMetrics/blob/main/specification/OpenMetrics.md
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, BufRead, Write},
+};
+
+use serde_json::Value;
+
+use crate::captures;
+
+#[derive(Debug)]
+/// Errors produced by [`openmetrics`]
+pub enum Error {
+    /// Wrapper for [`std::io::Error`].
+    Io(io::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// A summary of an export, see [`openmetrics`].
+pub struct Summary {
+    /// Samples written.
+    pub samples: u64,
+    /// Capture lines that were torn, corrupt or not capture lines.
+    pub skipped: u64,
+}
+
+/// The timestamped values of one series.
+type Series = Vec<(u128, String)>;
+
+#[derive(Debug, Default)]
+/// The samples of one metric family, series in order of first appearance.
+struct Family {
+    kind: &'static str,
+    index: HashMap<String, usize>,
+    series: Vec<(String, Series)>,
+}
+
+/// Replace any character not allowed in a metric or label name with `_`.
+fn sanitize_name(name: &str) -> String {
+    name.chars()
+        .enumerate()
+        .map(|(idx, c)| {
+            if c.is_ascii_alphabetic() || c == '_' || (idx > 0 && c.is_ascii_digit()) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Escape `value` for use as a label value.
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Render `labels` as an OpenMetrics label set, sorted by name.
+fn label_set(labels: &BTreeMap<String, String>) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value)))
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+/// Render a millisecond timestamp as OpenMetrics seconds.
+fn timestamp(millis: u128) -> String {
+    format!("{}.{:03}", millis / 1000, millis % 1000)
+}
+
+/// A capture line, parsed.
+struct Sample {
+    name: String,
+    kind: &'static str,
+    labels: String,
+    time: u128,
+    value: String,
+}
+
+/// Parse a capture record into a [`Sample`]. Returns `None` if `record` is not
+/// a capture line.
+fn parse(record: &str) -> Option<Sample> {
+    let record: HashMap<String, Value> = serde_json::from_str(record).ok()?;
+    let name = sanitize_name(record.get("metric_name")?.as_str()?);
+    let time = u128::from(record.get("time")?.as_u64()?);
+    let value = match record.get("value")? {
+        Value::Number(value) => value.to_string(),
+        _ => return None,
+    };
+    let kind = match record.get("metric_kind")?.as_str()? {
+        "counter" => "counter",
+        "gauge" | "histogram" => "gauge",
+        _ => return None,
+    };
+    let labels: BTreeMap<String, String> = record
+        .iter()
+        // The `run_id` is kept as a label, the metric's name and kind are not.
+        .filter(|(field, _)| {
+            !captures::SAMPLE_FIELDS.contains(&field.as_str())
+                && !matches!(field.as_str(), "metric_name" | "metric_kind")
+        })
+        .filter_map(|(field, value)| {
+            value
+                .as_str()
+                .map(|value| (sanitize_name(field), value.to_string()))
+        })
+        .collect();
+    Some(Sample {
+        name,
+        kind,
+        labels: label_set(&labels),
+        time,
+        value,
+    })
+}
+
+/// Convert the capture lines of `reader` to OpenMetrics text, written to
+/// `writer`.
+///
+/// The whole capture is held in memory: OpenMetrics requires that the samples
+/// of a metric family, and of each series within it, be contiguous.
+///
+/// # Errors
+///
+/// Function will return an error if reading from `reader` or writing to
+/// `writer` fails.
+pub fn openmetrics<R, W>(reader: R, mut writer: W) -> Result<Summary, Error>
+where
+    R: BufRead,
+    W: Write,
+{
+    let recovered = captures::recover(reader)?;
+    let mut summary = Summary {
+        samples: 0,
+        skipped: recovered.corrupt,
+    };
+
+    let mut families: BTreeMap<String, Family> = BTreeMap::new();
+    for record in &recovered.records {
+        let sample = match parse(record) {
+            Some(sample) => sample,
+            None => {
+                summary.skipped += 1;
+                continue;
+            }
+        };
+        let family = families.entry(sample.name).or_insert_with(|| Family {
+            kind: sample.kind,
+            ..Family::default()
+        });
+        let idx = match family.index.get(&sample.labels) {
+            Some(idx) => *idx,
+            None => {
+                family
+                    .index
+                    .insert(sample.labels.clone(), family.series.len());
+                family.series.push((sample.labels, Vec::new()));
+                family.series.len() - 1
+            }
+        };
+        family.series[idx].1.push((sample.time, sample.value));
+    }
+
+    for (name, family) in &families {
+        writeln!(writer, "# TYPE {} {}", name, family.kind)?;
+        let sample_name = if family.kind == "counter" {
+            format!("{}_total", name)
+        } else {
+            name.clone()
+        };
+        for (labels, samples) in &family.series {
+            for (time, value) in samples {
+                writeln!(
+                    writer,
+                    "{}{} {} {}",
+                    sample_name,
+                    labels,
+                    value,
+                    timestamp(*time)
+                )?;
+                summary.samples += 1;
+            }
+        }
+    }
+    writeln!(writer, "# EOF")?;
+    writer.flush()?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::{openmetrics, sanitize_name};
+
+    // Sanitized names are always valid OpenMetrics metric names.
+    proptest! {
+        #[test]
+        fn sanitized_names_valid(name in "\\PC+") {
+            let name = sanitize_name(&name);
+            let mut chars = name.chars();
+            let first = chars.next().unwrap();
+            prop_assert!(first.is_ascii_alphabetic() || first == '_');
+            prop_assert!(chars.all(|c| c.is_ascii_alphanumeric() || c == '_'));
+        }
+    }
+
+    // Every capture line becomes exactly one sample, and the output is
+    // terminated.
+    proptest! {
+        #[test]
+        fn one_sample_per_line(values in proptest::collection::vec((0..4_u8, any::<u32>()), 0..64)) {
+            let capture: String = values
+                .iter()
+                .enumerate()
+                .map(|(time, (name, value))| {
+                    format!(
+                        "{{\"run_id\":\"r\",\"time\":{},\"fetch_index\":0,\"metric_name\":\"m{}\",\"metric_kind\":\"counter\",\"value\":{},\"component\":\"c\"}}\n",
+                        time, name, value
+                    )
+                })
+                .collect();
+            let mut output = Vec::new();
+            let summary = openmetrics(capture.as_bytes(), &mut output).unwrap();
+            prop_assert_eq!(summary.samples, values.len() as u64);
+            prop_assert_eq!(summary.skipped, 0);
+            let output = String::from_utf8(output).unwrap();
+            prop_assert!(output.ends_with("# EOF\n"));
+        }
+    }
+}
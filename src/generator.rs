@@ -10,16 +10,158 @@
 //! indefinately, paying higher memory and longer startup for better
 //! experimental control.
 
+use std::{
+    io,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use byte_unit::Byte;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use serde::Deserialize;
-use tokio::sync::broadcast::Receiver;
+use tokio::{sync::broadcast::Receiver, time::Duration};
 
-use crate::signals::Shutdown;
+use crate::{
+    block::{self, Block},
+    control::Pause,
+    numa,
+    signals::Shutdown,
+    throttle, uring,
+};
 
+mod common;
+pub mod elasticsearch;
+pub mod fifo;
 pub mod file_gen;
+pub mod file_tree;
+pub mod grpc;
 pub mod http;
+pub mod jaeger;
 pub mod kafka;
+pub mod netflow;
+pub mod process_churn;
+pub mod pubsub;
+pub mod redis;
 pub mod splunk_hec;
+pub mod sqs;
+pub mod statsd;
+pub mod stdin;
 pub mod tcp;
+pub mod tls;
+pub mod unix_stream;
+#[cfg(target_os = "linux")]
+pub mod vsock;
+pub mod websocket;
+pub mod zipkin;
+
+/// Total bytes written to the target by all generators in this process. Used
+/// to detect when the target has stalled under load, see [`crate::watchdog`].
+static BYTES_WRITTEN: AtomicU64 = AtomicU64::new(0);
+
+/// Return the total bytes written to the target by all generators in this
+/// process.
+#[must_use]
+pub fn total_bytes_written() -> u64 {
+    BYTES_WRITTEN.load(Ordering::Relaxed)
+}
+
+/// Hand generators' block caches off between runs of one configuration
+/// through `dir`, so that each run offers byte-identical load and only the
+/// first pays to build the caches. Each cache is loaded from `dir` if an
+/// earlier run wrote it there, else built and written there.
+///
+/// # Errors
+///
+/// Function will return an error if `dir` cannot be created.
+pub fn hand_off_block_caches(dir: PathBuf) -> Result<(), io::Error> {
+    block::hand_off(dir)
+}
+
+/// Run `build`, the block caches it builds handed off as those of the
+/// generator named `component`, see [`hand_off_block_caches`]. Generators
+/// whose caches would otherwise be alike -- the same labels and block sizes --
+/// do not load one another's.
+pub fn as_component<F, T>(component: &str, build: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    block::as_component(component, build)
+}
+
+#[derive(Debug, Clone, Default)]
+/// Counts the bytes a single generator writes to the target. Clones share
+/// their count, so a meter outlives restarts of its generator. See
+/// [`crate::pairs`].
+pub struct Meter {
+    written: Arc<AtomicU64>,
+}
+
+impl Meter {
+    /// Record that the generator has written `bytes` to the target.
+    pub(crate) fn record(&self, bytes: u64) {
+        self.written.fetch_add(bytes, Ordering::Relaxed);
+        BYTES_WRITTEN.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Return the total bytes written by the generator.
+    #[must_use]
+    pub fn total(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+/// Randomized start offsets and phase jitter for generators
+///
+/// Generators started together throttle in lockstep, and the target sees their
+/// sends arrive in synchronized bursts. Offsetting each generator's start, and
+/// jittering each release of its throttles, decorrelates them. Offsets and
+/// jitter are drawn from `seed`, so a configuration jitters alike run to run.
+pub struct Jitter {
+    /// The seed offsets and jitter are drawn from
+    pub seed: [u8; 32],
+    /// Each generator starts a random time up to this many milliseconds after
+    /// the target, by default 0
+    #[serde(default)]
+    pub maximum_start_offset_milliseconds: u32,
+    /// The first release of capacity by each of a generator's throttles is
+    /// delayed a random time up to this many microseconds, by default 0, see
+    /// [`Pause::with_phase_jitter`]
+    #[serde(default)]
+    pub maximum_phase_jitter_microseconds: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The jitter of one generator, see [`Jitter::draw`].
+pub struct GeneratorJitter {
+    /// The time the generator starts after the target
+    pub start_offset: Duration,
+    /// The longest delay of a throttle's first release of capacity
+    pub maximum_phase_jitter: Duration,
+    /// The seed the generator's phase jitter is drawn from
+    pub phase_seed: u64,
+}
+
+impl Jitter {
+    /// Draw the jitter of each of `generators` generators, in order.
+    #[must_use]
+    pub fn draw(&self, generators: usize) -> Vec<GeneratorJitter> {
+        let mut rng = StdRng::from_seed(self.seed);
+        let maximum_start_offset = u64::from(self.maximum_start_offset_milliseconds);
+        (0..generators)
+            .map(|_| GeneratorJitter {
+                start_offset: Duration::from_millis(rng.gen_range(0..=maximum_start_offset)),
+                maximum_phase_jitter: Duration::from_micros(u64::from(
+                    self.maximum_phase_jitter_microseconds,
+                )),
+                phase_seed: rng.gen(),
+            })
+            .collect()
+    }
+}
 
 #[derive(Debug)]
 /// Errors produced by [`Server`].
@@ -34,9 +176,44 @@ pub enum Error {
     Kafka(kafka::Error),
     /// See [`crate::generator::file_gen::Error`] for details.
     FileGen(file_gen::Error),
+    /// See [`crate::generator::file_tree::Error`] for details.
+    FileTree(file_tree::Error),
+    /// See [`crate::generator::process_churn::Error`] for details.
+    ProcessChurn(process_churn::Error),
+    /// See [`crate::generator::grpc::Error`] for details.
+    Grpc(grpc::Error),
+    /// See [`crate::generator::unix_stream::Error`] for details.
+    UnixStream(unix_stream::Error),
+    /// See [`crate::generator::vsock::Error`] for details.
+    #[cfg(target_os = "linux")]
+    Vsock(vsock::Error),
+    /// See [`crate::generator::fifo::Error`] for details.
+    Fifo(fifo::Error),
+    /// See [`crate::generator::stdin::Error`] for details.
+    Stdin(stdin::Error),
+    /// See [`crate::generator::websocket::Error`] for details.
+    Websocket(websocket::Error),
+    /// See [`crate::generator::redis::Error`] for details.
+    Redis(redis::Error),
+    /// See [`crate::generator::statsd::Error`] for details.
+    Statsd(statsd::Error),
+    /// See [`crate::generator::sqs::Error`] for details.
+    Sqs(sqs::Error),
+    /// See [`crate::generator::elasticsearch::Error`] for details.
+    Elasticsearch(elasticsearch::Error),
+    /// See [`crate::generator::zipkin::Error`] for details.
+    Zipkin(zipkin::Error),
+    /// See [`crate::generator::jaeger::Error`] for details.
+    Jaeger(jaeger::Error),
+    /// See [`crate::generator::netflow::Error`] for details.
+    NetFlow(netflow::Error),
+    /// See [`crate::generator::pubsub::Error`] for details.
+    PubSub(pubsub::Error),
+    /// See [`crate::numa::Error`] for details.
+    Numa(numa::Error),
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 /// Configuration for [`Server`]
 pub enum Config {
@@ -50,6 +227,338 @@ pub enum Config {
     Kafka(kafka::Config),
     /// See [`crate::generator::file_gen::Config`] for details.
     FileGen(file_gen::Config),
+    /// See [`crate::generator::file_tree::Config`] for details.
+    FileTree(file_tree::Config),
+    /// See [`crate::generator::process_churn::Config`] for details.
+    ProcessChurn(process_churn::Config),
+    /// See [`crate::generator::grpc::Config`] for details.
+    Grpc(grpc::Config),
+    /// See [`crate::generator::unix_stream::Config`] for details.
+    UnixStream(unix_stream::Config),
+    /// See [`crate::generator::vsock::Config`] for details.
+    #[cfg(target_os = "linux")]
+    Vsock(vsock::Config),
+    /// See [`crate::generator::fifo::Config`] for details.
+    Fifo(fifo::Config),
+    /// See [`crate::generator::stdin::Config`] for details.
+    Stdin(stdin::Config),
+    /// See [`crate::generator::websocket::Config`] for details.
+    Websocket(websocket::Config),
+    /// See [`crate::generator::redis::Config`] for details.
+    Redis(redis::Config),
+    /// See [`crate::generator::statsd::Config`] for details.
+    Statsd(statsd::Config),
+    /// See [`crate::generator::sqs::Config`] for details.
+    Sqs(sqs::Config),
+    /// See [`crate::generator::elasticsearch::Config`] for details.
+    Elasticsearch(elasticsearch::Config),
+    /// See [`crate::generator::zipkin::Config`] for details.
+    Zipkin(zipkin::Config),
+    /// See [`crate::generator::jaeger::Config`] for details.
+    Jaeger(jaeger::Config),
+    /// See [`crate::generator::netflow::Config`] for details.
+    #[serde(rename = "netflow")]
+    NetFlow(netflow::Config),
+    /// See [`crate::generator::pubsub::Config`] for details.
+    #[serde(rename = "pubsub")]
+    PubSub(pubsub::Config),
+}
+
+impl Config {
+    /// The memory the generator allocates up front, its block cache, in bytes.
+    #[must_use]
+    pub fn planned_memory_bytes(&self) -> u64 {
+        let bytes = match self {
+            Config::Tcp(conf) => conf.maximum_prebuild_cache_size_bytes.get_bytes(),
+            Config::Http(conf) => conf.method.maximum_prebuild_cache_size_bytes().get_bytes(),
+            Config::SplunkHec(conf) => conf.maximum_prebuild_cache_size_bytes.get_bytes(),
+            Config::Kafka(conf) => conf.maximum_prebuild_cache_size_bytes.get_bytes(),
+            // Each duplicate builds its own block cache.
+            Config::FileGen(conf) => {
+                conf.maximum_prebuild_cache_size_bytes.get_bytes() * u128::from(conf.duplicates)
+            }
+            // The file tree and process churn generators build no block cache.
+            Config::FileTree(_) | Config::ProcessChurn(_) => 0,
+            Config::Grpc(conf) => conf.maximum_prebuild_cache_size_bytes.get_bytes(),
+            Config::UnixStream(conf) => conf.maximum_prebuild_cache_size_bytes.get_bytes(),
+            #[cfg(target_os = "linux")]
+            Config::Vsock(conf) => conf.maximum_prebuild_cache_size_bytes.get_bytes(),
+            Config::Fifo(conf) => conf.maximum_prebuild_cache_size_bytes.get_bytes(),
+            Config::Stdin(conf) => conf.maximum_prebuild_cache_size_bytes.get_bytes(),
+            Config::Websocket(conf) => conf.maximum_prebuild_cache_size_bytes.get_bytes(),
+            Config::Redis(conf) => conf.maximum_prebuild_cache_size_bytes.get_bytes(),
+            Config::Statsd(conf) => conf.maximum_prebuild_cache_size_bytes.get_bytes(),
+            Config::Sqs(conf) => conf.maximum_prebuild_cache_size_bytes.get_bytes(),
+            Config::Elasticsearch(conf) => conf.maximum_prebuild_cache_size_bytes.get_bytes(),
+            Config::Zipkin(conf) => conf.maximum_prebuild_cache_size_bytes.get_bytes(),
+            Config::Jaeger(conf) => conf.maximum_prebuild_cache_size_bytes.get_bytes(),
+            Config::NetFlow(conf) => conf.maximum_prebuild_cache_size_bytes.get_bytes(),
+            Config::PubSub(conf) => conf.maximum_prebuild_cache_size_bytes.get_bytes(),
+        };
+        u64::try_from(bytes).unwrap_or(u64::MAX)
+    }
+
+    /// The rate the generator is configured to send at, if it is limited in
+    /// bytes.
+    #[must_use]
+    pub fn bytes_per_second(&self) -> Option<Byte> {
+        match self {
+            Config::Tcp(conf) => Some(conf.bytes_per_second),
+            Config::Http(conf) => Some(conf.bytes_per_second),
+            Config::SplunkHec(conf) => Some(conf.bytes_per_second),
+            Config::Kafka(conf) => match conf.throughput {
+                kafka::Throughput::BytesPerSecond { amount } => Some(amount),
+                kafka::Throughput::Unlimited | kafka::Throughput::MessagesPerSecond { .. } => None,
+            },
+            Config::FileGen(conf) => Some(conf.bytes_per_second),
+            Config::Grpc(conf) => Some(conf.bytes_per_second),
+            Config::UnixStream(conf) => Some(conf.bytes_per_second),
+            #[cfg(target_os = "linux")]
+            Config::Vsock(conf) => Some(conf.bytes_per_second),
+            Config::Fifo(conf) => Some(conf.bytes_per_second),
+            Config::Stdin(conf) => Some(conf.bytes_per_second),
+            Config::Websocket(conf) => Some(conf.bytes_per_second),
+            Config::Redis(conf) => Some(conf.bytes_per_second),
+            Config::Statsd(conf) => Some(conf.bytes_per_second),
+            Config::Sqs(_)
+            | Config::FileTree(_)
+            | Config::ProcessChurn(_)
+            | Config::Zipkin(_)
+            | Config::Jaeger(_)
+            | Config::NetFlow(_)
+            | Config::PubSub(_) => None,
+            Config::Elasticsearch(conf) => Some(conf.bytes_per_second),
+        }
+    }
+
+    /// The seed of the generator's random operations.
+    #[must_use]
+    pub fn seed(&self) -> [u8; 32] {
+        match self {
+            Config::Tcp(conf) => conf.seed,
+            Config::Http(conf) => conf.seed,
+            Config::SplunkHec(conf) => conf.seed,
+            Config::Kafka(conf) => conf.seed,
+            Config::FileGen(conf) => conf.seed,
+            Config::FileTree(conf) => conf.seed,
+            Config::ProcessChurn(conf) => conf.seed,
+            Config::Grpc(conf) => conf.seed,
+            Config::UnixStream(conf) => conf.seed,
+            #[cfg(target_os = "linux")]
+            Config::Vsock(conf) => conf.seed,
+            Config::Fifo(conf) => conf.seed,
+            Config::Stdin(conf) => conf.seed,
+            Config::Websocket(conf) => conf.seed,
+            Config::Redis(conf) => conf.seed,
+            Config::Statsd(conf) => conf.seed,
+            Config::Sqs(conf) => conf.seed,
+            Config::Elasticsearch(conf) => conf.seed,
+            Config::Zipkin(conf) => conf.seed,
+            Config::Jaeger(conf) => conf.seed,
+            Config::NetFlow(conf) => conf.seed,
+            Config::PubSub(conf) => conf.seed,
+        }
+    }
+
+    /// Build the generator's block caches as [`Server::new`] does, without
+    /// creating the generator: one cache, or one for each of a file
+    /// generator's duplicates.
+    pub(crate) fn block_caches(&self) -> Result<Vec<Vec<Block>>, Error> {
+        let labels = vec![];
+        let block_caches = match self {
+            Config::Tcp(conf) => vec![tcp::block_cache(conf, &labels).map_err(Error::Tcp)?],
+            Config::Http(conf) => vec![http::block_cache(conf, &labels).map_err(Error::Http)?],
+            Config::SplunkHec(conf) => {
+                vec![splunk_hec::block_cache(conf, &labels).map_err(Error::SplunkHec)?]
+            }
+            Config::Kafka(conf) => vec![kafka::block_cache(conf, &labels).map_err(Error::Kafka)?],
+            Config::FileGen(conf) => {
+                file_gen::block_caches(conf, &labels).map_err(Error::FileGen)?
+            }
+            Config::FileTree(_) | Config::ProcessChurn(_) => vec![],
+            Config::Grpc(conf) => vec![grpc::block_cache(conf, &labels).map_err(Error::Grpc)?],
+            Config::UnixStream(conf) => {
+                vec![unix_stream::block_cache(conf, &labels).map_err(Error::UnixStream)?]
+            }
+            #[cfg(target_os = "linux")]
+            Config::Vsock(conf) => {
+                vec![vsock::block_cache(conf, &labels).map_err(Error::Vsock)?]
+            }
+            Config::Fifo(conf) => vec![fifo::block_cache(conf, &labels).map_err(Error::Fifo)?],
+            Config::Stdin(conf) => vec![stdin::block_cache(conf, &labels).map_err(Error::Stdin)?],
+            Config::Websocket(conf) => {
+                vec![websocket::block_cache(conf, &labels).map_err(Error::Websocket)?]
+            }
+            Config::Redis(conf) => vec![redis::block_cache(conf, &labels).map_err(Error::Redis)?],
+            Config::Statsd(conf) => {
+                vec![statsd::block_cache(conf, &labels).map_err(Error::Statsd)?]
+            }
+            Config::Sqs(conf) => vec![sqs::block_cache(conf, &labels).map_err(Error::Sqs)?],
+            Config::Elasticsearch(conf) => {
+                vec![elasticsearch::block_cache(conf, &labels).map_err(Error::Elasticsearch)?]
+            }
+            Config::Zipkin(conf) => {
+                vec![zipkin::block_cache(conf, &labels).map_err(Error::Zipkin)?]
+            }
+            Config::Jaeger(conf) => {
+                vec![jaeger::block_cache(conf, &labels).map_err(Error::Jaeger)?]
+            }
+            Config::NetFlow(conf) => {
+                vec![netflow::block_cache(conf, &labels).map_err(Error::NetFlow)?]
+            }
+            Config::PubSub(conf) => {
+                vec![pubsub::block_cache(conf, &labels).map_err(Error::PubSub)?]
+            }
+        };
+        Ok(block_caches)
+    }
+
+    /// The connections, or files, the generator holds open at once.
+    #[must_use]
+    pub fn planned_connections(&self) -> u64 {
+        match self {
+            // gRPC requests are multiplexed over one HTTP/2 connection.
+            Config::Tcp(_)
+            | Config::Kafka(_)
+            | Config::Grpc(_)
+            | Config::UnixStream(_)
+            | Config::Fifo(_)
+            | Config::Stdin(_)
+            | Config::Redis(_)
+            | Config::Statsd(_)
+            | Config::Jaeger(_)
+            | Config::NetFlow(_) => 1,
+            #[cfg(target_os = "linux")]
+            Config::Vsock(_) => 1,
+            Config::Http(conf) => u64::from(conf.parallel_connections),
+            Config::Sqs(conf) => u64::from(conf.parallel_connections),
+            Config::Elasticsearch(conf) => u64::from(conf.parallel_connections),
+            Config::Zipkin(conf) => u64::from(conf.parallel_connections),
+            Config::PubSub(conf) => u64::from(conf.parallel_connections),
+            Config::SplunkHec(conf) => u64::from(conf.parallel_connections),
+            Config::FileGen(conf) => u64::from(conf.duplicates),
+            Config::FileTree(_) => 0,
+            // Each live process holds a pipe to its stdin open.
+            Config::ProcessChurn(conf) => u64::from(conf.maximum_live.get()),
+            Config::Websocket(conf) => u64::from(conf.parallel_connections.max(1)),
+        }
+    }
+
+    /// Whether the generator locks its block cache into RAM.
+    #[must_use]
+    pub fn lock_block_cache(&self) -> bool {
+        match self {
+            Config::Tcp(conf) => conf.lock_block_cache,
+            Config::Http(conf) => conf.lock_block_cache,
+            Config::SplunkHec(conf) => conf.lock_block_cache,
+            Config::Kafka(conf) => conf.lock_block_cache,
+            Config::FileGen(conf) => conf.lock_block_cache,
+            Config::FileTree(_) | Config::ProcessChurn(_) => false,
+            Config::Grpc(conf) => conf.lock_block_cache,
+            Config::UnixStream(conf) => conf.lock_block_cache,
+            #[cfg(target_os = "linux")]
+            Config::Vsock(conf) => conf.lock_block_cache,
+            Config::Fifo(conf) => conf.lock_block_cache,
+            Config::Stdin(conf) => conf.lock_block_cache,
+            Config::Websocket(conf) => conf.lock_block_cache,
+            Config::Redis(conf) => conf.lock_block_cache,
+            Config::Statsd(conf) => conf.lock_block_cache,
+            Config::Sqs(conf) => conf.lock_block_cache,
+            Config::Elasticsearch(conf) => conf.lock_block_cache,
+            Config::Zipkin(conf) => conf.lock_block_cache,
+            Config::Jaeger(conf) => conf.lock_block_cache,
+            Config::NetFlow(conf) => conf.lock_block_cache,
+            Config::PubSub(conf) => conf.lock_block_cache,
+        }
+    }
+
+    /// The I/O backend of the generator.
+    #[must_use]
+    pub fn backend(&self) -> uring::Backend {
+        match self {
+            Config::Tcp(conf) => conf.backend,
+            Config::Http(_)
+            | Config::SplunkHec(_)
+            | Config::Kafka(_)
+            | Config::FileGen(_)
+            | Config::FileTree(_)
+            | Config::ProcessChurn(_)
+            | Config::Grpc(_)
+            | Config::UnixStream(_)
+            | Config::Fifo(_)
+            | Config::Stdin(_)
+            | Config::Websocket(_)
+            | Config::Redis(_)
+            | Config::Statsd(_)
+            | Config::Sqs(_)
+            | Config::Elasticsearch(_)
+            | Config::Zipkin(_)
+            | Config::Jaeger(_)
+            | Config::NetFlow(_)
+            | Config::PubSub(_) => uring::Backend::Epoll,
+            #[cfg(target_os = "linux")]
+            Config::Vsock(_) => uring::Backend::Epoll,
+        }
+    }
+
+    /// The NUMA placement of the generator, if configured.
+    #[must_use]
+    pub fn numa(&self) -> Option<numa::Placement> {
+        match self {
+            Config::Tcp(conf) => conf.numa,
+            Config::Http(conf) => conf.numa,
+            Config::SplunkHec(conf) => conf.numa,
+            Config::Kafka(conf) => conf.numa,
+            Config::FileGen(conf) => conf.numa,
+            Config::FileTree(conf) => conf.numa,
+            Config::ProcessChurn(conf) => conf.numa,
+            Config::Grpc(conf) => conf.numa,
+            Config::UnixStream(conf) => conf.numa,
+            #[cfg(target_os = "linux")]
+            Config::Vsock(conf) => conf.numa,
+            Config::Fifo(conf) => conf.numa,
+            Config::Stdin(conf) => conf.numa,
+            Config::Websocket(conf) => conf.numa,
+            Config::Redis(conf) => conf.numa,
+            Config::Statsd(conf) => conf.numa,
+            Config::Sqs(conf) => conf.numa,
+            Config::Elasticsearch(conf) => conf.numa,
+            Config::Zipkin(conf) => conf.numa,
+            Config::Jaeger(conf) => conf.numa,
+            Config::NetFlow(conf) => conf.numa,
+            Config::PubSub(conf) => conf.numa,
+        }
+    }
+
+    /// The algorithm the generator throttles with and the slow start its
+    /// connections ramp with, if any.
+    #[must_use]
+    pub fn throttle(&self) -> (throttle::Config, Option<throttle::SlowStart>) {
+        match self {
+            Config::Tcp(conf) => (conf.throttle, conf.slow_start),
+            Config::UnixStream(conf) => (conf.throttle, conf.slow_start),
+            #[cfg(target_os = "linux")]
+            Config::Vsock(conf) => (conf.throttle, conf.slow_start),
+            Config::Websocket(conf) => (conf.throttle, conf.slow_start),
+            Config::Redis(conf) => (conf.throttle, conf.slow_start),
+            Config::Http(conf) => (conf.throttle, None),
+            Config::SplunkHec(conf) => (conf.throttle, None),
+            Config::Kafka(conf) => (conf.throttle, None),
+            Config::FileGen(conf) => (conf.throttle, None),
+            Config::FileTree(conf) => (conf.throttle, None),
+            Config::ProcessChurn(conf) => (conf.throttle, None),
+            Config::Grpc(conf) => (conf.throttle, None),
+            Config::Fifo(conf) => (conf.throttle, None),
+            Config::Stdin(conf) => (conf.throttle, None),
+            Config::Statsd(conf) => (conf.throttle, None),
+            Config::Sqs(conf) => (conf.throttle, None),
+            Config::Elasticsearch(conf) => (conf.throttle, None),
+            Config::Zipkin(conf) => (conf.throttle, None),
+            Config::Jaeger(conf) => (conf.throttle, None),
+            Config::NetFlow(conf) => (conf.throttle, None),
+            Config::PubSub(conf) => (conf.throttle, None),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -68,31 +577,127 @@ pub enum Server {
     Kafka(kafka::Kafka),
     /// See [`crate::generator::file_gen::FileGen`] for details.
     FileGen(file_gen::FileGen),
+    /// See [`crate::generator::file_tree::FileTree`] for details.
+    FileTree(file_tree::FileTree),
+    /// See [`crate::generator::process_churn::ProcessChurn`] for details.
+    ProcessChurn(process_churn::ProcessChurn),
+    /// See [`crate::generator::grpc::Grpc`] for details.
+    Grpc(grpc::Grpc),
+    /// See [`crate::generator::unix_stream::UnixStream`] for details.
+    UnixStream(unix_stream::UnixStream),
+    /// See [`crate::generator::vsock::Vsock`] for details.
+    #[cfg(target_os = "linux")]
+    Vsock(vsock::Vsock),
+    /// See [`crate::generator::fifo::Fifo`] for details.
+    Fifo(fifo::Fifo),
+    /// See [`crate::generator::stdin::Stdin`] for details.
+    Stdin(stdin::Stdin),
+    /// See [`crate::generator::websocket::Websocket`] for details.
+    Websocket(websocket::Websocket),
+    /// See [`crate::generator::redis::Redis`] for details.
+    Redis(redis::Redis),
+    /// See [`crate::generator::statsd::Statsd`] for details.
+    Statsd(statsd::Statsd),
+    /// See [`crate::generator::sqs::Sqs`] for details.
+    Sqs(sqs::Sqs),
+    /// See [`crate::generator::elasticsearch::Elasticsearch`] for details.
+    Elasticsearch(elasticsearch::Elasticsearch),
+    /// See [`crate::generator::zipkin::Zipkin`] for details.
+    Zipkin(zipkin::Zipkin),
+    /// See [`crate::generator::jaeger::Jaeger`] for details.
+    Jaeger(jaeger::Jaeger),
+    /// See [`crate::generator::netflow::NetFlow`] for details.
+    NetFlow(netflow::NetFlow),
+    /// See [`crate::generator::pubsub::PubSub`] for details.
+    PubSub(pubsub::PubSub),
 }
 
 impl Server {
     /// Create a new [`Server`]
     ///
     /// This function creates a new [`Server`] instance, deferring to the
-    /// underlying sub-server.
+    /// underlying sub-server. The sub-server is held while `pause` is paused,
+    /// see [`crate::control`], and records the bytes it writes to `meter`.
     ///
     /// # Errors
     ///
     /// Function will return an error if the underlying sub-server creation
     /// signals error.
-    pub fn new(config: Config, shutdown: Shutdown) -> Result<Self, Error> {
+    pub fn new(
+        config: Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
         let srv = match config {
-            Config::Tcp(conf) => Self::Tcp(tcp::Tcp::new(&conf, shutdown).map_err(Error::Tcp)?),
-            Config::Http(conf) => Self::Http(http::Http::new(conf, shutdown).map_err(Error::Http)?),
+            Config::Tcp(conf) => {
+                Self::Tcp(tcp::Tcp::new(&conf, shutdown, pause, meter).map_err(Error::Tcp)?)
+            }
+            Config::Http(conf) => {
+                Self::Http(http::Http::new(conf, shutdown, pause, meter).map_err(Error::Http)?)
+            }
             Config::SplunkHec(conf) => Self::SplunkHec(
-                splunk_hec::SplunkHec::new(conf, shutdown).map_err(Error::SplunkHec)?,
+                splunk_hec::SplunkHec::new(conf, shutdown, pause, meter)
+                    .map_err(Error::SplunkHec)?,
             ),
             Config::Kafka(conf) => {
-                Self::Kafka(kafka::Kafka::new(conf, shutdown).map_err(Error::Kafka)?)
+                Self::Kafka(kafka::Kafka::new(conf, shutdown, pause, meter).map_err(Error::Kafka)?)
             }
-            Config::FileGen(conf) => {
-                Self::FileGen(file_gen::FileGen::new(conf, shutdown).map_err(Error::FileGen)?)
+            Config::FileGen(conf) => Self::FileGen(
+                file_gen::FileGen::new(conf, shutdown, pause, meter).map_err(Error::FileGen)?,
+            ),
+            Config::FileTree(conf) => Self::FileTree(
+                file_tree::FileTree::new(&conf, shutdown, pause).map_err(Error::FileTree)?,
+            ),
+            Config::ProcessChurn(conf) => {
+                Self::ProcessChurn(process_churn::ProcessChurn::new(&conf, shutdown, pause))
+            }
+            Config::Grpc(conf) => {
+                Self::Grpc(grpc::Grpc::new(conf, shutdown, pause, meter).map_err(Error::Grpc)?)
+            }
+            Config::UnixStream(conf) => Self::UnixStream(
+                unix_stream::UnixStream::new(&conf, shutdown, pause, meter)
+                    .map_err(Error::UnixStream)?,
+            ),
+            #[cfg(target_os = "linux")]
+            Config::Vsock(conf) => {
+                Self::Vsock(vsock::Vsock::new(&conf, shutdown, pause, meter).map_err(Error::Vsock)?)
+            }
+            Config::Fifo(conf) => {
+                Self::Fifo(fifo::Fifo::new(&conf, shutdown, pause, meter).map_err(Error::Fifo)?)
+            }
+            Config::Stdin(conf) => {
+                Self::Stdin(stdin::Stdin::new(&conf, shutdown, pause, meter).map_err(Error::Stdin)?)
             }
+            Config::Websocket(conf) => Self::Websocket(
+                websocket::Websocket::new(&conf, shutdown, pause, meter)
+                    .map_err(Error::Websocket)?,
+            ),
+            Config::Redis(conf) => {
+                Self::Redis(redis::Redis::new(&conf, shutdown, pause, meter).map_err(Error::Redis)?)
+            }
+            Config::Statsd(conf) => Self::Statsd(
+                statsd::Statsd::new(&conf, shutdown, pause, meter).map_err(Error::Statsd)?,
+            ),
+            Config::Sqs(conf) => {
+                Self::Sqs(sqs::Sqs::new(&conf, shutdown, pause, meter).map_err(Error::Sqs)?)
+            }
+            Config::Elasticsearch(conf) => Self::Elasticsearch(
+                elasticsearch::Elasticsearch::new(&conf, shutdown, pause, meter)
+                    .map_err(Error::Elasticsearch)?,
+            ),
+            Config::Zipkin(conf) => Self::Zipkin(
+                zipkin::Zipkin::new(&conf, shutdown, pause, meter).map_err(Error::Zipkin)?,
+            ),
+            Config::Jaeger(conf) => Self::Jaeger(
+                jaeger::Jaeger::new(&conf, shutdown, pause, meter).map_err(Error::Jaeger)?,
+            ),
+            Config::NetFlow(conf) => Self::NetFlow(
+                netflow::NetFlow::new(&conf, shutdown, pause, meter).map_err(Error::NetFlow)?,
+            ),
+            Config::PubSub(conf) => Self::PubSub(
+                pubsub::PubSub::new(&conf, shutdown, pause, meter).map_err(Error::PubSub)?,
+            ),
         };
         Ok(srv)
     }
@@ -115,12 +720,43 @@ impl Server {
             .expect("target failed to transmit PID, catastrophic failure");
         drop(pid_snd);
 
+        self.spin().await
+    }
+
+    /// Run this [`Server`] to completion without waiting for the target
+    ///
+    /// This function runs the sub-server its completion, or until a shutdown
+    /// signal is received. Unlike [`Server::run`] it is assumed that the target
+    /// is already online, as is the case when a generator is restarted.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the underlying sub-server signals
+    /// error.
+    pub async fn spin(self) -> Result<(), Error> {
         match self {
             Server::Tcp(inner) => inner.spin().await.map_err(Error::Tcp),
             Server::Http(inner) => inner.spin().await.map_err(Error::Http),
             Server::SplunkHec(inner) => inner.spin().await.map_err(Error::SplunkHec),
             Server::Kafka(inner) => inner.spin().await.map_err(Error::Kafka),
             Server::FileGen(inner) => inner.spin().await.map_err(Error::FileGen),
+            Server::FileTree(inner) => inner.spin().await.map_err(Error::FileTree),
+            Server::ProcessChurn(inner) => inner.spin().await.map_err(Error::ProcessChurn),
+            Server::Grpc(inner) => inner.spin().await.map_err(Error::Grpc),
+            Server::UnixStream(inner) => inner.spin().await.map_err(Error::UnixStream),
+            #[cfg(target_os = "linux")]
+            Server::Vsock(inner) => inner.spin().await.map_err(Error::Vsock),
+            Server::Fifo(inner) => inner.spin().await.map_err(Error::Fifo),
+            Server::Stdin(inner) => inner.spin().await.map_err(Error::Stdin),
+            Server::Websocket(inner) => inner.spin().await.map_err(Error::Websocket),
+            Server::Redis(inner) => inner.spin().await.map_err(Error::Redis),
+            Server::Statsd(inner) => inner.spin().await.map_err(Error::Statsd),
+            Server::Sqs(inner) => inner.spin().await.map_err(Error::Sqs),
+            Server::Elasticsearch(inner) => inner.spin().await.map_err(Error::Elasticsearch),
+            Server::Zipkin(inner) => inner.spin().await.map_err(Error::Zipkin),
+            Server::Jaeger(inner) => inner.spin().await.map_err(Error::Jaeger),
+            Server::NetFlow(inner) => inner.spin().await.map_err(Error::NetFlow),
+            Server::PubSub(inner) => inner.spin().await.map_err(Error::PubSub),
         }
     }
 }
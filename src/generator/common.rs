@@ -0,0 +1,577 @@
+//! Code shared between generators.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    num::NonZeroU32,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use byte_unit::Byte;
+use metrics::{counter, gauge};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    task::JoinHandle,
+};
+
+use crate::payload::{chunk_placeholders, CHUNK_ID_LEN};
+
+/// The trailing window over which [`RateWindow`] computes achieved rate.
+pub(crate) const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Tracks the bytes a generator has sent over a trailing window.
+///
+/// Each call to [`RateWindow::record`] updates the
+/// `achieved_bytes_per_second` gauge, the bytes sent in the trailing window
+/// divided by the window's length.
+#[derive(Debug)]
+pub(crate) struct RateWindow {
+    window: Duration,
+    samples: VecDeque<(Instant, u64)>,
+    total: u64,
+}
+
+impl RateWindow {
+    pub(crate) fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: VecDeque::new(),
+            total: 0,
+        }
+    }
+
+    /// Record that `bytes` have been sent to the target.
+    #[allow(clippy::ptr_arg)]
+    pub(crate) fn record(&mut self, bytes: u64, labels: &Vec<(String, String)>) {
+        let now = Instant::now();
+        self.samples.push_back((now, bytes));
+        self.total += bytes;
+        while let Some(&(at, old_bytes)) = self.samples.front() {
+            if now.duration_since(at) <= self.window {
+                break;
+            }
+            self.samples.pop_front();
+            self.total -= old_bytes;
+        }
+        gauge!(
+            "achieved_bytes_per_second",
+            self.total as f64 / self.window.as_secs_f64(),
+            labels
+        );
+    }
+}
+
+/// Tracks a generator's progress toward its optional finite data limits.
+///
+/// Generators by default run until the experiment ends. When a user sets
+/// `maximum_bytes` or `maximum_events` the generator instead stops once either
+/// limit is reached. Limits are checked at block granularity, meaning a
+/// generator may exceed its limit by no more than one block. Events are counted
+/// as newline delimited lines in a block.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Budget {
+    maximum_bytes: Option<u64>,
+    maximum_events: Option<u64>,
+    bytes: u64,
+    events: u64,
+}
+
+impl Budget {
+    pub(crate) fn new(maximum_bytes: Option<Byte>, maximum_events: Option<u64>) -> Self {
+        Self {
+            maximum_bytes: maximum_bytes.map(|b| b.get_bytes().try_into().unwrap_or(u64::MAX)),
+            maximum_events,
+            bytes: 0,
+            events: 0,
+        }
+    }
+
+    /// Record that `bytes` and `events` have been sent to the target.
+    pub(crate) fn record(&mut self, bytes: u64, events: u64) {
+        self.bytes = self.bytes.saturating_add(bytes);
+        self.events = self.events.saturating_add(events);
+    }
+
+    /// Returns true if either configured limit has been reached.
+    pub(crate) fn exhausted(&self) -> bool {
+        self.maximum_bytes.map_or(false, |max| self.bytes >= max)
+            || self.maximum_events.map_or(false, |max| self.events >= max)
+    }
+}
+
+/// The delay before the first reconnection attempt after a failed one, see
+/// [`Backoff`].
+const BACKOFF_MINIMUM: Duration = Duration::from_millis(10);
+
+/// The longest delay between reconnection attempts, see [`Backoff`].
+const BACKOFF_MAXIMUM: Duration = Duration::from_secs(5);
+
+/// Delays reconnection after failed connection attempts.
+///
+/// A target that refuses connections would otherwise be hammered with them as
+/// fast as the generator can loop. Each consecutive failure doubles the delay
+/// before the next attempt, from [`BACKOFF_MINIMUM`] up to
+/// [`BACKOFF_MAXIMUM`]. A successful attempt resets the delay.
+#[derive(Debug, Default)]
+pub(crate) struct Backoff {
+    delay: Option<Duration>,
+    until: Option<tokio::time::Instant>,
+}
+
+impl Backoff {
+    /// Wait until the next attempt may be made, completing at once if the
+    /// last attempt succeeded.
+    pub(crate) async fn ready(&self) {
+        if let Some(until) = self.until {
+            tokio::time::sleep_until(until).await;
+        }
+    }
+
+    /// Record that an attempt failed, returning the delay before the next.
+    pub(crate) fn failed(&mut self) -> Duration {
+        let delay = self
+            .delay
+            .map_or(BACKOFF_MINIMUM, |delay| (delay * 2).min(BACKOFF_MAXIMUM));
+        self.delay = Some(delay);
+        self.until = Some(tokio::time::Instant::now() + delay);
+        delay
+    }
+
+    /// Record that an attempt succeeded.
+    pub(crate) fn succeeded(&mut self) {
+        self.delay = None;
+        self.until = None;
+    }
+}
+
+/// Schedules heartbeats on connections left idle.
+///
+/// Targets commonly close connections that have been idle for a while, which
+/// a generator configured with a low rate may well leave its connections. A
+/// heartbeat is due on a connection once it has been idle, neither written to
+/// nor reset, for the configured interval. Without an interval no heartbeat is
+/// ever due.
+#[derive(Debug)]
+pub(crate) struct Heartbeat {
+    interval: Option<Duration>,
+    /// When each connection was last active.
+    active: Vec<tokio::time::Instant>,
+}
+
+impl Heartbeat {
+    /// Create a new [`Heartbeat`] of `connections` connections, due every
+    /// `seconds` of idleness if set.
+    pub(crate) fn new(seconds: Option<NonZeroU32>, connections: usize) -> Self {
+        Self {
+            interval: seconds.map(|seconds| Duration::from_secs(u64::from(seconds.get()))),
+            active: vec![tokio::time::Instant::now(); connections.max(1)],
+        }
+    }
+
+    /// Wait until a heartbeat is due, returning the connection it is due on.
+    /// Never completes if no interval is configured.
+    pub(crate) async fn due(&self) -> usize {
+        match self.interval {
+            Some(interval) => {
+                let (connection, active) = self
+                    .active
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, active)| **active)
+                    .unwrap();
+                tokio::time::sleep_until(*active + interval).await;
+                connection
+            }
+            None => futures::future::pending().await,
+        }
+    }
+
+    /// Record that `connection` is active, postponing its next heartbeat.
+    pub(crate) fn reset(&mut self, connection: usize) {
+        if let Some(active) = self.active.get_mut(connection) {
+            *active = tokio::time::Instant::now();
+        }
+    }
+}
+
+/// How the responses a target sends back on a connection are parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Responses {
+    /// Newline delimited messages of no particular meaning, counted as
+    /// `messages_read`.
+    Lines,
+    /// Fluent forward acknowledgements, msgpack maps of `ack` to the chunk id
+    /// acknowledged. Each acknowledgement of an outstanding chunk id is
+    /// counted as `request_ok`. Anything else, and each chunk id not
+    /// acknowledged within [`ACK_TIMEOUT`] or before the connection closes, is
+    /// counted as `request_failure`.
+    FluentAck,
+}
+
+/// How long a sent chunk id may go unacknowledged before it is counted as a
+/// failure.
+pub(crate) const ACK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often outstanding chunk ids are checked against [`ACK_TIMEOUT`].
+const ACK_EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Stamps a fresh chunk id over each placeholder chunk id of the fluent
+/// forward messages in a block, as the block is sent.
+///
+/// A cached block is sent many times over. Were its chunk ids fixed when the
+/// block was made the target would see each id acknowledged again and again,
+/// and no acknowledgement could be matched to the message it answers.
+#[derive(Debug)]
+pub(crate) struct ChunkIds {
+    base: u64,
+    next: u64,
+}
+
+impl ChunkIds {
+    /// Create a [`ChunkIds`]. Ids are a random base followed by a counter,
+    /// unique within the generator and, with high probability, across
+    /// generators.
+    pub(crate) fn new() -> Self {
+        Self {
+            base: rand::random(),
+            next: 0,
+        }
+    }
+
+    /// Overwrite each placeholder chunk id in `bytes` with a fresh id,
+    /// returning the ids in order.
+    pub(crate) fn stamp(&mut self, bytes: &mut [u8]) -> Vec<String> {
+        chunk_placeholders(bytes)
+            .into_iter()
+            .map(|offset| {
+                let id = format!("{:016x}{:016x}", self.base, self.next);
+                self.next = self.next.wrapping_add(1);
+                bytes[offset..offset + CHUNK_ID_LEN].copy_from_slice(id.as_bytes());
+                id
+            })
+            .collect()
+    }
+}
+
+/// The chunk ids sent on a connection and not yet acknowledged, with when
+/// each was sent.
+#[derive(Debug, Default)]
+struct Outstanding {
+    sent: Mutex<HashMap<String, Instant>>,
+}
+
+impl Outstanding {
+    /// Remove `id`, returning whether it was outstanding.
+    fn acked(&self, id: &str) -> bool {
+        self.sent.lock().unwrap().remove(id).is_some()
+    }
+
+    /// Remove the ids sent more than `timeout` ago, returning how many.
+    fn expire(&self, timeout: Duration) -> u64 {
+        let mut sent = self.sent.lock().unwrap();
+        let before = sent.len();
+        sent.retain(|_, at| at.elapsed() < timeout);
+        (before - sent.len()) as u64
+    }
+
+    /// Remove every id, returning how many.
+    fn clear(&self) -> u64 {
+        let mut sent = self.sent.lock().unwrap();
+        let count = sent.len() as u64;
+        sent.clear();
+        count
+    }
+}
+
+/// Count `count` chunk ids as failed with `error`.
+#[allow(clippy::ptr_arg)]
+fn record_unacked(count: u64, error: &str, labels: &Vec<(String, String)>) {
+    if count > 0 {
+        let mut error_labels = labels.clone();
+        error_labels.push(("error".to_string(), error.to_string()));
+        counter!("request_failure", count, &error_labels);
+    }
+}
+
+/// A response to fluent forward messages.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum FluentResponse {
+    /// An acknowledgement of the chunk id `ack`.
+    Ack { ack: String },
+    /// Anything else.
+    Other(serde::de::IgnoredAny),
+}
+
+/// Parse the fluent acknowledgements at the front of `pending`, removing them.
+/// Returns the chunk ids acknowledged and the number of other responses parsed.
+/// A response cut short is left in `pending` to be completed by the next read.
+/// Past a response that is not msgpack nothing can be parsed, and all of
+/// `pending` is counted as one other response.
+fn fluent_acks(pending: &mut Vec<u8>) -> (Vec<String>, u64) {
+    let (mut acks, mut others) = (Vec::new(), 0);
+    let mut consumed = 0;
+    while consumed < pending.len() {
+        let mut cursor = io::Cursor::new(&pending[consumed..]);
+        match rmp_serde::from_read::<_, FluentResponse>(&mut cursor) {
+            Ok(response) => {
+                consumed += usize::try_from(cursor.position()).unwrap();
+                match response {
+                    FluentResponse::Ack { ack } => acks.push(ack),
+                    FluentResponse::Other(_) => others += 1,
+                }
+            }
+            Err(
+                rmp_serde::decode::Error::InvalidMarkerRead(err)
+                | rmp_serde::decode::Error::InvalidDataRead(err),
+            ) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(_) => {
+                others += 1;
+                consumed = pending.len();
+            }
+        }
+    }
+    pending.drain(..consumed);
+    (acks, others)
+}
+
+/// Reads everything a target sends back on a connection, parsing it per
+/// [`Responses`].
+///
+/// A target may write acknowledgements or errors back on the connection a
+/// generator writes to. Were they never read the target's send buffer would
+/// fill, stalling the target in ways that have nothing to do with the load.
+/// Bytes read are counted as `bytes_read`. The drain stops when the
+/// connection's read side closes and is stopped when dropped. Chunk ids still
+/// outstanding when the drain stops or is dropped are counted as failures.
+#[derive(Debug)]
+pub(crate) struct Drain {
+    task: Option<JoinHandle<io::Result<()>>>,
+    outstanding: Arc<Outstanding>,
+    labels: Vec<(String, String)>,
+}
+
+impl Drain {
+    /// Spawn a task draining `reader`, its responses parsed per `responses`.
+    pub(crate) fn spawn<R>(
+        mut reader: R,
+        responses: Responses,
+        labels: Vec<(String, String)>,
+    ) -> Self
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        let outstanding = Arc::new(Outstanding::default());
+        let task_outstanding = Arc::clone(&outstanding);
+        let task_labels = labels.clone();
+        let task = tokio::spawn(async move {
+            let outstanding = task_outstanding;
+            let labels = task_labels;
+            let mut buf = vec![0; 8192];
+            let mut pending = Vec::new();
+            let mut expiry = tokio::time::interval(ACK_EXPIRY_INTERVAL);
+            loop {
+                let read = tokio::select! {
+                    read = reader.read(&mut buf) => read?,
+                    _ = expiry.tick(), if responses == Responses::FluentAck => {
+                        record_unacked(outstanding.expire(ACK_TIMEOUT), "ack_timeout", &labels);
+                        continue;
+                    }
+                };
+                if read == 0 {
+                    record_unacked(outstanding.clear(), "unacked", &labels);
+                    return Ok(());
+                }
+                counter!("bytes_read", read as u64, &labels);
+                match responses {
+                    Responses::Lines => {
+                        let messages = buf[..read].iter().filter(|byte| **byte == b'\n').count();
+                        if messages > 0 {
+                            counter!("messages_read", messages as u64, &labels);
+                        }
+                    }
+                    Responses::FluentAck => {
+                        pending.extend_from_slice(&buf[..read]);
+                        let (acks, others) = fluent_acks(&mut pending);
+                        let (mut ok, mut unexpected) = (0, 0);
+                        for ack in acks {
+                            if outstanding.acked(&ack) {
+                                ok += 1;
+                            } else {
+                                unexpected += 1;
+                            }
+                        }
+                        if ok > 0 {
+                            counter!("request_ok", ok, &labels);
+                        }
+                        record_unacked(unexpected, "unexpected_ack", &labels);
+                        record_unacked(others, "response", &labels);
+                    }
+                }
+            }
+        });
+        Self {
+            task: Some(task),
+            outstanding,
+            labels,
+        }
+    }
+
+    /// Record that the chunk ids `ids` are about to be sent. Ids must be
+    /// recorded before they are written, lest their acknowledgement be read
+    /// first.
+    pub(crate) fn sent(&self, ids: &[String]) {
+        if ids.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        let mut sent = self.outstanding.sent.lock().unwrap();
+        for id in ids {
+            sent.insert(id.clone(), now);
+        }
+    }
+
+    /// Forget the chunk ids `ids`, recorded as sent but whose write failed.
+    /// The failed write is counted on its own.
+    pub(crate) fn unsent(&self, ids: &[String]) {
+        let mut sent = self.outstanding.sent.lock().unwrap();
+        for id in ids {
+            sent.remove(id);
+        }
+    }
+
+    /// Wait until the target closes its side of the connection, returning an
+    /// error if reading failed instead. Completes at most once, never
+    /// completing again after.
+    pub(crate) async fn closed(&mut self) -> io::Result<()> {
+        match &mut self.task {
+            Some(task) => {
+                let res = task
+                    .await
+                    .unwrap_or_else(|err| Err(io::Error::new(io::ErrorKind::Other, err)));
+                self.task = None;
+                res
+            }
+            None => futures::future::pending().await,
+        }
+    }
+}
+
+impl Drop for Drain {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+        record_unacked(self.outstanding.clear(), "unacked", &self.labels);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::{fluent_acks, Backoff, Budget, ChunkIds, BACKOFF_MAXIMUM, BACKOFF_MINIMUM};
+    use crate::payload::{chunk_placeholders, Fluent, Serialize};
+
+    // A budget without limits is never exhausted.
+    proptest! {
+        #[test]
+        fn unlimited_never_exhausted(records in proptest::collection::vec((any::<u64>(), any::<u64>()), 0..100)) {
+            let mut budget = Budget::new(None, None);
+            for (bytes, events) in records {
+                budget.record(bytes, events);
+                prop_assert!(!budget.exhausted());
+            }
+        }
+    }
+
+    // A budget is exhausted exactly when recorded events reach the limit.
+    proptest! {
+        #[test]
+        fn events_exhaust(maximum_events: u32, records in proptest::collection::vec(any::<u16>(), 0..100)) {
+            let maximum_events = u64::from(maximum_events);
+            let mut budget = Budget::new(None, Some(maximum_events));
+            let mut total: u64 = 0;
+            for events in records {
+                budget.record(0, u64::from(events));
+                total += u64::from(events);
+                prop_assert_eq!(total >= maximum_events, budget.exhausted());
+            }
+        }
+    }
+
+    // Acknowledgements are all parsed however the bytes carrying them are
+    // split across reads.
+    proptest! {
+        #[test]
+        fn fluent_acks_across_reads(
+            chunks in proptest::collection::vec("[0-9a-f]{32}", 0..32),
+            split in 1_usize..64,
+        ) {
+            let mut bytes = Vec::new();
+            for chunk in &chunks {
+                let mut ack = std::collections::HashMap::new();
+                ack.insert("ack", chunk.as_str());
+                bytes.extend_from_slice(&rmp_serde::to_vec_named(&ack).unwrap());
+            }
+            let mut pending = Vec::new();
+            let (mut acks, mut others) = (Vec::new(), 0);
+            for read in bytes.chunks(split) {
+                pending.extend_from_slice(read);
+                let (a, o) = fluent_acks(&mut pending);
+                acks.extend(a);
+                others += o;
+            }
+            prop_assert_eq!(acks, chunks);
+            prop_assert_eq!(others, 0);
+            prop_assert!(pending.is_empty());
+        }
+    }
+
+    // A block sent repeatedly carries fresh chunk ids each time, one for each
+    // placeholder, and no placeholder survives stamping.
+    proptest! {
+        #[test]
+        fn chunk_ids_fresh_per_send(seed: u64, maximum_chunk_bytes in 1..1_024_usize, compressed: bool, sends in 1_usize..8) {
+            use rand::{rngs::SmallRng, SeedableRng};
+
+            let fluent = Fluent::packed_forward(maximum_chunk_bytes, compressed, true);
+            let mut block = Vec::new();
+            fluent.to_bytes(SmallRng::seed_from_u64(seed), 8_192, &mut block).unwrap();
+            let placeholders = chunk_placeholders(&block).len();
+
+            let mut chunk_ids = ChunkIds::new();
+            let mut seen = std::collections::HashSet::new();
+            for _ in 0..sends {
+                let mut bytes = block.clone();
+                let ids = chunk_ids.stamp(&mut bytes);
+                prop_assert_eq!(ids.len(), placeholders);
+                prop_assert!(chunk_placeholders(&bytes).is_empty());
+                for id in ids {
+                    prop_assert!(seen.insert(id));
+                }
+            }
+        }
+    }
+
+    // Consecutive failures back off no faster than doubling, never beyond the
+    // maximum, and a success starts the next failure over from the minimum.
+    proptest! {
+        #[test]
+        fn backoff_doubles_and_resets(failures in 1_usize..32) {
+            let mut backoff = Backoff::default();
+            let mut previous = None;
+            for _ in 0..failures {
+                let delay = backoff.failed();
+                prop_assert!(delay >= BACKOFF_MINIMUM && delay <= BACKOFF_MAXIMUM);
+                if let Some(previous) = previous {
+                    prop_assert_eq!(delay, (previous * 2).min(BACKOFF_MAXIMUM));
+                }
+                previous = Some(delay);
+            }
+            backoff.succeeded();
+            prop_assert_eq!(backoff.failed(), BACKOFF_MINIMUM);
+        }
+    }
+}
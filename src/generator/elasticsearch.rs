@@ -0,0 +1,493 @@
+//! The [Elasticsearch](https://www.elastic.co/elasticsearch/) `_bulk` speaking
+//! generator.
+//!
+//! Each line of the payload is a document. Lines that are JSON objects are
+//! indexed as they are, the objects of lines that are JSON arrays each as a
+//! document, and any other line as the `message` of a document of its own.
+//! Documents are batched `documents_per_request` at a time into NDJSON `_bulk`
+//! requests as the block cache is built, so the generator suits OpenSearch as
+//! well as Elasticsearch.
+
+use std::{
+    collections::HashMap,
+    num::{NonZeroU32, NonZeroUsize},
+    sync::Arc,
+};
+
+use byte_unit::{Byte, ByteUnit};
+use governor::state::direct::InsufficientCapacity;
+use hyper::{
+    client::HttpConnector,
+    header::{HeaderValue, CONTENT_TYPE},
+    Body, Client, Request, Uri,
+};
+use metrics::{counter, gauge};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+use tracing::info;
+
+use crate::{
+    address,
+    block::{self, chunk_bytes, Block},
+    control::Pause,
+    generator::{
+        common::{Budget, RateWindow, RATE_WINDOW},
+        http::Variant,
+        Meter,
+    },
+    numa, payload,
+    signals::Shutdown,
+    telemetry::hyper_error_kind,
+    throttle::{self, Throttle},
+};
+
+fn default_documents_per_request() -> NonZeroU32 {
+    NonZeroU32::new(100).unwrap()
+}
+
+fn default_parallel_connections() -> u16 {
+    10
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// The bulk action documents are written with.
+pub enum Action {
+    /// `index`, adding or replacing the document
+    Index,
+    /// `create`, adding the document. Data streams accept only `create`.
+    Create,
+}
+
+impl Default for Action {
+    fn default() -> Self {
+        Action::Index
+    }
+}
+
+impl Action {
+    /// The action line preceding each document in a `_bulk` body.
+    fn line(self) -> &'static [u8] {
+        match self {
+            Action::Index => b"{\"index\":{}}\n",
+            Action::Create => b"{\"create\":{}}\n",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration of this generator.
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// The URI of the cluster, for instance `http://localhost:9200`
+    #[serde(with = "http_serde::uri")]
+    pub target_uri: Uri,
+    /// The index, or data stream, documents are written to
+    pub index: String,
+    /// The bulk action documents are written with, by default `index`
+    #[serde(default)]
+    pub action: Action,
+    /// The payload generator to use for this target
+    pub variant: Variant,
+    /// The bytes per second to send to the target
+    pub bytes_per_second: byte_unit::Byte,
+    /// The documents sent in each `_bulk` request, by default 100
+    #[serde(default = "default_documents_per_request")]
+    pub documents_per_request: NonZeroU32,
+    /// The block sizes for messages to this target
+    pub block_sizes: Option<Vec<byte_unit::Byte>>,
+    /// The maximum size in bytes of the cache of prebuilt messages
+    pub maximum_prebuild_cache_size_bytes: byte_unit::Byte,
+    /// The total number of parallel connections to maintain, by default 10
+    #[serde(default = "default_parallel_connections")]
+    pub parallel_connections: u16,
+    /// The maximum number of bytes to send before this generator stops. If
+    /// unset the generator runs until the experiment ends.
+    pub maximum_bytes: Option<byte_unit::Byte>,
+    /// The maximum number of events -- documents -- to send before this
+    /// generator stops. If unset the generator runs until the experiment ends.
+    pub maximum_events: Option<u64>,
+    /// The algorithm used to throttle output to `bytes_per_second`. Defaults to a
+    /// token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Limit on the size of each newline delimited event. If unset events are
+    /// as large as the payload makes them.
+    pub event_limit: Option<payload::EventLimit>,
+    /// Whether to lock the block cache into RAM, touching each of its pages,
+    /// before the run starts. See mlock(2) for the limits on locked memory.
+    #[serde(default)]
+    pub lock_block_cache: bool,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+}
+
+#[derive(Debug)]
+/// Errors produced by [`Elasticsearch`].
+pub enum Error {
+    /// Rate limiter has insuficient capacity for payload. Indicates a serious
+    /// bug.
+    Governor(InsufficientCapacity),
+    /// Creation of payload blocks failed.
+    Block(block::Error),
+    /// Wrapper around [`hyper::http::Error`].
+    Http(hyper::http::Error),
+    /// The payload holds no documents.
+    NoDocuments,
+}
+
+impl From<block::Error> for Error {
+    fn from(error: block::Error) -> Self {
+        Error::Block(error)
+    }
+}
+
+impl From<hyper::http::Error> for Error {
+    fn from(error: hyper::http::Error) -> Self {
+        Error::Http(error)
+    }
+}
+
+impl From<InsufficientCapacity> for Error {
+    fn from(error: InsufficientCapacity) -> Self {
+        Error::Governor(error)
+    }
+}
+
+/// Append the documents of payload line `line`, which holds no newline, to
+/// `documents`, each encoded as a single line JSON object.
+fn documents(line: &[u8], documents: &mut Vec<Vec<u8>>) {
+    match serde_json::from_slice::<serde_json::Value>(line) {
+        Ok(serde_json::Value::Object(_)) => documents.push(line.to_vec()),
+        Ok(serde_json::Value::Array(values)) => {
+            for value in values {
+                let document = match value {
+                    serde_json::Value::Object(object) => serde_json::Value::Object(object),
+                    value => serde_json::json!({ "message": value }),
+                };
+                documents.push(serde_json::to_vec(&document).unwrap());
+            }
+        }
+        _ => {
+            let message = String::from_utf8_lossy(line);
+            documents.push(serde_json::to_vec(&serde_json::json!({ "message": message })).unwrap());
+        }
+    }
+}
+
+/// Encode the `_bulk` body writing `documents` with `action`.
+fn encode_request(action: Action, documents: &[&[u8]]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(
+        documents
+            .iter()
+            .map(|d| d.len() + action.line().len() + 1)
+            .sum(),
+    );
+    for document in documents {
+        body.extend_from_slice(action.line());
+        body.extend_from_slice(document);
+        body.push(b'\n');
+    }
+    body
+}
+
+/// Build the block cache of a generator configured by `config`, as
+/// [`Elasticsearch::new`] does. Each block is a `_bulk` body carrying exactly
+/// `documents_per_request` documents, the payload's documents taken in turn,
+/// the last request wrapping around to the first.
+///
+/// # Errors
+///
+/// Function will return an error if the configured block sizes cannot be
+/// chunked into the prebuild cache or the payload holds no documents.
+///
+/// # Panics
+///
+/// Function will panic if user has passed zero values for any byte values.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::ptr_arg)]
+pub(crate) fn block_cache(
+    config: &Config,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<Block>, Error> {
+    let mut rng = StdRng::from_seed(config.seed);
+    let block_sizes: Vec<NonZeroUsize> = config
+        .block_sizes
+        .clone()
+        .unwrap_or_else(|| {
+            vec![
+                Byte::from_unit(1.0 / 4.0, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(1.0 / 2.0, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(1_f64, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(2_f64, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(4_f64, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(8_f64, ByteUnit::KiB).unwrap(),
+            ]
+        })
+        .iter()
+        .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
+        .collect();
+    let block_chunks = chunk_bytes(
+        &mut rng,
+        NonZeroUsize::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as usize)
+            .expect("bytes must be non-zero"),
+        &block_sizes,
+    )?;
+    let mut payload_documents = Vec::new();
+    for blk in config
+        .variant
+        .block_cache(&mut rng, &block_chunks, config.event_limit, labels)
+    {
+        for line in blk.bytes.split(|byte| *byte == b'\n') {
+            if !line.iter().all(u8::is_ascii_whitespace) {
+                documents(line, &mut payload_documents);
+            }
+        }
+    }
+    if payload_documents.is_empty() {
+        return Err(Error::NoDocuments);
+    }
+
+    let documents_per_request = config.documents_per_request.get() as usize;
+    let requests = (payload_documents.len() + documents_per_request - 1) / documents_per_request;
+    let mut block_cache = Vec::with_capacity(requests);
+    for request in 0..requests {
+        let batch: Vec<&[u8]> = (0..documents_per_request)
+            .map(|idx| {
+                &payload_documents
+                    [(request * documents_per_request + idx) % payload_documents.len()][..]
+            })
+            .collect();
+        let bytes = encode_request(config.action, &batch);
+        block_cache.push(Block::new(
+            NonZeroU32::new(bytes.len() as u32).expect("requests are never empty"),
+            u64::from(config.documents_per_request.get()),
+            bytes,
+        ));
+    }
+    Ok(block_cache)
+}
+
+/// The per item result of a `_bulk` request.
+#[derive(Deserialize, Debug)]
+struct BulkItem {
+    status: u16,
+}
+
+/// The response to a `_bulk` request.
+#[derive(Deserialize, Debug)]
+struct BulkResponse {
+    #[serde(default)]
+    items: Vec<HashMap<String, BulkItem>>,
+}
+
+#[derive(Debug)]
+/// The Elasticsearch generator.
+///
+/// This generator is responsible for sending blocks to the target as the
+/// bodies of `_bulk` requests.
+pub struct Elasticsearch {
+    uri: Uri,
+    parallel_connections: u16,
+    connection_semaphore: Arc<Semaphore>,
+    throttle: Throttle,
+    block_cache: Vec<Block>,
+    metric_labels: Vec<(String, String)>,
+    budget: Budget,
+    shutdown: Shutdown,
+    meter: Meter,
+}
+
+impl Elasticsearch {
+    /// Create a new [`Elasticsearch`] instance
+    ///
+    /// # Errors
+    ///
+    /// Creation will fail if the block cache cannot be built or the target URI
+    /// and index do not form a valid URI.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if user has passed zero values for any byte
+    /// values. Sharp corners.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(
+        config: &Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
+        let bytes_per_second = NonZeroU32::new(config.bytes_per_second.get_bytes() as u32).unwrap();
+        let throttle = Throttle::new(config.throttle, bytes_per_second, pause);
+        let labels = vec![];
+        let metric_labels = address::uri_labels(&config.target_uri);
+        let mut block_cache = block_cache(config, &labels)?;
+        if config.lock_block_cache {
+            block::lock(&mut block_cache, &labels)?;
+        }
+
+        let mut parts = config.target_uri.clone().into_parts();
+        parts.path_and_query = Some(
+            format!("/{}/_bulk", config.index)
+                .parse()
+                .map_err(hyper::http::Error::from)?,
+        );
+        let uri = Uri::from_parts(parts).map_err(hyper::http::Error::from)?;
+
+        Ok(Self {
+            uri,
+            parallel_connections: config.parallel_connections,
+            connection_semaphore: Arc::new(Semaphore::new(config.parallel_connections as usize)),
+            block_cache,
+            throttle,
+            metric_labels,
+            budget: Budget::new(config.maximum_bytes, config.maximum_events),
+            shutdown,
+            meter,
+        })
+    }
+
+    /// Run [`Elasticsearch`] to completion or until a shutdown signal is
+    /// received.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if a request cannot be built.
+    ///
+    /// # Panics
+    ///
+    /// None known.
+    pub async fn spin(mut self) -> Result<(), Error> {
+        let client: Client<HttpConnector, Body> = Client::builder()
+            .pool_max_idle_per_host(self.parallel_connections as usize)
+            .retry_canceled_requests(false)
+            .build_http();
+        let mut throttle = self.throttle;
+        let uri = self.uri;
+        let connection_semaphore = self.connection_semaphore;
+
+        let labels = self.metric_labels;
+        let mut budget = self.budget;
+        let mut rate_window = RateWindow::new(RATE_WINDOW);
+        let mut blocks = self.block_cache.iter().cycle();
+
+        loop {
+            let blk = blocks.next().unwrap();
+            let total_bytes = blk.total_bytes;
+
+            tokio::select! {
+                _ = throttle.wait(total_bytes, &labels) => {
+                    rate_window.record(u64::from(total_bytes.get()), &labels);
+                    let client = client.clone();
+                    let labels = labels.clone();
+                    let meter = self.meter.clone();
+
+                    let block_length = blk.bytes.len();
+                    let request: Request<Body> = Request::post(uri.clone())
+                        .header(CONTENT_TYPE, HeaderValue::from_static("application/x-ndjson"))
+                        .body(Body::from(blk.bytes.clone()))?;
+
+                    let permit = Arc::clone(&connection_semaphore).acquire_owned().await.unwrap();
+                    tokio::spawn(async move {
+                        counter!("requests_sent", 1, &labels);
+                        match client.request(request).await {
+                            Ok(response) => {
+                                counter!("bytes_written", block_length as u64, &labels);
+                                meter.record(block_length as u64);
+                                let (parts, body) = response.into_parts();
+                                let status = parts.status;
+                                let mut status_labels = labels.clone();
+                                status_labels
+                                    .push(("status_code".to_string(), status.as_u16().to_string()));
+                                counter!("request_ok", 1, &status_labels);
+                                // A bulk request succeeds as a whole even if
+                                // some of its documents are rejected, each
+                                // document's status is in the response.
+                                if status.is_success() {
+                                    if let Ok(bytes) = hyper::body::to_bytes(body).await {
+                                        if let Ok(response) = serde_json::from_slice::<BulkResponse>(&bytes) {
+                                            let indexed = response
+                                                .items
+                                                .iter()
+                                                .flat_map(HashMap::values)
+                                                .filter(|item| item.status < 300)
+                                                .count();
+                                            counter!("documents_indexed", indexed as u64, &labels);
+                                            counter!(
+                                                "documents_failed",
+                                                (response.items.len() - indexed) as u64,
+                                                &labels
+                                            );
+                                        }
+                                    }
+                                }
+                            }
+                            Err(err) => {
+                                let mut error_labels = labels.clone();
+                                error_labels
+                                    .push(("error".to_string(), hyper_error_kind(&err).to_string()));
+                                counter!("request_failure", 1, &error_labels);
+                            }
+                        }
+                        drop(permit);
+                    });
+                    budget.record(u64::from(total_bytes.get()), blk.lines);
+                },
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    // Acquire all available connections, meaning that we have
+                    // no outstanding tasks in flight.
+                    let _semaphore = connection_semaphore.acquire_many(u32::from(self.parallel_connections)).await.unwrap();
+                    return Ok(());
+                },
+            }
+
+            if budget.exhausted() {
+                info!("finite data limit reached, generator complete");
+                let _semaphore = connection_semaphore
+                    .acquire_many(u32::from(self.parallel_connections))
+                    .await
+                    .unwrap();
+                gauge!("generator_complete", 1.0, &labels);
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::{documents, encode_request, Action};
+
+    // Every payload line yields documents that are single line JSON objects,
+    // and a bulk body alternates action lines with those documents.
+    proptest! {
+        #[test]
+        fn bulk_body_alternates_actions_and_documents(
+            lines in prop::collection::vec(any::<Vec<u8>>(), 1..16),
+            create: bool,
+        ) {
+            let action = if create { Action::Create } else { Action::Index };
+            let mut docs = Vec::new();
+            for line in lines.iter().filter(|line| !line.contains(&b'\n')) {
+                documents(line, &mut docs);
+            }
+            let body = encode_request(
+                action,
+                &docs.iter().map(|doc| &doc[..]).collect::<Vec<_>>(),
+            );
+            prop_assert!(body.is_empty() || body.ends_with(b"\n"));
+            let body_lines: Vec<&[u8]> = body.split(|byte| *byte == b'\n').collect();
+            prop_assert_eq!(body_lines.len(), docs.len() * 2 + 1);
+            for pair in body_lines.chunks_exact(2) {
+                prop_assert_eq!(pair[0], &action.line()[..action.line().len() - 1]);
+                let document = serde_json::from_slice::<serde_json::Value>(pair[1]).unwrap();
+                prop_assert!(document.is_object());
+            }
+        }
+    }
+}
@@ -0,0 +1,331 @@
+//! The named pipe writing generator.
+//!
+//! Some agents ingest only from a named pipe, a FIFO. This generator writes
+//! its block cache into one at a fixed byte rate, creating the FIFO if it does
+//! not exist. A FIFO cannot be written until a reader opens it, so the
+//! generator waits for the target to open it, and waits again should the
+//! target close it.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    num::{NonZeroU32, NonZeroUsize},
+    os::unix::fs::{FileTypeExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+};
+
+use byte_unit::{Byte, ByteUnit};
+use governor::state::direct::InsufficientCapacity;
+use metrics::counter;
+use nix::{errno::Errno, libc, sys::stat::Mode, unistd::mkfifo};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Deserialize;
+use tokio::{
+    io::unix::AsyncFd,
+    time::{self, Duration},
+};
+use tracing::info;
+
+use crate::{
+    block::{self, chunk_bytes, Block},
+    control::Pause,
+    generator::{
+        common::{Budget, RateWindow, RATE_WINDOW},
+        tcp::{record_block, GeneratorVariant},
+        Meter,
+    },
+    numa, payload,
+    signals::Shutdown,
+    telemetry::io_error_kind,
+    throttle::{self, Throttle},
+};
+
+/// The time between attempts to open the FIFO while it has no reader.
+const REOPEN_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration of this generator.
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// The path of the FIFO, created if it does not exist
+    pub path: PathBuf,
+    /// The payload variant
+    pub variant: GeneratorVariant,
+    /// The bytes per second to write to the FIFO
+    pub bytes_per_second: byte_unit::Byte,
+    /// The block sizes for messages to this target
+    pub block_sizes: Option<Vec<byte_unit::Byte>>,
+    /// The maximum size in bytes of the cache of prebuilt messages
+    pub maximum_prebuild_cache_size_bytes: byte_unit::Byte,
+    /// The maximum number of bytes to send before this generator stops. If
+    /// unset the generator runs until the experiment ends.
+    pub maximum_bytes: Option<byte_unit::Byte>,
+    /// The maximum number of events -- newline delimited lines -- to send
+    /// before this generator stops. If unset the generator runs until the
+    /// experiment ends.
+    pub maximum_events: Option<u64>,
+    /// The algorithm used to throttle output to `bytes_per_second`. Defaults to a
+    /// token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Limit on the size of each newline delimited event. If unset events are
+    /// as large as the payload makes them.
+    pub event_limit: Option<payload::EventLimit>,
+    /// Tuning for the shape of messages produced by the syslog5424 variant,
+    /// ignored by other variants.
+    #[serde(default)]
+    pub syslog5424: payload::Syslog5424Config,
+    /// Whether to lock the block cache into RAM, touching each of its pages,
+    /// before the run starts. See mlock(2) for the limits on locked memory.
+    #[serde(default)]
+    pub lock_block_cache: bool,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+}
+
+#[derive(Debug)]
+/// Errors produced by [`Fifo`].
+pub enum Error {
+    /// Rate limiter has insuficient capacity for payload. Indicates a serious
+    /// bug.
+    Governor(InsufficientCapacity),
+    /// Creation of payload blocks failed.
+    Block(block::Error),
+    /// Wrapper for [`std::io::Error`].
+    Io(io::Error),
+    /// The FIFO could not be created.
+    Mkfifo(Errno),
+    /// The path exists but is not a FIFO.
+    NotFifo(PathBuf),
+}
+
+impl From<block::Error> for Error {
+    fn from(error: block::Error) -> Self {
+        Error::Block(error)
+    }
+}
+
+impl From<InsufficientCapacity> for Error {
+    fn from(error: InsufficientCapacity) -> Self {
+        Error::Governor(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+/// Build the block cache of a generator configured by `config`, as
+/// [`Fifo::new`] does.
+///
+/// # Errors
+///
+/// Function will return an error if the configured block sizes cannot be
+/// chunked into the prebuild cache.
+///
+/// # Panics
+///
+/// Function will panic if user has passed zero values for any byte values.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::ptr_arg)]
+pub(crate) fn block_cache(
+    config: &Config,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<Block>, Error> {
+    let mut rng = StdRng::from_seed(config.seed);
+    let block_sizes: Vec<NonZeroUsize> = config
+        .block_sizes
+        .clone()
+        .unwrap_or_else(|| {
+            vec![
+                Byte::from_unit(1.0 / 32.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 16.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 8.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 4.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 2.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1_f64, ByteUnit::MB).unwrap(),
+            ]
+        })
+        .iter()
+        .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
+        .collect();
+    let block_chunks = chunk_bytes(
+        &mut rng,
+        NonZeroUsize::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as usize)
+            .expect("bytes must be non-zero"),
+        &block_sizes,
+    )?;
+    Ok(config.variant.block_cache(
+        &mut rng,
+        &block_chunks,
+        config.event_limit,
+        config.syslog5424,
+        labels,
+    ))
+}
+
+/// Create a FIFO at `path` unless one exists there.
+///
+/// # Errors
+///
+/// Function will return an error if `path` exists but is not a FIFO, or the
+/// FIFO cannot be created.
+fn ensure_fifo(path: &Path) -> Result<(), Error> {
+    match path.metadata() {
+        Ok(metadata) if metadata.file_type().is_fifo() => Ok(()),
+        Ok(_) => Err(Error::NotFifo(path.to_path_buf())),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            info!("creating FIFO {}", path.display());
+            mkfifo(path, Mode::from_bits_truncate(0o644)).map_err(Error::Mkfifo)
+        }
+        Err(err) => Err(Error::Io(err)),
+    }
+}
+
+/// Open the FIFO at `path` for writing, or `None` if no reader has it open.
+fn open(path: &Path) -> Result<Option<AsyncFd<File>>, io::Error> {
+    // Opened without blocking the open fails, rather than waits, when there
+    // is no reader.
+    match OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+    {
+        Ok(file) => AsyncFd::new(file).map(Some),
+        Err(err) if err.raw_os_error() == Some(libc::ENXIO) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Write all of `buf` into `fifo`, waiting for the reader to make room.
+async fn write_all(fifo: &AsyncFd<File>, mut buf: &[u8]) -> Result<(), io::Error> {
+    while !buf.is_empty() {
+        let mut guard = fifo.writable().await?;
+        match guard.try_io(|inner| {
+            let mut file = inner.get_ref();
+            file.write(buf)
+        }) {
+            Ok(Ok(0)) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(Ok(written)) => buf = &buf[written..],
+            Ok(Err(err)) => return Err(err),
+            Err(_would_block) => continue,
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+/// The FIFO generator.
+///
+/// This generator is responsible for writing into a named pipe the target
+/// reads from.
+pub struct Fifo {
+    path: PathBuf,
+    throttle: Throttle,
+    block_cache: Vec<Block>,
+    metric_labels: Vec<(String, String)>,
+    budget: Budget,
+    shutdown: Shutdown,
+    meter: Meter,
+}
+
+impl Fifo {
+    /// Create a new [`Fifo`] instance, creating the FIFO if it does not exist
+    ///
+    /// # Errors
+    ///
+    /// Creation will fail if the block cache cannot be built, or if the FIFO
+    /// cannot be created.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if user has passed zero values for any byte
+    /// values. Sharp corners.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(
+        config: &Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
+        let bytes_per_second = NonZeroU32::new(config.bytes_per_second.get_bytes() as u32).unwrap();
+        let throttle = Throttle::new(config.throttle, bytes_per_second, pause);
+        let labels = vec![];
+        let mut block_cache = block_cache(config, &labels)?;
+        if config.lock_block_cache {
+            block::lock(&mut block_cache, &labels)?;
+        }
+        ensure_fifo(&config.path)?;
+
+        Ok(Self {
+            path: config.path.clone(),
+            block_cache,
+            throttle,
+            metric_labels: labels,
+            budget: Budget::new(config.maximum_bytes, config.maximum_events),
+            shutdown,
+            meter,
+        })
+    }
+
+    /// Run [`Fifo`] to completion or until a shutdown signal is received.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the FIFO cannot be opened for a
+    /// reason other than having no reader. Write errors, a reader closing the
+    /// FIFO among them, are recorded and the FIFO re-opened.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if underlying byte capacity is not available.
+    pub async fn spin(mut self) -> Result<(), Error> {
+        let labels = self.metric_labels;
+        let mut budget = self.budget;
+        let mut rate_window = RateWindow::new(RATE_WINDOW);
+
+        let mut fifo = None;
+        let mut reopen = time::interval(REOPEN_INTERVAL);
+        let mut blocks = self.block_cache.iter().cycle();
+
+        loop {
+            let blk = blocks.next().unwrap();
+            let total_bytes = blk.total_bytes;
+
+            tokio::select! {
+                _ = reopen.tick(), if fifo.is_none() => {
+                    fifo = open(&self.path)?;
+                    if fifo.is_some() {
+                        info!("FIFO {} has a reader", self.path.display());
+                    }
+                }
+                _ = self.throttle.wait(total_bytes, &labels), if fifo.is_some() => {
+                    match write_all(fifo.as_ref().unwrap(), &blk.bytes).await {
+                        Ok(()) => {
+                            if record_block(blk, &labels, &self.meter, &mut rate_window, &mut budget) {
+                                return Ok(());
+                            }
+                        }
+                        Err(err) => {
+                            let mut error_labels = labels.clone();
+                            error_labels.push(("error".to_string(), io_error_kind(&err)));
+                            counter!("request_failure", 1, &error_labels);
+                            if err.kind() == io::ErrorKind::BrokenPipe {
+                                info!("FIFO {} reader disconnected", self.path.display());
+                                counter!("reader_disconnected", 1, &labels);
+                            }
+                            fifo = None;
+                        }
+                    }
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(());
+                },
+            }
+        }
+    }
+}
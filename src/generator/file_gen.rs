@@ -10,16 +10,13 @@ use std::{
     str,
     sync::{
         atomic::{AtomicU32, Ordering},
-        Arc,
+        Arc, Mutex,
     },
 };
 
 use byte_unit::{Byte, ByteUnit};
-use governor::{
-    clock, state,
-    state::direct::{self, InsufficientCapacity},
-    Quota, RateLimiter,
-};
+use futures::future::join_all;
+use governor::state::direct::InsufficientCapacity;
 use metrics::{counter, gauge};
 use rand::{prelude::StdRng, SeedableRng};
 use serde::Deserialize;
@@ -32,8 +29,14 @@ use tracing::info;
 
 use crate::{
     block::{self, chunk_bytes, construct_block_cache, Block},
-    payload,
+    control::Pause,
+    generator::{
+        common::{Budget, RateWindow, RATE_WINDOW},
+        Meter,
+    },
+    numa, payload,
     signals::Shutdown,
+    throttle::{self, Throttle},
 };
 
 #[derive(Debug)]
@@ -89,7 +92,7 @@ fn default_rotation() -> bool {
     true
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 /// Configuration of [`FileGen`]
 pub struct Config {
     /// The seed for random operations against this target
@@ -113,17 +116,125 @@ pub struct Config {
     /// written _continuously_ per second from this target. Higher bursts are
     /// possible as the internal governor accumulates, up to
     /// `maximum_bytes_burst`.
-    bytes_per_second: Byte,
+    pub bytes_per_second: Byte,
     /// The block sizes for messages to this target
     pub block_sizes: Option<Vec<byte_unit::Byte>>,
     /// Defines the maximum internal cache of this log target. file_gen will
     /// pre-build its outputs up to the byte capacity specified here.
-    maximum_prebuild_cache_size_bytes: Byte,
+    pub maximum_prebuild_cache_size_bytes: Byte,
     /// Determines whether the file generator mimics log rotation or not. If
     /// true, files will be rotated. If false, it is the responsibility of
     /// tailing software to remove old files.
     #[serde(default = "default_rotation")]
     rotate: bool,
+    /// The maximum number of bytes to write, across all duplicates, before
+    /// this generator stops. If unset the generator runs until the experiment
+    /// ends.
+    pub maximum_bytes: Option<Byte>,
+    /// The maximum number of events -- newline delimited lines -- to write,
+    /// across all duplicates, before this generator stops. If unset the
+    /// generator runs until the experiment ends.
+    pub maximum_events: Option<u64>,
+    /// The algorithm used to throttle output to `bytes_per_second`. Defaults to a
+    /// token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Limit on the size of each newline delimited event. If unset events are
+    /// as large as the payload makes them.
+    pub event_limit: Option<payload::EventLimit>,
+    /// Whether to lock the block cache into RAM, touching each of its pages,
+    /// before the run starts. See mlock(2) for the limits on locked memory.
+    #[serde(default)]
+    pub lock_block_cache: bool,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+}
+
+/// Build the block caches of a generator configured by `config`, one for each
+/// of its duplicates, as [`FileGen::new`] does.
+///
+/// # Errors
+///
+/// Function will return an error if the configured block sizes cannot be
+/// chunked into the prebuild cache.
+///
+/// # Panics
+///
+/// Function will panic if user has passed zero values for any byte values.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::ptr_arg)]
+pub(crate) fn block_caches(
+    config: &Config,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<Vec<Block>>, Error> {
+    let mut rng = StdRng::from_seed(config.seed);
+    let block_sizes: Vec<NonZeroUsize> = config
+        .block_sizes
+        .clone()
+        .unwrap_or_else(|| {
+            vec![
+                Byte::from_unit(1_f64, ByteUnit::MB).unwrap(),
+                Byte::from_unit(2_f64, ByteUnit::MB).unwrap(),
+                Byte::from_unit(4_f64, ByteUnit::MB).unwrap(),
+                Byte::from_unit(8_f64, ByteUnit::MB).unwrap(),
+                Byte::from_unit(16_f64, ByteUnit::MB).unwrap(),
+                Byte::from_unit(32_f64, ByteUnit::MB).unwrap(),
+            ]
+        })
+        .iter()
+        .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
+        .collect();
+    let maximum_prebuild_cache_size_bytes =
+        NonZeroU32::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as u32).unwrap();
+    let block_chunks = chunk_bytes(
+        &mut rng,
+        NonZeroUsize::new(maximum_prebuild_cache_size_bytes.get() as usize)
+            .expect("bytes must be non-zero"),
+        &block_sizes,
+    )?;
+
+    let mut block_caches = Vec::with_capacity(usize::from(config.duplicates));
+    for _ in 0..config.duplicates {
+        let block_cache = match config.variant {
+            Variant::Ascii => construct_block_cache(
+                &mut rng,
+                &payload::Ascii::default(),
+                &block_chunks,
+                config.event_limit,
+                labels,
+            ),
+            Variant::DatadogLog => construct_block_cache(
+                &mut rng,
+                &payload::DatadogLog::default(),
+                &block_chunks,
+                config.event_limit,
+                labels,
+            ),
+            Variant::Json => construct_block_cache(
+                &mut rng,
+                &payload::Json::default(),
+                &block_chunks,
+                config.event_limit,
+                labels,
+            ),
+            Variant::FoundationDb => construct_block_cache(
+                &mut rng,
+                &payload::FoundationDb::default(),
+                &block_chunks,
+                config.event_limit,
+                labels,
+            ),
+            Variant::Static { ref static_path } => construct_block_cache(
+                &mut rng,
+                &payload::Static::new(static_path),
+                &block_chunks,
+                config.event_limit,
+                labels,
+            ),
+        };
+        block_caches.push(block_cache);
+    }
+    Ok(block_caches)
 }
 
 #[derive(Debug)]
@@ -147,89 +258,47 @@ impl FileGen {
     ///
     /// Function will panic if variant is Static and the `static_path` is not
     /// set.
-    #[allow(clippy::cast_possible_truncation)]
-    pub fn new(config: Config, shutdown: Shutdown) -> Result<Self, Error> {
-        let mut rng = StdRng::from_seed(config.seed);
-        let block_sizes: Vec<NonZeroUsize> = config
-            .block_sizes
-            .unwrap_or_else(|| {
-                vec![
-                    Byte::from_unit(1_f64, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(2_f64, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(4_f64, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(8_f64, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(16_f64, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(32_f64, ByteUnit::MB).unwrap(),
-                ]
-            })
-            .iter()
-            .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
-            .collect();
-
+    #[allow(clippy::cast_possible_truncation, clippy::needless_pass_by_value)]
+    pub fn new(
+        config: Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
         let bytes_per_second = NonZeroU32::new(config.bytes_per_second.get_bytes() as u32).unwrap();
         let maximum_bytes_per_file =
             NonZeroU32::new(config.maximum_bytes_per_file.get_bytes() as u32).unwrap();
-        let maximum_prebuild_cache_size_bytes =
-            NonZeroU32::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as u32).unwrap();
-
-        let block_chunks = chunk_bytes(
-            &mut rng,
-            NonZeroUsize::new(maximum_prebuild_cache_size_bytes.get() as usize)
-                .expect("bytes must be non-zero"),
-            &block_sizes,
-        )?;
 
         let labels = vec![];
+        let block_caches = block_caches(&config, &labels)?;
         let mut handles = Vec::new();
         let file_index = Arc::new(AtomicU32::new(0));
-        for _ in 0..config.duplicates {
-            let rate_limiter: RateLimiter<
-                direct::NotKeyed,
-                state::InMemoryState,
-                clock::QuantaClock,
-            > = RateLimiter::direct(Quota::per_second(bytes_per_second));
+        let budget = Arc::new(Mutex::new(Budget::new(
+            config.maximum_bytes,
+            config.maximum_events,
+        )));
+        // Children share one window, `achieved_bytes_per_second` being the
+        // rate of the generator as a whole.
+        let rate_window = Arc::new(Mutex::new(RateWindow::new(RATE_WINDOW)));
+        for mut block_cache in block_caches {
+            let throttle = Throttle::new(config.throttle, bytes_per_second, pause.clone());
 
-            let block_cache = match config.variant {
-                Variant::Ascii => construct_block_cache(
-                    &mut rng,
-                    &payload::Ascii::default(),
-                    &block_chunks,
-                    &labels,
-                ),
-                Variant::DatadogLog => construct_block_cache(
-                    &mut rng,
-                    &payload::DatadogLog::default(),
-                    &block_chunks,
-                    &labels,
-                ),
-                Variant::Json => construct_block_cache(
-                    &mut rng,
-                    &payload::Json::default(),
-                    &block_chunks,
-                    &labels,
-                ),
-                Variant::FoundationDb => construct_block_cache(
-                    &mut rng,
-                    &payload::FoundationDb::default(),
-                    &block_chunks,
-                    &labels,
-                ),
-                Variant::Static { ref static_path } => construct_block_cache(
-                    &mut rng,
-                    &payload::Static::new(static_path),
-                    &block_chunks,
-                    &labels,
-                ),
-            };
+            if config.lock_block_cache {
+                block::lock(&mut block_cache, &labels)?;
+            }
 
             let child = Child {
                 path_template: config.path_template.clone(),
                 maximum_bytes_per_file,
                 bytes_per_second,
-                rate_limiter,
+                throttle,
                 block_cache,
                 file_index: Arc::clone(&file_index),
                 rotate: config.rotate,
+                budget: Arc::clone(&budget),
+                rate_window: Arc::clone(&rate_window),
+                metric_labels: labels.clone(),
+                meter: meter.clone(),
             };
 
             handles.push(tokio::spawn(child.spin()));
@@ -251,8 +320,24 @@ impl FileGen {
     #[allow(clippy::cast_precision_loss)]
     #[allow(clippy::cast_possible_truncation)]
     pub async fn spin(mut self) -> Result<(), Error> {
-        self.shutdown.recv().await;
-        info!("shutdown signal received");
+        // Children only complete on their own if they have hit an error or
+        // have exhausted their finite data limit.
+        let children = join_all(self.handles.iter_mut());
+        tokio::select! {
+            results = children => {
+                for res in results {
+                    if let Ok(Err(err)) = res {
+                        return Err(err);
+                    }
+                }
+                info!("finite data limit reached, generator complete");
+                gauge!("generator_complete", 1.0);
+                return Ok(());
+            }
+            _ = self.shutdown.recv() => {
+                info!("shutdown signal received");
+            }
+        }
         for handle in self.handles.drain(..) {
             handle.abort();
         }
@@ -264,14 +349,18 @@ struct Child {
     path_template: String,
     maximum_bytes_per_file: NonZeroU32,
     bytes_per_second: NonZeroU32,
-    rate_limiter: RateLimiter<direct::NotKeyed, state::InMemoryState, clock::QuantaClock>,
+    throttle: Throttle,
     block_cache: Vec<Block>,
     rotate: bool,
     file_index: Arc<AtomicU32>,
+    budget: Arc<Mutex<Budget>>,
+    rate_window: Arc<Mutex<RateWindow>>,
+    metric_labels: Vec<(String, String)>,
+    meter: Meter,
 }
 
 impl Child {
-    pub(crate) async fn spin(self) -> Result<(), Error> {
+    pub(crate) async fn spin(mut self) -> Result<(), Error> {
         let bytes_per_second = self.bytes_per_second.get() as usize;
         let mut bytes_written: u64 = 0;
         let maximum_bytes_per_file: u64 = u64::from(self.maximum_bytes_per_file.get());
@@ -294,7 +383,7 @@ impl Child {
             let total_newlines = blk.lines;
             let block = &blk.bytes;
 
-            self.rate_limiter.until_n_ready(total_bytes).await?;
+            self.throttle.wait(total_bytes, &self.metric_labels).await?;
 
             {
                 fp.write_all(block).await?;
@@ -303,12 +392,26 @@ impl Child {
                 // avoid needing to get a plain value from a non-zero by calling
                 // len here.
                 counter!("bytes_written", block.len() as u64);
+                self.meter.record(block.len() as u64);
                 counter!("lines_written", total_newlines);
 
+                self.rate_window
+                    .lock()
+                    .unwrap()
+                    .record(block.len() as u64, &self.metric_labels);
                 bytes_written += block.len() as u64;
                 gauge!("current_target_size_bytes", bytes_written as f64);
             }
 
+            let exhausted = {
+                let mut budget = self.budget.lock().unwrap();
+                budget.record(block.len() as u64, total_newlines);
+                budget.exhausted()
+            };
+            if exhausted {
+                return Ok(());
+            }
+
             if bytes_written > maximum_bytes_per_file {
                 if self.rotate {
                     // Delete file, leaving any open file handlers intact. This
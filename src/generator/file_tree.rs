@@ -0,0 +1,508 @@
+//! The file tree churn generator.
+//!
+//! File watchers and inotify heavy targets pay for metadata churn, not bytes.
+//! This generator creates, renames and deletes files and directories under a
+//! root directory, each at its own rate, growing a tree no deeper than
+//! `maximum_depth` of no more than `maximum_entries` entries. Files are
+//! created empty.
+//!
+//! The generator keeps a model of the tree it has built and draws each
+//! operation from the model. Entries are renamed within their directory and
+//! only files and empty directories are deleted, so that each operation is a
+//! single event to the target. The tree is not re-read from disk: should the
+//! target itself change the tree, operations on what it changed fail and are
+//! counted as `operation_failure`.
+
+use std::{
+    collections::HashMap,
+    fs as std_fs, io,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+};
+
+use futures::future;
+use governor::state::direct::InsufficientCapacity;
+use metrics::{counter, gauge};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Deserialize;
+use tokio::fs;
+use tracing::info;
+
+use crate::{
+    control::Pause,
+    numa,
+    signals::Shutdown,
+    telemetry::io_error_kind,
+    throttle::{self, Throttle},
+};
+
+/// The prefix of the name of each entry the generator creates.
+pub(crate) const ENTRY_PREFIX: &str = "lading-tree-";
+
+fn default_maximum_depth() -> NonZeroU32 {
+    NonZeroU32::new(4).unwrap()
+}
+
+fn default_maximum_entries() -> NonZeroU32 {
+    NonZeroU32::new(10_000).unwrap()
+}
+
+fn default_directory_percent() -> u8 {
+    10
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration of [`FileTree`]
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// The directory the tree is grown under, created if it does not exist
+    pub root: PathBuf,
+    /// The deepest an entry may be below `root`, its children at depth one
+    #[serde(default = "default_maximum_depth")]
+    pub maximum_depth: NonZeroU32,
+    /// The most entries the tree may hold. Creations beyond are skipped.
+    #[serde(default = "default_maximum_entries")]
+    pub maximum_entries: NonZeroU32,
+    /// The percent of created entries that are directories, the rest files
+    #[serde(default = "default_directory_percent")]
+    pub directory_percent: u8,
+    /// Entries created per second. If unset none are.
+    pub creates_per_second: Option<NonZeroU32>,
+    /// Entries renamed per second. If unset none are.
+    pub renames_per_second: Option<NonZeroU32>,
+    /// Entries deleted per second. If unset none are.
+    pub deletes_per_second: Option<NonZeroU32>,
+    /// The maximum number of operations to make before this generator stops.
+    /// If unset the generator runs until the experiment ends.
+    pub maximum_operations: Option<u64>,
+    /// The algorithm used to throttle each operation to its rate. Defaults to
+    /// a token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+}
+
+#[derive(Debug)]
+/// Errors produced by [`FileTree`].
+pub enum Error {
+    /// Rate limiter has insuficient capacity for payload. Indicates a serious
+    /// bug.
+    Governor(InsufficientCapacity),
+    /// Wrapper around [`std::io::Error`].
+    Io(io::Error),
+}
+
+impl From<InsufficientCapacity> for Error {
+    fn from(error: InsufficientCapacity) -> Self {
+        Error::Governor(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+/// An operation on the tree, by the paths it touches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operation {
+    CreateFile(PathBuf),
+    CreateDirectory(PathBuf),
+    Rename { from: PathBuf, to: PathBuf },
+    DeleteFile(PathBuf),
+    DeleteDirectory(PathBuf),
+}
+
+impl Operation {
+    /// The `operation` label of this operation.
+    fn name(&self) -> &'static str {
+        match self {
+            Operation::CreateFile(_) => "create_file",
+            Operation::CreateDirectory(_) => "create_directory",
+            Operation::Rename { .. } => "rename",
+            Operation::DeleteFile(_) => "delete_file",
+            Operation::DeleteDirectory(_) => "delete_directory",
+        }
+    }
+
+    /// Make this operation on disk.
+    async fn apply(&self) -> Result<(), io::Error> {
+        match self {
+            Operation::CreateFile(path) => fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)
+                .await
+                .map(drop),
+            Operation::CreateDirectory(path) => fs::create_dir(path).await,
+            Operation::Rename { from, to } => fs::rename(from, to).await,
+            Operation::DeleteFile(path) => fs::remove_file(path).await,
+            Operation::DeleteDirectory(path) => fs::remove_dir(path).await,
+        }
+    }
+}
+
+/// An entry of the tree.
+#[derive(Debug)]
+struct Node {
+    /// The directory holding this entry, `None` for the root.
+    parent: Option<u64>,
+    name: String,
+    /// The entries of a directory, `None` for a file.
+    children: Option<Vec<u64>>,
+    depth: u32,
+}
+
+/// The generator's model of the tree on disk.
+#[derive(Debug)]
+struct Tree {
+    root: PathBuf,
+    maximum_depth: u32,
+    maximum_entries: usize,
+    directory_percent: u8,
+    nodes: HashMap<u64, Node>,
+    /// Every entry but the root.
+    entries: Vec<u64>,
+    /// Every directory above the deepest level, the root among them.
+    parents: Vec<u64>,
+    next_id: u64,
+}
+
+/// The id of the root in [`Tree::nodes`].
+const ROOT: u64 = 0;
+
+impl Tree {
+    fn new(config: &Config) -> Self {
+        let mut nodes = HashMap::new();
+        nodes.insert(
+            ROOT,
+            Node {
+                parent: None,
+                name: String::new(),
+                children: Some(Vec::new()),
+                depth: 0,
+            },
+        );
+        Self {
+            root: config.root.clone(),
+            maximum_depth: config.maximum_depth.get(),
+            maximum_entries: config.maximum_entries.get() as usize,
+            directory_percent: config.directory_percent.min(100),
+            nodes,
+            entries: Vec::new(),
+            parents: vec![ROOT],
+            next_id: ROOT + 1,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The path of entry `id`.
+    fn path(&self, id: u64) -> PathBuf {
+        let mut names = Vec::new();
+        let mut node = &self.nodes[&id];
+        while let Some(parent) = node.parent {
+            names.push(node.name.as_str());
+            node = &self.nodes[&parent];
+        }
+        let mut path = self.root.clone();
+        path.extend(names.into_iter().rev());
+        path
+    }
+
+    /// A name no entry has had.
+    fn fresh_name(&mut self) -> (u64, String) {
+        let id = self.next_id;
+        self.next_id += 1;
+        (id, format!("{}{}", ENTRY_PREFIX, id))
+    }
+
+    /// Create an entry in a random directory, unless the tree is full.
+    fn create<R: Rng>(&mut self, rng: &mut R) -> Option<Operation> {
+        if self.len() >= self.maximum_entries {
+            return None;
+        }
+        // Directories at the deepest level are left empty, so that no entry is
+        // deeper than the maximum.
+        let parent = self.parents[rng.gen_range(0..self.parents.len())];
+        let depth = self.nodes[&parent].depth + 1;
+        let directory = rng.gen_range(0..100) < self.directory_percent;
+        let (id, name) = self.fresh_name();
+        self.nodes
+            .get_mut(&parent)
+            .and_then(|node| node.children.as_mut())
+            .expect("parents are directories")
+            .push(id);
+        self.nodes.insert(
+            id,
+            Node {
+                parent: Some(parent),
+                name,
+                children: directory.then(Vec::new),
+                depth,
+            },
+        );
+        self.entries.push(id);
+        let path = self.path(id);
+        if directory {
+            if depth < self.maximum_depth {
+                self.parents.push(id);
+            }
+            Some(Operation::CreateDirectory(path))
+        } else {
+            Some(Operation::CreateFile(path))
+        }
+    }
+
+    /// Rename a random entry within its directory, unless the tree is empty.
+    fn rename<R: Rng>(&mut self, rng: &mut R) -> Option<Operation> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let id = self.entries[rng.gen_range(0..self.entries.len())];
+        let from = self.path(id);
+        let (_, name) = self.fresh_name();
+        self.nodes.get_mut(&id).expect("entries are nodes").name = name;
+        Some(Operation::Rename {
+            from,
+            to: self.path(id),
+        })
+    }
+
+    /// Delete a random file or empty directory, unless the tree is empty. A
+    /// random entry is drawn and, while it is a directory with entries, one of
+    /// its entries drawn in its place.
+    fn delete<R: Rng>(&mut self, rng: &mut R) -> Option<Operation> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let mut id = self.entries[rng.gen_range(0..self.entries.len())];
+        while let Some(children) = self.nodes[&id]
+            .children
+            .as_ref()
+            .filter(|children| !children.is_empty())
+        {
+            id = children[rng.gen_range(0..children.len())];
+        }
+        let path = self.path(id);
+        let node = self.nodes.remove(&id).expect("entries are nodes");
+        if let Some(children) = node
+            .parent
+            .and_then(|parent| self.nodes.get_mut(&parent))
+            .and_then(|parent| parent.children.as_mut())
+        {
+            children.retain(|child| *child != id);
+        }
+        self.entries.retain(|entry| *entry != id);
+        if node.children.is_some() {
+            self.parents.retain(|parent| *parent != id);
+            Some(Operation::DeleteDirectory(path))
+        } else {
+            Some(Operation::DeleteFile(path))
+        }
+    }
+}
+
+/// Whether `name` is the name of an entry the generator creates.
+pub(crate) fn is_entry(name: &str) -> bool {
+    name.strip_prefix(ENTRY_PREFIX).map_or(false, |id| {
+        !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit())
+    })
+}
+
+/// Remove the entries a previous run left directly under `root`, and all they
+/// hold.
+fn clear(root: &Path) -> Result<(), io::Error> {
+    for entry in std_fs::read_dir(root)? {
+        let entry = entry?;
+        if !entry.file_name().to_str().map_or(false, is_entry) {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            std_fs::remove_dir_all(entry.path())?;
+        } else {
+            std_fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Wait for `throttle` to release one operation, forever if there is none.
+#[allow(clippy::ptr_arg)]
+async fn tick(
+    throttle: &mut Option<Throttle>,
+    labels: &Vec<(String, String)>,
+) -> Result<(), InsufficientCapacity> {
+    match throttle {
+        Some(throttle) => throttle.wait(NonZeroU32::new(1).unwrap(), labels).await,
+        None => future::pending().await,
+    }
+}
+
+#[derive(Debug)]
+/// The file tree churn generator.
+///
+/// This generator is responsible for churning a tree of files and
+/// directories the target watches.
+pub struct FileTree {
+    tree: Tree,
+    rng: StdRng,
+    creates: Option<Throttle>,
+    renames: Option<Throttle>,
+    deletes: Option<Throttle>,
+    maximum_operations: Option<u64>,
+    metric_labels: Vec<(String, String)>,
+    shutdown: Shutdown,
+}
+
+impl FileTree {
+    /// Create a new [`FileTree`] instance, creating `root` if it does not
+    /// exist and removing the entries a previous run left in it.
+    ///
+    /// # Errors
+    ///
+    /// Creation will fail if `root` cannot be created or cleared.
+    pub fn new(config: &Config, shutdown: Shutdown, pause: Pause) -> Result<Self, Error> {
+        std_fs::create_dir_all(&config.root)?;
+        clear(&config.root)?;
+        let throttle = |rate: Option<NonZeroU32>| {
+            rate.map(|rate| Throttle::new(config.throttle, rate, pause.clone()))
+        };
+
+        Ok(Self {
+            tree: Tree::new(config),
+            rng: StdRng::from_seed(config.seed),
+            creates: throttle(config.creates_per_second),
+            renames: throttle(config.renames_per_second),
+            deletes: throttle(config.deletes_per_second),
+            maximum_operations: config.maximum_operations,
+            metric_labels: vec![],
+            shutdown,
+        })
+    }
+
+    /// Run [`FileTree`] to completion or until a shutdown signal is received.
+    ///
+    /// # Errors
+    ///
+    /// None known. Operations that fail on disk are recorded and the
+    /// generator carries on.
+    #[allow(clippy::cast_precision_loss)]
+    pub async fn spin(mut self) -> Result<(), Error> {
+        let labels = self.metric_labels;
+        let mut operations: u64 = 0;
+
+        loop {
+            let operation = tokio::select! {
+                res = tick(&mut self.creates, &labels) => {
+                    res?;
+                    let operation = self.tree.create(&mut self.rng);
+                    if operation.is_none() {
+                        counter!("operation_skipped", 1, &labels);
+                    }
+                    operation
+                }
+                res = tick(&mut self.renames, &labels) => {
+                    res?;
+                    self.tree.rename(&mut self.rng)
+                }
+                res = tick(&mut self.deletes, &labels) => {
+                    res?;
+                    self.tree.delete(&mut self.rng)
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(());
+                }
+            };
+            let operation = match operation {
+                Some(operation) => operation,
+                None => continue,
+            };
+
+            let mut operation_labels = labels.clone();
+            operation_labels.push(("operation".to_string(), operation.name().to_string()));
+            match operation.apply().await {
+                Ok(()) => counter!("operations", 1, &operation_labels),
+                Err(err) => {
+                    operation_labels.push(("error".to_string(), io_error_kind(&err)));
+                    counter!("operation_failure", 1, &operation_labels);
+                }
+            }
+            gauge!("tree_entries", self.tree.len() as f64, &labels);
+
+            operations += 1;
+            if self
+                .maximum_operations
+                .map_or(false, |max| operations >= max)
+            {
+                info!("maximum operations reached, generator complete");
+                gauge!("generator_complete", 1.0);
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use proptest::prelude::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{Config, Operation, Tree};
+
+    fn config(maximum_depth: u32, maximum_entries: u32, directory_percent: u8) -> Config {
+        serde_yaml::from_str(&format!(
+            "seed: [{}]\nroot: /tree\nmaximum_depth: {}\nmaximum_entries: {}\ndirectory_percent: {}\n",
+            vec!["0"; 32].join(", "),
+            maximum_depth,
+            maximum_entries,
+            directory_percent
+        ))
+        .unwrap()
+    }
+
+    // However operations interleave the tree stays within its limits, the
+    // paths of its entries are distinct and each operation touches paths
+    // under the root no deeper than the maximum depth.
+    proptest! {
+        #[test]
+        fn tree_within_limits(
+            seed: u64,
+            maximum_depth in 1..6_u32,
+            maximum_entries in 1..64_u32,
+            directory_percent in 0..=100_u8,
+            operations in proptest::collection::vec(0..3_u8, 0..256),
+        ) {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut tree = Tree::new(&config(maximum_depth, maximum_entries, directory_percent));
+            for operation in operations {
+                let operation = match operation {
+                    0 => tree.create(&mut rng),
+                    1 => tree.rename(&mut rng),
+                    _ => tree.delete(&mut rng),
+                };
+                let paths = match operation {
+                    Some(Operation::Rename { from, to }) => vec![from, to],
+                    Some(Operation::CreateFile(path) | Operation::CreateDirectory(path)
+                        | Operation::DeleteFile(path) | Operation::DeleteDirectory(path)) => vec![path],
+                    None => vec![],
+                };
+                for path in paths {
+                    let depth = path.strip_prefix("/tree").unwrap().components().count();
+                    prop_assert!(depth >= 1 && depth <= maximum_depth as usize);
+                }
+                prop_assert!(tree.len() <= maximum_entries as usize);
+                let paths: HashSet<_> = tree.entries.iter().map(|id| tree.path(*id)).collect();
+                prop_assert_eq!(paths.len(), tree.len());
+            }
+        }
+    }
+}
@@ -0,0 +1,416 @@
+//! The gRPC protocol speaking generator.
+//!
+//! Each block is sent as the message of a unary gRPC request to a user
+//! specified service and method, over a single HTTP/2 connection. Blocks are
+//! sent as they are built, lading makes no claim that they are valid protobuf
+//! messages of the method's request type. Pair a payload with a method whose
+//! request it encodes, or a target that does not decode what it receives.
+
+use std::{
+    num::{NonZeroU32, NonZeroUsize},
+    sync::Arc,
+};
+
+use byte_unit::{Byte, ByteUnit};
+use governor::state::direct::InsufficientCapacity;
+use hyper::{
+    body::HttpBody,
+    client::{Client, HttpConnector},
+    header::{HeaderValue, CONTENT_TYPE, TE},
+    Body, Request, Response, Uri,
+};
+use metrics::{counter, gauge};
+use rand::{prelude::StdRng, SeedableRng};
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+use tracing::info;
+
+use crate::{
+    address,
+    block::{self, chunk_bytes, Block},
+    control::Pause,
+    generator::{
+        common::{Budget, RateWindow, RATE_WINDOW},
+        http::Variant,
+        Meter,
+    },
+    numa, payload,
+    signals::Shutdown,
+    telemetry::hyper_error_kind,
+    throttle::{self, Throttle},
+};
+
+/// The header, or trailer, a gRPC server reports the status of a call in.
+const GRPC_STATUS: &str = "grpc-status";
+/// The status of a call that completed successfully.
+const GRPC_STATUS_OK: &str = "0";
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration of this generator.
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// The URI of the target, its scheme and authority, for instance
+    /// `http://localhost:4317`
+    #[serde(with = "http_serde::uri")]
+    pub target_uri: Uri,
+    /// The fully qualified name of the service to call, for instance
+    /// `opentelemetry.proto.collector.logs.v1.LogsService`
+    pub service: String,
+    /// The method of the service to call, for instance `Export`
+    pub method: String,
+    /// The payload generator to use for this target
+    pub variant: Variant,
+    /// The bytes per second to send or receive from the target
+    pub bytes_per_second: byte_unit::Byte,
+    /// The block sizes for messages to this target
+    pub block_sizes: Option<Vec<byte_unit::Byte>>,
+    /// The maximum size in bytes of the cache of prebuilt messages
+    pub maximum_prebuild_cache_size_bytes: byte_unit::Byte,
+    /// The total number of requests in flight at once
+    pub concurrent_requests: u16,
+    /// The maximum number of bytes to send before this generator stops. If
+    /// unset the generator runs until the experiment ends.
+    pub maximum_bytes: Option<byte_unit::Byte>,
+    /// The maximum number of events -- newline delimited lines -- to send
+    /// before this generator stops. If unset the generator runs until the
+    /// experiment ends.
+    pub maximum_events: Option<u64>,
+    /// The algorithm used to throttle output to `bytes_per_second`. Defaults to a
+    /// token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Limit on the size of each newline delimited event. If unset events are
+    /// as large as the payload makes them.
+    pub event_limit: Option<payload::EventLimit>,
+    /// Whether to lock the block cache into RAM, touching each of its pages,
+    /// before the run starts. See mlock(2) for the limits on locked memory.
+    #[serde(default)]
+    pub lock_block_cache: bool,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+}
+
+#[derive(Debug)]
+/// Errors produced by [`Grpc`].
+pub enum Error {
+    /// Rate limiter has insuficient capacity for payload. Indicates a serious
+    /// bug.
+    Governor(InsufficientCapacity),
+    /// Creation of payload blocks failed.
+    Block(block::Error),
+    /// Wrapper around [`hyper::http::Error`].
+    Http(hyper::http::Error),
+    /// Wrapper around [`std::io::Error`].
+    Io(::std::io::Error),
+}
+
+impl From<block::Error> for Error {
+    fn from(error: block::Error) -> Self {
+        Error::Block(error)
+    }
+}
+
+impl From<hyper::http::Error> for Error {
+    fn from(error: hyper::http::Error) -> Self {
+        Error::Http(error)
+    }
+}
+
+impl From<InsufficientCapacity> for Error {
+    fn from(error: InsufficientCapacity) -> Self {
+        Error::Governor(error)
+    }
+}
+
+impl From<::std::io::Error> for Error {
+    fn from(error: ::std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+/// Frame `message` as a gRPC length-prefixed message, uncompressed.
+fn frame(message: &[u8]) -> Vec<u8> {
+    let length = u32::try_from(message.len()).expect("block sizes fit in u32");
+    let mut framed = Vec::with_capacity(message.len() + 5);
+    framed.push(0);
+    framed.extend_from_slice(&length.to_be_bytes());
+    framed.extend_from_slice(message);
+    framed
+}
+
+/// Build the block cache of a generator configured by `config`, as
+/// [`Grpc::new`] does. Each block is framed as a gRPC message, its total bytes
+/// including the frame header.
+///
+/// # Errors
+///
+/// Function will return an error if the configured block sizes cannot be
+/// chunked into the prebuild cache.
+///
+/// # Panics
+///
+/// Function will panic if user has passed zero values for any byte values.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::ptr_arg)]
+pub(crate) fn block_cache(
+    config: &Config,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<Block>, Error> {
+    let mut rng = StdRng::from_seed(config.seed);
+    let block_sizes: Vec<NonZeroUsize> = config
+        .block_sizes
+        .clone()
+        .unwrap_or_else(|| {
+            vec![
+                Byte::from_unit(1.0 / 32.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 16.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 8.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 4.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 2.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1_f64, ByteUnit::MB).unwrap(),
+            ]
+        })
+        .iter()
+        .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
+        .collect();
+    let block_chunks = chunk_bytes(
+        &mut rng,
+        NonZeroUsize::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as usize)
+            .expect("bytes must be non-zero"),
+        &block_sizes,
+    )?;
+    let mut block_cache =
+        config
+            .variant
+            .block_cache(&mut rng, &block_chunks, config.event_limit, labels);
+    for blk in &mut block_cache {
+        blk.bytes = frame(&blk.bytes);
+        blk.total_bytes =
+            NonZeroU32::new(blk.bytes.len() as u32).expect("framed blocks are non-empty");
+    }
+    Ok(block_cache)
+}
+
+/// The status code of the gRPC call answered by `response`: from its headers
+/// if the server answered with trailers only, otherwise from its trailers.
+async fn grpc_status(response: Response<Body>) -> Result<String, hyper::Error> {
+    if let Some(status) = response.headers().get(GRPC_STATUS) {
+        return Ok(String::from_utf8_lossy(status.as_bytes()).into_owned());
+    }
+    let mut body = response.into_body();
+    while let Some(data) = body.data().await {
+        data?;
+    }
+    let status = body
+        .trailers()
+        .await?
+        .and_then(|trailers| {
+            trailers
+                .get(GRPC_STATUS)
+                .map(|status| String::from_utf8_lossy(status.as_bytes()).into_owned())
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+    Ok(status)
+}
+
+/// The gRPC generator.
+///
+/// This generator is responsible for making unary gRPC calls to the target.
+#[derive(Debug)]
+pub struct Grpc {
+    uri: Uri,
+    concurrent_requests: u16,
+    request_semaphore: Arc<Semaphore>,
+    throttle: Throttle,
+    block_cache: Vec<Block>,
+    metric_labels: Vec<(String, String)>,
+    budget: Budget,
+    shutdown: Shutdown,
+    meter: Meter,
+}
+
+impl Grpc {
+    /// Create a new [`Grpc`] instance
+    ///
+    /// # Errors
+    ///
+    /// Creation will fail if the block cache cannot be built or the target URI
+    /// and method do not form a valid URI.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if user has passed zero values for any byte
+    /// values. Sharp corners.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(
+        config: Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
+        let bytes_per_second = NonZeroU32::new(config.bytes_per_second.get_bytes() as u32).unwrap();
+        let throttle = Throttle::new(config.throttle, bytes_per_second, pause);
+        let labels = vec![];
+        let metric_labels = address::uri_labels(&config.target_uri);
+        let mut block_cache = block_cache(&config, &labels)?;
+        if config.lock_block_cache {
+            block::lock(&mut block_cache, &labels)?;
+        }
+
+        let mut parts = config.target_uri.into_parts();
+        parts.path_and_query = Some(
+            format!("/{}/{}", config.service, config.method)
+                .parse()
+                .map_err(hyper::http::Error::from)?,
+        );
+        let uri = Uri::from_parts(parts).map_err(hyper::http::Error::from)?;
+
+        Ok(Self {
+            uri,
+            concurrent_requests: config.concurrent_requests,
+            request_semaphore: Arc::new(Semaphore::new(config.concurrent_requests as usize)),
+            block_cache,
+            throttle,
+            metric_labels,
+            budget: Budget::new(config.maximum_bytes, config.maximum_events),
+            shutdown,
+            meter,
+        })
+    }
+
+    /// Run [`Grpc`] to completion or until a shutdown signal is received.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if a request cannot be built.
+    ///
+    /// # Panics
+    ///
+    /// None known.
+    pub async fn spin(mut self) -> Result<(), Error> {
+        let client: Client<HttpConnector, Body> = Client::builder()
+            .http2_only(true)
+            .retry_canceled_requests(false)
+            .build_http();
+        let mut throttle = self.throttle;
+        let uri = self.uri;
+        let request_semaphore = self.request_semaphore;
+
+        let labels = self.metric_labels;
+        let mut budget = self.budget;
+        let mut rate_window = RateWindow::new(RATE_WINDOW);
+        let mut blocks = self.block_cache.iter().cycle();
+
+        loop {
+            let blk = blocks.next().unwrap();
+            let total_bytes = blk.total_bytes;
+
+            tokio::select! {
+                _ = throttle.wait(total_bytes, &labels) => {
+                    rate_window.record(u64::from(total_bytes.get()), &labels);
+                    let client = client.clone();
+                    let labels = labels.clone();
+                    let meter = self.meter.clone();
+
+                    let block_length = blk.bytes.len();
+                    let request: Request<Body> = Request::post(uri.clone())
+                        .header(CONTENT_TYPE, HeaderValue::from_static("application/grpc"))
+                        .header(TE, HeaderValue::from_static("trailers"))
+                        .body(Body::from(blk.bytes.clone()))?;
+
+                    let permit = Arc::clone(&request_semaphore).acquire_owned().await.unwrap();
+                    tokio::spawn(async move {
+                        counter!("requests_sent", 1, &labels);
+                        let status = match client.request(request).await {
+                            Ok(response) => grpc_status(response).await,
+                            Err(err) => Err(err),
+                        };
+                        match status {
+                            Ok(status) => {
+                                counter!("bytes_written", block_length as u64, &labels);
+                                meter.record(block_length as u64);
+                                let ok = status == GRPC_STATUS_OK;
+                                let mut status_labels = labels.clone();
+                                status_labels.push(("grpc_status".to_string(), status));
+                                if ok {
+                                    counter!("request_ok", 1, &status_labels);
+                                } else {
+                                    counter!("request_failure", 1, &status_labels);
+                                }
+                            }
+                            Err(err) => {
+                                let mut error_labels = labels.clone();
+                                error_labels
+                                    .push(("error".to_string(), hyper_error_kind(&err).to_string()));
+                                counter!("request_failure", 1, &error_labels);
+                            }
+                        }
+                        drop(permit);
+                    });
+                    budget.record(u64::from(total_bytes.get()), blk.lines);
+                },
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    // Acquire all available permits, meaning that we have no
+                    // outstanding requests in flight.
+                    let _semaphore = request_semaphore.acquire_many(u32::from(self.concurrent_requests)).await.unwrap();
+                    return Ok(());
+                },
+            }
+
+            if budget.exhausted() {
+                info!("finite data limit reached, generator complete");
+                // Acquire all available permits, meaning that we have no
+                // outstanding requests in flight.
+                let _semaphore = request_semaphore
+                    .acquire_many(u32::from(self.concurrent_requests))
+                    .await
+                    .unwrap();
+                gauge!("generator_complete", 1.0, &labels);
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::{block_cache, frame, Config};
+
+    fn config(maximum_prebuild_cache_size_bytes: u32) -> Config {
+        serde_yaml::from_str(&format!(
+            "seed: [{}]\ntarget_uri: http://localhost:4317\nservice: lading.Sink\nmethod: Push\nvariant: ascii\nbytes_per_second: 1 MiB\nblock_sizes: [256 B, 1 KiB, 4 KiB]\nmaximum_prebuild_cache_size_bytes: {} B\nconcurrent_requests: 1\n",
+            vec!["0"; 32].join(", "),
+            maximum_prebuild_cache_size_bytes
+        ))
+        .unwrap()
+    }
+
+    // The total bytes of each block, which the throttle and byte limits
+    // count, include its frame header.
+    proptest! {
+        #[test]
+        fn block_total_bytes_framed(maximum_prebuild_cache_size_bytes in 4096..65536_u32) {
+            let blocks = block_cache(&config(maximum_prebuild_cache_size_bytes), &vec![]).unwrap();
+            prop_assert!(!blocks.is_empty());
+            for blk in blocks {
+                prop_assert_eq!(blk.total_bytes.get() as usize, blk.bytes.len());
+            }
+        }
+    }
+
+    // A framed message is uncompressed and prefixed by its length, big-endian.
+    proptest! {
+        #[test]
+        fn frame_prefixes_length(message in proptest::collection::vec(any::<u8>(), 0..4096)) {
+            let framed = frame(&message);
+            prop_assert_eq!(framed[0], 0);
+            let length = u32::from_be_bytes([framed[1], framed[2], framed[3], framed[4]]);
+            prop_assert_eq!(length as usize, message.len());
+            prop_assert_eq!(&framed[5..], &message[..]);
+        }
+    }
+}
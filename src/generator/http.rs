@@ -7,33 +7,34 @@ use std::{
 };
 
 use byte_unit::{Byte, ByteUnit};
-use governor::{
-    clock, state,
-    state::direct::{self, InsufficientCapacity},
-    Quota, RateLimiter,
-};
+use governor::state::direct::InsufficientCapacity;
 use hyper::{
     client::{Client, HttpConnector},
-    header::CONTENT_LENGTH,
+    header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE},
     Body, HeaderMap, Request, Uri,
 };
-use metrics::counter;
-use once_cell::sync::OnceCell;
+use metrics::{counter, gauge};
 use rand::{prelude::StdRng, SeedableRng};
 use serde::Deserialize;
 use tokio::sync::Semaphore;
 use tracing::info;
 
 use crate::{
+    address,
     block::{self, chunk_bytes, construct_block_cache, Block},
-    payload,
+    control::Pause,
+    generator::{
+        common::{Budget, RateWindow, RATE_WINDOW},
+        Meter,
+    },
+    numa, payload,
     signals::Shutdown,
+    telemetry::hyper_error_kind,
+    throttle::{self, Throttle},
 };
 
-static CONNECTION_SEMAPHORE: OnceCell<Semaphore> = OnceCell::new();
-
 /// The HTTP method to be used in requests
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum Method {
     /// Make HTTP Post requests
@@ -43,9 +44,48 @@ pub enum Method {
         /// The maximum size in bytes of the cache of prebuilt messages
         maximum_prebuild_cache_size_bytes: byte_unit::Byte,
     },
+    /// Make HTTP Put requests
+    Put {
+        /// The payload generator to use for this target
+        variant: Variant,
+        /// The maximum size in bytes of the cache of prebuilt messages
+        maximum_prebuild_cache_size_bytes: byte_unit::Byte,
+    },
+}
+
+impl Method {
+    /// The payload generator requests are made with.
+    #[must_use]
+    pub fn variant(&self) -> &Variant {
+        match self {
+            Method::Post { variant, .. } | Method::Put { variant, .. } => variant,
+        }
+    }
+
+    /// The maximum size in bytes of the cache of prebuilt messages.
+    #[must_use]
+    pub fn maximum_prebuild_cache_size_bytes(&self) -> byte_unit::Byte {
+        match self {
+            Method::Post {
+                maximum_prebuild_cache_size_bytes,
+                ..
+            }
+            | Method::Put {
+                maximum_prebuild_cache_size_bytes,
+                ..
+            } => *maximum_prebuild_cache_size_bytes,
+        }
+    }
+
+    fn as_hyper(&self) -> hyper::Method {
+        match self {
+            Method::Post { .. } => hyper::Method::POST,
+            Method::Put { .. } => hyper::Method::PUT,
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 /// Variants supported by this generator.
 pub enum Variant {
@@ -67,19 +107,134 @@ pub enum Variant {
     Json,
     /// Generates a Apache Common log lines
     ApacheCommon,
+    /// Generates OTLP trace export requests, protobuf encoded. Sent by the
+    /// grpc generator to `opentelemetry.proto.collector.trace.v1.TraceService`
+    /// method `Export`, or over HTTP to a collector's `/v1/traces`.
+    OpentelemetryTraces(payload::OpentelemetryTracesConfig),
+    /// Generates points in InfluxDB line protocol. Sent by the http generator
+    /// to an InfluxDB `/write` endpoint.
+    InfluxLineProtocol(payload::InfluxConfig),
+    /// Generates Vector native `PushEventsRequest` messages of log events,
+    /// protobuf encoded. Sent by the grpc generator to `vector.Vector` method
+    /// `PushEvents`, the vector source's native protocol.
+    VectorNative(payload::VectorNativeConfig),
 }
 
-#[derive(Debug, Deserialize)]
+impl Variant {
+    /// The content type of request bodies, unless set in the configured
+    /// headers.
+    fn content_type(&self) -> &'static str {
+        match self {
+            Variant::SplunkHec | Variant::DatadogLog | Variant::FoundationDb | Variant::Json => {
+                "application/json"
+            }
+            Variant::Static { .. }
+            | Variant::Ascii
+            | Variant::ApacheCommon
+            | Variant::InfluxLineProtocol(_) => "text/plain",
+            Variant::OpentelemetryTraces(_) | Variant::VectorNative(_) => "application/x-protobuf",
+        }
+    }
+
+    /// Build a block cache of this variant's payloads, one block for each of
+    /// `block_chunks`.
+    #[allow(clippy::ptr_arg)]
+    pub(crate) fn block_cache(
+        &self,
+        rng: &mut StdRng,
+        block_chunks: &[usize],
+        event_limit: Option<payload::EventLimit>,
+        labels: &Vec<(String, String)>,
+    ) -> Vec<Block> {
+        match self {
+            Variant::Ascii => construct_block_cache(
+                rng,
+                &payload::Ascii::default(),
+                block_chunks,
+                event_limit,
+                labels,
+            ),
+            Variant::ApacheCommon => construct_block_cache(
+                rng,
+                &payload::ApacheCommon::default(),
+                block_chunks,
+                event_limit,
+                labels,
+            ),
+            Variant::SplunkHec => construct_block_cache(
+                rng,
+                &payload::SplunkHec::default(),
+                block_chunks,
+                event_limit,
+                labels,
+            ),
+            Variant::DatadogLog => construct_block_cache(
+                rng,
+                &payload::DatadogLog::default(),
+                block_chunks,
+                event_limit,
+                labels,
+            ),
+            Variant::Json => construct_block_cache(
+                rng,
+                &payload::Json::default(),
+                block_chunks,
+                event_limit,
+                labels,
+            ),
+            Variant::FoundationDb => construct_block_cache(
+                rng,
+                &payload::FoundationDb::default(),
+                block_chunks,
+                event_limit,
+                labels,
+            ),
+            Variant::Static { static_path } => construct_block_cache(
+                rng,
+                &payload::Static::new(static_path),
+                block_chunks,
+                event_limit,
+                labels,
+            ),
+            Variant::OpentelemetryTraces(config) => construct_block_cache(
+                rng,
+                &payload::OpentelemetryTraces::new(*config),
+                block_chunks,
+                event_limit,
+                labels,
+            ),
+            Variant::InfluxLineProtocol(config) => construct_block_cache(
+                rng,
+                &payload::Influx::new(*config),
+                block_chunks,
+                event_limit,
+                labels,
+            ),
+            Variant::VectorNative(config) => construct_block_cache(
+                rng,
+                &payload::VectorNative::new(*config),
+                block_chunks,
+                event_limit,
+                labels,
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
 /// Configuration of this generator.
 pub struct Config {
     /// The seed for random operations against this target
     pub seed: [u8; 32],
-    /// The URI for the target, must be a valid URI
+    /// The URI for the target, must be a valid URI. Requests are made to its
+    /// path and query, as given.
     #[serde(with = "http_serde::uri")]
     pub target_uri: Uri,
     /// The method to use against the URI
     pub method: Method,
-    /// Headers to include in the request
+    /// Headers to include in the request. A header may be given many values.
+    /// Unless set here the content type is that of the method's variant,
+    /// `application/json` for JSON payloads and `text/plain` otherwise.
     #[serde(with = "http_serde::header_map")]
     pub headers: HeaderMap,
     /// The bytes per second to send or receive from the target
@@ -88,6 +243,26 @@ pub struct Config {
     pub block_sizes: Option<Vec<byte_unit::Byte>>,
     /// The total number of parallel connections to maintain
     pub parallel_connections: u16,
+    /// The maximum number of bytes to send before this generator stops. If
+    /// unset the generator runs until the experiment ends.
+    pub maximum_bytes: Option<byte_unit::Byte>,
+    /// The maximum number of events -- newline delimited lines -- to send
+    /// before this generator stops. If unset the generator runs until the
+    /// experiment ends.
+    pub maximum_events: Option<u64>,
+    /// The algorithm used to throttle output to `bytes_per_second`. Defaults to a
+    /// token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Limit on the size of each newline delimited event. If unset events are
+    /// as large as the payload makes them.
+    pub event_limit: Option<payload::EventLimit>,
+    /// Whether to lock the block cache into RAM, touching each of its pages,
+    /// before the run starts. See mlock(2) for the limits on locked memory.
+    #[serde(default)]
+    pub lock_block_cache: bool,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
 }
 
 #[derive(Debug)]
@@ -136,20 +311,74 @@ impl From<::std::io::Error> for Error {
     }
 }
 
+/// Build the block cache of a generator configured by `config`, as
+/// [`Http::new`] does.
+///
+/// # Errors
+///
+/// Function will return an error if the configured block sizes cannot be
+/// chunked into the prebuild cache.
+///
+/// # Panics
+///
+/// Function will panic if user has passed zero values for any byte values.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::ptr_arg)]
+pub(crate) fn block_cache(
+    config: &Config,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<Block>, Error> {
+    let mut rng = StdRng::from_seed(config.seed);
+    let block_sizes: Vec<NonZeroUsize> = config
+        .block_sizes
+        .clone()
+        .unwrap_or_else(|| {
+            vec![
+                Byte::from_unit(1.0 / 8.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 4.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 2.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1_f64, ByteUnit::MB).unwrap(),
+                Byte::from_unit(2_f64, ByteUnit::MB).unwrap(),
+                Byte::from_unit(4_f64, ByteUnit::MB).unwrap(),
+            ]
+        })
+        .iter()
+        .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
+        .collect();
+    let block_chunks = chunk_bytes(
+        &mut rng,
+        NonZeroUsize::new(
+            config
+                .method
+                .maximum_prebuild_cache_size_bytes()
+                .get_bytes() as usize,
+        )
+        .expect("bytes must be non-zero"),
+        &block_sizes,
+    )?;
+    Ok(config
+        .method
+        .variant()
+        .block_cache(&mut rng, &block_chunks, config.event_limit, labels))
+}
+
 /// The HTTP generator.
 ///
-/// This generator is reposnsible for connecting to the target via HTTP. Today
-/// only POST and GET are supported.
+/// This generator is reposnsible for connecting to the target via HTTP, making
+/// POST or PUT requests.
 #[derive(Debug)]
 pub struct Http {
     uri: Uri,
     method: hyper::Method,
     headers: hyper::HeaderMap,
     parallel_connections: u16,
-    rate_limiter: RateLimiter<direct::NotKeyed, state::InMemoryState, clock::QuantaClock>,
+    connection_semaphore: Arc<Semaphore>,
+    throttle: Throttle,
     block_cache: Vec<Block>,
     metric_labels: Vec<(String, String)>,
+    budget: Budget,
     shutdown: Shutdown,
+    meter: Meter,
 }
 
 impl Http {
@@ -164,98 +393,42 @@ impl Http {
     /// Function will panic if user has passed non-zero values for any byte
     /// values. Sharp corners.
     #[allow(clippy::cast_possible_truncation)]
-    pub fn new(config: Config, shutdown: Shutdown) -> Result<Self, Error> {
-        let mut rng = StdRng::from_seed(config.seed);
-        let block_sizes: Vec<NonZeroUsize> = config
-            .block_sizes
-            .unwrap_or_else(|| {
-                vec![
-                    Byte::from_unit(1.0 / 8.0, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(1.0 / 4.0, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(1.0 / 2.0, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(1_f64, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(2_f64, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(4_f64, ByteUnit::MB).unwrap(),
-                ]
-            })
-            .iter()
-            .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
-            .collect();
+    pub fn new(
+        config: Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
         let bytes_per_second = NonZeroU32::new(config.bytes_per_second.get_bytes() as u32).unwrap();
-        let rate_limiter = RateLimiter::direct(Quota::per_second(bytes_per_second));
+        let throttle = Throttle::new(config.throttle, bytes_per_second, pause);
         let labels = vec![];
-        match config.method {
-            Method::Post {
-                variant,
-                maximum_prebuild_cache_size_bytes,
-            } => {
-                let block_chunks = chunk_bytes(
-                    &mut rng,
-                    NonZeroUsize::new(maximum_prebuild_cache_size_bytes.get_bytes() as usize)
-                        .expect("bytes must be non-zero"),
-                    &block_sizes,
-                )?;
-                let block_cache = match variant {
-                    Variant::Ascii => construct_block_cache(
-                        &mut rng,
-                        &payload::Ascii::default(),
-                        &block_chunks,
-                        &labels,
-                    ),
-                    Variant::ApacheCommon => construct_block_cache(
-                        &mut rng,
-                        &payload::ApacheCommon::default(),
-                        &block_chunks,
-                        &labels,
-                    ),
-                    Variant::SplunkHec => construct_block_cache(
-                        &mut rng,
-                        &payload::SplunkHec::default(),
-                        &block_chunks,
-                        &labels,
-                    ),
-                    Variant::DatadogLog => construct_block_cache(
-                        &mut rng,
-                        &payload::DatadogLog::default(),
-                        &block_chunks,
-                        &labels,
-                    ),
-                    Variant::Json => construct_block_cache(
-                        &mut rng,
-                        &payload::Json::default(),
-                        &block_chunks,
-                        &labels,
-                    ),
-                    Variant::FoundationDb => construct_block_cache(
-                        &mut rng,
-                        &payload::FoundationDb::default(),
-                        &block_chunks,
-                        &labels,
-                    ),
-                    Variant::Static { static_path } => construct_block_cache(
-                        &mut rng,
-                        &payload::Static::new(&static_path),
-                        &block_chunks,
-                        &labels,
-                    ),
-                };
-
-                CONNECTION_SEMAPHORE
-                    .set(Semaphore::new(config.parallel_connections as usize))
-                    .unwrap();
+        let metric_labels = address::uri_labels(&config.target_uri);
+        let mut block_cache = block_cache(&config, &labels)?;
+        if config.lock_block_cache {
+            block::lock(&mut block_cache, &labels)?;
+        }
 
-                Ok(Self {
-                    parallel_connections: config.parallel_connections,
-                    uri: config.target_uri,
-                    method: hyper::Method::POST,
-                    headers: config.headers,
-                    block_cache,
-                    rate_limiter,
-                    metric_labels: labels,
-                    shutdown,
-                })
-            }
+        let mut headers = config.headers;
+        if !headers.contains_key(CONTENT_TYPE) {
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static(config.method.variant().content_type()),
+            );
         }
+
+        Ok(Self {
+            parallel_connections: config.parallel_connections,
+            connection_semaphore: Arc::new(Semaphore::new(config.parallel_connections as usize)),
+            uri: config.target_uri,
+            method: config.method.as_hyper(),
+            headers,
+            block_cache,
+            throttle,
+            metric_labels,
+            budget: Budget::new(config.maximum_bytes, config.maximum_events),
+            shutdown,
+            meter,
+        })
     }
 
     /// Run [`Http`] to completion or until a shutdown signal is received.
@@ -274,11 +447,14 @@ impl Http {
             .retry_canceled_requests(false)
             .set_host(false)
             .build_http();
-        let rate_limiter = Arc::new(self.rate_limiter);
+        let mut throttle = self.throttle;
         let method = self.method;
         let uri = self.uri;
+        let connection_semaphore = self.connection_semaphore;
 
         let labels = self.metric_labels;
+        let mut budget = self.budget;
+        let mut rate_window = RateWindow::new(RATE_WINDOW);
         let mut blocks = self.block_cache.iter().cycle();
 
         loop {
@@ -286,9 +462,11 @@ impl Http {
             let total_bytes = blk.total_bytes;
 
             tokio::select! {
-                _ = rate_limiter.until_n_ready(total_bytes) => {
+                _ = throttle.wait(total_bytes, &labels) => {
+                    rate_window.record(u64::from(total_bytes.get()), &labels);
                     let client = client.clone();
                     let labels = labels.clone();
+                    let meter = self.meter.clone();
                     let method = method.clone();
                     let uri = uri.clone();
 
@@ -302,18 +480,17 @@ impl Http {
                         .body(body)
                         .unwrap();
                     let headers = request.headers_mut();
-                    for (k,v) in self.headers.clone().drain() {
-                        if let Some(k) = k {
-                            headers.insert(k,v);
-                        }
+                    for (k, v) in &self.headers {
+                        headers.append(k, v.clone());
                     }
 
-                    let permit = CONNECTION_SEMAPHORE.get().unwrap().acquire().await.unwrap();
+                    let permit = Arc::clone(&connection_semaphore).acquire_owned().await.unwrap();
                     tokio::spawn(async move {
                         counter!("requests_sent", 1, &labels);
                         match client.request(request).await {
                             Ok(response) => {
                                 counter!("bytes_written", block_length as u64, &labels);
+                                meter.record(block_length as u64);
                                 let status = response.status();
                                 let mut status_labels = labels.clone();
                                 status_labels
@@ -322,21 +499,35 @@ impl Http {
                             }
                             Err(err) => {
                                 let mut error_labels = labels.clone();
-                                error_labels.push(("error".to_string(), err.to_string()));
+                                error_labels
+                                    .push(("error".to_string(), hyper_error_kind(&err).to_string()));
                                 counter!("request_failure", 1, &error_labels);
                             }
                         }
                         drop(permit);
                     });
+                    budget.record(u64::from(total_bytes.get()), blk.lines);
                 },
                 _ = self.shutdown.recv() => {
                     info!("shutdown signal received");
                     // Acquire all available connections, meaning that we have
                     // no outstanding tasks in flight.
-                    let _semaphore = CONNECTION_SEMAPHORE.get().unwrap().acquire_many(u32::from(self.parallel_connections)).await.unwrap();
+                    let _semaphore = connection_semaphore.acquire_many(u32::from(self.parallel_connections)).await.unwrap();
                     return Ok(());
                 },
             }
+
+            if budget.exhausted() {
+                info!("finite data limit reached, generator complete");
+                // Acquire all available connections, meaning that we have no
+                // outstanding tasks in flight.
+                let _semaphore = connection_semaphore
+                    .acquire_many(u32::from(self.parallel_connections))
+                    .await
+                    .unwrap();
+                gauge!("generator_complete", 1.0, &labels);
+                return Ok(());
+            }
         }
     }
 }
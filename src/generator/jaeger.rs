@@ -0,0 +1,484 @@
+//! The Jaeger agent speaking generator.
+//!
+//! Each block is one UDP datagram holding an `emitBatch` call in the Thrift
+//! compact protocol, as Jaeger clients send to the agent's port 6831. A batch
+//! is the spans of one trace of one process, its service one of `services`,
+//! each span one of `operations` operations and a child of a span before it.
+//! Spans are packed into a datagram up to `spans_per_packet` and
+//! `maximum_packet_bytes`, and load is throttled in spans, not bytes.
+
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    num::{NonZeroU16, NonZeroU32},
+};
+
+use byte_unit::Byte;
+use governor::state::direct::InsufficientCapacity;
+use metrics::{counter, gauge};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Deserialize;
+use tokio::net::UdpSocket;
+use tracing::info;
+
+use crate::{
+    address,
+    block::{self, Block, Summary},
+    control::Pause,
+    generator::{
+        common::{Budget, RateWindow, RATE_WINDOW},
+        tcp::record_block,
+        Meter,
+    },
+    numa,
+    payload::protobuf::put_varint,
+    signals::Shutdown,
+    telemetry::io_error_kind,
+    throttle::{self, Throttle},
+};
+
+/// The earliest start time of a span, 2022-01-01T00:00:00Z in microseconds.
+/// Times are drawn from the seed, not the wall clock, so that payloads are
+/// deterministic.
+const EPOCH_MICROS: i64 = 1_640_995_200_000_000;
+/// The span of start times after [`EPOCH_MICROS`], a day in microseconds.
+const START_RANGE_MICROS: i64 = 86_400_000_000;
+
+// Thrift compact protocol types used here.
+const I32: u8 = 5;
+const I64: u8 = 6;
+const BINARY: u8 = 8;
+const LIST: u8 = 9;
+const STRUCT: u8 = 12;
+/// The compact protocol's identifier, leading every message.
+const PROTOCOL_ID: u8 = 0x82;
+/// Version 1 of the compact protocol, a oneway call.
+const VERSION_ONEWAY: u8 = (4 << 5) | 1;
+
+fn default_services() -> NonZeroU32 {
+    NonZeroU32::new(10).unwrap()
+}
+
+fn default_operations() -> NonZeroU32 {
+    NonZeroU32::new(20).unwrap()
+}
+
+fn default_spans_per_packet() -> NonZeroU16 {
+    NonZeroU16::new(10).unwrap()
+}
+
+fn default_maximum_packet_bytes() -> Byte {
+    // The largest datagram the Jaeger agent accepts by default.
+    Byte::from_bytes(65_000)
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration of this generator.
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// The address for the target, must be a valid SocketAddr
+    pub addr: String,
+    /// The number of distinct service names, by default 10
+    #[serde(default = "default_services")]
+    pub services: NonZeroU32,
+    /// The number of distinct operation names of each service, by default 20
+    #[serde(default = "default_operations")]
+    pub operations: NonZeroU32,
+    /// The most spans packed into one datagram, by default 10
+    #[serde(default = "default_spans_per_packet")]
+    pub spans_per_packet: NonZeroU16,
+    /// The largest datagram sent, by default 65000 bytes. A single span
+    /// larger than this is sent alone.
+    #[serde(default = "default_maximum_packet_bytes")]
+    pub maximum_packet_bytes: byte_unit::Byte,
+    /// The spans per second to send to the target
+    pub spans_per_second: NonZeroU32,
+    /// The maximum size in bytes of the cache of prebuilt datagrams
+    pub maximum_prebuild_cache_size_bytes: byte_unit::Byte,
+    /// The maximum number of bytes to send before this generator stops. If
+    /// unset the generator runs until the experiment ends.
+    pub maximum_bytes: Option<byte_unit::Byte>,
+    /// The maximum number of events -- spans -- to send before this generator
+    /// stops. If unset the generator runs until the experiment ends.
+    pub maximum_events: Option<u64>,
+    /// The algorithm used to throttle output to `spans_per_second`. Defaults
+    /// to a token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Whether to lock the block cache into RAM, touching each of its pages,
+    /// before the run starts. See mlock(2) for the limits on locked memory.
+    #[serde(default)]
+    pub lock_block_cache: bool,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+}
+
+#[derive(Debug)]
+/// Errors produced by [`Jaeger`].
+pub enum Error {
+    /// Rate limiter has insuficient capacity for payload. Indicates a serious
+    /// bug.
+    Governor(InsufficientCapacity),
+    /// Creation of payload blocks failed.
+    Block(block::Error),
+    /// Wrapper for [`std::io::Error`].
+    Io(io::Error),
+}
+
+impl From<block::Error> for Error {
+    fn from(error: block::Error) -> Self {
+        Error::Block(error)
+    }
+}
+
+impl From<InsufficientCapacity> for Error {
+    fn from(error: InsufficientCapacity) -> Self {
+        Error::Governor(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+/// Zigzag encode `value`, as the compact protocol does signed integers.
+#[allow(clippy::cast_sign_loss)]
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Append the header of a field of `kind`, `delta` after the previous field
+/// of its struct.
+fn field(delta: u8, kind: u8, buf: &mut Vec<u8>) {
+    buf.push((delta << 4) | kind);
+}
+
+fn i32_field(delta: u8, value: i32, buf: &mut Vec<u8>) {
+    field(delta, I32, buf);
+    put_varint(zigzag(i64::from(value)), buf);
+}
+
+fn i64_field(delta: u8, value: i64, buf: &mut Vec<u8>) {
+    field(delta, I64, buf);
+    put_varint(zigzag(value), buf);
+}
+
+fn string_field(delta: u8, value: &str, buf: &mut Vec<u8>) {
+    field(delta, BINARY, buf);
+    put_varint(value.len() as u64, buf);
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// Append the header of a list of `size` elements of `kind`.
+#[allow(clippy::cast_possible_truncation)]
+fn list_header(kind: u8, size: usize, buf: &mut Vec<u8>) {
+    if size < 15 {
+        buf.push(((size as u8) << 4) | kind);
+    } else {
+        buf.push(0xf0 | kind);
+        put_varint(size as u64, buf);
+    }
+}
+
+/// A span of a trace, see [`span`].
+struct Span {
+    trace_id: i64,
+    span_id: i64,
+    parent_span_id: i64,
+    operation: u32,
+    start_time: i64,
+    duration: i64,
+    status_code: &'static str,
+}
+
+/// Encode `span` as a Jaeger `Span` struct.
+fn span(span: &Span, buf: &mut Vec<u8>) {
+    i64_field(1, span.trace_id, buf); // traceIdLow
+    i64_field(1, 0, buf); // traceIdHigh
+    i64_field(1, span.span_id, buf);
+    i64_field(1, span.parent_span_id, buf);
+    string_field(1, &format!("operation_{}", span.operation), buf);
+    i32_field(2, 1, buf); // flags, sampled
+    i64_field(1, span.start_time, buf);
+    i64_field(1, span.duration, buf);
+    field(1, LIST, buf); // tags
+    list_header(STRUCT, 1, buf);
+    string_field(1, "http.status_code", buf);
+    i32_field(1, 0, buf); // vType, string
+    string_field(1, span.status_code, buf);
+    buf.push(0); // end of tag
+    buf.push(0); // end of span
+}
+
+/// Encode an `emitBatch` call carrying the encoded `spans` of the process of
+/// `service`.
+fn emit_batch(service: &str, spans: &[Vec<u8>]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(spans.iter().map(Vec::len).sum::<usize>() + 64);
+    buf.push(PROTOCOL_ID);
+    buf.push(VERSION_ONEWAY);
+    put_varint(0, &mut buf); // sequence id
+    put_varint(b"emitBatch".len() as u64, &mut buf);
+    buf.extend_from_slice(b"emitBatch");
+    field(1, STRUCT, &mut buf); // batch
+    field(1, STRUCT, &mut buf); // process
+    string_field(1, service, &mut buf);
+    buf.push(0); // end of process
+    field(1, LIST, &mut buf); // spans
+    list_header(STRUCT, spans.len(), &mut buf);
+    for span in spans {
+        buf.extend_from_slice(span);
+    }
+    buf.push(0); // end of batch
+    buf.push(0); // end of arguments
+    buf
+}
+
+/// Return one datagram of spans drawn from `rng`, and the number of spans
+/// packed. The datagram is at most `maximum_packet_bytes` long unless its one
+/// span is longer.
+fn packet<R>(rng: &mut R, config: &Config, maximum_packet_bytes: usize) -> (Vec<u8>, u64)
+where
+    R: Rng,
+{
+    let service = format!("service_{}", rng.gen_range(0..config.services.get()));
+    let trace_id = rng.gen::<i64>();
+    let start_time = EPOCH_MICROS + rng.gen_range(0..START_RANGE_MICROS);
+    let mut span_ids: Vec<i64> = Vec::new();
+    let mut spans: Vec<Vec<u8>> = Vec::new();
+    let mut packet = emit_batch(&service, &spans);
+    for idx in 0..config.spans_per_packet.get() {
+        let span_id = rng.gen::<i64>();
+        let parent_span_id = if span_ids.is_empty() {
+            0
+        } else {
+            span_ids[rng.gen_range(0..span_ids.len())]
+        };
+        let mut encoded = Vec::new();
+        span(
+            &Span {
+                trace_id,
+                span_id,
+                parent_span_id,
+                operation: rng.gen_range(0..config.operations.get()),
+                start_time: start_time + i64::from(idx),
+                duration: rng.gen_range(1..1_000_000),
+                status_code: ["200", "404", "500"][rng.gen_range(0..3)],
+            },
+            &mut encoded,
+        );
+        spans.push(encoded);
+        let candidate = emit_batch(&service, &spans);
+        if span_ids.is_empty() || candidate.len() <= maximum_packet_bytes {
+            packet = candidate;
+            span_ids.push(span_id);
+        } else {
+            spans.pop();
+            break;
+        }
+    }
+    (packet, span_ids.len() as u64)
+}
+
+/// Build the block cache of a generator configured by `config`, as
+/// [`Jaeger::new`] does. Each block is one datagram.
+///
+/// # Errors
+///
+/// None are known.
+///
+/// # Panics
+///
+/// Function will panic if user has passed zero values for any byte values.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::ptr_arg)]
+pub(crate) fn block_cache(
+    config: &Config,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<Block>, Error> {
+    let mut rng = StdRng::from_seed(config.seed);
+    let maximum_packet_bytes = (config.maximum_packet_bytes.get_bytes() as usize).max(1);
+    let maximum_cache_bytes = config.maximum_prebuild_cache_size_bytes.get_bytes() as usize;
+    assert!(maximum_cache_bytes > 0, "bytes must be non-zero");
+
+    let mut block_cache = Vec::new();
+    let mut cache_bytes = 0;
+    while cache_bytes < maximum_cache_bytes {
+        let (bytes, spans) = packet(&mut rng, config, maximum_packet_bytes);
+        cache_bytes += bytes.len();
+        block_cache.push(Block::new(
+            NonZeroU32::new(bytes.len() as u32).expect("packets are never empty"),
+            spans,
+            bytes,
+        ));
+    }
+    Summary::new(&block_cache).emit(labels);
+    gauge!("block_construction_complete", 1.0, labels);
+    Ok(block_cache)
+}
+
+#[derive(Debug)]
+/// The Jaeger generator.
+///
+/// This generator is responsible for sending Jaeger agent datagrams to the
+/// target.
+pub struct Jaeger {
+    addr: SocketAddr,
+    throttle: Throttle,
+    block_cache: Vec<Block>,
+    metric_labels: Vec<(String, String)>,
+    budget: Budget,
+    shutdown: Shutdown,
+    meter: Meter,
+}
+
+impl Jaeger {
+    /// Create a new [`Jaeger`] instance
+    ///
+    /// # Errors
+    ///
+    /// Creation will fail if the block cache cannot be built.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if the address is not a valid socket address.
+    pub fn new(
+        config: &Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
+        let throttle = Throttle::new(config.throttle, config.spans_per_second, pause);
+        let labels = vec![];
+        let mut block_cache = block_cache(config, &labels)?;
+        if config.lock_block_cache {
+            block::lock(&mut block_cache, &labels)?;
+        }
+
+        let addr = config
+            .addr
+            .to_socket_addrs()
+            .expect("could not convert to socket")
+            .next()
+            .unwrap();
+        Ok(Self {
+            addr,
+            block_cache,
+            throttle,
+            metric_labels: vec![address::label(addr.ip())],
+            budget: Budget::new(config.maximum_bytes, config.maximum_events),
+            shutdown,
+            meter,
+        })
+    }
+
+    /// Run [`Jaeger`] to completion or until a shutdown signal is received.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if no local socket can be bound. Send
+    /// errors are recorded and the datagram dropped.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if a datagram holds more spans than fit a `u32`.
+    pub async fn spin(mut self) -> Result<(), Error> {
+        let labels = self.metric_labels;
+        let mut budget = self.budget;
+        let mut rate_window = RateWindow::new(RATE_WINDOW);
+
+        let bind_addr = if self.addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(bind_addr).await?;
+        let mut blocks = self.block_cache.iter().cycle();
+
+        loop {
+            let blk = blocks.next().unwrap();
+            let spans = NonZeroU32::new(u32::try_from(blk.lines).unwrap())
+                .expect("packets are never empty");
+
+            tokio::select! {
+                _ = self.throttle.wait(spans, &labels) => {
+                    match socket.send_to(&blk.bytes, self.addr).await {
+                        Ok(_) => {
+                            counter!("packets_sent", 1, &labels);
+                            counter!("spans_sent", blk.lines, &labels);
+                            if record_block(blk, &labels, &self.meter, &mut rate_window, &mut budget) {
+                                return Ok(());
+                            }
+                        }
+                        Err(err) => {
+                            let mut error_labels = labels.clone();
+                            error_labels.push(("error".to_string(), io_error_kind(&err)));
+                            counter!("request_failure", 1, &error_labels);
+                        }
+                    }
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(());
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::{NonZeroU16, NonZeroU32};
+
+    use byte_unit::Byte;
+    use proptest::prelude::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{packet, zigzag, Config};
+    use crate::payload::protobuf::{put_varint, read_varint};
+
+    // Zigzag varints decode to the integers encoded.
+    proptest! {
+        #[test]
+        fn zigzag_varint_round_trip(value: i64) {
+            let mut buf = Vec::new();
+            put_varint(zigzag(value), &mut buf);
+            let (decoded, read) = read_varint(&buf);
+            prop_assert_eq!(read, buf.len());
+            let decoded = ((decoded >> 1) as i64) ^ -((decoded & 1) as i64);
+            prop_assert_eq!(decoded, value);
+        }
+    }
+
+    // A datagram is a oneway `emitBatch` call packing at most the configured
+    // spans, no larger than the maximum unless it holds one span.
+    proptest! {
+        #[test]
+        fn packet_shape(seed: u64, spans_per_packet in 1..64_u16, maximum_packet_bytes in 1..4096_usize) {
+            let config = Config {
+                seed: [0; 32],
+                addr: "127.0.0.1:6831".to_string(),
+                services: NonZeroU32::new(10).unwrap(),
+                operations: NonZeroU32::new(20).unwrap(),
+                spans_per_packet: NonZeroU16::new(spans_per_packet).unwrap(),
+                maximum_packet_bytes: Byte::from_bytes(maximum_packet_bytes as u128),
+                spans_per_second: NonZeroU32::new(1).unwrap(),
+                maximum_prebuild_cache_size_bytes: Byte::from_bytes(1),
+                maximum_bytes: None,
+                maximum_events: None,
+                throttle: Default::default(),
+                lock_block_cache: false,
+                numa: None,
+            };
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (bytes, spans) = packet(&mut rng, &config, maximum_packet_bytes);
+            prop_assert!(spans >= 1 && spans <= u64::from(spans_per_packet));
+            prop_assert!(spans == 1 || bytes.len() <= maximum_packet_bytes);
+            prop_assert_eq!(&bytes[..2], &[0x82, 0x81]);
+            prop_assert_eq!(&bytes[2..4], &[0, 9]);
+            prop_assert_eq!(&bytes[4..13], b"emitBatch");
+        }
+    }
+}
@@ -8,12 +8,8 @@ use std::{
 
 use byte_unit::{Byte, ByteUnit};
 use futures::{stream::FuturesUnordered, FutureExt, StreamExt};
-use governor::{
-    clock, state,
-    state::direct::{self, InsufficientCapacity},
-    Quota, RateLimiter,
-};
-use metrics::{counter, increment_counter};
+use governor::state::direct::InsufficientCapacity;
+use metrics::{counter, gauge, increment_counter};
 use rand::{prelude::StdRng, SeedableRng};
 use rdkafka::{
     config::FromClientConfig,
@@ -27,8 +23,14 @@ use tracing::info;
 
 use crate::{
     block::{self, chunk_bytes, construct_block_cache, Block},
-    payload,
+    control::Pause,
+    generator::{
+        common::{Budget, RateWindow, RATE_WINDOW},
+        Meter,
+    },
+    numa, payload,
     signals::Shutdown,
+    throttle::{self, Throttle},
 };
 
 /// Configuration for generator throughput.
@@ -85,6 +87,26 @@ pub struct Config {
     pub block_sizes: Option<Vec<byte_unit::Byte>>,
     /// Map of rdkafka=-specific overrides to apply to the producer
     pub producer_config: Option<HashMap<String, String>>,
+    /// The maximum number of bytes to send before this generator stops. If
+    /// unset the generator runs until the experiment ends.
+    pub maximum_bytes: Option<byte_unit::Byte>,
+    /// The maximum number of events -- newline delimited lines -- to send
+    /// before this generator stops. If unset the generator runs until the
+    /// experiment ends.
+    pub maximum_events: Option<u64>,
+    /// The algorithm used to throttle output to `throughput`. Defaults to a
+    /// token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Limit on the size of each newline delimited event. If unset events are
+    /// as large as the payload makes them.
+    pub event_limit: Option<payload::EventLimit>,
+    /// Whether to lock the block cache into RAM, touching each of its pages,
+    /// before the run starts. See mlock(2) for the limits on locked memory.
+    #[serde(default)]
+    pub lock_block_cache: bool,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
 }
 
 #[derive(Debug)]
@@ -134,7 +156,11 @@ pub struct Kafka {
     topic: String,
     producer_config: Option<HashMap<String, String>>,
     throughput: Throughput,
+    throttle: throttle::Config,
+    budget: Budget,
     shutdown: Shutdown,
+    pause: Pause,
+    meter: Meter,
 }
 
 impl Kafka {
@@ -149,34 +175,18 @@ impl Kafka {
     /// Function will panic if user has passed non-zero values for any byte
     /// values. Sharp corners.
     #[allow(clippy::cast_possible_truncation)]
-    pub fn new(config: Config, shutdown: Shutdown) -> Result<Self, Error> {
+    pub fn new(
+        config: Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
         let labels = vec![];
 
-        let block_sizes: Vec<NonZeroUsize> = config
-            .block_sizes
-            .clone()
-            .unwrap_or_else(|| {
-                vec![
-                    Byte::from_unit(1.0 / 8.0, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(1.0 / 16.0, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(1.0 / 32.0, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(1.0 / 64.0, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(1.0 / 128.0, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(1.0 / 256.0, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(1.0 / 512.0, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(1.0 / 1024.0, ByteUnit::MB).unwrap(),
-                ]
-            })
-            .iter()
-            .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
-            .collect();
-        let block_cache = generate_block_cache(
-            config.maximum_prebuild_cache_size_bytes,
-            config.variant,
-            config.seed,
-            &block_sizes,
-            &labels,
-        )?;
+        let mut block_cache = block_cache(&config, &labels)?;
+        if config.lock_block_cache {
+            block::lock(&mut block_cache, &labels)?;
+        }
 
         Ok(Self {
             block_cache,
@@ -184,8 +194,12 @@ impl Kafka {
             bootstrap_server: config.bootstrap_server,
             producer_config: config.producer_config,
             throughput: config.throughput,
+            throttle: config.throttle,
             topic: config.topic,
+            budget: Budget::new(config.maximum_bytes, config.maximum_events),
             shutdown,
+            pause,
+            meter,
         })
     }
 
@@ -204,6 +218,8 @@ impl Kafka {
         let bootstrap_server = self.bootstrap_server;
         let topic = self.topic;
         let labels = self.labels;
+        let mut budget = self.budget;
+        let mut rate_window = RateWindow::new(RATE_WINDOW);
 
         let mut client_config = ClientConfig::new();
         let mut config_values = self.producer_config.unwrap_or_default();
@@ -214,9 +230,9 @@ impl Kafka {
 
         let producer = FutureProducer::from_config(&client_config)?;
 
-        // Configure our rate limiter.
+        // Configure our throttle.
         let limit_by_bytes = matches!(self.throughput, Throughput::BytesPerSecond { .. });
-        let rate_limiter = get_rate_limiter(self.throughput);
+        let mut throttle = get_throttle(self.throttle, self.throughput, self.pause);
 
         let mut in_flight = FuturesUnordered::new();
 
@@ -228,6 +244,7 @@ impl Kafka {
                     Ok(block_size) => {
                         increment_counter!("request_ok", &labels);
                         counter!("bytes_written", block_size, &labels);
+                        self.meter.record(block_size);
                     }
                     Err(..) => {
                         counter!("request_failure", 1, &labels);
@@ -241,7 +258,7 @@ impl Kafka {
             let limiter_n = NonZeroU32::new(limiter_n).expect("should never be zero");
 
             tokio::select! {
-                _ = rate_limiter.until_n_ready(limiter_n) => {
+                _ = throttle.wait(limiter_n, &labels) => {
                     let mut record = Some(
                         FutureRecord::to(topic.as_ref())
                             .payload(&block.bytes)
@@ -254,6 +271,8 @@ impl Kafka {
                                 counter!("requests_sent", 1, &labels);
                                 in_flight
                                     .push(async move { fut.await.map(|_| u64::from(block_size.get())) });
+                                rate_window.record(u64::from(block_size.get()), &labels);
+                                budget.record(u64::from(block_size.get()), block.lines);
                                 break;
                             }
                             Err((e, old_record)) => {
@@ -270,15 +289,79 @@ impl Kafka {
                     return Ok(());
                 },
             }
+
+            if budget.exhausted() {
+                info!("finite data limit reached, generator complete");
+                while let Some(result) = in_flight.next().await {
+                    match result {
+                        Ok(block_size) => {
+                            increment_counter!("request_ok", &labels);
+                            counter!("bytes_written", block_size, &labels);
+                            self.meter.record(block_size);
+                        }
+                        Err(..) => {
+                            counter!("request_failure", 1, &labels);
+                        }
+                    }
+                }
+                gauge!("generator_complete", 1.0, &labels);
+                return Ok(());
+            }
         }
     }
 }
 
+/// Build the block cache of a generator configured by `config`, as
+/// [`Kafka::new`] does.
+///
+/// # Errors
+///
+/// Function will return an error if the configured block sizes cannot be
+/// chunked into the prebuild cache.
+///
+/// # Panics
+///
+/// Function will panic if user has passed zero values for any byte values.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::ptr_arg)]
+pub(crate) fn block_cache(
+    config: &Config,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<Block>, Error> {
+    let block_sizes: Vec<NonZeroUsize> = config
+        .block_sizes
+        .clone()
+        .unwrap_or_else(|| {
+            vec![
+                Byte::from_unit(1.0 / 8.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 16.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 32.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 64.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 128.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 256.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 512.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 1024.0, ByteUnit::MB).unwrap(),
+            ]
+        })
+        .iter()
+        .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
+        .collect();
+    generate_block_cache(
+        config.maximum_prebuild_cache_size_bytes,
+        config.variant,
+        config.seed,
+        &block_sizes,
+        config.event_limit,
+        labels,
+    )
+}
+
 fn generate_block_cache(
     cache_size: byte_unit::Byte,
     variant: Variant,
     seed: [u8; 32],
     block_sizes: &[NonZeroUsize],
+    event_limit: Option<payload::EventLimit>,
     #[allow(clippy::ptr_arg)] labels: &Vec<(String, String)>,
 ) -> Result<Vec<Block>, Error> {
     let mut rng = StdRng::from_seed(seed);
@@ -288,29 +371,43 @@ fn generate_block_cache(
     let chunks = chunk_bytes(&mut rng, total_size, block_sizes)?;
 
     let blocks = match variant {
-        Variant::Ascii => {
-            construct_block_cache(&mut rng, &payload::Ascii::default(), &chunks, labels)
-        }
-        Variant::DatadogLog => {
-            construct_block_cache(&mut rng, &payload::DatadogLog::default(), &chunks, labels)
-        }
-        Variant::Json => {
-            construct_block_cache(&mut rng, &payload::Json::default(), &chunks, labels)
-        }
-        Variant::FoundationDb => {
-            construct_block_cache(&mut rng, &payload::FoundationDb::default(), &chunks, labels)
-        }
+        Variant::Ascii => construct_block_cache(
+            &mut rng,
+            &payload::Ascii::default(),
+            &chunks,
+            event_limit,
+            labels,
+        ),
+        Variant::DatadogLog => construct_block_cache(
+            &mut rng,
+            &payload::DatadogLog::default(),
+            &chunks,
+            event_limit,
+            labels,
+        ),
+        Variant::Json => construct_block_cache(
+            &mut rng,
+            &payload::Json::default(),
+            &chunks,
+            event_limit,
+            labels,
+        ),
+        Variant::FoundationDb => construct_block_cache(
+            &mut rng,
+            &payload::FoundationDb::default(),
+            &chunks,
+            event_limit,
+            labels,
+        ),
     };
     Ok(blocks)
 }
 
-fn get_rate_limiter(
-    throughput: Throughput,
-) -> RateLimiter<direct::NotKeyed, state::InMemoryState, clock::QuantaClock> {
+fn get_throttle(config: throttle::Config, throughput: Throughput, pause: Pause) -> Throttle {
     match throughput {
         Throughput::Unlimited => {
             let amount = NonZeroU32::new(u32::MAX).expect("amount should not be zero");
-            RateLimiter::direct(Quota::per_second(amount))
+            Throttle::new(config, amount, pause)
         }
         Throughput::BytesPerSecond { amount } => {
             let amount = if amount.get_bytes() == 0 {
@@ -319,13 +416,13 @@ fn get_rate_limiter(
                 amount.get_bytes().try_into().unwrap_or(u32::MAX)
             };
             let amount = NonZeroU32::new(amount).expect("amount should not be zero");
-            RateLimiter::direct(Quota::per_second(amount))
+            Throttle::new(config, amount, pause)
         }
         Throughput::MessagesPerSecond { amount } => {
             let amount = if amount == 0 { 1 } else { amount as u32 };
             let amount = NonZeroU32::new(amount).expect("amount should not be zero");
 
-            RateLimiter::direct(Quota::per_second(amount))
+            Throttle::new(config, amount, pause)
         }
     }
 }
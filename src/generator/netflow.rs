@@ -0,0 +1,608 @@
+//! The NetFlow and IPFIX speaking generator.
+//!
+//! Each block is one UDP datagram of flow records as a router exports them to
+//! a flow collector, in NetFlow v5, NetFlow v9 or IPFIX. Records are drawn
+//! from `flows` distinct flows, each its own IPv4 5-tuple, so that the
+//! collector aggregates into as many flows. The v9 and IPFIX template is sent
+//! in every `template_interval` datagram, the first included, for a collector
+//! to decode the datagrams that follow. Load is throttled in datagrams, not
+//! bytes.
+//!
+//! Sequence numbers restart each time the block cache cycles, which a
+//! collector may count as lost or duplicated datagrams.
+
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    num::{NonZeroU16, NonZeroU32},
+};
+
+use governor::state::direct::InsufficientCapacity;
+use metrics::{counter, gauge};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Deserialize;
+use tokio::net::UdpSocket;
+use tracing::info;
+
+use crate::{
+    address,
+    block::{self, Block, Summary},
+    control::Pause,
+    generator::{
+        common::{Budget, RateWindow, RATE_WINDOW},
+        tcp::record_block,
+        Meter,
+    },
+    numa,
+    signals::Shutdown,
+    telemetry::io_error_kind,
+    throttle::{self, Throttle},
+};
+
+/// The export time of the first datagram, 2022-01-01T00:00:00Z in seconds.
+/// Times are drawn from the seed, not the wall clock, so that payloads are
+/// deterministic.
+const EPOCH_SECONDS: u32 = 1_640_995_200;
+/// The exporter's uptime at the first datagram, in milliseconds.
+const INITIAL_UPTIME_MILLIS: u32 = 3_600_000;
+/// The uptime that passes between datagrams, in milliseconds.
+const UPTIME_STEP_MILLIS: u32 = 10;
+/// The longest a flow lasts, in milliseconds.
+const MAXIMUM_FLOW_MILLIS: u32 = 60_000;
+/// The most records a NetFlow v5 datagram holds.
+const V5_MAXIMUM_RECORDS: u16 = 30;
+/// The most records a v9 or IPFIX datagram holds, so that it fits the largest
+/// UDP payload.
+const MAXIMUM_RECORDS: u16 = 1_900;
+/// The id of the one template of v9 and IPFIX datagrams.
+const TEMPLATE_ID: u16 = 256;
+/// The destination ports flows are drawn to.
+const DESTINATION_PORTS: [u16; 6] = [22, 53, 80, 123, 443, 8080];
+
+/// The fields of v9 and IPFIX data records, as information element id and
+/// length. v9 field types share their ids with IPFIX information elements.
+const FIELDS: [(u16, u16); 12] = [
+    (8, 4),  // sourceIPv4Address
+    (12, 4), // destinationIPv4Address
+    (7, 2),  // sourceTransportPort
+    (11, 2), // destinationTransportPort
+    (4, 1),  // protocolIdentifier
+    (6, 1),  // tcpControlBits
+    (2, 4),  // packetDeltaCount
+    (1, 4),  // octetDeltaCount
+    (22, 4), // flowStartSysUpTime
+    (21, 4), // flowEndSysUpTime
+    (10, 2), // ingressInterface
+    (14, 2), // egressInterface
+];
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// The export protocol of [`NetFlow`].
+pub enum Protocol {
+    /// NetFlow version 5, fixed records and no templates
+    NetflowV5,
+    /// NetFlow version 9, RFC 3954
+    NetflowV9,
+    /// IPFIX, RFC 7011
+    Ipfix,
+}
+
+fn default_flows() -> NonZeroU32 {
+    NonZeroU32::new(10_000).unwrap()
+}
+
+fn default_records_per_packet() -> NonZeroU16 {
+    NonZeroU16::new(V5_MAXIMUM_RECORDS).unwrap()
+}
+
+fn default_template_interval() -> NonZeroU32 {
+    NonZeroU32::new(20).unwrap()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration of this generator.
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// The address for the target, must be a valid SocketAddr
+    pub addr: String,
+    /// The export protocol
+    pub protocol: Protocol,
+    /// The number of distinct flows records are drawn from, by default 10000
+    #[serde(default = "default_flows")]
+    pub flows: NonZeroU32,
+    /// The records packed into one datagram, by default 30. NetFlow v5
+    /// datagrams hold no more than 30, v9 and IPFIX datagrams no more than
+    /// 1900.
+    #[serde(default = "default_records_per_packet")]
+    pub records_per_packet: NonZeroU16,
+    /// The template is sent in every this many datagrams, by default 20.
+    /// Ignored for NetFlow v5.
+    #[serde(default = "default_template_interval")]
+    pub template_interval: NonZeroU32,
+    /// The datagrams per second to send to the target
+    pub packets_per_second: NonZeroU32,
+    /// The maximum size in bytes of the cache of prebuilt datagrams
+    pub maximum_prebuild_cache_size_bytes: byte_unit::Byte,
+    /// The maximum number of bytes to send before this generator stops. If
+    /// unset the generator runs until the experiment ends.
+    pub maximum_bytes: Option<byte_unit::Byte>,
+    /// The maximum number of events -- flow records -- to send before this
+    /// generator stops. If unset the generator runs until the experiment ends.
+    pub maximum_events: Option<u64>,
+    /// The algorithm used to throttle output to `packets_per_second`.
+    /// Defaults to a token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Whether to lock the block cache into RAM, touching each of its pages,
+    /// before the run starts. See mlock(2) for the limits on locked memory.
+    #[serde(default)]
+    pub lock_block_cache: bool,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+}
+
+#[derive(Debug)]
+/// Errors produced by [`NetFlow`].
+pub enum Error {
+    /// Rate limiter has insuficient capacity for payload. Indicates a serious
+    /// bug.
+    Governor(InsufficientCapacity),
+    /// Creation of payload blocks failed.
+    Block(block::Error),
+    /// Wrapper for [`std::io::Error`].
+    Io(io::Error),
+}
+
+impl From<block::Error> for Error {
+    fn from(error: block::Error) -> Self {
+        Error::Block(error)
+    }
+}
+
+impl From<InsufficientCapacity> for Error {
+    fn from(error: InsufficientCapacity) -> Self {
+        Error::Governor(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+/// One flow record, see [`record`].
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    source: u32,
+    destination: u32,
+    source_port: u16,
+    destination_port: u16,
+    protocol: u8,
+    tcp_flags: u8,
+    packets: u32,
+    octets: u32,
+    first: u32,
+    last: u32,
+    input: u16,
+    output: u16,
+}
+
+/// Draw a record of one of `flows` flows, ended by `uptime`, from `rng`.
+///
+/// A flow's 5-tuple is determined by its index: its source is in 10.0.0.0/8,
+/// its destination in 192.168.0.0/16 and its protocol TCP but for every third
+/// flow, UDP.
+#[allow(clippy::cast_possible_truncation)]
+fn record<R>(rng: &mut R, flows: NonZeroU32, uptime: u32) -> Record
+where
+    R: Rng,
+{
+    let flow = rng.gen_range(0..flows.get());
+    let protocol = if flow % 3 == 0 { 17 } else { 6 };
+    let packets = rng.gen_range(1..1_000);
+    let duration = rng.gen_range(0..MAXIMUM_FLOW_MILLIS);
+    let last = uptime.saturating_sub(rng.gen_range(0..UPTIME_STEP_MILLIS));
+    Record {
+        source: 0x0A00_0000 | (flow & 0x00FF_FFFF),
+        destination: 0xC0A8_0000 | (flow.wrapping_mul(7919) & 0xFFFF),
+        source_port: 1024 + (flow % 64_000) as u16,
+        destination_port: DESTINATION_PORTS[(flow as usize) % DESTINATION_PORTS.len()],
+        protocol,
+        tcp_flags: if protocol == 6 { 0x1B } else { 0 },
+        packets,
+        octets: packets * rng.gen_range(40..1_500),
+        first: last.saturating_sub(duration),
+        last,
+        input: rng.gen_range(1..16),
+        output: rng.gen_range(1..16),
+    }
+}
+
+/// Append `record` as a NetFlow v5 flow record.
+fn v5_record(record: &Record, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&record.source.to_be_bytes());
+    buf.extend_from_slice(&record.destination.to_be_bytes());
+    buf.extend_from_slice(&0_u32.to_be_bytes()); // nexthop
+    buf.extend_from_slice(&record.input.to_be_bytes());
+    buf.extend_from_slice(&record.output.to_be_bytes());
+    buf.extend_from_slice(&record.packets.to_be_bytes());
+    buf.extend_from_slice(&record.octets.to_be_bytes());
+    buf.extend_from_slice(&record.first.to_be_bytes());
+    buf.extend_from_slice(&record.last.to_be_bytes());
+    buf.extend_from_slice(&record.source_port.to_be_bytes());
+    buf.extend_from_slice(&record.destination_port.to_be_bytes());
+    buf.push(0); // pad
+    buf.push(record.tcp_flags);
+    buf.push(record.protocol);
+    buf.push(0); // tos
+    buf.extend_from_slice(&[0; 4]); // source and destination AS
+    buf.extend_from_slice(&[8, 16]); // source and destination mask
+    buf.extend_from_slice(&[0; 2]); // pad
+}
+
+/// Append `record` as a v9 or IPFIX data record of [`FIELDS`].
+fn data_record(record: &Record, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&record.source.to_be_bytes());
+    buf.extend_from_slice(&record.destination.to_be_bytes());
+    buf.extend_from_slice(&record.source_port.to_be_bytes());
+    buf.extend_from_slice(&record.destination_port.to_be_bytes());
+    buf.push(record.protocol);
+    buf.push(record.tcp_flags);
+    buf.extend_from_slice(&record.packets.to_be_bytes());
+    buf.extend_from_slice(&record.octets.to_be_bytes());
+    buf.extend_from_slice(&record.first.to_be_bytes());
+    buf.extend_from_slice(&record.last.to_be_bytes());
+    buf.extend_from_slice(&record.input.to_be_bytes());
+    buf.extend_from_slice(&record.output.to_be_bytes());
+}
+
+/// Append a set, or flowset, of `set_id` holding `body`, padded to four bytes
+/// if `pad`.
+#[allow(clippy::cast_possible_truncation)]
+fn set(set_id: u16, body: &[u8], pad: bool, buf: &mut Vec<u8>) {
+    let padding = if pad { (4 - body.len() % 4) % 4 } else { 0 };
+    buf.extend_from_slice(&set_id.to_be_bytes());
+    buf.extend_from_slice(&((4 + body.len() + padding) as u16).to_be_bytes());
+    buf.extend_from_slice(body);
+    buf.resize(buf.len() + padding, 0);
+}
+
+/// The template record describing [`FIELDS`].
+#[allow(clippy::cast_possible_truncation)]
+fn template() -> Vec<u8> {
+    let mut template = Vec::with_capacity(4 + FIELDS.len() * 4);
+    template.extend_from_slice(&TEMPLATE_ID.to_be_bytes());
+    template.extend_from_slice(&(FIELDS.len() as u16).to_be_bytes());
+    for (id, length) in FIELDS {
+        template.extend_from_slice(&id.to_be_bytes());
+        template.extend_from_slice(&length.to_be_bytes());
+    }
+    template
+}
+
+/// Return datagram `index` of records drawn from `rng`, and the number of
+/// records it holds. `sequence` is the sequence number of the datagram, as
+/// each protocol counts it, and is advanced past it.
+#[allow(clippy::cast_possible_truncation)]
+fn packet<R>(rng: &mut R, config: &Config, index: u32, sequence: &mut u32) -> (Vec<u8>, u64)
+where
+    R: Rng,
+{
+    let uptime = INITIAL_UPTIME_MILLIS.wrapping_add(index.wrapping_mul(UPTIME_STEP_MILLIS));
+    let export_seconds =
+        EPOCH_SECONDS.wrapping_add(uptime.wrapping_sub(INITIAL_UPTIME_MILLIS) / 1000);
+    let count = match config.protocol {
+        Protocol::NetflowV5 => config.records_per_packet.get().min(V5_MAXIMUM_RECORDS),
+        Protocol::NetflowV9 | Protocol::Ipfix => {
+            config.records_per_packet.get().min(MAXIMUM_RECORDS)
+        }
+    };
+    let records: Vec<Record> = (0..count)
+        .map(|_| record(rng, config.flows, uptime))
+        .collect();
+    let with_template = index % config.template_interval.get() == 0;
+
+    let mut buf = Vec::new();
+    match config.protocol {
+        Protocol::NetflowV5 => {
+            buf.extend_from_slice(&5_u16.to_be_bytes());
+            buf.extend_from_slice(&count.to_be_bytes());
+            buf.extend_from_slice(&uptime.to_be_bytes());
+            buf.extend_from_slice(&export_seconds.to_be_bytes());
+            buf.extend_from_slice(&0_u32.to_be_bytes()); // residual nanoseconds
+            buf.extend_from_slice(&sequence.to_be_bytes());
+            buf.extend_from_slice(&[0, 0]); // engine type and id
+            buf.extend_from_slice(&0_u16.to_be_bytes()); // sampling interval
+            for record in &records {
+                v5_record(record, &mut buf);
+            }
+            *sequence = sequence.wrapping_add(u32::from(count));
+        }
+        Protocol::NetflowV9 => {
+            let total = u16::from(with_template) + count;
+            buf.extend_from_slice(&9_u16.to_be_bytes());
+            buf.extend_from_slice(&total.to_be_bytes());
+            buf.extend_from_slice(&uptime.to_be_bytes());
+            buf.extend_from_slice(&export_seconds.to_be_bytes());
+            buf.extend_from_slice(&sequence.to_be_bytes());
+            buf.extend_from_slice(&0_u32.to_be_bytes()); // source id
+            if with_template {
+                set(0, &template(), true, &mut buf);
+            }
+            let mut data = Vec::new();
+            for record in &records {
+                data_record(record, &mut data);
+            }
+            set(TEMPLATE_ID, &data, true, &mut buf);
+            *sequence = sequence.wrapping_add(1);
+        }
+        Protocol::Ipfix => {
+            buf.extend_from_slice(&10_u16.to_be_bytes());
+            buf.extend_from_slice(&0_u16.to_be_bytes()); // length, set below
+            buf.extend_from_slice(&export_seconds.to_be_bytes());
+            buf.extend_from_slice(&sequence.to_be_bytes());
+            buf.extend_from_slice(&0_u32.to_be_bytes()); // observation domain
+            if with_template {
+                set(2, &template(), false, &mut buf);
+            }
+            let mut data = Vec::new();
+            for record in &records {
+                data_record(record, &mut data);
+            }
+            set(TEMPLATE_ID, &data, false, &mut buf);
+            let length = buf.len() as u16;
+            buf[2..4].copy_from_slice(&length.to_be_bytes());
+            *sequence = sequence.wrapping_add(u32::from(count));
+        }
+    }
+    (buf, u64::from(count))
+}
+
+/// Build the block cache of a generator configured by `config`, as
+/// [`NetFlow::new`] does. Each block is one datagram.
+///
+/// # Errors
+///
+/// None are known.
+///
+/// # Panics
+///
+/// Function will panic if user has passed zero values for any byte values.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::ptr_arg)]
+pub(crate) fn block_cache(
+    config: &Config,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<Block>, Error> {
+    let mut rng = StdRng::from_seed(config.seed);
+    let maximum_cache_bytes = config.maximum_prebuild_cache_size_bytes.get_bytes() as usize;
+    assert!(maximum_cache_bytes > 0, "bytes must be non-zero");
+
+    let mut block_cache = Vec::new();
+    let mut cache_bytes = 0;
+    let mut index = 0;
+    let mut sequence = 0;
+    while cache_bytes < maximum_cache_bytes {
+        let (bytes, records) = packet(&mut rng, config, index, &mut sequence);
+        index = index.wrapping_add(1);
+        cache_bytes += bytes.len();
+        block_cache.push(Block::new(
+            NonZeroU32::new(bytes.len() as u32).expect("packets are never empty"),
+            records,
+            bytes,
+        ));
+    }
+    Summary::new(&block_cache).emit(labels);
+    gauge!("block_construction_complete", 1.0, labels);
+    Ok(block_cache)
+}
+
+#[derive(Debug)]
+/// The NetFlow generator.
+///
+/// This generator is responsible for sending NetFlow or IPFIX datagrams to the
+/// target.
+pub struct NetFlow {
+    addr: SocketAddr,
+    throttle: Throttle,
+    block_cache: Vec<Block>,
+    metric_labels: Vec<(String, String)>,
+    budget: Budget,
+    shutdown: Shutdown,
+    meter: Meter,
+}
+
+impl NetFlow {
+    /// Create a new [`NetFlow`] instance
+    ///
+    /// # Errors
+    ///
+    /// Creation will fail if the block cache cannot be built.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if the address is not a valid socket address.
+    pub fn new(
+        config: &Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
+        let throttle = Throttle::new(config.throttle, config.packets_per_second, pause);
+        let labels = vec![];
+        let mut block_cache = block_cache(config, &labels)?;
+        if config.lock_block_cache {
+            block::lock(&mut block_cache, &labels)?;
+        }
+
+        let addr = config
+            .addr
+            .to_socket_addrs()
+            .expect("could not convert to socket")
+            .next()
+            .unwrap();
+        Ok(Self {
+            addr,
+            block_cache,
+            throttle,
+            metric_labels: vec![address::label(addr.ip())],
+            budget: Budget::new(config.maximum_bytes, config.maximum_events),
+            shutdown,
+            meter,
+        })
+    }
+
+    /// Run [`NetFlow`] to completion or until a shutdown signal is received.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if no local socket can be bound. Send
+    /// errors are recorded and the datagram dropped.
+    ///
+    /// # Panics
+    ///
+    /// None known.
+    pub async fn spin(mut self) -> Result<(), Error> {
+        let labels = self.metric_labels;
+        let mut budget = self.budget;
+        let mut rate_window = RateWindow::new(RATE_WINDOW);
+
+        let bind_addr = if self.addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(bind_addr).await?;
+        let mut blocks = self.block_cache.iter().cycle();
+        let one = NonZeroU32::new(1).unwrap();
+
+        loop {
+            let blk = blocks.next().unwrap();
+
+            tokio::select! {
+                _ = self.throttle.wait(one, &labels) => {
+                    match socket.send_to(&blk.bytes, self.addr).await {
+                        Ok(_) => {
+                            counter!("packets_sent", 1, &labels);
+                            counter!("flows_sent", blk.lines, &labels);
+                            if record_block(blk, &labels, &self.meter, &mut rate_window, &mut budget) {
+                                return Ok(());
+                            }
+                        }
+                        Err(err) => {
+                            let mut error_labels = labels.clone();
+                            error_labels.push(("error".to_string(), io_error_kind(&err)));
+                            counter!("request_failure", 1, &error_labels);
+                        }
+                    }
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(());
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::{NonZeroU16, NonZeroU32};
+
+    use byte_unit::Byte;
+    use proptest::prelude::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{packet, Config, Protocol, FIELDS, V5_MAXIMUM_RECORDS};
+
+    fn u16_at(buf: &[u8], at: usize) -> u16 {
+        u16::from_be_bytes([buf[at], buf[at + 1]])
+    }
+
+    fn protocol() -> impl Strategy<Value = Protocol> {
+        prop_oneof![
+            Just(Protocol::NetflowV5),
+            Just(Protocol::NetflowV9),
+            Just(Protocol::Ipfix),
+        ]
+    }
+
+    // A datagram's header declares its version and what follows: v5 its
+    // fixed records, v9 and IPFIX sets whose lengths add up to the datagram.
+    proptest! {
+        #[test]
+        fn packet_shape(
+            seed: u64,
+            protocol in protocol(),
+            records_per_packet in 1..64_u16,
+            template_interval in 1..8_u32,
+            index in 0..64_u32,
+        ) {
+            let config = Config {
+                seed: [0; 32],
+                addr: "127.0.0.1:2055".to_string(),
+                protocol,
+                flows: NonZeroU32::new(100).unwrap(),
+                records_per_packet: NonZeroU16::new(records_per_packet).unwrap(),
+                template_interval: NonZeroU32::new(template_interval).unwrap(),
+                packets_per_second: NonZeroU32::new(1).unwrap(),
+                maximum_prebuild_cache_size_bytes: Byte::from_bytes(1),
+                maximum_bytes: None,
+                maximum_events: None,
+                throttle: Default::default(),
+                lock_block_cache: false,
+                numa: None,
+            };
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut sequence = 0;
+            let (bytes, records) = packet(&mut rng, &config, index, &mut sequence);
+            let with_template = index % template_interval == 0;
+            let record_bytes: usize = FIELDS.iter().map(|(_, length)| usize::from(*length)).sum();
+            match protocol {
+                Protocol::NetflowV5 => {
+                    let count = records_per_packet.min(V5_MAXIMUM_RECORDS);
+                    prop_assert_eq!(records, u64::from(count));
+                    prop_assert_eq!(u16_at(&bytes, 0), 5);
+                    prop_assert_eq!(u16_at(&bytes, 2), count);
+                    prop_assert_eq!(bytes.len(), 24 + 48 * usize::from(count));
+                    prop_assert_eq!(sequence, u32::from(count));
+                }
+                Protocol::NetflowV9 | Protocol::Ipfix => {
+                    prop_assert_eq!(records, u64::from(records_per_packet));
+                    let (version, header, template_set) = if protocol == Protocol::NetflowV9 {
+                        (9, 20, 0)
+                    } else {
+                        (10, 16, 2)
+                    };
+                    prop_assert_eq!(u16_at(&bytes, 0), version);
+                    if protocol == Protocol::Ipfix {
+                        prop_assert_eq!(usize::from(u16_at(&bytes, 2)), bytes.len());
+                    }
+                    let mut at = header;
+                    let mut sets = Vec::new();
+                    while at < bytes.len() {
+                        sets.push(u16_at(&bytes, at));
+                        at += usize::from(u16_at(&bytes, at + 2));
+                    }
+                    prop_assert_eq!(at, bytes.len());
+                    if with_template {
+                        prop_assert_eq!(sets, vec![template_set, 256]);
+                    } else {
+                        prop_assert_eq!(sets, vec![256]);
+                    }
+                    let data_set = bytes.len() - if protocol == Protocol::NetflowV9 {
+                        // The data set is padded to four bytes.
+                        let data = record_bytes * usize::from(records_per_packet);
+                        4 + data + (4 - data % 4) % 4
+                    } else {
+                        4 + record_bytes * usize::from(records_per_packet)
+                    };
+                    prop_assert_eq!(u16_at(&bytes, data_set), 256);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! The process spawning churn generator.
+//!
+//! Process monitoring targets pay for process events -- forks, execs and
+//! exits -- not bytes. This generator spawns short-lived processes at a fixed
+//! rate, each living for a lifetime drawn between `minimum_lifetime_millis`
+//! and `maximum_lifetime_millis` before it is killed, unless it exits first.
+//! Each process is given arguments of between `minimum_argv_bytes` and
+//! `maximum_argv_bytes` following the configured `arguments`, so that targets
+//! reading `/proc/<pid>/cmdline` pay for reading it.
+//!
+//! By default each process is `/bin/sh -c 'read _'`, which waits on its stdin,
+//! a pipe held open by the generator, until it is killed. The generated
+//! arguments are the shell's positional parameters and go unused. No more than
+//! `maximum_live` processes are alive at once; spawns beyond are skipped.
+
+use std::{
+    io,
+    num::NonZeroU32,
+    path::PathBuf,
+    process::{ExitStatus, Stdio},
+};
+
+use futures::{
+    future::{BoxFuture, FutureExt},
+    stream::{FuturesUnordered, StreamExt},
+};
+use governor::state::direct::InsufficientCapacity;
+use metrics::{counter, gauge};
+use rand::{distributions::Alphanumeric, rngs::StdRng, Rng, SeedableRng};
+use serde::Deserialize;
+use tokio::{
+    process::Command,
+    time::{self, Duration},
+};
+use tracing::info;
+
+use crate::{
+    control::Pause,
+    numa,
+    signals::Shutdown,
+    telemetry::io_error_kind,
+    throttle::{self, Throttle},
+};
+
+/// The longest argument the generator makes, in bytes.
+const MAXIMUM_ARGUMENT_BYTES: usize = 32;
+
+fn default_executable() -> PathBuf {
+    PathBuf::from("/bin/sh")
+}
+
+fn default_arguments() -> Vec<String> {
+    vec!["-c".to_string(), "read _".to_string()]
+}
+
+fn default_maximum_argv_bytes() -> u32 {
+    256
+}
+
+fn default_minimum_lifetime_millis() -> u64 {
+    100
+}
+
+fn default_maximum_lifetime_millis() -> u64 {
+    1_000
+}
+
+fn default_maximum_live() -> NonZeroU32 {
+    NonZeroU32::new(1_000).unwrap()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration of [`ProcessChurn`]
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// The processes spawned per second
+    pub spawns_per_second: NonZeroU32,
+    /// The executable each process runs
+    #[serde(default = "default_executable")]
+    pub executable: PathBuf,
+    /// The arguments each process is given ahead of those generated
+    #[serde(default = "default_arguments")]
+    pub arguments: Vec<String>,
+    /// The fewest bytes of arguments generated for each process
+    #[serde(default)]
+    pub minimum_argv_bytes: u32,
+    /// The most bytes of arguments generated for each process
+    #[serde(default = "default_maximum_argv_bytes")]
+    pub maximum_argv_bytes: u32,
+    /// The shortest a process lives before it is killed, in milliseconds
+    #[serde(default = "default_minimum_lifetime_millis")]
+    pub minimum_lifetime_millis: u64,
+    /// The longest a process lives before it is killed, in milliseconds
+    #[serde(default = "default_maximum_lifetime_millis")]
+    pub maximum_lifetime_millis: u64,
+    /// The most processes alive at once. Spawns beyond are skipped.
+    #[serde(default = "default_maximum_live")]
+    pub maximum_live: NonZeroU32,
+    /// The maximum number of processes to spawn before this generator stops.
+    /// If unset the generator runs until the experiment ends.
+    pub maximum_spawns: Option<u64>,
+    /// The algorithm used to throttle spawns to `spawns_per_second`. Defaults
+    /// to a token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+}
+
+#[derive(Debug)]
+/// Errors produced by [`ProcessChurn`].
+pub enum Error {
+    /// Rate limiter has insuficient capacity for payload. Indicates a serious
+    /// bug.
+    Governor(InsufficientCapacity),
+}
+
+impl From<InsufficientCapacity> for Error {
+    fn from(error: InsufficientCapacity) -> Self {
+        Error::Governor(error)
+    }
+}
+
+/// How a spawned process ended.
+#[derive(Debug)]
+enum Exit {
+    /// The process exited before its lifetime was over.
+    Exited(ExitStatus),
+    /// The process was killed at the end of its lifetime.
+    Killed,
+}
+
+/// Generate the arguments of one process: alphanumeric words of between
+/// `minimum_bytes` and `maximum_bytes` in total.
+fn argv<R>(rng: &mut R, minimum_bytes: u32, maximum_bytes: u32) -> Vec<String>
+where
+    R: Rng + ?Sized,
+{
+    let mut remaining = rng.gen_range(minimum_bytes..=maximum_bytes.max(minimum_bytes)) as usize;
+    let mut arguments = Vec::new();
+    while remaining > 0 {
+        let len = rng.gen_range(1..=remaining.min(MAXIMUM_ARGUMENT_BYTES));
+        let argument: String = (&mut *rng)
+            .sample_iter(&Alphanumeric)
+            .take(len)
+            .map(char::from)
+            .collect();
+        arguments.push(argument);
+        remaining -= len;
+    }
+    arguments
+}
+
+#[derive(Debug)]
+/// The process churn generator.
+///
+/// This generator is responsible for spawning the short-lived processes the
+/// target monitors.
+pub struct ProcessChurn {
+    config: Config,
+    rng: StdRng,
+    throttle: Throttle,
+    metric_labels: Vec<(String, String)>,
+    shutdown: Shutdown,
+}
+
+impl ProcessChurn {
+    /// Create a new [`ProcessChurn`] instance
+    #[must_use]
+    pub fn new(config: &Config, shutdown: Shutdown, pause: Pause) -> Self {
+        Self {
+            config: config.clone(),
+            rng: StdRng::from_seed(config.seed),
+            throttle: Throttle::new(config.throttle, config.spawns_per_second, pause),
+            metric_labels: vec![],
+            shutdown,
+        }
+    }
+
+    /// Spawn one process, returning its end once it has exited or been
+    /// killed.
+    fn spawn(&mut self) -> Result<BoxFuture<'static, Result<Exit, io::Error>>, io::Error> {
+        let minimum = self.config.minimum_lifetime_millis;
+        let maximum = self.config.maximum_lifetime_millis.max(minimum);
+        let lifetime = Duration::from_millis(self.rng.gen_range(minimum..=maximum));
+        let mut child = Command::new(&self.config.executable)
+            .args(&self.config.arguments)
+            .args(argv(
+                &mut self.rng,
+                self.config.minimum_argv_bytes,
+                self.config.maximum_argv_bytes,
+            ))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            // Processes alive when the generator stops are killed with it.
+            .kill_on_drop(true)
+            .spawn()?;
+        Ok(async move {
+            // Waiting on a child closes its stdin. It is held here instead, so
+            // that the default process reads nothing until it is killed.
+            let _stdin = child.stdin.take();
+            tokio::select! {
+                status = child.wait() => status.map(Exit::Exited),
+                _ = time::sleep(lifetime) => child.kill().await.map(|()| Exit::Killed),
+            }
+        }
+        .boxed())
+    }
+
+    /// Run [`ProcessChurn`] to completion or until a shutdown signal is
+    /// received.
+    ///
+    /// # Errors
+    ///
+    /// None known. Processes that fail to spawn are recorded and the
+    /// generator carries on.
+    ///
+    /// # Panics
+    ///
+    /// None known.
+    #[allow(clippy::cast_precision_loss)]
+    pub async fn spin(mut self) -> Result<(), Error> {
+        let labels = self.metric_labels.clone();
+        let maximum_live = self.config.maximum_live.get() as usize;
+        let mut live = FuturesUnordered::new();
+        let mut spawns: u64 = 0;
+
+        loop {
+            tokio::select! {
+                res = self.throttle.wait(NonZeroU32::new(1).unwrap(), &labels) => {
+                    res?;
+                    if live.len() >= maximum_live {
+                        counter!("spawn_skipped", 1, &labels);
+                    } else {
+                        match self.spawn() {
+                            Ok(exit) => {
+                                live.push(exit);
+                                counter!("processes_spawned", 1, &labels);
+                            }
+                            Err(err) => {
+                                let mut error_labels = labels.clone();
+                                error_labels.push(("error".to_string(), io_error_kind(&err)));
+                                counter!("spawn_failure", 1, &error_labels);
+                            }
+                        }
+                        spawns += 1;
+                        if self.config.maximum_spawns.map_or(false, |max| spawns >= max) {
+                            info!("maximum spawns reached, generator complete");
+                            gauge!("generator_complete", 1.0);
+                            return Ok(());
+                        }
+                    }
+                }
+                Some(exit) = live.next(), if !live.is_empty() => {
+                    match exit {
+                        Ok(Exit::Exited(status)) => {
+                            let mut exit_labels = labels.clone();
+                            let outcome = if status.success() { "success" } else { "failure" };
+                            exit_labels.push(("status".to_string(), outcome.to_string()));
+                            counter!("processes_exited", 1, &exit_labels);
+                        }
+                        Ok(Exit::Killed) => counter!("processes_killed", 1, &labels),
+                        Err(err) => {
+                            let mut error_labels = labels.clone();
+                            error_labels.push(("error".to_string(), io_error_kind(&err)));
+                            counter!("reap_failure", 1, &error_labels);
+                        }
+                    }
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(());
+                }
+            }
+            gauge!("processes_live", live.len() as f64, &labels);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{argv, MAXIMUM_ARGUMENT_BYTES};
+
+    // The generated arguments total between the minimum and maximum bytes,
+    // each a non-empty alphanumeric word no longer than the longest argument.
+    proptest! {
+        #[test]
+        fn argv_within_bounds(seed: u64, minimum_bytes in 0..1024_u32, maximum_bytes in 0..1024_u32) {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let arguments = argv(&mut rng, minimum_bytes, maximum_bytes);
+            let total: usize = arguments.iter().map(String::len).sum();
+            prop_assert!(total >= minimum_bytes as usize);
+            prop_assert!(total <= maximum_bytes.max(minimum_bytes) as usize);
+            for argument in arguments {
+                prop_assert!(!argument.is_empty() && argument.len() <= MAXIMUM_ARGUMENT_BYTES);
+                prop_assert!(argument.chars().all(|c| c.is_ascii_alphanumeric()));
+            }
+        }
+    }
+}
@@ -0,0 +1,473 @@
+//! The [Pub/Sub](https://cloud.google.com/pubsub) protocol speaking generator.
+//!
+//! Each block is the data of one message, published to a topic with the REST
+//! `publish` method, `messages_per_request` at a time. Message data is base64
+//! encoded into the JSON request as the block cache is built, and requests are
+//! throttled in messages, not bytes. Messages may carry one of
+//! `ordering_keys` ordering keys. Requests are not authenticated, which suits
+//! the Pub/Sub emulator.
+
+use std::{
+    num::{NonZeroU16, NonZeroU32, NonZeroUsize},
+    sync::Arc,
+};
+
+use byte_unit::{Byte, ByteUnit};
+use governor::state::direct::InsufficientCapacity;
+use hyper::{
+    client::HttpConnector,
+    header::{HeaderValue, CONTENT_TYPE},
+    http::uri::InvalidUri,
+    Body, Client, Request, Uri,
+};
+use metrics::{counter, gauge};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+use tracing::info;
+
+use crate::{
+    address,
+    block::{self, chunk_bytes, Block},
+    control::Pause,
+    generator::{
+        common::{Budget, RateWindow, RATE_WINDOW},
+        http::Variant,
+        Meter,
+    },
+    numa, payload,
+    signals::Shutdown,
+    telemetry::hyper_error_kind,
+    throttle::{self, Throttle},
+};
+
+/// The most messages Pub/Sub accepts in one `publish` request.
+const MAXIMUM_BATCH_MESSAGES: u16 = 1000;
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn default_messages_per_request() -> NonZeroU16 {
+    NonZeroU16::new(1).unwrap()
+}
+
+fn default_parallel_connections() -> u16 {
+    10
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration of this generator.
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// The URI of the Pub/Sub endpoint, for instance the emulator at
+    /// `http://localhost:8085`
+    #[serde(with = "http_serde::uri")]
+    pub target_uri: Uri,
+    /// The project of the topic
+    pub project: String,
+    /// The topic messages are published to
+    pub topic: String,
+    /// The payload generator to use for this target
+    pub variant: Variant,
+    /// The messages per second to send to the target
+    pub messages_per_second: NonZeroU32,
+    /// The messages published in each request, by default 1. Pub/Sub accepts
+    /// at most 1000.
+    #[serde(default = "default_messages_per_request")]
+    pub messages_per_request: NonZeroU16,
+    /// The number of distinct ordering keys messages carry, each message one
+    /// of them. If unset messages carry no ordering key.
+    pub ordering_keys: Option<NonZeroU32>,
+    /// The block sizes for messages to this target. Pub/Sub limits each
+    /// request to 10MB.
+    pub block_sizes: Option<Vec<byte_unit::Byte>>,
+    /// The maximum size in bytes of the cache of prebuilt messages
+    pub maximum_prebuild_cache_size_bytes: byte_unit::Byte,
+    /// The total number of parallel connections to maintain, by default 10
+    #[serde(default = "default_parallel_connections")]
+    pub parallel_connections: u16,
+    /// The maximum number of bytes to send before this generator stops. If
+    /// unset the generator runs until the experiment ends.
+    pub maximum_bytes: Option<byte_unit::Byte>,
+    /// The maximum number of events -- newline delimited lines -- to send
+    /// before this generator stops. If unset the generator runs until the
+    /// experiment ends.
+    pub maximum_events: Option<u64>,
+    /// The algorithm used to throttle output to `messages_per_second`.
+    /// Defaults to a token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Limit on the size of each newline delimited event. If unset events are
+    /// as large as the payload makes them.
+    pub event_limit: Option<payload::EventLimit>,
+    /// Whether to lock the block cache into RAM, touching each of its pages,
+    /// before the run starts. See mlock(2) for the limits on locked memory.
+    #[serde(default)]
+    pub lock_block_cache: bool,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+}
+
+#[derive(Debug)]
+/// Errors produced by [`PubSub`].
+pub enum Error {
+    /// Rate limiter has insuficient capacity for payload. Indicates a serious
+    /// bug.
+    Governor(InsufficientCapacity),
+    /// Creation of payload blocks failed.
+    Block(block::Error),
+    /// Wrapper around [`hyper::http::Error`].
+    Http(hyper::http::Error),
+    /// The topic's `publish` URI is not valid.
+    InvalidUri(InvalidUri),
+    /// More messages per request are configured than `publish` accepts.
+    MessagesPerRequest(u16),
+}
+
+impl From<block::Error> for Error {
+    fn from(error: block::Error) -> Self {
+        Error::Block(error)
+    }
+}
+
+impl From<hyper::http::Error> for Error {
+    fn from(error: hyper::http::Error) -> Self {
+        Error::Http(error)
+    }
+}
+
+impl From<InvalidUri> for Error {
+    fn from(error: InvalidUri) -> Self {
+        Error::InvalidUri(error)
+    }
+}
+
+impl From<InsufficientCapacity> for Error {
+    fn from(error: InsufficientCapacity) -> Self {
+        Error::Governor(error)
+    }
+}
+
+/// Encode `bytes` as padded, standard base64, as Pub/Sub accepts message
+/// data.
+fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0_u32, |n, (idx, byte)| {
+            n | (u32::from(*byte) << (16 - 8 * idx))
+        });
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                encoded.push(char::from(BASE64[((n >> (18 - 6 * idx)) & 0x3f) as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Message {
+    data: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ordering_key: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct PublishRequest {
+    messages: Vec<Message>,
+}
+
+/// Encode the request publishing `messages`, each with its ordering key.
+fn encode_request(messages: &[(&[u8], Option<u32>)]) -> Vec<u8> {
+    let request = PublishRequest {
+        messages: messages
+            .iter()
+            .map(|(data, key)| Message {
+                data: base64(data),
+                ordering_key: key.map(|key| format!("key_{}", key)),
+            })
+            .collect(),
+    };
+    serde_json::to_vec(&request).expect("publish requests always serialize")
+}
+
+/// Build the block cache of a generator configured by `config`, as
+/// [`PubSub::new`] does. Each block is a request carrying exactly
+/// `messages_per_request` messages, the payload blocks taken in turn, the last
+/// request wrapping around to the first.
+///
+/// # Errors
+///
+/// Function will return an error if the configured block sizes cannot be
+/// chunked into the prebuild cache or more messages per request are
+/// configured than Pub/Sub accepts.
+///
+/// # Panics
+///
+/// Function will panic if user has passed zero values for any byte values.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::ptr_arg)]
+pub(crate) fn block_cache(
+    config: &Config,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<Block>, Error> {
+    let messages_per_request = config.messages_per_request.get();
+    if messages_per_request > MAXIMUM_BATCH_MESSAGES {
+        return Err(Error::MessagesPerRequest(messages_per_request));
+    }
+    let mut rng = StdRng::from_seed(config.seed);
+    let block_sizes: Vec<NonZeroUsize> = config
+        .block_sizes
+        .clone()
+        .unwrap_or_else(|| {
+            vec![
+                Byte::from_unit(1.0 / 4.0, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(1.0 / 2.0, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(1_f64, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(2_f64, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(4_f64, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(8_f64, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(16_f64, ByteUnit::KiB).unwrap(),
+            ]
+        })
+        .iter()
+        .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
+        .collect();
+    let block_chunks = chunk_bytes(
+        &mut rng,
+        NonZeroUsize::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as usize)
+            .expect("bytes must be non-zero"),
+        &block_sizes,
+    )?;
+    let messages = config
+        .variant
+        .block_cache(&mut rng, &block_chunks, config.event_limit, labels);
+
+    let messages_per_request = usize::from(messages_per_request);
+    let requests = (messages.len() + messages_per_request - 1) / messages_per_request;
+    let mut block_cache = Vec::with_capacity(requests);
+    for request in 0..requests {
+        let batch: Vec<&Block> = (0..messages_per_request)
+            .map(|idx| &messages[(request * messages_per_request + idx) % messages.len()])
+            .collect();
+        let keyed: Vec<(&[u8], Option<u32>)> = batch
+            .iter()
+            .map(|blk| {
+                let key = config
+                    .ordering_keys
+                    .map(|keys| rng.gen_range(0..keys.get()));
+                (&blk.bytes[..], key)
+            })
+            .collect();
+        let bytes = encode_request(&keyed);
+        block_cache.push(Block::new(
+            NonZeroU32::new(bytes.len() as u32).expect("requests are never empty"),
+            batch.iter().map(|blk| blk.lines).sum(),
+            bytes,
+        ));
+    }
+    Ok(block_cache)
+}
+
+#[derive(Debug)]
+/// The Pub/Sub generator.
+///
+/// This generator is responsible for publishing blocks to the target as the
+/// messages of Pub/Sub `publish` requests.
+pub struct PubSub {
+    uri: Uri,
+    messages_per_request: NonZeroU32,
+    parallel_connections: u16,
+    connection_semaphore: Arc<Semaphore>,
+    throttle: Throttle,
+    block_cache: Vec<Block>,
+    metric_labels: Vec<(String, String)>,
+    budget: Budget,
+    shutdown: Shutdown,
+    meter: Meter,
+}
+
+impl PubSub {
+    /// Create a new [`PubSub`] instance
+    ///
+    /// # Errors
+    ///
+    /// Creation will fail if the block cache cannot be built or the topic's
+    /// `publish` URI is not valid.
+    pub fn new(
+        config: &Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
+        let uri: Uri = format!(
+            "{}/v1/projects/{}/topics/{}:publish",
+            config.target_uri.to_string().trim_end_matches('/'),
+            config.project,
+            config.topic
+        )
+        .parse()?;
+        let throttle = Throttle::new(config.throttle, config.messages_per_second, pause);
+        let labels = vec![];
+        let metric_labels = address::uri_labels(&config.target_uri);
+        let mut block_cache = block_cache(config, &labels)?;
+        if config.lock_block_cache {
+            block::lock(&mut block_cache, &labels)?;
+        }
+
+        Ok(Self {
+            uri,
+            messages_per_request: NonZeroU32::from(config.messages_per_request),
+            parallel_connections: config.parallel_connections,
+            connection_semaphore: Arc::new(Semaphore::new(config.parallel_connections as usize)),
+            block_cache,
+            throttle,
+            metric_labels,
+            budget: Budget::new(config.maximum_bytes, config.maximum_events),
+            shutdown,
+            meter,
+        })
+    }
+
+    /// Run [`PubSub`] to completion or until a shutdown signal is received.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if a request cannot be built.
+    ///
+    /// # Panics
+    ///
+    /// None known.
+    pub async fn spin(mut self) -> Result<(), Error> {
+        let client: Client<HttpConnector, Body> = Client::builder()
+            .pool_max_idle_per_host(self.parallel_connections as usize)
+            .retry_canceled_requests(false)
+            .build_http();
+        let mut throttle = self.throttle;
+        let uri = self.uri;
+        let connection_semaphore = self.connection_semaphore;
+        let messages_per_request = self.messages_per_request;
+
+        let labels = self.metric_labels;
+        let mut budget = self.budget;
+        let mut rate_window = RateWindow::new(RATE_WINDOW);
+        let mut blocks = self.block_cache.iter().cycle();
+
+        loop {
+            let blk = blocks.next().unwrap();
+            let total_bytes = blk.total_bytes;
+
+            tokio::select! {
+                _ = throttle.wait(messages_per_request, &labels) => {
+                    rate_window.record(u64::from(total_bytes.get()), &labels);
+                    let client = client.clone();
+                    let labels = labels.clone();
+                    let meter = self.meter.clone();
+
+                    let block_length = blk.bytes.len();
+                    let request: Request<Body> = Request::post(uri.clone())
+                        .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
+                        .body(Body::from(blk.bytes.clone()))?;
+
+                    let permit = Arc::clone(&connection_semaphore).acquire_owned().await.unwrap();
+                    tokio::spawn(async move {
+                        counter!("requests_sent", 1, &labels);
+                        match client.request(request).await {
+                            Ok(response) => {
+                                counter!("bytes_written", block_length as u64, &labels);
+                                meter.record(block_length as u64);
+                                let status = response.status();
+                                if status.is_success() {
+                                    counter!("messages_sent", u64::from(messages_per_request.get()), &labels);
+                                }
+                                let mut status_labels = labels.clone();
+                                status_labels
+                                    .push(("status_code".to_string(), status.as_u16().to_string()));
+                                counter!("request_ok", 1, &status_labels);
+                            }
+                            Err(err) => {
+                                let mut error_labels = labels.clone();
+                                error_labels
+                                    .push(("error".to_string(), hyper_error_kind(&err).to_string()));
+                                counter!("request_failure", 1, &error_labels);
+                            }
+                        }
+                        drop(permit);
+                    });
+                    budget.record(u64::from(total_bytes.get()), blk.lines);
+                },
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    // Acquire all available connections, meaning that we have
+                    // no outstanding tasks in flight.
+                    let _semaphore = connection_semaphore.acquire_many(u32::from(self.parallel_connections)).await.unwrap();
+                    return Ok(());
+                },
+            }
+
+            if budget.exhausted() {
+                info!("finite data limit reached, generator complete");
+                let _semaphore = connection_semaphore
+                    .acquire_many(u32::from(self.parallel_connections))
+                    .await
+                    .unwrap();
+                gauge!("generator_complete", 1.0, &labels);
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::{base64, encode_request, BASE64};
+
+    /// Decode padded, standard base64.
+    #[allow(clippy::cast_possible_truncation)]
+    fn decode(encoded: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for chunk in encoded.as_bytes().chunks(4) {
+            let digits: Vec<u32> = chunk
+                .iter()
+                .take_while(|digit| **digit != b'=')
+                .map(|digit| {
+                    u32::try_from(BASE64.iter().position(|b| b == digit).unwrap()).unwrap()
+                })
+                .collect();
+            let n = digits
+                .iter()
+                .enumerate()
+                .fold(0, |n, (idx, digit)| n | (digit << (18 - 6 * idx)));
+            for idx in 0..digits.len() - 1 {
+                bytes.push((n >> (16 - 8 * idx)) as u8);
+            }
+        }
+        bytes
+    }
+
+    // A request holds each message's data, base64 encoded, and its ordering
+    // key, if any.
+    proptest! {
+        #[test]
+        fn request_holds_messages(messages: Vec<(Vec<u8>, Option<u32>)>) {
+            let keyed: Vec<(&[u8], Option<u32>)> =
+                messages.iter().map(|(data, key)| (&data[..], *key)).collect();
+            let request: serde_json::Value =
+                serde_json::from_slice(&encode_request(&keyed)).unwrap();
+            let encoded = request["messages"].as_array().unwrap();
+            prop_assert_eq!(encoded.len(), messages.len());
+            for ((data, key), message) in messages.iter().zip(encoded) {
+                let encoded_data = message["data"].as_str().unwrap();
+                prop_assert_eq!(&base64(data), encoded_data);
+                prop_assert_eq!(&decode(encoded_data), data);
+                let encoded_key = message.get("orderingKey").map(|key| key.as_str().unwrap().to_string());
+                prop_assert_eq!(encoded_key, key.map(|key| format!("key_{}", key)));
+            }
+        }
+    }
+}
@@ -0,0 +1,499 @@
+//! The Redis protocol speaking generator.
+//!
+//! Each block is sent as the value of one Redis command, chosen by weight from
+//! a configured mix of commands -- pushing to a list, adding to a stream,
+//! publishing to a channel -- and encoded in RESP as the block cache is built.
+//! Commands are pipelined over a single connection, the target's replies read
+//! as they arrive and counted as `request_ok` or `request_failure`.
+
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    num::{NonZeroU32, NonZeroUsize},
+};
+
+use byte_unit::{Byte, ByteUnit};
+use governor::state::direct::InsufficientCapacity;
+use metrics::counter;
+use rand::{
+    distributions::{Distribution, WeightedError, WeightedIndex},
+    rngs::StdRng,
+    SeedableRng,
+};
+use serde::Deserialize;
+use tokio::{
+    io::{self, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    task::JoinHandle,
+};
+use tracing::info;
+
+use crate::{
+    address,
+    block::{self, chunk_bytes, Block},
+    control::Pause,
+    generator::{
+        common::{Budget, RateWindow, RATE_WINDOW},
+        http::Variant,
+        tcp::record_block,
+        Meter,
+    },
+    numa, payload,
+    signals::Shutdown,
+    telemetry::io_error_kind,
+    throttle::{self, Throttle},
+};
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn default_field() -> String {
+    "payload".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case", tag = "command")]
+/// A Redis command carrying a block as its value
+pub enum Command {
+    /// `LPUSH key block`
+    Lpush {
+        /// The list pushed to
+        key: String,
+    },
+    /// `RPUSH key block`
+    Rpush {
+        /// The list pushed to
+        key: String,
+    },
+    /// `XADD key [MAXLEN ~ maximum_length] * field block`
+    Xadd {
+        /// The stream added to
+        key: String,
+        /// The name of the entry's field holding the block, by default
+        /// `payload`
+        #[serde(default = "default_field")]
+        field: String,
+        /// Trim the stream to about this many entries. If unset the stream is
+        /// not trimmed.
+        maximum_length: Option<u64>,
+    },
+    /// `PUBLISH channel block`
+    Publish {
+        /// The channel published to
+        channel: String,
+    },
+}
+
+impl Command {
+    /// Encode this command, carrying `payload`, in RESP.
+    fn encode(&self, payload: &[u8]) -> Vec<u8> {
+        let maximum_length = match self {
+            Command::Xadd {
+                maximum_length: Some(maximum_length),
+                ..
+            } => maximum_length.to_string(),
+            _ => String::new(),
+        };
+        let args: Vec<&[u8]> = match self {
+            Command::Lpush { key } => vec![&b"LPUSH"[..], key.as_bytes(), payload],
+            Command::Rpush { key } => vec![&b"RPUSH"[..], key.as_bytes(), payload],
+            Command::Xadd {
+                key,
+                field,
+                maximum_length: None,
+            } => vec![
+                &b"XADD"[..],
+                key.as_bytes(),
+                &b"*"[..],
+                field.as_bytes(),
+                payload,
+            ],
+            Command::Xadd { key, field, .. } => vec![
+                &b"XADD"[..],
+                key.as_bytes(),
+                &b"MAXLEN"[..],
+                &b"~"[..],
+                maximum_length.as_bytes(),
+                &b"*"[..],
+                field.as_bytes(),
+                payload,
+            ],
+            Command::Publish { channel } => vec![&b"PUBLISH"[..], channel.as_bytes(), payload],
+        };
+        let mut encoded = Vec::with_capacity(payload.len() + 64);
+        encoded.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+        for arg in args {
+            encoded.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            encoded.extend_from_slice(arg);
+            encoded.extend_from_slice(b"\r\n");
+        }
+        encoded
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// A [`Command`] and how often it is sent relative to the others
+pub struct WeightedCommand {
+    /// The weight of this command in the mix, by default 1
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    /// The command
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration of this generator.
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// The address for the target, must be a valid SocketAddr
+    pub addr: String,
+    /// The payload generator to use for this target
+    pub variant: Variant,
+    /// The mix of commands blocks are sent with
+    pub commands: Vec<WeightedCommand>,
+    /// The bytes per second to send or receive from the target
+    pub bytes_per_second: byte_unit::Byte,
+    /// The block sizes for messages to this target
+    pub block_sizes: Option<Vec<byte_unit::Byte>>,
+    /// The maximum size in bytes of the cache of prebuilt messages
+    pub maximum_prebuild_cache_size_bytes: byte_unit::Byte,
+    /// The maximum number of bytes to send before this generator stops. If
+    /// unset the generator runs until the experiment ends.
+    pub maximum_bytes: Option<byte_unit::Byte>,
+    /// The maximum number of events -- newline delimited lines -- to send
+    /// before this generator stops. If unset the generator runs until the
+    /// experiment ends.
+    pub maximum_events: Option<u64>,
+    /// The algorithm used to throttle output to `bytes_per_second`. Defaults to a
+    /// token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Ramp each new connection up to its share of `bytes_per_second` rather
+    /// than offering it all at once. If unset connections start at full rate.
+    pub slow_start: Option<throttle::SlowStart>,
+    /// Limit on the size of each newline delimited event. If unset events are
+    /// as large as the payload makes them.
+    pub event_limit: Option<payload::EventLimit>,
+    /// Whether to lock the block cache into RAM, touching each of its pages,
+    /// before the run starts. See mlock(2) for the limits on locked memory.
+    #[serde(default)]
+    pub lock_block_cache: bool,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+}
+
+#[derive(Debug)]
+/// Errors produced by [`Redis`].
+pub enum Error {
+    /// Rate limiter has insuficient capacity for payload. Indicates a serious
+    /// bug.
+    Governor(InsufficientCapacity),
+    /// Creation of payload blocks failed.
+    Block(block::Error),
+    /// The command mix is empty or its weights are all zero.
+    Weights(WeightedError),
+}
+
+impl From<block::Error> for Error {
+    fn from(error: block::Error) -> Self {
+        Error::Block(error)
+    }
+}
+
+impl From<InsufficientCapacity> for Error {
+    fn from(error: InsufficientCapacity) -> Self {
+        Error::Governor(error)
+    }
+}
+
+impl From<WeightedError> for Error {
+    fn from(error: WeightedError) -> Self {
+        Error::Weights(error)
+    }
+}
+
+/// Build the block cache of a generator configured by `config`, as
+/// [`Redis::new`] does. Each block is encoded as a command of the mix.
+///
+/// # Errors
+///
+/// Function will return an error if the configured block sizes cannot be
+/// chunked into the prebuild cache or the command mix has no weight.
+///
+/// # Panics
+///
+/// Function will panic if user has passed zero values for any byte values.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::ptr_arg)]
+pub(crate) fn block_cache(
+    config: &Config,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<Block>, Error> {
+    let mut rng = StdRng::from_seed(config.seed);
+    let block_sizes: Vec<NonZeroUsize> = config
+        .block_sizes
+        .clone()
+        .unwrap_or_else(|| {
+            vec![
+                Byte::from_unit(1.0 / 32.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 16.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 8.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 4.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 2.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1_f64, ByteUnit::MB).unwrap(),
+            ]
+        })
+        .iter()
+        .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
+        .collect();
+    let block_chunks = chunk_bytes(
+        &mut rng,
+        NonZeroUsize::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as usize)
+            .expect("bytes must be non-zero"),
+        &block_sizes,
+    )?;
+    let mut block_cache =
+        config
+            .variant
+            .block_cache(&mut rng, &block_chunks, config.event_limit, labels);
+    let mix = WeightedIndex::new(config.commands.iter().map(|command| command.weight))?;
+    for blk in &mut block_cache {
+        blk.bytes = config.commands[mix.sample(&mut rng)]
+            .command
+            .encode(&blk.bytes);
+        blk.total_bytes =
+            NonZeroU32::new(blk.bytes.len() as u32).expect("commands are never empty");
+    }
+    Ok(block_cache)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A reply of the target, as far as it is read.
+enum Reply {
+    /// A simple string, integer or null reply.
+    Ok,
+    /// An error reply.
+    Error,
+    /// A bulk string reply of this many bytes, not counting its CRLF, that
+    /// follow the reply's first line.
+    Bulk(u64),
+}
+
+/// Classify the reply whose first line, with its CRLF, is `line`. The commands
+/// of [`Command`] are not answered with arrays.
+fn reply(line: &[u8]) -> Reply {
+    match line.first() {
+        Some(b'-') => Reply::Error,
+        Some(b'$') => std::str::from_utf8(&line[1..])
+            .ok()
+            .and_then(|len| len.trim_end().parse::<u64>().ok())
+            .map_or(Reply::Ok, Reply::Bulk),
+        _ => Reply::Ok,
+    }
+}
+
+/// Read the target's replies from `reader` until the connection closes.
+async fn read_replies(reader: OwnedReadHalf, labels: Vec<(String, String)>) {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line).await {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        match reply(&line) {
+            Reply::Ok => counter!("request_ok", 1, &labels),
+            Reply::Error => {
+                let mut error_labels = labels.clone();
+                error_labels.push(("error".to_string(), "reply".to_string()));
+                counter!("request_failure", 1, &error_labels);
+            }
+            Reply::Bulk(len) => {
+                if io::copy(&mut (&mut reader).take(len + 2), &mut io::sink())
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+                counter!("request_ok", 1, &labels);
+            }
+        }
+    }
+}
+
+/// A connection to the target, its replies read by a task of their own until
+/// the connection is dropped.
+struct Connection {
+    writer: OwnedWriteHalf,
+    replies: JoinHandle<()>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.replies.abort();
+    }
+}
+
+#[derive(Debug)]
+/// The Redis generator.
+///
+/// This generator is responsible for sending blocks to the target as Redis
+/// commands.
+pub struct Redis {
+    addr: SocketAddr,
+    throttle: Throttle,
+    block_cache: Vec<Block>,
+    metric_labels: Vec<(String, String)>,
+    budget: Budget,
+    shutdown: Shutdown,
+    pause: Pause,
+    meter: Meter,
+}
+
+impl Redis {
+    /// Create a new [`Redis`] instance
+    ///
+    /// # Errors
+    ///
+    /// Creation will fail if the block cache cannot be built.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if user has passed zero values for any byte
+    /// values. Sharp corners.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(
+        config: &Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
+        let bytes_per_second = NonZeroU32::new(config.bytes_per_second.get_bytes() as u32).unwrap();
+        let throttle = Throttle::new(config.throttle, bytes_per_second, pause.clone())
+            .with_slow_start(config.slow_start, bytes_per_second, 1);
+        let labels = vec![];
+        let mut block_cache = block_cache(config, &labels)?;
+        if config.lock_block_cache {
+            block::lock(&mut block_cache, &labels)?;
+        }
+
+        let addr = config
+            .addr
+            .to_socket_addrs()
+            .expect("could not convert to socket")
+            .next()
+            .unwrap();
+        Ok(Self {
+            addr,
+            block_cache,
+            throttle,
+            metric_labels: vec![address::label(addr.ip())],
+            budget: Budget::new(config.maximum_bytes, config.maximum_events),
+            shutdown,
+            pause,
+            meter,
+        })
+    }
+
+    /// Run [`Redis`] to completion or until a shutdown signal is received.
+    ///
+    /// # Errors
+    ///
+    /// None known, write errors are recorded and the connection re-made.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if underlying byte capacity is not available.
+    pub async fn spin(mut self) -> Result<(), Error> {
+        let labels = self.metric_labels;
+        let mut budget = self.budget;
+        let mut rate_window = RateWindow::new(RATE_WINDOW);
+
+        let mut connection: Option<Connection> = None;
+        let mut blocks = self.block_cache.iter().cycle();
+
+        loop {
+            let blk = blocks.next().unwrap();
+            let total_bytes = blk.total_bytes;
+
+            tokio::select! {
+                // A generator paused with its connections closed does not
+                // reconnect until resumed.
+                conn = TcpStream::connect(self.addr), if connection.is_none() && !self.pause.closes_connections() => {
+                    match conn {
+                        Ok(client) => {
+                            let (reader, writer) = client.into_split();
+                            let replies = tokio::spawn(read_replies(reader, labels.clone()));
+                            self.throttle.connected(0);
+                            connection = Some(Connection { writer, replies });
+                        }
+                        Err(err) => {
+                            let mut error_labels = labels.clone();
+                            error_labels.push(("error".to_string(), io_error_kind(&err)));
+                            counter!("connection_failure", 1, &error_labels);
+                        }
+                    }
+                }
+                _ = self.throttle.wait(total_bytes, &labels), if connection.is_some() => {
+                    let mut client = connection.take().unwrap();
+                    counter!("requests_sent", 1, &labels);
+                    match client.writer.write_all(&blk.bytes).await {
+                        Ok(()) => {
+                            connection = Some(client);
+                            if record_block(blk, &labels, &self.meter, &mut rate_window, &mut budget) {
+                                return Ok(());
+                            }
+                        }
+                        Err(err) => {
+                            let mut error_labels = labels.clone();
+                            error_labels.push(("error".to_string(), io_error_kind(&err)));
+                            counter!("request_failure", 1, &error_labels);
+                        }
+                    }
+                }
+                _ = self.pause.changed() => {
+                    if self.pause.closes_connections() {
+                        connection = None;
+                    }
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(());
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::{reply, Command, Reply};
+
+    // A command's encoding is a RESP array of bulk strings, its last the
+    // payload verbatim, and a bulk reply is always read as such.
+    proptest! {
+        #[test]
+        fn encode_ends_with_payload(key in "[a-z]{1,16}", payload: Vec<u8>, maximum_length: Option<u64>) {
+            let command = Command::Xadd { key, field: "payload".to_string(), maximum_length };
+            let encoded = command.encode(&payload);
+            let args = if maximum_length.is_some() { 8 } else { 5 };
+            let prefix = format!("*{}\r\n$4\r\nXADD\r\n", args);
+            prop_assert!(encoded.starts_with(prefix.as_bytes()));
+            let mut suffix = format!("${}\r\n", payload.len()).into_bytes();
+            suffix.extend_from_slice(&payload);
+            suffix.extend_from_slice(b"\r\n");
+            prop_assert!(encoded.ends_with(&suffix));
+
+            let line = format!("${}\r\n", payload.len());
+            prop_assert_eq!(reply(line.as_bytes()), Reply::Bulk(payload.len() as u64));
+        }
+    }
+}
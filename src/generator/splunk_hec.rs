@@ -10,35 +10,36 @@ use std::{
 
 use acknowledgements::Channels;
 use byte_unit::{Byte, ByteUnit};
-use governor::{
-    clock,
-    state::{self, direct},
-    Quota, RateLimiter,
-};
 use http::{
     header::{AUTHORIZATION, CONTENT_LENGTH},
     Method, Request, Uri,
 };
 use hyper::{client::HttpConnector, Body, Client};
 use metrics::{counter, gauge};
-use once_cell::sync::OnceCell;
 use rand::{prelude::StdRng, SeedableRng};
 use serde::Deserialize;
 use tokio::{
-    sync::{Semaphore, SemaphorePermit},
+    sync::{OwnedSemaphorePermit, Semaphore},
     time::timeout,
 };
 use tracing::info;
 
 use crate::{
+    address,
     block::{self, chunk_bytes, construct_block_cache, Block},
-    generator::splunk_hec::acknowledgements::Channel,
-    payload,
+    control::Pause,
+    generator::{
+        common::{Budget, RateWindow, RATE_WINDOW},
+        splunk_hec::acknowledgements::Channel,
+        Meter,
+    },
+    numa, payload,
     payload::SplunkHecEncoding,
     signals::Shutdown,
+    telemetry::hyper_error_kind,
+    throttle::{self, Throttle},
 };
 
-static CONNECTION_SEMAPHORE: OnceCell<Semaphore> = OnceCell::new();
 const SPLUNK_HEC_ACKNOWLEDGEMENTS_PATH: &str = "/services/collector/ack";
 const SPLUNK_HEC_JSON_PATH: &str = "/services/collector/event";
 const SPLUNK_HEC_TEXT_PATH: &str = "/services/collector/raw";
@@ -55,7 +56,7 @@ pub struct AckSettings {
 }
 
 /// Configuration for [`SplunkHec`]
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct Config {
     /// The seed for random operations against this target
     pub seed: [u8; 32],
@@ -76,6 +77,26 @@ pub struct Config {
     pub block_sizes: Option<Vec<byte_unit::Byte>>,
     /// The total number of parallel connections to maintain
     pub parallel_connections: u16,
+    /// The maximum number of bytes to send before this generator stops. If
+    /// unset the generator runs until the experiment ends.
+    pub maximum_bytes: Option<byte_unit::Byte>,
+    /// The maximum number of events -- newline delimited lines -- to send
+    /// before this generator stops. If unset the generator runs until the
+    /// experiment ends.
+    pub maximum_events: Option<u64>,
+    /// The algorithm used to throttle output to `bytes_per_second`. Defaults to a
+    /// token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Limit on the size of each newline delimited event. If unset events are
+    /// as large as the payload makes them.
+    pub event_limit: Option<payload::EventLimit>,
+    /// Whether to lock the block cache into RAM, touching each of its pages,
+    /// before the run starts. See mlock(2) for the limits on locked memory.
+    #[serde(default)]
+    pub lock_block_cache: bool,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -102,11 +123,64 @@ pub struct SplunkHec {
     uri: Uri,
     token: String,
     parallel_connections: u16,
-    rate_limiter: RateLimiter<direct::NotKeyed, state::InMemoryState, clock::QuantaClock>,
+    connection_semaphore: Arc<Semaphore>,
+    throttle: Throttle,
     block_cache: Vec<Block>,
     metric_labels: Vec<(String, String)>,
     channels: Channels,
+    budget: Budget,
     shutdown: Shutdown,
+    meter: Meter,
+}
+
+/// Build the block cache of a generator configured by `config`, as
+/// [`SplunkHec::new`] does.
+///
+/// # Errors
+///
+/// Function will return an error if the configured block sizes cannot be
+/// chunked into the prebuild cache.
+///
+/// # Panics
+///
+/// Function will panic if user has passed zero values for any byte values.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::ptr_arg)]
+pub(crate) fn block_cache(
+    config: &Config,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<Block>, Error> {
+    let mut rng = StdRng::from_seed(config.seed);
+    let block_sizes: Vec<NonZeroUsize> = config
+        .block_sizes
+        .clone()
+        .unwrap_or_else(|| {
+            vec![
+                Byte::from_unit(1.0 / 8.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 4.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 2.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1_f64, ByteUnit::MB).unwrap(),
+                Byte::from_unit(2_f64, ByteUnit::MB).unwrap(),
+                Byte::from_unit(4_f64, ByteUnit::MB).unwrap(),
+            ]
+        })
+        .iter()
+        .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
+        .collect();
+    let block_chunks = chunk_bytes(
+        &mut rng,
+        NonZeroUsize::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as usize)
+            .expect("bytes must be non-zero"),
+        &block_sizes,
+    )?;
+    let block_cache = construct_block_cache(
+        &mut rng,
+        &payload::SplunkHec::new(config.format),
+        &block_chunks,
+        config.event_limit,
+        labels,
+    );
+    Ok(block_cache)
 }
 
 /// Derive the intended path from the format configuration
@@ -137,40 +211,21 @@ impl SplunkHec {
     /// Function will panic if user has passed non-zero values for any byte
     /// values. Sharp corners.
     #[allow(clippy::cast_possible_truncation)]
-    pub fn new(config: Config, shutdown: Shutdown) -> Result<Self, Error> {
-        let mut rng = StdRng::from_seed(config.seed);
-        let block_sizes: Vec<NonZeroUsize> = config
-            .block_sizes
-            .unwrap_or_else(|| {
-                vec![
-                    Byte::from_unit(1.0 / 8.0, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(1.0 / 4.0, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(1.0 / 2.0, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(1_f64, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(2_f64, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(4_f64, ByteUnit::MB).unwrap(),
-                ]
-            })
-            .iter()
-            .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
-            .collect();
+    pub fn new(
+        config: Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
         let bytes_per_second = NonZeroU32::new(config.bytes_per_second.get_bytes() as u32).unwrap();
-        let rate_limiter = RateLimiter::direct(Quota::per_second(bytes_per_second));
+        let throttle = Throttle::new(config.throttle, bytes_per_second, pause);
         let labels = vec![];
+        let metric_labels = address::uri_labels(&config.target_uri);
         let uri = get_uri_by_format(&config.target_uri, config.format);
-
-        let block_chunks = chunk_bytes(
-            &mut rng,
-            NonZeroUsize::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as usize)
-                .expect("bytes must be non-zero"),
-            &block_sizes,
-        )?;
-        let block_cache = construct_block_cache(
-            &mut rng,
-            &payload::SplunkHec::new(config.format),
-            &block_chunks,
-            &labels,
-        );
+        let mut block_cache = block_cache(&config, &labels)?;
+        if config.lock_block_cache {
+            block::lock(&mut block_cache, &labels)?;
+        }
 
         let mut channels = Channels::new(config.parallel_connections);
         if let Some(ack_settings) = config.acknowledgements {
@@ -183,19 +238,18 @@ impl SplunkHec {
             channels.enable_acknowledgements(ack_uri, config.token.clone(), ack_settings);
         }
 
-        CONNECTION_SEMAPHORE
-            .set(Semaphore::new(config.parallel_connections as usize))
-            .unwrap();
-
         Ok(Self {
             channels,
             parallel_connections: config.parallel_connections,
+            connection_semaphore: Arc::new(Semaphore::new(config.parallel_connections as usize)),
             uri,
             token: config.token,
             block_cache,
-            rate_limiter,
-            metric_labels: labels,
+            throttle,
+            metric_labels,
+            budget: Budget::new(config.maximum_bytes, config.maximum_events),
             shutdown,
+            meter,
         })
     }
 
@@ -217,9 +271,12 @@ impl SplunkHec {
             .set_host(false)
             .build_http();
 
-        let rate_limiter = Arc::new(self.rate_limiter);
+        let mut throttle = self.throttle;
         let uri = self.uri;
+        let connection_semaphore = self.connection_semaphore;
         let labels = self.metric_labels;
+        let mut budget = self.budget;
+        let mut rate_window = RateWindow::new(RATE_WINDOW);
 
         gauge!(
             "maximum_requests",
@@ -235,9 +292,11 @@ impl SplunkHec {
             let total_bytes = blk.total_bytes;
 
             tokio::select! {
-                _ = rate_limiter.until_n_ready(total_bytes) => {
+                _ = throttle.wait(total_bytes, &labels) => {
+                    rate_window.record(u64::from(total_bytes.get()), &labels);
                     let client = client.clone();
                     let labels = labels.clone();
+                    let meter = self.meter.clone();
                     let uri = uri.clone();
 
                     let body = Body::from(blk.bytes.clone());
@@ -257,8 +316,9 @@ impl SplunkHec {
                     // think we could also possibly have the send request return
                     // the AckID, meaning we could just keep the channel logic
                     // in this main loop here and avoid the AckService entirely.
-                    let permit = CONNECTION_SEMAPHORE.get().unwrap().acquire().await.unwrap();
-                    tokio::spawn(send_hec_request(permit, block_length, labels, channel, client, request));
+                    let permit = Arc::clone(&connection_semaphore).acquire_owned().await.unwrap();
+                    tokio::spawn(send_hec_request(permit, block_length, labels, meter, channel, client, request));
+                    budget.record(u64::from(total_bytes.get()), blk.lines);
                 }
                 _ = self.shutdown.recv() => {
                     info!("shutdown signal received");
@@ -271,14 +331,28 @@ impl SplunkHec {
                     return Ok(());
                 },
             }
+
+            if budget.exhausted() {
+                info!("finite data limit reached, generator complete");
+                // Acquire all available connections, meaning that we have no
+                // outstanding tasks in flight. Unlike shutdown the target is
+                // still running and requests are bounded by a timeout.
+                let _semaphore = connection_semaphore
+                    .acquire_many(u32::from(self.parallel_connections))
+                    .await
+                    .unwrap();
+                gauge!("generator_complete", 1.0, &labels);
+                return Ok(());
+            }
         }
     }
 }
 
 async fn send_hec_request(
-    permit: SemaphorePermit<'_>,
+    permit: OwnedSemaphorePermit,
     block_length: usize,
     labels: Vec<(String, String)>,
+    meter: Meter,
     channel: Channel,
     client: Client<HttpConnector>,
     request: Request<Body>,
@@ -290,6 +364,7 @@ async fn send_hec_request(
         Ok(tm) => match tm {
             Ok(response) => {
                 counter!("bytes_written", block_length as u64, &labels);
+                meter.record(block_length as u64);
                 let (parts, body) = response.into_parts();
                 let status = parts.status;
                 let mut status_labels = labels.clone();
@@ -306,13 +381,13 @@ async fn send_hec_request(
             }
             Err(err) => {
                 let mut error_labels = labels.clone();
-                error_labels.push(("error".to_string(), err.to_string()));
+                error_labels.push(("error".to_string(), hyper_error_kind(&err).to_string()));
                 counter!("request_failure", 1, &error_labels);
             }
         },
-        Err(err) => {
+        Err(_elapsed) => {
             let mut error_labels = labels.clone();
-            error_labels.push(("error".to_string(), err.to_string()));
+            error_labels.push(("error".to_string(), "timeout".to_string()));
             counter!("request_timeout", 1, &error_labels);
         }
     }
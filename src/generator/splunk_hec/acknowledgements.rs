@@ -13,6 +13,7 @@ use tokio::{
 use tracing::{debug, info};
 
 use super::{AckSettings, SPLUNK_HEC_CHANNEL_HEADER};
+use crate::telemetry::hyper_error_kind;
 
 type AckId = u64;
 
@@ -222,7 +223,7 @@ async fn ack_request(
             }
         }
         Err(err) => {
-            counter!("ack_status_request_failure", 1, "channel_id" => channel_id.clone(), "error" => err.to_string());
+            counter!("ack_status_request_failure", 1, "channel_id" => channel_id.clone(), "error" => hyper_error_kind(&err));
         }
     }
 }
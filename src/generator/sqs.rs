@@ -0,0 +1,429 @@
+//! The [SQS](https://aws.amazon.com/sqs/) protocol speaking generator.
+//!
+//! Each block is the body of one message, sent to a queue with the
+//! `SendMessage` action or, `messages_per_request` at a time, with
+//! `SendMessageBatch`. Requests are form encoded as the block cache is built
+//! and are throttled in messages, not bytes. Requests are not signed, which
+//! suits localstack and mocks of SQS, the sqs blackhole among them.
+
+use std::{
+    num::{NonZeroU32, NonZeroU8, NonZeroUsize},
+    sync::Arc,
+};
+
+use byte_unit::{Byte, ByteUnit};
+use governor::state::direct::InsufficientCapacity;
+use hyper::{
+    client::HttpConnector,
+    header::{HeaderValue, CONTENT_TYPE},
+    Body, Client, Request, Uri,
+};
+use metrics::{counter, gauge};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+use tracing::info;
+
+use crate::{
+    address,
+    block::{self, chunk_bytes, Block},
+    control::Pause,
+    generator::{
+        common::{Budget, RateWindow, RATE_WINDOW},
+        http::Variant,
+        Meter,
+    },
+    numa, payload,
+    signals::Shutdown,
+    telemetry::hyper_error_kind,
+    throttle::{self, Throttle},
+};
+
+/// The most messages SQS accepts in one `SendMessageBatch`.
+const MAXIMUM_BATCH_MESSAGES: u8 = 10;
+
+fn default_messages_per_request() -> NonZeroU8 {
+    NonZeroU8::new(1).unwrap()
+}
+
+fn default_parallel_connections() -> u16 {
+    10
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration of this generator.
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// The URI of the SQS endpoint, for instance `http://localhost:4566`
+    #[serde(with = "http_serde::uri")]
+    pub target_uri: Uri,
+    /// The URL of the queue messages are sent to
+    pub queue_url: String,
+    /// The payload generator to use for this target. SQS accepts only text
+    /// message bodies, binary variants are rejected by the target.
+    pub variant: Variant,
+    /// The messages per second to send to the target
+    pub messages_per_second: NonZeroU32,
+    /// The messages sent in each request, by default 1. A single message is
+    /// sent with `SendMessage`, more with `SendMessageBatch`, which accepts at
+    /// most 10.
+    #[serde(default = "default_messages_per_request")]
+    pub messages_per_request: NonZeroU8,
+    /// The block sizes for messages to this target. SQS limits a message, and
+    /// each batch of messages, to 256KiB.
+    pub block_sizes: Option<Vec<byte_unit::Byte>>,
+    /// The maximum size in bytes of the cache of prebuilt messages
+    pub maximum_prebuild_cache_size_bytes: byte_unit::Byte,
+    /// The total number of parallel connections to maintain, by default 10
+    #[serde(default = "default_parallel_connections")]
+    pub parallel_connections: u16,
+    /// The maximum number of bytes to send before this generator stops. If
+    /// unset the generator runs until the experiment ends.
+    pub maximum_bytes: Option<byte_unit::Byte>,
+    /// The maximum number of events -- newline delimited lines -- to send
+    /// before this generator stops. If unset the generator runs until the
+    /// experiment ends.
+    pub maximum_events: Option<u64>,
+    /// The algorithm used to throttle output to `messages_per_second`.
+    /// Defaults to a token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Limit on the size of each newline delimited event. If unset events are
+    /// as large as the payload makes them.
+    pub event_limit: Option<payload::EventLimit>,
+    /// Whether to lock the block cache into RAM, touching each of its pages,
+    /// before the run starts. See mlock(2) for the limits on locked memory.
+    #[serde(default)]
+    pub lock_block_cache: bool,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+}
+
+#[derive(Debug)]
+/// Errors produced by [`Sqs`].
+pub enum Error {
+    /// Rate limiter has insuficient capacity for payload. Indicates a serious
+    /// bug.
+    Governor(InsufficientCapacity),
+    /// Creation of payload blocks failed.
+    Block(block::Error),
+    /// Wrapper around [`hyper::http::Error`].
+    Http(hyper::http::Error),
+    /// More messages per request are configured than `SendMessageBatch`
+    /// accepts.
+    MessagesPerRequest(u8),
+}
+
+impl From<block::Error> for Error {
+    fn from(error: block::Error) -> Self {
+        Error::Block(error)
+    }
+}
+
+impl From<hyper::http::Error> for Error {
+    fn from(error: hyper::http::Error) -> Self {
+        Error::Http(error)
+    }
+}
+
+impl From<InsufficientCapacity> for Error {
+    fn from(error: InsufficientCapacity) -> Self {
+        Error::Governor(error)
+    }
+}
+
+/// Append `value` to `form`, encoded as `application/x-www-form-urlencoded`.
+fn form_encode(value: &[u8], form: &mut Vec<u8>) {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    for byte in value {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'*' => {
+                form.push(*byte);
+            }
+            b' ' => form.push(b'+'),
+            _ => form.extend_from_slice(&[
+                b'%',
+                HEX[usize::from(byte >> 4)],
+                HEX[usize::from(byte & 0x0f)],
+            ]),
+        }
+    }
+}
+
+/// Encode the request sending `messages` to the queue at `queue_url`.
+pub(crate) fn encode_request(queue_url: &str, messages: &[&[u8]]) -> Vec<u8> {
+    let mut form =
+        Vec::with_capacity(messages.iter().map(|m| m.len() * 3 / 2).sum::<usize>() + 256);
+    if let [message] = messages {
+        form.extend_from_slice(b"Action=SendMessage&Version=2012-11-05&QueueUrl=");
+        form_encode(queue_url.as_bytes(), &mut form);
+        form.extend_from_slice(b"&MessageBody=");
+        form_encode(message, &mut form);
+    } else {
+        form.extend_from_slice(b"Action=SendMessageBatch&Version=2012-11-05&QueueUrl=");
+        form_encode(queue_url.as_bytes(), &mut form);
+        for (idx, message) in messages.iter().enumerate() {
+            let entry = format!("&SendMessageBatchRequestEntry.{}.", idx + 1);
+            form.extend_from_slice(entry.as_bytes());
+            form.extend_from_slice(format!("Id=message_{}", idx + 1).as_bytes());
+            form.extend_from_slice(entry.as_bytes());
+            form.extend_from_slice(b"MessageBody=");
+            form_encode(message, &mut form);
+        }
+    }
+    form
+}
+
+/// Build the block cache of a generator configured by `config`, as
+/// [`Sqs::new`] does. Each block is a request carrying exactly
+/// `messages_per_request` messages, the payload blocks taken in turn, the last
+/// request wrapping around to the first.
+///
+/// # Errors
+///
+/// Function will return an error if the configured block sizes cannot be
+/// chunked into the prebuild cache or more messages per request are
+/// configured than SQS accepts.
+///
+/// # Panics
+///
+/// Function will panic if user has passed zero values for any byte values.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::ptr_arg)]
+pub(crate) fn block_cache(
+    config: &Config,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<Block>, Error> {
+    let messages_per_request = config.messages_per_request.get();
+    if messages_per_request > MAXIMUM_BATCH_MESSAGES {
+        return Err(Error::MessagesPerRequest(messages_per_request));
+    }
+    let mut rng = StdRng::from_seed(config.seed);
+    let block_sizes: Vec<NonZeroUsize> = config
+        .block_sizes
+        .clone()
+        .unwrap_or_else(|| {
+            vec![
+                Byte::from_unit(1.0 / 4.0, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(1.0 / 2.0, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(1_f64, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(2_f64, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(4_f64, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(8_f64, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(16_f64, ByteUnit::KiB).unwrap(),
+            ]
+        })
+        .iter()
+        .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
+        .collect();
+    let block_chunks = chunk_bytes(
+        &mut rng,
+        NonZeroUsize::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as usize)
+            .expect("bytes must be non-zero"),
+        &block_sizes,
+    )?;
+    let messages = config
+        .variant
+        .block_cache(&mut rng, &block_chunks, config.event_limit, labels);
+
+    let messages_per_request = usize::from(messages_per_request);
+    let requests = (messages.len() + messages_per_request - 1) / messages_per_request;
+    let mut block_cache = Vec::with_capacity(requests);
+    for request in 0..requests {
+        let batch: Vec<&Block> = (0..messages_per_request)
+            .map(|idx| &messages[(request * messages_per_request + idx) % messages.len()])
+            .collect();
+        let bytes = encode_request(
+            &config.queue_url,
+            &batch.iter().map(|blk| &blk.bytes[..]).collect::<Vec<_>>(),
+        );
+        block_cache.push(Block::new(
+            NonZeroU32::new(bytes.len() as u32).expect("requests are never empty"),
+            batch.iter().map(|blk| blk.lines).sum(),
+            bytes,
+        ));
+    }
+    Ok(block_cache)
+}
+
+#[derive(Debug)]
+/// The SQS generator.
+///
+/// This generator is responsible for sending blocks to the target as the
+/// messages of SQS `SendMessage` and `SendMessageBatch` requests.
+pub struct Sqs {
+    uri: Uri,
+    messages_per_request: NonZeroU32,
+    parallel_connections: u16,
+    connection_semaphore: Arc<Semaphore>,
+    throttle: Throttle,
+    block_cache: Vec<Block>,
+    metric_labels: Vec<(String, String)>,
+    budget: Budget,
+    shutdown: Shutdown,
+    meter: Meter,
+}
+
+impl Sqs {
+    /// Create a new [`Sqs`] instance
+    ///
+    /// # Errors
+    ///
+    /// Creation will fail if the block cache cannot be built.
+    pub fn new(
+        config: &Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
+        let throttle = Throttle::new(config.throttle, config.messages_per_second, pause);
+        let labels = vec![];
+        let metric_labels = address::uri_labels(&config.target_uri);
+        let mut block_cache = block_cache(config, &labels)?;
+        if config.lock_block_cache {
+            block::lock(&mut block_cache, &labels)?;
+        }
+
+        Ok(Self {
+            uri: config.target_uri.clone(),
+            messages_per_request: NonZeroU32::from(config.messages_per_request),
+            parallel_connections: config.parallel_connections,
+            connection_semaphore: Arc::new(Semaphore::new(config.parallel_connections as usize)),
+            block_cache,
+            throttle,
+            metric_labels,
+            budget: Budget::new(config.maximum_bytes, config.maximum_events),
+            shutdown,
+            meter,
+        })
+    }
+
+    /// Run [`Sqs`] to completion or until a shutdown signal is received.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if a request cannot be built.
+    ///
+    /// # Panics
+    ///
+    /// None known.
+    pub async fn spin(mut self) -> Result<(), Error> {
+        let client: Client<HttpConnector, Body> = Client::builder()
+            .pool_max_idle_per_host(self.parallel_connections as usize)
+            .retry_canceled_requests(false)
+            .build_http();
+        let mut throttle = self.throttle;
+        let uri = self.uri;
+        let connection_semaphore = self.connection_semaphore;
+        let messages_per_request = self.messages_per_request;
+
+        let labels = self.metric_labels;
+        let mut budget = self.budget;
+        let mut rate_window = RateWindow::new(RATE_WINDOW);
+        let mut blocks = self.block_cache.iter().cycle();
+
+        loop {
+            let blk = blocks.next().unwrap();
+            let total_bytes = blk.total_bytes;
+
+            tokio::select! {
+                _ = throttle.wait(messages_per_request, &labels) => {
+                    rate_window.record(u64::from(total_bytes.get()), &labels);
+                    let client = client.clone();
+                    let labels = labels.clone();
+                    let meter = self.meter.clone();
+
+                    let block_length = blk.bytes.len();
+                    let request: Request<Body> = Request::post(uri.clone())
+                        .header(
+                            CONTENT_TYPE,
+                            HeaderValue::from_static("application/x-www-form-urlencoded"),
+                        )
+                        .body(Body::from(blk.bytes.clone()))?;
+
+                    let permit = Arc::clone(&connection_semaphore).acquire_owned().await.unwrap();
+                    tokio::spawn(async move {
+                        counter!("requests_sent", 1, &labels);
+                        match client.request(request).await {
+                            Ok(response) => {
+                                counter!("bytes_written", block_length as u64, &labels);
+                                meter.record(block_length as u64);
+                                let status = response.status();
+                                if status.is_success() {
+                                    counter!("messages_sent", u64::from(messages_per_request.get()), &labels);
+                                }
+                                let mut status_labels = labels.clone();
+                                status_labels
+                                    .push(("status_code".to_string(), status.as_u16().to_string()));
+                                counter!("request_ok", 1, &status_labels);
+                            }
+                            Err(err) => {
+                                let mut error_labels = labels.clone();
+                                error_labels
+                                    .push(("error".to_string(), hyper_error_kind(&err).to_string()));
+                                counter!("request_failure", 1, &error_labels);
+                            }
+                        }
+                        drop(permit);
+                    });
+                    budget.record(u64::from(total_bytes.get()), blk.lines);
+                },
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    // Acquire all available connections, meaning that we have
+                    // no outstanding tasks in flight.
+                    let _semaphore = connection_semaphore.acquire_many(u32::from(self.parallel_connections)).await.unwrap();
+                    return Ok(());
+                },
+            }
+
+            if budget.exhausted() {
+                info!("finite data limit reached, generator complete");
+                let _semaphore = connection_semaphore
+                    .acquire_many(u32::from(self.parallel_connections))
+                    .await
+                    .unwrap();
+                gauge!("generator_complete", 1.0, &labels);
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::form_encode;
+
+    /// Decode `application/x-www-form-urlencoded` `form`.
+    fn form_decode(form: &[u8]) -> Vec<u8> {
+        let mut value = Vec::with_capacity(form.len());
+        let mut idx = 0;
+        while idx < form.len() {
+            match form[idx] {
+                b'+' => value.push(b' '),
+                b'%' => {
+                    let hex = std::str::from_utf8(&form[idx + 1..idx + 3]).unwrap();
+                    value.push(u8::from_str_radix(hex, 16).unwrap());
+                    idx += 2;
+                }
+                byte => value.push(byte),
+            }
+            idx += 1;
+        }
+        value
+    }
+
+    // Encoded values decode to themselves and hold no form delimiters.
+    proptest! {
+        #[test]
+        fn form_encode_round_trip(value: Vec<u8>) {
+            let mut form = Vec::new();
+            form_encode(&value, &mut form);
+            prop_assert!(!form.contains(&b'&') && !form.contains(&b'='));
+            prop_assert_eq!(form_decode(&form), value);
+        }
+    }
+}
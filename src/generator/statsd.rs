@@ -0,0 +1,449 @@
+//! The statsd speaking generator.
+//!
+//! Each block is one UDP datagram packed with statsd metric lines --
+//! counters, gauges, timings and distributions -- in the DogStatsD dialect.
+//! The shape of the metrics is controlled: how many distinct metric names are
+//! drawn from, how many tags each metric carries and how many values each tag
+//! takes, and how many metrics are packed into a datagram up to a maximum
+//! datagram size. Metrics without tags, and without distributions, are plain
+//! statsd.
+
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    num::{NonZeroU16, NonZeroU32},
+};
+
+use byte_unit::Byte;
+use governor::state::direct::InsufficientCapacity;
+use metrics::{counter, gauge};
+use rand::{
+    distributions::{Distribution, WeightedError, WeightedIndex},
+    rngs::StdRng,
+    Rng, SeedableRng,
+};
+use serde::Deserialize;
+use tokio::net::UdpSocket;
+use tracing::info;
+
+use crate::{
+    address,
+    block::{self, Block, Summary},
+    control::Pause,
+    generator::{
+        common::{Budget, RateWindow, RATE_WINDOW},
+        tcp::record_block,
+        Meter,
+    },
+    numa,
+    signals::Shutdown,
+    telemetry::io_error_kind,
+    throttle::{self, Throttle},
+};
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn default_metric_names() -> NonZeroU32 {
+    NonZeroU32::new(1_000).unwrap()
+}
+
+fn default_tag_values() -> NonZeroU32 {
+    NonZeroU32::new(10).unwrap()
+}
+
+fn default_metrics_per_packet() -> NonZeroU16 {
+    NonZeroU16::new(1).unwrap()
+}
+
+fn default_maximum_packet_bytes() -> Byte {
+    // The largest UDP payload that fits an Ethernet frame without
+    // fragmentation, DogStatsD's own default.
+    Byte::from_bytes(1432)
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+/// The relative weights of each kind of metric in the mix
+pub struct Kinds {
+    /// The weight of counters, `|c`, by default 1
+    #[serde(default = "default_weight")]
+    pub counter: u32,
+    /// The weight of gauges, `|g`, by default 1
+    #[serde(default = "default_weight")]
+    pub gauge: u32,
+    /// The weight of timings, `|ms`, by default 1
+    #[serde(default = "default_weight")]
+    pub timing: u32,
+    /// The weight of distributions, `|d`, by default 1. Distributions are
+    /// particular to DogStatsD.
+    #[serde(default = "default_weight")]
+    pub distribution: u32,
+}
+
+impl Default for Kinds {
+    fn default() -> Self {
+        Self {
+            counter: default_weight(),
+            gauge: default_weight(),
+            timing: default_weight(),
+            distribution: default_weight(),
+        }
+    }
+}
+
+/// The type suffix of each kind of metric, in the order of [`Kinds::weights`].
+const KIND_SUFFIXES: [&str; 4] = ["c", "g", "ms", "d"];
+
+impl Kinds {
+    fn weights(self) -> [u32; 4] {
+        [self.counter, self.gauge, self.timing, self.distribution]
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration of this generator.
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// The address for the target, must be a valid SocketAddr
+    pub addr: String,
+    /// The mix of metric kinds, each equally likely by default
+    #[serde(default)]
+    pub kinds: Kinds,
+    /// The number of distinct metric names metrics are drawn from, by default
+    /// 1000
+    #[serde(default = "default_metric_names")]
+    pub metric_names: NonZeroU32,
+    /// The fewest tags a metric carries, by default 0
+    #[serde(default)]
+    pub minimum_tags: u8,
+    /// The most tags a metric carries, by default 0
+    #[serde(default)]
+    pub maximum_tags: u8,
+    /// The number of distinct values each tag takes, by default 10
+    #[serde(default = "default_tag_values")]
+    pub tag_values: NonZeroU32,
+    /// The most metrics packed into one datagram, by default 1
+    #[serde(default = "default_metrics_per_packet")]
+    pub maximum_metrics_per_packet: NonZeroU16,
+    /// The largest datagram sent, by default 1432 bytes. A single metric
+    /// larger than this is sent alone.
+    #[serde(default = "default_maximum_packet_bytes")]
+    pub maximum_packet_bytes: byte_unit::Byte,
+    /// The bytes per second to send or receive from the target
+    pub bytes_per_second: byte_unit::Byte,
+    /// The maximum size in bytes of the cache of prebuilt messages
+    pub maximum_prebuild_cache_size_bytes: byte_unit::Byte,
+    /// The maximum number of bytes to send before this generator stops. If
+    /// unset the generator runs until the experiment ends.
+    pub maximum_bytes: Option<byte_unit::Byte>,
+    /// The maximum number of events -- metrics -- to send before this
+    /// generator stops. If unset the generator runs until the experiment ends.
+    pub maximum_events: Option<u64>,
+    /// The algorithm used to throttle output to `bytes_per_second`. Defaults to a
+    /// token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Whether to lock the block cache into RAM, touching each of its pages,
+    /// before the run starts. See mlock(2) for the limits on locked memory.
+    #[serde(default)]
+    pub lock_block_cache: bool,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+}
+
+#[derive(Debug)]
+/// Errors produced by [`Statsd`].
+pub enum Error {
+    /// Rate limiter has insuficient capacity for payload. Indicates a serious
+    /// bug.
+    Governor(InsufficientCapacity),
+    /// Creation of payload blocks failed.
+    Block(block::Error),
+    /// The kinds of metric all have zero weight.
+    Weights(WeightedError),
+    /// Wrapper for [`std::io::Error`].
+    Io(io::Error),
+}
+
+impl From<block::Error> for Error {
+    fn from(error: block::Error) -> Self {
+        Error::Block(error)
+    }
+}
+
+impl From<InsufficientCapacity> for Error {
+    fn from(error: InsufficientCapacity) -> Self {
+        Error::Governor(error)
+    }
+}
+
+impl From<WeightedError> for Error {
+    fn from(error: WeightedError) -> Self {
+        Error::Weights(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+/// Return one metric line, without its newline, drawn from `rng`.
+fn metric<R>(rng: &mut R, config: &Config, kinds: &WeightedIndex<u32>) -> String
+where
+    R: Rng,
+{
+    let name = rng.gen_range(0..config.metric_names.get());
+    let suffix = KIND_SUFFIXES[kinds.sample(rng)];
+    let mut line = match suffix {
+        "c" | "ms" => format!(
+            "lading.metric_{}:{}|{}",
+            name,
+            rng.gen_range(1..1000),
+            suffix
+        ),
+        _ => format!(
+            "lading.metric_{}:{:.3}|{}",
+            name,
+            rng.gen_range(0.0..1000.0),
+            suffix
+        ),
+    };
+    let tags = rng.gen_range(config.minimum_tags..=config.maximum_tags.max(config.minimum_tags));
+    for tag in 0..tags {
+        let value = rng.gen_range(0..config.tag_values.get());
+        let separator = if tag == 0 { "|#" } else { "," };
+        line.push_str(&format!("{}tag_{}:value_{}", separator, tag, value));
+    }
+    line
+}
+
+/// Return one datagram packed with metrics drawn from `rng`, and the number of
+/// metrics packed. The datagram is at most `maximum_packet_bytes` long unless
+/// its one metric is longer.
+fn packet<R>(
+    rng: &mut R,
+    config: &Config,
+    kinds: &WeightedIndex<u32>,
+    maximum_packet_bytes: usize,
+) -> (Vec<u8>, u64)
+where
+    R: Rng,
+{
+    let mut packet = Vec::with_capacity(maximum_packet_bytes);
+    let mut metrics = 0;
+    for _ in 0..config.maximum_metrics_per_packet.get() {
+        let line = metric(rng, config, kinds);
+        if metrics > 0 {
+            if packet.len() + 1 + line.len() > maximum_packet_bytes {
+                break;
+            }
+            packet.push(b'\n');
+        }
+        packet.extend_from_slice(line.as_bytes());
+        metrics += 1;
+    }
+    (packet, metrics)
+}
+
+/// Build the block cache of a generator configured by `config`, as
+/// [`Statsd::new`] does. Each block is one datagram.
+///
+/// # Errors
+///
+/// Function will return an error if the kinds of metric all have zero weight.
+///
+/// # Panics
+///
+/// Function will panic if user has passed zero values for any byte values.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::ptr_arg)]
+pub(crate) fn block_cache(
+    config: &Config,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<Block>, Error> {
+    let mut rng = StdRng::from_seed(config.seed);
+    let kinds = WeightedIndex::new(config.kinds.weights())?;
+    let maximum_packet_bytes = (config.maximum_packet_bytes.get_bytes() as usize).max(1);
+    let maximum_cache_bytes = config.maximum_prebuild_cache_size_bytes.get_bytes() as usize;
+    assert!(maximum_cache_bytes > 0, "bytes must be non-zero");
+
+    let mut block_cache = Vec::new();
+    let mut cache_bytes = 0;
+    while cache_bytes < maximum_cache_bytes {
+        let (bytes, metrics) = packet(&mut rng, config, &kinds, maximum_packet_bytes);
+        cache_bytes += bytes.len();
+        block_cache.push(Block::new(
+            NonZeroU32::new(bytes.len() as u32).expect("packets are never empty"),
+            metrics,
+            bytes,
+        ));
+    }
+    Summary::new(&block_cache).emit(labels);
+    gauge!("block_construction_complete", 1.0, labels);
+    Ok(block_cache)
+}
+
+#[derive(Debug)]
+/// The statsd generator.
+///
+/// This generator is responsible for sending statsd datagrams to the target.
+pub struct Statsd {
+    addr: SocketAddr,
+    throttle: Throttle,
+    block_cache: Vec<Block>,
+    metric_labels: Vec<(String, String)>,
+    budget: Budget,
+    shutdown: Shutdown,
+    meter: Meter,
+}
+
+impl Statsd {
+    /// Create a new [`Statsd`] instance
+    ///
+    /// # Errors
+    ///
+    /// Creation will fail if the block cache cannot be built.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if user has passed zero values for any byte
+    /// values. Sharp corners.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(
+        config: &Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
+        let bytes_per_second = NonZeroU32::new(config.bytes_per_second.get_bytes() as u32).unwrap();
+        let throttle = Throttle::new(config.throttle, bytes_per_second, pause);
+        let labels = vec![];
+        let mut block_cache = block_cache(config, &labels)?;
+        if config.lock_block_cache {
+            block::lock(&mut block_cache, &labels)?;
+        }
+
+        let addr = config
+            .addr
+            .to_socket_addrs()
+            .expect("could not convert to socket")
+            .next()
+            .unwrap();
+        Ok(Self {
+            addr,
+            block_cache,
+            throttle,
+            metric_labels: vec![address::label(addr.ip())],
+            budget: Budget::new(config.maximum_bytes, config.maximum_events),
+            shutdown,
+            meter,
+        })
+    }
+
+    /// Run [`Statsd`] to completion or until a shutdown signal is received.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if no local socket can be bound. Send
+    /// errors are recorded and the datagram dropped.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if underlying byte capacity is not available.
+    pub async fn spin(mut self) -> Result<(), Error> {
+        let labels = self.metric_labels;
+        let mut budget = self.budget;
+        let mut rate_window = RateWindow::new(RATE_WINDOW);
+
+        let bind_addr = if self.addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(bind_addr).await?;
+        let mut blocks = self.block_cache.iter().cycle();
+
+        loop {
+            let blk = blocks.next().unwrap();
+            let total_bytes = blk.total_bytes;
+
+            tokio::select! {
+                _ = self.throttle.wait(total_bytes, &labels) => {
+                    match socket.send_to(&blk.bytes, self.addr).await {
+                        Ok(_) => {
+                            counter!("packets_sent", 1, &labels);
+                            if record_block(blk, &labels, &self.meter, &mut rate_window, &mut budget) {
+                                return Ok(());
+                            }
+                        }
+                        Err(err) => {
+                            let mut error_labels = labels.clone();
+                            error_labels.push(("error".to_string(), io_error_kind(&err)));
+                            counter!("request_failure", 1, &error_labels);
+                        }
+                    }
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(());
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::{NonZeroU16, NonZeroU32};
+
+    use byte_unit::Byte;
+    use proptest::prelude::*;
+    use rand::{distributions::WeightedIndex, rngs::StdRng, SeedableRng};
+
+    use super::{packet, Config, Kinds};
+
+    // A datagram packs at most the configured metrics, each carrying its
+    // configured tags, and is no larger than the maximum unless it holds one
+    // metric.
+    proptest! {
+        #[test]
+        fn packet_shape(seed: u64, minimum_tags in 0..4_u8, maximum_tags in 0..8_u8,
+                        metrics_per_packet in 1..64_u16, maximum_packet_bytes in 1..2048_usize) {
+            let config = Config {
+                seed: [0; 32],
+                addr: "127.0.0.1:8125".to_string(),
+                kinds: Kinds::default(),
+                metric_names: NonZeroU32::new(100).unwrap(),
+                minimum_tags,
+                maximum_tags,
+                tag_values: NonZeroU32::new(10).unwrap(),
+                maximum_metrics_per_packet: NonZeroU16::new(metrics_per_packet).unwrap(),
+                maximum_packet_bytes: Byte::from_bytes(maximum_packet_bytes as u128),
+                bytes_per_second: Byte::from_bytes(1),
+                maximum_prebuild_cache_size_bytes: Byte::from_bytes(1),
+                maximum_bytes: None,
+                maximum_events: None,
+                throttle: Default::default(),
+                lock_block_cache: false,
+                numa: None,
+            };
+            let kinds = WeightedIndex::new(config.kinds.weights()).unwrap();
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (bytes, metrics) = packet(&mut rng, &config, &kinds, maximum_packet_bytes);
+            let lines: Vec<&[u8]> = bytes.split(|b| *b == b'\n').collect();
+            prop_assert_eq!(lines.len() as u64, metrics);
+            prop_assert!(metrics >= 1 && metrics <= u64::from(metrics_per_packet));
+            prop_assert!(metrics == 1 || bytes.len() <= maximum_packet_bytes);
+            for line in lines {
+                let line = std::str::from_utf8(line).unwrap();
+                let tags = line.split_once("|#").map_or(0, |(_, tags)| tags.split(',').count());
+                prop_assert!(tags >= usize::from(minimum_tags));
+                prop_assert!(tags <= usize::from(maximum_tags.max(minimum_tags)));
+            }
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! The target stdin writing generator.
+//!
+//! Command line filters are benchmarked by piping data into them. This
+//! generator writes its block cache into the target's stdin at a fixed byte
+//! rate, the target's stdin piped by [`crate::target::Server`] when this
+//! generator is configured. Should the target be restarted its new stdin is
+//! written to. Once the generator's budget is spent the target's stdin is
+//! closed, so that the target sees the end of its input.
+//!
+//! A target has one stdin, so an experiment has at most one stdin generator.
+
+use std::{
+    io,
+    num::{NonZeroU32, NonZeroUsize},
+};
+
+use byte_unit::{Byte, ByteUnit};
+use governor::state::direct::InsufficientCapacity;
+use metrics::counter;
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Deserialize;
+use tokio::{
+    io::AsyncWriteExt,
+    time::{self, Duration},
+};
+use tracing::info;
+
+use crate::{
+    block::{self, chunk_bytes, Block},
+    control::Pause,
+    generator::{
+        common::{Budget, RateWindow, RATE_WINDOW},
+        tcp::{record_block, GeneratorVariant},
+        Meter,
+    },
+    numa, payload,
+    signals::Shutdown,
+    target,
+    telemetry::io_error_kind,
+    throttle::{self, Throttle},
+};
+
+/// The time between checks for the target's stdin while there is none, the
+/// target not yet started or restarting.
+const STDIN_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration of this generator.
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// The payload variant
+    pub variant: GeneratorVariant,
+    /// The bytes per second to write to the target's stdin
+    pub bytes_per_second: byte_unit::Byte,
+    /// The block sizes for messages to this target
+    pub block_sizes: Option<Vec<byte_unit::Byte>>,
+    /// The maximum size in bytes of the cache of prebuilt messages
+    pub maximum_prebuild_cache_size_bytes: byte_unit::Byte,
+    /// The maximum number of bytes to write before this generator closes the
+    /// target's stdin. If unset the generator runs until the experiment ends.
+    pub maximum_bytes: Option<byte_unit::Byte>,
+    /// The maximum number of events -- newline delimited lines -- to write
+    /// before this generator closes the target's stdin. If unset the generator
+    /// runs until the experiment ends.
+    pub maximum_events: Option<u64>,
+    /// The algorithm used to throttle output to `bytes_per_second`. Defaults to a
+    /// token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Limit on the size of each newline delimited event. If unset events are
+    /// as large as the payload makes them.
+    pub event_limit: Option<payload::EventLimit>,
+    /// Tuning for the shape of messages produced by the syslog5424 variant,
+    /// ignored by other variants.
+    #[serde(default)]
+    pub syslog5424: payload::Syslog5424Config,
+    /// Whether to lock the block cache into RAM, touching each of its pages,
+    /// before the run starts. See mlock(2) for the limits on locked memory.
+    #[serde(default)]
+    pub lock_block_cache: bool,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+}
+
+#[derive(Debug)]
+/// Errors produced by [`Stdin`].
+pub enum Error {
+    /// Rate limiter has insuficient capacity for payload. Indicates a serious
+    /// bug.
+    Governor(InsufficientCapacity),
+    /// Creation of payload blocks failed.
+    Block(block::Error),
+    /// Wrapper for [`std::io::Error`].
+    Io(io::Error),
+}
+
+impl From<block::Error> for Error {
+    fn from(error: block::Error) -> Self {
+        Error::Block(error)
+    }
+}
+
+impl From<InsufficientCapacity> for Error {
+    fn from(error: InsufficientCapacity) -> Self {
+        Error::Governor(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+/// Build the block cache of a generator configured by `config`, as
+/// [`Stdin::new`] does.
+///
+/// # Errors
+///
+/// Function will return an error if the configured block sizes cannot be
+/// chunked into the prebuild cache.
+///
+/// # Panics
+///
+/// Function will panic if user has passed zero values for any byte values.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::ptr_arg)]
+pub(crate) fn block_cache(
+    config: &Config,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<Block>, Error> {
+    let mut rng = StdRng::from_seed(config.seed);
+    let block_sizes: Vec<NonZeroUsize> = config
+        .block_sizes
+        .clone()
+        .unwrap_or_else(|| {
+            vec![
+                Byte::from_unit(1.0 / 32.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 16.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 8.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 4.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 2.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1_f64, ByteUnit::MB).unwrap(),
+            ]
+        })
+        .iter()
+        .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
+        .collect();
+    let block_chunks = chunk_bytes(
+        &mut rng,
+        NonZeroUsize::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as usize)
+            .expect("bytes must be non-zero"),
+        &block_sizes,
+    )?;
+    Ok(config.variant.block_cache(
+        &mut rng,
+        &block_chunks,
+        config.event_limit,
+        config.syslog5424,
+        labels,
+    ))
+}
+
+#[derive(Debug)]
+/// The target stdin generator.
+///
+/// This generator is responsible for writing into the stdin of the target.
+pub struct Stdin {
+    throttle: Throttle,
+    block_cache: Vec<Block>,
+    metric_labels: Vec<(String, String)>,
+    budget: Budget,
+    shutdown: Shutdown,
+    meter: Meter,
+}
+
+impl Stdin {
+    /// Create a new [`Stdin`] instance
+    ///
+    /// # Errors
+    ///
+    /// Creation will fail if the block cache cannot be built.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if user has passed zero values for any byte
+    /// values. Sharp corners.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(
+        config: &Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
+        let bytes_per_second = NonZeroU32::new(config.bytes_per_second.get_bytes() as u32).unwrap();
+        let throttle = Throttle::new(config.throttle, bytes_per_second, pause);
+        let labels = vec![];
+        let mut block_cache = block_cache(config, &labels)?;
+        if config.lock_block_cache {
+            block::lock(&mut block_cache, &labels)?;
+        }
+
+        Ok(Self {
+            block_cache,
+            throttle,
+            metric_labels: labels,
+            budget: Budget::new(config.maximum_bytes, config.maximum_events),
+            shutdown,
+            meter,
+        })
+    }
+
+    /// Run [`Stdin`] to completion or until a shutdown signal is received.
+    ///
+    /// # Errors
+    ///
+    /// None known. Write errors, the target exiting among them, are recorded
+    /// and the stdin of the restarted target, if it is restarted, written to.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if underlying byte capacity is not available.
+    pub async fn spin(mut self) -> Result<(), Error> {
+        let labels = self.metric_labels;
+        let mut budget = self.budget;
+        let mut rate_window = RateWindow::new(RATE_WINDOW);
+
+        let mut stdin = None;
+        let mut check = time::interval(STDIN_INTERVAL);
+        let mut blocks = self.block_cache.iter().cycle();
+
+        loop {
+            let blk = blocks.next().unwrap();
+            let total_bytes = blk.total_bytes;
+
+            tokio::select! {
+                _ = check.tick(), if stdin.is_none() => {
+                    stdin = target::take_stdin();
+                }
+                _ = self.throttle.wait(total_bytes, &labels), if stdin.is_some() => {
+                    let pipe = stdin.as_mut().unwrap();
+                    match pipe.write_all(&blk.bytes).await {
+                        Ok(()) => {
+                            if record_block(blk, &labels, &self.meter, &mut rate_window, &mut budget) {
+                                info!("budget spent, closing target stdin");
+                                // Dropping the pipe closes it, the target
+                                // reading to the end of its input.
+                                drop(stdin);
+                                return Ok(());
+                            }
+                        }
+                        Err(err) => {
+                            let mut error_labels = labels.clone();
+                            error_labels.push(("error".to_string(), io_error_kind(&err)));
+                            counter!("request_failure", 1, &error_labels);
+                            counter!("stdin_closed", 1, &labels);
+                            info!("target stdin closed: {}", err);
+                            stdin = None;
+                        }
+                    }
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(());
+                },
+            }
+        }
+    }
+}
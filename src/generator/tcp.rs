@@ -1,4 +1,19 @@
 //! The TCP protocol speaking generator.
+//!
+//! Blocks may be written with the io_uring backend, see [`crate::uring`], the
+//! generator then running on its own thread. Connections may instead be
+//! encrypted, see [`crate::generator::tls`], and messages octet counted rather
+//! than newline delimited, together making syslog over TLS per RFC 5425.
+//!
+//! The fluent variants may send heartbeats on an idle connection, Forward mode
+//! messages of tag `heartbeat` carrying no entries, so that a target's idle
+//! timeout does not close it between blocks.
+//!
+//! A target may half-close a connection, shutting down its side while the
+//! generator's side remains open for writing. The epoll backend watches for
+//! this, counting it as `connection_half_closed` apart from hard failures, and
+//! handles the connection per [`HalfClose`]. Whatever the target sends back is
+//! read and discarded, see [`Drain`].
 
 use std::{
     net::{SocketAddr, ToSocketAddrs},
@@ -7,24 +22,34 @@ use std::{
 };
 
 use byte_unit::{Byte, ByteUnit};
-use governor::{
-    clock, state,
-    state::direct::{self, InsufficientCapacity},
-    Quota, RateLimiter,
-};
-use metrics::counter;
+use governor::state::direct::InsufficientCapacity;
+use metrics::{counter, gauge, histogram};
 use rand::{rngs::StdRng, SeedableRng};
 use serde::Deserialize;
-use tokio::{io::AsyncWriteExt, net::TcpStream};
+use tokio::{
+    io::{self, AsyncWriteExt, WriteHalf},
+    net::{tcp::OwnedWriteHalf, TcpStream},
+    time::Instant,
+};
+use tokio_rustls::client::TlsStream;
 use tracing::info;
 
 use crate::{
+    address,
     block::{self, chunk_bytes, construct_block_cache, Block},
-    payload,
+    control::Pause,
+    generator::{
+        common::{Budget, ChunkIds, Drain, Heartbeat, RateWindow, Responses, RATE_WINDOW},
+        tls, Meter,
+    },
+    numa, payload,
     signals::Shutdown,
+    telemetry::io_error_kind,
+    throttle::{self, Throttle},
+    uring::{self, Backend},
 };
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 /// Configuration of this generator.
 pub struct Config {
     /// The seed for random operations against this target
@@ -39,6 +64,101 @@ pub struct Config {
     pub block_sizes: Option<Vec<byte_unit::Byte>>,
     /// The maximum size in bytes of the cache of prebuilt messages
     pub maximum_prebuild_cache_size_bytes: byte_unit::Byte,
+    /// The maximum number of bytes to send before this generator stops. If
+    /// unset the generator runs until the experiment ends.
+    pub maximum_bytes: Option<byte_unit::Byte>,
+    /// The maximum number of events -- newline delimited lines -- to send
+    /// before this generator stops. If unset the generator runs until the
+    /// experiment ends.
+    pub maximum_events: Option<u64>,
+    /// The algorithm used to throttle output to `bytes_per_second`. Defaults to a
+    /// token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Ramp each new connection up to its share of `bytes_per_second` rather
+    /// than offering it all at once. If unset connections start at full rate.
+    pub slow_start: Option<throttle::SlowStart>,
+    /// Limit on the size of each newline delimited event. If unset events are
+    /// as large as the payload makes them.
+    pub event_limit: Option<payload::EventLimit>,
+    /// Tuning for the shape of messages produced by the syslog5424 variant,
+    /// ignored by other variants.
+    #[serde(default)]
+    pub syslog5424: payload::Syslog5424Config,
+    /// How messages are delimited on the wire. Octet counting is supported by
+    /// the newline delimited variants only.
+    #[serde(default)]
+    pub framing: Framing,
+    /// Encrypt connections to the target with TLS. Not supported by the
+    /// io_uring backend.
+    pub tls: Option<tls::Config>,
+    /// Send a heartbeat on the connection once it has been idle this many
+    /// seconds. Supported by the fluent variants only. If unset no heartbeats
+    /// are sent.
+    pub heartbeat_seconds: Option<NonZeroU32>,
+    /// What to do once the target half-closes the connection. Defaults to
+    /// reconnecting. Half-closes are detected by the epoll backend only.
+    #[serde(default)]
+    pub half_close: HalfClose,
+    /// The I/O backend blocks are written with
+    #[serde(default)]
+    pub backend: Backend,
+    /// Whether to lock the block cache into RAM, touching each of its pages,
+    /// before the run starts. See mlock(2) for the limits on locked memory.
+    #[serde(default)]
+    pub lock_block_cache: bool,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// How messages are delimited on the wire.
+pub enum Framing {
+    /// Each message is followed by a newline, as the payload makes it.
+    NewlineDelimited,
+    /// Each message is preceded by its length in bytes and a space, per RFC
+    /// 5425 and RFC 6587.
+    OctetCounted,
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Framing::NewlineDelimited
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// What to do once the target half-closes a connection.
+pub enum HalfClose {
+    /// Drop the connection and reconnect.
+    Reconnect,
+    /// Keep writing to the connection, as the target may still read it, until
+    /// a write fails. Failed writes are counted with `error` label
+    /// `half_closed`.
+    KeepWriting,
+}
+
+impl Default for HalfClose {
+    fn default() -> Self {
+        HalfClose::Reconnect
+    }
+}
+
+/// Frame each newline delimited message of `bytes` by octet counting: its
+/// length in bytes, a space, then the message without its newline.
+fn octet_counted(bytes: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(bytes.len() + bytes.len() / 32);
+    for message in bytes.split(|byte| *byte == b'\n') {
+        if message.is_empty() {
+            continue;
+        }
+        framed.extend_from_slice(message.len().to_string().as_bytes());
+        framed.push(b' ');
+        framed.extend_from_slice(message);
+    }
+    framed
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -47,6 +167,21 @@ pub struct Config {
 pub enum GeneratorVariant {
     /// Generates Fluent messages
     Fluent,
+    /// Generates Fluent PackedForward messages
+    FluentPackedForward {
+        /// The maximum size of the entries of a single message, before any
+        /// compression.
+        maximum_chunk_bytes: byte_unit::Byte,
+        /// Whether to gzip compress entries, producing CompressedPackedForward
+        /// messages.
+        #[serde(default)]
+        compressed: bool,
+        /// Whether each message requests acknowledgement from the target.
+        /// Acknowledgements are counted as `request_ok`, messages left
+        /// unacknowledged as `request_failure`.
+        #[serde(default)]
+        require_ack_response: bool,
+    },
     /// Generates syslog5424 messages
     Syslog5424,
     /// Generates a static, user supplied data
@@ -55,6 +190,101 @@ pub enum GeneratorVariant {
         /// assumed to be line-oriented but no other claim is made on the file.
         static_path: PathBuf,
     },
+    /// Generates points in InfluxDB line protocol, as accepted by InfluxDB's
+    /// TCP listeners and Telegraf's socket listener.
+    InfluxLineProtocol(payload::InfluxConfig),
+    /// Generates metrics in Graphite's plaintext protocol
+    Graphite(payload::GraphiteConfig),
+}
+
+impl GeneratorVariant {
+    /// Whether this variant's messages are newline delimited, and so may be
+    /// framed by octet counting. The fluent variants' msgpack holds newline
+    /// bytes within its messages.
+    fn newline_delimited(&self) -> bool {
+        !matches!(
+            self,
+            GeneratorVariant::Fluent | GeneratorVariant::FluentPackedForward { .. }
+        )
+    }
+
+    /// How the target's responses to this variant's messages are parsed.
+    pub(crate) fn responses(&self) -> Responses {
+        match self {
+            GeneratorVariant::FluentPackedForward {
+                require_ack_response: true,
+                ..
+            } => Responses::FluentAck,
+            _ => Responses::Lines,
+        }
+    }
+
+    /// Build a block cache of this variant's payloads, one block for each of
+    /// `block_chunks`. The shape of syslog5424 messages is tuned by
+    /// `syslog5424`.
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::ptr_arg)]
+    pub(crate) fn block_cache(
+        &self,
+        rng: &mut StdRng,
+        block_chunks: &[usize],
+        event_limit: Option<payload::EventLimit>,
+        syslog5424: payload::Syslog5424Config,
+        labels: &Vec<(String, String)>,
+    ) -> Vec<Block> {
+        match self {
+            GeneratorVariant::Syslog5424 => construct_block_cache(
+                rng,
+                &payload::Syslog5424::new(syslog5424),
+                block_chunks,
+                event_limit,
+                labels,
+            ),
+            GeneratorVariant::Fluent => construct_block_cache(
+                rng,
+                &payload::Fluent::default(),
+                block_chunks,
+                event_limit,
+                labels,
+            ),
+            GeneratorVariant::FluentPackedForward {
+                maximum_chunk_bytes,
+                compressed,
+                require_ack_response,
+            } => construct_block_cache(
+                rng,
+                &payload::Fluent::packed_forward(
+                    maximum_chunk_bytes.get_bytes() as usize,
+                    *compressed,
+                    *require_ack_response,
+                ),
+                block_chunks,
+                event_limit,
+                labels,
+            ),
+            GeneratorVariant::Static { static_path } => construct_block_cache(
+                rng,
+                &payload::Static::new(static_path),
+                block_chunks,
+                event_limit,
+                labels,
+            ),
+            GeneratorVariant::InfluxLineProtocol(config) => construct_block_cache(
+                rng,
+                &payload::Influx::new(*config),
+                block_chunks,
+                event_limit,
+                labels,
+            ),
+            GeneratorVariant::Graphite(config) => construct_block_cache(
+                rng,
+                &payload::Graphite::new(*config),
+                block_chunks,
+                event_limit,
+                labels,
+            ),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -65,6 +295,21 @@ pub enum Error {
     Governor(InsufficientCapacity),
     /// Creation of payload blocks failed.
     Block(block::Error),
+    /// Wrapper for [`crate::uring::Error`].
+    Uring(uring::Error),
+    /// Wrapper for [`crate::generator::tls::Error`].
+    Tls(tls::Error),
+    /// Heartbeats are configured for a variant that has none.
+    HeartbeatUnsupported,
+    /// Octet counted framing is configured for a variant whose messages are
+    /// not newline delimited.
+    FramingUnsupported,
+}
+
+impl From<tls::Error> for Error {
+    fn from(error: tls::Error) -> Self {
+        Error::Tls(error)
+    }
 }
 
 impl From<block::Error> for Error {
@@ -79,16 +324,209 @@ impl From<InsufficientCapacity> for Error {
     }
 }
 
+/// Build the block cache of a generator configured by `config`, as
+/// [`Tcp::new`] does.
+///
+/// # Errors
+///
+/// Function will return an error if the configured block sizes cannot be
+/// chunked into the prebuild cache.
+///
+/// # Panics
+///
+/// Function will panic if user has passed zero values for any byte values.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::ptr_arg)]
+pub(crate) fn block_cache(
+    config: &Config,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<Block>, Error> {
+    let mut rng = StdRng::from_seed(config.seed);
+    let block_sizes: Vec<NonZeroUsize> = config
+        .block_sizes
+        .clone()
+        .unwrap_or_else(|| {
+            vec![
+                Byte::from_unit(1.0 / 32.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 16.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 8.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 4.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 2.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1_f64, ByteUnit::MB).unwrap(),
+                Byte::from_unit(2_f64, ByteUnit::MB).unwrap(),
+                Byte::from_unit(4_f64, ByteUnit::MB).unwrap(),
+            ]
+        })
+        .iter()
+        .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
+        .collect();
+    let block_chunks = chunk_bytes(
+        &mut rng,
+        NonZeroUsize::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as usize)
+            .expect("bytes must be non-zero"),
+        &block_sizes,
+    )?;
+    let mut block_cache = config.variant.block_cache(
+        &mut rng,
+        &block_chunks,
+        config.event_limit,
+        config.syslog5424,
+        labels,
+    );
+    if config.framing == Framing::OctetCounted {
+        for blk in &mut block_cache {
+            blk.bytes = octet_counted(&blk.bytes);
+            blk.total_bytes =
+                NonZeroU32::new(blk.bytes.len() as u32).expect("blocks are never empty");
+        }
+    }
+    Ok(block_cache)
+}
+
+/// Record the time a connection to the target took to establish, since
+/// `start`. A target whose accept queue is saturated is slow to connect to
+/// long before its throughput degrades.
+#[allow(clippy::ptr_arg)]
+fn record_connect(start: Instant, labels: &Vec<(String, String)>) -> Instant {
+    let connected = Instant::now();
+    histogram!(
+        "connect_seconds",
+        connected.duration_since(start).as_secs_f64(),
+        labels
+    );
+    connected
+}
+
+/// Record the time from a connection's establishment at `connected` to its
+/// first successful write.
+#[allow(clippy::ptr_arg)]
+fn record_first_write(connected: Instant, labels: &Vec<(String, String)>) {
+    histogram!(
+        "first_write_seconds",
+        connected.elapsed().as_secs_f64(),
+        labels
+    );
+}
+
+/// A fluent Forward mode message, `["heartbeat", []]`, carrying no entries.
+const FLUENT_HEARTBEAT: &[u8] = b"\x92\xa9heartbeat\x90";
+
+/// The write side of a connection.
+#[derive(Debug)]
+enum Writer {
+    Plain(OwnedWriteHalf),
+    Tls(WriteHalf<TlsStream<TcpStream>>),
+}
+
+/// A connection to the target, encrypted if TLS is configured. Whatever the
+/// target sends back is drained.
+#[derive(Debug)]
+struct Connection {
+    writer: Writer,
+    drain: Drain,
+    /// When the connection was established, until its first successful write.
+    connected: Option<Instant>,
+    /// Whether the target has closed its side of the connection.
+    half_closed: bool,
+}
+
+impl Connection {
+    /// Connect to `addr`, opening a TLS session over the connection if
+    /// `tls` is set. The target's responses are parsed per `responses`.
+    #[allow(clippy::ptr_arg)]
+    async fn open(
+        addr: SocketAddr,
+        tls: Option<&tls::Connector>,
+        responses: Responses,
+        labels: &Vec<(String, String)>,
+    ) -> io::Result<Self> {
+        let start = Instant::now();
+        let stream = TcpStream::connect(addr).await?;
+        let connected = record_connect(start, labels);
+        let (writer, drain) = match tls {
+            Some(connector) => {
+                let (reader, writer) = io::split(connector.connect(stream).await?);
+                (
+                    Writer::Tls(writer),
+                    Drain::spawn(reader, responses, labels.clone()),
+                )
+            }
+            None => {
+                let (reader, writer) = stream.into_split();
+                (
+                    Writer::Plain(writer),
+                    Drain::spawn(reader, responses, labels.clone()),
+                )
+            }
+        };
+        Ok(Self {
+            writer,
+            drain,
+            connected: Some(connected),
+            half_closed: false,
+        })
+    }
+
+    /// The kind of `err`, a failed write, suitable as a label value.
+    fn error_kind(&self, err: &io::Error) -> String {
+        if self.half_closed {
+            "half_closed".to_string()
+        } else {
+            io_error_kind(err)
+        }
+    }
+
+    /// Write `bytes` without recording the connection's first write, as a
+    /// heartbeat is.
+    async fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match &mut self.writer {
+            Writer::Plain(writer) => writer.write_all(bytes).await?,
+            Writer::Tls(writer) => {
+                writer.write_all(bytes).await?;
+                // The session buffers records until flushed.
+                writer.flush().await?;
+            }
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::ptr_arg)]
+    async fn write_all(&mut self, bytes: &[u8], labels: &Vec<(String, String)>) -> io::Result<()> {
+        self.write(bytes).await?;
+        if let Some(connected) = self.connected.take() {
+            record_first_write(connected, labels);
+        }
+        Ok(())
+    }
+}
+
+/// Wait until the target closes its side of `connection`, see
+/// [`Drain::closed`]. Never completes if there is no connection.
+async fn peer_closed(connection: &mut Option<Connection>) -> io::Result<()> {
+    match connection {
+        Some(connection) => connection.drain.closed().await,
+        None => futures::future::pending().await,
+    }
+}
+
 #[derive(Debug)]
 /// The TCP generator.
 ///
 /// This generator is responsible for connecting to the target via TCP
 pub struct Tcp {
     addr: SocketAddr,
-    rate_limiter: RateLimiter<direct::NotKeyed, state::InMemoryState, clock::QuantaClock>,
+    backend: Backend,
+    tls: Option<tls::Connector>,
+    responses: Responses,
+    throttle: Throttle,
+    heartbeat: Heartbeat,
+    half_close: HalfClose,
     block_cache: Vec<Block>,
     metric_labels: Vec<(String, String)>,
+    budget: Budget,
     shutdown: Shutdown,
+    pause: Pause,
+    meter: Meter,
 }
 
 impl Tcp {
@@ -96,62 +534,35 @@ impl Tcp {
     ///
     /// # Errors
     ///
-    /// Creation will fail if the underlying governor capacity exceeds u32.
+    /// Creation will fail if the underlying governor capacity exceeds u32, if
+    /// TLS is configured and cannot be set up, or if heartbeats are configured
+    /// for a variant other than the fluent ones.
     ///
     /// # Panics
     ///
     /// Function will panic if user has passed zero values for any byte
     /// values. Sharp corners.
     #[allow(clippy::cast_possible_truncation)]
-    pub fn new(config: &Config, shutdown: Shutdown) -> Result<Self, Error> {
-        let mut rng = StdRng::from_seed(config.seed);
-        let block_sizes: Vec<NonZeroUsize> = config
-            .block_sizes
-            .clone()
-            .unwrap_or_else(|| {
-                vec![
-                    Byte::from_unit(1.0 / 32.0, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(1.0 / 16.0, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(1.0 / 8.0, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(1.0 / 4.0, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(1.0 / 2.0, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(1_f64, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(2_f64, ByteUnit::MB).unwrap(),
-                    Byte::from_unit(4_f64, ByteUnit::MB).unwrap(),
-                ]
-            })
-            .iter()
-            .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
-            .collect();
+    pub fn new(
+        config: &Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
         let bytes_per_second = NonZeroU32::new(config.bytes_per_second.get_bytes() as u32).unwrap();
-        let rate_limiter = RateLimiter::direct(Quota::per_second(bytes_per_second));
+        let throttle = Throttle::new(config.throttle, bytes_per_second, pause.clone())
+            .with_slow_start(config.slow_start, bytes_per_second, 1);
+        if config.heartbeat_seconds.is_some() && config.variant.newline_delimited() {
+            return Err(Error::HeartbeatUnsupported);
+        }
+        if config.framing == Framing::OctetCounted && !config.variant.newline_delimited() {
+            return Err(Error::FramingUnsupported);
+        }
         let labels = vec![];
-        let block_chunks = chunk_bytes(
-            &mut rng,
-            NonZeroUsize::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as usize)
-                .expect("bytes must be non-zero"),
-            &block_sizes,
-        )?;
-        let block_cache = match &config.variant {
-            GeneratorVariant::Syslog5424 => construct_block_cache(
-                &mut rng,
-                &payload::Syslog5424::default(),
-                &block_chunks,
-                &labels,
-            ),
-            GeneratorVariant::Fluent => construct_block_cache(
-                &mut rng,
-                &payload::Fluent::default(),
-                &block_chunks,
-                &labels,
-            ),
-            GeneratorVariant::Static { static_path } => construct_block_cache(
-                &mut rng,
-                &payload::Static::new(static_path),
-                &block_chunks,
-                &labels,
-            ),
-        };
+        let mut block_cache = block_cache(config, &labels)?;
+        if config.lock_block_cache {
+            block::lock(&mut block_cache, &labels)?;
+        }
 
         let addr = config
             .addr
@@ -159,12 +570,35 @@ impl Tcp {
             .expect("could not convert to socket")
             .next()
             .unwrap();
+        let tls = match &config.tls {
+            Some(_) if config.backend == Backend::IoUring => {
+                return Err(Error::Tls(tls::Error::UnsupportedBackend));
+            }
+            Some(tls_config) => {
+                let host = config
+                    .addr
+                    .rsplit_once(':')
+                    .map_or(config.addr.as_str(), |(host, _port)| host)
+                    .trim_start_matches('[')
+                    .trim_end_matches(']');
+                Some(tls::Connector::new(tls_config, host)?)
+            }
+            None => None,
+        };
         Ok(Self {
             addr,
+            backend: config.backend,
+            tls,
+            responses: config.variant.responses(),
             block_cache,
-            rate_limiter,
-            metric_labels: labels,
+            throttle,
+            heartbeat: Heartbeat::new(config.heartbeat_seconds, 1),
+            half_close: config.half_close,
+            metric_labels: vec![address::label(addr.ip())],
+            budget: Budget::new(config.maximum_bytes, config.maximum_events),
             shutdown,
+            pause,
+            meter,
         })
     }
 
@@ -172,53 +606,229 @@ impl Tcp {
     ///
     /// # Errors
     ///
-    /// Function will return an error when the TCP socket cannot be written to.
+    /// Function will return an error when the TCP socket cannot be written to,
+    /// or the configured backend is not available.
     ///
     /// # Panics
     ///
     /// Function will panic if underlying byte capacity is not available.
-    pub async fn spin(mut self) -> Result<(), Error> {
+    pub async fn spin(self) -> Result<(), Error> {
+        match self.backend {
+            Backend::Epoll => self.spin_epoll().await,
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            Backend::IoUring => uring::run("tcp-generator", move || self.spin_uring())
+                .await
+                .map_err(Error::Uring)?,
+            #[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+            Backend::IoUring => Err(Error::Uring(uring::Error::Unsupported)),
+        }
+    }
+
+    async fn spin_epoll(mut self) -> Result<(), Error> {
         let labels = self.metric_labels;
+        let mut budget = self.budget;
+        let mut rate_window = RateWindow::new(RATE_WINDOW);
 
         let mut connection = None;
         let mut blocks = self.block_cache.iter().cycle();
+        let mut chunk_ids = ChunkIds::new();
+        // A block whose chunk ids are stamped as it is sent is copied into
+        // this buffer rather than borrowed from the cache.
+        let mut stamped: Vec<u8> = Vec::new();
 
         loop {
             let blk = blocks.next().unwrap();
             let total_bytes = blk.total_bytes;
 
             tokio::select! {
-                conn = TcpStream::connect(self.addr), if connection.is_none() => {
+                // A generator paused with its connections closed does not
+                // reconnect until resumed.
+                conn = Connection::open(self.addr, self.tls.as_ref(), self.responses, &labels), if connection.is_none() && !self.pause.closes_connections() => {
                     match conn {
                         Ok(client) => {
+                            self.throttle.connected(0);
+                            self.heartbeat.reset(0);
                             connection = Some(client);
                         }
                         Err(err) => {
                             let mut error_labels = labels.clone();
-                            error_labels.push(("error".to_string(), err.to_string()));
+                            error_labels.push(("error".to_string(), io_error_kind(&err)));
                             counter!("connection_failure", 1, &error_labels);
                         }
                     }
                 }
-                _ = self.rate_limiter.until_n_ready(total_bytes), if connection.is_some() => {
+                _ = self.throttle.wait(total_bytes, &labels), if connection.is_some() => {
                     let mut client = connection.unwrap();
-                    match client.write_all(&blk.bytes).await {
+                    let (bytes, ids) = if self.responses == Responses::FluentAck {
+                        stamped.clear();
+                        stamped.extend_from_slice(&blk.bytes);
+                        let ids = chunk_ids.stamp(&mut stamped);
+                        (&stamped[..], ids)
+                    } else {
+                        (&blk.bytes[..], Vec::new())
+                    };
+                    client.drain.sent(&ids);
+                    match client.write_all(bytes, &labels).await {
                         Ok(()) => {
-                            counter!(
-                                "bytes_written",
-                                u64::from(blk.total_bytes.get()),
-                                &labels
-                            );
+                            self.heartbeat.reset(0);
                             connection = Some(client);
+                            if record_block(blk, &labels, &self.meter, &mut rate_window, &mut budget) {
+                                return Ok(());
+                            }
                         }
                         Err(err) => {
+                            client.drain.unsent(&ids);
                             let mut error_labels = labels.clone();
-                            error_labels.push(("error".to_string(), err.to_string()));
+                            error_labels.push(("error".to_string(), client.error_kind(&err)));
                             counter!("request_failure", 1, &error_labels);
                             connection = None;
                         }
                     }
                 }
+                _ = self.heartbeat.due(), if connection.is_some() => {
+                    let mut client = connection.take().unwrap();
+                    match client.write(FLUENT_HEARTBEAT).await {
+                        Ok(()) => {
+                            counter!("heartbeats_sent", 1, &labels);
+                            connection = Some(client);
+                        }
+                        Err(err) => {
+                            let mut error_labels = labels.clone();
+                            error_labels.push(("error".to_string(), client.error_kind(&err)));
+                            counter!("request_failure", 1, &error_labels);
+                        }
+                    }
+                    self.heartbeat.reset(0);
+                }
+                closed = peer_closed(&mut connection) => {
+                    match closed {
+                        Ok(()) => {
+                            counter!("connection_half_closed", 1, &labels);
+                            match self.half_close {
+                                HalfClose::Reconnect => connection = None,
+                                HalfClose::KeepWriting => {
+                                    if let Some(client) = connection.as_mut() {
+                                        client.half_closed = true;
+                                    }
+                                }
+                            }
+                        }
+                        Err(err) => {
+                            let mut error_labels = labels.clone();
+                            error_labels.push(("error".to_string(), io_error_kind(&err)));
+                            counter!("request_failure", 1, &error_labels);
+                            connection = None;
+                        }
+                    }
+                }
+                _ = self.pause.changed() => {
+                    if self.pause.closes_connections() {
+                        connection = None;
+                    }
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(());
+                },
+            }
+        }
+    }
+    /// As [`Tcp::spin_epoll`], with the io_uring backend.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    async fn spin_uring(mut self) -> Result<(), Error> {
+        let labels = self.metric_labels;
+        let mut budget = self.budget;
+        let mut rate_window = RateWindow::new(RATE_WINDOW);
+
+        let addr = self.addr;
+        let mut connection = None;
+        // When the connection was established, until its first successful
+        // write.
+        let mut connected = None;
+        let mut blocks = self.block_cache.iter().cycle();
+        let mut chunk_ids = ChunkIds::new();
+        // The ring must own the bytes it writes, so each block is copied into
+        // this buffer rather than borrowed from the cache.
+        let mut buf: Vec<u8> = Vec::new();
+
+        loop {
+            let blk = blocks.next().unwrap();
+            let total_bytes = blk.total_bytes;
+
+            tokio::select! {
+                // A generator paused with its connections closed does not
+                // reconnect until resumed.
+                conn = async {
+                    let start = Instant::now();
+                    tokio_uring::net::TcpStream::connect(addr).await.map(|client| (client, start))
+                }, if connection.is_none() && !self.pause.closes_connections() => {
+                    match conn {
+                        Ok((client, start)) => {
+                            connected = Some(record_connect(start, &labels));
+                            self.throttle.connected(0);
+                            self.heartbeat.reset(0);
+                            connection = Some(client);
+                        }
+                        Err(err) => {
+                            let mut error_labels = labels.clone();
+                            error_labels.push(("error".to_string(), io_error_kind(&err)));
+                            counter!("connection_failure", 1, &error_labels);
+                        }
+                    }
+                }
+                _ = self.throttle.wait(total_bytes, &labels), if connection.is_some() => {
+                    let client = connection.take().unwrap();
+                    buf.clear();
+                    buf.extend_from_slice(&blk.bytes);
+                    // Responses are not read on this backend, so the ids
+                    // stamped are not tracked.
+                    if self.responses == Responses::FluentAck {
+                        chunk_ids.stamp(&mut buf);
+                    }
+                    let (written, returned) = client.write_all(buf).await;
+                    buf = returned;
+                    match written {
+                        Ok(()) => {
+                            self.heartbeat.reset(0);
+                            connection = Some(client);
+                            if let Some(connected) = connected.take() {
+                                record_first_write(connected, &labels);
+                            }
+                            if record_block(blk, &labels, &self.meter, &mut rate_window, &mut budget) {
+                                return Ok(());
+                            }
+                        }
+                        Err(err) => {
+                            let mut error_labels = labels.clone();
+                            error_labels.push(("error".to_string(), io_error_kind(&err)));
+                            counter!("request_failure", 1, &error_labels);
+                        }
+                    }
+                }
+                _ = self.heartbeat.due(), if connection.is_some() => {
+                    let client = connection.take().unwrap();
+                    buf.clear();
+                    buf.extend_from_slice(FLUENT_HEARTBEAT);
+                    let (written, returned) = client.write_all(buf).await;
+                    buf = returned;
+                    match written {
+                        Ok(()) => {
+                            counter!("heartbeats_sent", 1, &labels);
+                            connection = Some(client);
+                        }
+                        Err(err) => {
+                            let mut error_labels = labels.clone();
+                            error_labels.push(("error".to_string(), io_error_kind(&err)));
+                            counter!("request_failure", 1, &error_labels);
+                        }
+                    }
+                    self.heartbeat.reset(0);
+                }
+                _ = self.pause.changed() => {
+                    if self.pause.closes_connections() {
+                        connection = None;
+                    }
+                }
                 _ = self.shutdown.recv() => {
                     info!("shutdown signal received");
                     return Ok(());
@@ -227,3 +837,148 @@ impl Tcp {
         }
     }
 }
+
+/// Record that `blk` was written. Returns true if the generator's budget is
+/// exhausted.
+#[allow(clippy::ptr_arg)]
+pub(super) fn record_block(
+    blk: &Block,
+    labels: &Vec<(String, String)>,
+    meter: &Meter,
+    rate_window: &mut RateWindow,
+    budget: &mut Budget,
+) -> bool {
+    let bytes = u64::from(blk.total_bytes.get());
+    counter!("bytes_written", bytes, labels);
+    meter.record(bytes);
+    rate_window.record(bytes, labels);
+    budget.record(bytes, blk.lines);
+    if budget.exhausted() {
+        info!("finite data limit reached, generator complete");
+        gauge!("generator_complete", 1.0, labels);
+        return true;
+    }
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroU32;
+
+    use proptest::prelude::*;
+    use tokio::{
+        io::AsyncWriteExt,
+        net::TcpListener,
+        time::{self, Duration, Instant},
+    };
+
+    use super::{octet_counted, peer_closed, Config, Connection, Error, Heartbeat, Responses, Tcp};
+    use crate::{control::Pause, generator::Meter, signals::Shutdown};
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    // A heartbeat is due on the connection idle longest, once it has been idle
+    // for the interval, and never if no interval is configured.
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(8))]
+        #[test]
+        fn heartbeat_due_on_idlest(seconds in 1..3600_u32, connections in 1..8_usize, idle in 0..8_usize) {
+            let idle = idle % connections;
+            block_on(async move {
+                time::pause();
+                let created = Instant::now();
+                let mut heartbeat = Heartbeat::new(NonZeroU32::new(seconds), connections);
+                for connection in (0..connections).filter(|connection| *connection != idle) {
+                    time::advance(Duration::from_millis(1)).await;
+                    heartbeat.reset(connection);
+                }
+                prop_assert_eq!(heartbeat.due().await, idle);
+                prop_assert_eq!(Instant::now() - created, Duration::from_secs(u64::from(seconds)));
+
+                let never = Heartbeat::new(None, connections);
+                prop_assert!(time::timeout(Duration::from_secs(86_400), never.due()).await.is_err());
+                Ok(())
+            })?;
+        }
+    }
+
+    // Once the target closes its side of the connection, whatever it sent
+    // first, the close is seen exactly once. Without a connection none is.
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(8))]
+        #[test]
+        fn peer_closed_once(sent in proptest::collection::vec(any::<u8>(), 0..4096)) {
+            block_on(async move {
+                prop_assert!(time::timeout(Duration::from_millis(10), peer_closed(&mut None)).await.is_err());
+
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let addr = listener.local_addr().unwrap();
+                let mut connection = Some(Connection::open(addr, None, Responses::Lines, &vec![]).await.unwrap());
+                let (mut peer, _) = listener.accept().await.unwrap();
+                peer.write_all(&sent).await.unwrap();
+                drop(peer);
+
+                let closed = time::timeout(Duration::from_secs(10), peer_closed(&mut connection)).await;
+                prop_assert!(matches!(closed, Ok(Ok(()))));
+                prop_assert!(time::timeout(Duration::from_millis(10), peer_closed(&mut connection)).await.is_err());
+                Ok(())
+            })?;
+        }
+    }
+
+    // Octet counting is refused for the fluent variants, whose msgpack messages
+    // hold newline bytes, and accepted for the line variants.
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(8))]
+        #[test]
+        fn octet_counted_line_variants_only(variant in prop_oneof![
+            Just("fluent"),
+            Just("{fluent_packed_forward: {maximum_chunk_bytes: 4 KiB}}"),
+            Just("syslog5424"),
+            Just("{graphite: {}}"),
+        ]) {
+            let config: Config = serde_yaml::from_str(&format!(
+                "seed: [{}]\naddr: 127.0.0.1:5140\nvariant: {}\nbytes_per_second: 1 MiB\nblock_sizes: [1 KiB]\nmaximum_prebuild_cache_size_bytes: 4 KiB\nframing: octet_counted\n",
+                vec!["0"; 32].join(", "),
+                variant
+            ))
+            .unwrap();
+            let tcp = Tcp::new(&config, Shutdown::new(), Pause::default(), Meter::default());
+            if variant.contains("fluent") {
+                prop_assert!(matches!(tcp, Err(Error::FramingUnsupported)));
+            } else {
+                prop_assert!(tcp.is_ok());
+            }
+        }
+    }
+
+    // Octet counted frames read back as the messages of the newline delimited
+    // input, in order.
+    proptest! {
+        #[test]
+        fn octet_counted_frames(messages in proptest::collection::vec("[a-z <>=]{1,64}", 0..32)) {
+            let mut bytes = Vec::new();
+            for message in &messages {
+                bytes.extend_from_slice(message.as_bytes());
+                bytes.push(b'\n');
+            }
+
+            let framed = octet_counted(&bytes);
+            let mut rest = &framed[..];
+            let mut read = Vec::new();
+            while !rest.is_empty() {
+                let space = rest.iter().position(|byte| *byte == b' ').unwrap();
+                let length: usize = std::str::from_utf8(&rest[..space]).unwrap().parse().unwrap();
+                read.push(String::from_utf8(rest[space + 1..space + 1 + length].to_vec()).unwrap());
+                rest = &rest[space + 1 + length..];
+            }
+            prop_assert_eq!(read, messages);
+        }
+    }
+}
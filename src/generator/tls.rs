@@ -0,0 +1,150 @@
+//! TLS transport for stream generators
+//!
+//! A target whose intake is TLS only, such as syslog over TLS per RFC 5425,
+//! cannot be loaded with plaintext. A generator configured with [`Config`]
+//! wraps each of its connections in a TLS client session, verifying the
+//! target's certificate against a configured certificate authority and, if
+//! configured, presenting a client certificate of its own.
+
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::Deserialize;
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{self, Certificate, ClientConfig, PrivateKey, RootCertStore, ServerName},
+    TlsConnector,
+};
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+/// Configuration of the TLS transport
+pub struct Config {
+    /// Path to the PEM encoded certificates of the authorities that sign the
+    /// target's certificate
+    pub ca_path: PathBuf,
+    /// The name the target's certificate is verified against. If unset the
+    /// host of the generator's address is used.
+    pub server_name: Option<String>,
+    /// Path to the PEM encoded client certificate chain to present to the
+    /// target, if any. Requires `client_key_path`.
+    pub client_certificate_path: Option<PathBuf>,
+    /// Path to the PEM encoded private key of the client certificate
+    pub client_key_path: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+/// Errors produced by [`Connector`]
+pub enum Error {
+    /// A certificate or key file could not be read.
+    Io(io::Error),
+    /// Wrapper around [`rustls::Error`].
+    Rustls(rustls::Error),
+    /// The certificate authority file holds no usable certificates.
+    NoCertificateAuthorities,
+    /// The client key file holds no private key.
+    NoPrivateKey,
+    /// Only one of the client certificate and key is configured.
+    IncompleteClientCertificate,
+    /// The server name is neither a DNS name nor an IP address.
+    InvalidServerName(String),
+    /// TLS is not supported by the configured I/O backend.
+    UnsupportedBackend,
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<rustls::Error> for Error {
+    fn from(error: rustls::Error) -> Self {
+        Error::Rustls(error)
+    }
+}
+
+fn certificates(path: &Path) -> Result<Vec<Vec<u8>>, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    Ok(rustls_pemfile::certs(&mut reader)?)
+}
+
+fn private_key(path: &Path) -> Result<PrivateKey, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(
+                rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::ECKey(key),
+            ) => return Ok(PrivateKey(key)),
+            Some(_) => {}
+            None => return Err(Error::NoPrivateKey),
+        }
+    }
+}
+
+#[derive(Clone)]
+/// Opens TLS client sessions over established connections.
+pub(crate) struct Connector {
+    connector: TlsConnector,
+    server_name: ServerName,
+}
+
+impl std::fmt::Debug for Connector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Connector")
+            .field("server_name", &self.server_name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Connector {
+    /// Create a new [`Connector`]. The target's certificate is verified
+    /// against the configured server name or, if unset, `host`.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the configured certificates or key
+    /// cannot be read or are not usable.
+    pub(crate) fn new(config: &Config, host: &str) -> Result<Self, Error> {
+        let mut roots = RootCertStore::empty();
+        let (valid, _invalid) = roots.add_parsable_certificates(&certificates(&config.ca_path)?);
+        if valid == 0 {
+            return Err(Error::NoCertificateAuthorities);
+        }
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+        let client_config = match (&config.client_certificate_path, &config.client_key_path) {
+            (Some(certificate_path), Some(key_path)) => builder.with_single_cert(
+                certificates(certificate_path)?
+                    .into_iter()
+                    .map(Certificate)
+                    .collect(),
+                private_key(key_path)?,
+            )?,
+            (None, None) => builder.with_no_client_auth(),
+            _ => return Err(Error::IncompleteClientCertificate),
+        };
+
+        let name = config.server_name.as_deref().unwrap_or(host);
+        let server_name =
+            ServerName::try_from(name).map_err(|_| Error::InvalidServerName(name.to_string()))?;
+        Ok(Self {
+            connector: TlsConnector::from(Arc::new(client_config)),
+            server_name,
+        })
+    }
+
+    /// Open a TLS client session over `stream`.
+    pub(crate) async fn connect(&self, stream: TcpStream) -> io::Result<TlsStream<TcpStream>> {
+        self.connector
+            .connect(self.server_name.clone(), stream)
+            .await
+    }
+}
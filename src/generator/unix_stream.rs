@@ -0,0 +1,375 @@
+//! The Unix domain stream socket speaking generator.
+//!
+//! Many agents on a host listen only on a Unix domain socket. This generator
+//! connects to one and streams its block cache into it, reconnecting on error,
+//! as the TCP generator does over the network. Whatever the target sends back
+//! is read and discarded.
+
+use std::{
+    num::{NonZeroU32, NonZeroUsize},
+    path::PathBuf,
+};
+
+use byte_unit::{Byte, ByteUnit};
+use governor::state::direct::InsufficientCapacity;
+use metrics::counter;
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Deserialize;
+use tokio::{io::AsyncWriteExt, net::unix::OwnedWriteHalf};
+use tracing::info;
+
+use crate::{
+    block::{self, chunk_bytes, Block},
+    control::Pause,
+    generator::{
+        common::{Backoff, Budget, ChunkIds, Drain, RateWindow, Responses, RATE_WINDOW},
+        tcp::{record_block, GeneratorVariant},
+        Meter,
+    },
+    numa, payload,
+    signals::Shutdown,
+    telemetry::io_error_kind,
+    throttle::{self, Throttle},
+};
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration of this generator.
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// The path of the target's Unix domain socket
+    pub path: PathBuf,
+    /// The payload variant
+    pub variant: GeneratorVariant,
+    /// The bytes per second to send or receive from the target
+    pub bytes_per_second: byte_unit::Byte,
+    /// The block sizes for messages to this target
+    pub block_sizes: Option<Vec<byte_unit::Byte>>,
+    /// The maximum size in bytes of the cache of prebuilt messages
+    pub maximum_prebuild_cache_size_bytes: byte_unit::Byte,
+    /// The maximum number of bytes to send before this generator stops. If
+    /// unset the generator runs until the experiment ends.
+    pub maximum_bytes: Option<byte_unit::Byte>,
+    /// The maximum number of events -- newline delimited lines -- to send
+    /// before this generator stops. If unset the generator runs until the
+    /// experiment ends.
+    pub maximum_events: Option<u64>,
+    /// The algorithm used to throttle output to `bytes_per_second`. Defaults to a
+    /// token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Ramp each new connection up to its share of `bytes_per_second` rather
+    /// than offering it all at once. If unset connections start at full rate.
+    pub slow_start: Option<throttle::SlowStart>,
+    /// Limit on the size of each newline delimited event. If unset events are
+    /// as large as the payload makes them.
+    pub event_limit: Option<payload::EventLimit>,
+    /// Tuning for the shape of messages produced by the syslog5424 variant,
+    /// ignored by other variants.
+    #[serde(default)]
+    pub syslog5424: payload::Syslog5424Config,
+    /// Whether to lock the block cache into RAM, touching each of its pages,
+    /// before the run starts. See mlock(2) for the limits on locked memory.
+    #[serde(default)]
+    pub lock_block_cache: bool,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+}
+
+#[derive(Debug)]
+/// Errors produced by [`UnixStream`].
+pub enum Error {
+    /// Rate limiter has insuficient capacity for payload. Indicates a serious
+    /// bug.
+    Governor(InsufficientCapacity),
+    /// Creation of payload blocks failed.
+    Block(block::Error),
+}
+
+impl From<block::Error> for Error {
+    fn from(error: block::Error) -> Self {
+        Error::Block(error)
+    }
+}
+
+impl From<InsufficientCapacity> for Error {
+    fn from(error: InsufficientCapacity) -> Self {
+        Error::Governor(error)
+    }
+}
+
+/// Build the block cache of a generator configured by `config`, as
+/// [`UnixStream::new`] does.
+///
+/// # Errors
+///
+/// Function will return an error if the configured block sizes cannot be
+/// chunked into the prebuild cache.
+///
+/// # Panics
+///
+/// Function will panic if user has passed zero values for any byte values.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::ptr_arg)]
+pub(crate) fn block_cache(
+    config: &Config,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<Block>, Error> {
+    let mut rng = StdRng::from_seed(config.seed);
+    let block_sizes: Vec<NonZeroUsize> = config
+        .block_sizes
+        .clone()
+        .unwrap_or_else(|| {
+            vec![
+                Byte::from_unit(1.0 / 32.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 16.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 8.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 4.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 2.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1_f64, ByteUnit::MB).unwrap(),
+            ]
+        })
+        .iter()
+        .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
+        .collect();
+    let block_chunks = chunk_bytes(
+        &mut rng,
+        NonZeroUsize::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as usize)
+            .expect("bytes must be non-zero"),
+        &block_sizes,
+    )?;
+    Ok(config.variant.block_cache(
+        &mut rng,
+        &block_chunks,
+        config.event_limit,
+        config.syslog5424,
+        labels,
+    ))
+}
+
+/// A connection to the target, whatever the target sends back drained until
+/// the connection is dropped.
+#[derive(Debug)]
+struct Connection {
+    writer: OwnedWriteHalf,
+    drain: Drain,
+}
+
+#[derive(Debug)]
+/// The Unix domain stream socket generator.
+///
+/// This generator is responsible for connecting to the target via a Unix
+/// domain stream socket.
+pub struct UnixStream {
+    path: PathBuf,
+    responses: Responses,
+    throttle: Throttle,
+    block_cache: Vec<Block>,
+    metric_labels: Vec<(String, String)>,
+    budget: Budget,
+    shutdown: Shutdown,
+    pause: Pause,
+    meter: Meter,
+}
+
+impl UnixStream {
+    /// Create a new [`UnixStream`] instance
+    ///
+    /// # Errors
+    ///
+    /// Creation will fail if the underlying governor capacity exceeds u32.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if user has passed zero values for any byte
+    /// values. Sharp corners.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(
+        config: &Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
+        let bytes_per_second = NonZeroU32::new(config.bytes_per_second.get_bytes() as u32).unwrap();
+        let throttle = Throttle::new(config.throttle, bytes_per_second, pause.clone())
+            .with_slow_start(config.slow_start, bytes_per_second, 1);
+        let labels = vec![];
+        let mut block_cache = block_cache(config, &labels)?;
+        if config.lock_block_cache {
+            block::lock(&mut block_cache, &labels)?;
+        }
+
+        Ok(Self {
+            path: config.path.clone(),
+            responses: config.variant.responses(),
+            block_cache,
+            throttle,
+            metric_labels: labels,
+            budget: Budget::new(config.maximum_bytes, config.maximum_events),
+            shutdown,
+            pause,
+            meter,
+        })
+    }
+
+    /// Run [`UnixStream`] to completion or until a shutdown signal is received.
+    ///
+    /// # Errors
+    ///
+    /// None known, write errors are recorded and the connection re-made.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if underlying byte capacity is not available.
+    pub async fn spin(mut self) -> Result<(), Error> {
+        let labels = self.metric_labels;
+        let mut budget = self.budget;
+        let mut rate_window = RateWindow::new(RATE_WINDOW);
+
+        let mut connection = None;
+        let mut backoff = Backoff::default();
+        let mut blocks = self.block_cache.iter().cycle();
+        let mut chunk_ids = ChunkIds::new();
+        // A block whose chunk ids are stamped as it is sent is copied into
+        // this buffer rather than borrowed from the cache.
+        let mut stamped: Vec<u8> = Vec::new();
+
+        loop {
+            let blk = blocks.next().unwrap();
+            let total_bytes = blk.total_bytes;
+
+            tokio::select! {
+                // A generator paused with its connections closed does not
+                // reconnect until resumed.
+                conn = async {
+                    backoff.ready().await;
+                    tokio::net::UnixStream::connect(&self.path).await
+                }, if connection.is_none() && !self.pause.closes_connections() => {
+                    match conn {
+                        Ok(client) => {
+                            backoff.succeeded();
+                            self.throttle.connected(0);
+                            let (reader, writer) = client.into_split();
+                            connection = Some(Connection {
+                                writer,
+                                drain: Drain::spawn(reader, self.responses, labels.clone()),
+                            });
+                        }
+                        Err(err) => {
+                            backoff.failed();
+                            let mut error_labels = labels.clone();
+                            error_labels.push(("error".to_string(), io_error_kind(&err)));
+                            counter!("connection_failure", 1, &error_labels);
+                        }
+                    }
+                }
+                _ = self.throttle.wait(total_bytes, &labels), if connection.is_some() => {
+                    let mut client = connection.unwrap();
+                    let (bytes, ids) = if self.responses == Responses::FluentAck {
+                        stamped.clear();
+                        stamped.extend_from_slice(&blk.bytes);
+                        let ids = chunk_ids.stamp(&mut stamped);
+                        (&stamped[..], ids)
+                    } else {
+                        (&blk.bytes[..], Vec::new())
+                    };
+                    client.drain.sent(&ids);
+                    match client.writer.write_all(bytes).await {
+                        Ok(()) => {
+                            connection = Some(client);
+                            if record_block(blk, &labels, &self.meter, &mut rate_window, &mut budget) {
+                                return Ok(());
+                            }
+                        }
+                        Err(err) => {
+                            client.drain.unsent(&ids);
+                            let mut error_labels = labels.clone();
+                            error_labels.push(("error".to_string(), io_error_kind(&err)));
+                            counter!("request_failure", 1, &error_labels);
+                            connection = None;
+                        }
+                    }
+                }
+                _ = self.pause.changed() => {
+                    if self.pause.closes_connections() {
+                        connection = None;
+                    }
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(());
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use byte_unit::Byte;
+    use proptest::prelude::*;
+    use tokio::io::AsyncReadExt;
+
+    use super::{Config, UnixStream};
+    use crate::{
+        control::Pause,
+        generator::{tcp::GeneratorVariant, Meter},
+        signals::Shutdown,
+    };
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    // A generator started before its target listens retries, backing off, and
+    // sends its whole budget once the target is up.
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(8))]
+        #[test]
+        fn connects_once_target_listens(seed: [u8; 32], listen_after_milliseconds in 0_u64..200) {
+            block_on(async move {
+                let path = std::env::temp_dir().join(format!(
+                    "lading-unix-stream-test-{}-{}.sock",
+                    std::process::id(),
+                    listen_after_milliseconds
+                ));
+                let _res = std::fs::remove_file(&path);
+                let maximum_bytes = 64 * 1024;
+                let config = Config {
+                    seed,
+                    path: path.clone(),
+                    variant: GeneratorVariant::Syslog5424,
+                    bytes_per_second: Byte::from_bytes(1024 * 1024),
+                    block_sizes: Some(vec![Byte::from_bytes(1024)]),
+                    maximum_prebuild_cache_size_bytes: Byte::from_bytes(16 * 1024),
+                    maximum_bytes: Some(Byte::from_bytes(maximum_bytes)),
+                    maximum_events: None,
+                    throttle: Default::default(),
+                    slow_start: None,
+                    event_limit: None,
+                    syslog5424: Default::default(),
+                    lock_block_cache: false,
+                    numa: None,
+                };
+                let generator = UnixStream::new(&config, Shutdown::new(), Pause::default(), Meter::default()).unwrap();
+                let spin = tokio::spawn(generator.spin());
+
+                tokio::time::sleep(Duration::from_millis(listen_after_milliseconds)).await;
+                let listener = tokio::net::UnixListener::bind(&path).unwrap();
+                let (mut stream, _) = listener.accept().await.unwrap();
+                spin.await.unwrap().unwrap();
+                let mut received = Vec::new();
+                stream.read_to_end(&mut received).await.unwrap();
+                std::fs::remove_file(&path).unwrap();
+                prop_assert!(received.len() as u128 >= maximum_bytes);
+                Ok(())
+            })?;
+        }
+    }
+}
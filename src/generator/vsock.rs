@@ -0,0 +1,273 @@
+//! The vsock speaking generator.
+//!
+//! Targets run inside a virtual machine may be reached from the host only over
+//! vsock, see [`crate::vsock`]. This generator connects to a port of the
+//! target's VM and streams its block cache into it, reconnecting on error, as
+//! the TCP generator does over the network. Nothing the target sends back is
+//! read.
+
+use std::num::{NonZeroU32, NonZeroUsize};
+
+use byte_unit::{Byte, ByteUnit};
+use governor::state::direct::InsufficientCapacity;
+use metrics::counter;
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{
+    block::{self, chunk_bytes, Block},
+    control::Pause,
+    generator::{
+        common::{Budget, RateWindow, RATE_WINDOW},
+        tcp::{record_block, GeneratorVariant},
+        Meter,
+    },
+    numa, payload,
+    signals::Shutdown,
+    telemetry::io_error_kind,
+    throttle::{self, Throttle},
+    vsock,
+};
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration of this generator.
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// The context identifier of the target's VM
+    pub cid: u32,
+    /// The vsock port the target listens on
+    pub port: u32,
+    /// The payload variant
+    pub variant: GeneratorVariant,
+    /// The bytes per second to send or receive from the target
+    pub bytes_per_second: byte_unit::Byte,
+    /// The block sizes for messages to this target
+    pub block_sizes: Option<Vec<byte_unit::Byte>>,
+    /// The maximum size in bytes of the cache of prebuilt messages
+    pub maximum_prebuild_cache_size_bytes: byte_unit::Byte,
+    /// The maximum number of bytes to send before this generator stops. If
+    /// unset the generator runs until the experiment ends.
+    pub maximum_bytes: Option<byte_unit::Byte>,
+    /// The maximum number of events -- newline delimited lines -- to send
+    /// before this generator stops. If unset the generator runs until the
+    /// experiment ends.
+    pub maximum_events: Option<u64>,
+    /// The algorithm used to throttle output to `bytes_per_second`. Defaults to a
+    /// token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Ramp each new connection up to its share of `bytes_per_second` rather
+    /// than offering it all at once. If unset connections start at full rate.
+    pub slow_start: Option<throttle::SlowStart>,
+    /// Limit on the size of each newline delimited event. If unset events are
+    /// as large as the payload makes them.
+    pub event_limit: Option<payload::EventLimit>,
+    /// Tuning for the shape of messages produced by the syslog5424 variant,
+    /// ignored by other variants.
+    #[serde(default)]
+    pub syslog5424: payload::Syslog5424Config,
+    /// Whether to lock the block cache into RAM, touching each of its pages,
+    /// before the run starts. See mlock(2) for the limits on locked memory.
+    #[serde(default)]
+    pub lock_block_cache: bool,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+}
+
+#[derive(Debug)]
+/// Errors produced by [`Vsock`].
+pub enum Error {
+    /// Rate limiter has insuficient capacity for payload. Indicates a serious
+    /// bug.
+    Governor(InsufficientCapacity),
+    /// Creation of payload blocks failed.
+    Block(block::Error),
+}
+
+impl From<block::Error> for Error {
+    fn from(error: block::Error) -> Self {
+        Error::Block(error)
+    }
+}
+
+impl From<InsufficientCapacity> for Error {
+    fn from(error: InsufficientCapacity) -> Self {
+        Error::Governor(error)
+    }
+}
+
+/// Build the block cache of a generator configured by `config`, as
+/// [`Vsock::new`] does.
+///
+/// # Errors
+///
+/// Function will return an error if the configured block sizes cannot be
+/// chunked into the prebuild cache.
+///
+/// # Panics
+///
+/// Function will panic if user has passed zero values for any byte values.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::ptr_arg)]
+pub(crate) fn block_cache(
+    config: &Config,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<Block>, Error> {
+    let mut rng = StdRng::from_seed(config.seed);
+    let block_sizes: Vec<NonZeroUsize> = config
+        .block_sizes
+        .clone()
+        .unwrap_or_else(|| {
+            vec![
+                Byte::from_unit(1.0 / 32.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 16.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 8.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 4.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 2.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1_f64, ByteUnit::MB).unwrap(),
+            ]
+        })
+        .iter()
+        .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
+        .collect();
+    let block_chunks = chunk_bytes(
+        &mut rng,
+        NonZeroUsize::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as usize)
+            .expect("bytes must be non-zero"),
+        &block_sizes,
+    )?;
+    Ok(config.variant.block_cache(
+        &mut rng,
+        &block_chunks,
+        config.event_limit,
+        config.syslog5424,
+        labels,
+    ))
+}
+
+#[derive(Debug)]
+/// The vsock generator.
+///
+/// This generator is responsible for connecting to the target via a vsock
+/// stream socket.
+pub struct Vsock {
+    cid: u32,
+    port: u32,
+    throttle: Throttle,
+    block_cache: Vec<Block>,
+    metric_labels: Vec<(String, String)>,
+    budget: Budget,
+    shutdown: Shutdown,
+    pause: Pause,
+    meter: Meter,
+}
+
+impl Vsock {
+    /// Create a new [`Vsock`] instance
+    ///
+    /// # Errors
+    ///
+    /// Creation will fail if the underlying governor capacity exceeds u32.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if user has passed zero values for any byte
+    /// values. Sharp corners.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(
+        config: &Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
+        let bytes_per_second = NonZeroU32::new(config.bytes_per_second.get_bytes() as u32).unwrap();
+        let throttle = Throttle::new(config.throttle, bytes_per_second, pause.clone())
+            .with_slow_start(config.slow_start, bytes_per_second, 1);
+        let labels = vec![vsock::label()];
+        let mut block_cache = block_cache(config, &labels)?;
+        if config.lock_block_cache {
+            block::lock(&mut block_cache, &labels)?;
+        }
+
+        Ok(Self {
+            cid: config.cid,
+            port: config.port,
+            block_cache,
+            throttle,
+            metric_labels: labels,
+            budget: Budget::new(config.maximum_bytes, config.maximum_events),
+            shutdown,
+            pause,
+            meter,
+        })
+    }
+
+    /// Run [`Vsock`] to completion or until a shutdown signal is received.
+    ///
+    /// # Errors
+    ///
+    /// None known, write errors are recorded and the connection re-made.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if underlying byte capacity is not available.
+    pub async fn spin(mut self) -> Result<(), Error> {
+        let labels = self.metric_labels;
+        let mut budget = self.budget;
+        let mut rate_window = RateWindow::new(RATE_WINDOW);
+
+        let mut connection = None;
+        let mut blocks = self.block_cache.iter().cycle();
+
+        loop {
+            let blk = blocks.next().unwrap();
+            let total_bytes = blk.total_bytes;
+
+            tokio::select! {
+                // A generator paused with its connections closed does not
+                // reconnect until resumed.
+                conn = vsock::connect(self.cid, self.port), if connection.is_none() && !self.pause.closes_connections() => {
+                    match conn {
+                        Ok(client) => {
+                            self.throttle.connected(0);
+                            connection = Some(client);
+                        }
+                        Err(err) => {
+                            let mut error_labels = labels.clone();
+                            error_labels.push(("error".to_string(), io_error_kind(&err)));
+                            counter!("connection_failure", 1, &error_labels);
+                        }
+                    }
+                }
+                _ = self.throttle.wait(total_bytes, &labels), if connection.is_some() => {
+                    let client = connection.unwrap();
+                    match vsock::write_all(&client, &blk.bytes).await {
+                        Ok(()) => {
+                            connection = Some(client);
+                            if record_block(blk, &labels, &self.meter, &mut rate_window, &mut budget) {
+                                return Ok(());
+                            }
+                        }
+                        Err(err) => {
+                            let mut error_labels = labels.clone();
+                            error_labels.push(("error".to_string(), io_error_kind(&err)));
+                            counter!("request_failure", 1, &error_labels);
+                            connection = None;
+                        }
+                    }
+                }
+                _ = self.pause.changed() => {
+                    if self.pause.closes_connections() {
+                        connection = None;
+                    }
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(());
+                },
+            }
+        }
+    }
+}
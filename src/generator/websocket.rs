@@ -0,0 +1,786 @@
+//! The WebSocket protocol speaking generator.
+//!
+//! Each block is sent as one WebSocket message, a text or binary frame, over
+//! one of `parallel_connections` connections in turn. Connections are made to
+//! a `ws://` URI, TLS is not supported, and re-made on error after a backoff.
+//! Blocks are framed as they are written, straight from the cache. What the
+//! target sends back is read and discarded, its pings answered with pongs. A
+//! connection left idle may be kept open with ping frames.
+
+use std::{
+    io,
+    num::{NonZeroU32, NonZeroUsize},
+    str,
+};
+
+use byte_unit::{Byte, ByteUnit};
+use governor::state::direct::InsufficientCapacity;
+use hyper::Uri;
+use metrics::counter;
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{tcp::OwnedWriteHalf, TcpStream},
+    sync::mpsc,
+    task::JoinHandle,
+};
+use tokio_tungstenite::tungstenite::handshake::{client::generate_key, derive_accept_key};
+use tracing::info;
+
+use crate::{
+    address,
+    block::{self, chunk_bytes, Block},
+    control::Pause,
+    generator::{
+        common::{Backoff, Budget, Heartbeat, RateWindow, RATE_WINDOW},
+        http::Variant,
+        tcp::record_block,
+        Meter,
+    },
+    numa, payload,
+    signals::Shutdown,
+    telemetry::io_error_kind,
+    throttle::{self, Throttle},
+};
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// The kind of WebSocket frame blocks are sent in
+pub enum Frame {
+    /// Text frames. Every block must be valid UTF-8.
+    Text,
+    /// Binary frames
+    Binary,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration of this generator.
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// The URI of the target, for instance `ws://localhost:8080/ingest`
+    #[serde(with = "http_serde::uri")]
+    pub target_uri: Uri,
+    /// The payload generator to use for this target
+    pub variant: Variant,
+    /// The kind of frame blocks are sent in
+    pub frame: Frame,
+    /// The bytes per second to send or receive from the target
+    pub bytes_per_second: byte_unit::Byte,
+    /// The block sizes for messages to this target
+    pub block_sizes: Option<Vec<byte_unit::Byte>>,
+    /// The maximum size in bytes of the cache of prebuilt messages
+    pub maximum_prebuild_cache_size_bytes: byte_unit::Byte,
+    /// The total number of connections blocks are sent over, in turn
+    pub parallel_connections: u16,
+    /// The maximum number of bytes to send before this generator stops. If
+    /// unset the generator runs until the experiment ends.
+    pub maximum_bytes: Option<byte_unit::Byte>,
+    /// The maximum number of events -- newline delimited lines -- to send
+    /// before this generator stops. If unset the generator runs until the
+    /// experiment ends.
+    pub maximum_events: Option<u64>,
+    /// The algorithm used to throttle output to `bytes_per_second`. Defaults to a
+    /// token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Ramp each new connection up to its share of `bytes_per_second` rather
+    /// than offering it all at once. If unset connections start at full rate.
+    pub slow_start: Option<throttle::SlowStart>,
+    /// Send a ping frame on each connection once it has been idle this many
+    /// seconds. If unset no pings are sent.
+    pub heartbeat_seconds: Option<NonZeroU32>,
+    /// Limit on the size of each newline delimited event. If unset events are
+    /// as large as the payload makes them.
+    pub event_limit: Option<payload::EventLimit>,
+    /// Whether to lock the block cache into RAM, touching each of its pages,
+    /// before the run starts. See mlock(2) for the limits on locked memory.
+    #[serde(default)]
+    pub lock_block_cache: bool,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+}
+
+#[derive(Debug)]
+/// Errors produced by [`Websocket`].
+pub enum Error {
+    /// Rate limiter has insuficient capacity for payload. Indicates a serious
+    /// bug.
+    Governor(InsufficientCapacity),
+    /// Creation of payload blocks failed.
+    Block(block::Error),
+    /// A block is not valid UTF-8 and cannot be sent in a text frame.
+    Utf8(str::Utf8Error),
+    /// The target URI is not a `ws://` URI with a host.
+    Uri,
+}
+
+impl From<block::Error> for Error {
+    fn from(error: block::Error) -> Self {
+        Error::Block(error)
+    }
+}
+
+impl From<InsufficientCapacity> for Error {
+    fn from(error: InsufficientCapacity) -> Self {
+        Error::Governor(error)
+    }
+}
+
+impl From<str::Utf8Error> for Error {
+    fn from(error: str::Utf8Error) -> Self {
+        Error::Utf8(error)
+    }
+}
+
+/// Build the block cache of a generator configured by `config`, as
+/// [`Websocket::new`] does.
+///
+/// # Errors
+///
+/// Function will return an error if the configured block sizes cannot be
+/// chunked into the prebuild cache.
+///
+/// # Panics
+///
+/// Function will panic if user has passed zero values for any byte values.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::ptr_arg)]
+pub(crate) fn block_cache(
+    config: &Config,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<Block>, Error> {
+    let mut rng = StdRng::from_seed(config.seed);
+    let block_sizes: Vec<NonZeroUsize> = config
+        .block_sizes
+        .clone()
+        .unwrap_or_else(|| {
+            vec![
+                Byte::from_unit(1.0 / 32.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 16.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 8.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 4.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1.0 / 2.0, ByteUnit::MB).unwrap(),
+                Byte::from_unit(1_f64, ByteUnit::MB).unwrap(),
+            ]
+        })
+        .iter()
+        .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
+        .collect();
+    let block_chunks = chunk_bytes(
+        &mut rng,
+        NonZeroUsize::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as usize)
+            .expect("bytes must be non-zero"),
+        &block_sizes,
+    )?;
+    Ok(config
+        .variant
+        .block_cache(&mut rng, &block_chunks, config.event_limit, labels))
+}
+
+/// The opcode of a text frame.
+const OPCODE_TEXT: u8 = 0x1;
+/// The opcode of a binary frame.
+const OPCODE_BINARY: u8 = 0x2;
+/// The opcode of a close frame.
+const OPCODE_CLOSE: u8 = 0x8;
+/// The opcode of a ping frame.
+const OPCODE_PING: u8 = 0x9;
+/// The opcode of a pong frame.
+const OPCODE_PONG: u8 = 0xa;
+
+/// The longest handshake response head read before the handshake is
+/// abandoned.
+const MAXIMUM_RESPONSE_HEAD: usize = 16_384;
+
+/// The reasons a connection to the target cannot be opened.
+#[derive(Debug)]
+enum ConnectError {
+    /// The TCP connection failed.
+    Io(io::Error),
+    /// The target answered the handshake with a status other than 101.
+    Status(String),
+    /// The target's handshake response is malformed or does not accept the
+    /// request.
+    Handshake,
+}
+
+impl From<io::Error> for ConnectError {
+    fn from(error: io::Error) -> Self {
+        ConnectError::Io(error)
+    }
+}
+
+impl ConnectError {
+    /// The label of this error, for telemetry.
+    fn kind(&self) -> String {
+        match self {
+            ConnectError::Io(err) => io_error_kind(err),
+            ConnectError::Status(status) => status.clone(),
+            ConnectError::Handshake => "protocol".to_string(),
+        }
+    }
+}
+
+/// Open a TCP connection to `uri` and complete the WebSocket opening
+/// handshake over it. Returns the stream and any bytes the target sent past
+/// its handshake response.
+async fn handshake(uri: &Uri) -> Result<(TcpStream, Vec<u8>), ConnectError> {
+    let host = uri.host().expect("target URIs are checked for a host");
+    let mut stream = TcpStream::connect((host, uri.port_u16().unwrap_or(80))).await?;
+    let key = generate_key();
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: Upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: {}\r\n\r\n",
+        uri.path_and_query().map_or("/", |path| path.as_str()),
+        uri.authority().map_or(host, |authority| authority.as_str()),
+        key
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = Vec::new();
+    let mut buf = [0; 1024];
+    let head = loop {
+        if let Some(end) = response.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+        if response.len() > MAXIMUM_RESPONSE_HEAD {
+            return Err(ConnectError::Handshake);
+        }
+        let read = stream.read(&mut buf).await?;
+        if read == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        response.extend_from_slice(&buf[..read]);
+    };
+    check_response(&response[..head], &key)?;
+    Ok((stream, response.split_off(head)))
+}
+
+/// Check that `head`, the head of the target's handshake response, accepts
+/// the handshake request made with `key`.
+fn check_response(head: &[u8], key: &str) -> Result<(), ConnectError> {
+    let head = str::from_utf8(head).map_err(|_| ConnectError::Handshake)?;
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .unwrap_or_default();
+    if status != "101" {
+        return Err(ConnectError::Status(status.to_string()));
+    }
+    let accept = derive_accept_key(key.as_bytes());
+    let accepted = lines
+        .filter_map(|line| line.split_once(':'))
+        .any(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("sec-websocket-accept") && value.trim() == accept
+        });
+    if accepted {
+        Ok(())
+    } else {
+        Err(ConnectError::Handshake)
+    }
+}
+
+/// The header of a final, masked frame of `opcode` carrying `length` bytes of
+/// payload.
+///
+/// Frames a client sends must be masked, the payload XORed with the header's
+/// masking key. The key here is zero, which leaves the payload unchanged, so
+/// that blocks are written straight from the cache rather than copied to be
+/// masked. Targets are not expected to rely on masking keys being
+/// unpredictable.
+#[allow(clippy::cast_possible_truncation)]
+fn frame_header(opcode: u8, length: usize) -> Vec<u8> {
+    let mut header = Vec::with_capacity(14);
+    header.push(0x80 | opcode);
+    match length {
+        0..=125 => header.push(0x80 | length as u8),
+        126..=0xffff => {
+            header.push(0x80 | 126);
+            header.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        _ => {
+            header.push(0x80 | 127);
+            header.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    header.extend_from_slice(&[0; 4]);
+    header
+}
+
+/// A control frame the target sent, see [`Frames`].
+#[derive(Debug, PartialEq, Eq)]
+enum Control {
+    /// A ping carrying `payload`, to be answered with a pong carrying it back.
+    Ping(Vec<u8>),
+    /// The target is closing the connection.
+    Close,
+}
+
+/// Parses the frames the target sends as they are read.
+///
+/// Control frames are parsed whole, their payloads no more than 125 bytes.
+/// Data frames may be of any size and are skipped over as read, never held.
+#[derive(Debug, Default)]
+struct Frames {
+    /// Bytes read but not yet parsed, a frame cut short by the last read.
+    pending: Vec<u8>,
+    /// Bytes of the current data frame's payload not yet read.
+    skip: usize,
+}
+
+impl Frames {
+    /// Parse `bytes`, read from the target, returning the control frames
+    /// completed by them in order.
+    fn feed(&mut self, bytes: &[u8]) -> Vec<Control> {
+        let skipped = self.skip.min(bytes.len());
+        self.skip -= skipped;
+        self.pending.extend_from_slice(&bytes[skipped..]);
+
+        let mut controls = Vec::new();
+        while self.skip == 0 {
+            let pending = &self.pending;
+            if pending.len() < 2 {
+                break;
+            }
+            let opcode = pending[0] & 0x0f;
+            let (length, mut offset) = match pending[1] & 0x7f {
+                126 if pending.len() >= 4 => {
+                    (u64::from(u16::from_be_bytes([pending[2], pending[3]])), 4)
+                }
+                127 if pending.len() >= 10 => {
+                    let mut length = [0; 8];
+                    length.copy_from_slice(&pending[2..10]);
+                    (u64::from_be_bytes(length), 10)
+                }
+                126 | 127 => break,
+                length => (u64::from(length), 2),
+            };
+            let mask = if pending[1] & 0x80 == 0 {
+                None
+            } else if pending.len() >= offset + 4 {
+                let mut mask = [0; 4];
+                mask.copy_from_slice(&pending[offset..offset + 4]);
+                offset += 4;
+                Some(mask)
+            } else {
+                break;
+            };
+            let length = usize::try_from(length).unwrap_or(usize::MAX);
+
+            if opcode & 0x8 == 0 {
+                // A data frame, skipped over.
+                let available = length.min(pending.len() - offset);
+                self.pending.drain(..offset + available);
+                self.skip = length - available;
+                continue;
+            }
+            if pending.len() - offset < length {
+                break;
+            }
+            let mut payload = pending[offset..offset + length].to_vec();
+            if let Some(mask) = mask {
+                for (idx, byte) in payload.iter_mut().enumerate() {
+                    *byte ^= mask[idx % 4];
+                }
+            }
+            self.pending.drain(..offset + length);
+            match opcode {
+                OPCODE_PING => controls.push(Control::Ping(payload)),
+                OPCODE_CLOSE => controls.push(Control::Close),
+                _ => {}
+            }
+        }
+        controls
+    }
+}
+
+/// What the reader of a connection reports, see [`Connection`].
+#[derive(Debug)]
+enum Event {
+    /// The target sent a ping on `connection`.
+    Ping { connection: u64, payload: Vec<u8> },
+    /// The target closed `connection`, or reading from it failed.
+    Closed { connection: u64 },
+}
+
+/// A connection to the target, its opening handshake complete.
+///
+/// What the target sends is read by a task of its own, lest the target's send
+/// buffer fill. The task reports pings, for the generator to answer with
+/// pongs, and the connection closing. It is stopped when the connection is
+/// dropped.
+#[derive(Debug)]
+struct Connection {
+    /// Identifies the connection in the [`Event`]s its reader reports.
+    id: u64,
+    writer: OwnedWriteHalf,
+    reader: JoinHandle<()>,
+}
+
+impl Connection {
+    /// Wrap `stream`, `leftover` the bytes the target sent past its handshake
+    /// response, reporting to `events` as `id`.
+    fn new(
+        stream: TcpStream,
+        leftover: Vec<u8>,
+        id: u64,
+        events: mpsc::Sender<Event>,
+        labels: Vec<(String, String)>,
+    ) -> Self {
+        let (mut reader, writer) = stream.into_split();
+        let reader = tokio::spawn(async move {
+            let mut frames = Frames::default();
+            let mut controls = frames.feed(&leftover);
+            let mut buf = vec![0; 8192];
+            loop {
+                for control in controls {
+                    let event = match control {
+                        Control::Ping(payload) => Event::Ping {
+                            connection: id,
+                            payload,
+                        },
+                        Control::Close => Event::Closed { connection: id },
+                    };
+                    let closed = matches!(event, Event::Closed { .. });
+                    if events.send(event).await.is_err() || closed {
+                        return;
+                    }
+                }
+                match reader.read(&mut buf).await {
+                    Ok(read) if read > 0 => {
+                        counter!("bytes_read", read as u64, &labels);
+                        controls = frames.feed(&buf[..read]);
+                    }
+                    _ => {
+                        let _res = events.send(Event::Closed { connection: id }).await;
+                        return;
+                    }
+                }
+            }
+        });
+        Self { id, writer, reader }
+    }
+
+    /// Send a frame of `opcode` carrying `payload`.
+    async fn send(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        self.writer
+            .write_all(&frame_header(opcode, payload.len()))
+            .await?;
+        self.writer.write_all(payload).await
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// The slot in `connections` holding the connection identified by `id`, if
+/// it is still open.
+fn slot(connections: &mut [Option<Connection>], id: u64) -> Option<&mut Option<Connection>> {
+    connections
+        .iter_mut()
+        .find(|connection| connection.as_ref().map_or(false, |conn| conn.id == id))
+}
+
+#[derive(Debug)]
+/// The WebSocket generator.
+///
+/// This generator is responsible for streaming blocks to the target as
+/// WebSocket messages.
+pub struct Websocket {
+    uri: Uri,
+    opcode: u8,
+    parallel_connections: usize,
+    throttle: Throttle,
+    heartbeat: Heartbeat,
+    block_cache: Vec<Block>,
+    metric_labels: Vec<(String, String)>,
+    budget: Budget,
+    shutdown: Shutdown,
+    pause: Pause,
+    meter: Meter,
+}
+
+impl Websocket {
+    /// Create a new [`Websocket`] instance
+    ///
+    /// # Errors
+    ///
+    /// Creation will fail if the target URI is not a `ws://` URI, the block
+    /// cache cannot be built or, sending text frames, a block is not valid
+    /// UTF-8.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if user has passed zero values for any byte
+    /// values. Sharp corners.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(
+        config: &Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
+        if config.target_uri.scheme_str() != Some("ws") || config.target_uri.host().is_none() {
+            return Err(Error::Uri);
+        }
+        let bytes_per_second = NonZeroU32::new(config.bytes_per_second.get_bytes() as u32).unwrap();
+        let throttle = Throttle::new(config.throttle, bytes_per_second, pause.clone())
+            .with_slow_start(
+                config.slow_start,
+                bytes_per_second,
+                usize::from(config.parallel_connections.max(1)),
+            );
+        let labels = vec![];
+        let metric_labels = address::uri_labels(&config.target_uri);
+        let mut block_cache = block_cache(config, &labels)?;
+        if config.frame == Frame::Text {
+            for blk in &block_cache {
+                str::from_utf8(&blk.bytes)?;
+            }
+        }
+        if config.lock_block_cache {
+            block::lock(&mut block_cache, &labels)?;
+        }
+
+        Ok(Self {
+            uri: config.target_uri.clone(),
+            opcode: match config.frame {
+                Frame::Text => OPCODE_TEXT,
+                Frame::Binary => OPCODE_BINARY,
+            },
+            parallel_connections: usize::from(config.parallel_connections.max(1)),
+            block_cache,
+            throttle,
+            heartbeat: Heartbeat::new(
+                config.heartbeat_seconds,
+                usize::from(config.parallel_connections.max(1)),
+            ),
+            metric_labels,
+            budget: Budget::new(config.maximum_bytes, config.maximum_events),
+            shutdown,
+            pause,
+            meter,
+        })
+    }
+
+    /// Run [`Websocket`] to completion or until a shutdown signal is received.
+    ///
+    /// # Errors
+    ///
+    /// None known, send errors are recorded and the connection re-made.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if underlying byte capacity is not available.
+    pub async fn spin(mut self) -> Result<(), Error> {
+        let labels = self.metric_labels;
+        let mut budget = self.budget;
+        let mut rate_window = RateWindow::new(RATE_WINDOW);
+        let uri = &self.uri;
+
+        let mut connections: Vec<Option<Connection>> =
+            (0..self.parallel_connections).map(|_| None).collect();
+        let mut backoffs: Vec<Backoff> = (0..self.parallel_connections)
+            .map(|_| Backoff::default())
+            .collect();
+        let (events_sender, mut events) = mpsc::channel(64);
+        let mut opened: u64 = 0;
+        let mut next = 0;
+        let mut blocks = self.block_cache.iter().cycle();
+
+        loop {
+            let blk = blocks.next().unwrap();
+            let total_bytes = blk.total_bytes;
+            let idx = next % connections.len();
+
+            tokio::select! {
+                // A generator paused with its connections closed does not
+                // reconnect until resumed.
+                conn = async {
+                    backoffs[idx].ready().await;
+                    handshake(uri).await
+                }, if connections[idx].is_none() && !self.pause.closes_connections() => {
+                    match conn {
+                        Ok((stream, leftover)) => {
+                            backoffs[idx].succeeded();
+                            self.throttle.connected(idx);
+                            self.heartbeat.reset(idx);
+                            opened += 1;
+                            connections[idx] = Some(Connection::new(
+                                stream,
+                                leftover,
+                                opened,
+                                events_sender.clone(),
+                                labels.clone(),
+                            ));
+                        }
+                        Err(err) => {
+                            backoffs[idx].failed();
+                            let mut error_labels = labels.clone();
+                            error_labels.push(("error".to_string(), err.kind()));
+                            counter!("connection_failure", 1, &error_labels);
+                        }
+                    }
+                }
+                _ = self.throttle.wait(total_bytes, &labels), if connections[idx].is_some() => {
+                    let mut connection = connections[idx].take().unwrap();
+                    next += 1;
+                    match connection.send(self.opcode, &blk.bytes).await {
+                        Ok(()) => {
+                            self.heartbeat.reset(idx);
+                            connections[idx] = Some(connection);
+                            if record_block(blk, &labels, &self.meter, &mut rate_window, &mut budget) {
+                                return Ok(());
+                            }
+                        }
+                        Err(err) => {
+                            let mut error_labels = labels.clone();
+                            error_labels.push(("error".to_string(), io_error_kind(&err)));
+                            counter!("request_failure", 1, &error_labels);
+                        }
+                    }
+                }
+                connection = self.heartbeat.due() => {
+                    if let Some(mut conn) = connections[connection].take() {
+                        match conn.send(OPCODE_PING, &[]).await {
+                            Ok(()) => {
+                                counter!("heartbeats_sent", 1, &labels);
+                                connections[connection] = Some(conn);
+                            }
+                            Err(err) => {
+                                let mut error_labels = labels.clone();
+                                error_labels.push(("error".to_string(), io_error_kind(&err)));
+                                counter!("request_failure", 1, &error_labels);
+                            }
+                        }
+                    }
+                    self.heartbeat.reset(connection);
+                }
+                Some(event) = events.recv() => {
+                    match event {
+                        Event::Ping { connection, payload } => {
+                            if let Some(slot) = slot(&mut connections, connection) {
+                                let mut conn = slot.take().unwrap();
+                                match conn.send(OPCODE_PONG, &payload).await {
+                                    Ok(()) => *slot = Some(conn),
+                                    Err(err) => {
+                                        let mut error_labels = labels.clone();
+                                        error_labels.push(("error".to_string(), io_error_kind(&err)));
+                                        counter!("request_failure", 1, &error_labels);
+                                    }
+                                }
+                            }
+                        }
+                        Event::Closed { connection } => {
+                            if let Some(slot) = slot(&mut connections, connection) {
+                                counter!("connection_closed", 1, &labels);
+                                *slot = None;
+                            }
+                        }
+                    }
+                }
+                _ = self.pause.changed() => {
+                    if self.pause.closes_connections() {
+                        connections.iter_mut().for_each(|connection| *connection = None);
+                    }
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(());
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+
+    use super::{check_response, Control, Frames, OPCODE_BINARY, OPCODE_PING};
+
+    /// Frame `payload` as the target would, masked if `mask` is set.
+    #[allow(clippy::cast_possible_truncation)]
+    fn frame(opcode: u8, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+        let mut bytes = vec![0x80 | opcode];
+        let masked = if mask.is_some() { 0x80 } else { 0 };
+        match payload.len() {
+            length @ 0..=125 => bytes.push(masked | length as u8),
+            length @ 126..=0xffff => {
+                bytes.push(masked | 126);
+                bytes.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                bytes.push(masked | 127);
+                bytes.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+        match mask {
+            Some(mask) => {
+                bytes.extend_from_slice(&mask);
+                bytes.extend(
+                    payload
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, byte)| byte ^ mask[idx % 4]),
+                );
+            }
+            None => bytes.extend_from_slice(payload),
+        }
+        bytes
+    }
+
+    // Pings are parsed with their payloads intact, and data frames of any size
+    // skipped, however the bytes carrying them are split across reads.
+    proptest! {
+        #[test]
+        fn pings_parsed_across_reads(
+            frames in proptest::collection::vec(
+                (any::<bool>(), proptest::collection::vec(any::<u8>(), 0..125), 0_usize..70_000, proptest::option::of(any::<[u8; 4]>())),
+                0..16,
+            ),
+            split in 1_usize..4_096,
+        ) {
+            let mut bytes = Vec::new();
+            let mut pings = Vec::new();
+            for (ping, payload, data_length, mask) in &frames {
+                if *ping {
+                    bytes.extend(frame(OPCODE_PING, payload, *mask));
+                    pings.push(Control::Ping(payload.clone()));
+                } else {
+                    bytes.extend(frame(OPCODE_BINARY, &vec![0x89; *data_length], *mask));
+                }
+            }
+
+            let mut parsed = Frames::default();
+            let mut controls = Vec::new();
+            for read in bytes.chunks(split) {
+                controls.extend(parsed.feed(read));
+            }
+            prop_assert_eq!(controls, pings);
+            prop_assert!(parsed.pending.is_empty());
+            prop_assert_eq!(parsed.skip, 0);
+        }
+    }
+
+    // A handshake response is accepted only with status 101 and the accept
+    // key derived from the request's key.
+    proptest! {
+        #[test]
+        fn handshake_response_checked(key in "[A-Za-z0-9+/]{22}==", status in prop_oneof![Just(101_u16), 200_u16..600], matching: bool) {
+            let accept = if matching { derive_accept_key(key.as_bytes()) } else { "bogus".to_string() };
+            let head = format!(
+                "HTTP/1.1 {} Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                status, accept
+            );
+            let checked = check_response(head.as_bytes(), &key);
+            prop_assert_eq!(checked.is_ok(), status == 101 && matching);
+        }
+    }
+}
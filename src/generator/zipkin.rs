@@ -0,0 +1,371 @@
+//! The [Zipkin](https://zipkin.io/) v2 JSON speaking generator.
+//!
+//! Spans are batched `spans_per_request` at a time into JSON arrays as the
+//! block cache is built, each array the body of a request to `/api/v2/spans`.
+//! Requests are throttled in spans, not bytes.
+
+use std::{
+    num::{NonZeroU32, NonZeroUsize},
+    sync::Arc,
+};
+
+use byte_unit::{Byte, ByteUnit};
+use governor::state::direct::InsufficientCapacity;
+use hyper::{
+    client::HttpConnector,
+    header::{HeaderValue, CONTENT_TYPE},
+    Body, Client, Request, Uri,
+};
+use metrics::{counter, gauge};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+use tracing::info;
+
+use crate::{
+    address,
+    block::{self, chunk_bytes, construct_block_cache, Block},
+    control::Pause,
+    generator::{
+        common::{Budget, RateWindow, RATE_WINDOW},
+        Meter,
+    },
+    numa, payload,
+    signals::Shutdown,
+    telemetry::hyper_error_kind,
+    throttle::{self, Throttle},
+};
+
+fn default_spans_per_request() -> NonZeroU32 {
+    NonZeroU32::new(100).unwrap()
+}
+
+fn default_parallel_connections() -> u16 {
+    10
+}
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration of this generator.
+pub struct Config {
+    /// The seed for random operations against this target
+    pub seed: [u8; 32],
+    /// The URI of the collector, for instance `http://localhost:9411`
+    #[serde(with = "http_serde::uri")]
+    pub target_uri: Uri,
+    /// The shape of the spans sent, see [`payload::ZipkinConfig`]
+    #[serde(default)]
+    pub spans: payload::ZipkinConfig,
+    /// The spans per second to send to the target
+    pub spans_per_second: NonZeroU32,
+    /// The spans sent in each request, by default 100
+    #[serde(default = "default_spans_per_request")]
+    pub spans_per_request: NonZeroU32,
+    /// The block sizes for spans to this target
+    pub block_sizes: Option<Vec<byte_unit::Byte>>,
+    /// The maximum size in bytes of the cache of prebuilt spans
+    pub maximum_prebuild_cache_size_bytes: byte_unit::Byte,
+    /// The total number of parallel connections to maintain, by default 10
+    #[serde(default = "default_parallel_connections")]
+    pub parallel_connections: u16,
+    /// The maximum number of bytes to send before this generator stops. If
+    /// unset the generator runs until the experiment ends.
+    pub maximum_bytes: Option<byte_unit::Byte>,
+    /// The maximum number of events -- spans -- to send before this generator
+    /// stops. If unset the generator runs until the experiment ends.
+    pub maximum_events: Option<u64>,
+    /// The algorithm used to throttle output to `spans_per_second`. Defaults
+    /// to a token bucket.
+    #[serde(default)]
+    pub throttle: throttle::Config,
+    /// Whether to lock the block cache into RAM, touching each of its pages,
+    /// before the run starts. See mlock(2) for the limits on locked memory.
+    #[serde(default)]
+    pub lock_block_cache: bool,
+    /// Place this generator on a NUMA node, see [`crate::numa`]
+    pub numa: Option<numa::Placement>,
+}
+
+#[derive(Debug)]
+/// Errors produced by [`Zipkin`].
+pub enum Error {
+    /// Rate limiter has insuficient capacity for payload. Indicates a serious
+    /// bug.
+    Governor(InsufficientCapacity),
+    /// Creation of payload blocks failed.
+    Block(block::Error),
+    /// Wrapper around [`hyper::http::Error`].
+    Http(hyper::http::Error),
+}
+
+impl From<block::Error> for Error {
+    fn from(error: block::Error) -> Self {
+        Error::Block(error)
+    }
+}
+
+impl From<hyper::http::Error> for Error {
+    fn from(error: hyper::http::Error) -> Self {
+        Error::Http(error)
+    }
+}
+
+impl From<InsufficientCapacity> for Error {
+    fn from(error: InsufficientCapacity) -> Self {
+        Error::Governor(error)
+    }
+}
+
+/// Encode the request body carrying `spans`, each a JSON object, as a JSON
+/// array.
+fn encode_request(spans: &[&[u8]]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(spans.iter().map(|s| s.len() + 1).sum::<usize>() + 1);
+    body.push(b'[');
+    for (idx, span) in spans.iter().enumerate() {
+        if idx > 0 {
+            body.push(b',');
+        }
+        body.extend_from_slice(span);
+    }
+    body.push(b']');
+    body
+}
+
+/// Build the block cache of a generator configured by `config`, as
+/// [`Zipkin::new`] does. Each block is a request carrying exactly
+/// `spans_per_request` spans, the payload's spans taken in turn, the last
+/// request wrapping around to the first.
+///
+/// # Errors
+///
+/// Function will return an error if the configured block sizes cannot be
+/// chunked into the prebuild cache.
+///
+/// # Panics
+///
+/// Function will panic if user has passed zero values for any byte values.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::ptr_arg)]
+pub(crate) fn block_cache(
+    config: &Config,
+    labels: &Vec<(String, String)>,
+) -> Result<Vec<Block>, Error> {
+    let mut rng = StdRng::from_seed(config.seed);
+    let block_sizes: Vec<NonZeroUsize> = config
+        .block_sizes
+        .clone()
+        .unwrap_or_else(|| {
+            vec![
+                Byte::from_unit(1_f64, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(2_f64, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(4_f64, ByteUnit::KiB).unwrap(),
+                Byte::from_unit(8_f64, ByteUnit::KiB).unwrap(),
+            ]
+        })
+        .iter()
+        .map(|sz| NonZeroUsize::new(sz.get_bytes() as usize).expect("bytes must be non-zero"))
+        .collect();
+    let block_chunks = chunk_bytes(
+        &mut rng,
+        NonZeroUsize::new(config.maximum_prebuild_cache_size_bytes.get_bytes() as usize)
+            .expect("bytes must be non-zero"),
+        &block_sizes,
+    )?;
+    let payload_blocks = construct_block_cache(
+        &mut rng,
+        &payload::Zipkin::new(config.spans),
+        &block_chunks,
+        None,
+        labels,
+    );
+    let spans: Vec<&[u8]> = payload_blocks
+        .iter()
+        .flat_map(|blk| blk.bytes.split(|byte| *byte == b'\n'))
+        .filter(|span| !span.is_empty())
+        .collect();
+
+    let spans_per_request = config.spans_per_request.get() as usize;
+    let requests = (spans.len() + spans_per_request - 1) / spans_per_request;
+    let mut block_cache = Vec::with_capacity(requests);
+    for request in 0..requests {
+        let batch: Vec<&[u8]> = (0..spans_per_request)
+            .map(|idx| spans[(request * spans_per_request + idx) % spans.len()])
+            .collect();
+        let bytes = encode_request(&batch);
+        block_cache.push(Block::new(
+            NonZeroU32::new(bytes.len() as u32).expect("requests are never empty"),
+            u64::from(config.spans_per_request.get()),
+            bytes,
+        ));
+    }
+    Ok(block_cache)
+}
+
+#[derive(Debug)]
+/// The Zipkin generator.
+///
+/// This generator is responsible for sending blocks to the target as the
+/// bodies of Zipkin v2 span requests.
+pub struct Zipkin {
+    uri: Uri,
+    spans_per_request: NonZeroU32,
+    parallel_connections: u16,
+    connection_semaphore: Arc<Semaphore>,
+    throttle: Throttle,
+    block_cache: Vec<Block>,
+    metric_labels: Vec<(String, String)>,
+    budget: Budget,
+    shutdown: Shutdown,
+    meter: Meter,
+}
+
+impl Zipkin {
+    /// Create a new [`Zipkin`] instance
+    ///
+    /// # Errors
+    ///
+    /// Creation will fail if the block cache cannot be built or the target URI
+    /// does not form a valid URI with the spans path.
+    pub fn new(
+        config: &Config,
+        shutdown: Shutdown,
+        pause: Pause,
+        meter: Meter,
+    ) -> Result<Self, Error> {
+        let throttle = Throttle::new(config.throttle, config.spans_per_second, pause);
+        let labels = vec![];
+        let metric_labels = address::uri_labels(&config.target_uri);
+        let mut block_cache = block_cache(config, &labels)?;
+        if config.lock_block_cache {
+            block::lock(&mut block_cache, &labels)?;
+        }
+
+        let mut parts = config.target_uri.clone().into_parts();
+        parts.path_and_query = Some("/api/v2/spans".parse().map_err(hyper::http::Error::from)?);
+        let uri = Uri::from_parts(parts).map_err(hyper::http::Error::from)?;
+
+        Ok(Self {
+            uri,
+            spans_per_request: config.spans_per_request,
+            parallel_connections: config.parallel_connections,
+            connection_semaphore: Arc::new(Semaphore::new(config.parallel_connections as usize)),
+            block_cache,
+            throttle,
+            metric_labels,
+            budget: Budget::new(config.maximum_bytes, config.maximum_events),
+            shutdown,
+            meter,
+        })
+    }
+
+    /// Run [`Zipkin`] to completion or until a shutdown signal is received.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if a request cannot be built.
+    ///
+    /// # Panics
+    ///
+    /// None known.
+    pub async fn spin(mut self) -> Result<(), Error> {
+        let client: Client<HttpConnector, Body> = Client::builder()
+            .pool_max_idle_per_host(self.parallel_connections as usize)
+            .retry_canceled_requests(false)
+            .build_http();
+        let mut throttle = self.throttle;
+        let uri = self.uri;
+        let connection_semaphore = self.connection_semaphore;
+        let spans_per_request = self.spans_per_request;
+
+        let labels = self.metric_labels;
+        let mut budget = self.budget;
+        let mut rate_window = RateWindow::new(RATE_WINDOW);
+        let mut blocks = self.block_cache.iter().cycle();
+
+        loop {
+            let blk = blocks.next().unwrap();
+            let total_bytes = blk.total_bytes;
+
+            tokio::select! {
+                _ = throttle.wait(spans_per_request, &labels) => {
+                    rate_window.record(u64::from(total_bytes.get()), &labels);
+                    let client = client.clone();
+                    let labels = labels.clone();
+                    let meter = self.meter.clone();
+
+                    let block_length = blk.bytes.len();
+                    let request: Request<Body> = Request::post(uri.clone())
+                        .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
+                        .body(Body::from(blk.bytes.clone()))?;
+
+                    let permit = Arc::clone(&connection_semaphore).acquire_owned().await.unwrap();
+                    tokio::spawn(async move {
+                        counter!("requests_sent", 1, &labels);
+                        match client.request(request).await {
+                            Ok(response) => {
+                                counter!("bytes_written", block_length as u64, &labels);
+                                meter.record(block_length as u64);
+                                let status = response.status();
+                                if status.is_success() {
+                                    counter!("spans_sent", u64::from(spans_per_request.get()), &labels);
+                                }
+                                let mut status_labels = labels.clone();
+                                status_labels
+                                    .push(("status_code".to_string(), status.as_u16().to_string()));
+                                counter!("request_ok", 1, &status_labels);
+                            }
+                            Err(err) => {
+                                let mut error_labels = labels.clone();
+                                error_labels
+                                    .push(("error".to_string(), hyper_error_kind(&err).to_string()));
+                                counter!("request_failure", 1, &error_labels);
+                            }
+                        }
+                        drop(permit);
+                    });
+                    budget.record(u64::from(total_bytes.get()), blk.lines);
+                },
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    // Acquire all available connections, meaning that we have
+                    // no outstanding tasks in flight.
+                    let _semaphore = connection_semaphore.acquire_many(u32::from(self.parallel_connections)).await.unwrap();
+                    return Ok(());
+                },
+            }
+
+            if budget.exhausted() {
+                info!("finite data limit reached, generator complete");
+                let _semaphore = connection_semaphore
+                    .acquire_many(u32::from(self.parallel_connections))
+                    .await
+                    .unwrap();
+                gauge!("generator_complete", 1.0, &labels);
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::{collection, prelude::*};
+
+    use super::encode_request;
+
+    // A request body is a JSON array of exactly the spans batched into it.
+    proptest! {
+        #[test]
+        fn request_is_array_of_spans(ids in collection::vec(any::<u64>(), 1..64)) {
+            let spans: Vec<Vec<u8>> = ids
+                .iter()
+                .map(|id| serde_json::to_vec(&serde_json::json!({ "id": format!("{:016x}", id) })).unwrap())
+                .collect();
+            let body = encode_request(&spans.iter().map(|span| &span[..]).collect::<Vec<_>>());
+            let array: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+            prop_assert_eq!(array.len(), ids.len());
+            for (span, id) in array.iter().zip(ids.iter()) {
+                prop_assert_eq!(span["id"].as_str().unwrap(), format!("{:016x}", id));
+            }
+        }
+    }
+}
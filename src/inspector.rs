@@ -25,6 +25,7 @@ use tokio::{process::Command, sync::broadcast::Receiver};
 use tracing::{error, info};
 
 use crate::{
+    cleanup,
     common::{stdio, Output},
     signals::Shutdown,
 };
@@ -106,7 +107,7 @@ impl Server {
 
         let config = self.config;
 
-        let mut target_cmd = Command::new(config.command);
+        let mut target_cmd = Command::new(&config.command);
         let mut environment_variables = config.environment_variables.clone();
         environment_variables.insert(String::from("TARGET_PID"), target_pid.to_string());
 
@@ -119,6 +120,9 @@ impl Server {
             .args(config.arguments)
             .envs(environment_variables.iter());
         let mut target_child = target_cmd.spawn().map_err(Error::Io)?;
+        if let Some(pid) = target_child.id() {
+            cleanup::track(pid, &config.command);
+        }
 
         let target_wait = target_child.wait();
         tokio::select! {
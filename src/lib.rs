@@ -19,15 +19,41 @@
 #![allow(clippy::cast_precision_loss)]
 #![allow(clippy::multiple_crate_versions)]
 
+pub(crate) mod address;
+pub mod antagonist;
 pub mod blackhole;
 pub(crate) mod block;
+pub mod budget;
 pub mod captures;
+pub mod chaos;
+pub mod cleanup;
+pub mod clock;
 pub(crate) mod codec;
 mod common;
 pub mod config;
+pub mod control;
+pub mod dashboard;
+pub mod determinism;
+pub mod diff;
+pub mod export;
 pub mod generator;
 pub mod inspector;
+pub mod numa;
 pub mod observer;
+pub mod pairs;
 pub(crate) mod payload;
+pub mod preflight;
+pub mod pushgateway;
+pub mod runtime_stats;
 pub mod signals;
+pub mod status;
+pub mod supervisor;
+pub mod sweep;
 pub mod target;
+pub mod telemetry;
+pub mod throttle;
+pub mod trace;
+pub mod uring;
+#[cfg(target_os = "linux")]
+pub(crate) mod vsock;
+pub mod watchdog;
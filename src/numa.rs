@@ -0,0 +1,236 @@
+//! Place components on a NUMA node
+//!
+//! On a multi-socket host memory is local to one socket's node, and reaching
+//! across to another node's memory is slower and contends for the
+//! interconnect. A generator writing its block cache from, or a blackhole
+//! reading into, memory on the far node measures the interconnect as much as
+//! the target. A generator or blackhole may be configured with a
+//! [`Placement`], running it with [`run`] on a dedicated thread restricted to
+//! the node's CPUs, with its own runtime. The thread's memory, and that of the
+//! runtime's worker threads, is allocated from the node. Generators construct
+//! their block cache under [`with_memory`], so it too is allocated from the
+//! node.
+//!
+//! Placement is supported on Linux only.
+
+use std::{future::Future, io, num::NonZeroUsize};
+
+use serde::Deserialize;
+
+fn default_worker_threads() -> NonZeroUsize {
+    NonZeroUsize::new(1).unwrap()
+}
+
+#[derive(Debug)]
+/// Errors produced by [`run`] and [`with_memory`]
+pub enum Error {
+    /// Placement is not supported on this platform.
+    Unsupported,
+    /// The node does not exist or has no CPUs.
+    NoSuchNode(u32),
+    /// Wrapper for [`std::io::Error`].
+    Io(io::Error),
+    /// Wrapper for [`nix::errno::Errno`].
+    Errno(nix::errno::Errno),
+    /// The placed thread panicked.
+    Panicked,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+/// The NUMA placement of a component
+pub struct Placement {
+    /// The node to place the component on, as numbered in
+    /// `/sys/devices/system/node`
+    pub node: u32,
+    /// The number of runtime worker threads the component runs on, all
+    /// restricted to the node's CPUs
+    #[serde(default = "default_worker_threads")]
+    pub worker_threads: NonZeroUsize,
+}
+
+/// Parse a kernel CPU list, as in `/sys/devices/system/node/node0/cpulist`:
+/// comma separated CPUs and inclusive ranges of CPUs, `0-3,8,10-11`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_cpulist(cpulist: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for part in cpulist.trim().split(',').filter(|part| !part.is_empty()) {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let first: usize = first.parse().ok()?;
+                let last: usize = last.parse().ok()?;
+                if first > last {
+                    return None;
+                }
+                cpus.extend(first..=last);
+            }
+            None => cpus.push(part.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use nix::{
+        errno::Errno,
+        libc,
+        sched::{sched_setaffinity, CpuSet},
+        unistd::Pid,
+    };
+
+    use super::{parse_cpulist, Error};
+
+    // See set_mempolicy(2).
+    const MPOL_DEFAULT: libc::c_int = 0;
+    const MPOL_BIND: libc::c_int = 2;
+    const WORD_BITS: usize = libc::c_ulong::BITS as usize;
+
+    /// The CPUs of `node`.
+    pub(super) fn cpus(node: u32) -> Result<Vec<usize>, Error> {
+        let path = format!("/sys/devices/system/node/node{}/cpulist", node);
+        let cpulist = std::fs::read_to_string(path).map_err(|_| Error::NoSuchNode(node))?;
+        match parse_cpulist(&cpulist) {
+            Some(cpus) if !cpus.is_empty() => Ok(cpus),
+            _ => Err(Error::NoSuchNode(node)),
+        }
+    }
+
+    /// Restrict the calling thread to the CPUs of `node`.
+    pub(super) fn bind_cpus(node: u32) -> Result<(), Error> {
+        let mut set = CpuSet::new();
+        for cpu in cpus(node)? {
+            set.set(cpu).map_err(Error::Errno)?;
+        }
+        sched_setaffinity(Pid::from_raw(0), &set).map_err(Error::Errno)
+    }
+
+    /// Allocate the calling thread's memory from `node`, or by the default
+    /// policy if `node` is `None`.
+    pub(super) fn bind_memory(node: Option<u32>) -> Result<(), Error> {
+        let (mode, mask) = match node {
+            Some(node) => {
+                let node = node as usize;
+                let mut mask: Vec<libc::c_ulong> = vec![0; node / WORD_BITS + 1];
+                mask[node / WORD_BITS] |= 1 << (node % WORD_BITS);
+                (MPOL_BIND, mask)
+            }
+            None => (MPOL_DEFAULT, Vec::new()),
+        };
+        // The kernel reads one bit less than `maxnode`.
+        let maxnode = (mask.len() * WORD_BITS + 1) as libc::c_ulong;
+        let mask_ptr = if mask.is_empty() {
+            std::ptr::null()
+        } else {
+            mask.as_ptr()
+        };
+        // SAFETY: `mask_ptr` is null or points to `mask`, holding the
+        // `maxnode - 1` bits the kernel reads.
+        let res = unsafe { libc::syscall(libc::SYS_set_mempolicy, mode, mask_ptr, maxnode) };
+        if res == -1 {
+            return Err(Error::Errno(Errno::last()));
+        }
+        Ok(())
+    }
+}
+
+/// Call `f` with the calling thread's memory allocated from `node`, restoring
+/// the default policy once it returns.
+///
+/// # Errors
+///
+/// Function will return an error if `node` does not exist or placement is not
+/// supported.
+pub fn with_memory<F, T>(node: u32, f: F) -> Result<T, Error>
+where
+    F: FnOnce() -> T,
+{
+    #[cfg(target_os = "linux")]
+    {
+        linux::cpus(node)?;
+        linux::bind_memory(Some(node))?;
+        let output = f();
+        linux::bind_memory(None)?;
+        Ok(output)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (node, f);
+        Err(Error::Unsupported)
+    }
+}
+
+/// Run the future made by `make` to completion on a dedicated thread named for
+/// `name`, placed by `placement`.
+///
+/// # Errors
+///
+/// Function will return an error if the node does not exist, the thread or its
+/// runtime cannot be created or the thread panics.
+pub async fn run<F, Fut>(placement: Placement, name: &str, make: F) -> Result<Fut::Output, Error>
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future + 'static,
+    Fut::Output: Send + 'static,
+{
+    #[cfg(target_os = "linux")]
+    {
+        let (snd, rcv) = tokio::sync::oneshot::channel();
+        std::thread::Builder::new()
+            .name(format!("{}-node{}", name, placement.node))
+            .spawn(move || {
+                let runtime = linux::bind_cpus(placement.node)
+                    .and_then(|()| linux::bind_memory(Some(placement.node)))
+                    .and_then(|()| {
+                        // Worker threads are spawned from this thread, so
+                        // inherit its CPUs and memory policy.
+                        let mut builder = if placement.worker_threads.get() == 1 {
+                            tokio::runtime::Builder::new_current_thread()
+                        } else {
+                            let mut builder = tokio::runtime::Builder::new_multi_thread();
+                            builder.worker_threads(placement.worker_threads.get());
+                            builder
+                        };
+                        builder.enable_all().build().map_err(Error::Io)
+                    });
+                // The receiver is gone only if the component was dropped, in
+                // which case there is no one to report to.
+                let _ = snd.send(runtime.map(|runtime| runtime.block_on(make())));
+            })
+            .map_err(Error::Io)?;
+        rcv.await.map_err(|_| Error::Panicked)?
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (placement, name, make);
+        Err(Error::Unsupported)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::{collection, prelude::*};
+
+    use super::parse_cpulist;
+
+    // A CPU list rendered from ranges parses back to the CPUs of those ranges.
+    proptest! {
+        #[test]
+        fn cpulist_round_trip(ranges in collection::vec((0..256_usize, 0..8_usize), 0..8)) {
+            let rendered: Vec<String> = ranges
+                .iter()
+                .map(|(first, len)| {
+                    if *len == 0 {
+                        first.to_string()
+                    } else {
+                        format!("{}-{}", first, first + len)
+                    }
+                })
+                .collect();
+            let expected: Vec<usize> = ranges
+                .iter()
+                .flat_map(|(first, len)| *first..=first + len)
+                .collect();
+            prop_assert_eq!(parse_cpulist(&format!("{}\n", rendered.join(","))), Some(expected));
+        }
+    }
+}
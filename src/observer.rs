@@ -5,21 +5,90 @@
 //! allow for a sub-process to do out-of-band inspection of the target but
 //! cannot incorporate whatever it's doing into the capture data that lading
 //! produces. This observer, on Linux, looks up the target process in procfs and
-//! writes out key details about memory and CPU consumption into the capture
-//! data. On non-Linux systems the observer, if enabled, will emit a warning.
+//! writes out key details about memory, CPU, disk and network consumption into
+//! the capture data. On macOS the memory and CPU details, less those particular
+//! to procfs, are queried through libproc. On other systems the observer, if
+//! enabled, will emit a warning.
 
-use std::io;
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
 
 use nix::errno::Errno;
+use once_cell::sync::Lazy;
 use serde::Deserialize;
 use tokio::{sync::broadcast::Receiver, time};
 use tracing::info;
+#[cfg(not(target_os = "macos"))]
+use tracing::warn;
 
 use crate::signals::Shutdown;
 
 #[cfg(target_os = "linux")]
 use procfs::process::Process;
 
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "macos")]
+mod macos;
+
+/// Sentinel for [`TARGET_RSS_BYTES`] before the target has been observed.
+const UNOBSERVED: u64 = u64::MAX;
+
+/// The PID of the target and the CPU time, user and kernel, it has consumed in
+/// seconds, kept together so that a restarted target's time is never taken
+/// for its predecessor's. Used to detect when the target has stalled under
+/// load, see [`crate::watchdog`], and to hold it to its budget, see
+/// [`crate::budget`].
+static TARGET_CPU_SECONDS: Lazy<Mutex<Option<(u32, f64)>>> = Lazy::new(|| Mutex::new(None));
+
+/// The resident memory of the target in bytes, used to hold the target to its
+/// budget.
+static TARGET_RSS_BYTES: AtomicU64 = AtomicU64::new(UNOBSERVED);
+
+/// Record that the target, `pid`, has consumed `seconds` of CPU time in total.
+#[cfg_attr(not(any(target_os = "linux", target_os = "macos")), allow(dead_code))]
+fn record_cpu_seconds(pid: i32, seconds: f64) {
+    let pid = pid.try_into().expect("PID coercion failed");
+    *TARGET_CPU_SECONDS.lock().unwrap() = Some((pid, seconds));
+}
+
+/// Return the PID of the target and the CPU time it has consumed in seconds,
+/// if it has been observed.
+///
+/// # Panics
+///
+/// None known.
+#[must_use]
+pub fn target_cpu() -> Option<(u32, f64)> {
+    *TARGET_CPU_SECONDS.lock().unwrap()
+}
+
+/// Return the CPU time the target has consumed in seconds, if it has been
+/// observed.
+#[must_use]
+pub fn target_cpu_seconds() -> Option<f64> {
+    target_cpu().map(|(_, seconds)| seconds)
+}
+
+/// Record that the target has `bytes` of resident memory.
+#[cfg_attr(not(any(target_os = "linux", target_os = "macos")), allow(dead_code))]
+fn record_rss_bytes(bytes: u64) {
+    TARGET_RSS_BYTES.store(bytes, Ordering::Relaxed);
+}
+
+/// Return the resident memory of the target in bytes, if it has been
+/// observed.
+#[must_use]
+pub fn target_rss_bytes() -> Option<u64> {
+    let bytes = TARGET_RSS_BYTES.load(Ordering::Relaxed);
+    (bytes != UNOBSERVED).then(|| bytes)
+}
+
 #[derive(Debug)]
 /// Errors produced by [`Server`]
 pub enum Error {
@@ -73,6 +142,8 @@ impl Server {
     ///
     /// Target server will use the `broadcast::Sender` passed here to transmit
     /// its PID. This PID is passed to the sub-process as the first argument.
+    /// Should the target be restarted, see [`crate::chaos`], the observer
+    /// follows the restarted target.
     ///
     /// # Errors
     ///
@@ -94,10 +165,9 @@ impl Server {
             .recv()
             .await
             .expect("target failed to transmit PID, catastrophic failure");
-        drop(pid_snd);
 
-        let process = Process::new(target_pid.try_into().expect("PID coercion failed"))
-            .map_err(Error::ProcError)?;
+        let mut target_pid: i32 = target_pid.try_into().expect("PID coercion failed");
+        let mut process = Process::new(target_pid).map_err(Error::ProcError)?;
 
         let ticks_per_second: f64 =
             procfs::ticks_per_second().expect("cannot determine ticks per second") as f64;
@@ -132,10 +202,12 @@ impl Server {
                         gauge!("kernel_time_seconds", kernel_time_seconds);
                         // The time spent in user-space in seconds.
                         gauge!("user_time_seconds", user_time_seconds);
+                        record_cpu_seconds(target_pid, kernel_time_seconds + user_time_seconds);
                         // The uptime of the process in fractional seconds.
                         gauge!("uptime_seconds", process_uptime_seconds);
                         // Number of pages that the process has in real memory.
                         gauge!("rss_bytes", (stat.rss * page_size) as f64);
+                        record_rss_bytes((stat.rss * page_size).try_into().unwrap_or(0));
                         // Soft limit on RSS bytes, see RLIMIT_RSS in getrlimit(2).
                         gauge!("rsslim_bytes", stat.rsslim as f64);
                         // The size in bytes of the process in virtual memory.
@@ -143,6 +215,34 @@ impl Server {
                         // Number of threads this process has active.
                         gauge!("num_threads", stat.num_threads as f64);
                     }
+                    if let Ok(io) = process.io() {
+                        // Bytes the process has caused to be read from storage.
+                        gauge!("disk_read_bytes", io.read_bytes as f64);
+                        // Bytes the process has caused to be written to storage.
+                        gauge!("disk_write_bytes", io.write_bytes as f64);
+                    }
+                    if let Ok(interfaces) = linux::network(target_pid) {
+                        for interface in interfaces {
+                            let labels = vec![("interface".to_string(), interface.name)];
+                            // Bytes received and transmitted on the interface,
+                            // by all processes of the target's network namespace.
+                            gauge!("network_receive_bytes", interface.receive_bytes as f64, &labels);
+                            gauge!("network_transmit_bytes", interface.transmit_bytes as f64, &labels);
+                        }
+                    }
+                }
+                Ok(pid) = pid_snd.recv() => {
+                    let restarted_pid: i32 = pid.try_into().expect("PID coercion failed");
+                    // A target that exits again before it is found is
+                    // followed from its next restart.
+                    match Process::new(restarted_pid) {
+                        Ok(restarted) => {
+                            info!("following restarted target {}", pid);
+                            target_pid = restarted_pid;
+                            process = restarted;
+                        }
+                        Err(err) => warn!("could not follow restarted target {}, waiting for the next: {}", pid, err),
+                    }
                 }
                 _ = self.shutdown.recv() => {
                     info!("shutdown signal received");
@@ -151,8 +251,76 @@ impl Server {
             }
         }
     }
-    #[cfg(not(target_os = "linux"))]
-    pub async fn run(mut self, _pid_snd: Receiver<u32>) -> Result<ExitStatus, Error> {
-        warn!("observer unavailable on non-Linux system");
+
+    /// Run this [`Server`] to completion
+    ///
+    /// See the Linux implementation of this function. Here the target is
+    /// queried through libproc rather than procfs.
+    ///
+    /// # Errors
+    ///
+    /// None are known.
+    ///
+    /// # Panics
+    ///
+    /// None are known.
+    #[cfg(target_os = "macos")]
+    pub async fn run(mut self, mut pid_snd: Receiver<u32>) -> Result<(), Error> {
+        use std::time::Duration;
+
+        use metrics::gauge;
+
+        let target_pid = pid_snd
+            .recv()
+            .await
+            .expect("target failed to transmit PID, catastrophic failure");
+        let mut target_pid: i32 = target_pid.try_into().expect("PID coercion failed");
+
+        let mut libproc_delay = time::interval(Duration::from_secs(1));
+
+        loop {
+            tokio::select! {
+                _ = libproc_delay.tick() => {
+                    if let Ok(sample) = macos::sample(target_pid) {
+                        // The time spent in kernel-space in seconds.
+                        gauge!("kernel_time_seconds", sample.kernel_time_seconds);
+                        // The time spent in user-space in seconds.
+                        gauge!("user_time_seconds", sample.user_time_seconds);
+                        record_cpu_seconds(target_pid, sample.kernel_time_seconds + sample.user_time_seconds);
+                        // The uptime of the process in fractional seconds.
+                        gauge!("uptime_seconds", sample.uptime_seconds);
+                        // The bytes the process has in real memory.
+                        gauge!("rss_bytes", sample.rss_bytes as f64);
+                        record_rss_bytes(sample.rss_bytes);
+                        // The size in bytes of the process in virtual memory.
+                        gauge!("vsize_bytes", sample.vsize_bytes as f64);
+                        // Number of threads this process has active.
+                        gauge!("num_threads", f64::from(sample.num_threads));
+                    }
+                }
+                Ok(pid) = pid_snd.recv() => {
+                    info!("following restarted target {}", pid);
+                    target_pid = pid.try_into().expect("PID coercion failed");
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Run this [`Server`] to completion
+    ///
+    /// The observer is unavailable on this system, this function returns
+    /// immediately.
+    ///
+    /// # Errors
+    ///
+    /// None are known.
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    pub async fn run(self, _pid_snd: Receiver<u32>) -> Result<(), Error> {
+        warn!("observer unavailable on this system");
+        Ok(())
     }
 }
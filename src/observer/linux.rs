@@ -0,0 +1,80 @@
+//! Process observation on Linux details not covered by the procfs crate.
+
+use std::{fs, io};
+
+/// The network byte counters of an interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Interface {
+    /// The name of the interface.
+    pub(crate) name: String,
+    /// Bytes received on the interface.
+    pub(crate) receive_bytes: u64,
+    /// Bytes transmitted on the interface.
+    pub(crate) transmit_bytes: u64,
+}
+
+/// Parse the contents of a `net/dev` file, see proc(5). Lines that are not
+/// interface lines are skipped.
+fn parse_net_dev(contents: &str) -> Vec<Interface> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (name, counters) = line.split_once(':')?;
+            let counters: Vec<u64> = counters
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .ok()?;
+            // Receive bytes lead the receive counters, transmit bytes the
+            // transmit counters, eight of each.
+            Some(Interface {
+                name: name.trim().to_string(),
+                receive_bytes: *counters.first()?,
+                transmit_bytes: *counters.get(8)?,
+            })
+        })
+        .collect()
+}
+
+/// The network byte counters of each interface in the network namespace of
+/// `pid`. These are the namespace's totals, not those of `pid` alone, unless
+/// the target runs in a namespace of its own.
+pub(crate) fn network(pid: i32) -> Result<Vec<Interface>, io::Error> {
+    let contents = fs::read_to_string(format!("/proc/{}/net/dev", pid))?;
+    Ok(parse_net_dev(&contents))
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::{collection, prelude::*};
+
+    use super::{parse_net_dev, Interface};
+
+    const HEADER: &str = "Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+";
+
+    // Every interface line written in the kernel's format is parsed back, the
+    // header skipped.
+    proptest! {
+        #[test]
+        fn net_dev_round_trip(interfaces in collection::vec(("[a-z][a-z0-9]{0,14}", any::<u64>(), any::<u64>()), 0..16)) {
+            let mut contents = HEADER.to_string();
+            for (name, receive_bytes, transmit_bytes) in &interfaces {
+                contents.push_str(&format!(
+                    "{:>6}: {} 0 0 0 0 0 0 0 {} 0 0 0 0 0 0 0\n",
+                    name, receive_bytes, transmit_bytes
+                ));
+            }
+            let expected: Vec<Interface> = interfaces
+                .into_iter()
+                .map(|(name, receive_bytes, transmit_bytes)| Interface {
+                    name,
+                    receive_bytes,
+                    transmit_bytes,
+                })
+                .collect();
+            prop_assert_eq!(parse_net_dev(&contents), expected);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Process observation on macOS, by way of libproc.
+
+use std::{
+    mem,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use nix::{errno::Errno, libc};
+
+/// A sample of the resource consumption of a process.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Sample {
+    /// The time spent in kernel-space in seconds.
+    pub(crate) kernel_time_seconds: f64,
+    /// The time spent in user-space in seconds.
+    pub(crate) user_time_seconds: f64,
+    /// The uptime of the process in fractional seconds.
+    pub(crate) uptime_seconds: f64,
+    /// The resident set size of the process in bytes.
+    pub(crate) rss_bytes: u64,
+    /// The size in bytes of the process in virtual memory.
+    pub(crate) vsize_bytes: u64,
+    /// Number of threads the process has active.
+    pub(crate) num_threads: i32,
+}
+
+/// Query `flavor` information about `pid`, see proc_pidinfo(3).
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_possible_wrap)]
+fn pidinfo<T>(pid: i32, flavor: i32) -> Result<T, Errno> {
+    let mut info = mem::MaybeUninit::<T>::zeroed();
+    let size = mem::size_of::<T>() as i32;
+    // Safety: `info` is valid for writes of `size` bytes and `proc_pidinfo`
+    // writes no more than `size` bytes into it.
+    let written = unsafe { libc::proc_pidinfo(pid, flavor, 0, info.as_mut_ptr().cast(), size) };
+    if written <= 0 {
+        return Err(Errno::last());
+    }
+    if written < size {
+        return Err(Errno::EIO);
+    }
+    // Safety: `proc_pidinfo` has filled all `size` bytes of `info`.
+    Ok(unsafe { info.assume_init() })
+}
+
+/// Nanoseconds per mach absolute time unit, the unit of task CPU times. This is
+/// one on Intel but not on Apple silicon.
+fn nanos_per_tick() -> f64 {
+    let mut info = libc::mach_timebase_info { numer: 0, denom: 0 };
+    // Safety: `info` is a valid, writable `mach_timebase_info`.
+    let ret = unsafe { libc::mach_timebase_info(&mut info) };
+    if ret != 0 || info.denom == 0 {
+        return 1.0;
+    }
+    f64::from(info.numer) / f64::from(info.denom)
+}
+
+/// Sample the resource consumption of `pid`.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn sample(pid: i32) -> Result<Sample, Errno> {
+    let task: libc::proc_taskinfo = pidinfo(pid, libc::PROC_PIDTASKINFO)?;
+    let bsd: libc::proc_bsdinfo = pidinfo(pid, libc::PROC_PIDTBSDINFO)?;
+
+    let nanos_per_tick = nanos_per_tick();
+    let start = Duration::new(bsd.pbi_start_tvsec, (bsd.pbi_start_tvusec * 1_000) as u32);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    Ok(Sample {
+        kernel_time_seconds: task.pti_total_system as f64 * nanos_per_tick / 1e9,
+        user_time_seconds: task.pti_total_user as f64 * nanos_per_tick / 1e9,
+        uptime_seconds: now.saturating_sub(start).as_secs_f64(),
+        rss_bytes: task.pti_resident_size,
+        vsize_bytes: task.pti_virtual_size,
+        num_threads: task.pti_threadnum,
+    })
+}
@@ -0,0 +1,237 @@
+//! Account for load between paired generators and blackholes
+//!
+//! Lading's byte totals are process wide: with several generators and
+//! blackholes in one experiment the bytes one blackhole receives say nothing
+//! of which generator's load they were. A pair declares that the load offered
+//! by some generators is expected back at some blackholes, for instance the
+//! logs generator and the blackhole the target forwards logs to. Once a second
+//! [`Server`] reports, per pair, the bytes its generators have written and its
+//! blackholes received, labeled `pair`, along with the bytes lost and the
+//! efficiency, the fraction of written bytes received. The totals of each pair
+//! are logged as lading shuts down.
+
+use metrics::gauge;
+use serde::Deserialize;
+use tokio::time::{self, Duration};
+use tracing::info;
+
+use crate::{blackhole, generator, signals::Shutdown, status};
+
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+/// Configuration of a single pair
+pub struct Config {
+    /// The indices of the generators whose load the pair offers, in order of
+    /// the configuration's `generator` section
+    pub generators: Vec<usize>,
+    /// The indices of the blackholes the pair expects the load back at, in
+    /// order of the configuration's `blackhole` section
+    pub blackholes: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Errors produced by [`Server`]
+pub enum Error {
+    /// A pair names a generator index that is not configured.
+    UnknownGenerator {
+        /// The index of the pair
+        pair: usize,
+        /// The unknown generator index
+        generator: usize,
+    },
+    /// A pair names a blackhole index that is not configured.
+    UnknownBlackhole {
+        /// The index of the pair
+        pair: usize,
+        /// The unknown blackhole index
+        blackhole: usize,
+    },
+}
+
+/// The load of a single pair.
+#[derive(Debug)]
+struct Pair {
+    name: String,
+    generators: Vec<generator::Meter>,
+    blackholes: Vec<blackhole::Meter>,
+}
+
+impl Pair {
+    fn bytes_written(&self) -> u64 {
+        self.generators.iter().map(generator::Meter::total).sum()
+    }
+
+    fn bytes_received(&self) -> u64 {
+        self.blackholes.iter().map(blackhole::Meter::total).sum()
+    }
+}
+
+/// Return the bytes lost of `bytes_written`, given `bytes_received`. A
+/// blackhole may receive more than was written, the target adding framing or
+/// metadata of its own, in which case nothing is lost.
+#[must_use]
+pub fn bytes_lost(bytes_written: u64, bytes_received: u64) -> u64 {
+    bytes_written.saturating_sub(bytes_received)
+}
+
+#[derive(Debug)]
+/// Reports the load of each configured pair.
+pub struct Server {
+    pairs: Vec<Pair>,
+    shutdown: Shutdown,
+}
+
+impl Server {
+    /// Create a new [`Server`] instance of the pairs `configs`, given the
+    /// meters of the configured generators and blackholes in configuration
+    /// order.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if a pair names a generator or blackhole
+    /// that is not configured.
+    pub fn new(
+        configs: &[Config],
+        generators: &[generator::Meter],
+        blackholes: &[blackhole::Meter],
+        shutdown: Shutdown,
+    ) -> Result<Self, Error> {
+        let mut pairs = Vec::with_capacity(configs.len());
+        for (idx, config) in configs.iter().enumerate() {
+            let generators = config
+                .generators
+                .iter()
+                .map(|&generator| {
+                    generators
+                        .get(generator)
+                        .cloned()
+                        .ok_or(Error::UnknownGenerator {
+                            pair: idx,
+                            generator,
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let blackholes = config
+                .blackholes
+                .iter()
+                .map(|&blackhole| {
+                    blackholes
+                        .get(blackhole)
+                        .cloned()
+                        .ok_or(Error::UnknownBlackhole {
+                            pair: idx,
+                            blackhole,
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            pairs.push(Pair {
+                name: format!("pair_{}", idx),
+                generators,
+                blackholes,
+            });
+        }
+        Ok(Self { pairs, shutdown })
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn report(&self) {
+        for pair in &self.pairs {
+            let labels = vec![("pair".to_string(), pair.name.clone())];
+            let bytes_written = pair.bytes_written();
+            let bytes_received = pair.bytes_received();
+            gauge!("pair_bytes_written", bytes_written as f64, &labels);
+            gauge!("pair_bytes_received", bytes_received as f64, &labels);
+            gauge!(
+                "pair_bytes_lost",
+                bytes_lost(bytes_written, bytes_received) as f64,
+                &labels
+            );
+            if let Some(efficiency) = status::efficiency(bytes_written, bytes_received) {
+                gauge!("pair_efficiency", efficiency, &labels);
+            }
+        }
+    }
+
+    /// Run this [`Server`] to completion
+    ///
+    /// This function reports the load of each pair once a second until a
+    /// shutdown signal is received, then reports and logs the pairs' totals.
+    ///
+    /// # Errors
+    ///
+    /// None known.
+    pub async fn run(mut self) -> Result<(), Error> {
+        let mut report_delay = time::interval(Duration::from_secs(1));
+
+        loop {
+            tokio::select! {
+                _ = report_delay.tick() => self.report(),
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    self.report();
+                    for pair in &self.pairs {
+                        let bytes_written = pair.bytes_written();
+                        let bytes_received = pair.bytes_received();
+                        match status::efficiency(bytes_written, bytes_received) {
+                            Some(efficiency) => info!(
+                                "{}: generators wrote {} bytes, blackholes received {} bytes, lost {} bytes, efficiency {:.4}",
+                                pair.name,
+                                bytes_written,
+                                bytes_received,
+                                bytes_lost(bytes_written, bytes_received),
+                                efficiency
+                            ),
+                            None => info!(
+                                "{}: generators wrote no bytes, blackholes received {} bytes",
+                                pair.name, bytes_received
+                            ),
+                        }
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::{Config, Error, Server};
+    use crate::{blackhole, generator, signals::Shutdown};
+
+    // A pair is accepted if and only if it names configured components.
+    proptest! {
+        #[test]
+        fn pairs_name_configured_components(
+            generators in 0_usize..4,
+            blackholes in 0_usize..4,
+            generator in 0_usize..8,
+            blackhole in 0_usize..8,
+        ) {
+            let config = Config {
+                generators: vec![generator],
+                blackholes: vec![blackhole],
+            };
+            let result = Server::new(
+                &[config],
+                &vec![generator::Meter::default(); generators],
+                &vec![blackhole::Meter::default(); blackholes],
+                Shutdown::new(),
+            );
+            match result {
+                Ok(_) => prop_assert!(generator < generators && blackhole < blackholes),
+                Err(Error::UnknownGenerator { pair, generator: idx }) => {
+                    prop_assert_eq!(pair, 0);
+                    prop_assert_eq!(idx, generator);
+                    prop_assert!(generator >= generators);
+                }
+                Err(Error::UnknownBlackhole { pair, blackhole: idx }) => {
+                    prop_assert_eq!(pair, 0);
+                    prop_assert_eq!(idx, blackhole);
+                    prop_assert!(generator < generators && blackhole >= blackholes);
+                }
+            }
+        }
+    }
+}
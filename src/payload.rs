@@ -3,13 +3,19 @@ use std::io::{self, Write};
 pub(crate) use apache_common::ApacheCommon;
 pub(crate) use ascii::Ascii;
 pub(crate) use datadog_logs::DatadogLog;
-pub(crate) use fluent::Fluent;
+pub(crate) use fluent::{chunk_placeholders, Fluent, CHUNK_ID_LEN};
 pub(crate) use foundationdb::FoundationDb;
+pub(crate) use graphite::{Config as GraphiteConfig, Graphite};
+pub(crate) use influx::{Config as InfluxConfig, Influx};
 pub(crate) use json::Json;
+pub(crate) use opentelemetry_traces::{Config as OpentelemetryTracesConfig, OpentelemetryTraces};
 use rand::Rng;
+use serde::Deserialize;
 pub(crate) use splunk_hec::{Encoding as SplunkHecEncoding, SplunkHec};
 pub(crate) use statik::Static;
-pub(crate) use syslog::Syslog5424;
+pub(crate) use syslog::{Config as Syslog5424Config, Syslog5424};
+pub(crate) use vector_native::{Config as VectorNativeConfig, VectorNative};
+pub(crate) use zipkin::{Config as ZipkinConfig, Zipkin};
 
 mod apache_common;
 mod ascii;
@@ -17,10 +23,16 @@ mod common;
 mod datadog_logs;
 mod fluent;
 mod foundationdb;
+mod graphite;
+mod influx;
 mod json;
+mod opentelemetry_traces;
+pub(crate) mod protobuf;
 mod splunk_hec;
 mod statik;
 mod syslog;
+mod vector_native;
+mod zipkin;
 
 /// Errors related to serialization
 #[derive(Debug)]
@@ -59,6 +71,91 @@ impl From<io::Error> for Error {
     }
 }
 
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// The treatment of events larger than [`EventLimit::max_event_bytes`].
+pub enum Oversize {
+    /// Truncate the event to the maximum size.
+    Truncate,
+    /// Split the event into as many events of at most the maximum size as are
+    /// needed.
+    Split,
+}
+
+impl Default for Oversize {
+    fn default() -> Self {
+        Self::Truncate
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+/// A limit on the size of the newline delimited events of a payload.
+///
+/// Only meaningful for line oriented payloads. Events that are truncated or
+/// split will generally no longer parse as their payload's format, which may
+/// be exactly what the user wants to exercise.
+pub struct EventLimit {
+    /// The maximum size of an event, not including its newline.
+    pub max_event_bytes: byte_unit::Byte,
+    /// The treatment of events larger than `max_event_bytes`.
+    #[serde(default)]
+    pub oversize: Oversize,
+}
+
+impl EventLimit {
+    /// Copy the newline delimited events of `input` into `output`, enforcing
+    /// this limit. No more than `max_bytes` total are written. Returns the
+    /// number of oversize events that were written.
+    pub(crate) fn apply(&self, input: &[u8], max_bytes: usize, output: &mut Vec<u8>) -> u64 {
+        let max_event = usize::try_from(self.max_event_bytes.get_bytes())
+            .unwrap_or(usize::MAX)
+            .max(1);
+        let mut oversize_events = 0;
+        'events: for line in input.split_inclusive(|b| *b == b'\n') {
+            let (event, newline): (&[u8], &[u8]) = match line.split_last() {
+                Some((b'\n', event)) => (event, b"\n"),
+                _ => (line, b""),
+            };
+            if event.len() <= max_event {
+                if output.len() + line.len() > max_bytes {
+                    break;
+                }
+                output.extend_from_slice(line);
+                continue;
+            }
+            match self.oversize {
+                Oversize::Truncate => {
+                    if output.len() + max_event + newline.len() > max_bytes {
+                        break;
+                    }
+                    output.extend_from_slice(&event[..max_event]);
+                    output.extend_from_slice(newline);
+                }
+                Oversize::Split => {
+                    let mut pieces = event.chunks(max_event).peekable();
+                    while let Some(piece) = pieces.next() {
+                        // Every piece but the last is terminated by a newline
+                        // of our own, the last by the event's own, if any.
+                        let terminator: &[u8] = if pieces.peek().is_some() {
+                            b"\n"
+                        } else {
+                            newline
+                        };
+                        if output.len() + piece.len() + terminator.len() > max_bytes {
+                            oversize_events += 1;
+                            break 'events;
+                        }
+                        output.extend_from_slice(piece);
+                        output.extend_from_slice(terminator);
+                    }
+                }
+            }
+            oversize_events += 1;
+        }
+        oversize_events
+    }
+}
+
 pub(crate) trait Serialize {
     /// Write bytes into writer, subject to `max_bytes` limitations.
     ///
@@ -71,3 +168,49 @@ pub(crate) trait Serialize {
         R: Rng + Sized,
         W: Write;
 }
+
+#[cfg(test)]
+mod test {
+    use byte_unit::Byte;
+    use proptest::{collection, prelude::*};
+
+    use super::{EventLimit, Oversize};
+
+    fn oversize() -> impl Strategy<Value = Oversize> {
+        prop_oneof![Just(Oversize::Truncate), Just(Oversize::Split)]
+    }
+
+    // No event written by an `EventLimit` exceeds its maximum size, and no
+    // more than `max_bytes` are written in total.
+    proptest! {
+        #[test]
+        fn events_never_exceed_limit(input in collection::vec(any::<u8>(), 0..4_096), max_event_bytes in 1..512_u128, max_bytes in 0..4_096_usize, oversize in oversize()) {
+            let limit = EventLimit {
+                max_event_bytes: Byte::from_bytes(max_event_bytes),
+                oversize,
+            };
+            let mut output = Vec::new();
+            limit.apply(&input, max_bytes, &mut output);
+            prop_assert!(output.len() <= max_bytes);
+            for event in output.split(|b| *b == b'\n') {
+                prop_assert!(event.len() as u128 <= max_event_bytes);
+            }
+        }
+    }
+
+    // Splitting preserves every byte of the input's events when there is room
+    // enough to write them.
+    proptest! {
+        #[test]
+        fn split_preserves_event_bytes(input in collection::vec(any::<u8>(), 0..4_096), max_event_bytes in 1..512_u128) {
+            let limit = EventLimit {
+                max_event_bytes: Byte::from_bytes(max_event_bytes),
+                oversize: Oversize::Split,
+            };
+            let mut output = Vec::new();
+            limit.apply(&input, usize::MAX, &mut output);
+            let strip = |bytes: &[u8]| bytes.iter().copied().filter(|b| *b != b'\n').collect::<Vec<u8>>();
+            prop_assert_eq!(strip(&input), strip(&output));
+        }
+    }
+}
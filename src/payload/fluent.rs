@@ -1,16 +1,171 @@
 //! Implements [this
 //! protocol](https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1).
+//!
+//! By default a mix of Message and Forward mode events is produced. In packed
+//! mode entries are instead grouped into PackedForward messages, optionally
+//! gzip compressed as CompressedPackedForward, as fluent-bit sends them. Packed
+//! messages may request acknowledgement, carrying a `chunk` id in their option
+//! for the target to acknowledge. Blocks are cached and sent repeatedly, so
+//! the ids in a block are placeholders, each overwritten with a fresh id as the
+//! block is sent. See [`chunk_placeholders`].
 use std::{collections::HashMap, io::Write};
 
-use arbitrary::{size_hint, Unstructured};
+use arbitrary::{size_hint, Arbitrary, Unstructured};
+use flate2::{write::GzEncoder, Compression};
 use rand::Rng;
 use serde_tuple::Serialize_tuple;
 
 use super::common::AsciiStr;
 use crate::payload::{Error, Serialize};
 
+/// The length in characters of a chunk id.
+pub(crate) const CHUNK_ID_LEN: usize = 32;
+
+/// The chunk id written into every message requesting acknowledgement.
+const CHUNK_PLACEHOLDER: [u8; CHUNK_ID_LEN] = [b'0'; CHUNK_ID_LEN];
+
+/// The msgpack encoding of the option key `chunk` followed by the str8 header
+/// of a [`CHUNK_ID_LEN`] character id.
+#[allow(clippy::cast_possible_truncation)]
+const CHUNK_KEY: [u8; 8] = [0xa5, b'c', b'h', b'u', b'n', b'k', 0xd9, CHUNK_ID_LEN as u8];
+
+/// The offsets in `bytes` of each placeholder chunk id, in order.
+pub(crate) fn chunk_placeholders(bytes: &[u8]) -> Vec<usize> {
+    let width = CHUNK_KEY.len() + CHUNK_ID_LEN;
+    let mut offsets = Vec::new();
+    let mut start = 0;
+    while start + width <= bytes.len() {
+        let window = &bytes[start..start + width];
+        if window[..CHUNK_KEY.len()] == CHUNK_KEY && window[CHUNK_KEY.len()..] == CHUNK_PLACEHOLDER
+        {
+            offsets.push(start + CHUNK_KEY.len());
+            start += width;
+        } else {
+            start += 1;
+        }
+    }
+    offsets
+}
+
 #[derive(Debug, Default, Clone, Copy)]
-pub(crate) struct Fluent {}
+pub(crate) struct Fluent {
+    packed: Option<Packed>,
+}
+
+impl Fluent {
+    /// Create a [`Fluent`] producing PackedForward messages, each holding no
+    /// more than `maximum_chunk_bytes` of entries before compression. If
+    /// `compressed` is set entries are gzip compressed, producing
+    /// CompressedPackedForward messages. If `require_ack` is set each message
+    /// requests acknowledgement of a placeholder chunk id.
+    #[must_use]
+    pub(crate) fn packed_forward(
+        maximum_chunk_bytes: usize,
+        compressed: bool,
+        require_ack: bool,
+    ) -> Self {
+        Self {
+            packed: Some(Packed {
+                maximum_chunk_bytes,
+                compressed,
+                require_ack,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Packed {
+    maximum_chunk_bytes: usize,
+    compressed: bool,
+    require_ack: bool,
+}
+
+/// The option map of a PackedForward message.
+#[derive(serde::Serialize)]
+struct PackedOption {
+    size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compressed: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chunk: Option<String>,
+}
+
+/// Serializes as msgpack bin, as PackedForward entries are required to be.
+struct Bin<'a>(&'a [u8]);
+
+impl serde::Serialize for Bin<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+impl Packed {
+    /// Encode `size` msgpack encoded `entries` into a single message,
+    /// requesting acknowledgement if configured to.
+    fn encode(&self, tag: &str, entries: &[u8], size: usize) -> Result<Vec<u8>, Error> {
+        let option = PackedOption {
+            size,
+            compressed: self.compressed.then(|| "gzip"),
+            chunk: self
+                .require_ack
+                .then(|| String::from_utf8(CHUNK_PLACEHOLDER.to_vec()).unwrap()),
+        };
+        let encoding = if self.compressed {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(entries)?;
+            let entries = encoder.finish()?;
+            rmp_serde::to_vec_named(&(tag, Bin(&entries), option))?
+        } else {
+            rmp_serde::to_vec_named(&(tag, Bin(entries), option))?
+        };
+        Ok(encoding)
+    }
+
+    fn write_messages<W>(
+        &self,
+        mut unstructured: Unstructured<'_>,
+        max_bytes: usize,
+        writer: &mut W,
+    ) -> Result<(), Error>
+    where
+        W: Write,
+    {
+        let tag = unstructured.arbitrary::<AsciiStr>()?;
+        let entries = <Vec<Entry> as Arbitrary>::arbitrary_take_rest(unstructured)?;
+
+        let mut bytes_remaining = max_bytes;
+        let mut chunk: Vec<u8> = Vec::new();
+        let mut chunk_size = 0;
+        for entry in entries {
+            let encoding = rmp_serde::to_vec(&entry)?;
+            if chunk_size > 0 && chunk.len() + encoding.len() > self.maximum_chunk_bytes {
+                let message = self.encode(tag.as_str(), &chunk, chunk_size)?;
+                match bytes_remaining.checked_sub(message.len()) {
+                    Some(remainder) => {
+                        writer.write_all(&message)?;
+                        bytes_remaining = remainder;
+                    }
+                    None => return Ok(()),
+                }
+                chunk.clear();
+                chunk_size = 0;
+            }
+            chunk.extend_from_slice(&encoding);
+            chunk_size += 1;
+        }
+        if chunk_size > 0 {
+            let message = self.encode(tag.as_str(), &chunk, chunk_size)?;
+            if message.len() <= bytes_remaining {
+                writer.write_all(&message)?;
+            }
+        }
+        Ok(())
+    }
+}
 
 #[derive(serde::Serialize)]
 #[serde(untagged)]
@@ -185,6 +340,10 @@ impl Serialize for Fluent {
         rng.fill_bytes(&mut entropy);
         let unstructured = Unstructured::new(&entropy);
 
+        if let Some(packed) = self.packed {
+            return packed.write_messages(unstructured, max_bytes, writer);
+        }
+
         let members = <Vec<Member> as arbitrary::Arbitrary>::arbitrary_take_rest(unstructured)?;
         let members: Vec<Vec<u8>> = members
             .into_iter()
@@ -214,6 +373,7 @@ mod test {
     use proptest::prelude::*;
     use rand::{rngs::SmallRng, SeedableRng};
 
+    use super::chunk_placeholders;
     use crate::payload::{Fluent, Serialize};
 
     // We want to be sure that the serialized size of the payload does not
@@ -234,4 +394,40 @@ mod test {
             );
         }
     }
+
+    // Packed payloads, compressed or not, must also not exceed `max_bytes`.
+    proptest! {
+        #[test]
+        fn packed_payload_not_exceed_max_bytes(seed: u64, max_bytes: u16, maximum_chunk_bytes in 1..4_096_usize, compressed: bool, require_ack: bool) {
+            let max_bytes = max_bytes as usize;
+            let rng = SmallRng::seed_from_u64(seed);
+            let fluent = Fluent::packed_forward(maximum_chunk_bytes, compressed, require_ack);
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            fluent.to_bytes(rng, max_bytes, &mut bytes).unwrap();
+            prop_assert!(bytes.len() <= max_bytes);
+        }
+    }
+
+    // Every message requesting acknowledgement carries exactly one placeholder
+    // chunk id, and only those messages do.
+    proptest! {
+        #[test]
+        fn one_placeholder_per_message(seed: u64, max_bytes: u16, maximum_chunk_bytes in 1..4_096_usize, compressed: bool, require_ack: bool) {
+            let max_bytes = max_bytes as usize;
+            let rng = SmallRng::seed_from_u64(seed);
+            let fluent = Fluent::packed_forward(maximum_chunk_bytes, compressed, require_ack);
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            fluent.to_bytes(rng, max_bytes, &mut bytes).unwrap();
+            let mut messages = 0;
+            let mut cursor = std::io::Cursor::new(&bytes[..]);
+            while (cursor.position() as usize) < bytes.len() {
+                rmp_serde::from_read::<_, serde::de::IgnoredAny>(&mut cursor).unwrap();
+                messages += 1;
+            }
+            let placeholders = chunk_placeholders(&bytes).len();
+            prop_assert_eq!(placeholders, if require_ack { messages } else { 0 });
+        }
+    }
 }
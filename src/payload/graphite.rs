@@ -0,0 +1,172 @@
+use std::{
+    io::Write,
+    num::{NonZeroU32, NonZeroU8},
+};
+
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::payload::{Error, Serialize};
+
+/// The earliest timestamp of a metric, 2022-01-01T00:00:00Z in seconds. Times
+/// are drawn from the seed, not the wall clock, so that payloads are
+/// deterministic.
+const EPOCH_SECONDS: u64 = 1_640_995_200;
+/// The span of timestamps after [`EPOCH_SECONDS`], a day in seconds.
+const TIMESTAMP_RANGE_SECONDS: u64 = 86_400;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+/// Configuration for the shape of [`Graphite`] payloads.
+pub struct Config {
+    /// The number of segments of each metric path, by default 4
+    #[serde(default = "default_depth")]
+    pub depth: NonZeroU8,
+    /// The number of distinct metric paths, the unique series, by default
+    /// 1000
+    #[serde(default = "default_series")]
+    pub series: NonZeroU32,
+}
+
+fn default_depth() -> NonZeroU8 {
+    NonZeroU8::new(4).unwrap()
+}
+
+fn default_series() -> NonZeroU32 {
+    NonZeroU32::new(1_000).unwrap()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            depth: default_depth(),
+            series: default_series(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+/// Generates Graphite plaintext protocol metrics, one per line.
+///
+/// Each metric is one of `series` paths of `depth` segments, the paths forming
+/// a tree whose every level branches equally, with a value and a timestamp in
+/// seconds.
+pub(crate) struct Graphite {
+    config: Config,
+}
+
+impl Graphite {
+    #[must_use]
+    pub(crate) fn new(config: Config) -> Self {
+        Self { config }
+    }
+
+    /// The number of children of each node of the tree of paths, the least
+    /// that gives the tree `series` leaves at `depth`: the `depth`th root of
+    /// `series`, rounded up. The floating point estimate is corrected to the
+    /// exact integer root.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn branching(&self) -> u64 {
+        let series = self.config.series.get();
+        let depth = u32::from(self.config.depth.get());
+        let too_few = |branching: u64| {
+            branching
+                .checked_pow(depth)
+                .map_or(false, |leaves| leaves < u64::from(series))
+        };
+        let mut branching = (f64::from(series).powf(1.0 / f64::from(depth)).round() as u64).max(1);
+        while too_few(branching) {
+            branching += 1;
+        }
+        while branching > 1 && !too_few(branching - 1) {
+            branching -= 1;
+        }
+        branching
+    }
+
+    /// Return the path of series `series`.
+    fn path(&self, branching: u64, mut series: u64) -> String {
+        let depth = self.config.depth.get();
+        let mut segments = Vec::with_capacity(usize::from(depth));
+        for level in 0..depth {
+            segments.push(format!("level_{}_{}", level, series % branching));
+            series /= branching;
+        }
+        segments.reverse();
+        segments.join(".")
+    }
+}
+
+impl Serialize for Graphite {
+    fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        let branching = self.branching();
+        let mut bytes_remaining = max_bytes;
+        loop {
+            let series = rng.gen_range(0..self.config.series.get());
+            let value: f64 = rng.gen_range(0.0..1_000.0);
+            let timestamp = EPOCH_SECONDS + rng.gen_range(0..TIMESTAMP_RANGE_SECONDS);
+            let line = format!(
+                "{} {} {}\n",
+                self.path(branching, u64::from(series)),
+                value,
+                timestamp
+            );
+            match bytes_remaining.checked_sub(line.len()) {
+                Some(remainder) => {
+                    writer.write_all(line.as_bytes())?;
+                    bytes_remaining = remainder;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashSet,
+        num::{NonZeroU32, NonZeroU8},
+    };
+
+    use proptest::prelude::*;
+
+    use super::{Config, Graphite};
+
+    // The branching is the least whose tree has a leaf for every series.
+    proptest! {
+        #[test]
+        fn branching_least_sufficient(depth in 1_u8..=u8::MAX, series in 1_u32..=u32::MAX) {
+            let branching = Graphite::new(Config {
+                depth: NonZeroU8::new(depth).unwrap(),
+                series: NonZeroU32::new(series).unwrap(),
+            })
+            .branching();
+            let leaves = |branching: u64| branching.checked_pow(u32::from(depth)).unwrap_or(u64::MAX);
+            prop_assert!(leaves(branching) >= u64::from(series));
+            prop_assert!(branching == 1 || leaves(branching - 1) < u64::from(series));
+        }
+    }
+
+    // Each series has a path of its own of exactly `depth` segments.
+    proptest! {
+        #[test]
+        fn series_have_distinct_paths(depth in 1_u8..8, series in 1_u32..2_000) {
+            let graphite = Graphite::new(Config {
+                depth: NonZeroU8::new(depth).unwrap(),
+                series: NonZeroU32::new(series).unwrap(),
+            });
+            let branching = graphite.branching();
+            let mut paths = HashSet::new();
+            for idx in 0..u64::from(series) {
+                let path = graphite.path(branching, idx);
+                prop_assert_eq!(path.split('.').count(), usize::from(depth));
+                prop_assert!(paths.insert(path));
+            }
+        }
+    }
+}
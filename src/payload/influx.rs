@@ -0,0 +1,174 @@
+use std::{
+    io::Write,
+    num::{NonZeroU32, NonZeroU8},
+};
+
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::payload::{Error, Serialize};
+
+/// The earliest timestamp of a point, 2022-01-01T00:00:00Z in nanoseconds.
+/// Times are drawn from the seed, not the wall clock, so that payloads are
+/// deterministic.
+const EPOCH_NANOS: u64 = 1_640_995_200_000_000_000;
+/// The span of timestamps after [`EPOCH_NANOS`], a day in nanoseconds.
+const TIMESTAMP_RANGE_NANOS: u64 = 86_400_000_000_000;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+/// Configuration for the shape of [`Influx`] payloads.
+pub struct Config {
+    /// The number of distinct measurements, by default 10
+    #[serde(default = "default_measurements")]
+    pub measurements: NonZeroU32,
+    /// The number of tags on each point, by default 4
+    #[serde(default = "default_tags_per_point")]
+    pub tags_per_point: u8,
+    /// The number of distinct values each tag takes, by default 10. The
+    /// series of a payload number at most `measurements` times this many to
+    /// the power of `tags_per_point`.
+    #[serde(default = "default_tag_values")]
+    pub tag_values: NonZeroU32,
+    /// The number of fields of each point, by default 4
+    #[serde(default = "default_fields_per_point")]
+    pub fields_per_point: NonZeroU8,
+}
+
+fn default_measurements() -> NonZeroU32 {
+    NonZeroU32::new(10).unwrap()
+}
+
+fn default_tags_per_point() -> u8 {
+    4
+}
+
+fn default_tag_values() -> NonZeroU32 {
+    NonZeroU32::new(10).unwrap()
+}
+
+fn default_fields_per_point() -> NonZeroU8 {
+    NonZeroU8::new(4).unwrap()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            measurements: default_measurements(),
+            tags_per_point: default_tags_per_point(),
+            tag_values: default_tag_values(),
+            fields_per_point: default_fields_per_point(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+/// Generates points in InfluxDB line protocol, one per line.
+///
+/// Each point is of one of `measurements` measurements and carries
+/// `tags_per_point` tags, each drawn from `tag_values` values, and
+/// `fields_per_point` fields, floats, integers and booleans in turn, with a
+/// nanosecond timestamp.
+pub(crate) struct Influx {
+    config: Config,
+}
+
+impl Influx {
+    #[must_use]
+    pub(crate) fn new(config: Config) -> Self {
+        Self { config }
+    }
+
+    /// Encode one point, including its newline.
+    fn point<R>(&self, rng: &mut R) -> String
+    where
+        R: Rng,
+    {
+        let mut point = format!(
+            "measurement_{}",
+            rng.gen_range(0..self.config.measurements.get())
+        );
+        for tag in 0..self.config.tags_per_point {
+            let value = rng.gen_range(0..self.config.tag_values.get());
+            point.push_str(&format!(",tag_{}=value_{}", tag, value));
+        }
+        for field in 0..self.config.fields_per_point.get() {
+            let separator = if field == 0 { ' ' } else { ',' };
+            let value = match field % 3 {
+                0 => format!("{}", rng.gen_range(0.0_f64..1_000.0)),
+                1 => format!("{}i", rng.gen_range(-1_000_000_i64..1_000_000)),
+                _ => rng.gen::<bool>().to_string(),
+            };
+            point.push_str(&format!("{}field_{}={}", separator, field, value));
+        }
+        let timestamp = EPOCH_NANOS + rng.gen_range(0..TIMESTAMP_RANGE_NANOS);
+        point.push_str(&format!(" {}\n", timestamp));
+        point
+    }
+}
+
+impl Serialize for Influx {
+    fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        let mut bytes_remaining = max_bytes;
+        loop {
+            let point = self.point(&mut rng);
+            match bytes_remaining.checked_sub(point.len()) {
+                Some(remainder) => {
+                    writer.write_all(point.as_bytes())?;
+                    bytes_remaining = remainder;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::{NonZeroU32, NonZeroU8};
+
+    use proptest::prelude::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::Config;
+    use crate::payload::{Influx, Serialize};
+
+    // Every point is a measurement with the configured number of tags, the
+    // configured number of fields and a timestamp, no larger in total than
+    // `max_bytes`.
+    proptest! {
+        #[test]
+        fn points_have_configured_shape(
+            seed: u64,
+            max_bytes: u16,
+            tags_per_point in 0_u8..8,
+            fields_per_point in 1_u8..8,
+        ) {
+            let max_bytes = max_bytes as usize;
+            let rng = SmallRng::seed_from_u64(seed);
+            let influx = Influx::new(Config {
+                measurements: NonZeroU32::new(10).unwrap(),
+                tags_per_point,
+                tag_values: NonZeroU32::new(10).unwrap(),
+                fields_per_point: NonZeroU8::new(fields_per_point).unwrap(),
+            });
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            influx.to_bytes(rng, max_bytes, &mut bytes).unwrap();
+            prop_assert!(bytes.len() <= max_bytes);
+
+            let payload = std::str::from_utf8(&bytes).unwrap();
+            for point in payload.lines() {
+                let parts: Vec<&str> = point.split(' ').collect();
+                prop_assert_eq!(parts.len(), 3);
+                prop_assert_eq!(parts[0].split(',').count(), 1 + usize::from(tags_per_point));
+                prop_assert_eq!(parts[1].split(',').count(), usize::from(fields_per_point));
+                prop_assert!(parts[2].parse::<u64>().is_ok());
+            }
+        }
+    }
+}
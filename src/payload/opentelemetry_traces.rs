@@ -0,0 +1,200 @@
+use std::{
+    io::Write,
+    num::{NonZeroU16, NonZeroU32},
+};
+
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::payload::{
+    protobuf::{put_bytes, put_fixed64, put_key, put_varint, VARINT},
+    Error, Serialize,
+};
+
+/// The earliest start time of a span, 2022-01-01T00:00:00Z in nanoseconds.
+/// Times are drawn from the seed, not the wall clock, so that payloads are
+/// deterministic.
+const EPOCH_NANOS: u64 = 1_640_995_200_000_000_000;
+/// The span of start times after [`EPOCH_NANOS`], a day in nanoseconds.
+const START_RANGE_NANOS: u64 = 86_400_000_000_000;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+/// Configuration for the shape of [`OpentelemetryTraces`] payloads.
+pub struct Config {
+    /// The number of spans in each trace, by default 8
+    #[serde(default = "default_spans_per_trace")]
+    pub spans_per_trace: NonZeroU16,
+    /// The number of attributes on each trace's resource, by default 4
+    #[serde(default = "default_resource_attributes")]
+    pub resource_attributes: u8,
+    /// The number of distinct values each resource attribute takes, by
+    /// default 10. The resources of a payload number at most this many to the
+    /// power of `resource_attributes`.
+    #[serde(default = "default_resource_attribute_values")]
+    pub resource_attribute_values: NonZeroU32,
+}
+
+fn default_spans_per_trace() -> NonZeroU16 {
+    NonZeroU16::new(8).unwrap()
+}
+
+fn default_resource_attributes() -> u8 {
+    4
+}
+
+fn default_resource_attribute_values() -> NonZeroU32 {
+    NonZeroU32::new(10).unwrap()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            spans_per_trace: default_spans_per_trace(),
+            resource_attributes: default_resource_attributes(),
+            resource_attribute_values: default_resource_attribute_values(),
+        }
+    }
+}
+
+/// Encode a `KeyValue` whose value is an `AnyValue` string.
+fn key_value(key: &str, value: &str) -> Vec<u8> {
+    let mut any_value = Vec::with_capacity(value.len() + 2);
+    put_bytes(1, value.as_bytes(), &mut any_value);
+    let mut kv = Vec::with_capacity(key.len() + any_value.len() + 4);
+    put_bytes(1, key.as_bytes(), &mut kv);
+    put_bytes(2, &any_value, &mut kv);
+    kv
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+#[allow(clippy::module_name_repetitions)]
+/// Generates OTLP `ExportTraceServiceRequest` messages, protobuf encoded.
+///
+/// Each `ResourceSpans` of a message holds one trace: a resource with
+/// `resource_attributes` attributes, each drawn from
+/// `resource_attribute_values` values, and `spans_per_trace` spans sharing a
+/// trace id, the first the root and each other span the child of one before
+/// it.
+pub(crate) struct OpentelemetryTraces {
+    config: Config,
+}
+
+impl OpentelemetryTraces {
+    #[must_use]
+    pub(crate) fn new(config: Config) -> Self {
+        Self { config }
+    }
+
+    /// Encode one `ResourceSpans` message, holding one trace.
+    fn resource_spans<R>(&self, rng: &mut R) -> Vec<u8>
+    where
+        R: Rng,
+    {
+        let mut resource = Vec::new();
+        let service = rng.gen_range(0..self.config.resource_attribute_values.get());
+        put_bytes(
+            1,
+            &key_value("service.name", &format!("service_{}", service)),
+            &mut resource,
+        );
+        for attribute in 0..self.config.resource_attributes {
+            let value = rng.gen_range(0..self.config.resource_attribute_values.get());
+            put_bytes(
+                1,
+                &key_value(
+                    &format!("resource.attribute_{}", attribute),
+                    &format!("value_{}", value),
+                ),
+                &mut resource,
+            );
+        }
+
+        let mut scope = Vec::new();
+        put_bytes(1, b"lading", &mut scope);
+        let mut scope_spans = Vec::new();
+        put_bytes(1, &scope, &mut scope_spans);
+
+        let trace_id: [u8; 16] = rng.gen();
+        let mut span_ids: Vec<[u8; 8]> =
+            Vec::with_capacity(usize::from(self.config.spans_per_trace.get()));
+        let trace_start = EPOCH_NANOS + rng.gen_range(0..START_RANGE_NANOS);
+        for idx in 0..self.config.spans_per_trace.get() {
+            let span_id: [u8; 8] = rng.gen();
+            let mut span = Vec::new();
+            put_bytes(1, &trace_id, &mut span);
+            put_bytes(2, &span_id, &mut span);
+            if !span_ids.is_empty() {
+                let parent = span_ids[rng.gen_range(0..span_ids.len())];
+                put_bytes(4, &parent, &mut span);
+            }
+            put_bytes(5, format!("operation_{}", idx).as_bytes(), &mut span);
+            // SPAN_KIND_SERVER for the root, SPAN_KIND_INTERNAL otherwise.
+            put_key(6, VARINT, &mut span);
+            put_varint(if span_ids.is_empty() { 2 } else { 1 }, &mut span);
+            let start = trace_start + rng.gen_range(0..1_000_000_000);
+            put_fixed64(7, start, &mut span);
+            put_fixed64(8, start + rng.gen_range(1_000..1_000_000_000), &mut span);
+            put_bytes(2, &span, &mut scope_spans);
+            span_ids.push(span_id);
+        }
+
+        let mut resource_spans = Vec::new();
+        put_bytes(1, &resource, &mut resource_spans);
+        put_bytes(2, &scope_spans, &mut resource_spans);
+        resource_spans
+    }
+}
+
+impl Serialize for OpentelemetryTraces {
+    fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        // An `ExportTraceServiceRequest` is its repeated `resource_spans`,
+        // field 1, and so a concatenation of them is itself a request.
+        let mut bytes_remaining = max_bytes;
+        loop {
+            let mut field = Vec::new();
+            put_bytes(1, &self.resource_spans(&mut rng), &mut field);
+            match bytes_remaining.checked_sub(field.len()) {
+                Some(remainder) => {
+                    writer.write_all(&field)?;
+                    bytes_remaining = remainder;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use crate::payload::{protobuf::read_varint, OpentelemetryTraces, Serialize};
+
+    // A payload is a sequence of length delimited `resource_spans` fields that
+    // ends exactly where it should and is no larger than `max_bytes`.
+    proptest! {
+        #[test]
+        fn payload_not_exceed_max_bytes(seed: u64, max_bytes: u16) {
+            let max_bytes = max_bytes as usize;
+            let rng = SmallRng::seed_from_u64(seed);
+            let traces = OpentelemetryTraces::default();
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            traces.to_bytes(rng, max_bytes, &mut bytes).unwrap();
+            prop_assert!(bytes.len() <= max_bytes);
+
+            let mut rest = &bytes[..];
+            while !rest.is_empty() {
+                prop_assert_eq!(rest[0], 0x0a);
+                let (length, read) = read_varint(&rest[1..]);
+                rest = &rest[1 + read + length as usize..];
+            }
+        }
+    }
+}
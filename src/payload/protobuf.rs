@@ -0,0 +1,71 @@
+//! The protobuf wire encoding, as far as the payloads encoding it by hand
+//! need. Varints are the ULEB128 encoding Thrift's compact protocol shares.
+
+/// The wire type of varint fields.
+pub(crate) const VARINT: u8 = 0;
+/// The wire type of 64-bit fixed width fields.
+pub(crate) const FIXED64: u8 = 1;
+/// The wire type of length delimited fields.
+pub(crate) const LEN: u8 = 2;
+
+/// Append `value` to `buf` as a ULEB128 varint.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn put_varint(mut value: u64, buf: &mut Vec<u8>) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Append the key of field `field` of `wire_type`.
+pub(crate) fn put_key(field: u32, wire_type: u8, buf: &mut Vec<u8>) {
+    put_varint(u64::from((field << 3) | u32::from(wire_type)), buf);
+}
+
+/// Append field `field` holding `bytes`, length delimited.
+pub(crate) fn put_bytes(field: u32, bytes: &[u8], buf: &mut Vec<u8>) {
+    put_key(field, LEN, buf);
+    put_varint(bytes.len() as u64, buf);
+    buf.extend_from_slice(bytes);
+}
+
+/// Append field `field` holding `value`, 64-bit fixed width.
+pub(crate) fn put_fixed64(field: u32, value: u64, buf: &mut Vec<u8>) {
+    put_key(field, FIXED64, buf);
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Read a varint from the front of `buf`, returning it and the bytes read.
+///
+/// # Panics
+///
+/// Panics if `buf` ends before the varint does.
+#[cfg(test)]
+pub(crate) fn read_varint(buf: &[u8]) -> (u64, usize) {
+    let mut value = 0;
+    for (idx, byte) in buf.iter().enumerate() {
+        value |= u64::from(byte & 0x7f) << (7 * idx);
+        if byte & 0x80 == 0 {
+            return (value, idx + 1);
+        }
+    }
+    panic!("truncated varint");
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::{put_varint, read_varint};
+
+    // Varints decode to the value encoded.
+    proptest! {
+        #[test]
+        fn varint_round_trip(value: u64) {
+            let mut buf = Vec::new();
+            put_varint(value, &mut buf);
+            prop_assert_eq!(read_varint(&buf), (value, buf.len()));
+        }
+    }
+}
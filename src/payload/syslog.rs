@@ -1,14 +1,87 @@
-use std::{io::Write, time::SystemTime};
+use std::{fmt::Write as _, io::Write, num::NonZeroU32, time::SystemTime};
 
 use arbitrary::{size_hint, Unstructured};
 use rand::Rng;
+use serde::Deserialize;
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
 use crate::payload::{Error, Serialize};
 
+const SD_CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789 ().,";
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+/// An inclusive range of byte lengths, chosen from uniformly.
+pub struct LengthRange {
+    /// The minimum length in bytes
+    pub minimum: u16,
+    /// The maximum length in bytes
+    pub maximum: u16,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+/// Configuration for the shape of [`Syslog5424`] messages.
+///
+/// The defaults produce messages with no structured data, a small fixed set of
+/// hostnames and app names and a short JSON message.
+pub struct Config {
+    /// The maximum number of SD-ELEMENTs per message, the number in any one
+    /// message being chosen uniformly from zero to this value.
+    #[serde(default)]
+    pub maximum_sd_elements: u8,
+    /// The maximum number of SD-PARAMs per SD-ELEMENT, the number in any one
+    /// element being chosen uniformly from one to this value.
+    #[serde(default = "default_maximum_sd_params")]
+    pub maximum_sd_params: u8,
+    /// The length of each SD-PARAM value.
+    #[serde(default = "default_sd_param_value_bytes")]
+    pub sd_param_value_bytes: LengthRange,
+    /// The number of distinct hostnames. If unset a small fixed set is used.
+    #[serde(default)]
+    pub hostname_cardinality: Option<NonZeroU32>,
+    /// The number of distinct app names. If unset a small fixed set is used.
+    #[serde(default)]
+    pub app_name_cardinality: Option<NonZeroU32>,
+    /// The length of each message. If unset messages are short JSON objects.
+    #[serde(default)]
+    pub message_bytes: Option<LengthRange>,
+}
+
+fn default_maximum_sd_params() -> u8 {
+    4
+}
+
+fn default_sd_param_value_bytes() -> LengthRange {
+    LengthRange {
+        minimum: 1,
+        maximum: 32,
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            maximum_sd_elements: 0,
+            maximum_sd_params: default_maximum_sd_params(),
+            sd_param_value_bytes: default_sd_param_value_bytes(),
+            hostname_cardinality: None,
+            app_name_cardinality: None,
+            message_bytes: None,
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy)]
 #[allow(clippy::module_name_repetitions)]
-pub(crate) struct Syslog5424 {}
+pub(crate) struct Syslog5424 {
+    config: Config,
+}
+
+impl Syslog5424 {
+    #[must_use]
+    pub(crate) fn new(config: Config) -> Self {
+        Self { config }
+    }
+}
 
 const HOSTNAMES: [&str; 4] = [
     "troutwine.us",
@@ -55,14 +128,15 @@ impl<'a> arbitrary::Arbitrary<'a> for Message {
 }
 
 struct Member {
-    priority: u8,       // 0 - 191
-    syslog_version: u8, // 1 - 3
-    timestamp: String,  // seconds format in millis
-    hostname: String,   // name.tld
-    app_name: String,   // shortish string
-    procid: u16,        // 100 - 9999
-    msgid: u16,         // 1 - 999
-    message: String,    // shortish structured string
+    priority: u8,            // 0 - 191
+    syslog_version: u8,      // 1 - 3
+    timestamp: String,       // seconds format in millis
+    hostname: String,        // name.tld
+    app_name: String,        // shortish string
+    procid: u16,             // 100 - 9999
+    msgid: u16,              // 1 - 999
+    structured_data: String, // NILVALUE or SD-ELEMENTs
+    message: String,         // shortish structured string
 }
 
 fn to_rfc3339<T>(dt: T) -> String
@@ -72,49 +146,76 @@ where
     dt.into().format(&Rfc3339).unwrap()
 }
 
-impl<'a> arbitrary::Arbitrary<'a> for Member {
-    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+/// Produce a string of `range` length drawn from [`SD_CHARSET`], a charset
+/// that needs no escaping in SD-PARAM values.
+fn ascii(u: &mut Unstructured<'_>, range: LengthRange) -> arbitrary::Result<String> {
+    let len = u.int_in_range(range.minimum..=range.maximum.max(range.minimum))?;
+    let mut bytes = vec![0; len as usize];
+    u.fill_buffer(&mut bytes)?;
+    Ok(bytes
+        .into_iter()
+        .map(|b| SD_CHARSET[b as usize % SD_CHARSET.len()] as char)
+        .collect())
+}
+
+fn structured_data(u: &mut Unstructured<'_>, config: &Config) -> arbitrary::Result<String> {
+    let elements = u.int_in_range(0..=config.maximum_sd_elements)?;
+    if elements == 0 {
+        return Ok("-".to_string());
+    }
+    let mut sd = String::new();
+    for element in 0..elements {
+        write!(sd, "[sd{}@32473", element).unwrap();
+        let params = u.int_in_range(1..=config.maximum_sd_params.max(1))?;
+        for param in 0..params {
+            let value = ascii(u, config.sd_param_value_bytes)?;
+            write!(sd, " p{}=\"{}\"", param, value).unwrap();
+        }
+        sd.push(']');
+    }
+    Ok(sd)
+}
+
+impl Member {
+    fn generate(u: &mut Unstructured<'_>, config: &Config) -> arbitrary::Result<Self> {
         let priority = u.arbitrary::<u8>()? % 191;
         let syslog_version = (u.arbitrary::<u8>()? % 3) + 1;
         let timestamp = to_rfc3339(SystemTime::now());
-        let hostname_idx = u.arbitrary::<usize>()? % HOSTNAMES.len();
-        let app_name_idx = u.arbitrary::<usize>()? % APP_NAMES.len();
+        let hostname = match config.hostname_cardinality {
+            Some(cardinality) => format!(
+                "host{}.example.com",
+                u.int_in_range(0..=cardinality.get() - 1)?
+            ),
+            None => HOSTNAMES[u.arbitrary::<usize>()? % HOSTNAMES.len()].to_string(),
+        };
+        let app_name = match config.app_name_cardinality {
+            Some(cardinality) => format!("app{}", u.int_in_range(0..=cardinality.get() - 1)?),
+            None => APP_NAMES[u.arbitrary::<usize>()? % APP_NAMES.len()].to_string(),
+        };
         let procid = (u.arbitrary::<u16>()? % 9899) + 101;
         let msgid = (u.arbitrary::<u16>()? % 999) + 1;
-        let message = u.arbitrary::<Message>()?;
+        let structured_data = structured_data(u, config)?;
+        let message = match config.message_bytes {
+            Some(range) => ascii(u, range)?,
+            None => serde_json::to_string(&u.arbitrary::<Message>()?).unwrap(),
+        };
 
         Ok(Member {
             priority,
             syslog_version,
             timestamp,
-            hostname: HOSTNAMES[hostname_idx as usize].to_string(),
-            app_name: APP_NAMES[app_name_idx as usize].to_string(),
+            hostname,
+            app_name,
             procid,
             msgid,
-            message: serde_json::to_string(&message).unwrap(),
-        })
-    }
-
-    fn size_hint(depth: usize) -> (usize, Option<usize>) {
-        size_hint::recursion_guard(depth, |depth| {
-            size_hint::and_all(&[
-                <u8 as arbitrary::Arbitrary>::size_hint(depth),
-                <u8 as arbitrary::Arbitrary>::size_hint(depth),
-                (16, Some(64)), // timestamp
-                (8, Some(16)),  // hostnames
-                (4, Some(20)),  // app names
-                <u16 as arbitrary::Arbitrary>::size_hint(depth),
-                <u16 as arbitrary::Arbitrary>::size_hint(depth),
-                (128, Some(512)), // message
-            ])
+            structured_data,
+            message,
         })
     }
-}
 
-impl Member {
     fn into_string(self) -> String {
         format!(
-            "<{}>{} {} {} {} {} ID{} - {}",
+            "<{}>{} {} {} {} {} ID{} {} {}",
             self.priority,
             self.syslog_version,
             self.timestamp,
@@ -122,6 +223,7 @@ impl Member {
             self.app_name,
             self.procid,
             self.msgid,
+            self.structured_data,
             self.message
         )
     }
@@ -140,13 +242,11 @@ impl Serialize for Syslog5424 {
 
         let mut entropy: Vec<u8> = vec![0; max_bytes];
         rng.fill_bytes(&mut entropy);
-        let unstructured = Unstructured::new(&entropy);
-
-        let members = <Vec<Member> as arbitrary::Arbitrary>::arbitrary_take_rest(unstructured)?;
-        let encoded = members.into_iter().map(Member::into_string);
+        let mut unstructured = Unstructured::new(&entropy);
 
         let mut written_bytes = 0;
-        for line in encoded {
+        while !unstructured.is_empty() {
+            let line = Member::generate(&mut unstructured, &self.config)?.into_string();
             if line.len() + 1 + written_bytes > max_bytes {
                 break;
             }
@@ -165,6 +265,7 @@ mod test {
     use proptest::prelude::*;
     use rand::{rngs::SmallRng, SeedableRng};
 
+    use super::{Config, LengthRange};
     use crate::payload::{Serialize, Syslog5424};
 
     // We want to be sure that the serialized size of the payload does not
@@ -185,4 +286,27 @@ mod test {
             );
         }
     }
+
+    // Tuning structured data, cardinality and message length must not cause
+    // the payload to exceed `max_bytes`.
+    proptest! {
+        #[test]
+        fn configured_payload_not_exceed_max_bytes(seed: u64, max_bytes: u16, maximum_sd_elements in 0..8_u8, maximum_sd_params in 0..8_u8, minimum: u8, maximum: u8) {
+            let max_bytes = max_bytes as usize;
+            let rng = SmallRng::seed_from_u64(seed);
+            let range = LengthRange { minimum: u16::from(minimum), maximum: u16::from(maximum) };
+            let syslog = Syslog5424::new(Config {
+                maximum_sd_elements,
+                maximum_sd_params,
+                sd_param_value_bytes: range,
+                hostname_cardinality: std::num::NonZeroU32::new(100),
+                app_name_cardinality: std::num::NonZeroU32::new(10),
+                message_bytes: Some(range),
+            });
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            syslog.to_bytes(rng, max_bytes, &mut bytes).unwrap();
+            prop_assert!(bytes.len() <= max_bytes);
+        }
+    }
 }
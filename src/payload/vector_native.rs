@@ -0,0 +1,203 @@
+use std::{io::Write, num::NonZeroU32};
+
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Deserialize;
+
+use crate::payload::{
+    protobuf::{put_bytes, put_key, put_varint, VARINT},
+    Error, Serialize,
+};
+
+/// The earliest timestamp of an event, 2022-01-01T00:00:00Z in seconds.
+/// Timestamps are drawn from the seed, not the wall clock, so that payloads
+/// are deterministic.
+const EPOCH_SECONDS: u64 = 1_640_995_200;
+/// The span of timestamps after [`EPOCH_SECONDS`], a day in seconds.
+const TIMESTAMP_RANGE_SECONDS: u64 = 86_400;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+/// Configuration for the shape of [`VectorNative`] payloads.
+pub struct Config {
+    /// The number of distinct `host` values, by default 10
+    #[serde(default = "default_hosts")]
+    pub hosts: NonZeroU32,
+    /// The number of fields each log event carries beyond its `message`,
+    /// `timestamp` and `host`, by default 4
+    #[serde(default = "default_fields_per_event")]
+    pub fields_per_event: u8,
+    /// The number of distinct values each of those fields takes, by default
+    /// 10
+    #[serde(default = "default_field_values")]
+    pub field_values: NonZeroU32,
+}
+
+fn default_hosts() -> NonZeroU32 {
+    NonZeroU32::new(10).unwrap()
+}
+
+fn default_fields_per_event() -> u8 {
+    4
+}
+
+fn default_field_values() -> NonZeroU32 {
+    NonZeroU32::new(10).unwrap()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            hosts: default_hosts(),
+            fields_per_event: default_fields_per_event(),
+            field_values: default_field_values(),
+        }
+    }
+}
+
+/// Append an entry of a `map<string, Value>` at `field`, its value the
+/// encoded `Value` `value`.
+fn put_entry(field: u32, key: &str, value: &[u8], buf: &mut Vec<u8>) {
+    let mut entry = Vec::with_capacity(key.len() + value.len() + 4);
+    put_bytes(1, key.as_bytes(), &mut entry);
+    put_bytes(2, value, &mut entry);
+    put_bytes(field, &entry, buf);
+}
+
+/// Encode a `Value` holding `raw_bytes`.
+fn raw_bytes(bytes: &[u8]) -> Vec<u8> {
+    let mut value = Vec::with_capacity(bytes.len() + 2);
+    put_bytes(1, bytes, &mut value);
+    value
+}
+
+/// Encode a `Value` holding a `google.protobuf.Timestamp` of `seconds`.
+fn timestamp(seconds: u64) -> Vec<u8> {
+    let mut timestamp = Vec::with_capacity(8);
+    put_key(1, VARINT, &mut timestamp);
+    put_varint(seconds, &mut timestamp);
+    let mut value = Vec::with_capacity(12);
+    put_bytes(2, &timestamp, &mut value);
+    value
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+/// Generates Vector native `PushEventsRequest` messages, protobuf encoded.
+///
+/// Each `EventWrapper` of a message is a log event whose fields are a
+/// `message` of printable characters, a `timestamp`, a `host` of one of
+/// `hosts` hosts and `fields_per_event` fields of `field_values` values
+/// apiece.
+pub(crate) struct VectorNative {
+    config: Config,
+}
+
+impl VectorNative {
+    #[must_use]
+    pub(crate) fn new(config: Config) -> Self {
+        Self { config }
+    }
+
+    /// Encode one `EventWrapper` message, holding a log event.
+    fn event<R>(&self, rng: &mut R) -> Vec<u8>
+    where
+        R: Rng,
+    {
+        let mut log = Vec::new();
+        let message_len = rng.gen_range(16..256);
+        let message: Vec<u8> = (0..message_len).map(|_| rng.sample(Alphanumeric)).collect();
+        put_entry(1, "message", &raw_bytes(&message), &mut log);
+        put_entry(
+            1,
+            "timestamp",
+            &timestamp(EPOCH_SECONDS + rng.gen_range(0..TIMESTAMP_RANGE_SECONDS)),
+            &mut log,
+        );
+        let host = format!("host_{}", rng.gen_range(0..self.config.hosts.get()));
+        put_entry(1, "host", &raw_bytes(host.as_bytes()), &mut log);
+        for field in 0..self.config.fields_per_event {
+            let value = format!("value_{}", rng.gen_range(0..self.config.field_values.get()));
+            put_entry(
+                1,
+                &format!("field_{}", field),
+                &raw_bytes(value.as_bytes()),
+                &mut log,
+            );
+        }
+
+        let mut event = Vec::with_capacity(log.len() + 4);
+        put_bytes(1, &log, &mut event);
+        event
+    }
+}
+
+impl Serialize for VectorNative {
+    fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        // A `PushEventsRequest` is its repeated `events`, field 1, and so a
+        // concatenation of them is itself a request.
+        let mut bytes_remaining = max_bytes;
+        loop {
+            let mut field = Vec::new();
+            put_bytes(1, &self.event(&mut rng), &mut field);
+            match bytes_remaining.checked_sub(field.len()) {
+                Some(remainder) => {
+                    writer.write_all(&field)?;
+                    bytes_remaining = remainder;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use crate::payload::{protobuf::read_varint, Serialize, VectorNative};
+
+    /// Split `buf`, a sequence of length delimited fields, into the number of
+    /// each field and its contents.
+    fn fields(mut buf: &[u8]) -> Vec<(u64, &[u8])> {
+        let mut fields = Vec::new();
+        while !buf.is_empty() {
+            let (key, read) = read_varint(buf);
+            assert_eq!(key & 0x7, 2, "only length delimited fields are expected");
+            let (length, length_read) = read_varint(&buf[read..]);
+            let start = read + length_read;
+            fields.push((key >> 3, &buf[start..start + length as usize]));
+            buf = &buf[start + length as usize..];
+        }
+        fields
+    }
+
+    // A payload is a sequence of `events`, each a log of map entries, that
+    // ends exactly where it should and is no larger than `max_bytes`.
+    proptest! {
+        #[test]
+        fn payload_is_log_events(seed: u64, max_bytes: u16) {
+            let max_bytes = max_bytes as usize;
+            let rng = SmallRng::seed_from_u64(seed);
+            let vector = VectorNative::default();
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            vector.to_bytes(rng, max_bytes, &mut bytes).unwrap();
+            prop_assert!(bytes.len() <= max_bytes);
+
+            for (field, event) in fields(&bytes) {
+                prop_assert_eq!(field, 1);
+                let wrapped = fields(event);
+                prop_assert_eq!(wrapped.len(), 1);
+                prop_assert_eq!(wrapped[0].0, 1);
+                let entries = fields(wrapped[0].1);
+                prop_assert_eq!(entries.len(), 3 + 4);
+                let first = fields(entries[0].1);
+                prop_assert_eq!(first[0], (1, &b"message"[..]));
+            }
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    num::{NonZeroU32, NonZeroU8},
+};
+
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::payload::{Error, Serialize};
+
+/// The earliest start time of a span, 2022-01-01T00:00:00Z in microseconds.
+/// Times are drawn from the seed, not the wall clock, so that payloads are
+/// deterministic.
+const EPOCH_MICROS: u64 = 1_640_995_200_000_000;
+/// The span of start times after [`EPOCH_MICROS`], a day in microseconds.
+const START_RANGE_MICROS: u64 = 86_400_000_000;
+/// The longest duration of a span, a second in microseconds.
+const MAXIMUM_DURATION_MICROS: u64 = 1_000_000;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+/// Configuration for the shape of [`Zipkin`] payloads.
+pub struct Config {
+    /// The number of distinct service names, by default 10
+    #[serde(default = "default_services")]
+    pub services: NonZeroU32,
+    /// The number of distinct span names of each service, by default 20
+    #[serde(default = "default_operations")]
+    pub operations: NonZeroU32,
+    /// The number of spans in each trace, by default 4
+    #[serde(default = "default_spans_per_trace")]
+    pub spans_per_trace: NonZeroU8,
+}
+
+fn default_services() -> NonZeroU32 {
+    NonZeroU32::new(10).unwrap()
+}
+
+fn default_operations() -> NonZeroU32 {
+    NonZeroU32::new(20).unwrap()
+}
+
+fn default_spans_per_trace() -> NonZeroU8 {
+    NonZeroU8::new(4).unwrap()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            services: default_services(),
+            operations: default_operations(),
+            spans_per_trace: default_spans_per_trace(),
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Endpoint {
+    service_name: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Span {
+    trace_id: String,
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent_id: Option<String>,
+    name: String,
+    kind: &'static str,
+    timestamp: u64,
+    duration: u64,
+    local_endpoint: Endpoint,
+    tags: BTreeMap<&'static str, &'static str>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+/// Generates Zipkin v2 JSON spans, one per line.
+///
+/// Spans come in traces of `spans_per_trace`, the first the root and each
+/// other a child of a span before it in the trace. Each span is of one of
+/// `services` services and one of `operations` span names.
+pub(crate) struct Zipkin {
+    config: Config,
+}
+
+impl Zipkin {
+    #[must_use]
+    pub(crate) fn new(config: Config) -> Self {
+        Self { config }
+    }
+
+    /// Encode one trace, each span on a line of its own.
+    fn trace<R>(&self, rng: &mut R) -> Result<Vec<Vec<u8>>, Error>
+    where
+        R: Rng,
+    {
+        let trace_id = format!("{:016x}", rng.gen::<u64>());
+        let start = EPOCH_MICROS + rng.gen_range(0..START_RANGE_MICROS);
+        let mut ids: Vec<String> =
+            Vec::with_capacity(usize::from(self.config.spans_per_trace.get()));
+        let mut spans = Vec::with_capacity(ids.capacity());
+        for idx in 0..self.config.spans_per_trace.get() {
+            let parent_id = (idx > 0).then(|| ids[rng.gen_range(0..ids.len())].clone());
+            let id = format!("{:016x}", rng.gen::<u64>());
+            let status_code = ["200", "404", "500"][rng.gen_range(0..3)];
+            let span = Span {
+                trace_id: trace_id.clone(),
+                id: id.clone(),
+                parent_id,
+                name: format!(
+                    "operation_{}",
+                    rng.gen_range(0..self.config.operations.get())
+                ),
+                kind: if idx % 2 == 0 { "SERVER" } else { "CLIENT" },
+                timestamp: start + u64::from(idx),
+                duration: rng.gen_range(1..MAXIMUM_DURATION_MICROS),
+                local_endpoint: Endpoint {
+                    service_name: format!(
+                        "service_{}",
+                        rng.gen_range(0..self.config.services.get())
+                    ),
+                },
+                tags: [("http.status_code", status_code)].into_iter().collect(),
+            };
+            let mut line = serde_json::to_vec(&span)?;
+            line.push(b'\n');
+            spans.push(line);
+            ids.push(id);
+        }
+        Ok(spans)
+    }
+}
+
+impl Serialize for Zipkin {
+    fn to_bytes<W, R>(&self, mut rng: R, max_bytes: usize, writer: &mut W) -> Result<(), Error>
+    where
+        R: Rng + Sized,
+        W: Write,
+    {
+        let mut bytes_remaining = max_bytes;
+        loop {
+            for span in self.trace(&mut rng)? {
+                match bytes_remaining.checked_sub(span.len()) {
+                    Some(remainder) => {
+                        writer.write_all(&span)?;
+                        bytes_remaining = remainder;
+                    }
+                    None => return Ok(()),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::{NonZeroU32, NonZeroU8};
+
+    use proptest::prelude::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::Config;
+    use crate::payload::{Serialize, Zipkin};
+
+    // Every span is a JSON object of one of the configured services, no more
+    // than `max_bytes` in total, and every parent precedes its children.
+    proptest! {
+        #[test]
+        fn spans_have_configured_shape(
+            seed: u64,
+            max_bytes: u16,
+            services in 1_u32..32,
+            spans_per_trace in 1_u8..16,
+        ) {
+            let max_bytes = max_bytes as usize;
+            let rng = SmallRng::seed_from_u64(seed);
+            let zipkin = Zipkin::new(Config {
+                services: NonZeroU32::new(services).unwrap(),
+                operations: NonZeroU32::new(20).unwrap(),
+                spans_per_trace: NonZeroU8::new(spans_per_trace).unwrap(),
+            });
+
+            let mut bytes = Vec::with_capacity(max_bytes);
+            zipkin.to_bytes(rng, max_bytes, &mut bytes).unwrap();
+            prop_assert!(bytes.len() <= max_bytes);
+
+            let mut seen = Vec::new();
+            for line in std::str::from_utf8(&bytes).unwrap().lines() {
+                let span: serde_json::Value = serde_json::from_str(line).unwrap();
+                let service = span["localEndpoint"]["serviceName"].as_str().unwrap();
+                let service: u32 = service.trim_start_matches("service_").parse().unwrap();
+                prop_assert!(service < services);
+                if let Some(parent_id) = span["parentId"].as_str() {
+                    prop_assert!(seen.iter().any(|id: &String| id == parent_id));
+                }
+                seen.push(span["id"].as_str().unwrap().to_string());
+            }
+        }
+    }
+}
@@ -0,0 +1,368 @@
+//! Check the host before an experiment starts
+//!
+//! A run that fails halfway through setup, or halfway through the experiment,
+//! for want of file descriptors, disk or a free port wastes the rig's time. A
+//! run that does not fail but silently loses some of its load is worse.
+//! [`check`] inspects the host against what a configuration plans to do before
+//! anything is started: the open file limit against planned connections, free
+//! disk against captures and samples, the availability of blackhole and
+//! exporter ports, the locked memory limit against locked block caches and
+//! the support NUMA placement and io_uring need. Every problem found is
+//! reported at once, so that all can be fixed in one go.
+
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    net::{SocketAddr, UdpSocket},
+    path::{Path, PathBuf},
+};
+
+use byte_unit::Byte;
+use nix::{
+    libc,
+    sys::{socket::SockType, statvfs::statvfs},
+    unistd::geteuid,
+};
+
+use crate::{
+    blackhole,
+    config::{self, Config, Telemetry},
+    generator, uring,
+};
+
+/// File descriptors lading holds open besides its connections: its own files,
+/// the target's pipes, the capture file and the like.
+const BASELINE_FILE_DESCRIPTORS: u64 = 64;
+/// The free disk required by default on the filesystem of the capture file,
+/// see [`Options::minimum_free_bytes`].
+pub const DEFAULT_MINIMUM_FREE_BYTES: u64 = 1024 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Options of [`check`]
+pub struct Options {
+    /// Free disk required on the filesystem of the capture file, whose size is
+    /// not known ahead of time. Unbounded samples require the same.
+    pub minimum_free_bytes: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            minimum_free_bytes: DEFAULT_MINIMUM_FREE_BYTES,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A problem found by [`check`]
+pub struct Problem {
+    /// The check that found the problem: `file_descriptors`, `disk`, `ports`,
+    /// `locked_memory`, `numa` or `backend`.
+    pub check: &'static str,
+    /// What is wrong, and how it might be fixed.
+    pub detail: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.check, self.detail)
+    }
+}
+
+/// The soft limit of `limit`, or `None` if it is unlimited.
+// rlim_t is not u64 on every platform.
+#[allow(clippy::unnecessary_cast)]
+fn finite(limit: &libc::rlimit) -> Option<u64> {
+    (limit.rlim_cur != libc::RLIM_INFINITY).then(|| limit.rlim_cur as u64)
+}
+
+/// The soft limit on open files, or `None` if it is unlimited or unknown.
+fn open_files_limit() -> Option<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is valid for getrlimit to write to.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    finite(&limit)
+}
+
+/// The soft limit on locked memory, or `None` if it is unlimited or unknown.
+fn locked_memory_limit() -> Option<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is valid for getrlimit to write to.
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } != 0 {
+        return None;
+    }
+    finite(&limit)
+}
+
+/// `path` or, if it does not exist yet, its nearest ancestor that does.
+fn existing_ancestor(path: &Path) -> Option<&Path> {
+    path.ancestors()
+        .find(|ancestor| !ancestor.as_os_str().is_empty() && ancestor.exists())
+        .or_else(|| path.is_relative().then(|| Path::new(".")))
+}
+
+fn display(bytes: u64) -> String {
+    Byte::from_bytes(u128::from(bytes))
+        .get_appropriate_unit(true)
+        .to_string()
+}
+
+fn check_file_descriptors(
+    generators: &[&generator::Config],
+    blackholes: &[&blackhole::Config],
+    problems: &mut Vec<Problem>,
+) {
+    let limit = match open_files_limit() {
+        Some(limit) => limit,
+        None => return,
+    };
+    let planned = BASELINE_FILE_DESCRIPTORS
+        + generators
+            .iter()
+            .map(|cfg| cfg.planned_connections())
+            .sum::<u64>()
+        + blackholes
+            .iter()
+            .map(|cfg| cfg.planned_sockets())
+            .sum::<u64>();
+    if planned > limit {
+        problems.push(Problem {
+            check: "file_descriptors",
+            detail: format!(
+                "{} file descriptors are planned, not counting connections the target makes to blackholes, but the open file limit is {}; raise it with `ulimit -n`",
+                planned, limit
+            ),
+        });
+    }
+}
+
+fn check_disk(
+    config: &Config,
+    blackholes: &[&blackhole::Config],
+    minimum_free_bytes: u64,
+    problems: &mut Vec<Problem>,
+) {
+    let mut needs: Vec<(PathBuf, u64)> = Vec::new();
+    if let Telemetry::Log { ref path, .. } = config.telemetry {
+        let directory = path.parent().unwrap_or_else(|| Path::new("."));
+        needs.push((directory.to_path_buf(), minimum_free_bytes));
+    }
+    for sample in blackholes
+        .iter()
+        .flat_map(|cfg| cfg.sample().into_iter().chain(cfg.request_log()))
+    {
+        let bytes = sample.maximum_bytes.map_or(minimum_free_bytes, |maximum| {
+            u64::try_from(maximum.get_bytes()).unwrap_or(u64::MAX)
+        });
+        needs.push((sample.directory.clone(), bytes));
+    }
+
+    // Needs are summed by filesystem, several directories may share one.
+    let mut filesystems: BTreeMap<u64, (u64, u64, Vec<PathBuf>)> = BTreeMap::new();
+    for (directory, bytes) in needs {
+        let stat = match existing_ancestor(&directory).map(statvfs) {
+            Some(Ok(stat)) => stat,
+            _ => {
+                problems.push(Problem {
+                    check: "disk",
+                    detail: format!(
+                        "cannot determine the free space for {}",
+                        directory.display()
+                    ),
+                });
+                continue;
+            }
+        };
+        #[allow(clippy::useless_conversion)]
+        let available = u64::from(stat.blocks_available()) * u64::from(stat.fragment_size());
+        #[allow(clippy::useless_conversion)]
+        let entry = filesystems
+            .entry(u64::from(stat.filesystem_id()))
+            .or_insert((0, available, Vec::new()));
+        entry.0 = entry.0.saturating_add(bytes);
+        entry.2.push(directory);
+    }
+    for (needed, available, directories) in filesystems.into_values() {
+        if needed > available {
+            let directories: Vec<String> = directories
+                .iter()
+                .map(|directory| directory.display().to_string())
+                .collect();
+            problems.push(Problem {
+                check: "disk",
+                detail: format!(
+                    "{} are planned for {} but only {} is free",
+                    display(needed),
+                    directories.join(", "),
+                    display(available)
+                ),
+            });
+        }
+    }
+}
+
+fn check_ports(config: &Config, blackholes: &[&blackhole::Config], problems: &mut Vec<Problem>) {
+    let mut binds: Vec<(String, SocketAddr, bool)> = blackholes
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, cfg)| {
+            let datagram = matches!(cfg, blackhole::Config::Udp(_));
+            cfg.binding_addr()
+                .map(|addr| (format!("blackhole_{}", idx), addr, datagram))
+        })
+        .collect();
+    if let Telemetry::Prometheus {
+        prometheus_addr, ..
+    } = config.telemetry
+    {
+        binds.push(("prometheus exporter".to_string(), prometheus_addr, false));
+    }
+    for (component, addr, datagram) in binds {
+        // The socket is closed as soon as it is bound, freeing the port for
+        // the component. It is bound as the blackhole binds it, so that a
+        // dual-stack `[::]` conflicts as the blackhole's would.
+        let bound = if datagram {
+            blackhole::bind::<UdpSocket>(addr, SockType::Datagram, false)
+                .map(drop)
+                .map_err(io::Error::from)
+        } else {
+            blackhole::listen(addr, false)
+                .map(drop)
+                .map_err(io::Error::from)
+        };
+        if let Err(err) = bound {
+            problems.push(Problem {
+                check: "ports",
+                detail: format!("{} cannot bind {}: {}", component, addr, err),
+            });
+        }
+    }
+}
+
+fn check_locked_memory(generators: &[&generator::Config], problems: &mut Vec<Problem>) {
+    let locked: u64 = generators
+        .iter()
+        .filter(|cfg| cfg.lock_block_cache())
+        .map(|cfg| cfg.planned_memory_bytes())
+        .sum();
+    if locked > 0 && !geteuid().is_root() {
+        if let Some(limit) = locked_memory_limit() {
+            if locked > limit {
+                problems.push(Problem {
+                    check: "locked_memory",
+                    detail: format!(
+                        "{} of block cache is to be locked but the locked memory limit is {}; raise it with `ulimit -l`",
+                        display(locked),
+                        display(limit)
+                    ),
+                });
+            }
+        }
+    }
+}
+
+fn check_numa(
+    generators: &[&generator::Config],
+    blackholes: &[&blackhole::Config],
+    problems: &mut Vec<Problem>,
+) {
+    let placements = generators
+        .iter()
+        .enumerate()
+        .map(|(idx, cfg)| (format!("generator_{}", idx), cfg.numa()))
+        .chain(
+            blackholes
+                .iter()
+                .enumerate()
+                .map(|(idx, cfg)| (format!("blackhole_{}", idx), cfg.numa())),
+        );
+    for (component, placement) in placements {
+        let node = match placement {
+            Some(placement) => placement.node,
+            None => continue,
+        };
+        if !cfg!(target_os = "linux") {
+            problems.push(Problem {
+                check: "numa",
+                detail: format!(
+                    "{} is placed on a NUMA node, supported on Linux only",
+                    component
+                ),
+            });
+        } else if !Path::new(&format!("/sys/devices/system/node/node{}", node)).exists() {
+            problems.push(Problem {
+                check: "numa",
+                detail: format!(
+                    "{} is placed on NUMA node {}, which does not exist",
+                    component, node
+                ),
+            });
+        }
+    }
+}
+
+fn check_backends(
+    generators: &[&generator::Config],
+    blackholes: &[&blackhole::Config],
+    problems: &mut Vec<Problem>,
+) {
+    let backends = generators
+        .iter()
+        .enumerate()
+        .map(|(idx, cfg)| (format!("generator_{}", idx), cfg.backend()))
+        .chain(
+            blackholes
+                .iter()
+                .enumerate()
+                .map(|(idx, cfg)| (format!("blackhole_{}", idx), cfg.backend())),
+        );
+    let uring_available = cfg!(all(feature = "io-uring", target_os = "linux"));
+    for (component, backend) in backends {
+        if backend == uring::Backend::IoUring && !uring_available {
+            problems.push(Problem {
+                check: "backend",
+                detail: format!(
+                    "{} uses the io_uring backend but lading was not built with the `io-uring` feature",
+                    component
+                ),
+            });
+        }
+    }
+}
+
+/// Check the host against what `config` plans to do with `options`, returning
+/// every problem found. An empty result means the experiment may start.
+#[must_use]
+pub fn check(config: &Config, options: Options) -> Vec<Problem> {
+    let generators: Vec<&generator::Config> = match config.generator {
+        config::Generator::One(ref cfg) => vec![cfg.as_ref()],
+        config::Generator::Many(ref cfgs) => cfgs.iter().collect(),
+    };
+    let blackholes: Vec<&blackhole::Config> = match config.blackhole {
+        Some(config::Blackhole::One(ref cfg)) => vec![cfg.as_ref()],
+        Some(config::Blackhole::Many(ref cfgs)) => cfgs.iter().collect(),
+        None => vec![],
+    };
+
+    let mut problems = Vec::new();
+    check_file_descriptors(&generators, &blackholes, &mut problems);
+    check_disk(
+        config,
+        &blackholes,
+        options.minimum_free_bytes,
+        &mut problems,
+    );
+    check_ports(config, &blackholes, &mut problems);
+    check_locked_memory(&generators, &mut problems);
+    check_numa(&generators, &blackholes, &mut problems);
+    check_backends(&generators, &blackholes, &mut problems);
+    problems
+}
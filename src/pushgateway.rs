@@ -0,0 +1,217 @@
+//! Push lading's metrics to a Prometheus Pushgateway
+//!
+//! A run on an ephemeral CI runner is over, and the runner gone, before any
+//! scraper reaches lading's prometheus exporter. A [`Pusher`] instead pushes
+//! the exporter's metrics to a [Pushgateway] as lading shuts down, once all
+//! other components have stopped, and optionally every interval before that.
+//! Metrics are grouped under job `lading` and, if set, the experiment's id as
+//! `experiment_id`. Each push replaces the group's previous metrics.
+//!
+//! [Pushgateway]: https://github.com/prometheus/pushgateway
+
+use hyper::{
+    client::{Client, HttpConnector},
+    Body, Request, StatusCode, Uri,
+};
+use metrics_exporter_prometheus::PrometheusHandle;
+use serde::Deserialize;
+use tokio::time::{self, Duration};
+use tracing::{info, warn};
+
+use crate::signals::Shutdown;
+
+#[derive(Debug, Deserialize, Clone)]
+/// Configuration for [`Pusher`]
+pub struct Config {
+    /// The URI of the Pushgateway, for instance `http://pushgateway:9091`
+    #[serde(with = "http_serde::uri")]
+    pub uri: Uri,
+    /// The time, in seconds, between pushes while lading runs. If unset
+    /// metrics are pushed only as lading shuts down.
+    #[serde(default)]
+    pub interval_seconds: Option<u64>,
+}
+
+#[derive(Debug)]
+/// Errors produced by [`Pusher`]
+pub enum Error {
+    /// Wrapper around [`hyper::http::Error`].
+    Http(hyper::http::Error),
+    /// Wrapper around [`hyper::Error`].
+    Hyper(hyper::Error),
+    /// The Pushgateway answered a push with a status other than success.
+    Status(StatusCode),
+}
+
+impl From<hyper::http::Error> for Error {
+    fn from(error: hyper::http::Error) -> Self {
+        Error::Http(error)
+    }
+}
+
+impl From<hyper::Error> for Error {
+    fn from(error: hyper::Error) -> Self {
+        Error::Hyper(error)
+    }
+}
+
+const BASE64URL: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Encode `bytes` as padded, URL safe base64, as the Pushgateway accepts label
+/// values in a grouping key.
+fn base64url(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0_u32, |n, (idx, byte)| {
+            n | (u32::from(*byte) << (16 - 8 * idx))
+        });
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                encoded.push(char::from(
+                    BASE64URL[((n >> (18 - 6 * idx)) & 0x3f) as usize],
+                ));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Return the path of the grouping key of job `lading` and, if set,
+/// `experiment_id`.
+fn grouping_path(experiment_id: Option<&str>) -> String {
+    match experiment_id {
+        // An empty label value is base64 encoded as a lone `=`.
+        Some(id) if id.is_empty() => "/metrics/job/lading/experiment_id@base64/=".to_string(),
+        Some(id) => format!(
+            "/metrics/job/lading/experiment_id@base64/{}",
+            base64url(id.as_bytes())
+        ),
+        None => "/metrics/job/lading".to_string(),
+    }
+}
+
+#[allow(missing_debug_implementations)]
+/// Pushes the metrics of a prometheus exporter to a Pushgateway.
+pub struct Pusher {
+    uri: Uri,
+    interval: Option<Duration>,
+    handle: PrometheusHandle,
+    shutdown: Shutdown,
+}
+
+impl Pusher {
+    /// Create a new [`Pusher`] of the metrics rendered by `handle`, grouped by
+    /// `experiment_id` if set.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the configured URI and grouping key do
+    /// not form a valid URI.
+    pub fn new(
+        config: &Config,
+        experiment_id: Option<&str>,
+        handle: PrometheusHandle,
+        shutdown: Shutdown,
+    ) -> Result<Self, Error> {
+        let mut parts = config.uri.clone().into_parts();
+        parts.path_and_query = Some(
+            grouping_path(experiment_id)
+                .parse()
+                .map_err(hyper::http::Error::from)?,
+        );
+        let uri = Uri::from_parts(parts).map_err(hyper::http::Error::from)?;
+        Ok(Self {
+            uri,
+            interval: config
+                .interval_seconds
+                .map(|seconds| Duration::from_secs(seconds.max(1))),
+            handle,
+            shutdown,
+        })
+    }
+
+    async fn push(&self, client: &Client<HttpConnector, Body>) -> Result<(), Error> {
+        let request = Request::put(self.uri.clone())
+            .header("content-type", "text/plain; version=0.0.4")
+            .body(Body::from(self.handle.render()))?;
+        let response = client.request(request).await?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(Error::Status(response.status()))
+        }
+    }
+
+    /// Run [`Pusher`] until a shutdown signal is received, pushing once more
+    /// before returning.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the final push fails. Failures of
+    /// periodic pushes are logged and otherwise ignored.
+    pub async fn run(mut self) -> Result<(), Error> {
+        let client: Client<HttpConnector, Body> = Client::new();
+        // Without an interval the timer is never polled, only shutdown is.
+        let periodic = self.interval.is_some();
+        let mut interval = time::interval(self.interval.unwrap_or(Duration::from_secs(60)));
+        // The first tick completes immediately, there is nothing to push yet.
+        interval.tick().await;
+
+        loop {
+            tokio::select! {
+                _ = interval.tick(), if periodic => {
+                    if let Err(err) = self.push(&client).await {
+                        warn!("push to {} failed: {:?}", self.uri, err);
+                    }
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received, pushing final metrics to {}", self.uri);
+                    return self.push(&client).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::{base64url, BASE64URL};
+
+    /// Decode padded, URL safe base64.
+    #[allow(clippy::cast_possible_truncation)]
+    fn decode(encoded: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for chunk in encoded.as_bytes().chunks(4) {
+            let digits: Vec<u32> = chunk
+                .iter()
+                .take_while(|digit| **digit != b'=')
+                .map(|digit| {
+                    u32::try_from(BASE64URL.iter().position(|b| b == digit).unwrap()).unwrap()
+                })
+                .collect();
+            let n = digits
+                .iter()
+                .enumerate()
+                .fold(0, |n, (idx, digit)| n | (digit << (18 - 6 * idx)));
+            for idx in 0..digits.len() - 1 {
+                bytes.push((n >> (16 - 8 * idx)) as u8);
+            }
+        }
+        bytes
+    }
+
+    // Encoded values decode to themselves and use only URL safe characters.
+    proptest! {
+        #[test]
+        fn base64url_round_trip(value: Vec<u8>) {
+            let encoded = base64url(&value);
+            prop_assert_eq!(encoded.len() % 4, 0);
+            prop_assert!(encoded.bytes().all(|b| BASE64URL.contains(&b) || b == b'='));
+            prop_assert_eq!(decode(&encoded), value);
+        }
+    }
+}
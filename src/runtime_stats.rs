@@ -0,0 +1,325 @@
+//! Collect runtime statistics from the target
+//!
+//! The observer, see [`crate::observer`], sees the target from the outside:
+//! CPU, memory, I/O. For managed runtimes that is not the whole story. A JVM's
+//! resident memory says little about its heap and nothing about time lost to
+//! garbage collection. Runtime stat collectors poll an endpoint the target
+//! exposes about itself and fold what they learn into lading's captures.
+//!
+//! Supported are the JVM, by way of a [Jolokia] agent, and Go, by way of
+//! [expvar]'s `/debug/vars`. Collectors are configured per experiment, each
+//! polled once a second until the target is shut down. A failed poll is
+//! counted in `runtime_stats_failure` and otherwise ignored: the target may
+//! not be listening yet.
+//!
+//! [Jolokia]: https://jolokia.org/
+//! [expvar]: https://pkg.go.dev/expvar
+
+use std::time::Duration;
+
+use http::{StatusCode, Uri};
+use hyper::{
+    client::{Client, HttpConnector},
+    Body,
+};
+use metrics::{counter, gauge};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::time;
+use tracing::{debug, info};
+
+use crate::{signals::Shutdown, telemetry::hyper_error_kind};
+
+/// The time a single poll of a collector may take.
+const POLL_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+/// Configuration of a runtime stat collector
+pub enum Config {
+    /// Poll heap and garbage collection statistics of a JVM through a Jolokia
+    /// agent.
+    Jolokia {
+        /// The Jolokia agent's base URI, for instance
+        /// `http://localhost:8778/jolokia`
+        #[serde(with = "http_serde::uri")]
+        uri: Uri,
+    },
+    /// Poll the memory statistics of a Go program through expvar.
+    Expvar {
+        /// The expvar URI, for instance `http://localhost:6060/debug/vars`
+        #[serde(with = "http_serde::uri")]
+        uri: Uri,
+    },
+}
+
+impl Config {
+    fn name(&self) -> &'static str {
+        match self {
+            Config::Jolokia { .. } => "jolokia",
+            Config::Expvar { .. } => "expvar",
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Errors polling a collector. These are counted, not propagated.
+enum Error {
+    Hyper(hyper::Error),
+    Status(StatusCode),
+    Json(serde_json::Error),
+    Uri(http::uri::InvalidUri),
+    Timeout,
+}
+
+impl Error {
+    fn kind(&self) -> &'static str {
+        match self {
+            Error::Hyper(err) => hyper_error_kind(err),
+            Error::Status(_) => "status",
+            Error::Json(_) => "json",
+            Error::Uri(_) => "uri",
+            Error::Timeout => "timeout",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A single statistic read from a collector.
+struct Reading {
+    name: &'static str,
+    value: f64,
+    labels: Vec<(String, String)>,
+}
+
+impl Reading {
+    fn new(name: &'static str, value: f64) -> Self {
+        Self {
+            name,
+            value,
+            labels: Vec::new(),
+        }
+    }
+}
+
+/// Read the heap usage of a Jolokia `HeapMemoryUsage` response.
+fn jolokia_heap(response: &Value) -> Vec<Reading> {
+    [
+        ("used", "jvm_heap_used_bytes"),
+        ("committed", "jvm_heap_committed_bytes"),
+        ("max", "jvm_heap_max_bytes"),
+    ]
+    .iter()
+    .filter_map(|(field, name)| {
+        let value = response.get("value")?.get(field)?.as_f64()?;
+        Some(Reading::new(name, value))
+    })
+    .collect()
+}
+
+/// Read the collection counts and times of a Jolokia `GarbageCollector`
+/// response, labeled by garbage collector. The `collector` label names the
+/// runtime stat collector itself, see [`labels`].
+fn jolokia_gc(response: &Value) -> Vec<Reading> {
+    let beans = match response.get("value").and_then(Value::as_object) {
+        Some(beans) => beans,
+        None => return Vec::new(),
+    };
+    let mut readings = Vec::new();
+    for (bean, attributes) in beans {
+        // Bean names are of the form `java.lang:name=G1 Young
+        // Generation,type=GarbageCollector`.
+        let collector = bean
+            .split(&[':', ','][..])
+            .find_map(|property| property.strip_prefix("name="))
+            .unwrap_or(bean);
+        let labels = vec![("garbage_collector".to_string(), collector.to_string())];
+        if let Some(count) = attributes.get("CollectionCount").and_then(Value::as_f64) {
+            readings.push(Reading {
+                name: "jvm_gc_collection_count",
+                value: count,
+                labels: labels.clone(),
+            });
+        }
+        if let Some(millis) = attributes.get("CollectionTime").and_then(Value::as_f64) {
+            readings.push(Reading {
+                name: "jvm_gc_collection_seconds",
+                value: millis / 1_000.0,
+                labels,
+            });
+        }
+    }
+    readings
+}
+
+/// Read the `memstats` of an expvar response.
+fn expvar_memstats(response: &Value) -> Vec<Reading> {
+    let memstats = match response.get("memstats") {
+        Some(memstats) => memstats,
+        None => return Vec::new(),
+    };
+    [
+        ("HeapAlloc", "go_heap_alloc_bytes", 1.0),
+        ("HeapSys", "go_heap_sys_bytes", 1.0),
+        ("HeapObjects", "go_heap_objects", 1.0),
+        ("NextGC", "go_gc_next_bytes", 1.0),
+        ("NumGC", "go_gc_count", 1.0),
+        ("PauseTotalNs", "go_gc_pause_seconds", 1e-9),
+    ]
+    .iter()
+    .filter_map(|(field, name, scale)| {
+        let value = memstats.get(field)?.as_f64()?;
+        Some(Reading::new(name, value * scale))
+    })
+    .collect()
+}
+
+/// Return the labels of `reading`, polled by `collector`.
+fn labels(collector: &Config, reading: &Reading) -> Vec<(String, String)> {
+    let mut labels = vec![("collector".to_string(), collector.name().to_string())];
+    labels.extend(reading.labels.iter().cloned());
+    labels
+}
+
+/// GET `uri`, decoding the response body as JSON.
+async fn fetch(client: &Client<HttpConnector, Body>, uri: Uri) -> Result<Value, Error> {
+    let response = client.get(uri).await.map_err(Error::Hyper)?;
+    if !response.status().is_success() {
+        return Err(Error::Status(response.status()));
+    }
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(Error::Hyper)?;
+    serde_json::from_slice(&body).map_err(Error::Json)
+}
+
+/// Poll `collector` once.
+async fn poll(
+    client: &Client<HttpConnector, Body>,
+    collector: &Config,
+) -> Result<Vec<Reading>, Error> {
+    match collector {
+        Config::Jolokia { uri } => {
+            let base = uri.to_string();
+            let base = base.trim_end_matches('/');
+            let heap: Uri = format!("{}/read/java.lang:type=Memory/HeapMemoryUsage", base)
+                .parse()
+                .map_err(Error::Uri)?;
+            let gc: Uri = format!(
+                "{}/read/java.lang:type=GarbageCollector,name=*/CollectionCount,CollectionTime",
+                base
+            )
+            .parse()
+            .map_err(Error::Uri)?;
+            let mut readings = jolokia_heap(&fetch(client, heap).await?);
+            readings.extend(jolokia_gc(&fetch(client, gc).await?));
+            Ok(readings)
+        }
+        Config::Expvar { uri } => Ok(expvar_memstats(&fetch(client, uri.clone()).await?)),
+    }
+}
+
+#[derive(Debug)]
+/// The runtime stat collection server.
+///
+/// Polls each configured collector once a second, recording what it reads as
+/// gauges.
+pub struct Server {
+    collectors: Vec<Config>,
+    shutdown: Shutdown,
+}
+
+impl Server {
+    /// Create a new [`Server`] instance
+    #[must_use]
+    pub fn new(collectors: Vec<Config>, shutdown: Shutdown) -> Self {
+        Self {
+            collectors,
+            shutdown,
+        }
+    }
+
+    /// Run this [`Server`] to completion
+    ///
+    /// This function polls the configured collectors until a shutdown signal is
+    /// received. Failed polls are counted and otherwise ignored.
+    pub async fn run(mut self) {
+        let client: Client<HttpConnector, Body> = Client::builder().build_http();
+        let mut poll_delay = time::interval(Duration::from_secs(1));
+
+        loop {
+            tokio::select! {
+                _ = poll_delay.tick() => {
+                    for collector in &self.collectors {
+                        let result = time::timeout(POLL_TIMEOUT, poll(&client, collector))
+                            .await
+                            .unwrap_or_else(|_| Err(Error::Timeout));
+                        match result {
+                            Ok(readings) => {
+                                for reading in readings {
+                                    gauge!(reading.name, reading.value, &labels(collector, &reading));
+                                }
+                            }
+                            Err(err) => {
+                                debug!("runtime stat collection failed: {:?}", err);
+                                let error_labels = vec![
+                                    ("collector".to_string(), collector.name().to_string()),
+                                    ("error".to_string(), err.kind().to_string()),
+                                ];
+                                counter!("runtime_stats_failure", 1, &error_labels);
+                            }
+                        }
+                    }
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use serde_json::{json, Map, Value};
+
+    use super::{jolokia_gc, labels, Config, Reading};
+
+    // Each collector of a Jolokia garbage collector response is read, labeled
+    // by its name alone.
+    proptest! {
+        #[test]
+        fn jolokia_gc_labels_collector(name in "[A-Za-z0-9 ]{1,32}", count in any::<u32>(), millis in any::<u32>()) {
+            let bean = format!("java.lang:name={},type=GarbageCollector", name);
+            let mut beans = Map::new();
+            beans.insert(bean, json!({ "CollectionCount": count, "CollectionTime": millis }));
+            let response = json!({ "status": 200, "value": Value::Object(beans) });
+            let labels = vec![("garbage_collector".to_string(), name)];
+            prop_assert_eq!(jolokia_gc(&response), vec![
+                Reading { name: "jvm_gc_collection_count", value: f64::from(count), labels: labels.clone() },
+                Reading { name: "jvm_gc_collection_seconds", value: f64::from(millis) / 1_000.0, labels },
+            ]);
+        }
+    }
+
+    // The gauges of a Jolokia garbage collector response carry each label
+    // once: the runtime stat collector and the garbage collector.
+    proptest! {
+        #[test]
+        fn jolokia_gc_labels_rendered_once(name in "[A-Za-z0-9 ]{1,32}", count in any::<u32>()) {
+            let bean = format!("java.lang:name={},type=GarbageCollector", name);
+            let mut beans = Map::new();
+            beans.insert(bean, json!({ "CollectionCount": count }));
+            let response = json!({ "status": 200, "value": Value::Object(beans) });
+            let collector = Config::Jolokia { uri: "http://localhost:8778/jolokia".parse().unwrap() };
+            for reading in jolokia_gc(&response) {
+                prop_assert_eq!(labels(&collector, &reading), vec![
+                    ("collector".to_string(), "jolokia".to_string()),
+                    ("garbage_collector".to_string(), name.clone()),
+                ]);
+            }
+        }
+    }
+}
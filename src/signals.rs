@@ -3,15 +3,21 @@
 //! Lading manages at least one sub-process, possibly two and must coordinate
 //! shutdown with an experimental regime in addition to the target sub-process'
 //! potential failures. Controlling shutdown is the responsibility of the code
-//! in this module, specifically [`Shutdown`].
+//! in this module, specifically [`Shutdown`] and [`PhasedShutdown`].
 
-use std::sync::Arc;
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use tokio::{
     sync::broadcast,
-    time::{interval, Duration},
+    time::{interval, sleep, timeout, Duration, Instant},
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 #[derive(Debug)]
 /// Errors produced by [`Shutdown`]
@@ -36,10 +42,28 @@ pub struct Shutdown {
     /// instance.
     notify: broadcast::Receiver<()>,
 
+    /// Set once the shutdown signal has been sent, shared by all `Shutdown`
+    /// instances derived from the same root. An instance cloned after the
+    /// signal was sent never receives it, and consults this instead.
+    signaled: Arc<AtomicBool>,
+
     /// `true` if the shutdown signal has been received
     shutdown: bool,
 }
 
+#[derive(Debug, Clone)]
+/// Whether a [`Shutdown`] has been signaled, without participating in its
+/// shutdown. See [`Shutdown::signaled`].
+pub struct Signaled(Arc<AtomicBool>);
+
+impl Signaled {
+    /// Return `true` if the shutdown signal has been sent.
+    #[must_use]
+    pub fn is_signaled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
 impl Default for Shutdown {
     fn default() -> Self {
         Self::new()
@@ -56,10 +80,18 @@ impl Shutdown {
         Self {
             sender: Arc::new(shutdown_snd),
             notify: shutdown_rcv,
+            signaled: Arc::new(AtomicBool::new(false)),
             shutdown: false,
         }
     }
 
+    /// Return a [`Signaled`] for this and all derived `Shutdown` instances.
+    /// Unlike a clone, it is not waited on to shut down.
+    #[must_use]
+    pub fn signaled(&self) -> Signaled {
+        Signaled(Arc::clone(&self.signaled))
+    }
+
     /// Receive the shutdown notice. This function will block if a notice has
     /// not already been sent.
     pub async fn recv(&mut self) {
@@ -84,6 +116,7 @@ impl Shutdown {
     /// Function will return an error if the underlying tokio broadcast
     /// mechanism fails.
     pub fn signal(&self) -> Result<usize, Error> {
+        self.signaled.store(true, Ordering::SeqCst);
         self.sender.send(()).map_err(Error::Tokio)
     }
 
@@ -133,11 +166,128 @@ impl Shutdown {
 impl Clone for Shutdown {
     fn clone(&self) -> Self {
         let notify = self.sender.subscribe();
+        // The signal is flagged before it is sent. Subscribed too late to
+        // receive it, this instance sees the flag.
+        let shutdown = self.shutdown || self.signaled.load(Ordering::SeqCst);
 
         Self {
-            shutdown: self.shutdown,
+            shutdown,
             notify,
+            signaled: Arc::clone(&self.signaled),
             sender: Arc::clone(&self.sender),
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The phases of a [`PhasedShutdown`], in the order they are shut down.
+///
+/// A phase's discriminant indexes its root in [`PhasedShutdown`].
+pub enum Phase {
+    /// Generators, which push load into the target.
+    Generator,
+    /// The target and anything that inspects or observes it.
+    Target,
+    /// Blackholes, which the target pushes load into.
+    Blackhole,
+    /// Lading's own telemetry.
+    Telemetry,
+}
+
+#[derive(Debug)]
+/// Mechanism to control shutdown in lading, in phases.
+///
+/// A single [`Shutdown`] stops every component at once, truncating data that
+/// is in-flight between generators, the target and blackholes. This struct
+/// holds a root [`Shutdown`] per [`Phase`] and shuts them down in order:
+/// generators first, then -- once the pipeline has quiesced -- the target,
+/// then blackholes and finally telemetry, so that captures include the full
+/// tail of the experiment.
+pub struct PhasedShutdown {
+    /// The root of each phase, indexed by [`Phase`], taken once the phase is
+    /// shut down.
+    roots: [Option<Shutdown>; 4],
+}
+
+impl Default for PhasedShutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PhasedShutdown {
+    /// Create a new `PhasedShutdown` instance.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            roots: [
+                Some(Shutdown::new()),
+                Some(Shutdown::new()),
+                Some(Shutdown::new()),
+                Some(Shutdown::new()),
+            ],
+        }
+    }
+
+    /// Get a [`Shutdown`] instance for a component participating in `phase`.
+    ///
+    /// # Panics
+    ///
+    /// Function will panic if `phase` has already been shut down.
+    pub fn get(&self, phase: Phase) -> Shutdown {
+        self.roots[phase as usize]
+            .as_ref()
+            .expect("phase already shut down")
+            .clone()
+    }
+
+    /// Signal `phase` to shut down and wait up to `max_delay` for all its
+    /// instances to do so. Does nothing if `phase` is already shut down.
+    async fn shutdown_phase(&mut self, phase: Phase, max_delay: Duration) {
+        if let Some(root) = self.roots[phase as usize].take() {
+            info!("shutting down {:?} phase", phase);
+            if let Err(err) = root.signal() {
+                // No instances remain to receive the signal, nothing to wait
+                // on.
+                warn!("unable to signal {:?} phase: {:?}", phase, err);
+                return;
+            }
+            // `Shutdown::wait` uses `max_delay` as an interval period, which
+            // must be non-zero.
+            root.wait(max_delay.max(Duration::from_millis(1))).await;
+        }
+    }
+
+    /// Shut down all phases in order, taking no longer than `max_delay` in
+    /// total, not counting `cooldown`.
+    ///
+    /// Once the generators have shut down the target, blackholes and telemetry
+    /// are left running for `cooldown`, observing the target as it returns to
+    /// idle. Then `quiescence` is awaited before the target is signaled. This
+    /// is the caller's opportunity to wait for in-flight data to drain out of
+    /// the target.
+    pub async fn shutdown<F>(mut self, max_delay: Duration, cooldown: Duration, quiescence: F)
+    where
+        F: Future<Output = ()>,
+    {
+        let mut deadline = Instant::now() + max_delay;
+        let remaining = |deadline: Instant| deadline.saturating_duration_since(Instant::now());
+
+        self.shutdown_phase(Phase::Generator, remaining(deadline))
+            .await;
+        if !cooldown.is_zero() {
+            info!("generators stopped, cooling down for {:?}", cooldown);
+            sleep(cooldown).await;
+            deadline += cooldown;
+        }
+        if timeout(remaining(deadline), quiescence).await.is_err() {
+            warn!("pipeline did not quiesce before shutdown deadline");
+        }
+        self.shutdown_phase(Phase::Target, remaining(deadline))
+            .await;
+        self.shutdown_phase(Phase::Blackhole, remaining(deadline))
+            .await;
+        self.shutdown_phase(Phase::Telemetry, remaining(deadline))
+            .await;
+    }
+}
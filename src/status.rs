@@ -0,0 +1,310 @@
+//! Report the state of a run to a file
+//!
+//! Orchestration running lading wants to know where a run is -- warming up,
+//! collecting samples, shutting down -- and whether it is healthy, without
+//! scraping the prometheus exporter or parsing logs. The status [`Server`]
+//! writes a small JSON document to a file periodically, and whenever the run's
+//! [`State`] changes, for such orchestration to poll. The document holds the
+//! run's phase, elapsed and remaining time, the bytes written to and received
+//! from the target, their rates and their ratio, and health flags.
+//!
+//! The file is replaced atomically, by rename, so a reader never sees a
+//! partially written document.
+
+use std::{io, path::PathBuf};
+
+use serde::Serialize;
+use tokio::{
+    sync::watch,
+    time::{self, Duration, Instant},
+};
+
+use crate::{blackhole, captures, generator, watchdog};
+
+#[derive(Debug)]
+/// Errors produced by [`Server`]
+pub enum Error {
+    /// Wrapper for [`std::io::Error`]
+    Io(io::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// The phase of a run
+pub enum Phase {
+    /// Components are being set up, the target is not yet running.
+    Setup,
+    /// The target is running but samples are not yet collected.
+    Warmup,
+    /// Samples are collected.
+    Experiment,
+    /// The run is shutting down.
+    ShuttingDown,
+    /// The run is over.
+    Finished,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+/// Why the experiment ended
+pub enum Ending {
+    /// The experiment ran for its full duration.
+    DurationElapsed,
+    /// Generators finished and the target drained into the blackholes.
+    Drained,
+    /// Lading was interrupted.
+    Interrupted,
+    /// A component failed, aborting the experiment.
+    ComponentFailed,
+    /// The target exited before the experiment ended.
+    TargetExited,
+    /// The experiment was advanced by hand, see [`crate::control::Advance`].
+    Advanced,
+}
+
+impl Ending {
+    /// Whether the experiment was aborted rather than brought to an end, its
+    /// results not to be trusted.
+    #[must_use]
+    pub fn aborted(self) -> bool {
+        matches!(self, Ending::ComponentFailed | Ending::TargetExited)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The state of a run, sent to the [`Server`] as it changes
+pub struct State {
+    /// The run's phase
+    pub phase: Phase,
+    /// Why the experiment ended, once it has
+    pub ending: Option<Ending>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            phase: Phase::Setup,
+            ending: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Configuration for [`Server`]
+pub struct Config {
+    /// The path of the status file
+    pub path: PathBuf,
+    /// The time between writes of the status file
+    pub interval: Duration,
+    /// The experiment's id, if set
+    pub experiment_id: Option<String>,
+    /// The run's warmup duration
+    pub warmup: Duration,
+    /// The run's experiment duration
+    pub experiment: Duration,
+    /// Whether the run has blackholes. Without them no efficiency is
+    /// reported.
+    pub blackholes: bool,
+}
+
+#[derive(Debug, Serialize)]
+/// The status document
+struct Status<'a> {
+    experiment_id: Option<&'a str>,
+    phase: Phase,
+    ending: Option<Ending>,
+    elapsed_seconds: f64,
+    remaining_seconds: f64,
+    bytes_written: u64,
+    bytes_written_per_second: f64,
+    bytes_received: u64,
+    bytes_received_per_second: f64,
+    efficiency: Option<f64>,
+    healthy: bool,
+    target_stalled: bool,
+    component_failed: bool,
+    target_exited: bool,
+}
+
+/// The time remaining in the run: the rest of warmup and the experiment
+/// duration while warming up, the rest of the experiment duration while
+/// collecting samples and none otherwise. `in_phase` is the time spent in the
+/// current phase.
+fn remaining(config: &Config, phase: Phase, in_phase: Duration) -> Duration {
+    match phase {
+        Phase::Setup => config.warmup + config.experiment,
+        Phase::Warmup => config.warmup.saturating_sub(in_phase) + config.experiment,
+        Phase::Experiment => config.experiment.saturating_sub(in_phase),
+        Phase::ShuttingDown | Phase::Finished => Duration::ZERO,
+    }
+}
+
+/// The throughput efficiency of a run: the bytes received by its blackholes
+/// for each byte written by its generators. There is none until a byte is
+/// written.
+#[must_use]
+pub fn efficiency(bytes_written: u64, bytes_received: u64) -> Option<f64> {
+    (bytes_written > 0).then(|| bytes_received as f64 / bytes_written as f64)
+}
+
+/// Per-second rate of a counter that went from `last` to `now` over `elapsed`.
+fn rate(now: u64, last: u64, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        0.0
+    } else {
+        now.saturating_sub(last) as f64 / elapsed.as_secs_f64()
+    }
+}
+
+#[derive(Debug)]
+/// The status file writer.
+///
+/// Writes the status file every [`Config::interval`] and whenever the run's
+/// [`State`] changes, until the run is [`Phase::Finished`].
+pub struct Server {
+    config: Config,
+    state: watch::Receiver<State>,
+}
+
+impl Server {
+    /// Create a new [`Server`] instance
+    #[must_use]
+    pub fn new(config: Config, state: watch::Receiver<State>) -> Self {
+        Self { config, state }
+    }
+
+    /// Run this [`Server`] to completion
+    ///
+    /// The status file is last written once the run is [`Phase::Finished`],
+    /// or the sender of its state dropped.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the status file cannot be written.
+    pub async fn run(mut self) -> Result<(), Error> {
+        let started = Instant::now();
+        let mut write_delay = time::interval(self.config.interval);
+        let mut phase = self.state.borrow().phase;
+        let mut phase_started = started;
+        let mut last = (started, 0, 0);
+
+        loop {
+            let closed = tokio::select! {
+                _ = write_delay.tick() => false,
+                res = self.state.changed() => res.is_err(),
+            };
+            let state = *self.state.borrow();
+            let now = Instant::now();
+            if state.phase != phase {
+                phase = state.phase;
+                phase_started = now;
+            }
+
+            let bytes_written = generator::total_bytes_written();
+            let bytes_received = blackhole::total_bytes_received();
+            let (last_instant, last_written, last_received) = last;
+            let elapsed = now.duration_since(last_instant);
+            last = (now, bytes_written, bytes_received);
+
+            let target_stalled = watchdog::target_stalled();
+            let component_failed = state.ending == Some(Ending::ComponentFailed);
+            let target_exited = state.ending == Some(Ending::TargetExited);
+            let status = Status {
+                experiment_id: self.config.experiment_id.as_deref(),
+                phase,
+                ending: state.ending,
+                elapsed_seconds: now.duration_since(started).as_secs_f64(),
+                remaining_seconds: remaining(
+                    &self.config,
+                    phase,
+                    now.duration_since(phase_started),
+                )
+                .as_secs_f64(),
+                bytes_written,
+                bytes_written_per_second: rate(bytes_written, last_written, elapsed),
+                bytes_received,
+                bytes_received_per_second: rate(bytes_received, last_received, elapsed),
+                efficiency: if self.config.blackholes {
+                    efficiency(bytes_written, bytes_received)
+                } else {
+                    None
+                },
+                healthy: !(target_stalled || component_failed || target_exited),
+                target_stalled,
+                component_failed,
+                target_exited,
+            };
+            self.write(&status).await?;
+
+            if closed || phase == Phase::Finished {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Write `status` to a temporary file beside the status file, renaming it
+    /// over the status file.
+    async fn write(&self, status: &Status<'_>) -> Result<(), Error> {
+        captures::write_json(&self.config.path, status)
+            .await
+            .map_err(Error::Io)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use tokio::time::Duration;
+
+    use super::{efficiency, remaining, Config, Phase};
+
+    // Efficiency is reported once a byte is written, is finite and never
+    // negative, and is one when every byte written was received.
+    proptest! {
+        #[test]
+        fn efficiency_of_written(bytes_written: u64, bytes_received: u64) {
+            match efficiency(bytes_written, bytes_received) {
+                None => prop_assert_eq!(bytes_written, 0),
+                Some(efficiency) => {
+                    prop_assert!(bytes_written > 0);
+                    prop_assert!(efficiency >= 0.0);
+                    prop_assert!(efficiency.is_finite());
+                }
+            }
+            if bytes_written > 0 {
+                prop_assert_eq!(efficiency(bytes_written, bytes_written), Some(1.0));
+            }
+        }
+    }
+
+    // Remaining time never grows as a phase goes on, and is the full schedule
+    // before the target starts.
+    proptest! {
+        #[test]
+        fn remaining_shrinks(warmup in 0..600_u64, experiment in 0..3600_u64, earlier in 0..4200_u64, later in 0..4200_u64) {
+            let config = Config {
+                path: "status.json".into(),
+                interval: Duration::from_secs(1),
+                experiment_id: None,
+                warmup: Duration::from_secs(warmup),
+                experiment: Duration::from_secs(experiment),
+                blackholes: false,
+            };
+            let (earlier, later) = (earlier.min(later), earlier.max(later));
+            prop_assert_eq!(
+                remaining(&config, Phase::Setup, Duration::from_secs(earlier)),
+                Duration::from_secs(warmup + experiment)
+            );
+            for phase in [Phase::Warmup, Phase::Experiment, Phase::ShuttingDown] {
+                prop_assert!(
+                    remaining(&config, phase, Duration::from_secs(later))
+                        <= remaining(&config, phase, Duration::from_secs(earlier))
+                );
+            }
+            prop_assert_eq!(
+                remaining(&config, Phase::Experiment, Duration::from_secs(experiment)),
+                Duration::ZERO
+            );
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Supervise lading's components
+//!
+//! Generators and blackholes run as independent tasks. Should one of these
+//! tasks fail -- by panicking or by returning an error -- the experiment would,
+//! without supervision, silently degrade. The code in this module detects such
+//! failures, records them and either restarts the failed component or reports
+//! the failure so that the experiment may be aborted, per [`Policy`]. A
+//! component failing once its shutdown has been signaled is not restarted.
+
+use std::{fmt::Debug, future::Future};
+
+use metrics::counter;
+use serde::Deserialize;
+use tracing::{error, info, warn};
+
+use crate::signals::Signaled;
+
+#[derive(Debug)]
+/// Errors produced by [`supervise`]
+pub enum Error {
+    /// The component failed and may not be restarted.
+    Failed {
+        /// The name of the failed component.
+        component: String,
+        /// A description of the failure.
+        reason: String,
+    },
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// Defines what is done when a component fails.
+pub enum Policy {
+    /// Abort the experiment.
+    Abort,
+    /// Restart the failed component, up to `maximum_restarts` times. Once
+    /// restarts are exhausted the experiment is aborted.
+    Restart {
+        /// The maximum number of times a component will be restarted.
+        maximum_restarts: u32,
+    },
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::Abort
+    }
+}
+
+/// Run a component to completion, restarting it according to `policy`.
+///
+/// Each call to `make` must produce a fresh instance of the component, which
+/// is run in its own task so that panics are isolated. A component that
+/// completes without error is assumed to have done so intentionally, for
+/// instance on shutdown. A `component_failure` counter is incremented for
+/// every failure and a `component_restart` counter for every restart. A
+/// component that fails once `shutdown` is signaled is not restarted, its
+/// failure counted but not returned.
+///
+/// # Errors
+///
+/// Function will return an error if the component fails and `policy` does not
+/// allow it to be restarted.
+pub async fn supervise<M, F, E>(
+    component: String,
+    policy: Policy,
+    shutdown: Signaled,
+    mut make: M,
+) -> Result<(), Error>
+where
+    M: FnMut() -> F,
+    F: Future<Output = Result<(), E>> + Send + 'static,
+    E: Debug + Send + 'static,
+{
+    let labels = vec![("component".to_string(), component.clone())];
+    let mut restarts: u32 = 0;
+    loop {
+        let reason = match tokio::spawn(make()).await {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(err)) => format!("{:?}", err),
+            Err(err) => err.to_string(),
+        };
+        error!("component {} failed: {}", component, reason);
+        counter!("component_failure", 1, &labels);
+
+        if shutdown.is_signaled() {
+            info!(
+                "component {} failed while shutting down, not restarted",
+                component
+            );
+            return Ok(());
+        }
+        match policy {
+            Policy::Restart { maximum_restarts } if restarts < maximum_restarts => {
+                restarts += 1;
+                warn!(
+                    "restarting component {}, restart {} of {}",
+                    component, restarts, maximum_restarts
+                );
+                counter!("component_restart", 1, &labels);
+            }
+            Policy::Restart { .. } | Policy::Abort => {
+                return Err(Error::Failed { component, reason });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+
+    use proptest::prelude::*;
+
+    use super::{supervise, Policy};
+    use crate::signals::Shutdown;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    // A failing component is restarted until it succeeds or its restarts are
+    // exhausted, whichever is first.
+    proptest! {
+        #[test]
+        fn restarts_until_exhausted(maximum_restarts in 0_u32..8, failures in 0_u32..12) {
+            let shutdown = Shutdown::new();
+            let attempts = Arc::new(AtomicU32::new(0));
+            let make_attempts = Arc::clone(&attempts);
+            let res = block_on(supervise(
+                "test".to_string(),
+                Policy::Restart { maximum_restarts },
+                shutdown.signaled(),
+                move || {
+                    let attempt = make_attempts.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        if attempt < failures { Err("failed") } else { Ok(()) }
+                    }
+                },
+            ));
+            if failures <= maximum_restarts {
+                prop_assert!(res.is_ok());
+                prop_assert_eq!(attempts.load(Ordering::SeqCst), failures + 1);
+            } else {
+                prop_assert!(res.is_err());
+                prop_assert_eq!(attempts.load(Ordering::SeqCst), maximum_restarts + 1);
+            }
+        }
+    }
+
+    // A component that fails as its shutdown is signaled is not restarted,
+    // however many restarts remain, and instances cloned after the signal see
+    // it.
+    proptest! {
+        #[test]
+        fn no_restart_during_shutdown(maximum_restarts in 1_u32..8) {
+            let shutdown = Shutdown::new();
+            let signaled = shutdown.signaled();
+            let attempts = Arc::new(AtomicU32::new(0));
+            let make_attempts = Arc::clone(&attempts);
+            let res = block_on(supervise(
+                "test".to_string(),
+                Policy::Restart { maximum_restarts },
+                shutdown.signaled(),
+                move || {
+                    make_attempts.fetch_add(1, Ordering::SeqCst);
+                    shutdown.signal().unwrap();
+                    let mut late = shutdown.clone();
+                    async move {
+                        late.recv().await;
+                        Err::<(), _>("failed")
+                    }
+                },
+            ));
+            prop_assert!(res.is_ok());
+            prop_assert!(signaled.is_signaled());
+            prop_assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        }
+    }
+}
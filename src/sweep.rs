@@ -0,0 +1,419 @@
+//! Run an experiment across a matrix of configuration overrides
+//!
+//! Comparing a target across rates, payload variants and the like means
+//! running the same experiment many times, each with a slightly different
+//! configuration. A sweep [`Config`] names, for each configuration path to
+//! vary, the values to try. [`points`] expands the matrix into every
+//! combination and [`apply`] writes one combination into a base configuration,
+//! also labeling the experiment by it, see [`crate::config::Experiment`].
+//!
+//! Paths are dot separated, a segment indexing into a mapping by key or into a
+//! sequence by position: `generator.http.bytes_per_second`, or
+//! `generator.0.http.bytes_per_second` where several generators are
+//! configured.
+//!
+//! Once each run is complete its capture file is reduced by [`summarize`] and
+//! the runs compared side by side with [`write_summary`]. Two runs, the
+//! candidates of an A/B comparison, are compared metric by metric with
+//! [`write_comparison`].
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, BufRead, Write},
+};
+
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+
+use crate::captures;
+
+#[derive(Debug)]
+/// Errors produced by [`apply`]
+pub enum Error {
+    /// The path could not be applied to the configuration: a segment indexes
+    /// into a scalar, or past the end of a sequence.
+    Path(String),
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+/// Configuration of a sweep
+pub struct Config {
+    /// The values to try for each configuration path. Every combination is
+    /// run.
+    pub matrix: BTreeMap<String, Vec<Value>>,
+}
+
+/// One combination of the matrix, a value for each path.
+pub type Point = Vec<(String, Value)>;
+
+/// Expand `matrix` into every combination of its values, ordered with the last
+/// path varying fastest. An empty matrix has one point, the base configuration
+/// itself.
+#[must_use]
+pub fn points(matrix: &BTreeMap<String, Vec<Value>>) -> Vec<Point> {
+    let mut points: Vec<Point> = vec![Vec::new()];
+    for (path, values) in matrix {
+        points = points
+            .into_iter()
+            .flat_map(|point| {
+                values.iter().map(move |value| {
+                    let mut point = point.clone();
+                    point.push((path.clone(), value.clone()));
+                    point
+                })
+            })
+            .collect();
+    }
+    points
+}
+
+/// The experiment label recording the value of `path`.
+#[must_use]
+pub fn label_name(path: &str) -> String {
+    path.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Render `value` as an experiment label value.
+#[must_use]
+pub fn label_value(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        Value::Number(value) => value.to_string(),
+        Value::Bool(value) => value.to_string(),
+        Value::Null => "null".to_string(),
+        value => serde_json::to_string(value).unwrap_or_default(),
+    }
+}
+
+/// Set the value at `segments` in `config`, creating mappings along the way as
+/// needed.
+fn set(config: &mut Value, segments: &[&str], value: Value) -> Result<(), Error> {
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => {
+            *config = value;
+            return Ok(());
+        }
+    };
+    if config.is_null() {
+        *config = Value::Mapping(Mapping::new());
+    }
+    let child = match config {
+        Value::Mapping(mapping) => {
+            let key = Value::String((*segment).to_string());
+            if mapping.get(&key).is_none() {
+                mapping.insert(key.clone(), Value::Null);
+            }
+            mapping.get_mut(&key).unwrap()
+        }
+        Value::Sequence(sequence) => segment
+            .parse::<usize>()
+            .ok()
+            .and_then(|idx| sequence.get_mut(idx))
+            .ok_or_else(|| Error::Path((*segment).to_string()))?,
+        _ => return Err(Error::Path((*segment).to_string())),
+    };
+    set(child, rest, value)
+}
+
+/// Write `point` into the configuration `config`, labeling its experiment by
+/// the value of each path and by `run`. The experiment's id is set to
+/// `experiment_id`, if any, grouping the runs of the sweep.
+///
+/// # Errors
+///
+/// Function will return an error if a path of `point` cannot be applied to
+/// `config`.
+pub fn apply(
+    config: &mut Value,
+    point: &[(String, Value)],
+    experiment_id: Option<&str>,
+    run: &str,
+) -> Result<(), Error> {
+    for (path, value) in point {
+        let segments: Vec<&str> = path.split('.').collect();
+        set(config, &segments, value.clone()).map_err(|err| match err {
+            Error::Path(segment) => Error::Path(format!("{} (at {})", path, segment)),
+        })?;
+        set(
+            config,
+            &["experiment", "labels", &label_name(path)],
+            Value::String(label_value(value)),
+        )?;
+    }
+    set(
+        config,
+        &["experiment", "labels", "sweep_run"],
+        Value::String(run.to_string()),
+    )?;
+    if let Some(id) = experiment_id {
+        set(config, &["experiment", "id"], Value::String(id.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Reduce the capture lines of `reader` to one value per metric. Counters are
+/// reported as their final total, summed across series, as `<name>_total`. A
+/// series whose lines carry a `delta` totals its deltas, so that resets are
+/// counted through. Gauges are reported as the mean of all their samples as
+/// `<name>_mean`. Histograms are not reported.
+///
+/// # Errors
+///
+/// Function will return an error if reading from `reader` fails.
+pub fn summarize<R>(reader: R) -> Result<BTreeMap<String, f64>, io::Error>
+where
+    R: BufRead,
+{
+    let recovered = captures::recover(reader)?;
+    // The greatest value and, if recorded, the sum of deltas of each series.
+    let mut counters: HashMap<(String, String), (f64, Option<f64>)> = HashMap::new();
+    let mut gauges: HashMap<String, (f64, u64)> = HashMap::new();
+    for record in &recovered.records {
+        let record: BTreeMap<String, serde_json::Value> = match serde_json::from_str(record) {
+            Ok(record) => record,
+            Err(_) => continue,
+        };
+        let (name, kind, value) = match (
+            record
+                .get("metric_name")
+                .and_then(serde_json::Value::as_str),
+            record
+                .get("metric_kind")
+                .and_then(serde_json::Value::as_str),
+            record.get("value").and_then(serde_json::Value::as_f64),
+        ) {
+            (Some(name), Some(kind), Some(value)) => (name, kind, value),
+            _ => continue,
+        };
+        match kind {
+            "counter" => {
+                let series: Vec<String> = record
+                    .iter()
+                    .filter(|(field, _)| {
+                        !captures::SAMPLE_FIELDS.contains(&field.as_str())
+                            && field.as_str() != "run_id"
+                    })
+                    .map(|(field, value)| format!("{}={}", field, value))
+                    .collect();
+                let (maximum, deltas) = counters
+                    .entry((name.to_string(), series.join(",")))
+                    .or_insert((0.0, None));
+                *maximum = maximum.max(value);
+                if let Some(delta) = record.get("delta").and_then(serde_json::Value::as_f64) {
+                    *deltas = Some(deltas.unwrap_or(0.0) + delta);
+                }
+            }
+            "gauge" => {
+                let (sum, count) = gauges.entry(name.to_string()).or_insert((0.0, 0));
+                *sum += value;
+                *count += 1;
+            }
+            _ => {}
+        }
+    }
+
+    let mut summary = BTreeMap::new();
+    for ((name, _), (maximum, deltas)) in counters {
+        *summary.entry(format!("{}_total", name)).or_insert(0.0) += deltas.unwrap_or(maximum);
+    }
+    for (name, (sum, count)) in gauges {
+        summary.insert(format!("{}_mean", name), sum / count as f64);
+    }
+    Ok(summary)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The outcome of one run of a sweep.
+pub struct Run {
+    /// The name of the run.
+    pub name: String,
+    /// The point of the matrix run.
+    pub point: Point,
+    /// Whether the run exited successfully.
+    pub succeeded: bool,
+    /// The run's capture file, summarized. See [`summarize`].
+    pub summary: BTreeMap<String, f64>,
+}
+
+/// Quote `field` for CSV, if needed.
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Write `runs` to `writer` as CSV, a row per run and a column per path of the
+/// matrix and per summarized metric. Metrics a run did not capture are left
+/// empty.
+///
+/// # Errors
+///
+/// Function will return an error if writing to `writer` fails.
+pub fn write_summary<W>(runs: &[Run], mut writer: W) -> Result<(), io::Error>
+where
+    W: Write,
+{
+    let paths: Vec<&str> = runs
+        .first()
+        .map(|run| run.point.iter().map(|(path, _)| path.as_str()).collect())
+        .unwrap_or_default();
+    let mut metrics: Vec<&str> = runs
+        .iter()
+        .flat_map(|run| run.summary.keys().map(String::as_str))
+        .collect();
+    metrics.sort_unstable();
+    metrics.dedup();
+
+    let header: Vec<String> = ["run", "succeeded"]
+        .iter()
+        .chain(paths.iter())
+        .chain(metrics.iter())
+        .map(|column| csv_field(column))
+        .collect();
+    writeln!(writer, "{}", header.join(","))?;
+    for run in runs {
+        let mut row = vec![csv_field(&run.name), run.succeeded.to_string()];
+        row.extend(
+            run.point
+                .iter()
+                .map(|(_, value)| csv_field(&label_value(value))),
+        );
+        row.extend(metrics.iter().map(|metric| {
+            run.summary
+                .get(*metric)
+                .map(ToString::to_string)
+                .unwrap_or_default()
+        }));
+        writeln!(writer, "{}", row.join(","))?;
+    }
+    writer.flush()
+}
+
+/// The relative change from `a` to `b`, if `a` is not zero.
+#[must_use]
+pub fn change(a: f64, b: f64) -> Option<f64> {
+    (a != 0.0).then(|| (b - a) / a.abs())
+}
+
+/// Write the comparison of run `b` against run `a` to `writer` as CSV, a row
+/// per metric either run summarized with its value in each and the relative
+/// change from `a` to `b`. Values a run did not capture, and changes from
+/// zero, are left empty.
+///
+/// # Errors
+///
+/// Function will return an error if writing to `writer` fails.
+pub fn write_comparison<W>(a: &Run, b: &Run, mut writer: W) -> Result<(), io::Error>
+where
+    W: Write,
+{
+    let mut metrics: Vec<&str> = a
+        .summary
+        .keys()
+        .chain(b.summary.keys())
+        .map(String::as_str)
+        .collect();
+    metrics.sort_unstable();
+    metrics.dedup();
+
+    writeln!(
+        writer,
+        "metric,{},{},change",
+        csv_field(&a.name),
+        csv_field(&b.name)
+    )?;
+    for metric in metrics {
+        let (value_a, value_b) = (a.summary.get(metric), b.summary.get(metric));
+        let change = match (value_a, value_b) {
+            (Some(value_a), Some(value_b)) => change(*value_a, *value_b),
+            _ => None,
+        };
+        let field = |value: Option<&f64>| value.map(ToString::to_string).unwrap_or_default();
+        writeln!(
+            writer,
+            "{},{},{},{}",
+            csv_field(metric),
+            field(value_a),
+            field(value_b),
+            field(change.as_ref())
+        )?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use proptest::{collection, prelude::*};
+    use serde_yaml::Value;
+
+    use super::{apply, label_name, points, write_comparison, Run};
+
+    // A matrix expands to the product of its value counts, every point
+    // distinct.
+    proptest! {
+        #[test]
+        fn points_are_product(lengths in collection::vec(1..4_usize, 0..4)) {
+            let matrix: BTreeMap<String, Vec<Value>> = lengths
+                .iter()
+                .enumerate()
+                .map(|(idx, len)| {
+                    (format!("p{}", idx), (0..*len).map(Value::from).collect())
+                })
+                .collect();
+            let mut points = points(&matrix);
+            prop_assert_eq!(points.len(), lengths.iter().product::<usize>());
+            points.dedup();
+            prop_assert_eq!(points.len(), lengths.iter().product::<usize>());
+        }
+    }
+
+    // An applied value can be read back at its path, and is recorded as an
+    // experiment label.
+    proptest! {
+        #[test]
+        fn apply_sets_path(segments in collection::vec("[a-z]{1,8}", 1..4), value in any::<u32>()) {
+            let path = segments.join(".");
+            let mut config = Value::Null;
+            apply(&mut config, &[(path.clone(), Value::from(value))], Some("id"), "run-000").unwrap();
+            let mut found = &config;
+            for segment in &segments {
+                found = &found[segment.as_str()];
+            }
+            prop_assert_eq!(found, &Value::from(value));
+            let label = label_name(&path);
+            prop_assert_eq!(&config["experiment"]["labels"][label.as_str()], &Value::from(value.to_string()));
+            prop_assert_eq!(&config["experiment"]["id"], &Value::from("id"));
+        }
+    }
+
+    // A comparison has a row for every metric summarized by either run, and a
+    // header.
+    proptest! {
+        #[test]
+        fn comparison_covers_metrics(
+            a in collection::btree_map("[a-z]{1,8}", any::<f64>(), 0..16),
+            b in collection::btree_map("[a-z]{1,8}", any::<f64>(), 0..16),
+        ) {
+            let run = |name: &str, summary: BTreeMap<String, f64>| Run {
+                name: name.to_string(),
+                point: Vec::new(),
+                succeeded: true,
+                summary,
+            };
+            let mut metrics: Vec<&String> = a.keys().chain(b.keys()).collect();
+            metrics.sort_unstable();
+            metrics.dedup();
+            let mut buffer = Vec::new();
+            write_comparison(&run("a", a.clone()), &run("b", b.clone()), &mut buffer).unwrap();
+            let comparison = String::from_utf8(buffer).unwrap();
+            prop_assert_eq!(comparison.lines().count(), metrics.len() + 1);
+        }
+    }
+}
@@ -7,25 +7,81 @@
 //!
 //! It is lading's responsibility to start the target sub-process and shut it
 //! down cleanly by signaling SIGTERM to it. If the target crashes this is also
-//! detected and lading does a controlled shutdown.
+//! detected and lading does a controlled shutdown. A target killed on
+//! purpose, see [`crate::chaos`], is instead restarted.
+//!
+//! Lading provides the target with variables describing the experiment, the
+//! addresses its blackholes are bound to say, see [`Config::provide`]. A
+//! target's configuration need then not hard-code what differs between rigs.
+//! A target configured by file may have that file rendered from a template,
+//! see [`ConfigFile`].
+//!
+//! The target may read its load from stdin rather than the network. Its stdin
+//! is then piped and handed to the stdin generator, see
+//! [`crate::generator::stdin`], afresh each time the target is restarted.
 
 use std::{
     collections::HashMap,
     io,
     path::PathBuf,
     process::{ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Mutex,
+    },
 };
 
+use metrics::counter;
 use nix::{
     errno::Errno,
     sys::signal::{kill, SIGTERM},
     unistd::Pid,
 };
-use tokio::{process::Command, sync::broadcast::Sender};
+use once_cell::sync::Lazy;
+use tokio::{
+    process::{ChildStdin, Command},
+    sync::broadcast::Sender,
+};
 use tracing::{error, info};
 
 pub use crate::common::{Behavior, Output};
-use crate::{common::stdio, signals::Shutdown};
+use crate::{captures, cleanup, common::stdio, signals::Shutdown};
+
+/// The PID of the running target, zero if there is none.
+static TARGET_PID: AtomicU32 = AtomicU32::new(0);
+
+/// Whether the target's next exit is expected, the target to be restarted.
+static RESTART: AtomicBool = AtomicBool::new(false);
+
+/// The running target's stdin, if piped and not yet taken.
+static STDIN: Lazy<Mutex<Option<ChildStdin>>> = Lazy::new(|| Mutex::new(None));
+
+/// Return the PID of the running target, if there is one.
+#[must_use]
+pub fn target_pid() -> Option<u32> {
+    match TARGET_PID.load(Ordering::Relaxed) {
+        0 => None,
+        pid => Some(pid),
+    }
+}
+
+/// Take the running target's stdin, if it is piped and has not already been
+/// taken. Each start of the target pipes a new stdin.
+pub(crate) fn take_stdin() -> Option<ChildStdin> {
+    STDIN.lock().unwrap().take()
+}
+
+/// Expect the target's next exit, restarting the target once it exits rather
+/// than shutting the experiment down.
+pub fn expect_restart() {
+    RESTART.store(true, Ordering::Relaxed);
+}
+
+/// Withdraw [`expect_restart`], the exit that was to be expected not brought
+/// about after all.
+pub fn cancel_restart() {
+    RESTART.store(false, Ordering::Relaxed);
+}
 
 #[derive(Debug)]
 /// Errors produced by [`Server`]
@@ -48,6 +104,90 @@ pub struct Config {
     pub environment_variables: HashMap<String, String>,
     /// Manages stderr, stdout of the target sub-process.
     pub output: Output,
+    /// The target's configuration file, rendered before the target is
+    /// started.
+    pub config_file: Option<ConfigFile>,
+    /// Whether to pipe the target's stdin for the stdin generator, see
+    /// [`take_stdin`]. Otherwise the target's stdin is null.
+    pub stdin: bool,
+}
+
+#[derive(Debug, Clone)]
+/// A target configuration file rendered from a template
+///
+/// Each `${NAME}` in the template, `NAME` one of the target's environment
+/// variables, is substituted by the variable's value. This includes the
+/// variables lading provides, see [`Config::provide`].
+pub struct ConfigFile {
+    /// The path of the template.
+    pub template: PathBuf,
+    /// The path to write the rendered configuration to.
+    pub output: PathBuf,
+}
+
+impl ConfigFile {
+    /// Render the template with `variables`, writing the output.
+    fn render(&self, variables: &HashMap<String, String>) -> Result<(), Error> {
+        let template = std::fs::read_to_string(&self.template).map_err(Error::Io)?;
+        if let Some(parent) = self
+            .output
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent).map_err(Error::Io)?;
+        }
+        std::fs::write(&self.output, render(&template, variables)).map_err(Error::Io)
+    }
+}
+
+/// Substitute the value of each of `variables` for its references in
+/// `template`, in one pass: a reference in a substituted value is not itself
+/// substituted. References to unknown variables are left as they are.
+fn render(template: &str, variables: &HashMap<String, String>) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        rendered.push_str(&rest[..start]);
+        let reference = &rest[start..];
+        match reference
+            .find('}')
+            .and_then(|end| variables.get(&reference[2..end]).map(|value| (end, value)))
+        {
+            Some((end, value)) => {
+                rendered.push_str(value);
+                rest = &reference[end + 1..];
+            }
+            None => {
+                rendered.push_str("${");
+                rest = &reference[2..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Substitute `value` for each `${name}` in `template`.
+fn substitute(template: &str, name: &str, value: &str) -> String {
+    template.replace(&format!("${{{}}}", name), value)
+}
+
+impl Config {
+    /// Provide the variable `name` to the target. It is set in the target's
+    /// environment, unless already set there, and substituted for each
+    /// `${name}` in the target's arguments and the values of its environment
+    /// variables.
+    pub fn provide(&mut self, name: &str, value: &str) {
+        for argument in &mut self.arguments {
+            *argument = substitute(argument, name, value);
+        }
+        for env_value in self.environment_variables.values_mut() {
+            *env_value = substitute(env_value, name, value);
+        }
+        self.environment_variables
+            .entry(name.to_string())
+            .or_insert_with(|| value.to_string());
+    }
 }
 
 #[derive(Debug)]
@@ -83,59 +223,146 @@ impl Server {
     /// propagate. This is less than ideal.
     ///
     /// Target server will use the `broadcast::Sender` passed here to transmit
-    /// its PID.
+    /// its PID, and the PID of each restart of the target.
     ///
     /// # Errors
     ///
-    /// Function will return an error if the underlying program cannot be waited
-    /// on or will not shutdown when signaled to.
+    /// Function will return an error if the target's configuration file cannot
+    /// be rendered, or if the underlying program cannot be waited on or will
+    /// not shutdown when signaled to.
     ///
     /// # Panics
     ///
     /// None are known.
     pub async fn run(mut self, pid_snd: Sender<u32>) -> Result<ExitStatus, Error> {
         let config = self.config;
+        if let Some(ref config_file) = config.config_file {
+            config_file.render(&config.environment_variables)?;
+            info!(
+                "rendered target configuration {} from {}",
+                config_file.output.display(),
+                config_file.template.display()
+            );
+        }
 
-        let mut target_cmd = Command::new(config.command);
+        let mut target_cmd = Command::new(&config.command);
         target_cmd
-            .stdin(Stdio::null())
+            .stdin(if config.stdin {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
             .stdout(stdio(&config.output.stdout))
             .stderr(stdio(&config.output.stderr))
             .env_clear()
             .kill_on_drop(true)
             .args(config.arguments)
             .envs(config.environment_variables.iter());
-        let mut target_child = target_cmd.spawn().map_err(Error::Io)?;
-        let target_id = target_child.id().expect("target must have PID");
-        pid_snd
-            .send(target_id)
-            .expect("target server unable to transmit PID, catastrophic failure");
-        drop(pid_snd);
-
-        let target_wait = target_child.wait();
-        tokio::select! {
-            res = target_wait => {
-                match res {
-                    Ok(status) => {
-                        error!("child exited with status: {}", status);
-                        Ok(status)
-                    }
-                    Err(err) => {
-                        error!("child exited with error: {}", err);
-                        Err(Error::Io(err))
+
+        let mut first = true;
+        loop {
+            let mut target_child = target_cmd.spawn().map_err(Error::Io)?;
+            let target_id = target_child.id().expect("target must have PID");
+            TARGET_PID.store(target_id, Ordering::Relaxed);
+            cleanup::track(target_id, &config.command);
+            *STDIN.lock().unwrap() = target_child.stdin.take();
+            if first {
+                pid_snd
+                    .send(target_id)
+                    .expect("target server unable to transmit PID, catastrophic failure");
+                first = false;
+            } else {
+                info!("target restarted with PID {}", target_id);
+                counter!("target_restart", 1);
+                captures::annotate("target", format!("restarted with PID {}", target_id));
+                // Components that follow the target across restarts may have
+                // stopped listening, that is not an error.
+                let _ = pid_snd.send(target_id);
+            }
+
+            let target_wait = target_child.wait();
+            tokio::select! {
+                res = target_wait => {
+                    TARGET_PID.store(0, Ordering::Relaxed);
+                    STDIN.lock().unwrap().take();
+                    match res {
+                        Ok(status) if RESTART.swap(false, Ordering::Relaxed) => {
+                            info!("child exited as expected with status: {}, restarting", status);
+                        }
+                        Ok(status) => {
+                            error!("child exited with status: {}", status);
+                            return Ok(status);
+                        }
+                        Err(err) => {
+                            error!("child exited with error: {}", err);
+                            return Err(Error::Io(err));
+                        }
                     }
+                },
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    // Note that `Child::kill` sends SIGKILL which is not what we
+                    // want. We instead send SIGTERM so that the child has a chance
+                    // to clean up.
+                    let pid: Pid = Pid::from_raw(target_id.try_into().unwrap());
+                    kill(pid, SIGTERM).map_err(Error::Errno)?;
+                    let res = target_child.wait().await.map_err(Error::Io)?;
+                    TARGET_PID.store(0, Ordering::Relaxed);
+                    return Ok(res);
                 }
-            },
-            _ = self.shutdown.recv() => {
-                info!("shutdown signal received");
-                // Note that `Child::kill` sends SIGKILL which is not what we
-                // want. We instead send SIGTERM so that the child has a chance
-                // to clean up.
-                let pid: Pid = Pid::from_raw(target_id.try_into().unwrap());
-                kill(pid, SIGTERM).map_err(Error::Errno)?;
-                let res = target_child.wait().await.map_err(Error::Io)?;
-                Ok(res)
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use proptest::prelude::*;
+
+    use super::{render, Behavior, Config, Output};
+
+    // A provided variable is substituted everywhere it is referenced, and does
+    // not override a variable the user set. Provided names are upper case, the
+    // user's variable lower case, so that the two never collide.
+    proptest! {
+        #[test]
+        fn provide_substitutes(name in "[A-Z_]{1,16}", value in "[a-z0-9.:]{0,16}", prefix in "[a-z-]{0,8}") {
+            let reference = format!("${{{}}}", name);
+            let mut environment_variables = HashMap::new();
+            environment_variables.insert("user_set".to_string(), format!("{}{}", prefix, reference));
+            let mut config = Config {
+                command: "/bin/true".into(),
+                arguments: vec![format!("--{}={}", prefix, reference)],
+                environment_variables,
+                output: Output {
+                    stderr: Behavior::Quiet,
+                    stdout: Behavior::Quiet,
+                },
+                config_file: None,
+                stdin: false,
+            };
+            config.provide(&name, &value);
+            prop_assert_eq!(&config.arguments[0], &format!("--{}={}", prefix, value));
+            prop_assert_eq!(&config.environment_variables["user_set"], &format!("{}{}", prefix, value));
+            prop_assert_eq!(&config.environment_variables[&name], &value);
+
+            config.provide("user_set", "overridden");
+            prop_assert_eq!(&config.environment_variables["user_set"], &format!("{}{}", prefix, value));
+        }
+    }
+
+    // A rendered template has each known variable's references substituted,
+    // unknown references left and the text around them untouched.
+    proptest! {
+        #[test]
+        fn render_substitutes(name in "[A-Z_]{1,16}", value in "[a-z0-9.:${}]{0,16}", text in "[a-z {}:]{0,16}") {
+            let mut variables = HashMap::new();
+            variables.insert(name.clone(), value.clone());
+            let template = format!("{}${{{}}}{}${{unknown}}${{{}}}", text, name, text, name);
+            let expected = format!("{}{}{}${{unknown}}{}", text, value, text, value);
+            prop_assert_eq!(render(&template, &variables), expected);
+        }
+    }
+}
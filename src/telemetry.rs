@@ -0,0 +1,266 @@
+//! Guard lading's own telemetry against label cardinality explosions
+//!
+//! Lading labels some of its metrics with values it does not control, error
+//! descriptions in particular. A flapping target can then produce an unbounded
+//! number of unique label values, each a new time series. [`CardinalityLimit`]
+//! wraps a [`metrics::Recorder`] and caps the number of unique values each
+//! label of each metric may take, folding any excess into [`OVERFLOW`].
+//! Emission sites additionally map errors to a bounded set of kinds, see
+//! [`io_error_kind`] and [`hyper_error_kind`].
+
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    sync::RwLock,
+};
+
+use metrics::{Counter, Gauge, Histogram, Key, KeyName, Label, Recorder, Unit};
+use tracing::warn;
+
+/// The label value substituted once a label has reached its maximum number of
+/// unique values.
+pub const OVERFLOW: &str = "overflow";
+
+/// Label keys to the set of values seen for them, per metric name.
+type Seen = HashMap<String, HashMap<String, LabelValues>>;
+
+/// The unique values a label has taken, up to a maximum.
+///
+/// A value already admitted, or refused once the maximum is reached, is
+/// decided under a shared lock. Only a value admitted anew takes the lock
+/// exclusively, so that labels of values from a small set are cheap to check
+/// on every use.
+#[derive(Debug)]
+pub(crate) struct LabelValues {
+    maximum: usize,
+    values: RwLock<HashSet<String>>,
+}
+
+impl LabelValues {
+    /// Create a new [`LabelValues`] admitting `maximum` unique values.
+    pub(crate) fn new(maximum: usize) -> Self {
+        Self {
+            maximum,
+            values: RwLock::new(HashSet::new()),
+        }
+    }
+
+    /// Whether `value` may be used as itself, admitting it if there is room.
+    /// A value refused is to be replaced by a placeholder, as
+    /// [`CardinalityLimit`] replaces it by [`OVERFLOW`].
+    pub(crate) fn admit(&self, value: &str) -> bool {
+        {
+            let values = self.values.read().unwrap();
+            if values.contains(value) {
+                return true;
+            }
+            if values.len() >= self.maximum {
+                return false;
+            }
+        }
+        let mut values = self.values.write().unwrap();
+        if values.contains(value) || values.len() < self.maximum {
+            values.insert(value.to_string());
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[allow(missing_debug_implementations)]
+/// Wraps a [`metrics::Recorder`], capping the number of unique values of each
+/// label of each metric.
+///
+/// Metrics and labels already seen are checked under shared locks, so that
+/// emission sites do not contend with one another. A label is warned about the
+/// first time it overflows only.
+pub struct CardinalityLimit<R> {
+    inner: R,
+    maximum_label_values: usize,
+    seen: RwLock<Seen>,
+    /// The metric name and label key of each label warned about.
+    warned: RwLock<HashSet<(String, String)>>,
+}
+
+impl<R> CardinalityLimit<R> {
+    /// Create a new [`CardinalityLimit`] allowing each label of each metric
+    /// `maximum_label_values` unique values.
+    #[must_use]
+    pub fn new(inner: R, maximum_label_values: usize) -> Self {
+        Self {
+            inner,
+            maximum_label_values,
+            seen: RwLock::new(HashMap::new()),
+            warned: RwLock::new(HashSet::new()),
+        }
+    }
+
+    /// Whether `key`'s metric and each of its labels has been seen before.
+    fn known(&self, key: &Key) -> bool {
+        let seen = self.seen.read().unwrap();
+        seen.get(key.name()).map_or(false, |metric| {
+            key.labels().all(|label| metric.contains_key(label.key()))
+        })
+    }
+
+    /// Warn that label `label` of metric `name` overflowed, unless it has been
+    /// warned about already.
+    fn warn_once(&self, name: &str, label: &str) {
+        let warned = (name.to_string(), label.to_string());
+        if self.warned.read().unwrap().contains(&warned) {
+            return;
+        }
+        if self.warned.write().unwrap().insert(warned) {
+            warn!(
+                "label {} of metric {} exceeded {} unique values, folding into {}",
+                label, name, self.maximum_label_values, OVERFLOW
+            );
+        }
+    }
+
+    /// Return `key` with any label past its maximum number of unique values
+    /// replaced by [`OVERFLOW`], or `None` if `key` is within limits.
+    fn limit(&self, key: &Key) -> Option<Key> {
+        if !self.known(key) {
+            let mut seen = self.seen.write().unwrap();
+            let metric = seen.entry(key.name().to_string()).or_default();
+            for label in key.labels() {
+                metric
+                    .entry(label.key().to_string())
+                    .or_insert_with(|| LabelValues::new(self.maximum_label_values));
+            }
+        }
+
+        let seen = self.seen.read().unwrap();
+        let metric = &seen[key.name()];
+        let mut overflowed = false;
+        let labels: Vec<Label> = key
+            .labels()
+            .map(|label| {
+                if metric[label.key()].admit(label.value()) {
+                    return label.clone();
+                }
+                self.warn_once(key.name(), label.key());
+                overflowed = true;
+                Label::new(label.key().to_string(), OVERFLOW)
+            })
+            .collect();
+
+        overflowed.then(|| Key::from_parts(key.name().to_string(), labels))
+    }
+}
+
+impl<R> Recorder for CardinalityLimit<R>
+where
+    R: Recorder,
+{
+    fn describe_counter(&self, key: KeyName, unit: Option<Unit>, description: &'static str) {
+        self.inner.describe_counter(key, unit, description);
+    }
+
+    fn describe_gauge(&self, key: KeyName, unit: Option<Unit>, description: &'static str) {
+        self.inner.describe_gauge(key, unit, description);
+    }
+
+    fn describe_histogram(&self, key: KeyName, unit: Option<Unit>, description: &'static str) {
+        self.inner.describe_histogram(key, unit, description);
+    }
+
+    fn register_counter(&self, key: &Key) -> Counter {
+        match self.limit(key) {
+            Some(key) => self.inner.register_counter(&key),
+            None => self.inner.register_counter(key),
+        }
+    }
+
+    fn register_gauge(&self, key: &Key) -> Gauge {
+        match self.limit(key) {
+            Some(key) => self.inner.register_gauge(&key),
+            None => self.inner.register_gauge(key),
+        }
+    }
+
+    fn register_histogram(&self, key: &Key) -> Histogram {
+        match self.limit(key) {
+            Some(key) => self.inner.register_histogram(&key),
+            None => self.inner.register_histogram(key),
+        }
+    }
+}
+
+/// Map `err` to a bounded set of kinds, suitable as a label value.
+pub(crate) fn io_error_kind(err: &io::Error) -> String {
+    format!("{:?}", err.kind())
+}
+
+/// Map `err` to a bounded set of kinds, suitable as a label value.
+pub(crate) fn hyper_error_kind(err: &hyper::Error) -> &'static str {
+    if err.is_connect() {
+        "connect"
+    } else if err.is_closed() {
+        "closed"
+    } else if err.is_canceled() {
+        "canceled"
+    } else if err.is_incomplete_message() {
+        "incomplete_message"
+    } else if err.is_body_write_aborted() {
+        "body_write_aborted"
+    } else if err.is_parse() {
+        "parse"
+    } else if err.is_user() {
+        "user"
+    } else {
+        "other"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use metrics::{Key, Label};
+    use proptest::{collection, prelude::*};
+
+    use super::{CardinalityLimit, OVERFLOW};
+
+    // No matter the values offered a label never takes more than its maximum
+    // number of unique values, plus the overflow value.
+    proptest! {
+        #[test]
+        fn label_values_bounded(maximum_label_values in 0..32_usize, values in collection::vec(any::<u16>(), 0..256)) {
+            let limit = CardinalityLimit::new((), maximum_label_values);
+            let mut recorded = HashSet::new();
+            for value in values {
+                let key = Key::from_parts("metric", vec![Label::new("error", value.to_string())]);
+                let key = limit.limit(&key).unwrap_or(key);
+                for label in key.labels() {
+                    recorded.insert(label.value().to_string());
+                }
+            }
+            recorded.remove(OVERFLOW);
+            prop_assert!(recorded.len() <= maximum_label_values);
+        }
+    }
+
+    // However often a label overflows it is warned about once, and only a
+    // label that overflowed is warned about.
+    proptest! {
+        #[test]
+        fn overflow_warned_once(maximum_label_values in 0..8_usize, values in collection::vec(any::<u8>(), 0..64)) {
+            let limit = CardinalityLimit::new((), maximum_label_values);
+            let mut overflowed = false;
+            for value in values {
+                let key = Key::from_parts("metric", vec![Label::new("error", value.to_string())]);
+                overflowed |= limit.limit(&key).is_some();
+            }
+            let warned = limit.warned.read().unwrap();
+            if overflowed {
+                prop_assert_eq!(warned.len(), 1);
+                prop_assert!(warned.contains(&("metric".to_string(), "error".to_string())));
+            } else {
+                prop_assert!(warned.is_empty());
+            }
+        }
+    }
+}
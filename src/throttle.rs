@@ -0,0 +1,753 @@
+//! Throttle the output of generators
+//!
+//! Generators push load into the target at a user configured rate. How that
+//! rate is achieved -- the shape of the traffic, not just its average -- is
+//! determined by the algorithm selected by [`Config`]. The token bucket allows
+//! short bursts above the configured rate, whereas strict pacing releases
+//! capacity evenly, at the cost of never catching up after a stall. A sliced
+//! token bucket sits between the two, its bursts limited to a slice of a
+//! second's capacity.
+//!
+//! The token bucket is timed by quanta on `x86_64`, where it reads the TSC
+//! cheaply. Elsewhere, aarch64 included, the standard library's monotonic
+//! clock is used instead so that timing behavior is that of the platform's
+//! well-trodden clock.
+//!
+//! A throttle may additionally be configured with [`SlowStart`]. Each new
+//! connection then ramps its share of the rate linearly from a fraction of it
+//! to all of it over a window, as a well-behaved client would, rather than
+//! offering its full share the instant it connects.
+//!
+//! How closely a throttle holds its rate depends on the host, on the
+//! resolution of its clock and the granularity of its scheduler. [`calibrate`]
+//! measures this, so that the accuracy of a run's load may be judged.
+
+use std::num::NonZeroU32;
+
+use governor::{
+    clock, state,
+    state::direct::{self, InsufficientCapacity},
+    Quota, RateLimiter,
+};
+use metrics::histogram;
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep_until, Duration, Instant};
+
+use crate::control::Pause;
+
+/// The clock timing token bucket throttles.
+#[cfg(target_arch = "x86_64")]
+pub(crate) type Clock = clock::QuantaClock;
+/// The clock timing token bucket throttles.
+#[cfg(not(target_arch = "x86_64"))]
+pub(crate) type Clock = clock::MonotonicClock;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// Configuration for [`Throttle`]
+pub enum Config {
+    /// A token bucket. Capacity refills continuously and up to one second of
+    /// capacity may accumulate, allowing bursts above the configured rate.
+    TokenBucket,
+    /// Strict pacing, a leaky bucket. Each request for capacity is delayed so
+    /// that capacity is released at exactly the configured rate. Unused
+    /// capacity does not accumulate, so no bursts are possible.
+    Paced,
+    /// A token bucket holding only `slice_milliseconds` of capacity, so that
+    /// a high rate is not released as one large burst per second. A request
+    /// for more capacity than the bucket holds is released a slice at a time.
+    SlicedTokenBucket {
+        /// The capacity of the bucket, in milliseconds of the configured rate
+        slice_milliseconds: NonZeroU32,
+    },
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::TokenBucket
+    }
+}
+
+fn default_initial_percent() -> u8 {
+    10
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+/// Configuration of a throttle's slow start
+pub struct SlowStart {
+    /// The time, in seconds, over which a new connection ramps to its full
+    /// share of the configured rate
+    pub window_seconds: NonZeroU32,
+    /// The share of its rate a new connection starts at, in percent. Defaults
+    /// to 10, values are clamped to between 1 and 100.
+    #[serde(default = "default_initial_percent")]
+    pub initial_percent: u8,
+}
+
+/// Return the fraction of its rate a connection ramping from `initial` over
+/// `window` is allowed `elapsed` after it was established.
+fn ramp_fraction(initial: f64, elapsed: Duration, window: Duration) -> f64 {
+    if elapsed >= window {
+        1.0
+    } else {
+        (initial + (1.0 - initial) * (elapsed.as_secs_f64() / window.as_secs_f64())).min(1.0)
+    }
+}
+
+/// Return the capacity of a bucket holding `slice_milliseconds` of
+/// `units_per_second`, no less than one unit.
+#[allow(clippy::cast_possible_truncation)]
+fn slice_capacity(units_per_second: NonZeroU32, slice_milliseconds: NonZeroU32) -> NonZeroU32 {
+    let units = u64::from(units_per_second.get()) * u64::from(slice_milliseconds.get()) / 1000;
+    NonZeroU32::new(units.min(u64::from(u32::MAX)) as u32)
+        .unwrap_or_else(|| NonZeroU32::new(1).unwrap())
+}
+
+#[derive(Debug)]
+/// Throttles generator output to a fixed number of units per second.
+///
+/// A throttle whose generator is paused, see [`crate::control`], releases no
+/// capacity until the generator is resumed.
+pub(crate) struct Throttle {
+    algorithm: Algorithm,
+    pause: Pause,
+    ramp: Option<Ramp>,
+    /// Units the algorithm has released towards a request not yet released
+    /// whole, through slow start say. Kept across waits so that a wait
+    /// cancelled part way loses none of them, the next wait drawing on them
+    /// first.
+    banked: u32,
+    /// The phase jitter delaying the first release, see
+    /// [`Pause::with_phase_jitter`], until it has passed.
+    offset: Option<Duration>,
+    /// The instant the first release is delayed until, once drawn.
+    offset_until: Option<Instant>,
+}
+
+#[derive(Debug)]
+/// See [`SlowStart`].
+struct Ramp {
+    /// The fraction of its rate a new connection starts at.
+    initial: f64,
+    /// The time over which a new connection ramps to its full rate.
+    window: Duration,
+    /// Units released per second once no connection is ramping.
+    units_per_second: f64,
+    /// When each connection was established, while it ramps.
+    connections: Vec<Option<Instant>>,
+    /// The instant at which the next request may proceed.
+    next: Instant,
+}
+
+impl Ramp {
+    /// Return the fraction of the throttle's rate allowed at `now`, the mean of
+    /// each connection's, or `None` if no connection is ramping.
+    #[allow(clippy::cast_precision_loss)]
+    fn fraction(&mut self, now: Instant) -> Option<f64> {
+        let mut ramping = false;
+        let mut sum = 0.0;
+        for connection in &mut self.connections {
+            match connection {
+                Some(established) if now.duration_since(*established) < self.window => {
+                    ramping = true;
+                    sum +=
+                        ramp_fraction(self.initial, now.duration_since(*established), self.window);
+                }
+                _ => {
+                    *connection = None;
+                    sum += 1.0;
+                }
+            }
+        }
+        ramping.then(|| sum / self.connections.len() as f64)
+    }
+}
+
+#[derive(Debug)]
+enum Algorithm {
+    /// See [`Config::TokenBucket`] and [`Config::SlicedTokenBucket`].
+    TokenBucket {
+        limiter: RateLimiter<direct::NotKeyed, state::InMemoryState, Clock>,
+        /// The capacity of a sliced bucket. Requests for more are released a
+        /// slice at a time, whereas an unsliced bucket refuses them.
+        slice: Option<NonZeroU32>,
+    },
+    /// See [`Config::Paced`].
+    Paced {
+        /// Units released per second.
+        units_per_second: f64,
+        /// The instant at which the next request may proceed.
+        next: Instant,
+    },
+}
+
+impl Throttle {
+    /// Create a new [`Throttle`] releasing `units_per_second`, held while
+    /// `pause` is paused.
+    pub(crate) fn new(config: Config, units_per_second: NonZeroU32, pause: Pause) -> Self {
+        let algorithm = match config {
+            Config::TokenBucket => Algorithm::TokenBucket {
+                limiter: RateLimiter::direct_with_clock(
+                    Quota::per_second(units_per_second),
+                    &Clock::default(),
+                ),
+                slice: None,
+            },
+            Config::SlicedTokenBucket { slice_milliseconds } => {
+                let slice = slice_capacity(units_per_second, slice_milliseconds);
+                Algorithm::TokenBucket {
+                    limiter: RateLimiter::direct_with_clock(
+                        Quota::per_second(units_per_second).allow_burst(slice),
+                        &Clock::default(),
+                    ),
+                    slice: Some(slice),
+                }
+            }
+            Config::Paced => Algorithm::Paced {
+                units_per_second: f64::from(units_per_second.get()),
+                next: Instant::now(),
+            },
+        };
+        let offset = pause.phase_jitter();
+        Self {
+            algorithm,
+            pause,
+            ramp: None,
+            banked: 0,
+            offset,
+            offset_until: None,
+        }
+    }
+
+    /// Ramp each of `connections` new connections up to its share of this
+    /// throttle's rate per `slow_start`, if set. A connection's ramp starts
+    /// when it is reported by [`Throttle::connected`].
+    ///
+    /// A token bucket keeps accumulating capacity at the full rate while
+    /// connections ramp, so a burst of up to one second of capacity may follow
+    /// the ramp. Pair slow start with a paced throttle to avoid it.
+    pub(crate) fn with_slow_start(
+        mut self,
+        slow_start: Option<SlowStart>,
+        units_per_second: NonZeroU32,
+        connections: usize,
+    ) -> Self {
+        self.ramp = slow_start.map(|slow_start| Ramp {
+            initial: f64::from(slow_start.initial_percent.clamp(1, 100)) / 100.0,
+            window: Duration::from_secs(u64::from(slow_start.window_seconds.get())),
+            units_per_second: f64::from(units_per_second.get()),
+            connections: vec![None; connections.max(1)],
+            next: Instant::now(),
+        });
+        self
+    }
+
+    /// Report that connection `connection` was (re-)established, starting its
+    /// slow start ramp if one is configured.
+    pub(crate) fn connected(&mut self, connection: usize) {
+        if let Some(ramp) = &mut self.ramp {
+            if let Some(established) = ramp.connections.get_mut(connection) {
+                *established = Some(Instant::now());
+            }
+        }
+    }
+
+    /// Wait until the throttle has capacity for `n` units.
+    ///
+    /// The time spent waiting is recorded in the `throttle_wait_seconds`
+    /// histogram. Long waits indicate a generator is limited by its configured
+    /// quota, short waits that it is limited by the target. Time spent paused
+    /// is not recorded, nor is the phase jitter delaying the first release.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if `n` exceeds the capacity of an
+    /// unsliced token bucket throttle. Other throttles never error.
+    ///
+    /// # Cancel safety
+    ///
+    /// A wait that is cancelled, by losing a `tokio::select!` say, releases
+    /// nothing and loses no capacity.
+    #[allow(clippy::ptr_arg)]
+    pub(crate) async fn wait(
+        &mut self,
+        n: NonZeroU32,
+        labels: &Vec<(String, String)>,
+    ) -> Result<(), InsufficientCapacity> {
+        self.pause.until_running().await;
+        if let Some(offset) = self.offset {
+            // The offset is timed from the first wait, not from when the
+            // throttle was built, and survives the wait being cancelled.
+            let until = *self
+                .offset_until
+                .get_or_insert_with(|| Instant::now() + offset);
+            sleep_until(until).await;
+            self.offset = None;
+        }
+        let start = Instant::now();
+        let mut res = Ok(());
+        // Units released are banked as soon as they are, the bank only drawn
+        // down once the request is released whole.
+        if let Some(needed) = NonZeroU32::new(n.get().saturating_sub(self.banked)) {
+            res = match &mut self.algorithm {
+                Algorithm::TokenBucket {
+                    limiter,
+                    slice: None,
+                } => {
+                    let res = limiter.until_n_ready(needed).await;
+                    if res.is_ok() {
+                        self.banked += needed.get();
+                    }
+                    res
+                }
+                Algorithm::TokenBucket {
+                    limiter,
+                    slice: Some(slice),
+                } => {
+                    let mut remaining = needed.get();
+                    while remaining > 0 {
+                        let chunk = remaining.min(slice.get());
+                        // A slice never exceeds the bucket's capacity.
+                        limiter
+                            .until_n_ready(NonZeroU32::new(chunk).unwrap())
+                            .await
+                            .expect("slice exceeds the bucket's capacity");
+                        remaining -= chunk;
+                        self.banked += chunk;
+                    }
+                    Ok(())
+                }
+                Algorithm::Paced {
+                    units_per_second,
+                    next,
+                } => {
+                    // If we are behind schedule the request proceeds
+                    // immediately but credit for the idle time is not
+                    // accumulated. The schedule only advances once the
+                    // request is released, a cancelled wait leaving it be.
+                    let release = std::cmp::max(*next, start);
+                    sleep_until(release).await;
+                    *next = release
+                        + Duration::from_secs_f64(f64::from(needed.get()) / *units_per_second);
+                    self.banked += needed.get();
+                    Ok(())
+                }
+            };
+        }
+        if let (Some(ramp), Ok(())) = (&mut self.ramp, &res) {
+            // While connections ramp the request is paced at their allowed
+            // rate as well, below the rate the algorithm releases at. As the
+            // algorithm's schedule, the ramp's only advances once the request
+            // is released.
+            let now = Instant::now();
+            if let Some(fraction) = ramp.fraction(now) {
+                let release = std::cmp::max(ramp.next, now);
+                sleep_until(release).await;
+                ramp.next = release
+                    + Duration::from_secs_f64(
+                        f64::from(n.get()) / (ramp.units_per_second * fraction),
+                    );
+            }
+        }
+        histogram!(
+            "throttle_wait_seconds",
+            start.elapsed().as_secs_f64(),
+            labels
+        );
+        if res.is_ok() {
+            self.banked -= n.get();
+        }
+        res
+    }
+}
+
+/// The rate, in units per second, [`calibrate`] throttles at.
+const CALIBRATION_RATE: u32 = 10_000;
+/// The sleep [`calibrate`] measures the overshoot of.
+const CALIBRATION_SLEEP: Duration = Duration::from_millis(1);
+/// The number of sleeps [`calibrate`] takes the mean overshoot of.
+const CALIBRATION_SLEEP_SAMPLES: u32 = 100;
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+/// The accuracy of throttles on this host, see [`calibrate`].
+pub struct Calibration {
+    /// The rate throttled at, in units per second
+    pub rate_per_second: u32,
+    /// The accuracy of each throttle calibrated
+    pub throttles: Vec<ThrottleCalibration>,
+    /// The mean time a 1 millisecond sleep overran by, in seconds, an estimate
+    /// of the scheduler's granularity
+    pub sleep_overshoot_seconds: f64,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+/// The accuracy of one throttle, see [`Calibration`].
+pub struct ThrottleCalibration {
+    /// The throttle's algorithm and slow start, see [`name`]
+    pub throttle: String,
+    /// The error of the rate the throttle achieved, in percent of the rate it
+    /// should have. Negative if the achieved rate fell short.
+    pub error_percent: f64,
+}
+
+/// Return the name of a throttle of `config` with `slow_start`: `token_bucket`,
+/// `paced` or `sliced_token_bucket_<slice>ms`, suffixed with
+/// `_slow_start_<window>s_<initial>pct` if it ramps.
+#[must_use]
+pub fn name(config: Config, slow_start: Option<SlowStart>) -> String {
+    let mut name = match config {
+        Config::TokenBucket => "token_bucket".to_string(),
+        Config::Paced => "paced".to_string(),
+        Config::SlicedTokenBucket { slice_milliseconds } => {
+            format!("sliced_token_bucket_{}ms", slice_milliseconds)
+        }
+    };
+    if let Some(slow_start) = slow_start {
+        name.push_str(&format!(
+            "_slow_start_{}s_{}pct",
+            slow_start.window_seconds,
+            slow_start.initial_percent.clamp(1, 100)
+        ));
+    }
+    name
+}
+
+/// Return the error of `achieved` against `configured`, in percent of the
+/// latter.
+fn error_percent(achieved: f64, configured: f64) -> f64 {
+    (achieved - configured) / configured * 100.0
+}
+
+/// Return the mean fraction of its rate a connection ramping from `initial`
+/// over `window` is allowed across the first `elapsed` after it was
+/// established.
+fn mean_ramp_fraction(initial: f64, elapsed: Duration, window: Duration) -> f64 {
+    let elapsed = elapsed.as_secs_f64();
+    let window = window.as_secs_f64();
+    if elapsed <= 0.0 {
+        return initial;
+    }
+    let ramping = elapsed.min(window);
+    let ramped = (initial + (1.0 - initial) * ramping / (2.0 * window)) * ramping;
+    (ramped + (elapsed - ramping)) / elapsed
+}
+
+/// Measure the accuracy of `throttles` on this host, each an algorithm and
+/// the slow start it is configured with. Duplicates are calibrated once and
+/// the default token bucket is calibrated if there are none.
+///
+/// Each throttle releases single units at a fixed rate for `duration`, the
+/// rate it achieved compared against the rate it should have, that of its
+/// ramp if it has a slow start. A token bucket's initial burst is drained
+/// beforehand and not counted. The throttles are not labeled, their waits
+/// recorded in `throttle_wait_seconds` if a recorder is installed, so
+/// calibrate before installing one.
+///
+/// # Panics
+///
+/// None known.
+pub async fn calibrate(
+    throttles: &[(Config, Option<SlowStart>)],
+    duration: Duration,
+) -> Calibration {
+    let rate = NonZeroU32::new(CALIBRATION_RATE).unwrap();
+    let mut distinct: Vec<(Config, Option<SlowStart>)> = Vec::new();
+    for throttle in throttles {
+        if !distinct.contains(throttle) {
+            distinct.push(*throttle);
+        }
+    }
+    if distinct.is_empty() {
+        distinct.push((Config::default(), None));
+    }
+
+    let mut calibrations = Vec::with_capacity(distinct.len());
+    for (config, slow_start) in distinct {
+        let (achieved, expected) = achieved_rate(config, slow_start, rate, duration).await;
+        calibrations.push(ThrottleCalibration {
+            throttle: name(config, slow_start),
+            error_percent: error_percent(achieved, expected),
+        });
+    }
+
+    let mut overshoot = Duration::ZERO;
+    for _ in 0..CALIBRATION_SLEEP_SAMPLES {
+        let start = Instant::now();
+        tokio::time::sleep(CALIBRATION_SLEEP).await;
+        overshoot += start.elapsed().saturating_sub(CALIBRATION_SLEEP);
+    }
+
+    Calibration {
+        rate_per_second: rate.get(),
+        throttles: calibrations,
+        sleep_overshoot_seconds: (overshoot / CALIBRATION_SLEEP_SAMPLES).as_secs_f64(),
+    }
+}
+
+/// Return the rate, in units per second, a throttle of `config` and
+/// `slow_start` at `rate` achieves over `duration`, and the rate it should
+/// achieve.
+#[allow(clippy::cast_precision_loss)]
+async fn achieved_rate(
+    config: Config,
+    slow_start: Option<SlowStart>,
+    rate: NonZeroU32,
+    duration: Duration,
+) -> (f64, f64) {
+    let labels = Vec::new();
+    let mut throttle = Throttle::new(config, rate, Pause::default());
+    // A bucket starts full. It is drained before the ramp starts, so as not
+    // to be paced by it.
+    let burst = match config {
+        Config::TokenBucket => Some(rate),
+        Config::SlicedTokenBucket { slice_milliseconds } => {
+            Some(slice_capacity(rate, slice_milliseconds))
+        }
+        Config::Paced => None,
+    };
+    if let Some(burst) = burst {
+        throttle
+            .wait(burst, &labels)
+            .await
+            .expect("burst is the bucket's capacity");
+    }
+    let mut throttle = throttle.with_slow_start(slow_start, rate, 1);
+    throttle.connected(0);
+    let one = NonZeroU32::new(1).unwrap();
+    let mut units: u64 = 0;
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        throttle
+            .wait(one, &labels)
+            .await
+            .expect("one unit is within the bucket's capacity");
+        units += 1;
+    }
+    let elapsed = start.elapsed();
+    let fraction = slow_start.map_or(1.0, |slow_start| {
+        mean_ramp_fraction(
+            f64::from(slow_start.initial_percent.clamp(1, 100)) / 100.0,
+            elapsed,
+            Duration::from_secs(u64::from(slow_start.window_seconds.get())),
+        )
+    });
+    (
+        units as f64 / elapsed.as_secs_f64(),
+        f64::from(rate.get()) * fraction,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use tokio::time::{self, Duration};
+
+    use std::num::NonZeroU32;
+
+    use super::{
+        error_percent, mean_ramp_fraction, ramp_fraction, slice_capacity, Algorithm, Config,
+        SlowStart, Throttle,
+    };
+    use crate::control::Pause;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    // A ramping connection's fraction of its rate never decreases and stays
+    // between its initial fraction and the full rate, reaching the latter at
+    // the end of the window.
+    proptest! {
+        #[test]
+        fn ramp_fraction_bounded_and_nondecreasing(
+            initial_percent in 1_u8..=100,
+            window_millis in 1_u64..100_000,
+            a in 0_u64..200_000,
+            b in 0_u64..200_000,
+        ) {
+            let initial = f64::from(initial_percent) / 100.0;
+            let window = Duration::from_millis(window_millis);
+            let (earlier, later) = (a.min(b), a.max(b));
+            let earlier = ramp_fraction(initial, Duration::from_millis(earlier), window);
+            let later = ramp_fraction(initial, Duration::from_millis(later), window);
+            prop_assert!(initial <= earlier && earlier <= later && later <= 1.0);
+            prop_assert!((ramp_fraction(initial, window, window) - 1.0).abs() < f64::EPSILON);
+        }
+    }
+
+    // The error of an achieved rate is zero only when it is the configured
+    // rate, and its sign is that of the difference.
+    proptest! {
+        #[test]
+        fn error_percent_signed(achieved in 0_u32..1_000_000, configured in 1_u32..1_000_000) {
+            let error = error_percent(f64::from(achieved), f64::from(configured));
+            prop_assert_eq!(error.abs() < f64::EPSILON, achieved == configured);
+            prop_assert_eq!(error < 0.0, achieved < configured);
+            prop_assert!(error >= -100.0);
+        }
+    }
+
+    // The mean fraction of a ramp is the mean of its fraction sampled evenly
+    // across it, and reaches the full rate only in the limit.
+    proptest! {
+        #[test]
+        fn mean_ramp_fraction_is_mean_of_ramp(
+            initial_percent in 1_u8..=100,
+            window_millis in 1_u64..100_000,
+            elapsed_millis in 1_u64..200_000,
+        ) {
+            let initial = f64::from(initial_percent) / 100.0;
+            let window = Duration::from_millis(window_millis);
+            let samples = 10_000_u32;
+            let step = Duration::from_millis(elapsed_millis) / samples;
+            let sampled = (0..samples)
+                .map(|i| ramp_fraction(initial, step * i + step / 2, window))
+                .sum::<f64>()
+                / f64::from(samples);
+            let mean = mean_ramp_fraction(initial, Duration::from_millis(elapsed_millis), window);
+            prop_assert!((mean - sampled).abs() < 1e-3);
+            prop_assert!(initial - 1e-9 <= mean && mean <= 1.0 + 1e-9);
+        }
+    }
+
+    // A slice holds its share of a second's capacity, never nothing, and a
+    // slice of a second or more holds at least a second's.
+    proptest! {
+        #[test]
+        fn slice_capacity_proportional(units in 1_u32..=u32::MAX, millis in 1_u32..10_000) {
+            let slice = slice_capacity(
+                NonZeroU32::new(units).unwrap(),
+                NonZeroU32::new(millis).unwrap(),
+            )
+            .get();
+            let expected = (u64::from(units) * u64::from(millis) / 1000).clamp(1, u64::from(u32::MAX));
+            prop_assert_eq!(u64::from(slice), expected);
+            if millis >= 1000 {
+                prop_assert!(slice >= units);
+            }
+        }
+    }
+
+    // A paced throttle releases requests no faster than its rate, however
+    // many there are.
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(8))]
+        #[test]
+        fn paced_holds_rate(requests in 2_u32..20, units in 1_u32..5) {
+            // 1,000 units per second, a unit per millisecond.
+            let rate = NonZeroU32::new(1_000).unwrap();
+            let n = NonZeroU32::new(units).unwrap();
+            let labels = Vec::new();
+            block_on(async {
+                let mut throttle = Throttle::new(Config::Paced, rate, Pause::default());
+                let start = time::Instant::now();
+                for _ in 0..requests {
+                    throttle.wait(n, &labels).await.unwrap();
+                }
+                // The first request is released immediately.
+                let least = Duration::from_millis(u64::from((requests - 1) * units));
+                prop_assert!(start.elapsed() >= least);
+                Ok(())
+            })?;
+        }
+    }
+
+    // A paced wait cancelled before its release leaves the schedule be, the
+    // next request released when the cancelled one would have been.
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(8))]
+        #[test]
+        fn paced_wait_cancel_safe(cancellations in 1_usize..4) {
+            // One unit per second, so that the second request waits a second.
+            let rate = NonZeroU32::new(1).unwrap();
+            let one = NonZeroU32::new(1).unwrap();
+            let labels = Vec::new();
+            let next = |throttle: &Throttle| match throttle.algorithm {
+                Algorithm::Paced { next, .. } => next,
+                Algorithm::TokenBucket { .. } => unreachable!(),
+            };
+            block_on(async {
+                let mut throttle = Throttle::new(Config::Paced, rate, Pause::default());
+                throttle.wait(one, &labels).await.unwrap();
+                let scheduled = next(&throttle);
+                for _ in 0..cancellations {
+                    let cancelled =
+                        time::timeout(Duration::from_millis(5), throttle.wait(one, &labels)).await;
+                    prop_assert!(cancelled.is_err());
+                    prop_assert_eq!(next(&throttle), scheduled);
+                }
+                Ok(())
+            })?;
+        }
+    }
+
+    // A wait cancelled while slow start holds it leaves the ramp's schedule
+    // be, and keeps what the algorithm released to it for the next wait.
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(8))]
+        #[test]
+        fn ramp_wait_cancel_safe(cancellations in 1_usize..4) {
+            // A connection ramping from 1% of 1,000 units per second over 10
+            // seconds is allowed a unit per 100ms at first.
+            let rate = NonZeroU32::new(1_000).unwrap();
+            let one = NonZeroU32::new(1).unwrap();
+            let slow_start = SlowStart {
+                window_seconds: NonZeroU32::new(10).unwrap(),
+                initial_percent: 1,
+            };
+            let labels = Vec::new();
+            block_on(async {
+                let mut throttle = Throttle::new(Config::TokenBucket, rate, Pause::default())
+                    .with_slow_start(Some(slow_start), rate, 1);
+                throttle.connected(0);
+                throttle.wait(one, &labels).await.unwrap();
+                let scheduled = throttle.ramp.as_ref().unwrap().next;
+                for _ in 0..cancellations {
+                    let cancelled =
+                        time::timeout(Duration::from_millis(5), throttle.wait(one, &labels)).await;
+                    prop_assert!(cancelled.is_err());
+                    prop_assert_eq!(throttle.ramp.as_ref().unwrap().next, scheduled);
+                    prop_assert_eq!(throttle.banked, 1);
+                }
+                Ok(())
+            })?;
+        }
+    }
+
+    // A sliced wait cancelled part way keeps the slices released to it, and
+    // the next wait draws on them rather than the bucket.
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(8))]
+        #[test]
+        fn sliced_wait_cancel_safe(slices in 4_u32..8, cancel_after_millis in 15_u64..25) {
+            // 1,000 units per second in slices of 10 units, a slice per 10ms.
+            let rate = NonZeroU32::new(1_000).unwrap();
+            let slice_milliseconds = NonZeroU32::new(10).unwrap();
+            let n = NonZeroU32::new(slices * 10).unwrap();
+            let labels = Vec::new();
+            let banked = |throttle: &Throttle| throttle.banked;
+            block_on(async {
+                let mut throttle = Throttle::new(
+                    Config::SlicedTokenBucket { slice_milliseconds },
+                    rate,
+                    Pause::default(),
+                );
+                let cancelled = time::timeout(
+                    Duration::from_millis(cancel_after_millis),
+                    throttle.wait(n, &labels),
+                )
+                .await;
+                prop_assert!(cancelled.is_err());
+                let kept = banked(&throttle);
+                prop_assert!(kept > 0 && kept < n.get());
+                throttle.wait(n, &labels).await.unwrap();
+                prop_assert_eq!(banked(&throttle), 0);
+                Ok(())
+            })?;
+        }
+    }
+}
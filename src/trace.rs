@@ -0,0 +1,128 @@
+//! Export traces of lading's own run
+//!
+//! Correlating what lading was doing -- building block caches, warming up,
+//! shutting down -- with what the target was doing is easiest when both are
+//! traced to the same backend. A run may export OTLP traces of its lifecycle to
+//! a collector: a root `run` span and, beneath it, a span for each stage of the
+//! run -- `setup`, `warmup`, `experiment` and `shutdown` -- and for each
+//! generator's block cache build. Spans are exported in batches, flushed when
+//! the run [finishes](Lifecycle::finish).
+//!
+//! Only lading's lifecycle is traced. Requests generators make are not, they
+//! are far too many.
+
+use opentelemetry::{
+    global,
+    sdk::{trace as sdktrace, Resource},
+    trace::{Span, TraceContextExt, TraceError, Tracer},
+    Context, KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+
+#[derive(Debug)]
+/// Errors produced by [`Lifecycle`]
+pub enum Error {
+    /// Wrapper for [`opentelemetry::trace::TraceError`]
+    Trace(TraceError),
+}
+
+#[derive(Debug, Clone)]
+/// Configuration for [`Lifecycle`]
+pub struct Config {
+    /// The OTLP gRPC endpoint of the collector, `http://localhost:4317` say
+    pub endpoint: String,
+    /// The experiment's id, if set, attached to the `run` span
+    pub experiment_id: Option<String>,
+}
+
+#[derive(Debug)]
+struct Run {
+    tracer: sdktrace::Tracer,
+    root: Context,
+    stage: Option<Context>,
+}
+
+#[derive(Debug, Default)]
+/// The trace of a run's lifecycle
+///
+/// The default [`Lifecycle`] traces nothing, for runs not configured to export
+/// traces.
+pub struct Lifecycle {
+    run: Option<Run>,
+}
+
+impl Lifecycle {
+    /// Create a new [`Lifecycle`], starting its `run` span. Must be called
+    /// from within a tokio runtime, which exports spans.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the exporter cannot be created.
+    pub fn new(config: Config) -> Result<Self, Error> {
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(config.endpoint),
+            )
+            .with_trace_config(
+                sdktrace::config()
+                    .with_resource(Resource::new(vec![KeyValue::new("service.name", "lading")])),
+            )
+            .install_batch(opentelemetry::runtime::Tokio)
+            .map_err(Error::Trace)?;
+        let mut root = tracer.start("run");
+        if let Some(id) = config.experiment_id {
+            root.set_attribute(KeyValue::new("experiment_id", id));
+        }
+        Ok(Self {
+            run: Some(Run {
+                tracer,
+                root: Context::new().with_span(root),
+                stage: None,
+            }),
+        })
+    }
+
+    /// Enter the run's stage `name`, ending the previous stage's span.
+    pub fn stage(&mut self, name: &'static str) {
+        if let Some(ref mut run) = self.run {
+            if let Some(stage) = run.stage.take() {
+                stage.span().end();
+            }
+            let span = run.tracer.start_with_context(name, &run.root);
+            run.stage = Some(run.root.with_span(span));
+        }
+    }
+
+    /// Call `f` in a span `name` of the current stage, attributed to
+    /// `component`.
+    pub fn in_span<F, T>(&self, name: &'static str, component: &str, f: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        let mut span = self.run.as_ref().map(|run| {
+            let parent = run.stage.as_ref().unwrap_or(&run.root);
+            let mut span = run.tracer.start_with_context(name, parent);
+            span.set_attribute(KeyValue::new("component", component.to_string()));
+            span
+        });
+        let output = f();
+        if let Some(ref mut span) = span {
+            span.end();
+        }
+        output
+    }
+
+    /// End the run's spans, flushing any not yet exported.
+    pub fn finish(mut self) {
+        if let Some(mut run) = self.run.take() {
+            if let Some(stage) = run.stage.take() {
+                stage.span().end();
+            }
+            run.root.span().end();
+            global::shutdown_tracer_provider();
+        }
+    }
+}
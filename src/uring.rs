@@ -0,0 +1,68 @@
+//! The io_uring I/O backend
+//!
+//! At high rates the syscall overhead of readiness based I/O, one `epoll_wait`
+//! plus one `read` or `write` per operation, caps what a single host can push
+//! well below what a modern NIC can carry. The TCP generator and the TCP and
+//! UDP blackholes may instead be configured to use io_uring, submitting their
+//! I/O to the kernel through a shared ring. This backend requires lading be
+//! built with the `io-uring` feature and runs on Linux only.
+//!
+//! io_uring I/O cannot be driven by lading's main runtime. Each component, or
+//! each of a component's sockets, selecting this backend is run by [`run`] on
+//! a dedicated thread with its own single threaded runtime and ring.
+
+use std::io;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// The I/O backend of a component
+pub enum Backend {
+    /// Readiness based I/O on lading's main runtime
+    Epoll,
+    /// Completion based I/O with io_uring, see the module documentation
+    IoUring,
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Self::Epoll
+    }
+}
+
+#[derive(Debug)]
+/// Errors produced by [`run`]
+pub enum Error {
+    /// The io_uring backend was selected but lading was not built with it.
+    Unsupported,
+    /// The backend thread could not be spawned.
+    Io(io::Error),
+    /// The backend thread panicked.
+    Panicked,
+}
+
+/// Run the future made by `make` to completion on a dedicated thread named for
+/// `name`, driven by an io_uring runtime.
+///
+/// # Errors
+///
+/// Function will return an error if the thread cannot be spawned or panics.
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub(crate) async fn run<F, Fut>(name: &str, make: F) -> Result<Fut::Output, Error>
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: std::future::Future + 'static,
+    Fut::Output: Send + 'static,
+{
+    let (snd, rcv) = tokio::sync::oneshot::channel();
+    std::thread::Builder::new()
+        .name(format!("{}-uring", name))
+        .spawn(move || {
+            // The receiver is gone only if the component was dropped, in
+            // which case there is no one to report to.
+            let _ = snd.send(tokio_uring::start(make()));
+        })
+        .map_err(Error::Io)?;
+    rcv.await.map_err(|_| Error::Panicked)
+}
@@ -0,0 +1,158 @@
+//! AF_VSOCK stream sockets, the channel between a virtual machine and its
+//! host.
+//!
+//! A vsock address is a context identifier, the CID, naming the machine --
+//! the host is `2`, each guest is assigned its own -- and a port. Targets run
+//! inside a VM, Firecracker and the like, are reached over vsock without any
+//! network between guest and host. Neither std nor tokio speak vsock, so the
+//! socket is driven directly, non-blocking, through [`AsyncFd`].
+//!
+//! Generators and blackholes speaking vsock label their metrics with the
+//! `address_family` `vsock`, see [`crate::address`].
+
+use std::{
+    io,
+    os::unix::io::{AsRawFd, RawFd},
+};
+
+use nix::{
+    errno::Errno,
+    sys::socket::{self, sockopt, AddressFamily, MsgFlags, SockFlag, SockType, VsockAddr},
+    unistd,
+};
+use tokio::io::unix::AsyncFd;
+
+use crate::address;
+
+/// The backlog of listening sockets, see listen(2).
+const LISTEN_BACKLOG: usize = 1024;
+
+/// The CID a listening socket binds to accept connections from any CID,
+/// `VMADDR_CID_ANY`.
+pub(crate) const CID_ANY: u32 = u32::MAX;
+
+/// Return the `address_family` label of vsock connections.
+#[must_use]
+pub(crate) fn label() -> (String, String) {
+    (address::LABEL.to_string(), "vsock".to_string())
+}
+
+#[derive(Debug)]
+/// A vsock socket, closed when dropped.
+pub(crate) struct Socket {
+    fd: RawFd,
+}
+
+impl Socket {
+    /// Create a non-blocking vsock stream socket.
+    fn new() -> Result<Self, Errno> {
+        let fd = socket::socket(
+            AddressFamily::Vsock,
+            SockType::Stream,
+            SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        Ok(Self { fd })
+    }
+}
+
+impl AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        let _ = unistd::close(self.fd);
+    }
+}
+
+/// Connect to `port` of the machine `cid`.
+///
+/// # Errors
+///
+/// Function will return an error if the host does not support vsock or the
+/// connection is refused.
+pub(crate) async fn connect(cid: u32, port: u32) -> Result<AsyncFd<Socket>, io::Error> {
+    let sock = Socket::new()?;
+    match socket::connect(sock.fd, &VsockAddr::new(cid, port)) {
+        Ok(()) | Err(Errno::EINPROGRESS) => {}
+        Err(errno) => return Err(errno.into()),
+    }
+    let sock = AsyncFd::new(sock)?;
+    // The connection is made, or has failed, once the socket is writable.
+    let _ready = sock.writable().await?;
+    match socket::getsockopt(sock.get_ref().fd, sockopt::SocketError)? {
+        0 => Ok(sock),
+        errno => Err(io::Error::from_raw_os_error(errno)),
+    }
+}
+
+/// Bind a listening socket to `port` of the machine `cid`, [`CID_ANY`] to
+/// accept connections from any machine.
+///
+/// # Errors
+///
+/// Function will return an error if the host does not support vsock or the
+/// port cannot be bound.
+pub(crate) fn listen(cid: u32, port: u32) -> Result<AsyncFd<Socket>, io::Error> {
+    let sock = Socket::new()?;
+    socket::bind(sock.fd, &VsockAddr::new(cid, port))?;
+    socket::listen(sock.fd, LISTEN_BACKLOG)?;
+    AsyncFd::new(sock)
+}
+
+/// Accept a connection on `listener`, returning it and the CID it came from.
+pub(crate) async fn accept(
+    listener: &AsyncFd<Socket>,
+) -> Result<(AsyncFd<Socket>, u32), io::Error> {
+    loop {
+        let mut guard = listener.readable().await?;
+        match guard.try_io(|inner| {
+            socket::accept4(
+                inner.get_ref().fd,
+                SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+            )
+            .map_err(io::Error::from)
+        }) {
+            Ok(accepted) => {
+                let sock = Socket { fd: accepted? };
+                let peer: VsockAddr = socket::getpeername(sock.fd)?;
+                return Ok((AsyncFd::new(sock)?, peer.cid()));
+            }
+            Err(_would_block) => continue,
+        }
+    }
+}
+
+/// Read from `sock` into `buf`, returning the bytes read, zero once the peer
+/// has closed the connection.
+pub(crate) async fn read(sock: &AsyncFd<Socket>, buf: &mut [u8]) -> Result<usize, io::Error> {
+    loop {
+        let mut guard = sock.readable().await?;
+        match guard.try_io(|inner| {
+            socket::recv(inner.get_ref().fd, buf, MsgFlags::empty()).map_err(io::Error::from)
+        }) {
+            Ok(read) => return read,
+            Err(_would_block) => continue,
+        }
+    }
+}
+
+/// Write all of `buf` into `sock`, waiting for the peer to make room.
+pub(crate) async fn write_all(sock: &AsyncFd<Socket>, mut buf: &[u8]) -> Result<(), io::Error> {
+    while !buf.is_empty() {
+        let mut guard = sock.writable().await?;
+        // A peer that has gone away is reported as EPIPE, not SIGPIPE.
+        match guard.try_io(|inner| {
+            socket::send(inner.get_ref().fd, buf, MsgFlags::MSG_NOSIGNAL).map_err(io::Error::from)
+        }) {
+            Ok(Ok(0)) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(Ok(written)) => buf = &buf[written..],
+            Ok(Err(err)) => return Err(err),
+            Err(_would_block) => continue,
+        }
+    }
+    Ok(())
+}
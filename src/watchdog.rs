@@ -0,0 +1,235 @@
+//! Detect a stalled target
+//!
+//! A deadlocked target often looks healthy from the outside: its process is
+//! alive, it may even keep accepting writes into socket buffers for a while.
+//! What gives it away is that it stops doing any work. The watchdog compares
+//! the CPU time the target consumes, see [`crate::observer`], against the bytes
+//! generators write to it, see [`crate::generator`], once a second. Should the
+//! target sit idle while generators keep writing for long enough it is flagged
+//! as stalled: the `target_stalled` gauge is set, `target_stall` counted and a
+//! warning logged. If so configured the experiment is aborted.
+
+use std::{
+    num::NonZeroU32,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use metrics::{counter, gauge};
+use serde::Deserialize;
+use tokio::time::{self, Duration, Instant};
+use tracing::{info, warn};
+
+use crate::{generator, observer, signals::Shutdown};
+
+static TARGET_STALLED: AtomicBool = AtomicBool::new(false);
+
+/// Return whether the watchdog currently considers the target stalled.
+#[must_use]
+pub fn target_stalled() -> bool {
+    TARGET_STALLED.load(Ordering::Relaxed)
+}
+
+fn default_stall_seconds() -> NonZeroU32 {
+    NonZeroU32::new(30).unwrap()
+}
+
+fn default_idle_cpu() -> f64 {
+    0.01
+}
+
+#[derive(Debug)]
+/// Errors produced by [`Server`]
+pub enum Error {
+    /// The target stalled and the watchdog is configured to abort.
+    Stalled {
+        /// The time the target has been stalled for.
+        duration: Duration,
+    },
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+/// Configuration for [`Server`]
+pub struct Config {
+    /// The time in seconds the target must be idle under load before it is
+    /// considered stalled
+    #[serde(default = "default_stall_seconds")]
+    pub stall_seconds: NonZeroU32,
+    /// The CPU utilization, in cores, at or below which the target is idle
+    #[serde(default = "default_idle_cpu")]
+    pub idle_cpu: f64,
+    /// Whether to abort the experiment when the target stalls
+    #[serde(default)]
+    pub abort: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A change in the target's state, see [`Detector::observe`].
+enum Transition {
+    Stalled,
+    Recovered,
+}
+
+#[derive(Debug)]
+/// Tracks how long the target has been idle under load.
+struct Detector {
+    config: Config,
+    last: Option<(f64, u64)>,
+    idle_for: Duration,
+    stalled: bool,
+}
+
+impl Detector {
+    fn new(config: Config) -> Self {
+        Self {
+            config,
+            last: None,
+            idle_for: Duration::ZERO,
+            stalled: false,
+        }
+    }
+
+    /// Observe the target's total CPU seconds and the total bytes written to
+    /// it, `elapsed` after the previous observation.
+    fn observe(
+        &mut self,
+        cpu_seconds: f64,
+        bytes_written: u64,
+        elapsed: Duration,
+    ) -> Option<Transition> {
+        let (last_cpu_seconds, last_bytes_written) =
+            match self.last.replace((cpu_seconds, bytes_written)) {
+                Some(last) => last,
+                None => return None,
+            };
+        let utilization = (cpu_seconds - last_cpu_seconds) / elapsed.as_secs_f64();
+        if utilization <= self.config.idle_cpu && bytes_written > last_bytes_written {
+            self.idle_for += elapsed;
+        } else {
+            self.idle_for = Duration::ZERO;
+            if self.stalled {
+                self.stalled = false;
+                return Some(Transition::Recovered);
+            }
+            return None;
+        }
+        let stall = Duration::from_secs(self.config.stall_seconds.get().into());
+        if !self.stalled && self.idle_for >= stall {
+            self.stalled = true;
+            return Some(Transition::Stalled);
+        }
+        None
+    }
+}
+
+#[derive(Debug)]
+/// The target watchdog.
+///
+/// Checks once a second whether the target is idle while generators write to
+/// it, flagging the target as stalled once it has been for
+/// [`Config::stall_seconds`].
+pub struct Server {
+    config: Config,
+    shutdown: Shutdown,
+}
+
+impl Server {
+    /// Create a new [`Server`] instance
+    #[must_use]
+    pub fn new(config: Config, shutdown: Shutdown) -> Self {
+        Self { config, shutdown }
+    }
+
+    /// Run this [`Server`] to completion
+    ///
+    /// This function watches the target until a shutdown signal is received.
+    /// The target is not judged until it has been observed.
+    ///
+    /// # Errors
+    ///
+    /// Function will return an error if the target stalls and the watchdog is
+    /// configured to abort.
+    pub async fn run(mut self) -> Result<(), Error> {
+        let mut detector = Detector::new(self.config);
+        let mut check_delay = time::interval(Duration::from_secs(1));
+        let mut last_check = Instant::now();
+        gauge!("target_stalled", 0.0);
+
+        loop {
+            tokio::select! {
+                _ = check_delay.tick() => {
+                    let now = Instant::now();
+                    let elapsed = now.duration_since(last_check);
+                    last_check = now;
+                    let cpu_seconds = match observer::target_cpu_seconds() {
+                        Some(cpu_seconds) => cpu_seconds,
+                        None => continue,
+                    };
+                    if elapsed.is_zero() {
+                        continue;
+                    }
+                    match detector.observe(cpu_seconds, generator::total_bytes_written(), elapsed) {
+                        Some(Transition::Stalled) => {
+                            warn!(
+                                "target stalled: idle for {:?} while generators write to it",
+                                detector.idle_for
+                            );
+                            gauge!("target_stalled", 1.0);
+                            TARGET_STALLED.store(true, Ordering::Relaxed);
+                            counter!("target_stall", 1);
+                            if self.config.abort {
+                                return Err(Error::Stalled { duration: detector.idle_for });
+                            }
+                        }
+                        Some(Transition::Recovered) => {
+                            info!("target recovered from stall");
+                            gauge!("target_stalled", 0.0);
+                            TARGET_STALLED.store(false, Ordering::Relaxed);
+                        }
+                        None => {}
+                    }
+                }
+                _ = self.shutdown.recv() => {
+                    info!("shutdown signal received");
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroU32;
+
+    use proptest::prelude::*;
+    use tokio::time::Duration;
+
+    use super::{Config, Detector, Transition};
+
+    // A target whose CPU time does not advance while bytes are written to it
+    // is flagged stalled after exactly `stall_seconds`, and only once.
+    proptest! {
+        #[test]
+        fn idle_under_load_stalls(stall_seconds in 1..64_u32, extra in 0..64_u32) {
+            let mut detector = Detector::new(Config {
+                stall_seconds: NonZeroU32::new(stall_seconds).unwrap(),
+                idle_cpu: 0.01,
+                abort: false,
+            });
+            let second = Duration::from_secs(1);
+            prop_assert_eq!(detector.observe(1.0, 0, second), None);
+            for tick in 1..=(stall_seconds + extra) {
+                let transition = detector.observe(1.0, u64::from(tick), second);
+                if tick == stall_seconds {
+                    prop_assert_eq!(transition, Some(Transition::Stalled));
+                } else {
+                    prop_assert_eq!(transition, None);
+                }
+            }
+            prop_assert_eq!(
+                detector.observe(2.0, u64::from(stall_seconds + extra + 1), second),
+                Some(Transition::Recovered)
+            );
+        }
+    }
+}